@@ -0,0 +1,83 @@
+//! Scans the same encoded blocks after decoding them with `Block::decode`, which copies the
+//! entries out of the read buffer, and with `Block::decode_bytes`, which shares it.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench block_scan`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use mini_lsm_mvcc::block::{Block, BlockBuilder, BlockIterator};
+use mini_lsm_mvcc::key::KeySlice;
+
+const NUM_BLOCKS: usize = 1024;
+const ROUNDS: usize = 20;
+
+fn encoded_blocks() -> Vec<Bytes> {
+    let mut blocks = Vec::with_capacity(NUM_BLOCKS);
+    let mut key_idx = 0;
+    for _ in 0..NUM_BLOCKS {
+        let mut builder = BlockBuilder::new(4096);
+        loop {
+            let key = format!("key_{:010}", key_idx);
+            let value = format!("value_{:0100}", key_idx);
+            if !builder.add(KeySlice::from_slice(key.as_bytes(), 1), value.as_bytes()) {
+                break;
+            }
+            key_idx += 1;
+        }
+        blocks.push(builder.build().encode());
+    }
+    blocks
+}
+
+fn scan(mut iter: BlockIterator) -> usize {
+    let mut bytes = 0;
+    while iter.is_valid() {
+        bytes += iter.key().raw_len() + black_box(iter.value()).len();
+        iter.next();
+    }
+    bytes
+}
+
+fn bench(name: &str, copied_bytes: usize, mut f: impl FnMut() -> usize) {
+    let mut elapsed = Duration::ZERO;
+    let mut scanned_bytes = 0;
+    for _ in 0..ROUNDS {
+        let begin = Instant::now();
+        scanned_bytes += black_box(f());
+        elapsed += begin.elapsed();
+    }
+    println!(
+        "{:<14} {:>10.3?} per scan, {:>6} MB scanned, {:>6} MB copied per scan",
+        name,
+        elapsed / ROUNDS as u32,
+        (scanned_bytes / ROUNDS) >> 20,
+        copied_bytes >> 20,
+    );
+}
+
+fn main() {
+    let blocks = encoded_blocks();
+    let total_bytes = blocks.iter().map(|x| x.len()).sum::<usize>();
+    bench("decode", total_bytes, || {
+        blocks
+            .iter()
+            .map(|raw| {
+                scan(BlockIterator::create_and_seek_to_first(Arc::new(
+                    Block::decode(raw),
+                )))
+            })
+            .sum()
+    });
+    bench("decode_bytes", 0, || {
+        blocks
+            .iter()
+            .map(|raw| {
+                let block = Block::decode_bytes(raw.clone()).unwrap();
+                scan(BlockIterator::create_and_seek_to_first_ref(Arc::new(block)))
+            })
+            .sum()
+    });
+}
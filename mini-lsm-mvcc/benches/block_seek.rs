@@ -0,0 +1,73 @@
+//! Seeks to random keys in a dense block built with prefix compression and in the same block
+//! built with full keys, where the binary search compares keys without decoding them.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench block_seek`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::block::{
+    Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V3, BLOCK_FORMAT_VERSION,
+};
+use mini_lsm_mvcc::key::KeySlice;
+
+const NUM_SEEKS: usize = 1 << 20;
+const ROUNDS: usize = 5;
+
+/// Fills a 64KB block with short values, so that it holds many keys sharing a long prefix.
+/// Returns the block and the number of keys in it.
+fn build(mut builder: BlockBuilder) -> (Arc<Block>, usize) {
+    let mut num_keys = 0;
+    loop {
+        let key = format!("user_table/row_{:010}", num_keys);
+        if !builder.add(KeySlice::from_slice(key.as_bytes(), 1), b"value") {
+            break;
+        }
+        num_keys += 1;
+    }
+    (Arc::new(builder.build()), num_keys)
+}
+
+fn bench(name: &str, block: Arc<Block>, keys: &[Vec<u8>], format_version: u8) {
+    let encoded = block.encode();
+    let block = Arc::new(Block::decode_with_format_version(&encoded, format_version));
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+        let begin = Instant::now();
+        for key in keys {
+            iter.seek_to_key(KeySlice::from_slice(key, 1));
+            black_box(iter.value());
+        }
+        elapsed += begin.elapsed();
+    }
+    println!(
+        "{:<10} {:>8.1?} per seek, {:>6} bytes per block",
+        name,
+        elapsed / (ROUNDS * keys.len()) as u32,
+        encoded.len(),
+    );
+}
+
+fn main() {
+    let (prefix_block, num_prefix_keys) = build(BlockBuilder::new(1 << 16));
+    let (full_block, num_keys) = build(BlockBuilder::new_no_prefix(1 << 16));
+    // a simple LCG, so that both runs seek to the same keys
+    let mut state = 42u64;
+    let keys = (0..NUM_SEEKS)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let idx = (state >> 33) as usize % num_keys;
+            format!("user_table/row_{:010}", idx).into_bytes()
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "{} keys with prefix compression, {} keys without, seeking to the first {}",
+        num_prefix_keys, num_keys, num_keys
+    );
+    bench("prefix", prefix_block, &keys, BLOCK_FORMAT_VERSION);
+    bench("no_prefix", full_block, &keys, BLOCK_FORMAT_V3);
+}
@@ -0,0 +1,74 @@
+//! Probes keys against the bloom filters of 50 SSTs covering the same key range, like a point
+//! lookup going through the L0 SSTs, once hashing the key for every SST with `may_contain_key`
+//! and once hashing it once with `HashedKey`.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench hashed_key`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::key::KeySlice;
+use mini_lsm_mvcc::table::{HashedKey, SsTable, SsTableBuilder};
+
+const NUM_SSTS: usize = 50;
+const KEYS_PER_SST: usize = 2_000;
+const NUM_PROBES: usize = 100_000;
+const ROUNDS: usize = 5;
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!(
+        "user_table/secondary_index/column_{:010}/row_{:010}",
+        i % 97,
+        i
+    )
+    .into_bytes()
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let tables = (0..NUM_SSTS)
+        .map(|id| {
+            let mut builder = SsTableBuilder::new(4096);
+            // every SST spans the whole key range, so that only the bloom filter rejects a key
+            for i in 0..KEYS_PER_SST {
+                builder.add(
+                    KeySlice::from_slice(&key_of(i * NUM_SSTS + id), 1),
+                    b"value",
+                );
+            }
+            builder
+                .build(id, None, dir.path().join(format!("{}.sst", id)))
+                .unwrap()
+        })
+        .collect::<Vec<SsTable>>();
+    let probes = (0..NUM_PROBES)
+        .map(|i| key_of(i * 7919 % (KEYS_PER_SST * NUM_SSTS)))
+        .collect::<Vec<_>>();
+
+    let mut unhashed_elapsed = Duration::ZERO;
+    let mut hashed_elapsed = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let begin = Instant::now();
+        for key in &probes {
+            for table in &tables {
+                black_box(table.may_contain_key(key));
+            }
+        }
+        unhashed_elapsed += begin.elapsed();
+        let begin = Instant::now();
+        for key in &probes {
+            let key = HashedKey::new(key);
+            for table in &tables {
+                black_box(table.may_contain_hashed_key(key));
+            }
+        }
+        hashed_elapsed += begin.elapsed();
+    }
+    println!(
+        "{} keys against {} SSTs: rehashing per SST {:.1?} per round, hashing once {:.1?} per round",
+        NUM_PROBES,
+        NUM_SSTS,
+        unhashed_elapsed / ROUNDS as u32,
+        hashed_elapsed / ROUNDS as u32,
+    );
+}
@@ -0,0 +1,75 @@
+//! Looks up batches of random keys in an engine holding several levels of SSTs, once with a
+//! `get` per key and once with a single `multi_get` per batch.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench multi_get`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 200_000;
+const BATCH_SIZE: usize = 200;
+const NUM_BATCHES: usize = 500;
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("user_table/row_{:010}", i).into_bytes()
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 1 << 20;
+    options.block_size = 4096;
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    for i in 0..NUM_KEYS {
+        storage
+            .put(&key_of(i), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    // a simple LCG, so that both runs look up the same keys
+    let mut state = 42u64;
+    let batches = (0..NUM_BATCHES)
+        .map(|_| {
+            (0..BATCH_SIZE)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    key_of((state >> 33) as usize % NUM_KEYS)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut get_elapsed = Duration::ZERO;
+    let mut multi_get_elapsed = Duration::ZERO;
+    for batch in &batches {
+        let keys = batch.iter().map(|key| key.as_slice()).collect::<Vec<_>>();
+        let begin = Instant::now();
+        for key in &keys {
+            black_box(storage.get(key).unwrap());
+        }
+        get_elapsed += begin.elapsed();
+        let begin = Instant::now();
+        black_box(storage.multi_get(&keys).unwrap());
+        multi_get_elapsed += begin.elapsed();
+    }
+    println!(
+        "{} batches of {} keys: get {:.1?} per batch, multi_get {:.1?} per batch",
+        NUM_BATCHES,
+        BATCH_SIZE,
+        get_elapsed / NUM_BATCHES as u32,
+        multi_get_elapsed / NUM_BATCHES as u32,
+    );
+    storage.close().unwrap();
+}
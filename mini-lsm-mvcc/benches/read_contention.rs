@@ -0,0 +1,85 @@
+//! Reads keys from many threads while a writer keeps freezing memtables and the background
+//! threads keep flushing and compacting them, which replaces the state under the readers, and
+//! reports the read throughput for each number of reader threads.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench read_contention`.
+
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use mini_lsm_mvcc::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 100_000;
+const RUN_TIME: Duration = Duration::from_secs(2);
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("user_table/row_{:010}", i).into_bytes()
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    // small memtables, so that the churn replaces the state hundreds of times a second
+    options.target_sst_size = 1 << 16;
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    for i in 0..NUM_KEYS {
+        storage
+            .put(&key_of(i), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    for num_readers in [1, 2, 4, 8, 16] {
+        let done = AtomicBool::new(false);
+        let num_reads = AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    storage
+                        .put(&key_of(i % NUM_KEYS), format!("value_{}", i).as_bytes())
+                        .unwrap();
+                    i += 1;
+                }
+            });
+            for reader in 0..num_readers {
+                let (storage, done, num_reads) = (&storage, &done, &num_reads);
+                scope.spawn(move || {
+                    // a simple LCG per reader
+                    let mut state = reader as u64;
+                    let mut reads = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        black_box(
+                            storage
+                                .get(&key_of((state >> 33) as usize % NUM_KEYS))
+                                .unwrap(),
+                        );
+                        reads += 1;
+                    }
+                    num_reads.fetch_add(reads, Ordering::Relaxed);
+                });
+            }
+            std::thread::sleep(RUN_TIME);
+            done.store(true, Ordering::Relaxed);
+        });
+        let reads = num_reads.load(Ordering::Relaxed);
+        println!(
+            "{:>2} readers: {:>10.0} reads/s, {:>8.0} reads/s per reader",
+            num_readers,
+            reads as f64 / RUN_TIME.as_secs_f64(),
+            reads as f64 / RUN_TIME.as_secs_f64() / num_readers as f64,
+        );
+    }
+    storage.close().unwrap();
+}
@@ -0,0 +1,46 @@
+//! Writes distinct keys from many threads, each write logged to the WAL, and reports the write
+//! throughput for each number of shards the writes are split into.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench sharded_ingest`.
+
+use std::time::Instant;
+
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const NUM_WRITERS: usize = 8;
+const WRITES_PER_WRITER: usize = 50_000;
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("user_table/row_{:010}", i).into_bytes()
+}
+
+fn main() {
+    for num_shards in [1, 2, 4, 8] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.enable_wal = true;
+        options.num_shards = num_shards;
+        let storage = MiniLsm::open(dir.path(), options).unwrap();
+        let begin = Instant::now();
+        std::thread::scope(|scope| {
+            for writer in 0..NUM_WRITERS {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for i in (writer..NUM_WRITERS * WRITES_PER_WRITER).step_by(NUM_WRITERS) {
+                        storage
+                            .put(&key_of(i), format!("value_{}", i).as_bytes())
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let elapsed = begin.elapsed();
+        println!(
+            "{} shards: {:>10.0} writes/s with {} writers",
+            num_shards,
+            (NUM_WRITERS * WRITES_PER_WRITER) as f64 / elapsed.as_secs_f64(),
+            NUM_WRITERS,
+        );
+        storage.close().unwrap();
+    }
+}
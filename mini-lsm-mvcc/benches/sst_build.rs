@@ -0,0 +1,107 @@
+//! Writes the same sequence of SSTs with a fresh `SsTableBuilder` per SST, as compaction used to,
+//! and with one builder reset between SSTs, counting the allocations made by each.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench sst_build`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::key::KeySlice;
+use mini_lsm_mvcc::table::SsTableBuilder;
+
+const NUM_SSTS: usize = 16;
+const SST_SIZE: usize = 2 << 20;
+const BLOCK_SIZE: usize = 4096;
+const ROUNDS: usize = 5;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Adds entries to `builder` until it reaches the target SST size.
+fn fill(builder: &mut SsTableBuilder, sst_idx: usize, key: &mut Vec<u8>) {
+    let value = [b'v'; 100];
+    let mut i = 0;
+    while builder.estimated_size() < SST_SIZE {
+        key.clear();
+        write!(key, "user_table/{:04}/row_{:010}", sst_idx, i).unwrap();
+        builder.add(KeySlice::from_slice(key, 1), &value);
+        i += 1;
+    }
+}
+
+fn bench(name: &str, dir: &Path, reuse: bool) {
+    let mut elapsed = Duration::ZERO;
+    let mut allocations = 0;
+    let mut allocated_bytes = 0;
+    for _ in 0..ROUNDS {
+        let mut key = Vec::new();
+        let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+        let allocated_bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        let begin = Instant::now();
+        let mut builder = SsTableBuilder::new(BLOCK_SIZE);
+        for sst_idx in 0..NUM_SSTS {
+            let path = dir.join(format!("{}.sst", sst_idx));
+            fill(&mut builder, sst_idx, &mut key);
+            if reuse {
+                builder.build_and_reset(sst_idx, None, path).unwrap();
+            } else {
+                std::mem::replace(&mut builder, SsTableBuilder::new(BLOCK_SIZE))
+                    .build(sst_idx, None, path)
+                    .unwrap();
+            }
+        }
+        elapsed += begin.elapsed();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+        allocated_bytes += ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes_before;
+    }
+    let rounds = ROUNDS as u64;
+    println!(
+        "{:<6} {:>8.1?} per SST, {:>7} allocations ({:>5} MB) per {} SSTs",
+        name,
+        elapsed / (ROUNDS * NUM_SSTS) as u32,
+        allocations / rounds,
+        (allocated_bytes / rounds) >> 20,
+        NUM_SSTS,
+    );
+}
+
+fn main() {
+    // write to memory if possible, so that the disk does not dominate the timing
+    let shm = Path::new("/dev/shm");
+    let dir = if shm.is_dir() {
+        tempfile::tempdir_in(shm)
+    } else {
+        tempfile::tempdir()
+    }
+    .unwrap();
+    for _ in 0..2 {
+        bench("fresh", dir.path(), false);
+        bench("reused", dir.path(), true);
+    }
+}
@@ -0,0 +1,44 @@
+//! Runs a full compaction of the same data with each number of sub-compactions, keeping every
+//! version alive so that all of them are rewritten, and reports the wall-clock time it takes.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench subcompaction`.
+
+use std::time::Instant;
+
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 20_000;
+const NUM_ROUNDS: usize = 8;
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("user_table/row_{:010}", i).into_bytes()
+}
+
+fn main() {
+    for max_subcompactions in [1, 2, 4, 8] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.target_sst_size = 1 << 20;
+        options.max_subcompactions = max_subcompactions;
+        let storage = MiniLsm::open(dir.path(), options).unwrap();
+        let _txn = storage.new_txn().unwrap();
+        for round in 0..NUM_ROUNDS {
+            for i in 0..NUM_KEYS {
+                storage
+                    .put(&key_of(i), format!("value_{}_{:0100}", i, round).as_bytes())
+                    .unwrap();
+            }
+            storage.force_flush().unwrap();
+        }
+        let begin = Instant::now();
+        storage.force_full_compaction().unwrap();
+        let elapsed = begin.elapsed();
+        println!(
+            "{} sub-compactions: {:>8.1} ms to compact {} versions",
+            max_subcompactions,
+            elapsed.as_secs_f64() * 1000.0,
+            NUM_KEYS * NUM_ROUNDS,
+        );
+        storage.close().unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+//! Scans the live entries of a table made mostly of tombstones, skipping the tombstones with
+//! `SsTableIterator::skip_tombstones` on a table with a tombstone bitmap and on one without,
+//! where every tombstone is decoded and checked.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench tombstone_scan`.
+
+use std::hint::black_box;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::iterators::StorageIterator;
+use mini_lsm_mvcc::key::KeySlice;
+use mini_lsm_mvcc::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+const NUM_ENTRIES: usize = 500_000;
+/// Runs of this many tombstones are followed by a few live entries.
+const TOMBSTONE_RUN: usize = 2_000;
+const LIVE_RUN: usize = 50;
+const ROUNDS: usize = 20;
+
+fn build(path: &Path, with_bitmap: bool) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(4096);
+    if with_bitmap {
+        builder.enable_tombstone_bitmap();
+    }
+    for i in 0..NUM_ENTRIES {
+        let key = format!("key_{:010}", i);
+        let value = if i % (TOMBSTONE_RUN + LIVE_RUN) < TOMBSTONE_RUN {
+            String::new()
+        } else {
+            format!("value_{:0100}", i)
+        };
+        builder.add(KeySlice::from_slice(key.as_bytes(), 1), value.as_bytes());
+    }
+    builder.build(0, None, path).unwrap();
+    // reopen the table without a block cache, so that every scan reads its blocks again
+    Arc::new(SsTable::open(0, None, FileObject::open(path).unwrap()).unwrap())
+}
+
+fn scan_live(table: Arc<SsTable>) -> usize {
+    let mut iter = SsTableIterator::create_and_seek_to_first_no_cache(table).unwrap();
+    iter.skip_tombstones().unwrap();
+    let mut entries = 0;
+    while iter.is_valid() {
+        black_box(iter.value());
+        entries += 1;
+        iter.next().unwrap();
+    }
+    entries
+}
+
+/// The number of blocks holding at least one live entry, which are the only blocks read when
+/// the tombstone bitmap is used.
+fn live_blocks(table: &SsTable) -> usize {
+    let bitmap = table.tombstone_bitmap().unwrap();
+    (0..table.num_of_blocks())
+        .filter(|blk_idx| {
+            let start = bitmap.block_start(*blk_idx);
+            let end = start + table.block_metas()[*blk_idx].num_entries as usize;
+            bitmap
+                .next_live_entry(start)
+                .is_some_and(|entry| entry < end)
+        })
+        .count()
+}
+
+fn bench(name: &str, table: &Arc<SsTable>, blocks_read: usize, entries_decoded: usize) {
+    let mut elapsed = Duration::ZERO;
+    let mut live_entries = 0;
+    for _ in 0..ROUNDS {
+        let begin = Instant::now();
+        live_entries = black_box(scan_live(table.clone()));
+        elapsed += begin.elapsed();
+    }
+    println!(
+        "{:<14} {:>10.3?} per scan, {:>6} live entries, {:>5} blocks read, {:>7} entries decoded",
+        name,
+        elapsed / ROUNDS as u32,
+        live_entries,
+        blocks_read,
+        entries_decoded,
+    );
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let with_bitmap = build(&dir.path().join("1.sst"), true);
+    let without_bitmap = build(&dir.path().join("2.sst"), false);
+    let num_live = with_bitmap.properties().num_entries - with_bitmap.properties().num_tombstones;
+    let live_blocks = live_blocks(&with_bitmap);
+    bench(
+        "no bitmap",
+        &without_bitmap,
+        without_bitmap.num_of_blocks(),
+        NUM_ENTRIES,
+    );
+    // at most the first entry of each block read is decoded besides the live entries
+    bench(
+        "bitmap",
+        &with_bitmap,
+        live_blocks,
+        num_live as usize + live_blocks,
+    );
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::compact::CompactionController;
+use crate::destroy::{is_storage_file, LOCK_FILE};
+use crate::lsm_storage::{
+    replay_manifest, LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm,
+};
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::table::{FileObject, SsTable};
+
+/// Present in a directory while a backup is restored into it, see [`MiniLsm::restore`]. A
+/// directory holding it is refused by [`MiniLsm::open`], as the restore did not finish.
+pub(crate) const RESTORE_IN_PROGRESS: &str = "RESTORE_IN_PROGRESS";
+
+/// Fails if `path` holds a restore that did not finish.
+pub(crate) fn check_not_restoring(path: &Path) -> Result<()> {
+    if path.join(RESTORE_IN_PROGRESS).exists() {
+        bail!("{} holds an interrupted restore", path.display());
+    }
+    Ok(())
+}
+
+/// The SSTs a checkpoint holds, by id, with the size of their files. The default is the empty
+/// checkpoint, from which a backup delta is a full backup.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointManifest {
+    pub ssts: BTreeMap<usize, u64>,
+}
+
+/// Fails unless the SST in `path` opens and every block, the meta and the bloom filter match
+/// their checksums.
+fn verify_sst(path: &Path, id: usize) -> Result<()> {
+    let sst = SsTable::open(id, None, FileObject::open(path)?)?;
+    sst.copy_to(&mut std::io::sink())?;
+    Ok(())
+}
+
+/// What changed since a checkpoint, see [`MiniLsm::backup_delta`]. The SSTs to copy are held
+/// open, so that their files are kept even if they are compacted away before the plan is
+/// applied with [`MiniLsm::apply_backup_delta`].
+pub struct BackupPlan {
+    /// The new checkpoint, to take deltas from later on.
+    pub checkpoint: CheckpointManifest,
+    /// The SSTs of the new checkpoint missing from the old one, which are copied.
+    pub new_ssts: Vec<Arc<SsTable>>,
+    /// The records of the manifest of the new checkpoint, which install each SST where it is in
+    /// the storage.
+    pub manifest: Vec<ManifestRecord>,
+}
+
+/// The manifest records building the levels of `snapshot` from nothing, one SST at a time.
+fn snapshot_records(snapshot: &LsmStorageState, flush_to_l0: bool) -> Vec<ManifestRecord> {
+    let mut records = Vec::new();
+    if flush_to_l0 {
+        // an L0 SST is installed in front of the others
+        for id in snapshot.l0_sstables.iter().rev() {
+            records.push(ManifestRecord::Ingest(*id, 0, 0));
+        }
+        for (level, (_, ssts)) in snapshot.levels.iter().enumerate() {
+            for (index, id) in ssts.iter().enumerate() {
+                records.push(ManifestRecord::Ingest(*id, level + 1, index));
+            }
+        }
+    } else {
+        // each tier, oldest first, is created in front of the others by its first SST
+        for (_, ssts) in snapshot.levels.iter().rev() {
+            for (index, id) in ssts.iter().enumerate() {
+                let (level, index) = if index == 0 { (0, 0) } else { (1, index) };
+                records.push(ManifestRecord::Ingest(*id, level, index));
+            }
+        }
+    }
+    records
+}
+
+impl MiniLsm {
+    /// Plans a backup of the storage as it is now, relative to the checkpoint `since` taken
+    /// before. The memtables are flushed first, so that the SSTs hold every write made so far.
+    /// SST files are never rewritten, so the SSTs of `since` still in the storage are unchanged
+    /// and only the others have to be copied; an SST of `since` whose size differs fails the
+    /// plan.
+    pub fn backup_delta(&self, since: &CheckpointManifest) -> Result<BackupPlan> {
+        self.inner.check_writable()?;
+        if self
+            .inner
+            .state
+            .load()
+            .current_memtables()
+            .any(|memtable| !memtable.is_empty())
+        {
+            self.inner
+                .force_freeze_memtable(&self.inner.state_lock.lock())?;
+        }
+        while !self.inner.state.load().imm_memtables.is_empty() {
+            self.inner.force_flush_next_imm_memtable()?;
+        }
+
+        let snapshot = self.inner.state.load_full();
+        let mut checkpoint = CheckpointManifest::default();
+        let mut new_ssts = Vec::new();
+        let ids = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, ssts)| ssts));
+        for id in ids {
+            let sst = &snapshot.sstables[id];
+            match since.ssts.get(id) {
+                Some(size) if *size != sst.table_size() => {
+                    bail!(
+                        "SST {} has {} bytes, but {} in the checkpoint",
+                        id,
+                        sst.table_size(),
+                        size
+                    );
+                }
+                Some(_) => {}
+                None => new_ssts.push(sst.clone()),
+            }
+            checkpoint.ssts.insert(*id, sst.table_size());
+        }
+        let manifest = snapshot_records(
+            &snapshot,
+            self.inner.compaction_controller.load().flush_to_l0(),
+        );
+        Ok(BackupPlan {
+            checkpoint,
+            new_ssts,
+            manifest,
+        })
+    }
+
+    /// Materializes the checkpoint planned by [`Self::backup_delta`] in the new directory
+    /// `path`, from the previous checkpoint in `base_dir`: the SSTs of `base_dir` still in the
+    /// checkpoint are hard-linked, or copied if they cannot be, and the new ones are copied from
+    /// the storage, which are released once done. The checkpoint opens like any storage.
+    pub fn apply_backup_delta(
+        base_dir: impl AsRef<Path>,
+        plan: BackupPlan,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let (base_dir, path) = (base_dir.as_ref(), path.as_ref());
+        std::fs::create_dir_all(path)?;
+        if std::fs::read_dir(path)?.next().is_some() {
+            bail!("{} is not empty", path.display());
+        }
+
+        for sst in &plan.new_ssts {
+            let sst_path = LsmStorageInner::path_of_sst_static(path, sst.sst_id());
+            let mut file = BufWriter::new(
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&sst_path)?,
+            );
+            sst.copy_to(&mut file)
+                .with_context(|| format!("failed to copy SST {}", sst.sst_id()))?;
+            file.into_inner()?.sync_all()?;
+        }
+        for (id, size) in &plan.checkpoint.ssts {
+            if plan.new_ssts.iter().any(|sst| sst.sst_id() == *id) {
+                continue;
+            }
+            let base_path = LsmStorageInner::path_of_sst_static(base_dir, *id);
+            let base_size = std::fs::metadata(&base_path)
+                .with_context(|| format!("SST {} is missing from the base checkpoint", id))?
+                .len();
+            if base_size != *size {
+                bail!(
+                    "SST {} has {} bytes in the base checkpoint, but {} in the storage",
+                    id,
+                    base_size,
+                    size
+                );
+            }
+            let sst_path = LsmStorageInner::path_of_sst_static(path, *id);
+            if std::fs::hard_link(&base_path, &sst_path).is_err() {
+                // e.g. the checkpoints are on different file systems
+                std::fs::copy(&base_path, &sst_path)?;
+                File::open(&sst_path)?.sync_all()?;
+            }
+        }
+
+        let manifest = Manifest::create(path.join("MANIFEST"))?;
+        for record in plan.manifest {
+            manifest.add_record_when_init(record)?;
+        }
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Restores the backup in `backup_dir`, e.g. a checkpoint written by
+    /// [`Self::apply_backup_delta`], into `path`, which must be empty, and opens it. The SSTs are
+    /// hard-linked, or copied if they cannot be, and the manifest and the WALs are copied. The
+    /// manifest records and every SST it refers to are checked against their checksums before
+    /// the storage is opened. Until the restore succeeds, `path` holds [`RESTORE_IN_PROGRESS`],
+    /// so that an interrupted restore is never opened; such a directory can be removed with
+    /// [`Self::destroy`].
+    pub fn restore(
+        backup_dir: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Arc<Self>> {
+        let (backup_dir, path) = (backup_dir.as_ref(), path.as_ref());
+        std::fs::create_dir_all(path)?;
+        if std::fs::read_dir(path)?.next().is_some() {
+            bail!("{} is not empty", path.display());
+        }
+        File::create(path.join(RESTORE_IN_PROGRESS))?.sync_all()?;
+        File::open(path)?.sync_all()?;
+
+        for entry in std::fs::read_dir(backup_dir)
+            .with_context(|| format!("failed to list {}", backup_dir.display()))?
+        {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str().filter(|name| is_storage_file(name)) else {
+                continue;
+            };
+            if name == LOCK_FILE || name == RESTORE_IN_PROGRESS {
+                continue;
+            }
+            let (from, to) = (backup_dir.join(name), path.join(name));
+            // SSTs are never rewritten, unlike the manifest and the WALs
+            if !name.ends_with(".sst") || std::fs::hard_link(&from, &to).is_err() {
+                std::fs::copy(&from, &to)
+                    .with_context(|| format!("failed to copy {}", from.display()))?;
+                File::open(&to)?.sync_all()?;
+            }
+        }
+
+        let manifest_path = path.join("MANIFEST");
+        let records = Manifest::verify_records(&manifest_path)
+            .with_context(|| format!("{} is corrupt", backup_dir.join("MANIFEST").display()))?;
+        let mut state = LsmStorageState::create(&options);
+        replay_manifest(
+            &mut state,
+            &CompactionController::new(&options.compaction_options),
+            records,
+        );
+        for id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            verify_sst(&LsmStorageInner::path_of_sst_static(path, *id), *id).with_context(
+                || {
+                    format!(
+                        "{} is corrupt",
+                        LsmStorageInner::path_of_sst_static(backup_dir, *id).display()
+                    )
+                },
+            )?;
+        }
+
+        std::fs::remove_file(path.join(RESTORE_IN_PROGRESS))?;
+        File::open(path)?.sync_all()?;
+        Self::open(path, options)
+    }
+}
@@ -1,5 +1,62 @@
 pub mod mini_lsm_wrapper {
     pub use mini_lsm_mvcc::*;
+
+    /// The storage engine with the options the shared binaries set, which are those of the
+    /// starter crate. The options only this crate has keep their defaults for the week 1 tests.
+    #[allow(dead_code, unused_imports)]
+    pub mod lsm_storage {
+        pub use mini_lsm_mvcc::lsm_storage::*;
+
+        use std::ops::Deref;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        use anyhow::Result;
+        use mini_lsm_mvcc::compact::CompactionOptions;
+        use mini_lsm_mvcc::lsm_storage;
+
+        pub struct LsmStorageOptions {
+            pub block_size: usize,
+            pub target_sst_size: usize,
+            pub num_memtable_limit: usize,
+            pub compaction_options: CompactionOptions,
+            pub enable_wal: bool,
+            pub serializable: bool,
+        }
+
+        impl From<LsmStorageOptions> for lsm_storage::LsmStorageOptions {
+            fn from(options: LsmStorageOptions) -> Self {
+                Self {
+                    block_size: options.block_size,
+                    target_sst_size: options.target_sst_size,
+                    num_memtable_limit: options.num_memtable_limit,
+                    compaction_options: options.compaction_options,
+                    enable_wal: options.enable_wal,
+                    serializable: options.serializable,
+                    ..Self::default_for_week1_test()
+                }
+            }
+        }
+
+        pub struct MiniLsm(Arc<lsm_storage::MiniLsm>);
+
+        impl MiniLsm {
+            pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+                Ok(Arc::new(Self(lsm_storage::MiniLsm::open(
+                    path,
+                    options.into(),
+                )?)))
+            }
+        }
+
+        impl Deref for MiniLsm {
+            type Target = lsm_storage::MiniLsm;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
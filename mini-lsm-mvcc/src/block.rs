@@ -1,43 +1,352 @@
 mod builder;
 mod iterator;
 
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::key::KeySlice;
+
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
 
+/// The original block format, where entries do not carry a timestamp. Keys are read back with
+/// `TS_DEFAULT`.
+pub const BLOCK_FORMAT_V1: u8 = 1;
+/// Each entry stores a `u64` timestamp after the key.
+pub const BLOCK_FORMAT_V2: u8 = 2;
+/// Like [`BLOCK_FORMAT_V2`], but keys are stored in full (the overlap is always 0), so that they
+/// can be compared in place without reconstructing them from the first key of the block.
+pub const BLOCK_FORMAT_V3: u8 = 3;
+/// Like [`BLOCK_FORMAT_V2`], but the key overlap and key length of each entry take 1, 2 or 4
+/// bytes instead of 2: the narrowest width fitting the longest key of the block, which the
+/// builder widens as longer keys are added. The width is stored in a byte before the number of
+/// entries.
+pub const BLOCK_FORMAT_V4: u8 = 4;
+/// Like [`BLOCK_FORMAT_V2`], but each entry stores a byte of flags after its timestamp, e.g.
+/// [`ENTRY_FLAG_MERGE_OPERAND`].
+pub const BLOCK_FORMAT_V5: u8 = 5;
+/// Like [`BLOCK_FORMAT_V5`], but tombstones are marked by [`ENTRY_FLAG_TOMBSTONE`] rather than by
+/// an empty value, so that empty values can be stored.
+pub const BLOCK_FORMAT_V6: u8 = 6;
+/// The format version used for new blocks.
+pub const BLOCK_FORMAT_VERSION: u8 = BLOCK_FORMAT_V2;
+
+/// The entry is a merge operand, to be combined with the older versions of its key by the
+/// [`crate::merge_operator::MergeOperator`], rather than a value replacing them.
+pub const ENTRY_FLAG_MERGE_OPERAND: u8 = 1;
+/// The value of the entry ends with its expiry time, after which the entry reads as a tombstone,
+/// see [`crate::ttl::expiry_of`].
+pub const ENTRY_FLAG_TTL: u8 = 2;
+/// The entry is a tombstone. Only [`BLOCK_FORMAT_V6`] stores it; in the other formats, as in the
+/// entries written without flags, an entry is a tombstone if and only if its value is empty, see
+/// [`implied_entry_flags`].
+pub const ENTRY_FLAG_TOMBSTONE: u8 = 4;
+
+/// The flags of an entry stored without them: [`ENTRY_FLAG_TOMBSTONE`] for an empty value, the
+/// way deletes are written, and none otherwise.
+pub fn implied_entry_flags(value: &[u8]) -> u8 {
+    if value.is_empty() {
+        ENTRY_FLAG_TOMBSTONE
+    } else {
+        0
+    }
+}
+
+/// The narrowest width, in bytes, of the key lengths of [`BLOCK_FORMAT_V4`] blocks and of the
+/// block meta that holds `max_key_len`.
+pub(crate) fn key_len_width(max_key_len: usize) -> u8 {
+    if max_key_len <= u8::MAX as usize {
+        1
+    } else if max_key_len <= u16::MAX as usize {
+        2
+    } else {
+        4
+    }
+}
+
+/// Encodes a key length in `width` bytes, see [`key_len_width`].
+pub(crate) fn put_key_len(buf: &mut impl BufMut, len: usize, width: u8) {
+    match width {
+        1 => buf.put_u8(len as u8),
+        2 => buf.put_u16(len as u16),
+        _ => buf.put_u32(len as u32),
+    }
+}
+
+/// Decodes a key length encoded in `width` bytes.
+pub(crate) fn get_key_len(buf: &mut impl Buf, width: u8) -> usize {
+    match width {
+        1 => buf.get_u8() as usize,
+        2 => buf.get_u16() as usize,
+        _ => buf.get_u32() as usize,
+    }
+}
+
+/// The width of the key lengths of blocks in `format_version`, or 0 if it is stored in the block.
+fn fixed_key_len_width(format_version: u8) -> u8 {
+    if format_version == BLOCK_FORMAT_V4 {
+        0
+    } else {
+        SIZEOF_U16 as u8
+    }
+}
+
+fn is_supported(format_version: u8) -> bool {
+    matches!(
+        format_version,
+        BLOCK_FORMAT_V1
+            | BLOCK_FORMAT_V2
+            | BLOCK_FORMAT_V3
+            | BLOCK_FORMAT_V4
+            | BLOCK_FORMAT_V5
+            | BLOCK_FORMAT_V6
+    )
+}
+
+/// Whether entries of blocks in `format_version` store flags.
+pub(crate) fn has_entry_flags(format_version: u8) -> bool {
+    matches!(format_version, BLOCK_FORMAT_V5 | BLOCK_FORMAT_V6)
+}
+
+/// Whether blocks in `format_version` mark tombstones with [`ENTRY_FLAG_TOMBSTONE`].
+pub(crate) fn has_tombstone_flag(format_version: u8) -> bool {
+    format_version == BLOCK_FORMAT_V6
+}
+
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
-    pub(crate) data: Vec<u8>,
+    pub(crate) data: Bytes,
     pub(crate) offsets: Vec<u16>,
+    /// The format of the entries in `data`, which is not part of the encoded block.
+    pub(crate) format_version: u8,
+    /// The width of the key overlap and key length of the entries, see [`key_len_width`].
+    pub(crate) key_len_width: u8,
 }
 
 impl Block {
+    /// Encode the block. Offsets and lengths are encoded in big-endian byte order.
     pub fn encode(&self) -> Bytes {
-        let mut buf = self.data.clone();
+        let mut buf = self.data.to_vec();
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
             buf.put_u16(*offset);
         }
+        if fixed_key_len_width(self.format_version) == 0 {
+            buf.put_u8(self.key_len_width);
+        }
         // Adds number of elements at the end of the block
         buf.put_u16(offsets_len as u16);
         buf.into()
     }
 
+    /// Decode a block written in the current format version.
     pub fn decode(data: &[u8]) -> Self {
+        Self::decode_with_format_version(data, BLOCK_FORMAT_VERSION)
+    }
+
+    /// Decode a block written in the given format version.
+    pub fn decode_with_format_version(data: &[u8], format_version: u8) -> Self {
+        assert!(
+            is_supported(format_version),
+            "unsupported block format version {}",
+            format_version
+        );
         // get number of elements in the block
         let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let mut offsets_end = data.len() - SIZEOF_U16;
+        let key_len_width = match fixed_key_len_width(format_version) {
+            0 => {
+                offsets_end -= 1;
+                data[offsets_end]
+            }
+            width => width,
+        };
+        let data_end = offsets_end - entry_offsets_len * SIZEOF_U16;
+        let offsets_raw = &data[data_end..offsets_end];
         // get offset array
         let offsets = offsets_raw
             .chunks(SIZEOF_U16)
             .map(|mut x| x.get_u16())
             .collect();
         // retrieve data
-        let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        let data = Bytes::copy_from_slice(&data[0..data_end]);
+        Self {
+            data,
+            offsets,
+            format_version,
+            key_len_width,
+        }
+    }
+
+    /// Decode a block written in the given format version, checking that its number of entries,
+    /// key length width and entry offsets fit in `data` instead of panicking. The entries
+    /// themselves are not checked.
+    pub fn try_decode_with_format_version(data: &[u8], format_version: u8) -> Result<Self> {
+        let block =
+            Self::decode_bytes_with_format_version(Bytes::copy_from_slice(data), format_version)?;
+        Ok(Self {
+            data: block.raw.slice(..block.data_end),
+            offsets: (0..block.num_entries)
+                .map(|idx| block.offset(idx) as u16)
+                .collect(),
+            format_version,
+            key_len_width: block.key_len_width,
+        })
+    }
+
+    /// The number of bytes the decoded block holds in memory.
+    pub fn decoded_size(&self) -> usize {
+        self.data.len() + self.offsets.len() * SIZEOF_U16
+    }
+
+    /// The number of key-value pairs in the block.
+    pub fn num_entries(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// The value and the timestamp of the newest version of `user_key` at or below `read_ts` in
+    /// the block, if any. Entries are returned as stored, so a tombstone reads as its empty value
+    /// and a merge operand as the operand; the flags are read with a [`BlockIterator`]. In blocks
+    /// of [`BLOCK_FORMAT_V1`], every entry has the timestamp `TS_DEFAULT`.
+    pub fn get_with_ts(&self, user_key: &[u8], read_ts: u64) -> Option<(Bytes, u64)> {
+        let block = Arc::new(Self {
+            data: self.data.clone(),
+            offsets: self.offsets.clone(),
+            format_version: self.format_version,
+            key_len_width: self.key_len_width,
+        });
+        // the versions of a key are ordered from the newest
+        let iter =
+            BlockIterator::create_and_seek_to_key(block, KeySlice::from_slice(user_key, read_ts));
+        (iter.is_valid() && iter.key().key_ref() == user_key)
+            .then(|| (iter.value_bytes(), iter.key().ts()))
+    }
+
+    /// Whether entries of this block carry a timestamp.
+    pub(crate) fn has_ts(&self) -> bool {
+        self.format_version >= BLOCK_FORMAT_V2
+    }
+
+    /// Whether keys of this block are stored without prefix compression.
+    pub(crate) fn has_full_keys(&self) -> bool {
+        self.format_version == BLOCK_FORMAT_V3
+    }
+
+    /// Whether entries of this block store flags.
+    pub(crate) fn has_flags(&self) -> bool {
+        has_entry_flags(self.format_version)
+    }
+
+    /// Whether tombstones of this block are marked by [`ENTRY_FLAG_TOMBSTONE`].
+    pub(crate) fn has_tombstone_flag(&self) -> bool {
+        has_tombstone_flag(self.format_version)
+    }
+
+    /// Decode a block written in the current format version without copying it. The returned
+    /// block shares the allocation of `data`.
+    pub fn decode_bytes(data: Bytes) -> Result<BlockRef> {
+        Self::decode_bytes_with_format_version(data, BLOCK_FORMAT_VERSION)
+    }
+
+    /// Decode a block written in the given format version without copying it.
+    pub fn decode_bytes_with_format_version(data: Bytes, format_version: u8) -> Result<BlockRef> {
+        if !is_supported(format_version) {
+            bail!("unsupported block format version {}", format_version);
+        }
+        let fixed_width = fixed_key_len_width(format_version);
+        let trailer_len = if fixed_width == 0 {
+            SIZEOF_U16 + 1
+        } else {
+            SIZEOF_U16
+        };
+        if data.len() < trailer_len {
+            bail!("block too short");
+        }
+        let num_entries = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        let key_len_width = match fixed_width {
+            0 => data[data.len() - trailer_len],
+            width => width,
+        };
+        if !matches!(key_len_width, 1 | 2 | 4) {
+            bail!("invalid key length width {}", key_len_width);
+        }
+        let Some(data_end) = (data.len() - trailer_len).checked_sub(num_entries * SIZEOF_U16)
+        else {
+            bail!("block too short for {} entries", num_entries);
+        };
+        let block = BlockRef {
+            raw: data,
+            data_end,
+            num_entries,
+            format_version,
+            key_len_width,
+        };
+        if (0..num_entries).any(|idx| block.offset(idx) >= data_end) {
+            bail!("entry offset out of range");
+        }
+        Ok(block)
+    }
+}
+
+/// A block decoded in place from a shared buffer, e.g. a memory-mapped file, so that the data of
+/// the entries is never copied. Created by [`Block::decode_bytes`].
+pub struct BlockRef {
+    /// The encoded block.
+    raw: Bytes,
+    /// The end of the entries, where the offsets begin.
+    data_end: usize,
+    num_entries: usize,
+    format_version: u8,
+    key_len_width: u8,
+}
+
+impl BlockRef {
+    /// The entries of the block.
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.raw[..self.data_end]
+    }
+
+    /// The offset of the `idx`-th entry, read from the encoded offsets.
+    pub(crate) fn offset(&self, idx: usize) -> usize {
+        let begin = self.data_end + idx * SIZEOF_U16;
+        (&self.raw[begin..begin + SIZEOF_U16]).get_u16() as usize
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Whether entries of this block carry a timestamp.
+    pub(crate) fn has_ts(&self) -> bool {
+        self.format_version >= BLOCK_FORMAT_V2
+    }
+
+    /// Whether keys of this block are stored without prefix compression.
+    pub(crate) fn has_full_keys(&self) -> bool {
+        self.format_version == BLOCK_FORMAT_V3
+    }
+
+    /// Whether entries of this block store flags.
+    pub(crate) fn has_flags(&self) -> bool {
+        has_entry_flags(self.format_version)
+    }
+
+    /// Whether tombstones of this block are marked by [`ENTRY_FLAG_TOMBSTONE`].
+    pub(crate) fn has_tombstone_flag(&self) -> bool {
+        has_tombstone_flag(self.format_version)
+    }
+
+    /// The width of the key overlap and key length of the entries, see [`key_len_width`].
+    pub(crate) fn key_len_width(&self) -> u8 {
+        self.key_len_width
+    }
+
+    /// The buffer the block was decoded from.
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
     }
 }
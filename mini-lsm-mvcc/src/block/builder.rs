@@ -1,8 +1,12 @@
-use bytes::BufMut;
+use bytes::{Buf, BufMut};
 
 use crate::key::{KeySlice, KeyVec};
 
-use super::{Block, SIZEOF_U16};
+use super::{
+    fixed_key_len_width, get_key_len, has_entry_flags, has_tombstone_flag, implied_entry_flags,
+    key_len_width, put_key_len, Block, BLOCK_FORMAT_V2, BLOCK_FORMAT_V3, BLOCK_FORMAT_VERSION,
+    ENTRY_FLAG_TOMBSTONE, SIZEOF_U16,
+};
 
 /// Builds a block.
 pub struct BlockBuilder {
@@ -14,6 +18,10 @@ pub struct BlockBuilder {
     block_size: usize,
     /// The first key in the block
     first_key: KeyVec,
+    /// The format of the entries.
+    format_version: u8,
+    /// The width of the key overlap and key length of the entries so far.
+    key_len_width: u8,
 }
 
 fn compute_overlap(first_key: KeySlice, key: KeySlice) -> usize {
@@ -33,46 +41,159 @@ fn compute_overlap(first_key: KeySlice, key: KeySlice) -> usize {
 impl BlockBuilder {
     /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_format_version(block_size, BLOCK_FORMAT_VERSION)
+    }
+
+    /// Creates a new block builder storing full keys, which takes more space than prefix
+    /// compression but makes seeks within the block faster. See [`BLOCK_FORMAT_V3`].
+    pub fn new_no_prefix(block_size: usize) -> Self {
+        Self::new_with_format_version(block_size, BLOCK_FORMAT_V3)
+    }
+
+    /// Creates a new block builder writing entries in the given format version. Timestamps of
+    /// the keys are discarded by formats that do not store them.
+    pub fn new_with_format_version(block_size: usize, format_version: u8) -> Self {
         Self {
             offsets: Vec::new(),
             data: Vec::new(),
             block_size,
             first_key: KeyVec::new(),
+            format_version,
+            key_len_width: Self::initial_key_len_width(format_version),
         }
     }
 
+    fn initial_key_len_width(format_version: u8) -> u8 {
+        match fixed_key_len_width(format_version) {
+            0 => 1,
+            width => width,
+        }
+    }
+
+    /// Whether the width of the key lengths is chosen from the keys of the block.
+    fn has_variable_key_len_width(&self) -> bool {
+        fixed_key_len_width(self.format_version) == 0
+    }
+
     fn estimated_size(&self) -> usize {
+        let key_len_width = if self.has_variable_key_len_width() {
+            1
+        } else {
+            0
+        };
         SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
         // key-value pairs
+        + key_len_width
+    }
+
+    /// The size the block grows by when `key` and `value` are added.
+    fn entry_size(&self, key: KeySlice, value: &[u8]) -> usize {
+        if !self.has_variable_key_len_width() {
+            let flags_len = has_entry_flags(self.format_version) as usize;
+            return key.raw_len()
+                + value.len()
+                + SIZEOF_U16 * 3 /* key_len, value_len and offset */
+                + flags_len;
+        }
+        let width = self.key_len_width.max(key_len_width(key.key_len()));
+        // the entries so far are widened along with the new one
+        let widening = self.offsets.len() * 2 * (width - self.key_len_width) as usize;
+        widening
+            + key.raw_len()
+            + value.len()
+            + 2 * width as usize /* overlap and key_len */
+            + SIZEOF_U16 * 2 /* value_len and offset */
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Re-encodes the entries so far with key lengths of `width` bytes, to fit a longer key.
+    fn widen_key_lens(&mut self, width: u8) {
+        let old_data = std::mem::take(&mut self.data);
+        let mut data = Vec::with_capacity(
+            old_data.len() + self.offsets.len() * 2 * (width - self.key_len_width) as usize,
+        );
+        for offset in &mut self.offsets {
+            let mut entry = &old_data[*offset as usize..];
+            let overlap = get_key_len(&mut entry, self.key_len_width);
+            let key_len = get_key_len(&mut entry, self.key_len_width);
+            // the key, timestamp, value length and value are copied as they are
+            let ts_end = key_len + std::mem::size_of::<u64>();
+            let value_len = (&entry[ts_end..]).get_u16() as usize;
+            *offset = data.len() as u16;
+            put_key_len(&mut data, overlap, width);
+            put_key_len(&mut data, key_len, width);
+            data.extend_from_slice(&entry[..ts_end + SIZEOF_U16 + value_len]);
+        }
+        self.data = data;
+        self.key_len_width = width;
+    }
+
+    /// Adds a key-value pair to the block, an empty value being a tombstone. Returns false when
+    /// the block is full.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_with_flags(key, value, implied_entry_flags(value))
+    }
+
+    /// Adds a key-value pair with entry flags, e.g. [`super::ENTRY_FLAG_MERGE_OPERAND`], to the
+    /// block. Returns false when the block is full. Flags other than those implied by the value
+    /// (see [`implied_entry_flags`]) need a format storing them, see [`super::BLOCK_FORMAT_V5`],
+    /// and an empty value that is not a tombstone needs [`super::BLOCK_FORMAT_V6`].
+    #[must_use]
+    pub fn add_with_flags(&mut self, key: KeySlice, value: &[u8], flags: u8) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
-            && !self.is_empty()
+        let flags = if has_tombstone_flag(self.format_version) {
+            flags
+        } else {
+            assert_eq!(
+                flags & ENTRY_FLAG_TOMBSTONE,
+                implied_entry_flags(value),
+                "block format version {} stores tombstones as empty values only",
+                self.format_version
+            );
+            flags & !ENTRY_FLAG_TOMBSTONE
+        };
+        assert!(
+            flags == 0 || has_entry_flags(self.format_version),
+            "block format version {} does not store entry flags",
+            self.format_version
+        );
+        if self.estimated_size() + self.entry_size(key, value) > self.block_size && !self.is_empty()
         {
             return false;
         }
+        if self.has_variable_key_len_width() {
+            let width = key_len_width(key.key_len());
+            if width > self.key_len_width {
+                self.widen_key_lens(width);
+            }
+        }
         // Add the offset of the data into the offset array.
         self.offsets.push(self.data.len() as u16);
-        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        let overlap = if self.format_version == BLOCK_FORMAT_V3 {
+            0
+        } else {
+            compute_overlap(self.first_key.as_key_slice(), key)
+        };
         // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        put_key_len(&mut self.data, overlap, self.key_len_width);
         // Encode key length.
-        self.data.put_u16((key.key_len() - overlap) as u16);
+        put_key_len(&mut self.data, key.key_len() - overlap, self.key_len_width);
         // Encode key content.
         self.data.put(&key.key_ref()[overlap..]);
         // Encode key ts
-        self.data.put_u64(key.ts());
+        if self.format_version >= BLOCK_FORMAT_V2 {
+            self.data.put_u64(key.ts());
+        }
+        if has_entry_flags(self.format_version) {
+            self.data.put_u8(flags);
+        }
         // Encode value length.
         self.data.put_u16(value.len() as u16);
         // Encode value content.
         self.data.put(value);
 
         if self.first_key.is_empty() {
-            self.first_key = key.to_key_vec();
+            self.first_key.set_from_slice(key);
         }
 
         true
@@ -83,14 +204,46 @@ impl BlockBuilder {
         self.offsets.is_empty()
     }
 
+    /// The number of key-value pairs in the block.
+    pub fn num_entries(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Append the encoded block to `buf`, as [`Block::encode`] on the built block would, without
+    /// building it.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        if self.is_empty() {
+            panic!("block should not be empty");
+        }
+        buf.reserve(self.estimated_size());
+        buf.extend_from_slice(&self.data);
+        for offset in &self.offsets {
+            buf.put_u16(*offset);
+        }
+        if self.has_variable_key_len_width() {
+            buf.put_u8(self.key_len_width);
+        }
+        buf.put_u16(self.offsets.len() as u16);
+    }
+
+    /// Clear the block for the next one, keeping the allocated buffers.
+    pub fn reset(&mut self) {
+        self.offsets.clear();
+        self.data.clear();
+        self.first_key.clear();
+        self.key_len_width = Self::initial_key_len_width(self.format_version);
+    }
+
     /// Finalize the block.
     pub fn build(self) -> Block {
         if self.is_empty() {
             panic!("block should not be empty");
         }
         Block {
-            data: self.data,
+            data: self.data.into(),
             offsets: self.offsets,
+            format_version: self.format_version,
+            key_len_width: self.key_len_width,
         }
     }
 }
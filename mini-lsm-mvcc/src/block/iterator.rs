@@ -1,60 +1,176 @@
 use std::sync::Arc;
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 
 use crate::{
-    block::SIZEOF_U16,
-    key::{KeySlice, KeyVec},
+    block::{
+        get_key_len, implied_entry_flags, BLOCK_FORMAT_VERSION, ENTRY_FLAG_TOMBSTONE, SIZEOF_U16,
+    },
+    key::{KeySlice, KeyVec, TS_DEFAULT},
 };
 
-use super::Block;
+use super::{Block, BlockRef};
+
+/// A block owning its decoded data, or one decoded in place.
+enum BlockHandle {
+    Owned(Arc<Block>),
+    Shared(Arc<BlockRef>),
+}
+
+impl BlockHandle {
+    fn data(&self) -> &[u8] {
+        match self {
+            Self::Owned(block) => &block.data,
+            Self::Shared(block) => block.data(),
+        }
+    }
+
+    fn num_entries(&self) -> usize {
+        match self {
+            Self::Owned(block) => block.offsets.len(),
+            Self::Shared(block) => block.num_entries(),
+        }
+    }
+
+    fn offset(&self, idx: usize) -> usize {
+        match self {
+            Self::Owned(block) => block.offsets[idx] as usize,
+            Self::Shared(block) => block.offset(idx),
+        }
+    }
+
+    fn has_ts(&self) -> bool {
+        match self {
+            Self::Owned(block) => block.has_ts(),
+            Self::Shared(block) => block.has_ts(),
+        }
+    }
+
+    fn key_len_width(&self) -> u8 {
+        match self {
+            Self::Owned(block) => block.key_len_width,
+            Self::Shared(block) => block.key_len_width(),
+        }
+    }
+
+    fn has_full_keys(&self) -> bool {
+        match self {
+            Self::Owned(block) => block.has_full_keys(),
+            Self::Shared(block) => block.has_full_keys(),
+        }
+    }
+
+    fn has_flags(&self) -> bool {
+        match self {
+            Self::Owned(block) => block.has_flags(),
+            Self::Shared(block) => block.has_flags(),
+        }
+    }
+
+    fn has_tombstone_flag(&self) -> bool {
+        match self {
+            Self::Owned(block) => block.has_tombstone_flag(),
+            Self::Shared(block) => block.has_tombstone_flag(),
+        }
+    }
+
+    /// The key of the `idx`-th entry, read in place. Only for blocks storing full keys.
+    fn full_key_at(&self, idx: usize) -> KeySlice<'_> {
+        debug_assert!(self.has_full_keys());
+        let mut entry = &self.data()[self.offset(idx)..];
+        get_key_len(&mut entry, self.key_len_width());
+        let key_len = get_key_len(&mut entry, self.key_len_width());
+        let ts = (&entry[key_len..]).get_u64();
+        KeySlice::from_slice(&entry[..key_len], ts)
+    }
+
+    fn get_first_key(&self) -> KeyVec {
+        if self.num_entries() == 0 {
+            return KeyVec::new();
+        }
+        let mut buf = self.data();
+        get_key_len(&mut buf, self.key_len_width());
+        let key_len = get_key_len(&mut buf, self.key_len_width());
+        let key = &buf[..key_len];
+        buf.advance(key_len);
+        let ts = if self.has_ts() {
+            buf.get_u64()
+        } else {
+            TS_DEFAULT
+        };
+        KeyVec::from_vec_with_ts(key.to_vec(), ts)
+    }
+}
 
 /// Iterates on a block.
 pub struct BlockIterator {
     /// reference to the block
-    block: Arc<Block>,
+    block: BlockHandle,
     /// the current key at the iterator position
     key: KeyVec,
     /// the value range from the block
     value_range: (usize, usize),
+    /// the flags of the current entry, 0 for blocks not storing them
+    flags: u8,
     /// the current index at the iterator position
     idx: usize,
     /// the first key in the block
     first_key: KeyVec,
 }
 
-impl Block {
-    fn get_first_key(&self) -> KeyVec {
-        let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
-        let key = &buf[..key_len];
-        buf.advance(key_len);
-        KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
-    }
-}
-
 impl BlockIterator {
-    fn new(block: Arc<Block>) -> Self {
+    fn new(block: BlockHandle) -> Self {
         Self {
             first_key: block.get_first_key(),
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            flags: 0,
             idx: 0,
         }
     }
 
+    /// Creates an iterator over no entries, which is never valid.
+    pub fn empty() -> Self {
+        Self::new(BlockHandle::Owned(Arc::new(Block {
+            data: Bytes::new(),
+            offsets: Vec::new(),
+            format_version: BLOCK_FORMAT_VERSION,
+            key_len_width: SIZEOF_U16 as u8,
+        })))
+    }
+
     /// Creates a block iterator and seek to the first entry.
     pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
-        let mut iter = Self::new(block);
+        let mut iter = Self::new(BlockHandle::Owned(block));
         iter.seek_to_first();
         iter
     }
 
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(BlockHandle::Owned(block));
+        iter.seek_to_last();
+        iter
+    }
+
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
-        let mut iter = Self::new(block);
+        let mut iter = Self::new(BlockHandle::Owned(block));
+        iter.seek_to_key(key);
+        iter
+    }
+
+    /// Creates an iterator over a block decoded in place and seek to the first entry.
+    pub fn create_and_seek_to_first_ref(block: Arc<BlockRef>) -> Self {
+        let mut iter = Self::new(BlockHandle::Shared(block));
+        iter.seek_to_first();
+        iter
+    }
+
+    /// Creates an iterator over a block decoded in place and seek to the first key that >= `key`.
+    pub fn create_and_seek_to_key_ref(block: Arc<BlockRef>, key: KeySlice) -> Self {
+        let mut iter = Self::new(BlockHandle::Shared(block));
         iter.seek_to_key(key);
         iter
     }
@@ -68,7 +184,32 @@ impl BlockIterator {
     /// Returns the value of the current entry.
     pub fn value(&self) -> &[u8] {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
-        &self.block.data[self.value_range.0..self.value_range.1]
+        &self.block.data()[self.value_range.0..self.value_range.1]
+    }
+
+    /// Returns the flags of the current entry, see [`super::ENTRY_FLAG_MERGE_OPERAND`]. For blocks
+    /// in a format not storing them, only [`super::ENTRY_FLAG_TOMBSTONE`] may be set, implied by an
+    /// empty value.
+    pub fn flags(&self) -> u8 {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.flags
+    }
+
+    /// Whether the current entry is a tombstone, see [`ENTRY_FLAG_TOMBSTONE`].
+    pub fn is_tombstone(&self) -> bool {
+        self.flags() & ENTRY_FLAG_TOMBSTONE != 0
+    }
+
+    /// Returns the value of the current entry as [`Bytes`], never copied: it shares the
+    /// allocation of the block data, e.g. a block from the block cache or the buffer a
+    /// [`BlockRef`] is decoded from, which stays in memory as long as the value does.
+    pub fn value_bytes(&self) -> Bytes {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        let range = self.value_range.0..self.value_range.1;
+        match &self.block {
+            BlockHandle::Owned(block) => block.data.slice(range),
+            BlockHandle::Shared(block) => block.raw.slice(range),
+        }
     }
 
     /// Returns true if the iterator is valid.
@@ -81,52 +222,134 @@ impl BlockIterator {
         self.seek_to(0);
     }
 
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        match self.block.num_entries().checked_sub(1) {
+            Some(idx) => self.seek_to(idx),
+            None => self.seek_to(0),
+        }
+    }
+
     /// Seeks to the idx-th key in the block.
     fn seek_to(&mut self, idx: usize) {
-        if idx >= self.block.offsets.len() {
+        if idx >= self.block.num_entries() {
             self.key.clear();
             self.value_range = (0, 0);
             return;
         }
-        let offset = self.block.offsets[idx] as usize;
+        let offset = self.block.offset(idx);
         self.seek_to_offset(offset);
         self.idx = idx;
     }
 
+    /// Seeks to the `idx`-th entry in the block, or past the end if there is no such entry.
+    pub fn seek_to_index(&mut self, idx: usize) {
+        self.seek_to(idx);
+    }
+
+    /// The index of the current entry in the block.
+    pub fn index(&self) -> usize {
+        self.idx
+    }
+
+    /// The offset of the current entry within the block data, which together with the offset of
+    /// the block in the file locates the entry, see [`crate::table::BlockMeta::offset`].
+    pub fn current_offset(&self) -> usize {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.block.offset(self.idx)
+    }
+
     /// Move to the next key in the block.
     pub fn next(&mut self) {
         self.idx += 1;
         self.seek_to(self.idx);
     }
 
+    /// Move to the previous key in the block, for iterating in descending order. The iterator
+    /// becomes invalid when moving back from the first key.
+    pub fn prev(&mut self) {
+        match self.idx.checked_sub(1) {
+            Some(idx) => self.seek_to(idx),
+            None => self.seek_to(self.block.num_entries()),
+        }
+    }
+
     /// Seek to the specified position and update the current `key` and `value`
     /// Index update will be handled by caller
     fn seek_to_offset(&mut self, offset: usize) {
-        let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
-        // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
-        let key = &entry[..key_len];
-        self.key.clear();
-        self.key.append(&self.first_key.key_ref()[..overlap_len]);
-        self.key.append(key);
-        entry.advance(key_len);
-        let ts = entry.get_u64();
-        self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
+        let mut key = std::mem::take(&mut self.key);
+        let mut value_len_offset = self.decode_key_at_offset(offset, &mut key);
+        self.key = key;
+        self.flags = 0;
+        if self.block.has_flags() {
+            self.flags = self.block.data()[value_len_offset];
+            value_len_offset += 1;
+        }
+        let value_len = (&self.block.data()[value_len_offset..]).get_u16() as usize;
         // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        let value_offset_begin = value_len_offset + SIZEOF_U16;
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
-        entry.advance(value_len);
+        if !self.block.has_tombstone_flag() {
+            self.flags |= implied_entry_flags(self.value());
+        }
+    }
+
+    /// Decodes the key of the entry at `offset` into `key`, and returns the offset of what
+    /// follows the key: the flags of the entry if the block stores them, else the value length.
+    fn decode_key_at_offset(&self, offset: usize, key: &mut KeyVec) -> usize {
+        let mut entry = &self.block.data()[offset..];
+        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // we don't need to manually advance it
+        let key_len_width = self.block.key_len_width();
+        let overlap_len = get_key_len(&mut entry, key_len_width);
+        let key_len = get_key_len(&mut entry, key_len_width);
+        key.clear();
+        key.append(&self.first_key.key_ref()[..overlap_len]);
+        key.append(&entry[..key_len]);
+        entry.advance(key_len);
+        let (ts, ts_len) = if self.block.has_ts() {
+            (entry.get_u64(), std::mem::size_of::<u64>())
+        } else {
+            (TS_DEFAULT, 0)
+        };
+        key.set_ts(ts);
+        offset + 2 * key_len_width as usize + key_len + ts_len
+    }
+
+    /// Returns the key of the `idx`-th entry in the block without moving the iterator, e.g. to
+    /// look ahead of the current entry.
+    ///
+    /// Panics if `idx` is out of range.
+    pub fn key_at(&self, idx: usize) -> KeyVec {
+        assert!(
+            idx < self.block.num_entries(),
+            "entry index {} out of range",
+            idx
+        );
+        let mut key = KeyVec::new();
+        self.decode_key_at_offset(self.block.offset(idx), &mut key);
+        key
     }
 
     /// Seek to the first key that is >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) {
+        if self.block.has_full_keys() {
+            // compare the keys in place, and only decode the entry found
+            let (mut low, mut high) = (0, self.block.num_entries());
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if self.block.full_key_at(mid) < key {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            self.seek_to(low);
+            return;
+        }
         let mut low = 0;
-        let mut high = self.block.offsets.len();
+        let mut high = self.block.num_entries();
         while low < high {
             let mid = low + (high - low) / 2;
             self.seek_to(mid);
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The source of wall-clock time for time-based policies of the storage engine, e.g. periodic
+/// compaction. Tests use a [`MockClock`] to fast-forward time.
+pub trait Clock: Send + Sync {
+    /// Seconds since the Unix epoch.
+    fn now_secs(&self) -> u64;
+}
+
+impl std::fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Clock({})", self.now_secs())
+    }
+}
+
+/// The system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs())
+    }
+}
+
+/// A clock that only moves when told to.
+pub struct MockClock {
+    now_secs: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now_secs: u64) -> Self {
+        Self {
+            now_secs: AtomicU64::new(now_secs),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now_secs
+            .fetch_add(duration.as_secs(), Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_secs(&self) -> u64 {
+        self.now_secs.load(Ordering::SeqCst)
+    }
+}
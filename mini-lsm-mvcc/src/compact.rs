@@ -1,29 +1,59 @@
+mod delete_triggered;
+mod filter;
+mod iterator;
 mod leveled;
+mod merge_build;
+mod plan;
+mod pool;
+mod priority;
 mod simple_leveled;
+mod stats;
+mod streaming;
 mod tiered;
+mod writer;
 
 use std::collections::HashSet;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use delete_triggered::KeyRange;
+pub(crate) use delete_triggered::{TombstoneReports, TombstoneRun};
+pub use filter::{CompactionEntryFilter, FilterDecision};
+pub use iterator::{CompactionIterOptions, CompactionIterStats, CompactionIterator};
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+pub use merge_build::{merge_build, MergeBuildOptions};
+pub use plan::{CompactionPlan, CompactionPlanLevel};
+pub(crate) use pool::SubcompactionPool;
+pub use priority::{CompactionPriority, LevelCompactionDebt, STALL_IMMINENT_SCORE};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
+pub(crate) use stats::StatsCollector;
+pub use stats::{BackgroundJobKind, BackgroundJobStats, CompactionStats};
+pub use streaming::StreamingCompactionIterator;
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
+use writer::CompactionWriter;
 
+use crate::block::implied_entry_flags;
+use crate::event_listener::{CompactionJobInfo, CompactionProgress};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState, ShardedStorageState};
 use crate::manifest::ManifestRecord;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::table::{SsTable, SsTableIterator};
+use crate::trace::{self, trace_span, Span, SpanContext};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The number of input entries a compaction reads between two progress updates.
+const PROGRESS_REPORT_ENTRIES: u64 = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
@@ -32,6 +62,14 @@ pub enum CompactionTask {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
     },
+    /// Rewrites an SST that is older than `periodic_compaction_seconds` in place, i.e. the output
+    /// replaces it in the same level or tier.
+    Periodic {
+        /// The level of the SST, 0 for tiered compaction.
+        level: usize,
+        sst_id: usize,
+        is_bottom_level: bool,
+    },
 }
 
 impl CompactionTask {
@@ -41,6 +79,111 @@ impl CompactionTask {
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            CompactionTask::Periodic {
+                is_bottom_level, ..
+            } => *is_bottom_level,
+        }
+    }
+
+    /// Returns the level the output of this task is written to, 0 for tiered compaction.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(_) => 0,
+            CompactionTask::Periodic { level, .. } => *level,
+        }
+    }
+
+    /// Returns the levels read by this task, or the ids of the input tiers for tiered compaction.
+    fn input_levels(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => vec![0, 1],
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            }) => vec![upper_level.unwrap_or(0), *lower_level],
+            CompactionTask::Tiered(task) => task.tiers.iter().map(|(id, _)| *id).collect(),
+            CompactionTask::Periodic { level, .. } => vec![*level],
+        }
+    }
+
+    /// Returns the ids of all SSTs consumed by this task.
+    pub fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, files)| files)
+                .copied()
+                .collect(),
+            CompactionTask::Periodic { sst_id, .. } => vec![*sst_id],
+        }
+    }
+
+    /// Returns the ids of the SSTs consumed by this task grouped into sorted runs, from the
+    /// newest, e.g. each L0 SST on its own followed by L1.
+    fn input_runs(&self) -> Vec<&[usize]> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables
+                .iter()
+                .map(std::slice::from_ref)
+                .chain([l1_sstables.as_slice()])
+                .collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => match upper_level {
+                Some(_) => vec![
+                    upper_level_sst_ids.as_slice(),
+                    lower_level_sst_ids.as_slice(),
+                ],
+                None => upper_level_sst_ids
+                    .iter()
+                    .map(std::slice::from_ref)
+                    .chain([lower_level_sst_ids.as_slice()])
+                    .collect(),
+            },
+            CompactionTask::Tiered(task) => {
+                task.tiers.iter().map(|(_, ids)| ids.as_slice()).collect()
+            }
+            CompactionTask::Periodic { sst_id, .. } => vec![std::slice::from_ref(sst_id)],
         }
     }
 }
@@ -53,21 +196,70 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    pub fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
+    /// Generates a compaction task that does not contain any of the `busy` SSTs, together with
+    /// the reason it was picked.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(CompactionTask, String)> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
-                .map(CompactionTask::Leveled),
+                .generate_compaction_task_excluding(snapshot, busy)
+                .map(|(task, reason)| (CompactionTask::Leveled(task), reason)),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
-                .map(CompactionTask::Simple),
+                .generate_compaction_task_excluding(snapshot, busy)
+                .map(|(task, reason)| (CompactionTask::Simple(task), reason)),
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
-                .map(CompactionTask::Tiered),
+                .generate_compaction_task_excluding(snapshot, busy)
+                .map(|(task, reason)| (CompactionTask::Tiered(task), reason)),
             CompactionController::NoCompaction => unreachable!(),
         }
     }
 
+    /// Generates a task moving the SSTs overlapping `range` one level closer to the bottom level,
+    /// or `None` if none is left above it. Tiered compaction does not compact key ranges.
+    fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        range: &KeyRange,
+    ) -> Option<CompactionTask> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl
+                .generate_range_compaction_task(snapshot, range)
+                .map(CompactionTask::Leveled),
+            CompactionController::Simple(ctrl) => ctrl
+                .generate_range_compaction_task(snapshot, range)
+                .map(CompactionTask::Simple),
+            CompactionController::Tiered(_) | CompactionController::NoCompaction => None,
+        }
+    }
+
+    /// Computes how much compaction work each level is behind on. Empty without compaction.
+    pub fn compaction_debt(&self, snapshot: &LsmStorageState) -> Vec<LevelCompactionDebt> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.compaction_debt(snapshot),
+            CompactionController::Simple(ctrl) => ctrl.compaction_debt(snapshot),
+            CompactionController::Tiered(ctrl) => ctrl.compaction_debt(snapshot),
+            CompactionController::NoCompaction => Vec::new(),
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -75,6 +267,16 @@ impl CompactionController {
         output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
         match (self, task) {
+            (_, CompactionTask::Periodic { sst_id, .. }) => {
+                apply_periodic_compaction_result(snapshot, *sst_id, output)
+            }
+            (
+                _,
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                },
+            ) => apply_full_compaction_result(snapshot, l0_sstables, l1_sstables, output),
             (CompactionController::Leveled(ctrl), CompactionTask::Leveled(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
@@ -89,6 +291,48 @@ impl CompactionController {
     }
 }
 
+/// Replaces L1 with `output` and removes the compacted SSTs from L0. SSTs flushed during the
+/// compaction are newer than its output and stay in L0.
+fn apply_full_compaction_result(
+    snapshot: &LsmStorageState,
+    l0_sstables: &[usize],
+    l1_sstables: &[usize],
+    output: &[usize],
+) -> (LsmStorageState, Vec<usize>) {
+    let mut snapshot = snapshot.clone();
+    assert_eq!(l1_sstables, snapshot.levels[0].1);
+    snapshot.levels[0].1 = output.to_vec();
+    let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
+    snapshot.l0_sstables = snapshot
+        .l0_sstables
+        .iter()
+        .filter(|x| !l0_sstables_map.remove(x))
+        .copied()
+        .collect::<Vec<_>>();
+    assert!(l0_sstables_map.is_empty());
+    let files_to_remove = l0_sstables.iter().chain(l1_sstables).copied().collect();
+    (snapshot, files_to_remove)
+}
+
+/// Replaces `sst_id` with `output` at the same position of its level or tier. The output of a
+/// periodic compaction covers a subset of the key range of its input, so levels stay sorted.
+fn apply_periodic_compaction_result(
+    snapshot: &LsmStorageState,
+    sst_id: usize,
+    output: &[usize],
+) -> (LsmStorageState, Vec<usize>) {
+    let mut snapshot = snapshot.clone();
+    let ssts = snapshot
+        .levels
+        .iter_mut()
+        .map(|(_, ssts)| ssts)
+        .find(|ssts| ssts.contains(&sst_id))
+        .unwrap_or_else(|| panic!("SST {} not found in any level", sst_id));
+    let idx = ssts.iter().position(|id| *id == sst_id).unwrap();
+    ssts.splice(idx..=idx, output.iter().copied());
+    (snapshot, vec![sst_id])
+}
+
 impl CompactionController {
     pub fn flush_to_l0(&self) -> bool {
         matches!(
@@ -111,103 +355,432 @@ pub enum CompactionOptions {
     NoCompaction,
 }
 
+impl CompactionOptions {
+    /// Checks that the engine can switch from these options to `new` while running. The
+    /// strategy and the number of levels are fixed when the engine is opened, while the other
+    /// options can be tuned as long as they are valid.
+    pub(crate) fn validate_change(&self, new: &CompactionOptions) -> Result<()> {
+        match (self, new) {
+            (CompactionOptions::Leveled(old), CompactionOptions::Leveled(new)) => {
+                if old.max_levels != new.max_levels {
+                    bail!("max_levels cannot be changed at runtime");
+                }
+                if new.level_size_multiplier < 2 {
+                    bail!("level_size_multiplier must be at least 2");
+                }
+                if new.level0_file_num_compaction_trigger == 0 {
+                    bail!("level0_file_num_compaction_trigger must be positive");
+                }
+                if new.base_level_size_mb == 0 {
+                    bail!("base_level_size_mb must be positive");
+                }
+            }
+            (CompactionOptions::Simple(old), CompactionOptions::Simple(new)) => {
+                if old.max_levels != new.max_levels {
+                    bail!("max_levels cannot be changed at runtime");
+                }
+                if new.size_ratio_percent == 0 {
+                    bail!("size_ratio_percent must be positive");
+                }
+                if new.level0_file_num_compaction_trigger == 0 {
+                    bail!("level0_file_num_compaction_trigger must be positive");
+                }
+            }
+            (CompactionOptions::Tiered(_), CompactionOptions::Tiered(new)) => {
+                if new.num_tiers == 0 {
+                    bail!("num_tiers must be positive");
+                }
+                if new.min_merge_width < 2 {
+                    bail!("min_merge_width must be at least 2");
+                }
+            }
+            (CompactionOptions::NoCompaction, CompactionOptions::NoCompaction) => {}
+            (old, new) => bail!(
+                "cannot switch the compaction strategy at runtime from {:?} to {:?}",
+                old,
+                new
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Tracks consecutive compaction failures to back off retries.
+#[derive(Debug, Default)]
+pub(crate) struct CompactionBackoff {
+    consecutive_failures: usize,
+    /// No compaction is attempted before this time.
+    retry_at: Option<Instant>,
+    /// Set after `max_compaction_failures` consecutive failures, cleared by the next success.
+    background_error: Option<String>,
+}
+
 impl LsmStorageInner {
+    /// Writes the merged input into new SSTs. For each user key, all versions above the watermark
+    /// are kept together with the newest version at or below it, which is dropped as well if it is
+    /// a tombstone and the task compacts into the bottom level. Versions covered by one of
+    /// `range_tombstones` at or below the watermark are dropped, and so is such a range tombstone
+    /// at the bottom level. Versions of the same user key are never split across two output SSTs.
     fn compact_generate_sst_from_iter(
         &self,
-        mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
-        compact_to_bottom_level: bool,
+        iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+        upper: Option<&[u8]>,
+        range_tombstones: &[RangeTombstone],
+        watermark: u64,
+        task: &CompactionTask,
+        progress: &CompactionProgress,
     ) -> Result<Vec<Arc<SsTable>>> {
-        let mut builder = None;
-        let mut new_sst = Vec::new();
-        let watermark = self.mvcc().watermark();
+        let expired_range_tombstones = FragmentedRangeTombstones::new(
+            range_tombstones
+                .iter()
+                .filter(|tombstone| tombstone.ts <= watermark)
+                .cloned(),
+        );
+        let compact_to_bottom_level = task.compact_to_bottom_level();
+        let kept_range_tombstones = range_tombstones
+            .iter()
+            .filter(|tombstone| !compact_to_bottom_level || tombstone.ts > watermark)
+            .cloned()
+            .collect();
+        let has_entry_flags = {
+            let state = self.state.load();
+            task.input_sst_ids()
+                .iter()
+                .any(|id| state.sstables[id].has_entry_flags())
+        };
+        let mut writer = CompactionWriter::new(self, kept_range_tombstones, has_entry_flags);
+        if let Err(e) = CompactionIterator::create_with_options(
+            iter,
+            upper,
+            watermark,
+            compact_to_bottom_level,
+            CompactionIterOptions {
+                merge_operator: self.options.merge_operator.clone(),
+                expired_range_tombstones: expired_range_tombstones.clone(),
+                now_secs: self.options.clock.now_secs(),
+            },
+        )
+        .and_then(|iter| {
+            self.compact_generate_sst_from_iter_inner(
+                iter,
+                &expired_range_tombstones,
+                watermark,
+                task,
+                progress,
+                &mut writer,
+            )
+        })
+        .and_then(|()| writer.finish())
+        .and_then(|()| self.check_compaction_abandoned())
+        {
+            // do not leave partially written outputs behind
+            self.remove_sst_files(writer.outputs());
+            return Err(e);
+        }
+        Ok(writer.into_outputs())
+    }
+
+    fn compact_generate_sst_from_iter_inner<
+        I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+    >(
+        &self,
+        mut iter: CompactionIterator<I>,
+        expired_range_tombstones: &FragmentedRangeTombstones,
+        watermark: u64,
+        task: &CompactionTask,
+        progress: &CompactionProgress,
+        writer: &mut CompactionWriter,
+    ) -> Result<()> {
         let mut last_key = Vec::<u8>::new();
-        let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
+        let compact_to_bottom_level = task.compact_to_bottom_level();
+        let output_level = task.output_level();
+        let newest_snapshot_ts = self.mvcc().newest_snapshot_ts();
+        let mut reported_entries = 0;
         'outer: while iter.is_valid() {
-            if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+            self.check_compaction_abandoned()?;
+            let read_entries = iter.stats().entries_read();
+            if read_entries - reported_entries >= PROGRESS_REPORT_ENTRIES {
+                progress.advance(read_entries - reported_entries);
+                reported_entries = read_entries;
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
+            let mut value_override = None;
             if !same_as_last_key {
-                first_key_below_watermark = true;
-            }
-
-            if compact_to_bottom_level
-                && !same_as_last_key
-                && iter.key().ts() <= watermark
-                && iter.value().is_empty()
-            {
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
-                iter.next()?;
-                first_key_below_watermark = false;
-                continue;
             }
 
-            if iter.key().ts() <= watermark {
-                if same_as_last_key && !first_key_below_watermark {
-                    iter.next()?;
-                    continue;
-                }
-
-                first_key_below_watermark = false;
-
-                if !compaction_filters.is_empty() {
-                    for filter in &compaction_filters {
-                        match filter {
-                            CompactionFilter::Prefix(x) => {
-                                if iter.key().key_ref().starts_with(x) {
-                                    iter.next()?;
-                                    continue 'outer;
-                                }
+            if iter.key().ts() <= watermark && !compaction_filters.is_empty() {
+                for filter in &compaction_filters {
+                    match filter {
+                        CompactionFilter::Prefix(x) => {
+                            if iter.key().key_ref().starts_with(x) {
+                                iter.next()?;
+                                continue 'outer;
                             }
                         }
                     }
                 }
             }
 
-            let builder_inner = builder.as_mut().unwrap();
-
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
-                let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
-                new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+            if expired_range_tombstones.covers(iter.key().key_ref(), iter.key().ts()) {
+                iter.next()?;
+                continue;
             }
 
-            let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
-
-            if !same_as_last_key {
-                last_key.clear();
-                last_key.extend(iter.key().key_ref());
+            // Only the latest version of a key that no active snapshot can see is passed to the
+            // entry filter, so that snapshots never observe the change. Entries with flags, i.e.
+            // merge operands and values with an expiry time, are not.
+            if let Some(entry_filter) = &self.options.compaction_entry_filter {
+                if !same_as_last_key
+                    && !iter.is_tombstone()
+                    && iter.flags() == 0
+                    && !matches!(newest_snapshot_ts, Some(ts) if iter.key().ts() <= ts)
+                {
+                    match entry_filter.filter(output_level, iter.key().key_ref(), iter.value()) {
+                        FilterDecision::Keep => {}
+                        // Older versions below the watermark are dropped as well, so no
+                        // tombstone is needed to shadow them.
+                        FilterDecision::Remove
+                            if compact_to_bottom_level && iter.key().ts() <= watermark =>
+                        {
+                            iter.next()?;
+                            continue;
+                        }
+                        FilterDecision::Remove => value_override = Some(Bytes::new()),
+                        FilterDecision::ChangeValue(value) => value_override = Some(value),
+                    }
+                }
             }
 
+            // the entries passed to the filter have no flags, and an empty value set by the
+            // filter is a tombstone
+            let (value, flags) = match &value_override {
+                Some(value) => (value.as_ref(), implied_entry_flags(value)),
+                None => (iter.value(), iter.flags()),
+            };
+            writer.add(iter.key(), value, flags, !same_as_last_key)?;
+
             iter.next()?;
         }
-        if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
-            new_sst.push(sst);
+        progress.advance(iter.stats().entries_read() - reported_entries);
+        Ok(())
+    }
+
+    /// Picks user keys that split the key space of a compaction task into roughly equal-sized
+    /// sub-ranges, using the block boundaries of the input SSTs. All versions of a user key always
+    /// fall into the same sub-range.
+    pub(crate) fn subcompaction_split_points(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Vec<Bytes> {
+        if self.options.max_subcompactions <= 1 {
+            return Vec::new();
+        }
+        let mut block_keys = task
+            .input_sst_ids()
+            .iter()
+            .flat_map(|id| snapshot.sstables[id].block_meta.iter())
+            .map(|meta| meta.first_key.key_ref())
+            .collect::<Vec<_>>();
+        block_keys.sort();
+        block_keys.dedup();
+        let num_ranges = self.options.max_subcompactions.min(block_keys.len());
+        (1..num_ranges)
+            .map(|i| Bytes::copy_from_slice(block_keys[i * block_keys.len() / num_ranges]))
+            .collect()
+    }
+
+    /// Runs a compaction task and returns its output SSTs. The progress is reported to the event
+    /// listeners under `job_id`, and the output SSTs are traced under `span`.
+    pub(crate) fn compact(
+        self: &Arc<Self>,
+        task: &CompactionTask,
+        job_id: u64,
+        span: &Span,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let snapshot = self.state.load_full();
+        let watermark = self.mvcc().watermark();
+        let total_entries = task
+            .input_sst_ids()
+            .iter()
+            .map(|id| snapshot.sstables[id].properties().num_entries)
+            .sum();
+        let progress = Arc::new(CompactionProgress::new(
+            job_id,
+            self.options.event_listeners.clone(),
+            total_entries,
+        ));
+        let split_points = self.subcompaction_split_points(&snapshot, task);
+        if split_points.is_empty() {
+            return self.compact_range(&snapshot, task, None, None, watermark, &progress);
+        }
+
+        let mut ranges = Vec::with_capacity(split_points.len() + 1);
+        let mut lower = None;
+        for split_point in split_points {
+            ranges.push((lower, Some(split_point.clone())));
+            lower = Some(split_point);
+        }
+        ranges.push((lower, None));
+
+        let task = Arc::new(task.clone());
+        let context = SpanContext::new(span);
+        let receivers = ranges
+            .into_iter()
+            .map(|(lower, upper)| {
+                let this = self.clone();
+                let snapshot = snapshot.clone();
+                let task = task.clone();
+                let progress = progress.clone();
+                let context = context.clone();
+                self.subcompaction_pool.spawn(move || {
+                    // the output SSTs are traced under the compaction span
+                    context.in_scope(|| {
+                        this.compact_range(
+                            &snapshot,
+                            &task,
+                            lower.as_deref(),
+                            upper.as_deref(),
+                            watermark,
+                            &progress,
+                        )
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = receivers.into_iter().map(|receiver| {
+            receiver
+                .recv()
+                .unwrap_or_else(|_| Err(anyhow!("sub-compaction was dropped before it ran")))
+        });
+
+        let mut new_sst = Vec::new();
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(ssts) => new_sst.extend(ssts),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = error {
+            self.remove_sst_files(&new_sst);
+            return Err(e);
         }
         Ok(new_sst)
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
+    fn remove_sst_files(&self, ssts: &[Arc<SsTable>]) {
+        for sst in ssts {
+            std::fs::remove_file(self.path_of_sst(sst.sst_id())).ok();
+        }
+    }
+
+    /// Collects the statistics of a finished compaction task. `snapshot` must contain the input
+    /// SSTs of the task.
+    fn compaction_job_stats(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        output: &[Arc<SsTable>],
+        duration: Duration,
+    ) -> BackgroundJobStats {
+        let inputs = task
+            .input_sst_ids()
+            .iter()
+            .map(|id| snapshot.sstables[id].clone())
+            .collect::<Vec<_>>();
+        let entries_read = inputs.iter().map(|x| x.properties().num_entries).sum();
+        let entries_written = output.iter().map(|x| x.properties().num_entries).sum();
+        BackgroundJobStats {
+            kind: BackgroundJobKind::Compaction,
+            input_levels: task.input_levels(),
+            output_level: task.output_level(),
+            input_files: inputs.len(),
+            input_bytes: inputs.iter().map(|x| x.table_size()).sum(),
+            output_files: output.len(),
+            output_bytes: output.iter().map(|x| x.table_size()).sum(),
+            entries_read,
+            entries_written,
+            entries_dropped: entries_read.saturating_sub(entries_written),
+            duration,
+        }
+    }
+
+    /// Compacts the user key range `[lower, upper)` of a task, where `None` means unbounded.
+    fn compact_range(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        watermark: u64,
+        progress: &CompactionProgress,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let rate_limiter = Some(self.rate_limiter.clone());
+        let seek_table = |id: &usize| match lower {
+            Some(key) => SsTableIterator::create_and_seek_to_key_with_rate_limiter(
+                snapshot.sstables[id].clone(),
+                KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                rate_limiter.clone(),
+            ),
+            None => SsTableIterator::create_and_seek_to_first_with_rate_limiter(
+                snapshot.sstables[id].clone(),
+                rate_limiter.clone(),
+            ),
+        };
+        let seek_concat = |ids: &[usize]| {
+            let ssts = ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>();
+            match lower {
+                Some(key) => SstConcatIterator::create_and_seek_to_key_with_rate_limiter(
+                    ssts,
+                    KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                    rate_limiter.clone(),
+                ),
+                None => SstConcatIterator::create_and_seek_to_first_with_rate_limiter(
+                    ssts,
+                    rate_limiter.clone(),
+                ),
+            }
         };
+        let mut range_tombstones = task
+            .input_sst_ids()
+            .iter()
+            .flat_map(|id| snapshot.sstables[id].range_tombstones())
+            .filter_map(|tombstone| tombstone.clip(lower, upper))
+            .collect::<Vec<_>>();
+        // the shards each flush a copy of a range tombstone, keep one
+        range_tombstones.sort_by(|a, b| (&a.start, &a.end, a.ts).cmp(&(&b.start, &b.end, b.ts)));
+        range_tombstones.dedup();
+        if self.options.compaction_block_budget > 0 {
+            let runs = task
+                .input_runs()
+                .into_iter()
+                .map(|ids| ids.iter().map(|id| snapshot.sstables[id].clone()).collect())
+                .collect();
+            let iter = StreamingCompactionIterator::create(
+                runs,
+                lower.map(|key| KeySlice::from_slice(key, TS_RANGE_BEGIN)),
+                self.options.compaction_block_budget,
+                rate_limiter.clone(),
+            )?;
+            return self.compact_generate_sst_from_iter(
+                iter,
+                upper,
+                &range_tombstones,
+                watermark,
+                task,
+                progress,
+            );
+        }
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -215,19 +788,20 @@ impl LsmStorageInner {
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
                 for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
+                    l0_iters.push(Box::new(seek_table(id)?));
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    seek_concat(l1_sstables)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    upper,
+                    &range_tombstones,
+                    watermark,
+                    task,
+                    progress,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -244,200 +818,451 @@ impl LsmStorageInner {
                 ..
             }) => match upper_level {
                 Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iter = seek_concat(upper_level_sst_ids)?;
+                    let lower_iter = seek_concat(lower_level_sst_ids)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        upper,
+                        &range_tombstones,
+                        watermark,
+                        task,
+                        progress,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
                     for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
+                        upper_iters.push(Box::new(seek_table(id)?));
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = seek_concat(lower_level_sst_ids)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        upper,
+                        &range_tombstones,
+                        watermark,
+                        task,
+                        progress,
                     )
                 }
             },
+            CompactionTask::Periodic { sst_id, .. } => self.compact_generate_sst_from_iter(
+                seek_table(sst_id)?,
+                upper,
+                &range_tombstones,
+                watermark,
+                task,
+                progress,
+            ),
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
                 let mut iters = Vec::with_capacity(tiers.len());
                 for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(seek_concat(tier_sst_ids)?));
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
-                    task.compact_to_bottom_level(),
+                    upper,
+                    &range_tombstones,
+                    watermark,
+                    task,
+                    progress,
                 )
             }
         }
     }
 
-    pub fn force_full_compaction(&self) -> Result<()> {
+    /// Compacts all SSTs in L0 and L1 into L1. Foreground writes are not blocked while the
+    /// inputs are merged: only the SSTs in the state at the start are compacted, and the state
+    /// lock is taken only to install the result, so SSTs flushed in the meantime stay in L0.
+    pub fn force_full_compaction(self: &Arc<Self>) -> Result<()> {
+        self.check_writable()?;
         let CompactionOptions::NoCompaction = self.options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
 
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
+        let (snapshot, compaction_task) = {
+            let mut busy = self.compacting_ssts.lock();
+            let snapshot = self.state.load_full();
+            let compaction_task = CompactionTask::ForceFullCompaction {
+                l0_sstables: snapshot.l0_sstables.clone(),
+                l1_sstables: snapshot.levels[0].1.clone(),
+            };
+            let input_sst_ids = compaction_task.input_sst_ids();
+            if input_sst_ids.iter().any(|id| busy.contains(id)) {
+                bail!("another full compaction is running");
+            }
+            busy.extend(input_sst_ids);
+            (snapshot, compaction_task)
         };
+        let input_sst_ids = compaction_task.input_sst_ids();
+        let result = self.run_full_compaction(snapshot, compaction_task);
+        let mut busy = self.compacting_ssts.lock();
+        for id in &input_sst_ids {
+            busy.remove(id);
+        }
+        result
+    }
 
-        let l0_sstables = snapshot.l0_sstables.clone();
-        let l1_sstables = snapshot.levels[0].1.clone();
-        let compaction_task = CompactionTask::ForceFullCompaction {
-            l0_sstables: l0_sstables.clone(),
-            l1_sstables: l1_sstables.clone(),
+    fn run_full_compaction(
+        self: &Arc<Self>,
+        snapshot: Arc<ShardedStorageState>,
+        compaction_task: CompactionTask,
+    ) -> Result<()> {
+        println!("force full compaction: {:?}", compaction_task);
+        let job_info = CompactionJobInfo {
+            job_id: self.next_compaction_job_id(),
+            description: format!("{:?}", compaction_task),
+            reason: "force full compaction".to_string(),
+            input_sst_ids: compaction_task.input_sst_ids(),
         };
+        self.notify_listeners(|listener| listener.on_compaction_begin(&job_info));
+        let span = trace_span!(
+            "compaction",
+            job_id = job_info.job_id,
+            task = %job_info.description,
+            input_bytes = tracing::field::Empty,
+            output_bytes = tracing::field::Empty
+        );
+        let _entered = span.enter();
 
-        println!("force full compaction: {:?}", compaction_task);
+        let begin = Instant::now();
+        let sstables = self.compact(&compaction_task, job_info.job_id, &span)?;
+        let job_stats =
+            self.compaction_job_stats(&snapshot, &compaction_task, &sstables, begin.elapsed());
+        span.record("input_bytes", job_stats.input_bytes);
+        span.record("output_bytes", job_stats.output_bytes);
+        let ids = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+        let ssts_to_remove =
+            self.install_compaction_result(compaction_task, sstables, |state, task| {
+                let CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                } = task
+                else {
+                    unreachable!()
+                };
+                apply_full_compaction_result(state, l0_sstables, l1_sstables, &ids)
+            })?;
+        self.stats.record_job(job_stats.clone());
+        self.metrics.record_job(&job_stats);
+        self.notify_listeners(|listener| {
+            listener.on_compaction_complete(&job_info, &ids, &job_stats)
+        });
+        drop(snapshot);
+        self.delete_obsolete_ssts(ssts_to_remove)?;
 
-        let sstables = self.compact(&compaction_task)?;
-        let mut ids = Vec::with_capacity(sstables.len());
+        println!("force full compaction done, new SSTs: {:?}", ids);
 
-        {
-            let state_lock = self.state_lock.lock();
-            let mut state = self.state.read().as_ref().clone();
-            for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-                let result = state.sstables.remove(sst);
-                assert!(result.is_some());
-            }
-            for new_sst in sstables {
-                ids.push(new_sst.sst_id());
-                let result = state.sstables.insert(new_sst.sst_id(), new_sst);
-                assert!(result.is_none());
-            }
-            assert_eq!(l1_sstables, state.levels[0].1);
-            state.levels[0].1 = ids.clone();
-            let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
-            state.l0_sstables = state
-                .l0_sstables
-                .iter()
-                .filter(|x| !l0_sstables_map.remove(x))
-                .copied()
-                .collect::<Vec<_>>();
-            assert!(l0_sstables_map.is_empty());
-            *self.state.write() = Arc::new(state);
-            self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
-                &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
-            )?;
+        Ok(())
+    }
+
+    /// Plans the compaction task the strategy would pick from `snapshot`, skipping the `busy`
+    /// SSTs.
+    fn plan_compaction_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<CompactionPlan> {
+        let compaction_controller = self.compaction_controller.load();
+        if let CompactionController::NoCompaction = **compaction_controller {
+            return None;
         }
-        for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
+        let (task, reason) = compaction_controller
+            .generate_compaction_task_excluding(snapshot, busy)
+            .or_else(|| self.periodic_compaction_task(snapshot, busy))
+            .or_else(|| self.delete_triggered_compaction_task(snapshot, busy))?;
+        Some(CompactionPlan::new(snapshot, task, reason))
+    }
+
+    /// Picks the oldest SST that has exceeded `periodic_compaction_seconds` to be rewritten in
+    /// place. L0 SSTs are skipped, as they are compacted soon anyway, and so are SSTs without a
+    /// creation time.
+    fn periodic_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(CompactionTask, String)> {
+        if self.options.periodic_compaction_seconds == 0 {
+            return None;
         }
+        let now = self.options.clock.now_secs();
+        let tiered = matches!(
+            **self.compaction_controller.load(),
+            CompactionController::Tiered(_)
+        );
+        let num_levels = snapshot.levels.len();
+        let (creation_time, level_idx, sst_id) = snapshot
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level_idx, (_, ssts))| ssts.iter().map(move |id| (level_idx, *id)))
+            .filter(|(_, id)| !busy.contains(id))
+            .map(|(level_idx, id)| {
+                let creation_time = snapshot.sstables[&id].properties().creation_time;
+                (creation_time, level_idx, id)
+            })
+            .filter(|(creation_time, _, _)| {
+                // the age of SSTs written without a creation time is unknown
+                *creation_time != 0
+                    && now.saturating_sub(*creation_time) > self.options.periodic_compaction_seconds
+            })
+            .min()?;
+        let task = CompactionTask::Periodic {
+            level: if tiered { 0 } else { level_idx + 1 },
+            sst_id,
+            is_bottom_level: level_idx + 1 == num_levels,
+        };
+        let reason = format!(
+            "SST {} is {}s old, exceeding the periodic compaction age of {}s",
+            sst_id,
+            now.saturating_sub(creation_time),
+            self.options.periodic_compaction_seconds
+        );
+        Some((task, reason))
+    }
 
-        println!("force full compaction done, new SSTs: {:?}", ids);
+    /// Picks a task compacting a key range where scans skipped many tombstones, see
+    /// [`TombstoneReports`]. Ranges are compacted one level at a time until only the bottom
+    /// level overlaps them.
+    fn delete_triggered_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(CompactionTask, String)> {
+        let reports = self.tombstone_reports.as_ref()?;
+        reports.sweep();
+        for range in reports.queued() {
+            let Some(task) = self
+                .compaction_controller
+                .load()
+                .generate_range_compaction_task(snapshot, &range)
+            else {
+                reports.remove(&range);
+                continue;
+            };
+            if task.input_sst_ids().iter().any(|id| busy.contains(id)) {
+                continue;
+            }
+            let reason = format!(
+                "delete-triggered compaction of [{:?}, {:?}], where scans skipped many tombstones",
+                range.first, range.last
+            );
+            return Some((task, reason));
+        }
+        None
+    }
 
-        Ok(())
+    /// Plans the compaction task that would run next without running it. Only metadata already
+    /// in memory is read.
+    pub fn plan_compaction(&self) -> Option<CompactionPlan> {
+        let busy = self.compacting_ssts.lock();
+        let snapshot = self.state.load_full();
+        self.plan_compaction_excluding(&snapshot, &busy)
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
-        };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
+    /// Picks a compaction task that does not conflict with the tasks running on other workers
+    /// and claims its input SSTs.
+    fn claim_compaction_task(&self) -> Option<(Arc<ShardedStorageState>, CompactionPlan)> {
+        let mut busy = self.compacting_ssts.lock();
+        // Read the state with the claims locked, so that SSTs released by a finished task are
+        // never seen in a state from before that task was installed.
+        let snapshot = self.state.load_full();
+        let plan = self.plan_compaction_excluding(&snapshot, &busy)?;
+        busy.extend(plan.task.input_sst_ids());
+        Some((snapshot, plan))
+    }
+
+    pub(crate) fn trigger_compaction(self: &Arc<Self>) -> Result<()> {
+        let Some((snapshot, plan)) = self.claim_compaction_task() else {
             return Ok(());
         };
+        let input_sst_ids = plan.task.input_sst_ids();
+        let result = self.run_compaction_task(snapshot, plan.task, plan.reason);
+        // release the claims only after the result has been installed
+        let mut busy = self.compacting_ssts.lock();
+        for id in &input_sst_ids {
+            busy.remove(id);
+        }
+        result
+    }
+
+    fn run_compaction_task(
+        self: &Arc<Self>,
+        snapshot: Arc<ShardedStorageState>,
+        task: CompactionTask,
+        reason: String,
+    ) -> Result<()> {
         self.dump_structure();
-        println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
-        let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
-        let ssts_to_remove = {
-            let state_lock = self.state_lock.lock();
-            let mut snapshot = self.state.read().as_ref().clone();
-            let mut new_sst_ids = Vec::new();
-            for file_to_add in sstables {
-                new_sst_ids.push(file_to_add.sst_id());
-                let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
-                assert!(result.is_none());
-            }
-            let (mut snapshot, files_to_remove) = self
-                .compaction_controller
-                .apply_compaction_result(&snapshot, &task, &output);
-            let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
-            for file_to_remove in &files_to_remove {
-                let result = snapshot.sstables.remove(file_to_remove);
-                assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
-                ssts_to_remove.push(result.unwrap());
-            }
-            let mut state = self.state.write();
-            *state = Arc::new(snapshot);
-            drop(state);
-            self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
-            ssts_to_remove
+        println!("running compaction task: {:?}, reason: {}", task, reason);
+        let job_info = CompactionJobInfo {
+            job_id: self.next_compaction_job_id(),
+            description: format!("{:?}", task),
+            reason: reason.clone(),
+            input_sst_ids: task.input_sst_ids(),
         };
+        *self.last_compaction_reason.lock() = Some(reason);
+        self.notify_listeners(|listener| listener.on_compaction_begin(&job_info));
+        let span = trace_span!(
+            "compaction",
+            job_id = job_info.job_id,
+            task = %job_info.description,
+            input_bytes = tracing::field::Empty,
+            output_bytes = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        let begin = Instant::now();
+        let sstables = self.compact(&task, job_info.job_id, &span)?;
+        let job_stats = self.compaction_job_stats(&snapshot, &task, &sstables, begin.elapsed());
+        span.record("input_bytes", job_stats.input_bytes);
+        span.record("output_bytes", job_stats.output_bytes);
+        let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+        let ssts_to_remove = self.install_compaction_result(task, sstables, |state, task| {
+            self.compaction_controller
+                .load()
+                .apply_compaction_result(state, task, &output)
+        })?;
+        self.stats.record_job(job_stats.clone());
+        self.metrics.record_job(&job_stats);
+        self.notify_listeners(|listener| {
+            listener.on_compaction_complete(&job_info, &output, &job_stats)
+        });
         println!(
             "compaction finished: {} files removed, {} files added, output={:?}",
             ssts_to_remove.len(),
             output.len(),
             output
         );
-        for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
-        }
-        self.sync_dir()?;
+        // the snapshot holds the inputs, which would keep their files
+        drop(snapshot);
+        self.delete_obsolete_ssts(ssts_to_remove)?;
 
         Ok(())
     }
 
-    pub(crate) fn spawn_compaction_thread(
+    /// Installs the output SSTs of a compaction, in an order that never loses data or leaves the
+    /// state referencing a missing file if the process crashes in between:
+    ///
+    /// 1. the outputs, each synced when written, are made durable by syncing the directory;
+    /// 2. the manifest record commits the compaction;
+    /// 3. the in-memory state is switched to the outputs;
+    /// 4. the inputs are deleted by the caller, see [`Self::delete_obsolete_ssts`].
+    ///
+    /// A crash before the record is written loses the compaction, and a crash after it leaves the
+    /// inputs behind. Either way, recovery removes the files the manifest does not reference. The
+    /// outputs are also left to recovery if this fails, as the record may have been written.
+    /// `apply` computes the new state and the ids of the inputs to remove from the state with the
+    /// outputs added. Returns the input SSTs removed from the state.
+    fn install_compaction_result(
+        &self,
+        task: CompactionTask,
+        sstables: Vec<Arc<SsTable>>,
+        apply: impl FnOnce(&LsmStorageState, &CompactionTask) -> (LsmStorageState, Vec<usize>),
+    ) -> Result<Vec<Arc<SsTable>>> {
+        self.failpoint("compaction_outputs_written")?;
+        self.sync_dir()?;
+        self.failpoint("compaction_dir_synced")?;
+
+        let state_lock = self.state_lock.lock();
+        let mut snapshot = self.state.load().as_ref().clone();
+        let mut new_sst_ids = Vec::with_capacity(sstables.len());
+        for file_to_add in sstables {
+            new_sst_ids.push(file_to_add.sst_id());
+            let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
+            assert!(result.is_none());
+        }
+        let (state, files_to_remove) = apply(&snapshot, &task);
+        snapshot.state = state;
+        let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
+        for file_to_remove in &files_to_remove {
+            let result = snapshot.sstables.remove(file_to_remove);
+            assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
+            ssts_to_remove.push(result.unwrap());
+        }
+        self.manifest()
+            .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+        self.failpoint("compaction_manifest_written")?;
+        self.state.store(Arc::new(snapshot));
+        drop(state_lock);
+        self.write_controller.notify_progress();
+        self.failpoint("compaction_state_installed")?;
+        Ok(ssts_to_remove)
+    }
+
+    /// Runs a compaction task unless a previous failure is still being backed off. A failed task
+    /// leaves no output behind and is retried after `compaction_retry_backoff`, doubled on every
+    /// consecutive failure. After `max_compaction_failures` consecutive failures, the error is
+    /// reported by [`Self::background_error`] until a compaction succeeds.
+    pub(crate) fn trigger_compaction_with_backoff(self: &Arc<Self>) -> Result<()> {
+        if let Some(retry_at) = self.compaction_backoff.lock().retry_at {
+            if Instant::now() < retry_at {
+                return Ok(());
+            }
+        }
+        let result = self.trigger_compaction();
+        if let Err(e) = &result {
+            trace::background_error("compaction", e);
+        }
+        let mut backoff = self.compaction_backoff.lock();
+        match &result {
+            Ok(()) => *backoff = CompactionBackoff::default(),
+            Err(e) => {
+                self.stats.record_compaction_failure(e);
+                backoff.consecutive_failures += 1;
+                let delay = self.options.compaction_retry_backoff
+                    * (1 << (backoff.consecutive_failures - 1).min(6));
+                backoff.retry_at = Some(Instant::now() + delay);
+                if backoff.consecutive_failures >= self.options.max_compaction_failures {
+                    backoff.background_error = Some(format!(
+                        "{} consecutive compaction failures, last error: {:#}",
+                        backoff.consecutive_failures, e
+                    ));
+                }
+            }
+        }
+        result
+    }
+
+    /// The background error, if compaction keeps failing. Compaction is still retried, but L0
+    /// will grow until it succeeds again.
+    pub fn background_error(&self) -> Option<String> {
+        self.compaction_backoff.lock().background_error.clone()
+    }
+
+    /// Spawns `num_compaction_threads` workers, each picking and running compaction tasks that do
+    /// not conflict with the others. Every worker stops after receiving one message from `rx`.
+    pub(crate) fn spawn_compaction_threads(
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+    ) -> Result<Vec<std::thread::JoinHandle<()>>> {
+        let mut handles = Vec::new();
         if let CompactionOptions::Leveled(_)
         | CompactionOptions::Simple(_)
         | CompactionOptions::Tiered(_) = self.options.compaction_options
         {
-            let this = self.clone();
-            let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+            for _ in 0..self.options.num_compaction_threads.max(1) {
+                let this = self.clone();
+                let rx = rx.clone();
+                handles.push(std::thread::spawn(move || {
+                    let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                    loop {
+                        crossbeam_channel::select! {
+                            recv(ticker) -> _ => if !this.is_background_compaction_paused() {
+                                if let Err(e) = this.trigger_compaction_with_backoff() {
+                                    eprintln!("compaction failed: {}", e);
+                                }
+                            },
+                            recv(rx) -> _ => return
+                        }
                     }
-                }
-            });
-            return Ok(Some(handle));
+                }));
+            }
         }
-        Ok(None)
+        Ok(handles)
     }
 
     fn trigger_flush(&self) -> Result<()> {
         let res = {
-            let state = self.state.read();
+            let state = self.state.load();
             state.imm_memtables.len() >= self.options.num_memtable_limit
         };
         if res {
@@ -457,6 +1282,7 @@ impl LsmStorageInner {
             loop {
                 crossbeam_channel::select! {
                     recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
+                        trace::background_error("flush", &e);
                         eprintln!("flush failed: {}", e);
                     },
                     recv(rx) -> _ => return
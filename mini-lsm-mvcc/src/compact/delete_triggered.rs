@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::table::SsTable;
+
+/// A user key range `[first, last]`, both ends included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KeyRange {
+    pub(crate) first: Bytes,
+    pub(crate) last: Bytes,
+}
+
+impl KeyRange {
+    pub(crate) fn overlaps_sst(&self, sst: &SsTable) -> bool {
+        sst.first_key_bytes() <= &self.last[..] && sst.last_key_bytes() >= &self.first[..]
+    }
+
+    fn overlaps(&self, other: &KeyRange) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+/// Feedback from scans to the compaction scheduler. A scan that skips at least `threshold`
+/// tombstones in a row before yielding a key reports the range of those tombstones. The
+/// compaction threads periodically sweep the reports into a queue of non-overlapping ranges,
+/// which are compacted down to the bottom level when there is no other compaction work, so that
+/// the tombstones are dropped.
+pub(crate) struct TombstoneReports {
+    threshold: usize,
+    num_reports: AtomicU64,
+    skipped_tombstones: AtomicU64,
+    /// Ranges reported since the last sweep.
+    reported: Mutex<Vec<KeyRange>>,
+    /// Ranges waiting to be compacted, sorted and non-overlapping.
+    queue: Mutex<Vec<KeyRange>>,
+}
+
+impl TombstoneReports {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            num_reports: AtomicU64::new(0),
+            skipped_tombstones: AtomicU64::new(0),
+            reported: Mutex::new(Vec::new()),
+            queue: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Called by a scan when a run of `count` skipped tombstones ends.
+    pub(crate) fn end_run(&self, first: &[u8], last: &[u8], count: usize) {
+        self.skipped_tombstones
+            .fetch_add(count as u64, Ordering::Relaxed);
+        if count < self.threshold {
+            return;
+        }
+        self.num_reports.fetch_add(1, Ordering::Relaxed);
+        self.reported.lock().push(KeyRange {
+            first: Bytes::copy_from_slice(first),
+            last: Bytes::copy_from_slice(last),
+        });
+    }
+
+    /// Moves the reported ranges into the queue, merging overlapping ranges.
+    pub(crate) fn sweep(&self) {
+        let reported = std::mem::take(&mut *self.reported.lock());
+        if reported.is_empty() {
+            return;
+        }
+        let mut queue = self.queue.lock();
+        let mut ranges = std::mem::take(&mut *queue);
+        ranges.extend(reported);
+        ranges.sort_by(|a, b| a.first.cmp(&b.first));
+        for range in ranges {
+            match queue.last_mut() {
+                Some(prev) if prev.overlaps(&range) => {
+                    if range.last > prev.last {
+                        prev.last = range.last;
+                    }
+                }
+                _ => queue.push(range),
+            }
+        }
+    }
+
+    /// The ranges waiting to be compacted.
+    pub(crate) fn queued(&self) -> Vec<KeyRange> {
+        self.queue.lock().clone()
+    }
+
+    /// Removes a range that has been compacted down to the bottom level.
+    pub(crate) fn remove(&self, range: &KeyRange) {
+        self.queue.lock().retain(|x| x != range);
+    }
+
+    pub(crate) fn num_reports(&self) -> u64 {
+        self.num_reports.load(Ordering::Relaxed)
+    }
+
+    /// Tombstones skipped by all scans so far.
+    pub(crate) fn skipped_tombstones(&self) -> u64 {
+        self.skipped_tombstones.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks the run of tombstones a scan is skipping.
+#[derive(Default)]
+pub(crate) struct TombstoneRun {
+    first: Vec<u8>,
+    last: Vec<u8>,
+    count: usize,
+}
+
+impl TombstoneRun {
+    pub(crate) fn skip(&mut self, key: &[u8]) {
+        if self.count == 0 {
+            self.first.clear();
+            self.first.extend(key);
+        }
+        self.last.clear();
+        self.last.extend(key);
+        self.count += 1;
+    }
+
+    pub(crate) fn end(&mut self, reports: &TombstoneReports) {
+        if self.count > 0 {
+            reports.end_run(&self.first, &self.last, self.count);
+            self.count = 0;
+        }
+    }
+}
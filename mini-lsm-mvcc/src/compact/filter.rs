@@ -0,0 +1,33 @@
+use bytes::Bytes;
+
+/// The decision of a [`CompactionEntryFilter`] for one entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Keep the entry as is.
+    Keep,
+    /// Remove the entry. A tombstone is written instead, unless the output is the bottom level.
+    Remove,
+    /// Replace the value of the entry. An empty value is written as a tombstone.
+    ChangeValue(Bytes),
+}
+
+/// An application-defined rule applied to entries during compaction, e.g. to expire rows without
+/// issuing deletes. The filter is only invoked on the latest version of a key when that version
+/// is below the watermark, so entries still needed by a snapshot are never changed. Tombstones
+/// are never passed to the filter.
+///
+/// The decision must only depend on the arguments, as compactions may be re-run.
+pub trait CompactionEntryFilter: Send + Sync {
+    /// The name of the filter, used for debugging.
+    fn name(&self) -> &str;
+
+    /// Decide what to do with an entry written to `level`. For tiered compaction, which has no
+    /// levels, `level` is always 0.
+    fn filter(&self, level: usize, key: &[u8], value: &[u8]) -> FilterDecision;
+}
+
+impl std::fmt::Debug for dyn CompactionEntryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CompactionEntryFilter({})", self.name())
+    }
+}
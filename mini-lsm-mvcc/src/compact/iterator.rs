@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::block::{
+    implied_entry_flags, ENTRY_FLAG_MERGE_OPERAND, ENTRY_FLAG_TOMBSTONE, ENTRY_FLAG_TTL,
+};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::merge_operator::{full_merge, MergeOperator};
+use crate::range_tombstone::FragmentedRangeTombstones;
+use crate::ttl;
+
+/// Counters collected by a [`CompactionIterator`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionIterStats {
+    /// Tombstones removed together with the versions they shadow at the bottom level.
+    pub tombstones_dropped: u64,
+    /// Versions at or below the watermark shadowed by a newer version at or below it.
+    pub versions_collapsed: u64,
+    /// Entries produced by the iterator.
+    pub entries_kept: u64,
+}
+
+impl CompactionIterStats {
+    /// Entries read from the inner iterator so far.
+    pub fn entries_read(&self) -> u64 {
+        self.tombstones_dropped + self.versions_collapsed + self.entries_kept
+    }
+}
+
+/// What a [`CompactionIterator`] resolves besides the versions no snapshot can see.
+#[derive(Clone, Default)]
+pub struct CompactionIterOptions {
+    /// Combines merge operands. Without it, the operands are kept as they are, together with the
+    /// value below them.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The range tombstones at or below the watermark, which end the chains of merge operands.
+    pub expired_range_tombstones: FragmentedRangeTombstones,
+    /// The current time, in seconds since the Unix epoch. Entries with an expiry time at or
+    /// before it are turned into tombstones, see [`ENTRY_FLAG_TTL`].
+    pub now_secs: u64,
+}
+
+/// Wraps the merged input of a compaction and skips the versions no snapshot can see. For each
+/// user key, all versions above the watermark are kept together with the newest version at or
+/// below it, which is dropped as well if it is a tombstone and `drop_tombstones` is set. Entries
+/// at or after `upper` (a user key) are not produced.
+///
+/// When that newest version is a merge operand, it is combined with the older versions: into a
+/// value once the value below the operands is known, else into fewer operands through partial
+/// merges. Expired entries are produced as tombstones, which a snapshot cannot tell apart.
+pub struct CompactionIterator<I> {
+    iter: I,
+    upper: Option<Bytes>,
+    watermark: u64,
+    drop_tombstones: bool,
+    /// The user key of the last entry seen in the inner iterator.
+    last_key: Vec<u8>,
+    /// Whether a version of `last_key` at or below the watermark has been seen.
+    seen_below_watermark: bool,
+    options: CompactionIterOptions,
+    /// Entries of `last_key` combined from merge operands, newest first, produced before the
+    /// inner iterator, which is already past the operands. Each has a timestamp, a value and
+    /// flags, [`ENTRY_FLAG_MERGE_OPERAND`] if it is still a merge operand.
+    pending: VecDeque<(u64, Bytes, u8)>,
+    stats: CompactionIterStats,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> CompactionIterator<I> {
+    pub fn create(
+        iter: I,
+        upper: Option<&[u8]>,
+        watermark: u64,
+        drop_tombstones: bool,
+    ) -> Result<Self> {
+        Self::create_with_options(
+            iter,
+            upper,
+            watermark,
+            drop_tombstones,
+            CompactionIterOptions::default(),
+        )
+    }
+
+    /// Like [`Self::create`], also resolving merge operands and expired entries as told by
+    /// `options`.
+    pub fn create_with_options(
+        iter: I,
+        upper: Option<&[u8]>,
+        watermark: u64,
+        drop_tombstones: bool,
+        options: CompactionIterOptions,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            iter,
+            upper: upper.map(Bytes::copy_from_slice),
+            watermark,
+            drop_tombstones,
+            last_key: Vec::new(),
+            seen_below_watermark: false,
+            options,
+            pending: VecDeque::new(),
+            stats: CompactionIterStats::default(),
+        };
+        iter.skip_invisible()?;
+        Ok(iter)
+    }
+
+    /// The counters collected so far. They are final once the iterator is exhausted.
+    pub fn stats(&self) -> CompactionIterStats {
+        self.stats
+    }
+
+    fn past_upper(&self) -> bool {
+        match &self.upper {
+            Some(upper) => self.iter.key().key_ref() >= upper.as_ref(),
+            None => false,
+        }
+    }
+
+    /// Whether the entry the inner iterator is at has expired.
+    fn is_expired(&self) -> bool {
+        ttl::is_expired(self.iter.flags(), self.iter.value(), self.options.now_secs)
+    }
+
+    /// Move the inner iterator to the next entry that should be kept.
+    fn skip_invisible(&mut self) -> Result<()> {
+        while self.iter.is_valid() && !self.past_upper() {
+            if self.iter.key().key_ref() != self.last_key {
+                self.last_key.clear();
+                self.last_key.extend(self.iter.key().key_ref());
+                self.seen_below_watermark = false;
+            }
+            if self.iter.key().ts() > self.watermark {
+                break;
+            }
+            if self.seen_below_watermark {
+                self.stats.versions_collapsed += 1;
+                self.iter.next()?;
+                continue;
+            }
+            self.seen_below_watermark = true;
+            // an operand deleted by a range tombstone is dropped like a value
+            if self.iter.is_merge_operand()
+                && !self
+                    .options
+                    .expired_range_tombstones
+                    .covers(&self.last_key, self.iter.key().ts())
+            {
+                if self.merge_operands()? {
+                    break;
+                }
+                continue;
+            }
+            // No file below the bottom level can hold an older version of this key, so the
+            // tombstone can be dropped together with the versions it shadows.
+            if self.drop_tombstones && (self.iter.is_tombstone() || self.is_expired()) {
+                self.stats.tombstones_dropped += 1;
+                self.iter.next()?;
+                continue;
+            }
+            break;
+        }
+        if self.is_valid() {
+            self.stats.entries_kept += 1;
+        }
+        Ok(())
+    }
+
+    /// Combines the merge operand the inner iterator is at, the newest version of `last_key` at
+    /// or below the watermark, with the older versions into `pending`. Returns whether any entry
+    /// is pending.
+    fn merge_operands(&mut self) -> Result<bool> {
+        // newest first
+        let mut operands = Vec::new();
+        let mut existing = None;
+        // whether `existing` is the value below the operands, which is always the case at the
+        // bottom level
+        let mut complete = self.drop_tombstones;
+        while self.iter.is_valid() && self.iter.key().key_ref() == self.last_key {
+            let ts = self.iter.key().ts();
+            if self
+                .options
+                .expired_range_tombstones
+                .covers(&self.last_key, ts)
+            {
+                complete = true;
+                break;
+            }
+            if !self.iter.is_merge_operand() {
+                if self.iter.flags() & ENTRY_FLAG_TTL != 0 && !self.is_expired() {
+                    // combined with the operands, the value would no longer expire
+                    complete = false;
+                    break;
+                }
+                if !self.iter.is_tombstone() && !self.is_expired() {
+                    existing = Some(self.iter.value_bytes());
+                }
+                complete = true;
+                break;
+            }
+            operands.push((ts, self.iter.value_bytes()));
+            self.iter.next()?;
+        }
+        let num_operands = operands.len() as u64;
+        if complete && self.options.merge_operator.is_some() {
+            let ts = operands[0].0;
+            let operands = operands
+                .into_iter()
+                .map(|(_, operand)| operand)
+                .collect::<Vec<_>>();
+            let value = full_merge(
+                self.options.merge_operator.as_ref(),
+                &self.last_key,
+                existing.as_deref(),
+                &operands,
+            )?;
+            self.stats.versions_collapsed += num_operands - 1;
+            if value.is_empty() && self.drop_tombstones {
+                self.stats.tombstones_dropped += 1;
+                return Ok(false);
+            }
+            // an empty result deletes the key
+            let flags = implied_entry_flags(&value);
+            self.pending.push_back((ts, value, flags));
+            return Ok(true);
+        }
+        if let Some(merge_operator) = &self.options.merge_operator {
+            // oldest first, each combined operand taking the timestamp of the newest one in it
+            let mut combined: Vec<(u64, Bytes)> = Vec::with_capacity(operands.len());
+            for (ts, operand) in operands.into_iter().rev() {
+                if let Some((last_ts, last)) = combined.last_mut() {
+                    if let Some(merged) =
+                        merge_operator.partial_merge(&self.last_key, last, &operand)
+                    {
+                        *last_ts = ts;
+                        *last = merged;
+                        continue;
+                    }
+                }
+                combined.push((ts, operand));
+            }
+            combined.reverse();
+            operands = combined;
+        }
+        self.stats.versions_collapsed += num_operands - operands.len() as u64;
+        // the operands still need the value below them, if any
+        self.seen_below_watermark = false;
+        self.pending.extend(
+            operands
+                .into_iter()
+                .map(|(ts, operand)| (ts, operand, ENTRY_FLAG_MERGE_OPERAND)),
+        );
+        Ok(true)
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for CompactionIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice<'_> {
+        match self.pending.front() {
+            Some((ts, _, _)) => KeySlice::from_slice(&self.last_key, *ts),
+            None => self.iter.key(),
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        match self.pending.front() {
+            Some((_, value, _)) => value,
+            None if self.is_expired() => &[],
+            None => self.iter.value(),
+        }
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        match self.pending.front() {
+            Some((_, value, _)) => value.clone(),
+            None if self.is_expired() => Bytes::new(),
+            None => self.iter.value_bytes(),
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        match self.pending.front() {
+            Some((_, _, flags)) => *flags,
+            None if self.is_expired() => ENTRY_FLAG_TOMBSTONE,
+            None => self.iter.flags(),
+        }
+    }
+
+    fn is_tombstone(&self) -> bool {
+        match self.pending.front() {
+            Some((_, _, flags)) => flags & ENTRY_FLAG_TOMBSTONE != 0,
+            None if self.is_expired() => true,
+            None => self.iter.is_tombstone(),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.pending.is_empty() || (self.iter.is_valid() && !self.past_upper())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.pending.pop_front().is_some() {
+            if !self.pending.is_empty() {
+                self.stats.entries_kept += 1;
+                return Ok(());
+            }
+            // the inner iterator is already past the operands
+            return self.skip_invisible();
+        }
+        self.iter.next()?;
+        self.skip_invisible()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -2,9 +2,11 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::delete_triggered::KeyRange;
+use super::priority::{ssts_size, CompactionPriority, LevelCompactionDebt};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -37,24 +39,24 @@ impl LeveledCompactionController {
         sst_ids: &[usize],
         in_level: usize,
     ) -> Vec<usize> {
+        // compare user keys, so that the versions of a key are never split between the output and
+        // an SST left in the lower level
         let begin_key = sst_ids
             .iter()
-            .map(|id| snapshot.sstables[id].first_key())
+            .map(|id| snapshot.sstables[id].first_key_bytes())
             .min()
-            .cloned()
             .unwrap();
         let end_key = sst_ids
             .iter()
-            .map(|id| snapshot.sstables[id].last_key())
+            .map(|id| snapshot.sstables[id].last_key_bytes())
             .max()
-            .cloned()
             .unwrap();
         let mut overlap_ssts = Vec::new();
         for sst_id in &snapshot.levels[in_level - 1].1 {
             let sst = &snapshot.sstables[sst_id];
-            let first_key = sst.first_key();
-            let last_key = sst.last_key();
-            if !(last_key < &begin_key || first_key > &end_key) {
+            let first_key = sst.first_key_bytes();
+            let last_key = sst.last_key_bytes();
+            if !(last_key < begin_key || first_key > end_key) {
                 overlap_ssts.push(*sst_id);
             }
         }
@@ -65,62 +67,66 @@ impl LeveledCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<LeveledCompactionTask> {
-        // step 1: compute target level size
-        let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
-        let mut real_level_size = Vec::with_capacity(self.options.max_levels);
-        let mut base_level = self.options.max_levels;
-        for i in 0..self.options.max_levels {
-            real_level_size.push(
-                snapshot.levels[i]
-                    .1
-                    .iter()
-                    .map(|x| snapshot.sstables.get(x).unwrap().table_size())
-                    .sum::<u64>() as usize,
-            );
-        }
-        let base_level_size_bytes = self.options.base_level_size_mb * 1024 * 1024;
+        self.generate_compaction_task_with_reason(snapshot)
+            .map(|(task, _)| task)
+    }
 
-        // select base level and compute target level size
-        target_level_size[self.options.max_levels - 1] =
-            real_level_size[self.options.max_levels - 1].max(base_level_size_bytes);
-        for i in (0..(self.options.max_levels - 1)).rev() {
-            let next_level_size = target_level_size[i + 1];
-            let this_level_size = next_level_size / self.options.level_size_multiplier;
-            if next_level_size > base_level_size_bytes {
-                target_level_size[i] = this_level_size;
-            }
-            if target_level_size[i] > 0 {
-                base_level = i + 1;
-            }
-        }
+    /// Generates a compaction task together with the reason it was picked. Level sizes are
+    /// compensated by their garbage ratio, and within a level the SST with the most garbage is
+    /// compacted first.
+    pub fn generate_compaction_task_with_reason(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<(LeveledCompactionTask, String)> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Like [`Self::generate_compaction_task_with_reason`], but never picks a task containing any
+    /// of the `busy` SSTs, which are being compacted by other tasks. Levels are tried in priority
+    /// order until a task without conflicts is found.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(LeveledCompactionTask, String)> {
+        let is_busy = |ids: &[usize]| ids.iter().any(|id| busy.contains(id));
+        let (target_level_size, real_level_size, base_level) = self.level_sizes(snapshot);
+        let debt = self.compaction_debt(snapshot);
 
         // Flush L0 SST is the top priority
-        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
-            println!("flush L0 SST to base level {}", base_level);
-            return Some(LeveledCompactionTask {
+        if debt[0].priority == Some(CompactionPriority::L0Pressure)
+            && !is_busy(&snapshot.l0_sstables)
+        {
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
+            let task = LeveledCompactionTask {
                 upper_level: None,
                 upper_level_sst_ids: snapshot.l0_sstables.clone(),
                 lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: base_level == self.options.max_levels,
-            });
-        }
-
-        let mut priorities = Vec::with_capacity(self.options.max_levels);
-        for level in 0..self.options.max_levels {
-            let prio = real_level_size[level] as f64 / target_level_size[level] as f64;
-            if prio > 1.0 {
-                priorities.push((prio, level + 1));
+            };
+            if !is_busy(&task.lower_level_sst_ids)
+                && !self.conflicts_with_running_tasks(snapshot, busy, &task)
+            {
+                println!("flush L0 SST to base level {}", base_level);
+                let reason = format!(
+                    "{} L0 SSTs reached the trigger of {}",
+                    snapshot.l0_sstables.len(),
+                    self.options.level0_file_num_compaction_trigger
+                );
+                return Some((task, reason));
             }
         }
-        priorities.sort_by(|a, b| a.partial_cmp(b).unwrap().reverse());
 
-        let priority = priorities.first();
-        if let Some((_, level)) = priority {
+        // then levels that are about to stall, and finally levels over their target size
+        let mut priorities = debt[1..]
+            .iter()
+            .filter_map(|debt| debt.priority.map(|prio| (prio, debt.score, debt.level)))
+            .collect::<Vec<_>>();
+        priorities.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.total_cmp(&a.1)));
+
+        if !priorities.is_empty() {
             println!(
                 "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
                 target_level_size
@@ -133,28 +139,227 @@ impl LeveledCompactionController {
                     .collect::<Vec<_>>(),
                 base_level,
             );
+        }
+        for &(_, score, level) in &priorities {
+            // select the sst with the most garbage to compact, or the oldest one on ties
+            let mut candidates = snapshot.levels[level - 1]
+                .1
+                .iter()
+                .copied()
+                .filter(|id| !busy.contains(id))
+                .collect::<Vec<_>>();
+            candidates.sort_by(|a, b| {
+                let garbage_a = snapshot.sstables[a].properties().garbage_ratio();
+                let garbage_b = snapshot.sstables[b].properties().garbage_ratio();
+                garbage_b.total_cmp(&garbage_a).then(a.cmp(b))
+            });
+            for selected_sst in candidates {
+                let lower_level_sst_ids =
+                    self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
+                if is_busy(&lower_level_sst_ids) {
+                    continue;
+                }
+                let task = LeveledCompactionTask {
+                    upper_level: Some(level),
+                    upper_level_sst_ids: vec![selected_sst],
+                    lower_level: level + 1,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+                };
+                if self.conflicts_with_running_tasks(snapshot, busy, &task) {
+                    continue;
+                }
+                println!(
+                    "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
+                    priorities
+                );
+                let reason = format!(
+                    "level {} exceeds its target size by {:.3}x, SST {} has garbage ratio {:.3}",
+                    level,
+                    score,
+                    selected_sst,
+                    snapshot.sstables[&selected_sst]
+                        .properties()
+                        .garbage_ratio()
+                );
+                return Some((task, reason));
+            }
+        }
+        None
+    }
 
-            let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
-            println!(
-                "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
-                priorities
-            );
+    /// Whether the output of `task` could overlap the output of a running task into the same
+    /// level, which would break the order of the level even though the two tasks share no input.
+    /// The output of a task covers the whole key range of its inputs, including the gaps between
+    /// the L0 SSTs or the upper level SSTs of a range compaction. The running tasks are known from
+    /// their `busy` inputs: the busy L0 SSTs, compacted together into the base level, and each run
+    /// of adjacent busy SSTs in the levels above and at the output level.
+    pub(crate) fn conflicts_with_running_tasks(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+        task: &LeveledCompactionTask,
+    ) -> bool {
+        if busy.is_empty() {
+            return false;
+        }
+        let key_range = |ids: &mut dyn Iterator<Item = &usize>| {
+            ids.fold(None, |range: Option<(&[u8], &[u8])>, id| {
+                let sst = &snapshot.sstables[id];
+                let (first, last) = (sst.first_key_bytes(), sst.last_key_bytes());
+                Some(match range {
+                    Some((begin, end)) => (begin.min(first), end.max(last)),
+                    None => (first, last),
+                })
+            })
+        };
+        let Some((begin, end)) = key_range(
+            &mut task
+                .upper_level_sst_ids
+                .iter()
+                .chain(&task.lower_level_sst_ids),
+        ) else {
+            return false;
+        };
+        let overlaps = |range: Option<(&[u8], &[u8])>| {
+            range.is_some_and(|(first, last)| first <= end && begin <= last)
+        };
+        let (_, _, base_level) = self.level_sizes(snapshot);
+        if task.lower_level == base_level
+            && overlaps(key_range(
+                &mut snapshot.l0_sstables.iter().filter(|id| busy.contains(id)),
+            ))
+        {
+            return true;
+        }
+        let mut levels = vec![task.lower_level];
+        if task.lower_level > 1 {
+            levels.push(task.lower_level - 1);
+        }
+        levels.into_iter().any(|level| {
+            snapshot.levels[level - 1]
+                .1
+                .split(|id| !busy.contains(id))
+                .any(|run| overlaps(key_range(&mut run.iter())))
+        })
+    }
+
+    /// Generates a task moving the SSTs overlapping `range` one level down: all of L0 into the
+    /// base level if any L0 SST overlaps it, otherwise the overlapping SSTs of the highest level
+    /// above the bottom level that has any. Returns `None` once only the bottom level overlaps.
+    pub(crate) fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        range: &KeyRange,
+    ) -> Option<LeveledCompactionTask> {
+        let overlapping = |sst_ids: &[usize]| {
+            sst_ids
+                .iter()
+                .copied()
+                .filter(|id| range.overlaps_sst(&snapshot.sstables[id]))
+                .collect::<Vec<_>>()
+        };
+        if !overlapping(&snapshot.l0_sstables).is_empty() {
+            // L0 SSTs overlap with each other, so all of them must be compacted together
+            let (_, _, base_level) = self.level_sizes(snapshot);
+            return Some(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                lower_level: base_level,
+                lower_level_sst_ids: self.find_overlapping_ssts(
+                    snapshot,
+                    &snapshot.l0_sstables,
+                    base_level,
+                ),
+                is_lower_level_bottom_level: base_level == self.options.max_levels,
+            });
+        }
+        for level in 1..self.options.max_levels {
+            let upper_level_sst_ids = overlapping(&snapshot.levels[level - 1].1);
+            if upper_level_sst_ids.is_empty() {
+                continue;
+            }
             return Some(LeveledCompactionTask {
                 upper_level: Some(level),
-                upper_level_sst_ids: vec![selected_sst],
-                lower_level: level + 1,
                 lower_level_sst_ids: self.find_overlapping_ssts(
                     snapshot,
-                    &[selected_sst],
+                    &upper_level_sst_ids,
                     level + 1,
                 ),
+                upper_level_sst_ids,
+                lower_level: level + 1,
                 is_lower_level_bottom_level: level + 1 == self.options.max_levels,
             });
         }
         None
     }
 
+    /// Computes the target size of each level, the real size of each level compensated by its
+    /// garbage, and the base level L0 is compacted into. Sizes are indexed by level minus one.
+    fn level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
+        let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
+        let mut real_level_size = Vec::with_capacity(self.options.max_levels);
+        let mut base_level = self.options.max_levels;
+        for i in 0..self.options.max_levels {
+            real_level_size.push(
+                snapshot.levels[i]
+                    .1
+                    .iter()
+                    .map(|x| snapshot.sstables.get(x).unwrap().compensated_size())
+                    .sum::<u64>() as usize,
+            );
+        }
+        let base_level_size_bytes = self.options.base_level_size_mb * 1024 * 1024;
+
+        // select base level and compute target level size
+        target_level_size[self.options.max_levels - 1] =
+            real_level_size[self.options.max_levels - 1].max(base_level_size_bytes);
+        for i in (0..(self.options.max_levels - 1)).rev() {
+            let next_level_size = target_level_size[i + 1];
+            let this_level_size = next_level_size / self.options.level_size_multiplier;
+            if next_level_size > base_level_size_bytes {
+                target_level_size[i] = this_level_size;
+            }
+            if target_level_size[i] > 0 {
+                base_level = i + 1;
+            }
+        }
+        (target_level_size, real_level_size, base_level)
+    }
+
+    /// Computes the compaction debt of L0 and each level. L0 is scored by its number of files
+    /// against the trigger, other levels by their size against their target size.
+    pub fn compaction_debt(&self, snapshot: &LsmStorageState) -> Vec<LevelCompactionDebt> {
+        let (target_level_size, real_level_size, _) = self.level_sizes(snapshot);
+        let l0_triggered =
+            snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger;
+        let mut debt = vec![LevelCompactionDebt {
+            level: 0,
+            score: snapshot.l0_sstables.len() as f64
+                / self.options.level0_file_num_compaction_trigger as f64,
+            priority: l0_triggered.then_some(CompactionPriority::L0Pressure),
+            debt_bytes: if l0_triggered {
+                ssts_size(snapshot, &snapshot.l0_sstables)
+            } else {
+                0
+            },
+        }];
+        for (i, (&target, &real)) in target_level_size.iter().zip(&real_level_size).enumerate() {
+            let score = match (real, target) {
+                (0, _) => 0.0,
+                (_, 0) => f64::INFINITY,
+                _ => real as f64 / target as f64,
+            };
+            debt.push(LevelCompactionDebt {
+                level: i + 1,
+                score,
+                priority: CompactionPriority::from_score(score),
+                debt_bytes: real.saturating_sub(target) as u64,
+            });
+        }
+        debt
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -0,0 +1,104 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::{CompactionIterOptions, CompactionIterator};
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{BlockCache, LsmStorageInner};
+use crate::table::{SsTable, SsTableBuilder};
+
+/// How [`merge_build`] merges its inputs and writes the output SSTs.
+#[derive(Clone)]
+pub struct MergeBuildOptions {
+    /// The target size of the data blocks.
+    pub block_size: usize,
+    /// The format version of the data blocks, which must store entry flags if the inputs have
+    /// any, see [`crate::block::BLOCK_FORMAT_V6`].
+    pub format_version: u8,
+    /// An output SST is finished once it reaches this size, before the next user key.
+    pub target_sst_size: usize,
+    /// All versions above it are kept, together with the newest version at or below it.
+    pub watermark: u64,
+    /// Whether the outputs go to the bottom level, where the newest version at or below the
+    /// watermark is dropped as well if it is a tombstone.
+    pub drop_tombstones: bool,
+    /// How merge operands and expired entries are resolved.
+    pub iter_options: CompactionIterOptions,
+    /// The cache the blocks of the output SSTs are read through.
+    pub block_cache: Option<Arc<BlockCache>>,
+}
+
+/// Merges the sorted `inputs`, preferring the one with the smaller index for the same key as a
+/// [`MergeIterator`] does, and writes the result into SSTs in the directory `path`, numbered from
+/// `out_id` and named like those of the storage. The versions no snapshot can see are dropped as
+/// by a [`CompactionIterator`], and each output gets its own bloom filter. Versions of the same
+/// user key are never split across two outputs, so the outputs hold non-overlapping key ranges,
+/// in order. On error, the outputs written so far are removed.
+pub fn merge_build<I>(
+    inputs: Vec<Box<I>>,
+    out_id: usize,
+    path: impl AsRef<Path>,
+    options: &MergeBuildOptions,
+) -> Result<Vec<SsTable>>
+where
+    I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+{
+    let path = path.as_ref();
+    let mut outputs = Vec::new();
+    if let Err(e) = merge_build_inner(inputs, out_id, path, options, &mut outputs) {
+        for sst in &outputs {
+            std::fs::remove_file(LsmStorageInner::path_of_sst_static(path, sst.sst_id())).ok();
+        }
+        return Err(e);
+    }
+    Ok(outputs)
+}
+
+fn merge_build_inner<I>(
+    inputs: Vec<Box<I>>,
+    out_id: usize,
+    path: &Path,
+    options: &MergeBuildOptions,
+    outputs: &mut Vec<SsTable>,
+) -> Result<()>
+where
+    I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+{
+    let mut iter = CompactionIterator::create_with_options(
+        MergeIterator::create(inputs),
+        None,
+        options.watermark,
+        options.drop_tombstones,
+        options.iter_options.clone(),
+    )?;
+    let mut builder =
+        SsTableBuilder::new_with_format_version(options.block_size, options.format_version);
+    let mut last_key = Vec::<u8>::new();
+    let mut build = |builder: &mut SsTableBuilder| -> Result<()> {
+        let sst_id = out_id + outputs.len();
+        outputs.push(builder.build_and_reset(
+            sst_id,
+            options.block_cache.clone(),
+            LsmStorageInner::path_of_sst_static(path, sst_id),
+        )?);
+        Ok(())
+    };
+    while iter.is_valid() {
+        if iter.key().key_ref() != last_key {
+            if !builder.is_empty() && builder.estimated_size() >= options.target_sst_size {
+                build(&mut builder)?;
+            }
+            last_key.clear();
+            last_key.extend(iter.key().key_ref());
+        }
+        builder.add_with_flags(iter.key(), iter.value(), iter.flags());
+        iter.next()?;
+    }
+    if !builder.is_empty() {
+        build(&mut builder)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use super::{CompactionTask, LeveledCompactionTask, SimpleLeveledCompactionTask};
+use crate::lsm_storage::LsmStorageState;
+
+/// The input SSTs a compaction task reads from one level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionPlanLevel {
+    /// The level, 0 for L0, or the tier id for tiered compaction.
+    pub level: usize,
+    /// The input SSTs of the level together with their sizes.
+    pub ssts: Vec<(usize, u64)>,
+}
+
+impl CompactionPlanLevel {
+    pub fn bytes(&self) -> u64 {
+        self.ssts.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// What a compaction task would do, computed only from the metadata of its input SSTs.
+#[derive(Debug)]
+pub struct CompactionPlan {
+    pub task: CompactionTask,
+    /// Why the task was picked.
+    pub reason: String,
+    pub inputs: Vec<CompactionPlanLevel>,
+    /// The number of entries expected to be dropped: versions shadowed within an input SST, and
+    /// tombstones if the task compacts into the bottom level. Versions shadowed across SSTs
+    /// are not known without reading them, while versions visible to a snapshot are kept, so
+    /// this is only an estimate.
+    pub estimated_entries_dropped: u64,
+    /// The size of the inputs scaled by the fraction of entries expected to survive.
+    pub estimated_output_bytes: u64,
+}
+
+impl CompactionPlan {
+    /// Plans `task`, whose input SSTs must all be in `snapshot`.
+    pub(crate) fn new(snapshot: &LsmStorageState, task: CompactionTask, reason: String) -> Self {
+        let inputs = input_ssts_per_level(&task)
+            .into_iter()
+            .map(|(level, sst_ids)| CompactionPlanLevel {
+                level,
+                ssts: sst_ids
+                    .iter()
+                    .map(|id| (*id, snapshot.sstables[id].table_size()))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        let (mut num_entries, mut entries_dropped) = (0, 0);
+        for id in task.input_sst_ids() {
+            let properties = snapshot.sstables[&id].properties();
+            num_entries += properties.num_entries;
+            entries_dropped += properties.num_shadowed_entries;
+            if task.compact_to_bottom_level() {
+                entries_dropped += properties.num_tombstones;
+            }
+        }
+        let input_bytes = inputs.iter().map(|x| x.bytes()).sum::<u64>();
+        let estimated_output_bytes = if num_entries == 0 {
+            0
+        } else {
+            (input_bytes as f64 * (num_entries - entries_dropped) as f64 / num_entries as f64)
+                as u64
+        };
+        Self {
+            task,
+            reason,
+            inputs,
+            estimated_entries_dropped: entries_dropped,
+            estimated_output_bytes,
+        }
+    }
+
+    /// The total size of the input SSTs.
+    pub fn input_bytes(&self) -> u64 {
+        self.inputs.iter().map(|x| x.bytes()).sum()
+    }
+}
+
+/// Groups the input SSTs of a task by the level (or tier) they are read from.
+fn input_ssts_per_level(task: &CompactionTask) -> Vec<(usize, Vec<usize>)> {
+    match task {
+        CompactionTask::ForceFullCompaction {
+            l0_sstables,
+            l1_sstables,
+        } => vec![(0, l0_sstables.clone()), (1, l1_sstables.clone())],
+        CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level,
+            upper_level_sst_ids,
+            lower_level,
+            lower_level_sst_ids,
+            ..
+        })
+        | CompactionTask::Simple(SimpleLeveledCompactionTask {
+            upper_level,
+            upper_level_sst_ids,
+            lower_level,
+            lower_level_sst_ids,
+            ..
+        }) => vec![
+            (upper_level.unwrap_or(0), upper_level_sst_ids.clone()),
+            (*lower_level, lower_level_sst_ids.clone()),
+        ],
+        CompactionTask::Tiered(task) => task.tiers.clone(),
+        CompactionTask::Periodic { level, sst_id, .. } => vec![(*level, vec![*sst_id])],
+    }
+}
@@ -0,0 +1,48 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{Receiver, Sender};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads running the sub-compactions of all the compaction tasks of a storage
+/// engine, so that the threads compacting at once stay bounded by the size of the pool however
+/// many tasks run concurrently. Sub-compactions beyond the size of the pool wait in its queue.
+/// The threads exit once the pool is dropped and the queue is drained.
+pub(crate) struct SubcompactionPool {
+    sender: Sender<Job>,
+}
+
+impl SubcompactionPool {
+    pub(crate) fn new(num_threads: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Job>();
+        for _ in 0..num_threads {
+            let receiver = receiver.clone();
+            std::thread::spawn(move || {
+                while let Ok(job) = receiver.recv() {
+                    job();
+                }
+            });
+        }
+        Self { sender }
+    }
+
+    /// Queues `f` on the pool, the result is received once a thread of the pool has run it. A
+    /// panic in `f` is returned as an error and does not take the thread down.
+    pub(crate) fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Receiver<Result<T>> {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .unwrap_or_else(|_| Err(anyhow!("sub-compaction panicked")));
+            sender.send(result).ok();
+        });
+        if let Err(e) = self.sender.send(job) {
+            // a pool without threads runs the job on the caller
+            (e.into_inner())();
+        }
+        receiver
+    }
+}
@@ -0,0 +1,56 @@
+use crate::lsm_storage::LsmStorageState;
+
+/// Why a level needs compaction, from the lowest to the highest priority. When several triggers
+/// fire at the same time, controllers pick a task for the highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompactionPriority {
+    /// The level violates its size ratio or target size.
+    SizeRatio,
+    /// The level is at least [`STALL_IMMINENT_SCORE`] times beyond its target, so it will hold
+    /// up the levels above it (and eventually writes) if it is not relieved soon.
+    StallImminent,
+    /// L0 reached its file number trigger (or, for tiered compaction, the number of sorted runs
+    /// reached its limit). This directly slows down reads and stalls writes.
+    L0Pressure,
+}
+
+/// The score at which a level violating its size target is considered to stall writes soon.
+pub const STALL_IMMINENT_SCORE: f64 = 2.0;
+
+impl CompactionPriority {
+    /// The priority of a level other than L0 with the given score, which is how far the level is
+    /// beyond its target (1.0 being right at the target).
+    pub fn from_score(score: f64) -> Option<Self> {
+        if score >= STALL_IMMINENT_SCORE {
+            Some(Self::StallImminent)
+        } else if score > 1.0 {
+            Some(Self::SizeRatio)
+        } else {
+            None
+        }
+    }
+}
+
+/// How much compaction work a level is behind on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelCompactionDebt {
+    /// 0 for L0, otherwise the position of the level (or tier) in `LsmStorageState::levels`
+    /// plus one.
+    pub level: usize,
+    /// How far the level is beyond its target, 1.0 being right at the target.
+    pub score: f64,
+    /// Set if the level needs compaction.
+    pub priority: Option<CompactionPriority>,
+    /// Bytes that need to be compacted out of the level to bring it back within its target.
+    pub debt_bytes: u64,
+}
+
+/// The total size of the given SSTs. SSTs without an entry in `snapshot.sstables` (as in the
+/// compaction simulator) are ignored.
+pub(super) fn ssts_size(snapshot: &LsmStorageState, sst_ids: &[usize]) -> u64 {
+    sst_ids
+        .iter()
+        .filter_map(|id| snapshot.sstables.get(id))
+        .map(|sst| sst.table_size())
+        .sum()
+}
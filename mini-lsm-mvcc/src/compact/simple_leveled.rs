@@ -2,8 +2,25 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::delete_triggered::KeyRange;
+use super::priority::{ssts_size, CompactionPriority, LevelCompactionDebt};
 use crate::lsm_storage::LsmStorageState;
 
+/// The fraction of tombstones and shadowed versions among all entries of the given SSTs. SSTs
+/// without an entry in `snapshot.sstables` (as in the compaction simulator) are ignored.
+pub(super) fn level_garbage_ratio(snapshot: &LsmStorageState, sst_ids: &[usize]) -> f64 {
+    let (mut garbage, mut entries) = (0, 0);
+    for sst in sst_ids.iter().filter_map(|id| snapshot.sstables.get(id)) {
+        let properties = sst.properties();
+        garbage += properties.num_tombstones + properties.num_shadowed_entries;
+        entries += properties.num_entries;
+    }
+    if entries == 0 {
+        return 0.0;
+    }
+    garbage as f64 / entries as f64
+}
+
 #[derive(Debug, Clone)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
@@ -11,7 +28,7 @@ pub struct SimpleLeveledCompactionOptions {
     pub max_levels: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -37,40 +54,163 @@ impl SimpleLeveledCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_with_reason(snapshot)
+            .map(|(task, _)| task)
+    }
+
+    /// Generates a compaction task together with the reason it was picked. When several levels
+    /// violate the size ratio, the one with the highest [`CompactionPriority`] is compacted first,
+    /// and among those the one whose upper level holds the most garbage (tombstones and shadowed
+    /// versions).
+    pub fn generate_compaction_task_with_reason(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<(SimpleLeveledCompactionTask, String)> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Like [`Self::generate_compaction_task_with_reason`], but skips level pairs that contain any
+    /// of the `busy` SSTs, which are being compacted by other tasks.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(SimpleLeveledCompactionTask, String)> {
+        let level_sizes = self.level_sizes(snapshot);
+        let mut candidate: Option<(usize, f64, CompactionPriority, f64)> = None;
+        for debt in self.compaction_debt(snapshot) {
+            let Some(priority) = debt.priority else {
+                continue;
+            };
+            let i = debt.level;
+            let lower_level = i + 1;
+            let upper_level_sst_ids = if i == 0 {
+                &snapshot.l0_sstables
+            } else {
+                &snapshot.levels[i - 1].1
+            };
+            let lower_level_sst_ids = &snapshot.levels[lower_level - 1].1;
+            if upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .any(|id| busy.contains(id))
+            {
+                continue;
+            }
+            let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
+            let garbage_ratio = level_garbage_ratio(snapshot, upper_level_sst_ids);
+            if !matches!(candidate, Some((_, _, best_priority, best_garbage_ratio))
+                if (priority, garbage_ratio) <= (best_priority, best_garbage_ratio))
+            {
+                candidate = Some((i, size_ratio, priority, garbage_ratio));
+            }
+        }
+
+        let (i, size_ratio, _, garbage_ratio) = candidate?;
+        let lower_level = i + 1;
+        println!(
+            "compaction triggered at level {} and {} with size ratio {}",
+            i, lower_level, size_ratio
+        );
+        let task = SimpleLeveledCompactionTask {
+            upper_level: if i == 0 { None } else { Some(i) },
+            upper_level_sst_ids: if i == 0 {
+                snapshot.l0_sstables.clone()
+            } else {
+                snapshot.levels[i - 1].1.clone()
+            },
+            lower_level,
+            lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
+            is_lower_level_bottom_level: lower_level == self.options.max_levels,
+        };
+        let reason = format!(
+            "size ratio {:.3} between level {} and {}, garbage ratio {:.3}",
+            size_ratio, i, lower_level, garbage_ratio
+        );
+        Some((task, reason))
+    }
+
+    /// Generates a task compacting the highest level above the bottom level that has an SST
+    /// overlapping `range` into the level below it. Returns `None` once only the bottom level
+    /// overlaps.
+    pub(crate) fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        range: &KeyRange,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        (0..self.options.max_levels).find_map(|i| {
+            let upper_level_sst_ids = if i == 0 {
+                &snapshot.l0_sstables
+            } else {
+                &snapshot.levels[i - 1].1
+            };
+            if !upper_level_sst_ids
+                .iter()
+                .any(|id| range.overlaps_sst(&snapshot.sstables[id]))
+            {
+                return None;
+            }
+            Some(SimpleLeveledCompactionTask {
+                upper_level: if i == 0 { None } else { Some(i) },
+                upper_level_sst_ids: upper_level_sst_ids.clone(),
+                lower_level: i + 1,
+                lower_level_sst_ids: snapshot.levels[i].1.clone(),
+                is_lower_level_bottom_level: i + 1 == self.options.max_levels,
+            })
+        })
+    }
+
+    /// The number of SSTs in L0 and each level.
+    fn level_sizes(&self, snapshot: &LsmStorageState) -> Vec<usize> {
         let mut level_sizes = Vec::new();
         level_sizes.push(snapshot.l0_sstables.len());
         for (_, files) in &snapshot.levels {
             level_sizes.push(files.len());
         }
+        level_sizes
+    }
 
-        for i in 0..self.options.max_levels {
-            if i == 0
-                && snapshot.l0_sstables.len() < self.options.level0_file_num_compaction_trigger
-            {
-                continue;
-            }
-
-            let lower_level = i + 1;
-            let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
-            if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
-                println!(
-                    "compaction triggered at level {} and {} with size ratio {}",
-                    i, lower_level, size_ratio
-                );
-                return Some(SimpleLeveledCompactionTask {
-                    upper_level: if i == 0 { None } else { Some(i) },
-                    upper_level_sst_ids: if i == 0 {
-                        snapshot.l0_sstables.clone()
+    /// Computes the compaction debt of L0 and each level. A level is scored by how far the size
+    /// ratio to the level below it falls short of `size_ratio_percent`, and L0 additionally needs
+    /// to reach its file number trigger. The debt of a level that needs compaction is its whole
+    /// size, as all of it is compacted into the level below.
+    pub fn compaction_debt(&self, snapshot: &LsmStorageState) -> Vec<LevelCompactionDebt> {
+        let level_sizes = self.level_sizes(snapshot);
+        (0..=self.options.max_levels)
+            .map(|i| {
+                let score = if i == self.options.max_levels || level_sizes[i] == 0 {
+                    0.0
+                } else if level_sizes[i + 1] == 0 {
+                    f64::INFINITY
+                } else {
+                    level_sizes[i] as f64 * self.options.size_ratio_percent as f64
+                        / (level_sizes[i + 1] as f64 * 100.0)
+                };
+                let priority = if i == 0 {
+                    (score > 1.0
+                        && snapshot.l0_sstables.len()
+                            >= self.options.level0_file_num_compaction_trigger)
+                        .then_some(CompactionPriority::L0Pressure)
+                } else {
+                    CompactionPriority::from_score(score)
+                };
+                let ssts = if i == 0 {
+                    &snapshot.l0_sstables
+                } else {
+                    &snapshot.levels[i - 1].1
+                };
+                LevelCompactionDebt {
+                    level: i,
+                    score,
+                    priority,
+                    debt_bytes: if priority.is_some() {
+                        ssts_size(snapshot, ssts)
                     } else {
-                        snapshot.levels[i - 1].1.clone()
+                        0
                     },
-                    lower_level,
-                    lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
-                    is_lower_level_bottom_level: lower_level == self.options.max_levels,
-                });
-            }
-        }
-        None
+                }
+            })
+            .collect()
     }
 
     /// Apply the compaction result.
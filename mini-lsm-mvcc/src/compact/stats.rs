@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::LevelCompactionDebt;
+use crate::write_stall::WriteStall;
+
+/// The kind of a background job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundJobKind {
+    Flush,
+    Compaction,
+}
+
+/// Statistics of one completed flush or compaction.
+#[derive(Clone, Debug)]
+pub struct BackgroundJobStats {
+    pub kind: BackgroundJobKind,
+    /// Levels read by a compaction (0 for L0), or the ids of the input tiers in tiered
+    /// compaction. Empty for flushes.
+    pub input_levels: Vec<usize>,
+    /// The level the output SSTs are written to. Flushes and tiered compactions report 0.
+    pub output_level: usize,
+    pub input_files: usize,
+    /// Bytes read from input SSTs, or the approximate size of the flushed memtable.
+    pub input_bytes: u64,
+    pub output_files: usize,
+    pub output_bytes: u64,
+    pub entries_read: u64,
+    pub entries_written: u64,
+    /// Entries garbage-collected by the job, i.e. `entries_read - entries_written`.
+    pub entries_dropped: u64,
+    pub duration: Duration,
+}
+
+/// Statistics of background flushes and compactions.
+#[derive(Debug, Clone, Default)]
+pub struct CompactionStats {
+    /// Why the most recent compaction task was picked.
+    pub last_task_reason: Option<String>,
+    /// Time flushes have spent blocked by the rate limiter.
+    pub flush_throttled_time: Duration,
+    /// Time compactions have spent blocked by the rate limiter.
+    pub compaction_throttled_time: Duration,
+    /// The most recent jobs, from earliest to latest.
+    pub recent_jobs: Vec<BackgroundJobStats>,
+    pub num_flushes: u64,
+    pub num_compactions: u64,
+    /// Bytes of keys and values written by the user, including deletes.
+    pub user_bytes_written: u64,
+    pub flush_bytes_written: u64,
+    pub compaction_bytes_read: u64,
+    pub compaction_bytes_written: u64,
+    /// Number of compaction tasks that failed.
+    pub num_compaction_failures: u64,
+    /// The error of the most recent failed compaction task.
+    pub last_compaction_error: Option<String>,
+    /// Number of ranges reported by scans for delete-triggered compaction.
+    pub num_tombstone_reports: u64,
+    /// Tombstones skipped by scans, counted only with delete-triggered compaction enabled.
+    pub scan_skipped_tombstones: u64,
+    /// The compaction debt of each level at the time the stats were taken.
+    pub compaction_debt: Vec<LevelCompactionDebt>,
+    /// Time writes have spent delayed by a write stall, see
+    /// [`crate::write_stall::WriteStallOptions`].
+    pub write_delayed_time: Duration,
+    /// Time writes have spent stopped by a write stall.
+    pub write_stopped_time: Duration,
+    pub num_delayed_writes: u64,
+    pub num_stopped_writes: u64,
+    /// The stall last seen by a write, `None` if it was not stalled.
+    pub last_write_stall: Option<WriteStall>,
+}
+
+impl CompactionStats {
+    /// Bytes written to SSTs by flushes and compactions divided by bytes written by the user.
+    /// Returns 0 if nothing has been written yet.
+    pub fn write_amplification(&self) -> f64 {
+        if self.user_bytes_written == 0 {
+            return 0.0;
+        }
+        (self.flush_bytes_written + self.compaction_bytes_written) as f64
+            / self.user_bytes_written as f64
+    }
+}
+
+/// Collects background job statistics. Cumulative counters cover the lifetime of the storage
+/// engine, while only the last [`StatsCollector::RECENT_JOBS_CAPACITY`] jobs are retained.
+#[derive(Default)]
+pub(crate) struct StatsCollector {
+    recent_jobs: Mutex<VecDeque<BackgroundJobStats>>,
+    num_flushes: AtomicU64,
+    num_compactions: AtomicU64,
+    user_bytes_written: AtomicU64,
+    flush_bytes_written: AtomicU64,
+    compaction_bytes_read: AtomicU64,
+    compaction_bytes_written: AtomicU64,
+    num_compaction_failures: AtomicU64,
+    last_compaction_error: Mutex<Option<String>>,
+}
+
+impl StatsCollector {
+    pub(crate) const RECENT_JOBS_CAPACITY: usize = 64;
+
+    pub(crate) fn record_user_write(&self, bytes: usize) {
+        self.user_bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_job(&self, job: BackgroundJobStats) {
+        match job.kind {
+            BackgroundJobKind::Flush => {
+                self.num_flushes.fetch_add(1, Ordering::Relaxed);
+                self.flush_bytes_written
+                    .fetch_add(job.output_bytes, Ordering::Relaxed);
+            }
+            BackgroundJobKind::Compaction => {
+                self.num_compactions.fetch_add(1, Ordering::Relaxed);
+                self.compaction_bytes_read
+                    .fetch_add(job.input_bytes, Ordering::Relaxed);
+                self.compaction_bytes_written
+                    .fetch_add(job.output_bytes, Ordering::Relaxed);
+            }
+        }
+        let mut recent_jobs = self.recent_jobs.lock();
+        if recent_jobs.len() == Self::RECENT_JOBS_CAPACITY {
+            recent_jobs.pop_front();
+        }
+        recent_jobs.push_back(job);
+    }
+
+    pub(crate) fn record_compaction_failure(&self, error: &anyhow::Error) {
+        self.num_compaction_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_compaction_error.lock() = Some(format!("{:#}", error));
+    }
+
+    /// Fill the job counters of `stats`.
+    pub(crate) fn fill(&self, stats: &mut CompactionStats) {
+        stats.recent_jobs = self.recent_jobs.lock().iter().cloned().collect();
+        stats.num_flushes = self.num_flushes.load(Ordering::Relaxed);
+        stats.num_compactions = self.num_compactions.load(Ordering::Relaxed);
+        stats.user_bytes_written = self.user_bytes_written.load(Ordering::Relaxed);
+        stats.flush_bytes_written = self.flush_bytes_written.load(Ordering::Relaxed);
+        stats.compaction_bytes_read = self.compaction_bytes_read.load(Ordering::Relaxed);
+        stats.compaction_bytes_written = self.compaction_bytes_written.load(Ordering::Relaxed);
+        stats.num_compaction_failures = self.num_compaction_failures.load(Ordering::Relaxed);
+        stats.last_compaction_error = self.last_compaction_error.lock().clone();
+    }
+}
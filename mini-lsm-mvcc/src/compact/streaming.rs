@@ -0,0 +1,326 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::block::{Block, BlockIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::table::SsTable;
+
+/// Where an input of a [`StreamingCompactionIterator`] is in its current block.
+enum Position {
+    /// The block is not in memory. `key` is the key of the next entry, the first key of the
+    /// block or the entry it was released at, unless `entry` is `None`.
+    Unloaded {
+        key: KeyVec,
+        /// The index of the next entry in the block, or `None` to seek to `key`, which is then
+        /// only a lower bound of the next key.
+        entry: Option<usize>,
+    },
+    Loaded(BlockIterator),
+    Done,
+}
+
+/// A sorted run of SSTs read one block at a time.
+struct Input {
+    tables: Vec<Arc<SsTable>>,
+    table_idx: usize,
+    blk_idx: usize,
+    position: Position,
+}
+
+impl Input {
+    fn create(tables: Vec<Arc<SsTable>>, lower: Option<KeySlice>) -> Self {
+        let mut input = Self {
+            table_idx: match lower {
+                Some(key) => tables.partition_point(|table| table.last_key().as_key_slice() < key),
+                None => 0,
+            },
+            tables,
+            blk_idx: 0,
+            position: Position::Done,
+        };
+        match (lower, input.tables.get(input.table_idx)) {
+            (Some(key), Some(table)) if table.num_of_blocks() > 0 => {
+                input.blk_idx = table.find_block_idx(key).unwrap();
+                input.position = Position::Unloaded {
+                    key: key.to_key_vec(),
+                    entry: None,
+                };
+            }
+            _ => input.move_to_block(),
+        }
+        input
+    }
+
+    /// The key of the next entry, or a lower bound of it, with whether it is exact.
+    fn head_key(&self) -> Option<(KeySlice<'_>, bool)> {
+        match &self.position {
+            Position::Unloaded { key, entry } => Some((key.as_key_slice(), entry.is_some())),
+            Position::Loaded(iter) => Some((iter.key(), true)),
+            Position::Done => None,
+        }
+    }
+
+    fn is_loaded(&self) -> bool {
+        matches!(self.position, Position::Loaded(_))
+    }
+
+    fn iter(&self) -> &BlockIterator {
+        match &self.position {
+            Position::Loaded(iter) => iter,
+            _ => unreachable!("the block of the input is not loaded"),
+        }
+    }
+
+    /// Moves to the first entry of the block at `table_idx` and `blk_idx`, or of the next block
+    /// that exists, without loading it.
+    fn move_to_block(&mut self) {
+        while let Some(table) = self.tables.get(self.table_idx) {
+            if let Some(meta) = table.block_metas().get(self.blk_idx) {
+                self.position = Position::Unloaded {
+                    key: meta.first_key.as_key_slice().to_key_vec(),
+                    entry: Some(0),
+                };
+                return;
+            }
+            self.table_idx += 1;
+            self.blk_idx = 0;
+        }
+        self.position = Position::Done;
+    }
+
+    fn next_block(&mut self) {
+        self.blk_idx += 1;
+        self.move_to_block();
+    }
+
+    fn load(&mut self, reader: &mut BlockReader) -> Result<()> {
+        let Position::Unloaded { key, entry } = &self.position else {
+            return Ok(());
+        };
+        let block = reader.read(&self.tables[self.table_idx], self.blk_idx)?;
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        match entry {
+            Some(entry) => iter.seek_to_index(*entry),
+            None => iter.seek_to_key(key.as_key_slice()),
+        }
+        if iter.is_valid() {
+            self.position = Position::Loaded(iter);
+        } else {
+            self.next_block();
+        }
+        Ok(())
+    }
+
+    /// Releases the block, to be read again once the input is the smallest.
+    fn unload(&mut self) {
+        let Position::Loaded(iter) = &self.position else {
+            return;
+        };
+        self.position = Position::Unloaded {
+            key: iter.key().to_key_vec(),
+            entry: Some(iter.index()),
+        };
+    }
+
+    /// Moves to the next entry, releasing the block once it is drained.
+    fn next(&mut self) {
+        let Position::Loaded(iter) = &mut self.position else {
+            return;
+        };
+        iter.next();
+        if !iter.is_valid() {
+            self.next_block();
+        }
+    }
+}
+
+/// Reads the blocks of the inputs, bypassing the block cache on a miss.
+struct BlockReader {
+    rate_limiter: Option<Arc<RateLimiter>>,
+    num_block_reads: u64,
+}
+
+impl BlockReader {
+    fn read(&mut self, table: &SsTable, blk_idx: usize) -> Result<Arc<Block>> {
+        if let Some(block) = table.get_cached_block(blk_idx) {
+            return Ok(block);
+        }
+        let block = table.read_block(blk_idx)?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.request(
+                table.blocks_len_on_disk(blk_idx, blk_idx + 1),
+                IoPriority::Low,
+            );
+        }
+        self.num_block_reads += 1;
+        Ok(block)
+    }
+}
+
+/// Merges sorted runs of SSTs for compaction while holding at most a given number of blocks in
+/// memory. The blocks are read strictly in the order their keys are consumed: an input's next
+/// block is only read once the first key of the block is the smallest key left, and a block is
+/// released as soon as it is drained. When reading a block would go over the budget, the loaded
+/// block whose next key is the largest is released first, and read again when its turn comes,
+/// so memory stays bounded at the cost of re-reads when the runs interleave finely.
+///
+/// The runs are given from the newest, and for the same key only the entry of the earliest run
+/// is returned, as by a [`crate::iterators::merge_iterator::MergeIterator`].
+pub struct StreamingCompactionIterator {
+    inputs: Vec<Input>,
+    reader: BlockReader,
+    max_buffered_blocks: usize,
+    peak_buffered_blocks: usize,
+    /// The input of the current entry, which is always loaded, or `None` once all are drained.
+    current: Option<usize>,
+    /// The key of the previous entry, used to skip the same key in the later runs.
+    prev_key: KeyVec,
+}
+
+impl StreamingCompactionIterator {
+    /// Creates the iterator over `runs` of SSTs, each sorted and non-overlapping, positioned at
+    /// the first key at or after `lower`. At most `max_buffered_blocks` blocks, at least one, are
+    /// held at a time. The block reads are charged to `rate_limiter` at low priority.
+    pub fn create(
+        runs: Vec<Vec<Arc<SsTable>>>,
+        lower: Option<KeySlice>,
+        max_buffered_blocks: usize,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        if max_buffered_blocks == 0 {
+            bail!("the buffer budget of a streaming compaction must be at least one block");
+        }
+        let mut iter = Self {
+            inputs: runs
+                .into_iter()
+                .map(|tables| Input::create(tables, lower))
+                .collect(),
+            reader: BlockReader {
+                rate_limiter,
+                num_block_reads: 0,
+            },
+            max_buffered_blocks,
+            peak_buffered_blocks: 0,
+            current: None,
+            prev_key: KeyVec::new(),
+        };
+        iter.select()?;
+        Ok(iter)
+    }
+
+    /// The number of blocks held in memory.
+    pub fn buffered_blocks(&self) -> usize {
+        self.inputs.iter().filter(|input| input.is_loaded()).count()
+    }
+
+    /// The most blocks held in memory at once so far, never above the budget.
+    pub fn peak_buffered_blocks(&self) -> usize {
+        self.peak_buffered_blocks
+    }
+
+    /// The number of blocks read from the disk so far, counting the re-reads of released blocks
+    /// but not the blocks found in the block cache.
+    pub fn num_block_reads(&self) -> u64 {
+        self.reader.num_block_reads
+    }
+
+    /// Makes the input with the smallest key current, loading its block if needed. An input
+    /// whose lower bound is the smallest is loaded first, since its next key may be smaller than
+    /// those of the others; on the same key, the earliest run wins.
+    fn select(&mut self) -> Result<()> {
+        loop {
+            let smallest = self
+                .inputs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, input)| Some((input.head_key()?, idx)))
+                .min()
+                .map(|(_, idx)| idx);
+            let Some(idx) = smallest else {
+                self.current = None;
+                return Ok(());
+            };
+            if self.inputs[idx].is_loaded() {
+                self.current = Some(idx);
+                return Ok(());
+            }
+            self.load(idx)?;
+        }
+    }
+
+    /// Loads the block of input `idx`, first releasing the blocks furthest ahead to stay within
+    /// the budget.
+    fn load(&mut self, idx: usize) -> Result<()> {
+        while self.buffered_blocks() >= self.max_buffered_blocks {
+            let furthest = self
+                .inputs
+                .iter()
+                .enumerate()
+                .filter(|(_, input)| input.is_loaded())
+                .max_by(|(_, a), (_, b)| a.iter().key().cmp(&b.iter().key()))
+                .map(|(idx, _)| idx)
+                .unwrap();
+            self.inputs[furthest].unload();
+        }
+        self.inputs[idx].load(&mut self.reader)?;
+        self.peak_buffered_blocks = self.peak_buffered_blocks.max(self.buffered_blocks());
+        Ok(())
+    }
+}
+
+impl StorageIterator for StreamingCompactionIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice<'_> {
+        self.inputs[self.current.unwrap()].iter().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.inputs[self.current.unwrap()].iter().value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.inputs[self.current.unwrap()].iter().value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.inputs[self.current.unwrap()].iter().flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.inputs[self.current.unwrap()].iter().is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let Some(current) = self.current else {
+            return Ok(());
+        };
+        self.prev_key
+            .set_from_slice(self.inputs[current].iter().key());
+        self.inputs[current].next();
+        self.select()?;
+        while let Some(current) = self.current {
+            if self.key() != self.prev_key.as_key_slice() {
+                break;
+            }
+            self.inputs[current].next();
+            self.select()?;
+        }
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inputs
+            .iter()
+            .filter(|input| input.head_key().is_some())
+            .count()
+    }
+}
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
+use super::priority::{ssts_size, CompactionPriority, LevelCompactionDebt};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
@@ -31,10 +32,50 @@ impl TieredCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_with_reason(snapshot)
+            .map(|(task, _)| task)
+    }
+
+    /// Generates a compaction task together with the reason it was picked.
+    pub fn generate_compaction_task_with_reason(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<(TieredCompactionTask, String)> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Like [`Self::generate_compaction_task_with_reason`], but returns `None` while any SST is
+    /// `busy` in another task. Tiered compaction tasks always include the oldest tiers, so two of
+    /// them can never run concurrently.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+    ) -> Option<(TieredCompactionTask, String)> {
+        if !busy.is_empty() {
+            return None;
+        }
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
         );
+        let (num_tiers_to_take, bottom_tier_included, reason) = self.pick_tiers(snapshot)?;
+        println!("compaction triggered by {}", reason);
+        let task = TieredCompactionTask {
+            tiers: snapshot
+                .levels
+                .iter()
+                .take(num_tiers_to_take)
+                .cloned()
+                .collect::<Vec<_>>(),
+            bottom_tier_included,
+        };
+        Some((task, reason))
+    }
+
+    /// Picks how many of the newest tiers to merge, whether the bottom tier is included, and the
+    /// reason.
+    fn pick_tiers(&self, snapshot: &LsmStorageState) -> Option<(usize, bool, String)> {
         if snapshot.levels.len() < self.options.num_tiers {
             return None;
         }
@@ -46,14 +87,8 @@ impl TieredCompactionController {
         let space_amp_ratio =
             (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
-            println!(
-                "compaction triggered by space amplification ratio: {}",
-                space_amp_ratio
-            );
-            return Some(TieredCompactionTask {
-                tiers: snapshot.levels.clone(),
-                bottom_tier_included: true,
-            });
+            let reason = format!("space amplification ratio {:.3}%", space_amp_ratio);
+            return Some((snapshot.levels.len(), true, reason));
         }
         let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
         // compaction triggered by size ratio
@@ -63,33 +98,45 @@ impl TieredCompactionController {
             let next_level_size = snapshot.levels[id + 1].1.len();
             let current_size_ratio = size as f64 / next_level_size as f64;
             if current_size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
-                println!(
-                    "compaction triggered by size ratio: {}",
-                    current_size_ratio * 100.0
-                );
-                return Some(TieredCompactionTask {
-                    tiers: snapshot
-                        .levels
-                        .iter()
-                        .take(id + 2)
-                        .cloned()
-                        .collect::<Vec<_>>(),
-                    bottom_tier_included: id + 2 >= snapshot.levels.len(),
-                });
+                let reason = format!("size ratio {:.3}%", current_size_ratio * 100.0);
+                return Some((id + 2, id + 2 >= snapshot.levels.len(), reason));
             }
         }
         // trying to reduce sorted runs without respecting size ratio
         let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
-        println!("compaction triggered by reducing sorted runs");
-        return Some(TieredCompactionTask {
-            tiers: snapshot
-                .levels
-                .iter()
-                .take(num_tiers_to_take)
-                .cloned()
-                .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
-        });
+        Some((
+            num_tiers_to_take,
+            snapshot.levels.len() >= num_tiers_to_take,
+            "reducing sorted runs".to_string(),
+        ))
+    }
+
+    /// Computes the compaction debt of each tier. Every trigger of tiered compaction requires
+    /// the number of sorted runs to reach `num_tiers`, so all tiers share the same score and
+    /// only the tiers the next task would merge are in debt.
+    pub fn compaction_debt(&self, snapshot: &LsmStorageState) -> Vec<LevelCompactionDebt> {
+        let score = snapshot.levels.len() as f64 / self.options.num_tiers as f64;
+        let task_tiers = self
+            .pick_tiers(snapshot)
+            .map_or(0, |(num_tiers, _, _)| num_tiers);
+        snapshot
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(i, (_, sst_ids))| {
+                let in_task = i < task_tiers;
+                LevelCompactionDebt {
+                    level: i + 1,
+                    score,
+                    priority: in_task.then_some(CompactionPriority::L0Pressure),
+                    debt_bytes: if in_task {
+                        ssts_size(snapshot, sst_ids)
+                    } else {
+                        0
+                    },
+                }
+            })
+            .collect()
     }
 
     pub fn apply_compaction_result(
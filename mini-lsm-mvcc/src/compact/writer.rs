@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::block::{BLOCK_FORMAT_V6, ENTRY_FLAG_MERGE_OPERAND};
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+use crate::range_tombstone::RangeTombstone;
+use crate::rate_limiter::IoPriority;
+use crate::table::{SsTable, SsTableBuilder};
+use crate::trace::trace_span;
+
+/// Writes the output SSTs of a compaction through a single [`SsTableBuilder`], so that its
+/// buffers (block data, entry offsets, key scratch space and bloom filter hashes) are cleared
+/// between blocks and between output SSTs instead of being allocated again.
+pub(crate) struct CompactionWriter<'a> {
+    storage: &'a LsmStorageInner,
+    builder: SsTableBuilder,
+    /// Whether the current SST has any entry.
+    has_entries: bool,
+    target_sst_size: usize,
+    /// Split between the output SSTs, each getting the part within its user keys.
+    range_tombstones: Vec<RangeTombstone>,
+    /// The first user key of the current SST, `None` for the first SST.
+    lower: Option<Bytes>,
+    outputs: Vec<Arc<SsTable>>,
+}
+
+impl<'a> CompactionWriter<'a> {
+    /// Creates a writer whose SSTs store entry flags if `has_entry_flags`, i.e. if any input SST
+    /// does.
+    pub(crate) fn new(
+        storage: &'a LsmStorageInner,
+        range_tombstones: Vec<RangeTombstone>,
+        has_entry_flags: bool,
+    ) -> Self {
+        let mut builder = if has_entry_flags {
+            SsTableBuilder::new_with_format_version(storage.options.block_size, BLOCK_FORMAT_V6)
+        } else {
+            SsTableBuilder::new(storage.options.block_size)
+        };
+        builder.set_creation_time(storage.options.clock.now_secs());
+        builder.set_rate_limiter(storage.rate_limiter.clone(), IoPriority::Low);
+        Self {
+            storage,
+            builder,
+            has_entries: false,
+            target_sst_size: storage.dynamic_options.load().target_sst_size,
+            range_tombstones,
+            lower: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Adds an entry to the current SST. Once it reaches the target size, the SST is written
+    /// before the next entry that starts a new user key, so that versions of the same user key
+    /// are never split across two SSTs. Merge operands can only be written with a merge
+    /// operator.
+    pub(crate) fn add(
+        &mut self,
+        key: KeySlice,
+        value: &[u8],
+        flags: u8,
+        new_user_key: bool,
+    ) -> Result<()> {
+        if flags & ENTRY_FLAG_MERGE_OPERAND != 0 && self.storage.options.merge_operator.is_none() {
+            bail!("merge operands cannot be compacted without a merge operator");
+        }
+        if new_user_key && self.builder.estimated_size() >= self.target_sst_size {
+            self.finish_sst(Some(key.key_ref()))?;
+        }
+        self.builder.add_with_flags(key, value, flags);
+        self.has_entries = true;
+        Ok(())
+    }
+
+    /// Writes the current SST, which ends before the user key `upper`, if any.
+    fn finish_sst(&mut self, upper: Option<&[u8]>) -> Result<()> {
+        for tombstone in &self.range_tombstones {
+            if let Some(tombstone) = tombstone.clip(self.lower.as_deref(), upper) {
+                self.builder.add_range_tombstone(tombstone);
+            }
+        }
+        self.lower = upper.map(Bytes::copy_from_slice);
+        let sst_id = self.storage.next_sst_id();
+        let span = trace_span!(
+            "compaction_output",
+            sst_id,
+            output_bytes = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        let sst = self
+            .builder
+            .build_and_reset(
+                sst_id,
+                Some(self.storage.block_cache.clone()),
+                self.storage.path_of_new_sst(sst_id)?,
+            )?
+            .with_cache_namespace(self.storage.cache_namespace)
+            .with_metrics(self.storage.metrics.clone());
+        self.builder
+            .set_creation_time(self.storage.options.clock.now_secs());
+        self.has_entries = false;
+        span.record("output_bytes", sst.table_size());
+        self.outputs.push(Arc::new(sst));
+        Ok(())
+    }
+
+    /// Writes the last SST, if it has any entry or range tombstone.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        let has_range_tombstones = self
+            .range_tombstones
+            .iter()
+            .any(|tombstone| tombstone.clip(self.lower.as_deref(), None).is_some());
+        if self.has_entries || has_range_tombstones {
+            self.finish_sst(None)?;
+        }
+        Ok(())
+    }
+
+    /// The SSTs written so far, e.g. to remove them when the compaction fails.
+    pub(crate) fn outputs(&self) -> &[Arc<SsTable>] {
+        &self.outputs
+    }
+
+    pub(crate) fn into_outputs(self) -> Vec<Arc<SsTable>> {
+        self.outputs
+    }
+}
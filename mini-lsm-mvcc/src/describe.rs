@@ -0,0 +1,122 @@
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::mem_table::MemTable;
+use crate::table::SsTable;
+
+/// Keys in an [`LsmStateDescription`] are cut after this many bytes.
+pub const DESCRIBED_KEY_MAX_LEN: usize = 32;
+
+/// A snapshot of the structure of the engine, see [`LsmStorageInner::describe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LsmStateDescription {
+    pub memtable: MemTableDescription,
+    /// From the newest to the oldest.
+    pub imm_memtables: Vec<MemTableDescription>,
+    /// From the newest to the oldest.
+    pub l0_sstables: Vec<SstDescription>,
+    pub levels: Vec<LevelDescription>,
+    /// The compaction options, as printed by `Debug`.
+    pub compaction_strategy: String,
+    /// The compaction task the strategy would pick now, as printed by `Debug`, if any.
+    pub pending_task: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemTableDescription {
+    pub id: usize,
+    pub approximate_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LevelDescription {
+    /// The level, or the tier id for tiered compaction.
+    pub level: usize,
+    pub sstables: Vec<SstDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SstDescription {
+    pub id: usize,
+    pub size: u64,
+    /// The first and last user keys, see [`describe_key`], or `None` when keys are omitted.
+    pub first_key: Option<String>,
+    pub last_key: Option<String>,
+    /// Number of entries, including tombstones and all versions.
+    pub num_entries: u64,
+}
+
+/// Prints a key for humans: bytes outside of printable ASCII are escaped, and keys longer than
+/// [`DESCRIBED_KEY_MAX_LEN`] bytes are cut and end with `...`.
+pub fn describe_key(key: &[u8]) -> String {
+    let mut described = key
+        .iter()
+        .take(DESCRIBED_KEY_MAX_LEN)
+        .flat_map(|byte| std::ascii::escape_default(*byte))
+        .map(char::from)
+        .collect::<String>();
+    if key.len() > DESCRIBED_KEY_MAX_LEN {
+        described.push_str("...");
+    }
+    described
+}
+
+fn describe_memtable(memtable: &MemTable) -> MemTableDescription {
+    MemTableDescription {
+        id: memtable.id(),
+        approximate_size: memtable.approximate_size(),
+    }
+}
+
+fn describe_sst(table: &SsTable, include_keys: bool) -> SstDescription {
+    SstDescription {
+        id: table.sst_id(),
+        size: table.table_size(),
+        first_key: include_keys.then(|| describe_key(table.first_key().key_ref())),
+        last_key: include_keys.then(|| describe_key(table.last_key().key_ref())),
+        num_entries: table.properties().num_entries,
+    }
+}
+
+impl LsmStorageInner {
+    /// Describes the memtables, the SSTs of each level and the compaction strategy, a structured
+    /// [`Self::dump_structure`] e.g. served as JSON with the `serde` feature. Keys are omitted
+    /// unless `include_keys`.
+    pub fn describe(&self, include_keys: bool) -> LsmStateDescription {
+        let snapshot = self.state.load_full();
+        let describe_ssts = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| describe_sst(&snapshot.sstables[id], include_keys))
+                .collect()
+        };
+        LsmStateDescription {
+            memtable: describe_memtable(&snapshot.memtable),
+            imm_memtables: snapshot
+                .imm_memtables
+                .iter()
+                .map(|memtable| describe_memtable(memtable))
+                .collect(),
+            l0_sstables: describe_ssts(&snapshot.l0_sstables),
+            levels: snapshot
+                .levels
+                .iter()
+                .map(|(level, ids)| LevelDescription {
+                    level: *level,
+                    sstables: describe_ssts(ids),
+                })
+                .collect(),
+            compaction_strategy: format!("{:?}", self.dynamic_options().compaction_options),
+            pending_task: self
+                .plan_compaction()
+                .map(|plan| format!("{:?}", plan.task)),
+        }
+    }
+}
+
+impl MiniLsm {
+    pub fn describe(&self, include_keys: bool) -> LsmStateDescription {
+        self.inner.describe(include_keys)
+    }
+}
@@ -0,0 +1,154 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::backup::RESTORE_IN_PROGRESS;
+use crate::keyspace::{check_keyspace_name, KEYSPACES_DIR};
+use crate::lsm_storage::MiniLsm;
+
+/// The file holding the id of the process that opened the storage for writing, removed when the
+/// storage is closed.
+pub(crate) const LOCK_FILE: &str = "LOCK";
+
+/// Writes the lock file of the storage in `path`, replacing the one left behind by a process that
+/// did not close the storage.
+pub(crate) fn write_lock_file(path: &Path) -> Result<()> {
+    std::fs::write(path.join(LOCK_FILE), std::process::id().to_string())
+        .context("failed to write the lock file")?;
+    Ok(())
+}
+
+pub(crate) fn remove_lock_file(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path.join(LOCK_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the lock file in `path` was written by a process still running. Only Linux can tell a
+/// process that has exited, so the lock file of another process is taken as live elsewhere.
+fn is_locked(path: &Path) -> Result<bool> {
+    let contents = match std::fs::read_to_string(path.join(LOCK_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    // left half-written by a process that crashed
+    let Ok(pid) = contents.trim().parse::<u32>() else {
+        return Ok(false);
+    };
+    if pid == std::process::id() || !cfg!(target_os = "linux") {
+        return Ok(true);
+    }
+    Ok(Path::new("/proc").join(pid.to_string()).exists())
+}
+
+/// Whether the engine may have written a file of this name to the directory of a storage.
+pub(crate) fn is_storage_file(name: &str) -> bool {
+    if name == "MANIFEST" || name == LOCK_FILE || name == RESTORE_IN_PROGRESS {
+        return true;
+    }
+    name.strip_suffix(".sst")
+        .or_else(|| name.strip_suffix(".wal"))
+        .is_some_and(|id| !id.is_empty() && id.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// The files in `path`, and the directories for which `is_storage_dir` holds. Fails if `path`
+/// holds anything else, as it does not look like a storage then.
+fn list_storage_entries(
+    path: &Path,
+    is_storage_dir: impl Fn(&str) -> bool,
+) -> Result<(Vec<OsString>, Vec<OsString>)> {
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in
+        std::fs::read_dir(path).with_context(|| format!("failed to list {}", path.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+        let file_type = entry.file_type()?;
+        if file_type.is_file() && name.to_str().is_some_and(is_storage_file) {
+            files.push(name);
+        } else if file_type.is_dir() && name.to_str().is_some_and(&is_storage_dir) {
+            dirs.push(name);
+        } else {
+            bail!(
+                "{} does not look like a storage, it holds {:?}",
+                path.display(),
+                name
+            );
+        }
+    }
+    Ok((files, dirs))
+}
+
+fn remove_files(path: &Path, files: &[OsString]) -> Result<()> {
+    for name in files {
+        std::fs::remove_file(path.join(name))
+            .with_context(|| format!("failed to remove {:?}", name))?;
+    }
+    Ok(())
+}
+
+fn remove_dir(path: &Path) -> Result<()> {
+    std::fs::remove_dir(path).with_context(|| format!("failed to remove {}", path.display()))
+}
+
+impl MiniLsm {
+    /// Removes the storage in `path`, which must not be open: its SSTs, WALs, manifest and lock
+    /// file, and its keyspaces, see [`Self::open_with_keyspaces`], then the directory itself.
+    /// Nothing is removed if the directory does not look like a storage, i.e. it is empty or
+    /// holds anything but files named as the engine names them and the directory of the
+    /// keyspaces, or if a lock file of the storage or of a keyspace was written by a process
+    /// still running, including this one.
+    pub fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let (mut files, dirs) = list_storage_entries(path, |name| name == KEYSPACES_DIR)?;
+        if files.is_empty() {
+            bail!(
+                "{} does not look like a storage, it is empty",
+                path.display()
+            );
+        }
+        if is_locked(path)? {
+            bail!("{} is open, close the storage first", path.display());
+        }
+        // the segments of the log of the keyspaces, and the files of each keyspace
+        let mut keyspaces: Vec<(PathBuf, Vec<OsString>)> = Vec::new();
+        for dir in dirs {
+            let dir = path.join(dir);
+            let (segments, keyspace_dirs) =
+                list_storage_entries(&dir, |name| check_keyspace_name(name).is_ok())?;
+            for keyspace_dir in keyspace_dirs {
+                let keyspace_dir = dir.join(keyspace_dir);
+                let (keyspace_files, _) = list_storage_entries(&keyspace_dir, |_| false)?;
+                if is_locked(&keyspace_dir)? {
+                    bail!(
+                        "{} is open, close the storage first",
+                        keyspace_dir.display()
+                    );
+                }
+                keyspaces.push((keyspace_dir, keyspace_files));
+            }
+            keyspaces.push((dir, segments));
+        }
+
+        // the keyspaces before the storage, and the manifest last, so that a storage left
+        // half-removed fails to open instead of opening empty
+        for (dir, files) in keyspaces {
+            remove_files(&dir, &files)?;
+            remove_dir(&dir)?;
+        }
+        files.sort_by_key(|name| name == "MANIFEST");
+        remove_files(path, &files)?;
+        remove_dir(path)
+    }
+
+    /// Closes the storage, see [`Self::close`], and then removes it, see [`Self::destroy`].
+    pub fn close_and_destroy(&self) -> Result<()> {
+        self.inner.check_writable()?;
+        self.close()?;
+        Self::destroy(&self.inner.path)
+    }
+}
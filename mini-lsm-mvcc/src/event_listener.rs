@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::compact::BackgroundJobStats;
+
+/// A flush of an immutable memtable to an L0 SST.
+#[derive(Clone, Debug)]
+pub struct FlushJobInfo {
+    /// The id of the memtable, which is also the id of the SST it is flushed to.
+    pub sst_id: usize,
+    /// The approximate size of the memtable.
+    pub memtable_size: usize,
+}
+
+/// A compaction task.
+#[derive(Clone, Debug)]
+pub struct CompactionJobInfo {
+    /// Identifies the job in the progress and completion events.
+    pub job_id: u64,
+    /// The task being run.
+    pub description: String,
+    /// Why the task was picked.
+    pub reason: String,
+    pub input_sst_ids: Vec<usize>,
+}
+
+/// Receives the lifecycle events of the background jobs, e.g. to export them to a tracing
+/// system. Listeners are registered in [`crate::lsm_storage::LsmStorageOptions`] and invoked in
+/// registration order. The engine holds no lock that the write path waits on while calling
+/// them, so a slow listener only delays the job it is notified about. Jobs that fail are not
+/// reported as complete.
+pub trait EventListener: Send + Sync {
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    fn on_flush_complete(&self, _info: &FlushJobInfo, _stats: &BackgroundJobStats) {}
+
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) {}
+
+    /// Called whenever the estimated progress of a compaction grows by at least a percent. The
+    /// estimate is the share of input entries the compaction has read, so it does not reach
+    /// 100 before the output is written.
+    fn on_compaction_progress(&self, _job_id: u64, _percent: u8) {}
+
+    /// Called once the output of the compaction has been installed.
+    fn on_compaction_complete(
+        &self,
+        _info: &CompactionJobInfo,
+        _output_sst_ids: &[usize],
+        _stats: &BackgroundJobStats,
+    ) {
+    }
+
+    /// Called when writes are slowed down or stopped to let the background jobs catch up.
+    fn on_write_stall(&self, _reason: &str) {}
+}
+
+impl std::fmt::Debug for dyn EventListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventListener")
+    }
+}
+
+/// Tracks the progress of a compaction task, shared by its sub-compactions.
+pub(crate) struct CompactionProgress {
+    job_id: u64,
+    listeners: Vec<Arc<dyn EventListener>>,
+    total_entries: u64,
+    read_entries: AtomicU64,
+    reported_percent: AtomicU64,
+}
+
+impl CompactionProgress {
+    pub(crate) fn new(
+        job_id: u64,
+        listeners: Vec<Arc<dyn EventListener>>,
+        total_entries: u64,
+    ) -> Self {
+        Self {
+            job_id,
+            listeners,
+            total_entries,
+            read_entries: AtomicU64::new(0),
+            reported_percent: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds `entries` read from the input, and reports the progress if it has grown by at least
+    /// a percent.
+    pub(crate) fn advance(&self, entries: u64) {
+        if self.listeners.is_empty() || self.total_entries == 0 || entries == 0 {
+            return;
+        }
+        let read_entries = self.read_entries.fetch_add(entries, Ordering::Relaxed) + entries;
+        let percent = (read_entries * 100 / self.total_entries).min(100);
+        if self.reported_percent.fetch_max(percent, Ordering::Relaxed) < percent {
+            for listener in &self.listeners {
+                listener.on_compaction_progress(self.job_id, percent as u8);
+            }
+        }
+    }
+}
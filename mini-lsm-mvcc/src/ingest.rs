@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::clock::{Clock, SystemClock};
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+/// Writes a standalone SST to ingest with [`crate::lsm_storage::MiniLsm::ingest_ssts`], e.g. from
+/// sorted data produced offline. Keys must be added in strictly increasing order, and are written
+/// at [`TS_DEFAULT`], so that the file can be linked into the storage as is when ingested behind
+/// the existing data, see [`IngestOptions::ingest_behind`].
+pub struct SstWriter {
+    builder: SsTableBuilder,
+    path: PathBuf,
+    last_key: Option<Vec<u8>>,
+}
+
+impl SstWriter {
+    /// Starts an SST to be written to `path` once finished, with blocks of `block_size` bytes.
+    pub fn new(path: impl AsRef<Path>, block_size: usize) -> Self {
+        let mut builder = SsTableBuilder::new(block_size);
+        builder.set_creation_time(SystemClock.now_secs());
+        Self {
+            builder,
+            path: path.as_ref().to_path_buf(),
+            last_key: None,
+        }
+    }
+
+    /// Adds `key` with `value`, which must not be empty.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if value.is_empty() {
+            bail!("an empty value is a tombstone, use `delete` instead");
+        }
+        self.add(key, value)
+    }
+
+    /// Adds a tombstone for `key`, which hides the key in the data the SST is ingested in front of.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.add(key, b"")
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            bail!("key cannot be empty");
+        }
+        if let Some(last_key) = &self.last_key {
+            if key <= last_key.as_slice() {
+                bail!("keys must be added in strictly increasing order");
+            }
+        }
+        self.builder
+            .add(KeySlice::from_slice(key, TS_DEFAULT), value);
+        self.last_key = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// Writes the SST, with the properties of its entries, and returns the size of the file.
+    pub fn finish(self) -> Result<u64> {
+        if self.last_key.is_none() {
+            bail!("cannot write an SST without entries");
+        }
+        let sst = self.builder.build(0, None, &self.path)?;
+        Ok(sst.table_size())
+    }
+}
+
+/// How SSTs are ingested, see [`crate::lsm_storage::MiniLsm::ingest_ssts`].
+#[derive(Clone, Debug, Default)]
+pub struct IngestOptions {
+    /// Removes the files once ingested, instead of leaving them in place.
+    pub move_files: bool,
+    /// Ingests the data as older than everything in the storage, at [`TS_DEFAULT`], instead of
+    /// newer: existing keys keep their values, and existing snapshots see the ingested keys. The
+    /// files go to the bottom level, which none of them may overlap, and neither may they overlap
+    /// each other. Files holding every entry at [`TS_DEFAULT`], e.g. written by [`SstWriter`], are
+    /// linked into the storage instead of being rewritten.
+    pub ingest_behind: bool,
+}
+
+impl LsmStorageInner {
+    /// Ingests an SST built outside of the engine, e.g. by [`SsTableBuilder`], like
+    /// [`Self::ingest_ssts`] with the default options. Returns the level the SST is installed in.
+    pub fn ingest_sst(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let levels = self.ingest_ssts(&[path.as_ref().to_path_buf()], &IngestOptions::default())?;
+        Ok(levels[0])
+    }
+
+    /// Ingests SSTs built outside of the engine, e.g. by [`SstWriter`], without going through
+    /// the memtable, and returns the level each is installed in. Only the latest version of each
+    /// key in a file is kept, rewritten at a new commit timestamp, so that the data is newer than
+    /// anything in the engine and invisible to existing snapshots; a file is newer than the files
+    /// before it in `paths`. Each SST goes to the deepest level it can, see
+    /// [`Self::ingestion_level`]. With [`IngestOptions::ingest_behind`] set, the data is older
+    /// than anything in the engine instead. The SSTs are installed at once, by a single manifest
+    /// record.
+    pub fn ingest_ssts(&self, paths: &[PathBuf], options: &IngestOptions) -> Result<Vec<usize>> {
+        self.check_writable()?;
+        let mut externals = Vec::with_capacity(paths.len());
+        for path in paths {
+            let external = SsTable::open(0, None, FileObject::open(path)?)
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            if external.num_of_blocks() == 0 {
+                bail!("cannot ingest {}, an SST without entries", path.display());
+            }
+            if !external.range_tombstones().is_empty() {
+                bail!(
+                    "cannot ingest {}, an SST with range tombstones",
+                    path.display()
+                );
+            }
+            externals.push(Arc::new(external));
+        }
+        if options.ingest_behind {
+            // at the same timestamp, overlapping keys would be read back at random
+            let mut sorted = externals.iter().zip(paths).collect::<Vec<_>>();
+            sorted.sort_by(|a, b| a.0.first_key().cmp(b.0.first_key()));
+            for pair in sorted.windows(2) {
+                if pair[0].0.last_key().key_ref() >= pair[1].0.first_key().key_ref() {
+                    bail!(
+                        "{} and {} overlap, and cannot both be ingested behind",
+                        pair[0].1.display(),
+                        pair[1].1.display()
+                    );
+                }
+            }
+        }
+
+        // hold the write lock until the SSTs are installed, so that no write commits at a later
+        // timestamp in the meantime
+        let write_lock = self.mvcc().lock_writes();
+        self.check_open(&write_lock)?;
+        let latest_commit_ts = self.mvcc().latest_commit_ts();
+        let remove_ssts = |ssts: &[Arc<SsTable>]| {
+            for sst in ssts {
+                std::fs::remove_file(self.path_of_sst(sst.sst_id())).ok();
+            }
+        };
+        let mut ssts = Vec::with_capacity(paths.len());
+        for (idx, (external, path)) in externals.into_iter().zip(paths).enumerate() {
+            let ts = if options.ingest_behind {
+                TS_DEFAULT
+            } else {
+                latest_commit_ts + 1 + idx as u64
+            };
+            let sst_id = self.next_sst_id();
+            let sst = self.path_of_new_sst(sst_id).and_then(|sst_path| {
+                if options.ingest_behind && external.max_ts() == TS_DEFAULT {
+                    self.link_external_sst(path, sst_id, &sst_path)
+                } else {
+                    self.rewrite_external_sst(external, ts, sst_id, &sst_path)
+                }
+            });
+            match sst {
+                Ok(sst) => ssts.push(Arc::new(sst)),
+                Err(e) => {
+                    remove_ssts(&ssts);
+                    return Err(e);
+                }
+            }
+        }
+
+        let levels = {
+            // claims of running compactions must not change until the SSTs are installed
+            let busy = self.compacting_ssts.lock();
+            let state_lock = self.state_lock.lock();
+            let mut snapshot = self.state.load().as_ref().clone();
+            let flush_to_l0 = self.compaction_controller.load().flush_to_l0();
+            let mut installed = Vec::with_capacity(ssts.len());
+            for sst in &ssts {
+                // later SSTs see the earlier ones, and go above them where they overlap
+                let level = if options.ingest_behind {
+                    match self.ingestion_behind_level(&snapshot, &busy, sst) {
+                        Ok(level) => level,
+                        Err(e) => {
+                            remove_ssts(&ssts);
+                            return Err(e);
+                        }
+                    }
+                } else {
+                    self.ingestion_level(&snapshot, &busy, sst)
+                };
+                let index = if level == 0 {
+                    0
+                } else {
+                    snapshot.levels[level - 1]
+                        .1
+                        .partition_point(|id| snapshot.sstables[id].first_key() < sst.first_key())
+                };
+                snapshot.sstables.insert(sst.sst_id(), sst.clone());
+                apply_ingestion(&mut snapshot, flush_to_l0, sst.sst_id(), level, index);
+                installed.push((sst.sst_id(), level, index));
+            }
+            self.state.store(Arc::new(snapshot));
+            self.sync_dir()?;
+            let levels = installed.iter().map(|(_, level, _)| *level).collect();
+            self.manifest()
+                .add_record(&state_lock, ManifestRecord::IngestBatch(installed))?;
+            levels
+        };
+        if !options.ingest_behind {
+            self.mvcc()
+                .update_commit_ts(latest_commit_ts + paths.len() as u64);
+        }
+        for (path, sst) in paths.iter().zip(&ssts) {
+            println!("ingested {} as {}.sst", path.display(), sst.sst_id());
+            if options.move_files {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Writes the latest version of each key of `external` at `ts` to the new SST `sst_id`.
+    fn rewrite_external_sst(
+        &self,
+        external: Arc<SsTable>,
+        ts: u64,
+        sst_id: usize,
+        sst_path: &Path,
+    ) -> Result<SsTable> {
+        let mut builder = SsTableBuilder::new(self.options.block_size);
+        builder.set_creation_time(self.options.clock.now_secs());
+        let mut iter = SsTableIterator::create_and_seek_to_first(external)?;
+        let mut last_key = Vec::new();
+        while iter.is_valid() {
+            if iter.key().key_ref() != last_key {
+                last_key.clear();
+                last_key.extend(iter.key().key_ref());
+                builder.add(KeySlice::from_slice(&last_key, ts), iter.value());
+            }
+            iter.next()?;
+        }
+        Ok(builder
+            .build(sst_id, Some(self.block_cache.clone()), sst_path)?
+            .with_cache_namespace(self.cache_namespace)
+            .with_metrics(self.metrics.clone()))
+    }
+
+    /// Links the external SST in `path` into the storage as the SST `sst_id`, or copies it if it
+    /// cannot be linked, e.g. from another file system.
+    fn link_external_sst(&self, path: &Path, sst_id: usize, sst_path: &Path) -> Result<SsTable> {
+        if std::fs::hard_link(path, sst_path).is_err() {
+            std::fs::copy(path, sst_path)
+                .with_context(|| format!("failed to copy {}", path.display()))?;
+            std::fs::File::open(sst_path)?.sync_all()?;
+        }
+        Ok(SsTable::open(
+            sst_id,
+            Some(self.block_cache.clone()),
+            FileObject::open(sst_path)?,
+        )?
+        .with_cache_namespace(self.cache_namespace)
+        .with_metrics(self.metrics.clone()))
+    }
+
+    /// Picks the level where `sst` is ingested behind the data of the storage: the bottom level,
+    /// as a tombstone only reaches the level it is dropped in through the levels above it. Fails
+    /// if `sst` overlaps the bottom level, or if a running compaction writes to it, i.e. it holds
+    /// one of the `busy` SSTs or, with leveled compaction, the nearest non-empty level above it
+    /// does. With tiered compaction, the bottom level is the oldest tier.
+    fn ingestion_behind_level(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+        sst: &SsTable,
+    ) -> Result<usize> {
+        let Some((_, bottom)) = snapshot.levels.last() else {
+            // the first tier
+            return Ok(0);
+        };
+        if overlaps(snapshot, bottom, sst) {
+            bail!("cannot ingest behind an SST overlapping the bottom level");
+        }
+        let is_busy = |ids: &[usize]| ids.iter().any(|id| busy.contains(id));
+        let mut writes_to_bottom = is_busy(bottom);
+        if self.compaction_controller.load().flush_to_l0() {
+            // a compaction writes below its input, skipping empty levels
+            let mut above_busy = is_busy(&snapshot.l0_sstables);
+            for (_, ssts) in &snapshot.levels[..snapshot.levels.len() - 1] {
+                if !ssts.is_empty() {
+                    above_busy = is_busy(ssts);
+                }
+            }
+            writes_to_bottom |= above_busy;
+        }
+        if writes_to_bottom {
+            bail!("cannot ingest behind while a compaction writes to the bottom level");
+        }
+        Ok(snapshot.levels.len())
+    }
+
+    /// Picks the deepest level where `sst` can be installed: none of the SSTs in that level or the
+    /// levels above it may overlap its key range, or the ingested data would be older than data
+    /// below it. Levels that a running compaction writes to, i.e. holding one of the `busy` SSTs
+    /// or below the nearest non-empty level holding one, are skipped, as installing the result of
+    /// the compaction would not account for the ingested SST. Falls back to L0, which is also
+    /// where tiered compaction places every ingested SST, as a new tier.
+    pub(crate) fn ingestion_level(
+        &self,
+        snapshot: &LsmStorageState,
+        busy: &HashSet<usize>,
+        sst: &SsTable,
+    ) -> usize {
+        if !self.compaction_controller.load().flush_to_l0() {
+            return 0;
+        }
+        if overlaps(snapshot, &snapshot.l0_sstables, sst) {
+            return 0;
+        }
+        let is_busy = |ids: &[usize]| ids.iter().any(|id| busy.contains(id));
+        let mut level = 0;
+        let mut above_busy = is_busy(&snapshot.l0_sstables);
+        for (idx, (_, ssts)) in snapshot.levels.iter().enumerate() {
+            if overlaps(snapshot, ssts, sst) {
+                break;
+            }
+            let level_busy = is_busy(ssts);
+            if !level_busy && !above_busy {
+                level = idx + 1;
+            }
+            if !ssts.is_empty() {
+                above_busy = level_busy;
+            }
+        }
+        level
+    }
+}
+
+/// Whether any of the SSTs `ids` overlaps the key range of `sst`.
+fn overlaps(snapshot: &LsmStorageState, ids: &[usize], sst: &SsTable) -> bool {
+    ids.iter().any(|id| {
+        let other = &snapshot.sstables[id];
+        other.first_key().key_ref() <= sst.last_key().key_ref()
+            && sst.first_key().key_ref() <= other.last_key().key_ref()
+    })
+}
+
+/// Installs an ingested SST in `level` at `index`, or on top of L0 (as a new tier with
+/// `flush_to_l0` unset) for level 0. Shared by ingestion and manifest recovery.
+pub(crate) fn apply_ingestion(
+    state: &mut LsmStorageState,
+    flush_to_l0: bool,
+    sst_id: usize,
+    level: usize,
+    index: usize,
+) {
+    if level > 0 {
+        state.levels[level - 1].1.insert(index, sst_id);
+    } else if flush_to_l0 {
+        state.l0_sstables.insert(0, sst_id);
+    } else {
+        state.levels.insert(0, (sst_id, vec![sst_id]));
+    }
+}
@@ -1,7 +1,18 @@
+pub mod changes_only_iterator;
 pub mod concat_iterator;
+pub mod limited_merge_iterator;
 pub mod merge_iterator;
+pub mod ordered_guard;
+pub mod paginated_iterator;
+pub mod peekable_iterator;
+pub mod std_iterator;
 pub mod two_merge_iterator;
 
+use bytes::Bytes;
+use std_iterator::StdIterator;
+
+use crate::block::ENTRY_FLAG_MERGE_OPERAND;
+
 pub trait StorageIterator {
     type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
     where
@@ -10,6 +21,31 @@ pub trait StorageIterator {
     /// Get the current value.
     fn value(&self) -> &[u8];
 
+    /// Get the current value as [`Bytes`], which outlives the current position. The default
+    /// copies the value, while iterators over buffers already held as [`Bytes`] share them.
+    fn value_bytes(&self) -> Bytes {
+        Bytes::copy_from_slice(self.value())
+    }
+
+    /// The flags of the current entry, e.g. [`ENTRY_FLAG_MERGE_OPERAND`]. Iterators over user
+    /// keys, which resolve what the flags stand for, always return 0.
+    fn flags(&self) -> u8 {
+        0
+    }
+
+    /// Whether the current entry is a tombstone. The default takes an empty value for one, while
+    /// iterators over entries with flags tell them apart from empty values by
+    /// [`crate::block::ENTRY_FLAG_TOMBSTONE`].
+    fn is_tombstone(&self) -> bool {
+        self.value().is_empty()
+    }
+
+    /// Whether the current entry is a merge operand to be combined with the older versions of
+    /// its key, rather than a value, see [`crate::merge_operator::MergeOperator`].
+    fn is_merge_operand(&self) -> bool {
+        self.flags() & ENTRY_FLAG_MERGE_OPERAND != 0
+    }
+
     /// Get the current key.
     fn key(&self) -> Self::KeyType<'_>;
 
@@ -23,4 +59,12 @@ pub trait StorageIterator {
     fn num_active_iterators(&self) -> usize {
         1
     }
+
+    /// Convert this iterator into a standard iterator over owned key-value pairs.
+    fn into_std_iter(self) -> StdIterator<Self>
+    where
+        Self: Sized,
+    {
+        StdIterator::new(self)
+    }
 }
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+
+/// Yields an entry of an iterator only when its value differs from the value of the entry
+/// yielded before it, e.g. to read the boundaries where the value of a range of keys changes
+/// rather than every key holding the same value. The first entry is always yielded. Only the
+/// values are compared, so with an iterator yielding several versions of a key, a version holding
+/// the same value as the entry before it is skipped too.
+pub struct ChangesOnlyIterator<I: StorageIterator> {
+    iter: I,
+    /// The value of the current entry, which the next entries are compared against.
+    value: Vec<u8>,
+}
+
+impl<I: StorageIterator> ChangesOnlyIterator<I> {
+    pub fn new(iter: I) -> Self {
+        let value = if iter.is_valid() {
+            iter.value().to_vec()
+        } else {
+            Vec::new()
+        };
+        Self { iter, value }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for ChangesOnlyIterator<I> {
+    type KeyType<'a>
+        = I::KeyType<'a>
+    where
+        Self: 'a;
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.iter.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.iter.is_tombstone()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.iter.is_valid() {
+            return Ok(());
+        }
+        self.iter.next()?;
+        while self.iter.is_valid() && self.iter.value() == self.value.as_slice() {
+            self.iter.next()?;
+        }
+        if self.iter.is_valid() {
+            self.value.clear();
+            self.value.extend_from_slice(self.iter.value());
+        }
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
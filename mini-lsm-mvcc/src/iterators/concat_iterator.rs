@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 
 use crate::{
-    key::KeySlice,
-    table::{SsTable, SsTableIterator},
+    key::{KeySlice, TS_RANGE_BEGIN},
+    rate_limiter::RateLimiter,
+    table::{BlockReadOptions, CacheHint, SsTable, SsTableIterator},
 };
 
 use super::StorageIterator;
@@ -15,65 +17,272 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// How the tables read blocks, unless reading for compaction.
+    read_options: BlockReadOptions,
+    /// Whether the tables are iterated in descending key order, from the last one down to the
+    /// first one. `next_sst_idx` is then one past the index of the next table to open.
+    reverse: bool,
 }
 
 impl SstConcatIterator {
-    fn check_sst_valid(sstables: &[Arc<SsTable>]) {
-        for sst in sstables {
-            assert!(sst.first_key() <= sst.last_key());
+    /// Checks that `tables` form a sorted run: each table starts after the previous one ends,
+    /// so that none of them overlaps another. Fails naming the first offending pair otherwise.
+    pub fn validate_non_overlapping(tables: &[Arc<SsTable>]) -> Result<()> {
+        for sst in tables {
+            if sst.first_key() > sst.last_key() {
+                bail!(
+                    "SST {} starts at {:?}, after its last key {:?}",
+                    sst.sst_id(),
+                    sst.first_key(),
+                    sst.last_key()
+                );
+            }
         }
-        if !sstables.is_empty() {
-            for i in 0..(sstables.len() - 1) {
-                assert!(sstables[i].last_key() < sstables[i + 1].first_key());
+        for pair in tables.windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            // the key range of a table ending with a range tombstone excludes its last key,
+            // which the next table may start with
+            let end_excluded = prev.last_key().ts() == TS_RANGE_BEGIN;
+            if next.first_key() < prev.last_key()
+                || (next.first_key() == prev.last_key() && !end_excluded)
+            {
+                bail!(
+                    "SST {} starting at {:?} overlaps SST {} ending at {:?}",
+                    next.sst_id(),
+                    next.first_key(),
+                    prev.sst_id(),
+                    prev.last_key()
+                );
             }
         }
+        Ok(())
     }
 
-    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
-        Self::check_sst_valid(&sstables);
-        if sstables.is_empty() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: 0,
-                sstables,
-            });
+    /// Checks the tables in debug builds, see [`Self::validate_non_overlapping`].
+    fn check_sst_valid(sstables: &[Arc<SsTable>]) -> Result<()> {
+        if cfg!(debug_assertions) {
+            Self::validate_non_overlapping(sstables)?;
         }
+        Ok(())
+    }
+
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_rate_limiter(sstables, None)
+    }
+
+    /// Create an iterator for compaction. Block reads from the disk are charged to the rate
+    /// limiter, if any.
+    pub fn create_and_seek_to_first_with_rate_limiter(
+        sstables: Vec<Arc<SsTable>>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(sstables, rate_limiter, BlockReadOptions::default())
+    }
+
+    /// Create an iterator reading blocks through the block cache as told by `hint`.
+    pub fn create_and_seek_to_first_with_cache_hint(
+        sstables: Vec<Arc<SsTable>>,
+        hint: CacheHint,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_with_read_options(sstables, hint.into())
+    }
+
+    /// Create an iterator reading blocks as told by `options`.
+    pub fn create_and_seek_to_first_with_read_options(
+        sstables: Vec<Arc<SsTable>>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(sstables, None, options)
+    }
+
+    fn create_and_seek_to_first_inner(
+        sstables: Vec<Arc<SsTable>>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        read_options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::check_sst_valid(&sstables)?;
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
-                sstables[0].clone(),
-            )?),
-            next_sst_idx: 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
+            rate_limiter,
+            read_options,
+            reverse: false,
         };
+        if !iter.sstables.is_empty() {
+            iter.current = Some(iter.open_table(0)?);
+            iter.next_sst_idx = 1;
+        }
         iter.move_until_valid()?;
         Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
-        Self::check_sst_valid(&sstables);
+        Self::create_and_seek_to_key_with_rate_limiter(sstables, key, None)
+    }
+
+    /// Create an iterator for compaction and seek to the first key-value pair which >= `key`.
+    /// Block reads from the disk are charged to the rate limiter, if any.
+    pub fn create_and_seek_to_key_with_rate_limiter(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(sstables, key, rate_limiter, BlockReadOptions::default())
+    }
+
+    /// Create an iterator reading blocks through the block cache as told by `hint`, and seek to
+    /// the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key_with_cache_hint(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        hint: CacheHint,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_with_read_options(sstables, key, hint.into())
+    }
+
+    /// Create an iterator reading blocks as told by `options`, and seek to the first key-value
+    /// pair which >= `key`.
+    pub fn create_and_seek_to_key_with_read_options(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(sstables, key, None, options)
+    }
+
+    fn create_and_seek_to_key_inner(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        read_options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::check_sst_valid(&sstables)?;
         let idx: usize = sstables
             .partition_point(|table| table.first_key().as_key_slice() <= key)
             .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: sstables.len(),
+            sstables,
+            rate_limiter,
+            read_options,
+            reverse: false,
+        };
+        if idx < iter.sstables.len() {
+            let table = iter.sstables[idx].clone();
+            iter.current = Some(match &iter.rate_limiter {
+                Some(_) => SsTableIterator::create_and_seek_to_key_with_rate_limiter(
+                    table,
+                    key,
+                    iter.rate_limiter.clone(),
+                )?,
+                None => SsTableIterator::create_and_seek_to_key_with_read_options(
+                    table,
+                    key,
+                    read_options,
+                )?,
             });
+            iter.next_sst_idx = idx + 1;
         }
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    /// Opens the `idx`-th table at its first key-value pair, reading blocks for compaction if
+    /// there is a rate limiter, or as told by the read options.
+    fn open_table(&self, idx: usize) -> Result<SsTableIterator> {
+        let table = self.sstables[idx].clone();
+        match &self.rate_limiter {
+            Some(_) => SsTableIterator::create_and_seek_to_first_with_rate_limiter(
+                table,
+                self.rate_limiter.clone(),
+            ),
+            None => SsTableIterator::create_and_seek_to_first_with_read_options(
+                table,
+                self.read_options,
+            ),
+        }
+    }
+
+    /// Create an iterator moving in descending key order, and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::check_sst_valid(&sstables)?;
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
+            current: None,
+            next_sst_idx: sstables.len(),
             sstables,
+            rate_limiter: None,
+            read_options: BlockReadOptions::default(),
+            reverse: true,
         };
         iter.move_until_valid()?;
         Ok(iter)
     }
 
+    /// Create an iterator moving in descending key order, and seek to the last key-value pair
+    /// which <= `key`.
+    pub fn create_and_seek_to_key_rev(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
+        Self::check_sst_valid(&sstables)?;
+        let idx = sstables.partition_point(|table| table.first_key().as_key_slice() <= key);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: idx,
+            sstables,
+            rate_limiter: None,
+            read_options: BlockReadOptions::default(),
+            reverse: true,
+        };
+        if let Some(idx) = idx.checked_sub(1) {
+            iter.current = Some(SsTableIterator::create_and_seek_to_key_rev(
+                iter.sstables[idx].clone(),
+                key,
+            )?);
+            iter.next_sst_idx = idx;
+        }
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    /// Moves to the previous entry in key order, whichever the direction of the iterator, rolling
+    /// over to the last entry of the previous table at the start of a table. Becomes invalid when
+    /// moving back from the first entry, and does nothing once invalid.
+    pub fn prev(&mut self) -> Result<()> {
+        let Some(current) = self.current.as_mut() else {
+            return Ok(());
+        };
+        current.prev()?;
+        if current.is_valid() {
+            return Ok(());
+        }
+        let mut idx = if self.reverse {
+            self.next_sst_idx
+        } else {
+            self.next_sst_idx - 1
+        };
+        self.current = None;
+        while idx > 0 {
+            idx -= 1;
+            let table = self.sstables[idx].clone();
+            let iter = if self.reverse {
+                SsTableIterator::create_and_seek_to_last(table)?
+            } else {
+                SsTableIterator::create_and_seek_to_last_ascending(table)?
+            };
+            if iter.is_valid() {
+                self.current = Some(iter);
+                break;
+            }
+        }
+        self.next_sst_idx = if self.reverse { idx } else { idx + 1 };
+        Ok(())
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
+        if self.reverse {
+            return self.move_until_valid_rev();
+        }
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
                 break;
@@ -81,14 +290,26 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                self.current = Some(self.open_table(self.next_sst_idx)?);
                 self.next_sst_idx += 1;
             }
         }
         Ok(())
     }
+
+    fn move_until_valid_rev(&mut self) -> Result<()> {
+        while !matches!(&self.current, Some(iter) if iter.is_valid()) {
+            if self.next_sst_idx == 0 {
+                self.current = None;
+                break;
+            }
+            self.next_sst_idx -= 1;
+            self.current = Some(SsTableIterator::create_and_seek_to_last(
+                self.sstables[self.next_sst_idx].clone(),
+            )?);
+        }
+        Ok(())
+    }
 }
 
 impl StorageIterator for SstConcatIterator {
@@ -102,6 +323,18 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn value_bytes(&self) -> Bytes {
+        self.current.as_ref().unwrap().value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.current.as_ref().unwrap().flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.current.as_ref().unwrap().is_tombstone()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
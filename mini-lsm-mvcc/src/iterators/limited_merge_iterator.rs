@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    key::KeySlice,
+    table::{SsTable, SsTableIterator},
+};
+
+use super::StorageIterator;
+
+/// Merge SSTs whose key ranges may overlap, keeping at most `max_open_iterators` of their
+/// iterators open at a time. As the key range of each SST is known from its meta, an SST only
+/// needs to be opened once the merge reaches its first key, and is closed as soon as it is
+/// exhausted. The number of open iterators is therefore the number of SSTs overlapping the
+/// current key, and the iterator fails if that exceeds the limit. If the same key occurs in
+/// several SSTs, prefer the one with smaller index.
+pub struct LimitedMergeIterator {
+    /// The open iterators with the index of their SST.
+    open: Vec<(usize, SsTableIterator)>,
+    /// The position in `open` of the iterator holding the current key.
+    current: Option<usize>,
+    /// The SSTs not opened yet with their index, sorted by first key in reverse, so that the
+    /// next one to open is at the end.
+    pending: Vec<(usize, Arc<SsTable>)>,
+    max_open_iterators: usize,
+}
+
+impl LimitedMergeIterator {
+    pub fn create_and_seek_to_first(
+        sstables: Vec<Arc<SsTable>>,
+        max_open_iterators: usize,
+    ) -> Result<Self> {
+        if max_open_iterators == 0 {
+            bail!("at least one iterator must be allowed to open");
+        }
+        let mut pending = sstables.into_iter().enumerate().collect::<Vec<_>>();
+        pending
+            .sort_by(|(a_idx, a), (b_idx, b)| (b.first_key(), b_idx).cmp(&(a.first_key(), a_idx)));
+        let mut iter = Self {
+            open: Vec::new(),
+            current: None,
+            pending,
+            max_open_iterators,
+        };
+        iter.open_overlapping()?;
+        Ok(iter)
+    }
+
+    /// The position in `open` of the iterator with the smallest key.
+    fn min_open(&self) -> Option<usize> {
+        (0..self.open.len()).min_by(|&a, &b| {
+            let (a_idx, a) = &self.open[a];
+            let (b_idx, b) = &self.open[b];
+            (a.key(), a_idx).cmp(&(b.key(), b_idx))
+        })
+    }
+
+    /// Closes the exhausted iterators and opens every SST starting at or before the smallest key
+    /// of the open ones, then selects the current iterator.
+    fn open_overlapping(&mut self) -> Result<()> {
+        self.open.retain(|(_, iter)| iter.is_valid());
+        loop {
+            let min_open = self.min_open();
+            let Some((_, next_sst)) = self.pending.last() else {
+                break;
+            };
+            if let Some(min_open) = min_open {
+                if next_sst.first_key().as_key_slice() > self.open[min_open].1.key() {
+                    break;
+                }
+            }
+            if self.open.len() == self.max_open_iterators {
+                bail!(
+                    "more than {} SSTs overlap at key {:?}",
+                    self.max_open_iterators,
+                    next_sst.first_key()
+                );
+            }
+            let (idx, sst) = self.pending.pop().unwrap();
+            let iter = SsTableIterator::create_and_seek_to_first(sst)?;
+            if iter.is_valid() {
+                self.open.push((idx, iter));
+            }
+        }
+        self.current = self.min_open();
+        Ok(())
+    }
+}
+
+impl StorageIterator for LimitedMergeIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice<'_> {
+        self.open[self.current.unwrap()].1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.open[self.current.unwrap()].1.value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.open[self.current.unwrap()].1.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.open[self.current.unwrap()].1.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.open[self.current.unwrap()].1.is_tombstone()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let key = self.key().to_key_vec();
+        // Skip the same key in the other SSTs as well.
+        for (_, iter) in &mut self.open {
+            if iter.key() == key.as_key_slice() {
+                iter.next()?;
+            }
+        }
+        self.open_overlapping()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.open.len()
+    }
+}
@@ -3,12 +3,19 @@ use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use crate::key::KeySlice;
 
 use super::StorageIterator;
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator with its index. The heap top is the iterator at the smallest key, or at the
+/// largest key when `descending` is set, and the one with the smallest index among equal keys.
+struct HeapWrapper<I: StorageIterator> {
+    idx: usize,
+    iter: Box<I>,
+    descending: bool,
+}
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -21,12 +28,13 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     #[allow(clippy::non_canonical_partial_ord_impl)]
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(&other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
-        }
-        .map(|x| x.reverse())
+        let key_order = self.iter.key().cmp(&other.iter.key());
+        let key_order = if self.descending {
+            key_order
+        } else {
+            key_order.reverse()
+        };
+        Some(key_order.then_with(|| self.idx.cmp(&other.idx).reverse()))
     }
 }
 
@@ -45,6 +53,17 @@ pub struct MergeIterator<I: StorageIterator> {
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, false)
+    }
+
+    /// Merge multiple iterators moving in descending key order, e.g. SST iterators created with
+    /// [`crate::table::SsTableIterator::create_and_seek_to_last`]. The merged iterator moves in
+    /// descending order too, and still prefers the iterator with smaller index for the same key.
+    pub fn create_rev(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, true)
+    }
+
+    fn create_inner(iters: Vec<Box<I>>, descending: bool) -> Self {
         if iters.is_empty() {
             return Self {
                 iters: BinaryHeap::new(),
@@ -59,13 +78,21 @@ impl<I: StorageIterator> MergeIterator<I> {
             let mut iters = iters;
             return Self {
                 iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
+                current: Some(HeapWrapper {
+                    idx: 0,
+                    iter: iters.pop().unwrap(),
+                    descending,
+                }),
             };
         }
 
         for (idx, iter) in iters.into_iter().enumerate() {
             if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+                heap.push(HeapWrapper {
+                    idx,
+                    iter,
+                    descending,
+                });
             }
         }
 
@@ -83,17 +110,29 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
     type KeyType<'a> = KeySlice<'a>;
 
     fn key(&self) -> KeySlice {
-        self.current.as_ref().unwrap().1.key()
+        self.current.as_ref().unwrap().iter.key()
     }
 
     fn value(&self) -> &[u8] {
-        self.current.as_ref().unwrap().1.value()
+        self.current.as_ref().unwrap().iter.value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.current.as_ref().unwrap().iter.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.current.as_ref().unwrap().iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.current.as_ref().unwrap().iter.is_tombstone()
     }
 
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
-            .map(|x| x.1.is_valid())
+            .map(|x| x.iter.is_valid())
             .unwrap_or(false)
     }
 
@@ -102,18 +141,22 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
             debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
+                if current.descending {
+                    inner_iter.iter.key() <= current.iter.key()
+                } else {
+                    inner_iter.iter.key() >= current.iter.key()
+                },
                 "heap invariant violated"
             );
-            if inner_iter.1.key() == current.1.key() {
+            if inner_iter.iter.key() == current.iter.key() {
                 // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
+                if let e @ Err(_) = inner_iter.iter.next() {
                     PeekMut::pop(inner_iter);
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
-                if !inner_iter.1.is_valid() {
+                if !inner_iter.iter.is_valid() {
                     PeekMut::pop(inner_iter);
                 }
             } else {
@@ -121,10 +164,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
             }
         }
 
-        current.1.next()?;
+        current.iter.next()?;
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
-        if !current.1.is_valid() {
+        if !current.iter.is_valid() {
             if let Some(iter) = self.iters.pop() {
                 *current = iter;
             }
@@ -144,12 +187,12 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
     fn num_active_iterators(&self) -> usize {
         self.iters
             .iter()
-            .map(|x| x.1.num_active_iterators())
+            .map(|x| x.iter.num_active_iterators())
             .sum::<usize>()
             + self
                 .current
                 .as_ref()
-                .map(|x| x.1.num_active_iterators())
+                .map(|x| x.iter.num_active_iterators())
                 .unwrap_or(0)
     }
 }
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+
+/// Wraps an iterator to check at runtime that its keys are strictly increasing, panicking with
+/// both keys on the first violation. Meant for catching ordering bugs of new iterators in tests:
+/// the check only runs with debug assertions, otherwise the wrapper does nothing.
+pub struct OrderedGuard<I> {
+    iter: I,
+    prev_key: KeyVec,
+    allow_equal_keys: bool,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> OrderedGuard<I> {
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            prev_key: KeyVec::new(),
+            allow_equal_keys: false,
+        }
+    }
+
+    /// Accept a key equal to the previous one (same user key and timestamp), e.g. for iterators
+    /// over several sources that do not deduplicate them.
+    pub fn allow_equal_keys(&mut self) {
+        self.allow_equal_keys = true;
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+
+    fn check_order(&self) {
+        let (prev, key) = (self.prev_key.as_key_slice(), self.iter.key());
+        let ordered = if self.allow_equal_keys {
+            key >= prev
+        } else {
+            key > prev
+        };
+        if !ordered {
+            panic!(
+                "iterator out of order: key {:?}@{} after key {:?}@{}",
+                Bytes::copy_from_slice(key.key_ref()),
+                key.ts(),
+                Bytes::copy_from_slice(prev.key_ref()),
+                prev.ts()
+            );
+        }
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for OrderedGuard<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.iter.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.iter.is_tombstone()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let check = cfg!(debug_assertions) && self.iter.is_valid();
+        if check {
+            self.prev_key.set_from_slice(self.iter.key());
+        }
+        self.iter.next()?;
+        if check && self.iter.is_valid() {
+            self.check_order();
+        }
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
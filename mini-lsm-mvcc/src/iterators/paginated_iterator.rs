@@ -0,0 +1,67 @@
+use anyhow::Result;
+
+use super::StorageIterator;
+
+/// Skips the first `offset` entries of an iterator and yields at most `limit` of the following
+/// ones, e.g. to list the keys of a range one page at a time. The underlying iterator is not
+/// advanced past the last entry of the page, so that no block beyond it is read.
+pub struct PaginatedIterator<I: StorageIterator> {
+    iter: I,
+    /// The number of entries left to yield, including the current one.
+    remaining: usize,
+}
+
+impl<I: StorageIterator> PaginatedIterator<I> {
+    /// Advances `iter` past `offset` entries, or to its end if it has fewer.
+    pub fn new(mut iter: I, offset: usize, limit: usize) -> Result<Self> {
+        for _ in 0..offset {
+            if !iter.is_valid() {
+                break;
+            }
+            iter.next()?;
+        }
+        Ok(Self {
+            iter,
+            remaining: limit,
+        })
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for PaginatedIterator<I> {
+    type KeyType<'a> = I::KeyType<'a> where Self: 'a;
+
+    fn is_valid(&self) -> bool {
+        self.remaining > 0 && self.iter.is_valid()
+    }
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn flags(&self) -> u8 {
+        self.iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.iter.is_tombstone()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.iter.next()?;
+        }
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+
+use super::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+
+/// Wraps an iterator, e.g. a [`super::merge_iterator::MergeIterator`], as a cursor whose current
+/// entry can be peeked any number of times without advancing. The entry is copied into buffers
+/// reused across moves, so that peeking does not go back to the underlying iterator. Once `next`
+/// fails, the cursor is invalid and every later call to `next` fails.
+pub struct Peekable<I> {
+    iter: I,
+    key: KeyVec,
+    value: Vec<u8>,
+    flags: u8,
+    is_tombstone: bool,
+    is_valid: bool,
+    has_errored: bool,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> Peekable<I> {
+    pub fn new(iter: I) -> Self {
+        let mut peekable = Self {
+            iter,
+            key: KeyVec::new(),
+            value: Vec::new(),
+            flags: 0,
+            is_tombstone: false,
+            is_valid: false,
+            has_errored: false,
+        };
+        peekable.fill();
+        peekable
+    }
+
+    /// Copies the current entry of the underlying iterator, if any.
+    fn fill(&mut self) {
+        self.is_valid = self.iter.is_valid();
+        if self.is_valid {
+            self.key.set_from_slice(self.iter.key());
+            self.value.clear();
+            self.value.extend_from_slice(self.iter.value());
+            self.flags = self.iter.flags();
+            self.is_tombstone = self.iter.is_tombstone();
+        }
+    }
+
+    /// The current entry, or `None` at the end or after an error.
+    pub fn peek(&self) -> Option<(KeySlice<'_>, &[u8])> {
+        self.is_valid()
+            .then(|| (self.key.as_key_slice(), self.value.as_slice()))
+    }
+
+    pub fn peek_key(&self) -> Option<KeySlice<'_>> {
+        self.peek().map(|(key, _)| key)
+    }
+
+    pub fn peek_value(&self) -> Option<&[u8]> {
+        self.peek().map(|(_, value)| value)
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for Peekable<I>
+{
+    type KeyType<'a>
+        = KeySlice<'a>
+    where
+        Self: 'a;
+
+    fn is_valid(&self) -> bool {
+        self.is_valid && !self.has_errored
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.peek_key()
+            .expect("invalid access to the underlying iterator")
+    }
+
+    fn value(&self) -> &[u8] {
+        self.peek_value()
+            .expect("invalid access to the underlying iterator")
+    }
+
+    fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.is_tombstone
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if !self.is_valid {
+            return Ok(());
+        }
+        if let Err(e) = self.iter.next() {
+            self.has_errored = true;
+            return Err(e);
+        }
+        self.fill();
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::key::KeySlice;
+
+use super::StorageIterator;
+
+/// A key type that can be copied out of a [`StorageIterator`] into an owned buffer.
+pub trait ToKeyBytes {
+    fn to_key_bytes(&self) -> Bytes;
+}
+
+impl ToKeyBytes for &[u8] {
+    fn to_key_bytes(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+/// Only the user key is returned, the timestamp is dropped.
+impl ToKeyBytes for KeySlice<'_> {
+    fn to_key_bytes(&self) -> Bytes {
+        Bytes::copy_from_slice(self.key_ref())
+    }
+}
+
+/// Wraps a [`StorageIterator`] into a standard [`Iterator`] yielding owned key-value pairs.
+/// An error from the underlying iterator is yielded once and ends the iteration.
+pub struct StdIterator<I: StorageIterator> {
+    iter: I,
+    error: Option<anyhow::Error>,
+    finished: bool,
+}
+
+impl<I: StorageIterator> StdIterator<I> {
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            error: None,
+            finished: false,
+        }
+    }
+}
+
+impl<I> Iterator for StdIterator<I>
+where
+    I: StorageIterator + 'static,
+    for<'a> I::KeyType<'a>: ToKeyBytes,
+{
+    type Item = Result<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            self.finished = true;
+            return Some(Err(e));
+        }
+        if self.finished || !self.iter.is_valid() {
+            return None;
+        }
+        let item = (
+            self.iter.key().to_key_bytes(),
+            Bytes::copy_from_slice(self.iter.value()),
+        );
+        if let Err(e) = self.iter.next() {
+            self.error = Some(e);
+        }
+        Some(Ok(item))
+    }
+}
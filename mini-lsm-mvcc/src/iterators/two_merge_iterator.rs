@@ -1,4 +1,5 @@
 use anyhow::Result;
+use bytes::Bytes;
 
 use super::StorageIterator;
 
@@ -8,6 +9,8 @@ pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    /// Whether both iterators move in descending key order.
+    descending: bool,
 }
 
 impl<
@@ -15,14 +18,18 @@ impl<
         B: 'static + for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
     > TwoMergeIterator<A, B>
 {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, descending: bool) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        if descending {
+            a.key() > b.key()
+        } else {
+            a.key() < b.key()
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
@@ -33,13 +40,23 @@ impl<
     }
 
     pub fn create(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, false)
+    }
+
+    /// Merges two iterators moving in descending key order, still preferring A for the same key.
+    pub fn create_rev(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, true)
+    }
+
+    fn create_inner(a: A, b: B, descending: bool) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            descending,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = Self::choose_a(&iter.a, &iter.b, descending);
         Ok(iter)
     }
 }
@@ -69,6 +86,30 @@ impl<
         }
     }
 
+    fn value_bytes(&self) -> Bytes {
+        if self.choose_a {
+            self.a.value_bytes()
+        } else {
+            self.b.value_bytes()
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        if self.choose_a {
+            self.a.flags()
+        } else {
+            self.b.flags()
+        }
+    }
+
+    fn is_tombstone(&self) -> bool {
+        if self.choose_a {
+            self.a.is_tombstone()
+        } else {
+            self.b.is_tombstone()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -84,7 +125,7 @@ impl<
             self.b.next()?;
         }
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.descending);
         Ok(())
     }
 
@@ -198,3 +198,55 @@ impl<T: AsRef<[u8]> + Ord> Ord for Key<T> {
         (self.0.as_ref(), Reverse(self.1)).cmp(&(other.0.as_ref(), Reverse(other.1)))
     }
 }
+
+/// Keys are serialized as `{"key": "<hex>", "ts": <ts>}` so that arbitrary binary keys stay
+/// JSON-safe.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use bytes::Bytes;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::KeyBytes;
+
+    #[derive(Serialize, Deserialize)]
+    struct HexKey {
+        key: String,
+        ts: u64,
+    }
+
+    fn encode_hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn decode_hex(data: &str) -> Option<Vec<u8>> {
+        data.as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair)
+                    .ok()
+                    .filter(|pair| pair.len() == 2)?;
+                u8::from_str_radix(pair, 16).ok()
+            })
+            .collect()
+    }
+
+    impl Serialize for KeyBytes {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            HexKey {
+                key: encode_hex(self.key_ref()),
+                ts: self.ts(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for KeyBytes {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let HexKey { key, ts } = HexKey::deserialize(deserializer)?;
+            let key = decode_hex(&key)
+                .ok_or_else(|| D::Error::custom(format!("invalid hex key: {}", key)))?;
+            Ok(KeyBytes::from_bytes_with_ts(Bytes::from(key), ts))
+        }
+    }
+}
@@ -0,0 +1,727 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::block::implied_entry_flags;
+use crate::compact::CompactionController;
+use crate::key::{KeyBytes, KeySlice};
+use crate::lsm_storage::{
+    block_size_weigher, new_block_cache, replay_manifest, BatchEntry, BlockCache, LsmStorageInner,
+    LsmStorageOptions, MiniLsm, ShardedStorageState, WriteBatchRecord,
+};
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::mem_table::MemTable;
+use crate::metrics::Metrics;
+use crate::mvcc::txn::TxnIterator;
+use crate::mvcc::LsmMvccInner;
+use crate::range_tombstone::RangeTombstone;
+use crate::wal::Wal;
+
+/// The directory holding the keyspaces of a storage: the segments of the log they share, see
+/// [`KeyspaceLog`], and a subdirectory with the SSTs of each keyspace.
+pub(crate) const KEYSPACES_DIR: &str = "keyspaces";
+
+/// A keyspace of a storage, with its own memtables, SSTs and options, see
+/// [`MiniLsm::open_with_keyspaces`].
+#[derive(Clone)]
+pub struct KeyspaceHandle {
+    name: String,
+    storage: Arc<MiniLsm>,
+}
+
+impl KeyspaceHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The storage of the keyspace, e.g. to flush or compact it.
+    pub fn storage(&self) -> &Arc<MiniLsm> {
+        &self.storage
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.storage.get(key)
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.storage.put(key, value)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.storage.delete(key)
+    }
+
+    /// Writes a batch to this keyspace alone, see [`MiniLsm::write_keyspace_batch`] for a batch
+    /// across keyspaces.
+    pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
+        self.storage.write_batch(batch)
+    }
+
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.storage.scan(lower, upper)
+    }
+
+    fn keyspace(&self) -> &Keyspace {
+        self.storage.inner.keyspace.as_ref().unwrap()
+    }
+}
+
+/// What a storage opened as a keyspace of another knows of it, see
+/// [`LsmStorageInner::keyspace`].
+pub(crate) struct Keyspace {
+    pub(crate) name: String,
+    pub(crate) log: Arc<KeyspaceLog>,
+}
+
+/// Prefixes `key` with the keyspace `name`, to log the entries of several keyspaces in one WAL.
+pub(crate) fn tag_key(name: &str, key: &[u8]) -> Result<Vec<u8>> {
+    let tagged_len = 1 + name.len() + key.len();
+    if tagged_len >= u16::MAX as usize {
+        bail!("key too long to be written to a keyspace");
+    }
+    let mut tagged = Vec::with_capacity(tagged_len);
+    tagged.push(name.len() as u8);
+    tagged.extend_from_slice(name.as_bytes());
+    tagged.extend_from_slice(key);
+    Ok(tagged)
+}
+
+/// Splits a key tagged by [`tag_key`] into the keyspace name and the key.
+fn untag_key(tagged: &[u8]) -> Result<(&str, &[u8])> {
+    let name_len = *tagged.first().context("empty tagged key")? as usize;
+    if tagged.len() < 1 + name_len {
+        bail!("truncated tagged key");
+    }
+    let name = std::str::from_utf8(&tagged[1..1 + name_len])?;
+    Ok((name, &tagged[1 + name_len..]))
+}
+
+pub(crate) fn check_keyspace_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > u8::MAX as usize
+        || !name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-')
+    {
+        bail!(
+            "invalid keyspace name {:?}, use up to 255 letters, digits, '_' or '-'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// The log of the writes to all the keyspaces of a storage, in place of WALs of their own, with
+/// the keys tagged with their keyspace, see [`tag_key`], so that a batch across keyspaces is a
+/// single record, which recovery replays entirely or not at all.
+///
+/// The log is split into segments, `keyspaces/<n>.wal`. A new segment is started whenever a
+/// keyspace freezes its memtable, so that the writes to each memtable of a keyspace are in the
+/// segments from the one it started at to the one the next memtable of the keyspace started at.
+/// The segment each memtable starts at is recorded in the manifest, see
+/// [`ManifestRecord::NewKeyspaceMemtable`], and a segment is removed once every memtable of any
+/// keyspace started at or before it is flushed.
+pub(crate) struct KeyspaceLog {
+    dir: PathBuf,
+    inner: Mutex<KeyspaceLogInner>,
+}
+
+struct KeyspaceLogInner {
+    /// The segment being written.
+    segment: usize,
+    wal: Wal,
+    /// The earliest segment that may not be removed yet.
+    first_segment: usize,
+    /// For each keyspace, the segments its memtables not flushed yet started at, from the
+    /// earliest memtable.
+    memtable_segments: HashMap<String, VecDeque<usize>>,
+}
+
+/// The writes to a memtable of a keyspace replayed from the [`KeyspaceLog`].
+#[derive(Default)]
+pub(crate) struct ReplayedWrites {
+    entries: Vec<(KeyBytes, Bytes, u8)>,
+    range_tombstones: Vec<RangeTombstone>,
+}
+
+impl ReplayedWrites {
+    fn apply(&self, memtable: &MemTable) -> Result<()> {
+        memtable.put_batch_with_flags(
+            self.entries
+                .iter()
+                .map(|(key, value, flags)| (key.as_key_slice(), value.as_ref(), *flags)),
+        )?;
+        for tombstone in &self.range_tombstones {
+            memtable.put_range_tombstone(tombstone.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// For each segment of the [`KeyspaceLog`] a memtable of a keyspace started at, the id of the
+/// memtable and whether it is flushed.
+type MemtableSegments = BTreeMap<usize, (usize, bool)>;
+
+/// The writes replayed to the memtables of a keyspace not flushed yet, by memtable id, with the
+/// segment each memtable started at.
+type ReplayedMemtables = BTreeMap<usize, (usize, ReplayedWrites)>;
+
+/// The [`MemtableSegments`] of a keyspace, from its manifest records.
+fn memtable_segments(records: &[ManifestRecord]) -> MemtableSegments {
+    let mut segments = BTreeMap::new();
+    let mut segment_of = HashMap::new();
+    for record in records {
+        match record {
+            ManifestRecord::NewKeyspaceMemtable(id, segment) => {
+                segments.insert(*segment, (*id, false));
+                segment_of.insert(*id, *segment);
+            }
+            ManifestRecord::Flush(id) => {
+                if let Some(segment) = segment_of.get(id) {
+                    segments.insert(*segment, (*id, true));
+                }
+            }
+            _ => {}
+        }
+    }
+    segments
+}
+
+/// The writes to replay a write to the keyspace `name` logged in `segment` into, `None` if the
+/// memtable it was written to is flushed.
+fn writes_to<'a>(
+    replayed: &'a mut HashMap<String, ReplayedMemtables>,
+    keyspaces: &HashMap<&str, MemtableSegments>,
+    name: &str,
+    segment: usize,
+) -> Result<Option<&'a mut ReplayedWrites>> {
+    let Some(memtables) = keyspaces.get(name) else {
+        bail!(
+            "the keyspace log holds writes to the unknown keyspace {}",
+            name
+        );
+    };
+    match memtables.range(..=segment).next_back() {
+        Some((&start, &(id, false))) => Ok(Some(
+            &mut replayed
+                .entry(name.to_string())
+                .or_default()
+                .entry(id)
+                .or_insert_with(|| (start, ReplayedWrites::default()))
+                .1,
+        )),
+        // flushed, or written before the keyspace had a memtable
+        _ => Ok(None),
+    }
+}
+
+impl KeyspaceLog {
+    fn path_of_segment(dir: &Path, segment: usize) -> PathBuf {
+        dir.join(format!("{:05}.wal", segment))
+    }
+
+    /// The segments of the log in `dir`, in the order they were written.
+    fn list_segments(dir: &Path) -> Result<Vec<usize>> {
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if let Some(segment) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".wal"))
+                .and_then(|segment| segment.parse::<usize>().ok())
+            {
+                segments.push(segment);
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Replays the writes in the segments of the log in `dir` to the memtables not flushed yet of
+    /// `keyspaces`, each given with its [`MemtableSegments`]. Returns the replayed writes of each
+    /// keyspace, and the segments of the log.
+    fn replay(
+        dir: &Path,
+        keyspaces: &HashMap<&str, MemtableSegments>,
+    ) -> Result<(HashMap<String, ReplayedMemtables>, Vec<usize>)> {
+        let segments = Self::list_segments(dir)?;
+        // the writes before the earliest memtable not flushed yet are all in SSTs
+        let first_unflushed = keyspaces
+            .values()
+            .flat_map(|memtables| {
+                memtables
+                    .iter()
+                    .filter(|(_, (_, flushed))| !flushed)
+                    .map(|(segment, _)| *segment)
+            })
+            .min();
+        let mut replayed = HashMap::new();
+        let Some(first_unflushed) = first_unflushed else {
+            return Ok((replayed, segments));
+        };
+        for &segment in segments
+            .iter()
+            .filter(|segment| **segment >= first_unflushed)
+        {
+            let entries = SkipMap::new();
+            let entry_flags = SkipMap::new();
+            let mut range_tombstones = Vec::new();
+            // the log is written unsharded, so it holds no batch part
+            Wal::replay(
+                Self::path_of_segment(dir, segment),
+                &entries,
+                &entry_flags,
+                &mut range_tombstones,
+                &mut Vec::new(),
+            )?;
+            for entry in entries.iter() {
+                let (name, key) = untag_key(entry.key().key_ref())?;
+                let flags = entry_flags
+                    .get(entry.key())
+                    .map(|flags| *flags.value())
+                    .unwrap_or_else(|| implied_entry_flags(entry.value()));
+                if let Some(writes) = writes_to(&mut replayed, keyspaces, name, segment)? {
+                    writes.entries.push((
+                        KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key), entry.key().ts()),
+                        entry.value().clone(),
+                        flags,
+                    ));
+                }
+            }
+            for tombstone in range_tombstones {
+                let (name, start) = untag_key(&tombstone.start)?;
+                let (_, end) = untag_key(&tombstone.end)?;
+                if let Some(writes) = writes_to(&mut replayed, keyspaces, name, segment)? {
+                    writes.range_tombstones.push(RangeTombstone::new(
+                        Bytes::copy_from_slice(start),
+                        Bytes::copy_from_slice(end),
+                        tombstone.ts,
+                    ));
+                }
+            }
+        }
+        Ok((replayed, segments))
+    }
+
+    /// Starts the log in `dir` at the new segment `segment`, after the existing `segments`, which
+    /// are removed once no memtable needs them, see [`Self::remove_obsolete_segments`].
+    fn open(dir: &Path, segments: &[usize], segment: usize) -> Result<Self> {
+        let wal = Wal::create(Self::path_of_segment(dir, segment))?;
+        File::open(dir)?.sync_all()?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            inner: Mutex::new(KeyspaceLogInner {
+                segment,
+                wal,
+                first_segment: segments.first().copied().unwrap_or(segment),
+                memtable_segments: HashMap::new(),
+            }),
+        })
+    }
+
+    /// The segment being written.
+    fn segment(&self) -> usize {
+        self.inner.lock().segment
+    }
+
+    /// Tracks a memtable of the keyspace `name` started at `segment`, after its other memtables.
+    fn add_memtable(&self, name: &str, segment: usize) {
+        self.inner
+            .lock()
+            .memtable_segments
+            .entry(name.to_string())
+            .or_default()
+            .push_back(segment);
+    }
+
+    /// Logs the entries written by a batch to each keyspace, as a single record.
+    pub(crate) fn put_batch(&self, batch: &[(&str, &[BatchEntry])]) -> Result<()> {
+        let mut tagged = Vec::new();
+        for (name, data) in batch {
+            for (key, value, flags) in data.iter() {
+                tagged.push((tag_key(name, key.key_ref())?, key.ts(), *value, *flags));
+            }
+        }
+        self.inner.lock().wal.put_batch_with_flags(
+            tagged
+                .iter()
+                .map(|(key, ts, value, flags)| (KeySlice::from_slice(key, *ts), *value, *flags)),
+        )
+    }
+
+    /// Logs a range tombstone written to the keyspace `name`.
+    pub(crate) fn put_range_tombstone(&self, name: &str, tombstone: &RangeTombstone) -> Result<()> {
+        let tagged = RangeTombstone::new(
+            tag_key(name, &tombstone.start)?.into(),
+            tag_key(name, &tombstone.end)?.into(),
+            tombstone.ts,
+        );
+        self.inner.lock().wal.put_range_tombstone(&tagged)
+    }
+
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.inner.lock().wal.sync()
+    }
+
+    /// Starts a new segment for the new memtable of the keyspace `name`, once the current one is
+    /// synced. Returns the new segment.
+    pub(crate) fn rotate(&self, name: &str) -> Result<usize> {
+        let mut inner = self.inner.lock();
+        inner.wal.sync()?;
+        let segment = inner.segment + 1;
+        inner.wal = Wal::create(Self::path_of_segment(&self.dir, segment))?;
+        inner.segment = segment;
+        inner
+            .memtable_segments
+            .entry(name.to_string())
+            .or_default()
+            .push_back(segment);
+        drop(inner);
+        File::open(&self.dir)?.sync_all()?;
+        Ok(segment)
+    }
+
+    /// Stops tracking the earliest memtable of the keyspace `name`, once it is flushed, and
+    /// removes the segments no memtable needs anymore.
+    pub(crate) fn memtable_flushed(&self, name: &str) -> Result<()> {
+        self.inner
+            .lock()
+            .memtable_segments
+            .get_mut(name)
+            .and_then(|segments| segments.pop_front());
+        self.remove_obsolete_segments()
+    }
+
+    /// Removes the segments before the one the earliest memtable not flushed yet started at.
+    fn remove_obsolete_segments(&self) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let first_needed = inner
+            .memtable_segments
+            .values()
+            .filter_map(|segments| segments.front().copied())
+            .min()
+            .unwrap_or(inner.segment)
+            .min(inner.segment);
+        if first_needed <= inner.first_segment {
+            return Ok(());
+        }
+        for segment in inner.first_segment..first_needed {
+            match std::fs::remove_file(Self::path_of_segment(&self.dir, segment)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        inner.first_segment = first_needed;
+        drop(inner);
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+impl LsmStorageInner {
+    /// Opens the keyspace `keyspace` of a storage, with its SSTs in `path`, like
+    /// [`Self::open_with_block_cache`] opens a storage. Its state is rebuilt from `records`, its
+    /// records in the manifest of the storage, or `None` if it is created, and its memtables not
+    /// flushed yet from `memtables`, their writes replayed from the log shared by the keyspaces,
+    /// by id with the segment they started at.
+    #[allow(clippy::too_many_arguments)]
+    fn open_keyspace(
+        path: &Path,
+        options: LsmStorageOptions,
+        block_cache: Arc<BlockCache>,
+        cache_namespace: u32,
+        keyspace: Keyspace,
+        manifest: Manifest,
+        records: Option<Vec<ManifestRecord>>,
+        memtables: ReplayedMemtables,
+    ) -> Result<Self> {
+        options.write_stall.validate(options.num_memtable_limit)?;
+        if options.num_shards > 1 {
+            bail!("the writes to a keyspace cannot be sharded");
+        }
+        std::fs::create_dir_all(path).context("failed to create keyspace dir")?;
+        let mut state = ShardedStorageState::create(&options);
+        let metrics = Arc::new(Metrics::default());
+        let mut next_sst_id = 0;
+        let mut last_commit_ts = 0;
+        if let Some(records) = records {
+            let compaction_controller = CompactionController::new(&options.compaction_options);
+            let replayed = replay_manifest(&mut state, &compaction_controller, records);
+            last_commit_ts = Self::open_ssts(
+                path,
+                &mut state,
+                &replayed,
+                &options,
+                &block_cache,
+                cache_namespace,
+                &metrics,
+            )?;
+            next_sst_id = replayed.max_id + 1;
+            for (id, (segment, writes)) in memtables {
+                let memtable = MemTable::create(id);
+                writes.apply(&memtable)?;
+                last_commit_ts = last_commit_ts.max(memtable.max_ts());
+                keyspace.log.add_memtable(&keyspace.name, segment);
+                state.imm_memtables.insert(0, Arc::new(memtable));
+            }
+            println!(
+                "{} memtables of keyspace {} recovered",
+                state.imm_memtables.len(),
+                keyspace.name
+            );
+        }
+        let segment = keyspace.log.segment();
+        manifest.add_record_when_init(ManifestRecord::NewKeyspaceMemtable(next_sst_id, segment))?;
+        keyspace.log.add_memtable(&keyspace.name, segment);
+        state.memtable = Arc::new(MemTable::create(next_sst_id));
+        next_sst_id += 1;
+
+        let mut storage = Self::from_state(
+            path,
+            options,
+            state,
+            block_cache,
+            next_sst_id,
+            Some(manifest),
+            last_commit_ts,
+        );
+        storage.cache_namespace = cache_namespace;
+        storage.metrics = metrics;
+        storage.keyspace = Some(keyspace);
+        storage.sync_dir()?;
+        Ok(storage)
+    }
+}
+
+impl MiniLsm {
+    /// Opens the storage in `path` like [`Self::open`], together with the keyspaces given with
+    /// their options. Each keyspace has its own memtables and SSTs, in a subdirectory of
+    /// `keyspaces`, and is flushed and compacted by its own threads with its options, e.g. its
+    /// compaction strategy, block size and compaction filters. The keyspaces are recorded in the
+    /// manifest of the storage, along with the state of each, and log their writes to one log
+    /// they share, see [`KeyspaceLog`]. They share the block cache of the storage, sized by
+    /// `options`, and its timestamps, so that [`Self::write_keyspace_batch`] commits a batch
+    /// across keyspaces at once. The writes to a keyspace cannot be sharded.
+    ///
+    /// Keyspaces are created the first time they are given, and every keyspace created before
+    /// must be given again whenever the storage is opened.
+    pub fn open_with_keyspaces(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        keyspaces: Vec<(String, LsmStorageOptions)>,
+    ) -> Result<Arc<Self>> {
+        let path = path.as_ref();
+        for (idx, (name, _)) in keyspaces.iter().enumerate() {
+            check_keyspace_name(name)?;
+            if keyspaces[..idx].iter().any(|(other, _)| other == name) {
+                bail!("keyspace {} is given twice", name);
+            }
+        }
+
+        let block_cache = Arc::new(new_block_cache(
+            options.block_cache_size,
+            block_size_weigher,
+        ));
+        let mut inner =
+            LsmStorageInner::open_with_block_cache(path, options, block_cache.clone(), 0)?;
+        let mut created = Vec::new();
+        let mut records = HashMap::<String, Vec<ManifestRecord>>::new();
+        for record in Manifest::read_records(path.join("MANIFEST"))? {
+            match record {
+                ManifestRecord::NewKeyspace(name) => created.push(name),
+                ManifestRecord::Keyspace(name, record) => {
+                    records.entry(name).or_default().push(*record)
+                }
+                _ => {}
+            }
+        }
+        for name in &created {
+            if !keyspaces.iter().any(|(other, _)| other == name) {
+                bail!("keyspace {} exists, but is not given", name);
+            }
+        }
+
+        let keyspaces_dir = path.join(KEYSPACES_DIR);
+        std::fs::create_dir_all(&keyspaces_dir).context("failed to create keyspaces dir")?;
+        let memtables = keyspaces
+            .iter()
+            .map(|(name, _)| {
+                let memtables = records
+                    .get(name)
+                    .map(|records| memtable_segments(records))
+                    .unwrap_or_default();
+                (name.as_str(), memtables)
+            })
+            .collect::<HashMap<_, _>>();
+        let (mut replayed, segments) = KeyspaceLog::replay(&keyspaces_dir, &memtables)?;
+        // after every segment a memtable may have started at
+        let segment = segments
+            .iter()
+            .chain(memtables.values().flat_map(|memtables| memtables.keys()))
+            .max()
+            .map_or(0, |segment| segment + 1);
+        let log = Arc::new(KeyspaceLog::open(&keyspaces_dir, &segments, segment)?);
+
+        let mut keyspace_inners = Vec::with_capacity(keyspaces.len());
+        for (idx, (name, options)) in keyspaces.into_iter().enumerate() {
+            let keyspace_records = if created.contains(&name) {
+                Some(records.remove(&name).unwrap_or_default())
+            } else {
+                inner
+                    .manifest()
+                    .add_record_when_init(ManifestRecord::NewKeyspace(name.clone()))?;
+                None
+            };
+            let keyspace = LsmStorageInner::open_keyspace(
+                &keyspaces_dir.join(&name),
+                options,
+                block_cache.clone(),
+                idx as u32 + 1,
+                Keyspace {
+                    name: name.clone(),
+                    log: log.clone(),
+                },
+                inner.manifest().for_keyspace(&name),
+                keyspace_records,
+                replayed.remove(&name).unwrap_or_default(),
+            )
+            .with_context(|| format!("failed to open keyspace {}", name))?;
+            keyspace_inners.push((name, keyspace));
+        }
+        log.remove_obsolete_segments()?;
+        File::open(path)?.sync_all()?;
+
+        // one timestamp for all the keyspaces, so that a snapshot of one is consistent with the
+        // others
+        let last_commit_ts = keyspace_inners
+            .iter()
+            .map(|(_, keyspace)| keyspace.mvcc().latest_commit_ts())
+            .fold(inner.mvcc().latest_commit_ts(), u64::max);
+        let mvcc = Arc::new(LsmMvccInner::new(last_commit_ts));
+        inner.mvcc = Some(mvcc.clone());
+        let mut handles = Vec::with_capacity(keyspace_inners.len());
+        for (name, mut keyspace) in keyspace_inners {
+            keyspace.mvcc = Some(mvcc.clone());
+            handles.push(KeyspaceHandle {
+                name,
+                storage: Self::start(Arc::new(keyspace), Vec::new())?,
+            });
+        }
+        Self::start(Arc::new(inner), handles)
+    }
+
+    /// The keyspace `name`, if it was given to [`Self::open_with_keyspaces`].
+    pub fn keyspace(&self, name: &str) -> Option<KeyspaceHandle> {
+        self.keyspaces
+            .iter()
+            .find(|keyspace| keyspace.name == name)
+            .cloned()
+    }
+
+    /// Writes the records, each to its keyspace, with a single commit timestamp, so that readers
+    /// see all of them or none of them. The batch is logged as a single record of the log shared
+    /// by the keyspaces, so that recovery replays all of it or none of it, and costs no more than
+    /// a batch to one keyspace.
+    pub fn write_keyspace_batch<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&KeyspaceHandle, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        let mut groups: Vec<(&KeyspaceHandle, Vec<&WriteBatchRecord<T>>)> = Vec::new();
+        for (keyspace, record) in batch {
+            if !self
+                .keyspaces
+                .iter()
+                .any(|other| Arc::ptr_eq(&other.storage, &keyspace.storage))
+            {
+                bail!(
+                    "keyspace {} is not a keyspace of this storage",
+                    keyspace.name
+                );
+            }
+            match groups
+                .iter_mut()
+                .find(|(other, _)| Arc::ptr_eq(&other.storage, &keyspace.storage))
+            {
+                Some((_, records)) => records.push(record),
+                None => groups.push((keyspace, vec![record])),
+            }
+        }
+
+        let mvcc = self.inner.mvcc();
+        let serializable = groups
+            .iter()
+            .any(|(keyspace, _)| keyspace.storage.inner.options.serializable);
+        let _commit_lock = serializable.then(|| mvcc.commit_lock.lock());
+        let write_lock = mvcc.lock_writes();
+        for (keyspace, _) in &groups {
+            keyspace.storage.inner.check_writable()?;
+            keyspace.storage.inner.check_open(&write_lock)?;
+        }
+        let ts = mvcc.latest_commit_ts() + 1;
+        let expiring_values = groups
+            .iter()
+            .map(|(keyspace, records)| keyspace.storage.inner.expiring_values::<T, _>(records))
+            .collect::<Vec<_>>();
+        let entries = groups
+            .iter()
+            .zip(&expiring_values)
+            .map(|((keyspace, records), expiring_values)| {
+                keyspace
+                    .storage
+                    .inner
+                    .batch_entries(records, expiring_values, ts)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // no memtable written to is frozen before the batch is in all of them, so that each
+        // memtable holds the writes of the segments of the log it is replayed from
+        let gates = groups
+            .iter()
+            .map(|(keyspace, _)| keyspace.storage.inner.memtable_gate.read())
+            .collect::<Vec<_>>();
+        let logged = groups
+            .iter()
+            .zip(&entries)
+            .filter(|((keyspace, _), _)| keyspace.storage.inner.options.enable_wal)
+            .map(|((keyspace, _), (data, _))| (keyspace.name.as_str(), data.as_slice()))
+            .collect::<Vec<_>>();
+        if let Some((keyspace, _)) = groups.first() {
+            if !logged.is_empty() {
+                keyspace.keyspace().log.put_batch(&logged)?;
+            }
+        }
+        let mut sizes = Vec::with_capacity(groups.len());
+        for ((keyspace, _), (data, bytes_written)) in groups.iter().zip(&entries) {
+            let inner = &keyspace.storage.inner;
+            sizes.push(inner.write_entries_to_memtables(ts, data)?);
+            inner.stats.record_user_write(*bytes_written);
+            inner.metrics.record_write(data.len(), *bytes_written);
+        }
+        drop(gates);
+        if serializable {
+            self.inner.record_committed_keys(
+                batch.iter().map(|(_, record)| match record {
+                    WriteBatchRecord::Put(key, _)
+                    | WriteBatchRecord::Del(key)
+                    | WriteBatchRecord::Merge(key, _)
+                    | WriteBatchRecord::PutWithTtl(key, _, _) => key.as_ref(),
+                }),
+                ts,
+            );
+        }
+        mvcc.update_commit_ts(ts);
+        for ((keyspace, _), sizes) in groups.iter().zip(sizes) {
+            for (shard, size) in sizes {
+                keyspace.storage.inner.try_freeze(shard, size)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,32 @@
+pub mod backup;
 pub mod block;
+pub mod clock;
 pub mod compact;
 pub mod debug;
+pub mod describe;
+mod destroy;
+pub mod event_listener;
+pub mod ingest;
 pub mod iterators;
 pub mod key;
+pub mod keyspace;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod metrics;
 pub mod mvcc;
+pub mod property;
+pub mod range_tombstone;
+pub mod rate_limiter;
+pub mod read_only;
+mod shard;
 pub mod table;
+mod trace;
+pub mod ttl;
 pub mod wal;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
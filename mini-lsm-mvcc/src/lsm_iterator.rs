@@ -1,14 +1,19 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
 
+use crate::compact::{TombstoneReports, TombstoneRun};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::{full_merge, MergeOperator};
+use crate::range_tombstone::FragmentedRangeTombstones;
 use crate::table::SsTableIterator;
+use crate::ttl;
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
 type LsmIteratorInner = TwoMergeIterator<
@@ -21,7 +26,20 @@ pub struct LsmIterator {
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
+    /// The current time, in seconds since the Unix epoch. Versions with an expiry time at or
+    /// before it read as tombstones.
+    now_secs: u64,
+    /// The range tombstones visible at `read_ts`, which delete the older versions they cover.
+    range_tombstones: FragmentedRangeTombstones,
     prev_key: Vec<u8>,
+    /// Combines the merge operands of a key with its value.
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The value of `prev_key` combined from its merge operands, in which case `inner` is already
+    /// past the versions combined.
+    merged_value: Option<Bytes>,
+    /// Where runs of skipped tombstones are reported, if delete-triggered compaction is enabled.
+    tombstone_reports: Option<Arc<TombstoneReports>>,
+    tombstone_run: TombstoneRun,
 }
 
 impl LsmIterator {
@@ -29,30 +47,47 @@ impl LsmIterator {
         iter: LsmIteratorInner,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        now_secs: u64,
+        range_tombstones: FragmentedRangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        tombstone_reports: Option<Arc<TombstoneReports>>,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             inner: iter,
             end_bound,
             read_ts,
+            now_secs,
+            range_tombstones,
             prev_key: Vec::new(),
+            merge_operator,
+            merged_value: None,
+            tombstone_reports,
+            tombstone_run: TombstoneRun::default(),
         };
+        // the first key may already be past the end bound, e.g. when an SST seek lands after it
+        iter.check_end_bound();
         iter.move_to_key()?;
         Ok(iter)
     }
 
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
+        self.check_end_bound();
+        Ok(())
+    }
+
+    /// Invalidates the iterator once the inner iterator goes past the end bound.
+    fn check_end_bound(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
+            return;
         }
         match self.end_bound.as_ref() {
             Bound::Unbounded => {}
             Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
             Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
         }
-        Ok(())
     }
 
     fn move_to_key(&mut self) -> Result<()> {
@@ -77,12 +112,74 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
-                break;
+            if !self
+                .range_tombstones
+                .covers(&self.prev_key, self.inner.key().ts())
+            {
+                if self.inner.is_merge_operand() {
+                    // past the end bound, the iterator is done without combining them
+                    if !self.is_valid || self.merge_operands()? {
+                        break;
+                    }
+                } else if !self.inner.is_tombstone() && !self.is_expired() {
+                    break;
+                }
             }
+            if self.is_valid && self.tombstone_reports.is_some() {
+                self.tombstone_run.skip(&self.prev_key);
+            }
+        }
+        // a key is yielded or the scan is done
+        if let Some(reports) = &self.tombstone_reports {
+            self.tombstone_run.end(reports);
         }
         Ok(())
     }
+
+    /// Whether the version `inner` is at has expired.
+    fn is_expired(&self) -> bool {
+        ttl::is_expired(self.inner.flags(), self.inner.value(), self.now_secs)
+    }
+
+    /// Combines the merge operand `inner` is at with the older versions of `prev_key`, down to
+    /// its newest value, tombstone or version covered by a range tombstone. Returns whether the
+    /// result is a value, which is then yielded from `merged_value`.
+    fn merge_operands(&mut self) -> Result<bool> {
+        let mut operands = Vec::new();
+        let mut existing = None;
+        while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+            if self
+                .range_tombstones
+                .covers(&self.prev_key, self.inner.key().ts())
+            {
+                break;
+            }
+            if !self.inner.is_merge_operand() {
+                if !self.inner.is_tombstone() && !self.is_expired() {
+                    existing = Some(ttl::user_value_bytes(
+                        self.inner.flags(),
+                        self.inner.value_bytes(),
+                    ));
+                }
+                break;
+            }
+            operands.push(self.inner.value_bytes());
+            self.next_inner()?;
+        }
+        let value = full_merge(
+            self.merge_operator.as_ref(),
+            &self.prev_key,
+            existing.as_deref(),
+            &operands,
+        )?;
+        if value.is_empty() {
+            return Ok(false);
+        }
+        // the key was within the end bound, whatever `inner` is at now
+        self.is_valid = true;
+        self.merged_value = Some(value);
+        Ok(true)
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -93,15 +190,38 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
+        if self.merged_value.is_some() {
+            return &self.prev_key;
+        }
         self.inner.key().key_ref()
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.merged_value {
+            Some(value) => value,
+            None => ttl::user_value(self.inner.flags(), self.inner.value()),
+        }
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        match &self.merged_value {
+            Some(value) => value.clone(),
+            None => ttl::user_value_bytes(self.inner.flags(), self.inner.value_bytes()),
+        }
+    }
+
+    fn is_tombstone(&self) -> bool {
+        // only the keys with a value are yielded, which may be empty
+        false
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
+        if self.merged_value.take().is_some() {
+            // `inner` is already past the versions combined
+            self.check_end_bound();
+        } else {
+            self.next_inner()?;
+        }
         self.move_to_key()?;
         Ok(())
     }
@@ -111,6 +231,150 @@ impl StorageIterator for LsmIterator {
     }
 }
 
+/// Iterates over the user keys in descending order, for an inner iterator created in descending
+/// order. The versions of a key then come oldest first, so all of them are read to find the
+/// newest one visible at `read_ts`, combined with the merge operands written after it, whose key
+/// and value are copied before moving on.
+pub struct LsmRevIterator {
+    inner: LsmIteratorInner,
+    lower_bound: Bound<Bytes>,
+    read_ts: u64,
+    /// The current time, in seconds since the Unix epoch. Versions with an expiry time at or
+    /// before it read as tombstones.
+    now_secs: u64,
+    /// The range tombstones visible at `read_ts`, which delete the older versions they cover.
+    range_tombstones: FragmentedRangeTombstones,
+    /// Combines the merge operands of a key with its value.
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    /// Whether `value` holds a value of `key`, which may be empty, rather than nothing.
+    has_value: bool,
+    /// The merge operands written after `value`, oldest first.
+    operands: Vec<Bytes>,
+    is_valid: bool,
+}
+
+impl LsmRevIterator {
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        lower_bound: Bound<Bytes>,
+        read_ts: u64,
+        now_secs: u64,
+        range_tombstones: FragmentedRangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            inner: iter,
+            lower_bound,
+            read_ts,
+            now_secs,
+            range_tombstones,
+            merge_operator,
+            key: Vec::new(),
+            value: Vec::new(),
+            has_value: false,
+            operands: Vec::new(),
+            is_valid: false,
+        };
+        iter.move_to_key()?;
+        Ok(iter)
+    }
+
+    /// Whether the inner iterator is at an entry above the lower bound.
+    fn inner_in_range(&self) -> bool {
+        if !self.inner.is_valid() {
+            return false;
+        }
+        let key = self.inner.key().key_ref();
+        match self.lower_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(lower) => key >= lower.as_ref(),
+            Bound::Excluded(lower) => key > lower.as_ref(),
+        }
+    }
+
+    fn move_to_key(&mut self) -> Result<()> {
+        while self.inner_in_range() {
+            self.key.clear();
+            self.key.extend(self.inner.key().key_ref());
+            self.value.clear();
+            self.has_value = false;
+            self.operands.clear();
+            while self.inner_in_range() && self.inner.key().key_ref() == self.key {
+                let ts = self.inner.key().ts();
+                if ts <= self.read_ts {
+                    if self.range_tombstones.covers(&self.key, ts) {
+                        self.value.clear();
+                        self.has_value = false;
+                        self.operands.clear();
+                    } else if self.inner.is_merge_operand() {
+                        self.operands.push(self.inner.value_bytes());
+                    } else {
+                        let (flags, value) = (self.inner.flags(), self.inner.value());
+                        self.value.clear();
+                        self.has_value = !self.inner.is_tombstone()
+                            && !ttl::is_expired(flags, value, self.now_secs);
+                        if self.has_value {
+                            self.value.extend(ttl::user_value(flags, value));
+                        }
+                        self.operands.clear();
+                    }
+                }
+                self.inner.next()?;
+            }
+            if !self.operands.is_empty() {
+                self.operands.reverse();
+                let existing = self.has_value.then_some(&self.value[..]);
+                let value = full_merge(
+                    self.merge_operator.as_ref(),
+                    &self.key,
+                    existing,
+                    &self.operands,
+                )?;
+                self.value.clear();
+                self.value.extend(&value);
+                // an empty result deletes the key
+                self.has_value = !value.is_empty();
+            }
+            if self.has_value {
+                self.is_valid = true;
+                return Ok(());
+            }
+        }
+        self.is_valid = false;
+        Ok(())
+    }
+}
+
+impl StorageIterator for LsmRevIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn is_tombstone(&self) -> bool {
+        false
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.move_to_key()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
+
 /// A wrapper around existing iterator, will prevent users from calling `next` when the iterator is
 /// invalid. If an iterator is already invalid, `next` does not do anything. If `next` returns an error,
 /// `is_valid` should return false, and `next` should always return an error.
@@ -149,6 +413,20 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn value_bytes(&self) -> Bytes {
+        if self.has_errored || !self.iter.is_valid() {
+            panic!("invalid access to the underlying iterator");
+        }
+        self.iter.value_bytes()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        if self.has_errored || !self.iter.is_valid() {
+            panic!("invalid access to the underlying iterator");
+        }
+        self.iter.is_tombstone()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid and not errored
         if self.has_errored {
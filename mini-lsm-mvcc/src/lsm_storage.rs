@@ -1,39 +1,89 @@
-use std::collections::{BTreeSet, HashMap};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use arc_swap::{ArcSwap, Guard};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use crate::block::Block;
+use crate::backup::check_not_restoring;
+use crate::block::{
+    Block, BLOCK_FORMAT_V6, ENTRY_FLAG_MERGE_OPERAND, ENTRY_FLAG_TOMBSTONE, ENTRY_FLAG_TTL,
+};
+use crate::clock::{Clock, SystemClock};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    BackgroundJobKind, BackgroundJobStats, CompactionBackoff, CompactionController,
+    CompactionEntryFilter, CompactionOptions, CompactionPlan, CompactionStats,
+    LeveledCompactionOptions, SimpleLeveledCompactionOptions, StatsCollector, SubcompactionPool,
+    TombstoneReports,
 };
+use crate::destroy::{remove_lock_file, write_lock_file};
+use crate::event_listener::{EventListener, FlushJobInfo};
+use crate::ingest::{apply_ingestion, IngestOptions};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::{self, KeySlice};
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::keyspace::{Keyspace, KeyspaceHandle};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmRevIterator};
 use crate::manifest::{Manifest, ManifestRecord};
-use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
-use crate::mvcc::txn::{Transaction, TxnIterator};
-use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::mem_table::{map_bound, map_user_key_range, MemTable};
+use crate::merge_operator::MergeOperator;
+use crate::metrics::Metrics;
+use crate::mvcc::txn::{Transaction, TxnIterator, TxnRevIterator};
+use crate::mvcc::{CommittedTxnData, LsmMvccInner};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::shard::{apply_batch_parts, shard_of, validate_num_shards};
+use crate::table::{
+    BlockReadOptions, CacheHint, CachedLookup, FileObject, HashedKey, SsTable, SsTableBuilder,
+    SsTableIterator,
+};
+use crate::trace::{self, trace_span};
+use crate::ttl;
+use crate::write_stall::{WriteController, WriteStallOptions};
+
+/// The key of a block in the block cache: the namespace of its SST, see
+/// [`SsTable::with_cache_namespace`], the id of the SST and the index of the block.
+pub type BlockCacheKey = (u32, usize, usize);
+
+pub type BlockCache = moka::sync::Cache<BlockCacheKey, Arc<Block>>;
+
+/// An entry of a write batch to write to a memtable: the key, the value and the flags of the entry.
+pub(crate) type BatchEntry<'a> = (KeySlice<'a>, &'a [u8], u8);
+
+/// Create a block cache holding at most `capacity` in total weight of blocks, where the weight of
+/// each block is given by `weigher`.
+pub fn new_block_cache(
+    capacity: u64,
+    weigher: impl Fn(&BlockCacheKey, &Arc<Block>) -> u32 + Send + Sync + 'static,
+) -> BlockCache {
+    BlockCache::builder()
+        .max_capacity(capacity)
+        .weigher(weigher)
+        .build()
+}
 
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+/// Weigh a cached block by its decoded size in bytes, so that the capacity of the block cache is
+/// a memory budget.
+pub fn block_size_weigher(_: &BlockCacheKey, block: &Arc<Block>) -> u32 {
+    block.decoded_size().try_into().unwrap_or(u32::MAX)
+}
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
 pub struct LsmStorageState {
-    /// The current memtable.
+    /// The current memtable, that of shard 0 when the writes are sharded.
     pub memtable: Arc<MemTable>,
-    /// Immutable memtables, from latest to earliest.
+    /// Immutable memtables of all the shards, from latest to earliest.
     pub imm_memtables: Vec<Arc<MemTable>>,
     /// L0 SSTs, from latest to earliest.
     pub l0_sstables: Vec<usize>,
@@ -41,16 +91,113 @@ pub struct LsmStorageState {
     /// compaction.
     pub levels: Vec<(usize, Vec<usize>)>,
     /// SST objects.
-    pub sstables: HashMap<usize, Arc<SsTable>>,
+    pub sstables: SstMap,
+}
+
+/// The SST objects of a [`LsmStorageState`] by id, which also keeps track of the SSTs holding
+/// range tombstones so that reads only look into these for the range tombstones.
+#[derive(Clone, Default)]
+pub struct SstMap {
+    ssts: HashMap<usize, Arc<SsTable>>,
+    /// The ids of the SSTs with range tombstones.
+    with_range_tombstones: BTreeSet<usize>,
+}
+
+impl SstMap {
+    pub fn insert(&mut self, sst_id: usize, sst: Arc<SsTable>) -> Option<Arc<SsTable>> {
+        if sst.range_tombstones().is_empty() {
+            self.with_range_tombstones.remove(&sst_id);
+        } else {
+            self.with_range_tombstones.insert(sst_id);
+        }
+        self.ssts.insert(sst_id, sst)
+    }
+
+    pub fn remove(&mut self, sst_id: &usize) -> Option<Arc<SsTable>> {
+        self.with_range_tombstones.remove(sst_id);
+        self.ssts.remove(sst_id)
+    }
+
+    /// The SSTs holding range tombstones.
+    pub fn with_range_tombstones(&self) -> impl Iterator<Item = &Arc<SsTable>> + '_ {
+        self.with_range_tombstones
+            .iter()
+            .map(|sst_id| &self.ssts[sst_id])
+    }
+}
+
+impl std::ops::Deref for SstMap {
+    type Target = HashMap<usize, Arc<SsTable>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ssts
+    }
+}
+
+impl FromIterator<(usize, Arc<SsTable>)> for SstMap {
+    fn from_iter<T: IntoIterator<Item = (usize, Arc<SsTable>)>>(iter: T) -> Self {
+        let mut map = Self::default();
+        for (sst_id, sst) in iter {
+            map.insert(sst_id, sst);
+        }
+        map
+    }
+}
+
+/// The state of the storage engine along with the current memtables of the shards after shard 0,
+/// see [`LsmStorageOptions::num_shards`]. Kept apart from [`LsmStorageState`], which the
+/// compaction controllers and the compaction simulator build and read without any shard.
+#[derive(Clone)]
+pub struct ShardedStorageState {
+    pub state: LsmStorageState,
+    /// Empty when the writes are not sharded.
+    pub shard_memtables: Vec<Arc<MemTable>>,
+}
+
+impl std::ops::Deref for ShardedStorageState {
+    type Target = LsmStorageState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl std::ops::DerefMut for ShardedStorageState {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.state
+    }
 }
 
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
+    /// A merge operand, see [`LsmStorageInner::merge`].
+    Merge(T, T),
+    /// A value expiring after the duration, see [`LsmStorageInner::put_with_ttl`].
+    PutWithTtl(T, T, Duration),
+}
+
+/// The outcome of [`LsmStorageInner::compare_and_swap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CasResult {
+    /// The key held the expected value, which has been replaced.
+    Swapped,
+    /// The key did not hold the expected value and was left as it is. `current` is its value,
+    /// or `None` if it is absent.
+    Mismatch { current: Option<Bytes> },
+}
+
+/// The outcome of [`LsmStorageInner::get_cached_only`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedGet {
+    /// The value of the key, or `None` if it is absent.
+    Value(Option<Bytes>),
+    /// Finding the value would need a block that is not in the block cache, so it is unknown.
+    WouldBlock,
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
+    pub(crate) fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
@@ -70,17 +217,239 @@ impl LsmStorageState {
     }
 }
 
+impl ShardedStorageState {
+    pub(crate) fn create(options: &LsmStorageOptions) -> Self {
+        Self {
+            state: LsmStorageState::create(options),
+            shard_memtables: Vec::new(),
+        }
+    }
+
+    /// The current memtable of `shard`.
+    pub fn current_memtable(&self, shard: usize) -> &Arc<MemTable> {
+        match shard {
+            0 => &self.memtable,
+            shard => &self.shard_memtables[shard - 1],
+        }
+    }
+
+    pub(crate) fn current_memtable_mut(&mut self, shard: usize) -> &mut Arc<MemTable> {
+        match shard {
+            0 => &mut self.memtable,
+            shard => &mut self.shard_memtables[shard - 1],
+        }
+    }
+
+    /// The current memtables of all the shards, from shard 0.
+    pub fn current_memtables(&self) -> impl Iterator<Item = &Arc<MemTable>> + '_ {
+        std::iter::once(&self.memtable).chain(self.shard_memtables.iter())
+    }
+
+    /// The current memtables followed by the immutable ones.
+    pub fn memtables(&self) -> impl Iterator<Item = &Arc<MemTable>> + '_ {
+        self.current_memtables().chain(self.imm_memtables.iter())
+    }
+
+    /// The memtables that may hold keys of `shard`, those of the other shards left out.
+    pub fn memtables_of_shard(&self, shard: usize) -> impl Iterator<Item = &Arc<MemTable>> + '_ {
+        self.memtables()
+            .filter(move |memtable| memtable.shard().is_none_or(|tag| tag == shard))
+    }
+
+    /// The range tombstones visible at `read_ts` that may cover a user key in the range.
+    pub(crate) fn range_tombstones(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> FragmentedRangeTombstones {
+        let visible = |tombstone: &RangeTombstone| {
+            tombstone.ts <= read_ts && tombstone.overlaps(lower, upper)
+        };
+        let mut tombstones = self
+            .memtables()
+            .flat_map(|memtable| memtable.range_tombstones())
+            .filter(visible)
+            .collect::<Vec<_>>();
+        for sst in self.sstables.with_range_tombstones() {
+            tombstones.extend(
+                sst.range_tombstones()
+                    .iter()
+                    .filter(|tombstone| visible(tombstone))
+                    .cloned(),
+            );
+        }
+        FragmentedRangeTombstones::new(tombstones)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LsmStorageOptions {
     // Block size in bytes
     pub block_size: usize,
-    // SST size in bytes, also the approximate memtable capacity limit
+    // SST size in bytes, also the approximate memtable capacity limit. All compaction strategies
+    // cut their output at this size, but never between two versions of the same user key, so an
+    // output SST may exceed it.
     pub target_sst_size: usize,
     // Maximum number of memtables in memory, flush to L0 when exceeding this limit
     pub num_memtable_limit: usize,
+    // Number of shards the writes are split into by the hash of their keys, a power of two. Each
+    // shard has its own memtable and WAL, so that writers of different shards insert in
+    // parallel, and its flushes produce L0 SSTs tagged with the shard, which point reads of the
+    // other shards skip; the levels below L0 are shared. A batch across shards stays atomic. The
+    // immutable memtables of all shards count towards `num_memtable_limit`. 1 disables sharding.
+    pub num_shards: usize,
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Maximum number of sub-compactions a single compaction task can be split into. The
+    // sub-compactions of all tasks share a pool of as many threads.
+    pub max_subcompactions: usize,
+    // Maximum number of input blocks a compaction (or each of its sub-compactions) holds in
+    // memory, reading them one at a time in key order, see `StreamingCompactionIterator`. 0
+    // reads each input SST ahead instead.
+    pub compaction_block_budget: usize,
+    // Number of background compaction workers, each running one task at a time. Tasks running
+    // concurrently never share an input SST.
+    pub num_compaction_threads: usize,
+    // Background I/O (flush and compaction) limit in bytes per second, 0 means unlimited
+    pub rate_limit_bytes_per_sec: u64,
+    // Application-defined filter applied to the latest version of each key during compaction
+    pub compaction_entry_filter: Option<Arc<dyn CompactionEntryFilter>>,
+    // Combines the operands written by `merge` with the values of their keys. Must be set to
+    // write operands, and to read the keys that have any.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // Block cache capacity in bytes of decoded blocks
+    pub block_cache_size: u64,
+    // SSTs older than this are rewritten in place when there is no other compaction work, which
+    // re-runs the compaction filter on them. 0 disables periodic compaction.
+    pub periodic_compaction_seconds: u64,
+    // Source of wall-clock time, e.g. for the age of SSTs
+    pub clock: Arc<dyn Clock>,
+    // Delay before retrying a failed compaction, doubled on every consecutive failure
+    pub compaction_retry_backoff: Duration,
+    // Number of consecutive compaction failures after which a background error is reported
+    pub max_compaction_failures: usize,
+    // A scan skipping at least this many tombstones in a row schedules a compaction of their
+    // range. 0 disables delete-triggered compaction.
+    pub delete_triggered_compaction_threshold: usize,
+    // Notified of flushes, compactions and write stalls, in this order
+    pub event_listeners: Vec<Arc<dyn EventListener>>,
+    // How scans read SST blocks through the block cache. `Probe` serves hot blocks from the
+    // cache without filling it with the blocks of large scans; point lookups always fill it.
+    pub scan_cache_hint: CacheHint,
+    // Whether `close` flushes the memtables to SSTs, so that reopening replays no WAL. Without
+    // the WAL, they are always flushed.
+    pub close_flush: bool,
+    // Whether `close` abandons the compactions in progress, removing their outputs, rather than
+    // waiting for them to finish
+    pub close_abandons_compactions: bool,
+    // When writes are slowed down or stopped for the flushes and compactions to catch up
+    pub write_stall: WriteStallOptions,
+}
+
+/// Options of a single read, see [`LsmStorageInner::get_with_options`] and
+/// [`LsmStorageInner::scan_with_options`]. The default reads like [`LsmStorageInner::get`] and
+/// [`LsmStorageInner::scan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Whether SST blocks read from the disk are checked against their checksums, see
+    /// [`BlockReadOptions::verify_checksums`]. Blocks in the block cache are never checked again.
+    pub verify_checksums: bool,
+    /// Whether SST blocks read from the disk are inserted into the block cache. When off, blocks
+    /// already in the cache are still read from it, see [`CacheHint::Probe`].
+    pub fill_cache: bool,
+    /// The timestamp to read at, or `None` for the latest committed one. Versions below the
+    /// watermark may already have been garbage collected.
+    pub read_ts: Option<u64>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: true,
+            fill_cache: true,
+            read_ts: None,
+        }
+    }
+}
+
+/// Options of a single write, see [`LsmStorageInner::write_batch_with_options`]. The default
+/// writes like [`LsmStorageInner::write_batch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Whether a write that a write stall would delay or stop fails with a
+    /// [`crate::write_stall::WouldBlock`] instead, see [`LsmStorageOptions::write_stall`].
+    pub no_stall: bool,
+}
+
+impl ReadOptions {
+    /// How blocks are read, where `cache_hint` is the hint used when filling the cache.
+    fn block_read_options(&self, cache_hint: CacheHint) -> BlockReadOptions {
+        BlockReadOptions {
+            cache_hint: match cache_hint {
+                CacheHint::Fill if !self.fill_cache => CacheHint::Probe,
+                hint => hint,
+            },
+            verify_checksums: self.verify_checksums,
+        }
+    }
+}
+
+/// The space taken on the disk by the engine, see [`LsmStorageInner::disk_usage`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiskUsage {
+    /// Size of all the live SSTs.
+    pub sst_bytes: u64,
+    /// Size of the WALs of the memtables not flushed yet.
+    pub wal_bytes: u64,
+    /// Size of the SSTs of L0, followed by the size of each level.
+    pub by_level: Vec<u64>,
+    /// `sst_bytes` over the estimated size of the live data in them, i.e. the SSTs without their
+    /// tombstones and versions shadowed within the same SST, estimated from the table
+    /// properties. Versions shadowed by another SST are not known, so this underestimates the
+    /// amplification when keys are overwritten across SSTs. 1.0 when there is no SST.
+    pub space_amplification: f64,
+}
+
+/// The options that can be changed while the engine is running, see
+/// [`MiniLsm::set_options`]. They are initialized from [`LsmStorageOptions`].
+#[derive(Debug, Clone)]
+pub struct DynamicOptions {
+    pub target_sst_size: usize,
+    pub rate_limit_bytes_per_sec: u64,
+    /// Must use the same strategy and number of levels as the engine was opened with.
+    pub compaction_options: CompactionOptions,
+}
+
+/// Changes to [`DynamicOptions`]. Options left as `None` keep their current value.
+#[derive(Debug, Clone, Default)]
+pub struct OptionsDelta {
+    pub target_sst_size: Option<usize>,
+    pub rate_limit_bytes_per_sec: Option<u64>,
+    pub compaction_options: Option<CompactionOptions>,
+}
+
+impl DynamicOptions {
+    /// Returns these options with `delta` applied, or an error if any change is invalid.
+    fn apply(&self, delta: OptionsDelta) -> Result<Self> {
+        let mut options = self.clone();
+        if let Some(target_sst_size) = delta.target_sst_size {
+            if target_sst_size == 0 {
+                bail!("target_sst_size must be positive");
+            }
+            options.target_sst_size = target_sst_size;
+        }
+        if let Some(rate_limit_bytes_per_sec) = delta.rate_limit_bytes_per_sec {
+            options.rate_limit_bytes_per_sec = rate_limit_bytes_per_sec;
+        }
+        if let Some(compaction_options) = delta.compaction_options {
+            self.compaction_options
+                .validate_change(&compaction_options)?;
+            options.compaction_options = compaction_options;
+        }
+        Ok(options)
+    }
 }
 
 impl LsmStorageOptions {
@@ -91,7 +460,25 @@ impl LsmStorageOptions {
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
             num_memtable_limit: 50,
+            num_shards: 1,
             serializable: false,
+            max_subcompactions: 1,
+            compaction_block_budget: 0,
+            num_compaction_threads: 1,
+            rate_limit_bytes_per_sec: 0,
+            compaction_entry_filter: None,
+            merge_operator: None,
+            block_cache_size: 4 << 30,
+            periodic_compaction_seconds: 0,
+            clock: Arc::new(SystemClock),
+            compaction_retry_backoff: Duration::from_millis(100),
+            max_compaction_failures: 3,
+            delete_triggered_compaction_threshold: 1000,
+            event_listeners: Vec::new(),
+            scan_cache_hint: CacheHint::Fill,
+            close_flush: false,
+            close_abandons_compactions: false,
+            write_stall: WriteStallOptions::default(),
         }
     }
 
@@ -102,7 +489,25 @@ impl LsmStorageOptions {
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
             num_memtable_limit: 2,
+            num_shards: 1,
             serializable: false,
+            max_subcompactions: 1,
+            compaction_block_budget: 0,
+            num_compaction_threads: 1,
+            rate_limit_bytes_per_sec: 0,
+            compaction_entry_filter: None,
+            merge_operator: None,
+            block_cache_size: 4 << 30,
+            periodic_compaction_seconds: 0,
+            clock: Arc::new(SystemClock),
+            compaction_retry_backoff: Duration::from_millis(100),
+            max_compaction_failures: 3,
+            delete_triggered_compaction_threshold: 1000,
+            event_listeners: Vec::new(),
+            scan_cache_hint: CacheHint::Fill,
+            close_flush: false,
+            close_abandons_compactions: false,
+            write_stall: WriteStallOptions::default(),
         }
     }
 
@@ -113,7 +518,25 @@ impl LsmStorageOptions {
             compaction_options,
             enable_wal: false,
             num_memtable_limit: 2,
+            num_shards: 1,
             serializable: false,
+            max_subcompactions: 1,
+            compaction_block_budget: 0,
+            num_compaction_threads: 1,
+            rate_limit_bytes_per_sec: 0,
+            compaction_entry_filter: None,
+            merge_operator: None,
+            block_cache_size: 4 << 30,
+            periodic_compaction_seconds: 0,
+            clock: Arc::new(SystemClock),
+            compaction_retry_backoff: Duration::from_millis(100),
+            max_compaction_failures: 3,
+            delete_triggered_compaction_threshold: 1000,
+            event_listeners: Vec::new(),
+            scan_cache_hint: CacheHint::Fill,
+            close_flush: false,
+            close_abandons_compactions: false,
+            write_stall: WriteStallOptions::default(),
         }
     }
 }
@@ -145,8 +568,192 @@ fn range_overlap(
     true
 }
 
-fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bool {
-    table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
+/// Whether no user key is within the bounds, e.g. when the lower bound is above the upper one.
+pub(crate) fn is_empty_range(lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (Bound::Included(lower), Bound::Excluded(upper))
+        | (Bound::Excluded(lower), Bound::Included(upper))
+        | (Bound::Excluded(lower), Bound::Excluded(upper)) => lower >= upper,
+        _ => false,
+    }
+}
+
+/// What [`replay_manifest`] recovers besides the SST ids of the state.
+#[derive(Debug, Default)]
+pub(crate) struct ReplayedManifest {
+    /// The ids of the memtables not flushed yet, whose WALs hold their entries.
+    pub(crate) memtables: BTreeSet<usize>,
+    /// The largest id of a memtable or an SST.
+    pub(crate) max_id: usize,
+    /// The shard of each SST flushed from the memtable of a shard, with the number of shards.
+    sst_shards: HashMap<usize, (usize, usize)>,
+    /// The batches across shards dropped by a recovery, see [`apply_batch_parts`].
+    pub(crate) dropped_batches: HashSet<u64>,
+}
+
+impl ReplayedManifest {
+    /// The shard of the SST `sst_id`, if it was flushed from the memtable of a shard while the
+    /// writes were split into `num_shards` shards, the number they are split into now.
+    pub(crate) fn sst_shard(&self, sst_id: usize, num_shards: usize) -> Option<usize> {
+        match self.sst_shards.get(&sst_id) {
+            Some(&(shard, flushed_num_shards)) if flushed_num_shards == num_shards => Some(shard),
+            _ => None,
+        }
+    }
+}
+
+/// Applies the manifest `records` to the SST ids of `state`, without opening the SSTs.
+pub(crate) fn replay_manifest(
+    state: &mut LsmStorageState,
+    compaction_controller: &CompactionController,
+    records: Vec<ManifestRecord>,
+) -> ReplayedManifest {
+    let mut replayed = ReplayedManifest::default();
+    let memtables = &mut replayed.memtables;
+    let mut max_id = 0;
+    for record in records {
+        match record {
+            ManifestRecord::Flush(sst_id) | ManifestRecord::FlushShard(sst_id, _, _) => {
+                let res = memtables.remove(&sst_id);
+                assert!(res, "memtable not exist?");
+                if compaction_controller.flush_to_l0() {
+                    state.l0_sstables.insert(0, sst_id);
+                } else {
+                    state.levels.insert(0, (sst_id, vec![sst_id]));
+                }
+                if let ManifestRecord::FlushShard(_, shard, num_shards) = record {
+                    replayed.sst_shards.insert(sst_id, (shard, num_shards));
+                }
+                max_id = max_id.max(sst_id);
+            }
+            ManifestRecord::NewMemtable(x) | ManifestRecord::NewKeyspaceMemtable(x, _) => {
+                max_id = max_id.max(x);
+                memtables.insert(x);
+            }
+            ManifestRecord::DropBatch(seq) => {
+                replayed.dropped_batches.insert(seq);
+            }
+            // applied to the keyspaces, see `MiniLsm::open_with_keyspaces`
+            ManifestRecord::NewKeyspace(_) | ManifestRecord::Keyspace(..) => {}
+            ManifestRecord::Compaction(task, output) => {
+                let (new_state, _) =
+                    compaction_controller.apply_compaction_result(state, &task, &output);
+                // TODO: apply remove again
+                *state = new_state;
+                max_id = max_id.max(output.iter().max().copied().unwrap_or_default());
+            }
+            ManifestRecord::Ingest(sst_id, level, index) => {
+                apply_ingestion(
+                    state,
+                    compaction_controller.flush_to_l0(),
+                    sst_id,
+                    level,
+                    index,
+                );
+                max_id = max_id.max(sst_id);
+            }
+            ManifestRecord::IngestBatch(ssts) => {
+                for (sst_id, level, index) in ssts {
+                    apply_ingestion(
+                        state,
+                        compaction_controller.flush_to_l0(),
+                        sst_id,
+                        level,
+                        index,
+                    );
+                    max_id = max_id.max(sst_id);
+                }
+            }
+        }
+    }
+    replayed.max_id = max_id;
+    replayed
+}
+
+/// Holds the state of a storage, read without locking, see [`LsmStorageInner::state`].
+pub(crate) struct StateCell {
+    state: ArcSwap<ShardedStorageState>,
+    /// Shared with [`LsmStorageInner::state_lock`], held whenever a new state is stored.
+    state_lock: Arc<Mutex<()>>,
+}
+
+impl StateCell {
+    pub(crate) fn new(state: ShardedStorageState, state_lock: Arc<Mutex<()>>) -> Self {
+        Self {
+            state: ArcSwap::from_pointee(state),
+            state_lock,
+        }
+    }
+
+    /// A snapshot of the state, for short reads.
+    pub(crate) fn load(&self) -> Guard<Arc<ShardedStorageState>> {
+        self.state.load()
+    }
+
+    /// A snapshot of the state, for reads holding it for long, e.g. iterators.
+    pub(crate) fn load_full(&self) -> Arc<ShardedStorageState> {
+        self.state.load_full()
+    }
+
+    /// Replaces the state. Must be called with `state_lock` held.
+    pub(crate) fn store(&self, state: Arc<ShardedStorageState>) {
+        debug_assert!(
+            self.state_lock.is_locked(),
+            "state stored without state_lock"
+        );
+        self.state.store(state)
+    }
+
+    /// Same as [`Self::load`], named after the lock the other engines of the course keep their
+    /// state behind, for the code shared with them.
+    pub(crate) fn read(&self) -> Guard<Arc<ShardedStorageState>> {
+        self.load()
+    }
+
+    /// Replaces the state once the guard is dropped, like the write guard of the lock the other
+    /// engines keep their state behind, for the tests shared with them. The guard holds
+    /// `state_lock`, so that no other writer changes the state in the meantime.
+    #[cfg(test)]
+    pub(crate) fn write(&self) -> StateWriteGuard<'_> {
+        let state_lock = self.state_lock.lock();
+        StateWriteGuard {
+            cell: self,
+            state: self.load_full(),
+            _state_lock: state_lock,
+        }
+    }
+}
+
+/// See [`StateCell::write`].
+#[cfg(test)]
+pub(crate) struct StateWriteGuard<'a> {
+    cell: &'a StateCell,
+    state: Arc<ShardedStorageState>,
+    _state_lock: MutexGuard<'a, ()>,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for StateWriteGuard<'_> {
+    type Target = Arc<ShardedStorageState>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+impl std::ops::DerefMut for StateWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+impl Drop for StateWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.cell.store(self.state.clone());
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -156,16 +763,67 @@ pub enum CompactionFilter {
 
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
-    pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
-    pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    /// Read without locking: each read loads one snapshot of the state and sticks to it. A new
+    /// state is only stored with `state_lock` held, so that its writers never miss each other's
+    /// changes.
+    pub(crate) state: StateCell,
+    pub(crate) state_lock: Arc<Mutex<()>>,
+    /// Held shared by the writes to the memtable, and exclusively by a freeze swapping it, so
+    /// that no write lands in a memtable once it is frozen, see [`Self::write_to_memtables`].
+    pub(crate) memtable_gate: RwLock<()>,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
+    /// The namespace of the SSTs in `block_cache`, which keyspaces share, see
+    /// [`SsTable::with_cache_namespace`].
+    pub(crate) cache_namespace: u32,
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
-    pub(crate) compaction_controller: CompactionController,
+    /// Built from `dynamic_options`, and replaced whenever they change.
+    pub(crate) compaction_controller: ArcSwap<CompactionController>,
+    /// The options that can be changed at runtime, which take precedence over `options`.
+    pub(crate) dynamic_options: ArcSwap<DynamicOptions>,
+    /// `None` if the storage is read-only, see [`Self::open_read_only`].
     pub(crate) manifest: Option<Manifest>,
-    pub(crate) mvcc: Option<LsmMvccInner>,
+    /// Shared by the keyspaces of a storage, see [`MiniLsm::open_with_keyspaces`].
+    pub(crate) mvcc: Option<Arc<LsmMvccInner>>,
+    /// Set if the storage is a keyspace of another, whose memtables have no WAL: the writes are
+    /// logged to the log shared by the keyspaces instead.
+    pub(crate) keyspace: Option<Keyspace>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// Runs the sub-compactions of all compaction tasks, with `max_subcompactions` threads, see
+    /// [`LsmStorageOptions::max_subcompactions`].
+    pub(crate) subcompaction_pool: SubcompactionPool,
+    pub(crate) rate_limiter: Arc<RateLimiter>,
+    pub(crate) last_compaction_reason: Mutex<Option<String>>,
+    pub(crate) stats: StatsCollector,
+    /// Shared with the SSTs of the storage, see [`SsTable::with_metrics`].
+    pub(crate) metrics: Arc<Metrics>,
+    /// When set, the compaction workers do not pick new tasks. Flushes are not affected.
+    compaction_paused: AtomicBool,
+    /// Set once [`MiniLsm::close`] stops accepting writes.
+    closed: AtomicBool,
+    /// Set by [`MiniLsm::close`] to make the compactions in progress fail, see
+    /// [`LsmStorageOptions::close_abandons_compactions`].
+    compactions_abandoned: AtomicBool,
+    /// Delays and stops the writes while the flushes or compactions are behind.
+    pub(crate) write_controller: WriteController,
+    /// Input SSTs of the compaction tasks currently running.
+    pub(crate) compacting_ssts: Mutex<HashSet<usize>>,
+    /// Consecutive compaction failures, shared by all compaction workers.
+    pub(crate) compaction_backoff: Mutex<CompactionBackoff>,
+    /// Ranges where scans skipped many tombstones, `None` if delete-triggered compaction is
+    /// disabled.
+    pub(crate) tombstone_reports: Option<Arc<TombstoneReports>>,
+    /// Serializes flushes, so that the memtable being flushed stays the earliest one while the
+    /// event listeners are notified without holding `state_lock`.
+    flush_lock: Mutex<()>,
+    next_compaction_job_id: AtomicU64,
+    /// SSTs removed from the state whose files are kept until no iterator or snapshot reads them.
+    obsolete_ssts: Mutex<Vec<Arc<SsTable>>>,
+    /// Points where a test makes the engine fail, e.g. to simulate a crash, see
+    /// [`Self::failpoint`].
+    #[cfg(test)]
+    pub(crate) failpoints: Mutex<HashSet<&'static str>>,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -175,69 +833,137 @@ pub struct MiniLsm {
     flush_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 1 day 6)
     flush_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
-    /// Notifies the compaction thread to stop working. (In week 2)
+    /// Notifies the compaction workers to stop working, one message per worker. (In week 2)
     compaction_notifier: crossbeam_channel::Sender<()>,
-    /// The handle for the compaction thread. (In week 2)
-    compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// The handles for the compaction workers. (In week 2)
+    pub(crate) compaction_threads: Mutex<Vec<std::thread::JoinHandle<()>>>,
+    /// The keyspaces opened with the storage, see [`Self::open_with_keyspaces`].
+    pub(crate) keyspaces: Vec<KeyspaceHandle>,
 }
 
+/// The error of the writes to a storage once it is closed, see [`MiniLsm::close`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosedError;
+
+impl fmt::Display for ClosedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the storage is closed")
+    }
+}
+
+impl std::error::Error for ClosedError {}
+
 impl Drop for MiniLsm {
     fn drop(&mut self) {
-        self.compaction_notifier.send(()).ok();
-        self.flush_notifier.send(()).ok();
+        // best effort for a storage dropped without being closed, a no-op otherwise
+        if let Err(e) = self.close() {
+            eprintln!("failed to close the storage: {:#}", e);
+        }
     }
 }
 
 impl MiniLsm {
+    fn stop_compaction_threads(&self) {
+        for _ in 0..self.compaction_threads.lock().len() {
+            self.compaction_notifier.send(()).ok();
+        }
+    }
+
+    /// Closes the storage:
+    ///
+    /// 1. new writes fail with a [`ClosedError`], once the writes in progress are done;
+    /// 2. the WAL is synced;
+    /// 3. the flush thread and the compaction workers are stopped and joined, once the
+    ///    compactions in progress are finished or abandoned, see
+    ///    [`LsmStorageOptions::close_abandons_compactions`];
+    /// 4. the memtables are flushed to SSTs if [`LsmStorageOptions::close_flush`] is set or the
+    ///    WAL is disabled;
+    /// 5. the manifest is synced, the lock file removed and the directory synced;
+    /// 6. the keyspaces, if any, are closed the same way.
+    ///
+    /// Every step runs even if an earlier one fails, and the first error is returned. Closing
+    /// again does nothing; dropping the storage closes it if it was not closed.
     pub fn close(&self) -> Result<()> {
-        self.inner.sync_dir()?;
-        self.compaction_notifier.send(()).ok();
-        self.flush_notifier.send(()).ok();
+        if self.inner.is_read_only() {
+            // nothing to write, and no background thread to stop
+            return Ok(());
+        }
+        if !self.inner.stop_writes() {
+            return Ok(());
+        }
+        let mut result = self.inner.sync();
 
-        let mut compaction_thread = self.compaction_thread.lock();
-        if let Some(compaction_thread) = compaction_thread.take() {
-            compaction_thread
-                .join()
-                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        if self.inner.options.close_abandons_compactions {
+            self.inner.abandon_compactions();
         }
-        let mut flush_thread = self.flush_thread.lock();
-        if let Some(flush_thread) = flush_thread.take() {
-            flush_thread
+        self.stop_compaction_threads();
+        self.flush_notifier.send(()).ok();
+        let compaction_threads = std::mem::take(&mut *self.compaction_threads.lock());
+        let flush_thread = self.flush_thread.lock().take();
+        for thread in compaction_threads.into_iter().chain(flush_thread) {
+            let joined = thread
                 .join()
-                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                .map_err(|e| anyhow::anyhow!("background thread panicked: {:?}", e));
+            result = result.and(joined);
         }
 
-        if self.inner.options.enable_wal {
-            self.inner.sync()?;
-            self.inner.sync_dir()?;
-            return Ok(());
+        if self.inner.options.close_flush || !self.inner.options.enable_wal {
+            result = result.and(self.flush_all_memtables());
         }
+        result = result.and(self.inner.delete_obsolete_ssts(Vec::new()));
+        result = result.and(self.inner.manifest().sync());
+        result = result.and(remove_lock_file(&self.inner.path));
+        result = result.and(self.inner.sync_dir());
+        for keyspace in &self.keyspaces {
+            result = result.and(keyspace.storage().close());
+        }
+        result
+    }
 
-        // create memtable and skip updating manifest
-        if !self.inner.state.read().memtable.is_empty() {
-            self.inner
-                .freeze_memtable_with_memtable(Arc::new(MemTable::create(
-                    self.inner.next_sst_id(),
-                )))?;
+    /// Flushes all the memtables to SSTs, once the background threads are stopped.
+    fn flush_all_memtables(&self) -> Result<()> {
+        // create memtable and skip updating manifest, as it is never written to
+        let state_lock = self.inner.state_lock.lock();
+        let shards = self
+            .inner
+            .state
+            .load()
+            .current_memtables()
+            .map(|memtable| !memtable.is_empty())
+            .collect::<Vec<_>>();
+        for (shard, non_empty) in shards.into_iter().enumerate() {
+            if non_empty {
+                self.inner.freeze_memtable_with_memtable(
+                    &state_lock,
+                    shard,
+                    Arc::new(MemTable::create(self.inner.next_sst_id())),
+                )?;
+            }
         }
+        drop(state_lock);
 
         while {
-            let snapshot = self.inner.state.read();
+            let snapshot = self.inner.state.load();
             !snapshot.imm_memtables.is_empty()
         } {
             self.inner.force_flush_next_imm_memtable()?;
         }
-        self.inner.sync_dir()?;
-
         Ok(())
     }
 
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
-        let inner = Arc::new(LsmStorageInner::open(path, options)?);
+        Self::start(Arc::new(LsmStorageInner::open(path, options)?), Vec::new())
+    }
+
+    /// Starts the flush thread and the compaction workers of an opened storage.
+    pub(crate) fn start(
+        inner: Arc<LsmStorageInner>,
+        keyspaces: Vec<KeyspaceHandle>,
+    ) -> Result<Arc<Self>> {
         let (tx1, rx) = crossbeam_channel::unbounded();
-        let compaction_thread = inner.spawn_compaction_thread(rx)?;
+        let compaction_threads = inner.spawn_compaction_threads(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
         Ok(Arc::new(Self {
@@ -245,7 +971,25 @@ impl MiniLsm {
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
-            compaction_thread: Mutex::new(compaction_thread),
+            compaction_threads: Mutex::new(compaction_threads),
+            keyspaces,
+        }))
+    }
+
+    /// Open the storage in a directory another engine may be writing to, without writing to it,
+    /// see [`LsmStorageInner::open_read_only`]. No flush or compaction thread is started.
+    pub fn open_read_only(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+        let inner = Arc::new(LsmStorageInner::open_read_only(path, options)?);
+        // nothing receives the notifications, as there is no background thread
+        let (compaction_notifier, _) = crossbeam_channel::unbounded();
+        let (flush_notifier, _) = crossbeam_channel::unbounded();
+        Ok(Arc::new(Self {
+            inner,
+            flush_notifier,
+            flush_thread: Mutex::new(None),
+            compaction_notifier,
+            compaction_threads: Mutex::new(Vec::new()),
+            keyspaces: Vec::new(),
         }))
     }
 
@@ -253,10 +997,82 @@ impl MiniLsm {
         self.inner.add_compaction_filter(compaction_filter)
     }
 
+    pub fn set_rate_limit(&self, bytes_per_sec: u64) {
+        self.inner.set_rate_limit(bytes_per_sec)
+    }
+
+    pub fn throttled_time(&self, priority: IoPriority) -> Duration {
+        self.inner.throttled_time(priority)
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.inner.compaction_stats()
+    }
+
+    pub fn plan_compaction(&self) -> Option<CompactionPlan> {
+        self.inner.plan_compaction()
+    }
+
+    pub fn approximate_size_of_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        self.inner.approximate_size_of_range(lower, upper)
+    }
+
+    pub fn disk_usage(&self) -> Result<DiskUsage> {
+        self.inner.disk_usage()
+    }
+
+    pub fn set_options(&self, delta: OptionsDelta) -> Result<()> {
+        self.inner.set_options(delta)
+    }
+
+    pub fn dynamic_options(&self) -> Arc<DynamicOptions> {
+        self.inner.dynamic_options()
+    }
+
+    pub fn ingest_sst(&self, path: impl AsRef<Path>) -> Result<usize> {
+        self.inner.ingest_sst(path)
+    }
+
+    pub fn ingest_ssts(&self, paths: &[PathBuf], options: &IngestOptions) -> Result<Vec<usize>> {
+        self.inner.ingest_ssts(paths, options)
+    }
+
+    pub fn background_error(&self) -> Option<String> {
+        self.inner.background_error()
+    }
+
+    pub fn pause_background_compaction(&self) {
+        self.inner.pause_background_compaction()
+    }
+
+    pub fn resume_background_compaction(&self) {
+        self.inner.resume_background_compaction()
+    }
+
+    pub fn is_background_compaction_paused(&self) -> bool {
+        self.inner.is_background_compaction_paused()
+    }
+
+    /// Get the value of a key, see [`LsmStorageInner::get`].
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         self.inner.get(key)
     }
 
+    /// Get the value of a key in its own allocation, see [`LsmStorageInner::get_copied`].
+    pub fn get_copied(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_copied(key)
+    }
+
+    /// Get the value of a key, see [`LsmStorageInner::get_with_options`].
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.inner.get_with_options(key, options)
+    }
+
+    /// Get the value of a key from memory only, see [`LsmStorageInner::get_cached_only`].
+    pub fn get_cached_only(&self, key: &[u8]) -> Result<CachedGet> {
+        self.inner.get_cached_only(key)
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
@@ -265,10 +1081,53 @@ impl MiniLsm {
         self.inner.put(key, value)
     }
 
+    pub fn put_with_options(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.put_with_options(key, value, options)
+    }
+
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_with_options(batch, options)
+    }
+
+    /// Put a key-value pair expiring after `ttl`, see [`LsmStorageInner::put_with_ttl`].
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    /// Writes a merge operand for `key`, see [`LsmStorageInner::merge`].
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(key, operand)
+    }
+
+    /// Removes the user keys from `start` (inclusive) to `end` (exclusive), see
+    /// [`LsmStorageInner::delete_range`].
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range(start, end).map(|_| ())
+    }
+
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get(keys)
+    }
+
+    /// Replaces the value of a key if it is as expected, see
+    /// [`LsmStorageInner::compare_and_swap`].
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<CasResult> {
+        self.inner.compare_and_swap(key, expected, new)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -281,13 +1140,34 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_rev(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnRevIterator> {
+        self.inner.scan_rev(lower, upper)
+    }
+
+    /// Create an iterator over a range of keys, see [`LsmStorageInner::scan_with_options`].
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_with_options(lower, upper, options)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
-        if !self.inner.state.read().memtable.is_empty() {
+        self.inner.check_writable()?;
+        if self
+            .inner
+            .state
+            .load()
+            .current_memtables()
+            .any(|memtable| !memtable.is_empty())
+        {
             self.inner
                 .force_freeze_memtable(&self.inner.state_lock.lock())?;
         }
-        if !self.inner.state.read().imm_memtables.is_empty() {
+        if !self.inner.state.load().imm_memtables.is_empty() {
             self.inner.force_flush_next_imm_memtable()?;
         }
         Ok(())
@@ -312,140 +1192,266 @@ impl LsmStorageInner {
         self.manifest.as_ref().unwrap()
     }
 
+    /// The keyspace the storage is, if its writes are logged, see [`Self::keyspace`].
+    fn logged_keyspace(&self) -> Option<&Keyspace> {
+        self.keyspace.as_ref().filter(|_| self.options.enable_wal)
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
+        let block_cache = Arc::new(new_block_cache(
+            options.block_cache_size,
+            block_size_weigher,
+        ));
+        Self::open_with_block_cache(path, options, block_cache, 0)
+    }
+
+    /// Opens the storage like [`Self::open`], caching the blocks of its SSTs in `block_cache`
+    /// under `cache_namespace`, e.g. a cache shared by the keyspaces of a storage.
+    pub(crate) fn open_with_block_cache(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        block_cache: Arc<BlockCache>,
+        cache_namespace: u32,
+    ) -> Result<Self> {
+        options.write_stall.validate(options.num_memtable_limit)?;
+        validate_num_shards(options.num_shards)?;
+        let mut state = ShardedStorageState::create(&options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
-        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
         let manifest;
 
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
-        };
+        let compaction_controller = CompactionController::new(&options.compaction_options);
 
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
         }
+        check_not_restoring(path)?;
+        write_lock_file(path)?;
         let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
+        let metrics = Arc::new(Metrics::default());
         if !manifest_path.exists() {
-            if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
-            }
+            state.memtable = Arc::new(Self::create_memtable(
+                path,
+                &options,
+                0,
+                0,
+                options.enable_wal,
+            )?);
             manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
             manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            for shard in 1..options.num_shards {
+                let memtable =
+                    Self::create_memtable(path, &options, next_sst_id, shard, options.enable_wal)?;
+                manifest.add_record_when_init(ManifestRecord::NewMemtable(memtable.id()))?;
+                state.shard_memtables.push(Arc::new(memtable));
+                next_sst_id += 1;
+            }
         } else {
             let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
-            for record in records {
-                match record {
-                    ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
-                            state.l0_sstables.insert(0, sst_id);
-                        } else {
-                            state.levels.insert(0, (sst_id, vec![sst_id]));
-                        }
-                        next_sst_id = next_sst_id.max(sst_id);
-                    }
-                    ManifestRecord::NewMemtable(x) => {
-                        next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
-                    }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) =
-                            compaction_controller.apply_compaction_result(&state, &task, &output);
-                        // TODO: apply remove again
-                        state = new_state;
-                        next_sst_id =
-                            next_sst_id.max(output.iter().max().copied().unwrap_or_default());
-                    }
-                }
-            }
-
-            let mut sst_cnt = 0;
-            // recover SSTs
-            for table_id in state
-                .l0_sstables
-                .iter()
-                .chain(state.levels.iter().flat_map(|(_, files)| files))
-            {
-                let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
-                )?;
-                last_commit_ts = last_commit_ts.max(sst.max_ts());
-                state.sstables.insert(table_id, Arc::new(sst));
-                sst_cnt += 1;
-            }
-            println!("{} SSTs opened", sst_cnt);
-
-            next_sst_id += 1;
+            let replayed = replay_manifest(&mut state, &compaction_controller, records);
+            next_sst_id = next_sst_id.max(replayed.max_id);
+            last_commit_ts = Self::open_ssts(
+                path,
+                &mut state,
+                &replayed,
+                &options,
+                &block_cache,
+                cache_namespace,
+                &metrics,
+            )?;
+            next_sst_id += 1;
 
             // recover memtables
             if options.enable_wal {
+                let mut recovered = Vec::with_capacity(replayed.memtables.len());
+                for id in replayed.memtables.iter() {
+                    let mut batch_parts = Vec::new();
+                    let memtable = MemTable::recover_from_wal(
+                        *id,
+                        Self::path_of_wal_static(path, *id),
+                        &mut batch_parts,
+                    )?;
+                    recovered.push((memtable, batch_parts));
+                }
+                let batches =
+                    apply_batch_parts(&recovered, &replayed.memtables, &replayed.dropped_batches);
+                for seq in batches.dropped {
+                    println!("dropped the batch {} missing some of its parts", seq);
+                    m.add_record_when_init(ManifestRecord::DropBatch(seq))?;
+                }
+                // the timestamps of the dropped batches are never given again
+                let dropped_max_seq = replayed.dropped_batches.iter().max().copied();
+                last_commit_ts = last_commit_ts
+                    .max(batches.max_seq)
+                    .max(dropped_max_seq.unwrap_or_default());
                 let mut wal_cnt = 0;
-                for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
+                for (memtable, _) in recovered {
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts());
                     if !memtable.is_empty() {
                         state.imm_memtables.insert(0, Arc::new(memtable));
                         wal_cnt += 1;
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
+            }
+            for shard in 0..options.num_shards {
+                let memtable = Arc::new(Self::create_memtable(
+                    path,
+                    &options,
                     next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
+                    shard,
+                    options.enable_wal,
                 )?);
-            } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                m.add_record_when_init(ManifestRecord::NewMemtable(memtable.id()))?;
+                next_sst_id += 1;
+                if shard == 0 {
+                    state.memtable = memtable;
+                } else {
+                    state.shard_memtables.push(memtable);
+                }
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
-            next_sst_id += 1;
             manifest = m;
         };
 
-        let storage = Self {
-            state: Arc::new(RwLock::new(Arc::new(state))),
-            state_lock: Mutex::new(()),
+        let mut storage = Self::from_state(
+            path,
+            options,
+            state,
+            block_cache,
+            next_sst_id,
+            Some(manifest),
+            last_commit_ts,
+        );
+        storage.cache_namespace = cache_namespace;
+        storage.metrics = metrics;
+        storage.sync_dir()?;
+
+        Ok(storage)
+    }
+
+    /// Opens the SSTs in `path` of the `state` recovered from the manifest, and removes the
+    /// others. Returns the newest timestamp of their entries.
+    pub(crate) fn open_ssts(
+        path: &Path,
+        state: &mut LsmStorageState,
+        replayed: &ReplayedManifest,
+        options: &LsmStorageOptions,
+        block_cache: &Arc<BlockCache>,
+        cache_namespace: u32,
+        metrics: &Arc<Metrics>,
+    ) -> Result<u64> {
+        let mut max_ts = 0;
+        let mut sst_cnt = 0;
+        let table_ids = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<Vec<_>>();
+        for table_id in table_ids {
+            let mut sst = SsTable::open(
+                table_id,
+                Some(block_cache.clone()),
+                FileObject::open(&Self::path_of_sst_static(path, table_id))
+                    .context("failed to open SST")?,
+            )?
+            .with_cache_namespace(cache_namespace)
+            .with_metrics(metrics.clone());
+            if let Some(shard) = replayed.sst_shard(table_id, options.num_shards) {
+                sst = sst.with_shard(shard);
+            }
+            max_ts = max_ts.max(sst.max_ts());
+            state.sstables.insert(table_id, Arc::new(sst));
+            sst_cnt += 1;
+        }
+        println!("{} SSTs opened", sst_cnt);
+        Self::remove_orphan_ssts(path, state)?;
+        Ok(max_ts)
+    }
+
+    /// Creates the memtable `id` of `shard`, with a WAL if `with_wal` is set. When the writes are
+    /// sharded, the memtable is tagged with its shard, see [`MemTable::with_shard`].
+    fn create_memtable(
+        path: &Path,
+        options: &LsmStorageOptions,
+        id: usize,
+        shard: usize,
+        with_wal: bool,
+    ) -> Result<MemTable> {
+        let memtable = if with_wal {
+            MemTable::create_with_wal(id, Self::path_of_wal_static(path, id))?
+        } else {
+            MemTable::create(id)
+        };
+        Ok(if options.num_shards > 1 {
+            memtable.with_shard(shard)
+        } else {
+            memtable
+        })
+    }
+
+    /// Builds the storage around a recovered `state`. Without a manifest, the storage is
+    /// read-only, see [`Self::is_read_only`].
+    pub(crate) fn from_state(
+        path: &Path,
+        options: LsmStorageOptions,
+        state: ShardedStorageState,
+        block_cache: Arc<BlockCache>,
+        next_sst_id: usize,
+        manifest: Option<Manifest>,
+        last_commit_ts: u64,
+    ) -> Self {
+        let state_lock = Arc::new(Mutex::new(()));
+        Self {
+            state: StateCell::new(state, state_lock.clone()),
+            state_lock,
+            memtable_gate: RwLock::new(()),
             path: path.to_path_buf(),
             block_cache,
+            cache_namespace: 0,
             next_sst_id: AtomicUsize::new(next_sst_id),
-            compaction_controller,
-            manifest: Some(manifest),
+            compaction_controller: ArcSwap::from_pointee(CompactionController::new(
+                &options.compaction_options,
+            )),
+            dynamic_options: ArcSwap::from_pointee(DynamicOptions {
+                target_sst_size: options.target_sst_size,
+                rate_limit_bytes_per_sec: options.rate_limit_bytes_per_sec,
+                compaction_options: options.compaction_options.clone(),
+            }),
+            manifest,
+            rate_limiter: Arc::new(RateLimiter::new(options.rate_limit_bytes_per_sec)),
+            last_compaction_reason: Mutex::new(None),
+            stats: StatsCollector::default(),
+            metrics: Arc::new(Metrics::default()),
+            compaction_paused: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            compactions_abandoned: AtomicBool::new(false),
+            write_controller: WriteController::default(),
+            compacting_ssts: Mutex::new(HashSet::new()),
+            compaction_backoff: Mutex::new(CompactionBackoff::default()),
+            subcompaction_pool: SubcompactionPool::new(if options.max_subcompactions > 1 {
+                options.max_subcompactions
+            } else {
+                0
+            }),
+            tombstone_reports: (options.delete_triggered_compaction_threshold > 0).then(|| {
+                Arc::new(TombstoneReports::new(
+                    options.delete_triggered_compaction_threshold,
+                ))
+            }),
             options: options.into(),
-            mvcc: Some(LsmMvccInner::new(last_commit_ts)),
+            mvcc: Some(Arc::new(LsmMvccInner::new(last_commit_ts))),
+            keyspace: None,
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
-        };
-        storage.sync_dir()?;
-
-        Ok(storage)
+            flush_lock: Mutex::new(()),
+            next_compaction_job_id: AtomicU64::new(0),
+            obsolete_ssts: Mutex::new(Vec::new()),
+            #[cfg(test)]
+            failpoints: Mutex::new(HashSet::new()),
+        }
     }
 
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
@@ -454,27 +1460,278 @@ impl LsmStorageInner {
     }
 
     pub fn sync(&self) -> Result<()> {
-        self.state.read().memtable.sync_wal()
+        if let Some(keyspace) = &self.keyspace {
+            return keyspace.log.sync();
+        }
+        for memtable in self.state.load().current_memtables() {
+            memtable.sync_wal()?;
+        }
+        Ok(())
+    }
+
+    /// Change the background I/O rate limit at runtime. 0 disables rate limiting.
+    pub fn set_rate_limit(&self, bytes_per_sec: u64) {
+        self.rate_limiter.set_bytes_per_sec(bytes_per_sec);
+    }
+
+    /// Get the time flushes and compactions have spent blocked by the rate limiter.
+    pub fn throttled_time(&self, priority: IoPriority) -> Duration {
+        self.rate_limiter.throttled_time(priority)
+    }
+
+    pub fn compaction_stats(&self) -> CompactionStats {
+        let mut stats = CompactionStats {
+            last_task_reason: self.last_compaction_reason.lock().clone(),
+            flush_throttled_time: self.rate_limiter.throttled_time(IoPriority::High),
+            compaction_throttled_time: self.rate_limiter.throttled_time(IoPriority::Low),
+            compaction_debt: self
+                .compaction_controller
+                .load()
+                .compaction_debt(&self.state.load()),
+            ..Default::default()
+        };
+        self.stats.fill(&mut stats);
+        self.write_controller.fill(&mut stats);
+        if let Some(reports) = &self.tombstone_reports {
+            stats.num_tombstone_reports = reports.num_reports();
+            stats.scan_skipped_tombstones = reports.skipped_tombstones();
+        }
+        stats
+    }
+
+    /// Estimates the size of the user keys in the range, from the block meta of the SSTs (see
+    /// [`SsTable::approximate_bytes_in_range`]) and the entries of the memtables, counting every
+    /// version and tombstone. No block is read.
+    pub fn approximate_size_of_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        if is_empty_range(lower, upper) {
+            return 0;
+        }
+        let snapshot = self.state.load_full();
+        let memtable_bytes = snapshot
+            .memtables()
+            .map(|memtable| memtable.approximate_bytes_in_range(lower, upper) as u64)
+            .sum::<u64>();
+        let sst_bytes = snapshot
+            .sstables
+            .values()
+            .map(|table| table.approximate_bytes_in_range(lower, upper))
+            .sum::<u64>();
+        memtable_bytes + sst_bytes
+    }
+
+    /// Reports the space taken by the live SSTs, from their sizes in the state, and by the WALs,
+    /// from the metadata of the files. No block is read.
+    pub fn disk_usage(&self) -> Result<DiskUsage> {
+        let snapshot = self.state.load_full();
+        let level_bytes = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>()
+        };
+        let mut by_level = vec![level_bytes(&snapshot.l0_sstables)];
+        by_level.extend(snapshot.levels.iter().map(|(_, ids)| level_bytes(ids)));
+        let sst_bytes = by_level.iter().sum::<u64>();
+        let live_bytes = snapshot
+            .sstables
+            .values()
+            .map(|table| table.table_size() as f64 * (1.0 - table.properties().garbage_ratio()))
+            .sum::<f64>();
+        let mut wal_bytes = 0;
+        if self.options.enable_wal {
+            for memtable in snapshot.memtables() {
+                match std::fs::metadata(self.path_of_wal(memtable.id())) {
+                    Ok(metadata) => wal_bytes += metadata.len(),
+                    // the WAL of a memtable flushed in the meantime is removed
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(DiskUsage {
+            sst_bytes,
+            wal_bytes,
+            by_level,
+            space_amplification: if live_bytes > 0.0 {
+                sst_bytes as f64 / live_bytes
+            } else {
+                1.0
+            },
+        })
+    }
+
+    /// Validates `delta` and applies it to the running engine. Either all changes take effect or,
+    /// if any of them is invalid, none does. Compaction tasks already running finish with the
+    /// old options.
+    pub fn set_options(&self, delta: OptionsDelta) -> Result<()> {
+        let _state_lock = self.state_lock.lock();
+        let old = self.dynamic_options.load_full();
+        let new = old.apply(delta)?;
+        self.rate_limiter
+            .set_bytes_per_sec(new.rate_limit_bytes_per_sec);
+        self.compaction_controller
+            .store(Arc::new(CompactionController::new(&new.compaction_options)));
+        println!("options changed from {:?} to {:?}", old, new);
+        self.dynamic_options.store(Arc::new(new));
+        Ok(())
+    }
+
+    /// The current values of the options that can be changed at runtime.
+    pub fn dynamic_options(&self) -> Arc<DynamicOptions> {
+        self.dynamic_options.load_full()
+    }
+
+    /// Stop the compaction thread from picking new tasks, e.g. during a bulk load. A task that is
+    /// already running is finished.
+    pub fn pause_background_compaction(&self) {
+        self.compaction_paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Let the compaction thread pick new tasks again, so that the accumulated backlog drains.
+    pub fn resume_background_compaction(&self) {
+        self.compaction_paused.store(false, Ordering::SeqCst);
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    pub fn is_background_compaction_paused(&self) -> bool {
+        self.compaction_paused.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting writes, see [`MiniLsm::close`]. The flag is set with the write lock held,
+    /// under which the writes check it, so no write is applied after this returns. Returns false
+    /// if the writes were already stopped.
+    pub(crate) fn stop_writes(&self) -> bool {
+        let _write_lock = self.mvcc().lock_writes();
+        self.write_controller.close();
+        !self.closed.swap(true, Ordering::SeqCst)
+    }
+
+    /// Fails with a [`ClosedError`] once the writes are stopped by [`Self::stop_writes`].
+    pub(crate) fn check_open(&self, _write_lock: &MutexGuard<'_, ()>) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ClosedError.into());
+        }
+        Ok(())
+    }
+
+    /// Makes the compactions in progress fail, leaving no output behind, see
+    /// [`LsmStorageOptions::close_abandons_compactions`]. Their I/O is no longer throttled, so
+    /// that they notice it soon.
+    pub(crate) fn abandon_compactions(&self) {
+        self.compactions_abandoned.store(true, Ordering::SeqCst);
+        self.rate_limiter.set_bytes_per_sec(0);
+    }
+
+    /// Fails if the compactions were abandoned by [`Self::abandon_compactions`].
+    pub(crate) fn check_compaction_abandoned(&self) -> Result<()> {
+        if self.compactions_abandoned.load(Ordering::SeqCst) {
+            bail!("compaction abandoned as the storage is closed");
+        }
+        Ok(())
+    }
+
+    /// Get the value of a key without copying it: a value read from an SST shares the allocation
+    /// of its cached block, and one read from a memtable shares the allocation of the entry. The
+    /// whole block stays in memory as long as the value is held, even once evicted from the block
+    /// cache, so holding many small values for long can pin far more memory than they take. Use
+    /// [`Self::get_copied`] to get values detached from the blocks.
     pub fn get(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
+        let span = trace_span!("get", key_len = key.len(), outcome = tracing::field::Empty);
+        let _entered = span.enter();
         let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.get(key)
+        let result = txn.get(key);
+        span.record(
+            "outcome",
+            match &result {
+                Ok(Some(_)) => "found",
+                Ok(None) => "not_found",
+                Err(_) => "error",
+            },
+        );
+        result
+    }
+
+    /// Get the value of a key like [`Self::get`], copied into its own allocation.
+    pub fn get_copied(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
+        Ok(self.get(key)?.map(|value| Bytes::copy_from_slice(&value)))
+    }
+
+    /// Get the value of a key like [`Self::get`], reading as told by `options`. The read is not
+    /// part of a transaction.
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        let read_ts = options
+            .read_ts
+            .unwrap_or_else(|| self.mvcc().latest_commit_ts());
+        self.get_with_ts_inner(key, read_ts, options.block_read_options(CacheHint::Fill))
+    }
+
+    /// Get the value of a key from memory only: the memtables, and the SSTs through the blocks
+    /// already in the block cache. Meant for best-effort reads that would rather not wait on the
+    /// disk: once an SST that may hold a newer version than those found so far needs a block
+    /// that is not cached, nothing is read and [`CachedGet::WouldBlock`] is returned. So is it for
+    /// merge operands, whose older versions may be cold. The read is not part of a transaction.
+    pub fn get_cached_only(&self, key: &[u8]) -> Result<CachedGet> {
+        let read_ts = self.mvcc().latest_commit_ts();
+        let snapshot = self.state.load_full();
+        let range_tombstones =
+            snapshot.range_tombstones(Bound::Included(key), Bound::Included(key), read_ts);
+        let now_secs = self.options.clock.now_secs();
+        let found = |ts: u64, value: Bytes, flags: u8| {
+            if flags & ENTRY_FLAG_TOMBSTONE != 0
+                || range_tombstones.covers(key, ts)
+                || ttl::is_expired(flags, &value, now_secs)
+            {
+                CachedGet::Value(None)
+            } else if flags & ENTRY_FLAG_MERGE_OPERAND != 0 {
+                CachedGet::WouldBlock
+            } else {
+                CachedGet::Value(Some(ttl::user_value_bytes(flags, value)))
+            }
+        };
+
+        let shard = shard_of(key, self.options.num_shards);
+        for memtable in snapshot.memtables_of_shard(shard) {
+            // the newest version at or below `read_ts` comes first
+            let iter = memtable.scan(
+                Bound::Included(KeySlice::from_slice(key, read_ts)),
+                Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            );
+            if iter.is_valid() {
+                return Ok(found(iter.key().ts(), iter.value_bytes(), iter.flags()));
+            }
+        }
+
+        // hashed once for the bloom filters of all the SSTs
+        let hashed_key = HashedKey::new(key);
+        let level_ssts = snapshot.levels.iter().flat_map(|(_, ids)| ids.iter());
+        for id in snapshot.l0_sstables.iter().chain(level_ssts) {
+            if !snapshot.sstables[id].in_shard(shard) {
+                continue;
+            }
+            match snapshot.sstables[id].get_cached_only(hashed_key, read_ts) {
+                CachedLookup::Found { ts, value, flags } => return Ok(found(ts, value, flags)),
+                CachedLookup::NotFound => {}
+                CachedLookup::WouldBlock => return Ok(CachedGet::WouldBlock),
+            }
+        }
+        Ok(CachedGet::Value(None))
     }
 
     pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.state.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        self.get_with_ts_inner(key, read_ts, BlockReadOptions::default())
+    }
+
+    fn get_with_ts_inner(
+        &self,
+        key: &[u8],
+        read_ts: u64,
+        read_options: BlockReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let timer = self.metrics.start_timer();
+        let snapshot = self.state.load_full();
+        // the memtables and L0 SSTs of the other shards cannot hold the key
+        let shard = shard_of(key, self.options.num_shards);
 
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
+        for memtable in snapshot.memtables_of_shard(shard) {
             memtable_iters.push(Box::new(memtable.scan(
                 Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
                 Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
@@ -482,32 +1739,20 @@ impl LsmStorageInner {
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
+        // hashed once for the bloom filters of all the SSTs
+        let hashed_key = HashedKey::new(key);
         let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
-        let keep_table = |key: &[u8], table: &SsTable| {
-            if key_within(
-                key,
-                table.first_key().as_key_slice(),
-                table.last_key().as_key_slice(),
-            ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
-                }
-            }
-            false
-        };
-
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
-            if keep_table(key, &table) {
-                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                    table,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?));
+            if table.in_shard(shard) && table.may_contain_hashed_key(hashed_key) {
+                l0_iters.push(Box::new(
+                    SsTableIterator::create_and_seek_to_key_with_read_options(
+                        table,
+                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        read_options,
+                    )?,
+                ));
             }
         }
         let l0_iter = MergeIterator::create(l0_iters);
@@ -516,13 +1761,14 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(snapshot.levels[0].1.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
+                if table.may_contain_hashed_key(hashed_key) {
                     level_ssts.push(table);
                 }
             }
-            let level_iter = SstConcatIterator::create_and_seek_to_key(
+            let level_iter = SstConcatIterator::create_and_seek_to_key_with_read_options(
                 level_ssts,
                 KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                read_options,
             )?;
             level_iters.push(Box::new(level_iter));
         }
@@ -534,55 +1780,338 @@ impl LsmStorageInner {
             )?,
             Bound::Unbounded,
             read_ts,
+            self.options.clock.now_secs(),
+            snapshot.range_tombstones(Bound::Included(key), Bound::Included(key), read_ts),
+            self.options.merge_operator.clone(),
+            None,
         )?;
 
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        let value = (iter.is_valid() && iter.key() == key).then(|| iter.value_bytes());
+        self.metrics.record_get(timer, value.as_ref());
+        Ok(value)
+    }
+
+    /// Get many keys from the storage as of one snapshot, see [`Self::multi_get_with_ts`].
+    pub fn multi_get(self: &Arc<Self>, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.multi_get(keys)
+    }
+
+    /// Looks up the keys like [`Self::get_with_ts`] on each of them, sharing the work between
+    /// them: the state is read once, and each SST is visited once with the sorted keys falling in
+    /// its range, see [`SsTable::multi_get`]. The values are returned in the order of `keys`.
+    /// While any memtable or SST may hold entries with flags, e.g. merge operands, the keys are
+    /// looked up one by one.
+    pub(crate) fn multi_get_with_ts(
+        &self,
+        keys: &[&[u8]],
+        read_ts: u64,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let snapshot = self.state.load_full();
+        let has_entry_flags = snapshot
+            .memtables()
+            .any(|memtable| memtable.has_entry_flags())
+            || snapshot.sstables.values().any(|sst| sst.has_entry_flags());
+        if has_entry_flags {
+            // the flags are resolved by the iterator of single lookups
+            return keys
+                .iter()
+                .map(|key| self.get_with_ts(key, read_ts))
+                .collect();
+        }
+
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&idx| keys[idx]);
+        let sorted_keys = order.iter().map(|&idx| keys[idx]).collect::<Vec<_>>();
+        // the newest version at or below `read_ts` of each sorted key found so far
+        let mut versions: Vec<Option<(u64, Bytes)>> = vec![None; keys.len()];
+        let mut found = |idx: usize, ts: u64, value: &[u8]| {
+            if !matches!(&versions[idx], Some((newest_ts, _)) if *newest_ts >= ts) {
+                versions[idx] = Some((ts, Bytes::copy_from_slice(value)));
+            }
+        };
+
+        for memtable in snapshot.memtables() {
+            for (idx, key) in sorted_keys.iter().enumerate() {
+                let iter = memtable.scan(
+                    Bound::Included(KeySlice::from_slice(key, read_ts)),
+                    Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+                );
+                if iter.is_valid() {
+                    found(idx, iter.key().ts(), iter.value());
+                }
+            }
+        }
+        let hashed_keys = sorted_keys
+            .iter()
+            .map(|key| HashedKey::new(key))
+            .collect::<Vec<_>>();
+        let sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, ids)| ids.iter()));
+        for id in sst_ids {
+            snapshot.sstables[id].multi_get_hashed(&hashed_keys, read_ts, &mut found)?;
+        }
+
+        let range_tombstones = match (sorted_keys.first(), sorted_keys.last()) {
+            (Some(first), Some(last)) => {
+                snapshot.range_tombstones(Bound::Included(first), Bound::Included(last), read_ts)
+            }
+            _ => FragmentedRangeTombstones::default(),
+        };
+        let mut values = vec![None; keys.len()];
+        for (sorted_idx, version) in versions.into_iter().enumerate() {
+            let Some((ts, value)) = version else {
+                continue;
+            };
+            if !value.is_empty() && !range_tombstones.covers(sorted_keys[sorted_idx], ts) {
+                values[order[sorted_idx]] = Some(value);
+            }
         }
-        Ok(None)
+        self.metrics.record_reads(
+            keys.len(),
+            values.iter().flatten().map(|value| value.len()).sum(),
+        );
+        Ok(values)
     }
 
+    /// Writes the batch with a single commit timestamp, so that readers see all of it or none of
+    /// it, as one WAL record, so that recovery replays all of it or none of it. The memtable is
+    /// only frozen between batches, which keeps a batch within one memtable and its WAL. When the
+    /// writes are sharded, each shard written to gets one record, see [`Self::write_entries`].
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
+        if self.options.num_shards > 1 {
+            return self.write_batch_sharded(batch);
+        }
+        let write_lock = self.mvcc().lock_writes();
+        self.write_batch_with_lock(&write_lock, batch)
+    }
+
+    fn write_batch_with_lock<T: AsRef<[u8]>>(
+        &self,
+        write_lock: &MutexGuard<'_, ()>,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<u64> {
+        self.check_writable()?;
+        self.check_open(write_lock)?;
         let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
+        let sizes = self.write_batch_at(ts, batch)?;
+        self.mvcc().update_commit_ts(ts);
+        for (shard, size) in sizes {
+            self.try_freeze(shard, size)?;
+        }
+        Ok(ts)
+    }
+
+    /// Writes the batch like [`Self::write_batch_with_lock`], holding the write lock only to take
+    /// the commit timestamp, so that the batches going to different shards are written in
+    /// parallel. The batch becomes visible once the batches before it are written too, see
+    /// [`LsmMvccInner::finish_commit`]. A batch failing half-way may be visible in part until the
+    /// storage is reopened, and recovery drops it as a whole.
+    fn write_batch_sharded<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
+        self.check_writable()?;
+        let ts = {
+            let write_lock = self.mvcc().write_lock.lock();
+            self.check_open(&write_lock)?;
+            self.mvcc().start_commit(&write_lock)
+        };
+        let sizes = self.write_batch_at(ts, batch);
+        self.mvcc().finish_commit(ts);
+        for (shard, size) in sizes? {
+            self.try_freeze(shard, size)?;
+        }
+        Ok(ts)
+    }
+
+    /// Writes the entries of `batch` at `ts` to the memtables, see [`Self::write_entries`].
+    fn write_batch_at<T: AsRef<[u8]>>(
+        &self,
+        ts: u64,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<Vec<(usize, usize)>> {
+        let expiring_values = self.expiring_values(batch);
+        let (data, bytes_written) = self.batch_entries(batch, &expiring_values, ts)?;
+        let sizes = self.write_entries(ts, &data)?;
+        self.stats.record_user_write(bytes_written);
+        self.metrics.record_write(data.len(), bytes_written);
+        Ok(sizes)
+    }
+
+    /// Writes the entries of a batch committed at `ts` to the current memtables of their shards,
+    /// each shard as one WAL record. When the batch spans several shards, each record is a part
+    /// naming the memtables of all the parts, so that recovery applies the batch only if none of
+    /// its parts is lost, see [`crate::shard::apply_batch_parts`]. No memtable is frozen in the
+    /// meantime. Returns the shards written to, with the size of their memtables.
+    pub(crate) fn write_entries(
+        &self,
+        ts: u64,
+        data: &[(KeySlice, &[u8], u8)],
+    ) -> Result<Vec<(usize, usize)>> {
+        let _gate = self.memtable_gate.read();
+        if let Some(keyspace) = self.logged_keyspace() {
+            keyspace.log.put_batch(&[(keyspace.name.as_str(), data)])?;
+        }
+        self.write_entries_to_memtables(ts, data)
+    }
+
+    /// Writes the entries like [`Self::write_entries`], with `memtable_gate` held by the caller,
+    /// and without logging them to the log shared by the keyspaces, which the caller does.
+    pub(crate) fn write_entries_to_memtables(
+        &self,
+        ts: u64,
+        data: &[(KeySlice, &[u8], u8)],
+    ) -> Result<Vec<(usize, usize)>> {
+        let num_shards = self.options.num_shards;
+        let snapshot = self.state.load();
+        if num_shards == 1 {
+            snapshot
+                .memtable
+                .put_batch_with_flags(data.iter().copied())?;
+            return Ok(vec![(0, snapshot.memtable.approximate_size())]);
+        }
+        let mut by_shard = BTreeMap::<usize, Vec<_>>::new();
+        for entry in data {
+            by_shard
+                .entry(shard_of(entry.0.key_ref(), num_shards))
+                .or_default()
+                .push(*entry);
+        }
+        let memtable_ids = by_shard
+            .keys()
+            .map(|shard| snapshot.current_memtable(*shard).id())
+            .collect::<Vec<_>>();
+        let mut sizes = Vec::with_capacity(by_shard.len());
+        for (shard, entries) in by_shard {
+            let memtable = snapshot.current_memtable(shard);
+            if memtable_ids.len() == 1 {
+                memtable.put_batch_with_flags(entries.iter().copied())?;
+            } else {
+                if !sizes.is_empty() {
+                    self.failpoint("sharded-batch-part")?;
+                }
+                memtable.put_batch_part(ts, &memtable_ids, entries.iter().copied())?;
+            }
+            sizes.push((shard, memtable.approximate_size()));
+        }
+        Ok(sizes)
+    }
+
+    /// The values of the records of `batch` with an expiry time, encoded with it for
+    /// [`Self::batch_entries`] to borrow, `None` for the other records.
+    pub(crate) fn expiring_values<T: AsRef<[u8]>, R: Borrow<WriteBatchRecord<T>>>(
+        &self,
+        batch: &[R],
+    ) -> Vec<Option<Vec<u8>>> {
+        let now_secs = self.options.clock.now_secs();
+        batch
+            .iter()
+            .map(|record| match record.borrow() {
+                WriteBatchRecord::PutWithTtl(_, value, ttl) => Some(ttl::encode_value_with_expiry(
+                    value.as_ref(),
+                    ttl::expiry_time(now_secs, *ttl),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The entries writing the records of `batch` at `ts`, with their flags, and the bytes of
+    /// user data they hold.
+    pub(crate) fn batch_entries<'a, T: AsRef<[u8]> + 'a, R: Borrow<WriteBatchRecord<T>>>(
+        &self,
+        batch: &'a [R],
+        expiring_values: &'a [Option<Vec<u8>>],
+        ts: u64,
+    ) -> Result<(Vec<BatchEntry<'a>>, usize)> {
+        let mut data = Vec::with_capacity(batch.len());
+        let mut bytes_written = 0;
+        for (record, expiring_value) in batch.iter().zip(expiring_values) {
+            match record.borrow() {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
-                    }
-                    self.try_freeze(size)?;
+                    bytes_written += key.len();
+                    data.push((
+                        KeySlice::from_slice(key, ts),
+                        b"".as_slice(),
+                        ENTRY_FLAG_TOMBSTONE,
+                    ));
                 }
                 WriteBatchRecord::Put(key, value) => {
                     let key = key.as_ref();
                     let value = value.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
+                    bytes_written += key.len() + value.len();
+                    data.push((KeySlice::from_slice(key, ts), value, 0));
+                }
+                WriteBatchRecord::Merge(key, operand) => {
+                    let key = key.as_ref();
+                    let operand = operand.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    assert!(!operand.is_empty(), "merge operand cannot be empty");
+                    if self.options.merge_operator.is_none() {
+                        bail!("merge operands cannot be written without a merge operator");
                     }
-                    self.try_freeze(size)?;
+                    bytes_written += key.len() + operand.len();
+                    data.push((
+                        KeySlice::from_slice(key, ts),
+                        operand,
+                        ENTRY_FLAG_MERGE_OPERAND,
+                    ));
+                }
+                WriteBatchRecord::PutWithTtl(key, value, _) => {
+                    let key = key.as_ref();
+                    let value = value.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    bytes_written += key.len() + value.len();
+                    let value = expiring_value.as_deref().unwrap();
+                    data.push((KeySlice::from_slice(key, ts), value, ENTRY_FLAG_TTL));
                 }
             }
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        Ok((data, bytes_written))
     }
 
+    /// Writes the records atomically, see [`Self::write_batch_inner`]. With serializable
+    /// transactions, the batch is committed as a transaction, which checks it for conflicts.
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
     ) -> Result<()> {
+        self.write_batch_with_options(batch, &WriteOptions::default())
+    }
+
+    /// Writes the records like [`Self::write_batch`], once any write stall is over, see
+    /// [`LsmStorageOptions::write_stall`].
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.wait_for_write_stall(options)?;
         if !self.options.serializable {
             self.write_batch_inner(batch)?;
+        } else if batch.iter().any(|record| match record {
+            WriteBatchRecord::Put(_, value) => value.as_ref().is_empty(),
+            WriteBatchRecord::Del(_) => false,
+            WriteBatchRecord::Merge(..) | WriteBatchRecord::PutWithTtl(..) => true,
+        }) {
+            // transactions only hold non-empty plain values, so empty values, operands and values
+            // with an expiry time are written like a compare-and-swap
+            let _commit_lock = self.mvcc().commit_lock.lock();
+            let write_lock = self.mvcc().lock_writes();
+            let ts = self.write_batch_with_lock(&write_lock, batch)?;
+            self.record_committed_keys(
+                batch.iter().map(|record| match record {
+                    WriteBatchRecord::Put(key, _)
+                    | WriteBatchRecord::Del(key)
+                    | WriteBatchRecord::Merge(key, _)
+                    | WriteBatchRecord::PutWithTtl(key, _, _) => key.as_ref(),
+                }),
+                ts,
+            );
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             for record in batch {
@@ -593,6 +2122,9 @@ impl LsmStorageInner {
                     WriteBatchRecord::Put(key, value) => {
                         txn.put(key.as_ref(), value.as_ref());
                     }
+                    WriteBatchRecord::Merge(..) | WriteBatchRecord::PutWithTtl(..) => {
+                        unreachable!()
+                    }
                 }
             }
             txn.commit()?;
@@ -602,36 +2134,153 @@ impl LsmStorageInner {
 
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(self: &Arc<Self>, key: &[u8], value: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.put(key, value);
-            txn.commit()?;
-        }
-        Ok(())
+        let span = trace_span!("put", key_len = key.len(), outcome = tracing::field::Empty);
+        let _entered = span.enter();
+        let result = self.write_batch(&[WriteBatchRecord::Put(key, value)]);
+        trace::record_outcome(&span, &result, "ok");
+        result
     }
 
-    /// Remove a key from the storage by writing an empty value.
+    /// Put a key-value pair like [`Self::put`] with `options`.
+    pub fn put_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_with_options(&[WriteBatchRecord::Put(key, value)], options)
+    }
+
+    /// Put a key-value pair that expires once `ttl` has passed, by the clock of the options. From
+    /// then on, reads treat it as deleted, and compactions drop it. A TTL of zero expires it at
+    /// once, and a later put without a TTL replaces it, along with its expiry time.
+    pub fn put_with_ttl(self: &Arc<Self>, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::PutWithTtl(key, value, ttl)])
+    }
+
+    /// Remove a key from the storage by writing a tombstone.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.delete(key);
-            txn.commit()?;
+        let span = trace_span!(
+            "delete",
+            key_len = key.len(),
+            outcome = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        let result = self.write_batch(&[WriteBatchRecord::Del(key)]);
+        trace::record_outcome(&span, &result, "ok");
+        result
+    }
+
+    /// Writes a merge operand for `key`, which reads combine with the value of the key through the
+    /// [`MergeOperator`] of the options, e.g. to increment a counter without reading it. Fails
+    /// if no merge operator is set.
+    pub fn merge(self: &Arc<Self>, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Merge(key, operand)])
+    }
+
+    /// Records a write of `keys` committed at `ts` outside of a transaction, so that the
+    /// transactions that read any of them before conflict with it.
+    pub(crate) fn record_committed_keys<'a>(&self, keys: impl Iterator<Item = &'a [u8]>, ts: u64) {
+        self.mvcc().committed_txns.lock().insert(
+            ts,
+            CommittedTxnData {
+                key_hashes: keys.map(farmhash::hash32).collect(),
+                read_ts: ts - 1,
+                commit_ts: ts,
+            },
+        );
+    }
+
+    /// Replaces the value of `key` by `new`, deleting the key if `new` is `None`, only if its
+    /// latest value is `expected`, `None` meaning that the key is absent. The value is read and
+    /// written with the write lock held, so no other write commits in between; the write goes
+    /// through the WAL and the memtable like [`Self::put`].
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<CasResult> {
+        // taken before the write lock, like transactions committing
+        let _commit_lock = self
+            .options
+            .serializable
+            .then(|| self.mvcc().commit_lock.lock());
+        let write_lock = self.mvcc().lock_writes();
+        self.check_open(&write_lock)?;
+        let current = self.get_with_ts(key, self.mvcc().latest_commit_ts())?;
+        if current.as_deref() != expected {
+            return Ok(CasResult::Mismatch { current });
         }
-        Ok(())
+        let record = match new {
+            Some(value) => WriteBatchRecord::Put(key, value),
+            None => WriteBatchRecord::Del(key),
+        };
+        let ts = self.write_batch_with_lock(&write_lock, &[record])?;
+        if self.options.serializable {
+            // transactions that read the key before the swap conflict with it
+            self.record_committed_keys(std::iter::once(key), ts);
+        }
+        Ok(CasResult::Swapped)
+    }
+
+    /// Removes the user keys from `start` (inclusive) to `end` (exclusive) by writing a single
+    /// range tombstone, regardless of how many keys the range holds. Reads skip the versions it
+    /// covers right away, and compaction drops them. Returns the commit timestamp.
+    ///
+    /// When the writes are sharded, the range may hold keys of any shard, so the tombstone is
+    /// written to the memtable of every shard. Each shard then flushes a copy of it no earlier
+    /// than the versions it covers in the shard, as compaction expects when it drops the
+    /// tombstone at the bottom level.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<u64> {
+        self.check_writable()?;
+        if start >= end {
+            bail!("the start of the range must be smaller than its end");
+        }
+        if self.options.serializable {
+            // the conflict check of transactions only tracks single keys
+            bail!("delete_range is not supported with serializable transactions");
+        }
+        let write_lock = self.mvcc().lock_writes();
+        self.check_open(&write_lock)?;
+        let ts = self.mvcc().latest_commit_ts() + 1;
+        let tombstone = RangeTombstone::new(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+            ts,
+        );
+        let sizes = self.write_to_memtables(|memtable| {
+            if let Some(keyspace) = self.logged_keyspace() {
+                keyspace
+                    .log
+                    .put_range_tombstone(&keyspace.name, &tombstone)?;
+            }
+            memtable.put_range_tombstone(tombstone.clone())?;
+            Ok(memtable.approximate_size())
+        })?;
+        self.mvcc().update_commit_ts(ts);
+        for (shard, size) in sizes.into_iter().enumerate() {
+            self.try_freeze(shard, size)?;
+        }
+        Ok(ts)
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
-        if estimated_size >= self.options.target_sst_size {
+    /// Freezes the current memtable of `shard` if it reached the target SST size, estimated as
+    /// `estimated_size` by the write to it.
+    pub(crate) fn try_freeze(&self, shard: usize, estimated_size: usize) -> Result<()> {
+        let target_sst_size = self.dynamic_options.load().target_sst_size;
+        if estimated_size >= target_sst_size {
             let state_lock = self.state_lock.lock();
-            let guard = self.state.read();
+            let guard = self.state.load();
             // the memtable could have already been frozen, check again to ensure we really need to freeze
-            if guard.memtable.approximate_size() >= self.options.target_sst_size {
+            if guard.current_memtable(shard).approximate_size() >= target_sst_size {
                 drop(guard);
-                self.force_freeze_memtable(&state_lock)?;
+                self.freeze_shard_memtable(&state_lock, shard)?;
+                drop(state_lock);
+                let num_imm_memtables = self.state.load().imm_memtables.len();
+                if num_imm_memtables >= self.options.num_memtable_limit {
+                    trace::write_stall(num_imm_memtables, self.options.num_memtable_limit);
+                }
             }
         }
         Ok(())
@@ -645,6 +2294,17 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    /// The path to write the new SST `id` to. SST files are never rewritten once written, which
+    /// backups rely on to link the files they already hold, see
+    /// [`crate::backup::CheckpointManifest`], so a file already there fails the write.
+    pub(crate) fn path_of_new_sst(&self, id: usize) -> Result<PathBuf> {
+        let path = self.path_of_sst(id);
+        if path.exists() {
+            bail!("SST {} already exists and cannot be rewritten", id);
+        }
+        Ok(path)
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -658,40 +2318,155 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn freeze_memtable_with_memtable(&self, memtable: Arc<MemTable>) -> Result<()> {
-        let mut guard = self.state.write();
+    /// Removes the SST files in `path` that the recovered `state` does not reference: the outputs
+    /// of flushes and compactions whose manifest record was never written, and the inputs of
+    /// compactions that were not deleted yet when the engine stopped.
+    fn remove_orphan_ssts(path: &Path, state: &LsmStorageState) -> Result<()> {
+        let mut removed = 0;
+        for entry in std::fs::read_dir(path)? {
+            let file_name = entry?.file_name();
+            let Some(id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".sst"))
+                .and_then(|id| id.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if !state.sstables.contains_key(&id) {
+                std::fs::remove_file(Self::path_of_sst_static(path, id))?;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            File::open(path)?.sync_all()?;
+            println!("{} orphan SSTs removed", removed);
+        }
+        Ok(())
+    }
+
+    /// Deletes the files of `ssts`, which are no longer part of the state, together with those of
+    /// SSTs deferred by previous calls. The files of SSTs still read by an iterator or a snapshot
+    /// of the state are kept until a later call after they are released.
+    pub(crate) fn delete_obsolete_ssts(&self, ssts: Vec<Arc<SsTable>>) -> Result<()> {
+        let unpinned = {
+            let mut obsolete = self.obsolete_ssts.lock();
+            obsolete.extend(ssts);
+            let (pinned, unpinned): (Vec<_>, Vec<_>) = std::mem::take(&mut *obsolete)
+                .into_iter()
+                .partition(|sst| Arc::strong_count(sst) > 1);
+            *obsolete = pinned;
+            unpinned
+        };
+        if unpinned.is_empty() {
+            return Ok(());
+        }
+        for sst in unpinned {
+            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+        }
+        self.sync_dir()
+    }
+
+    /// Fails if a test armed the failpoint `name`, which is disarmed as it fires. Used to stop at
+    /// the boundaries between the steps of an operation, as a crash would.
+    pub(crate) fn failpoint(&self, name: &'static str) -> Result<()> {
+        #[cfg(test)]
+        if self.failpoints.lock().remove(name) {
+            bail!("failpoint {} fired", name);
+        }
+        #[cfg(not(test))]
+        let _ = name;
+        Ok(())
+    }
+
+    /// Writes to the current memtable of each shard with `f`, from shard 0. A freeze waits for
+    /// the writes in progress, and the writes after it go to the new memtable.
+    pub(crate) fn write_to_memtables<T>(
+        &self,
+        f: impl FnMut(&Arc<MemTable>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let _gate = self.memtable_gate.read();
+        self.state.load().current_memtables().map(f).collect()
+    }
+
+    fn freeze_memtable_with_memtable(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        shard: usize,
+        memtable: Arc<MemTable>,
+    ) -> Result<()> {
+        let gate = self.memtable_gate.write();
+        if let Some(keyspace) = &self.keyspace {
+            // the writes to the new memtable start at a new segment of the shared log, recorded
+            // before any write can reach it
+            let segment = keyspace.log.rotate(&keyspace.name)?;
+            self.manifest().add_record(
+                state_lock_observer,
+                ManifestRecord::NewKeyspaceMemtable(memtable.id(), segment),
+            )?;
+        }
         // Swap the current memtable with a new one.
-        let mut snapshot = guard.as_ref().clone();
-        let old_memtable = std::mem::replace(&mut snapshot.memtable, memtable);
+        let mut snapshot = self.state.load().as_ref().clone();
+        let old_memtable = std::mem::replace(snapshot.current_memtable_mut(shard), memtable);
         // Add the memtable to the immutable memtables.
         snapshot.imm_memtables.insert(0, old_memtable.clone());
+        self.metrics
+            .record_write_stall(snapshot.imm_memtables.len() >= self.options.num_memtable_limit);
         // Update the snapshot.
-        *guard = Arc::new(snapshot);
+        self.state.store(Arc::new(snapshot));
 
-        drop(guard);
+        drop(gate);
         old_memtable.sync_wal()?;
 
         Ok(())
     }
 
-    /// Force freeze the current memtable to an immutable memtable
+    /// Force freeze the current memtable to an immutable memtable. When the writes are sharded,
+    /// the current memtables of all the shards written to since their last freeze are frozen.
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
-        let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
-        } else {
-            Arc::new(MemTable::create(memtable_id))
-        };
-
-        self.freeze_memtable_with_memtable(memtable)?;
+        let shards = self
+            .state
+            .load()
+            .current_memtables()
+            .enumerate()
+            .filter(|(_, memtable)| !memtable.is_empty())
+            .map(|(shard, _)| shard)
+            .collect::<Vec<_>>();
+        if shards.is_empty() {
+            return self.freeze_shard_memtable(state_lock_observer, 0);
+        }
+        for shard in shards {
+            self.freeze_shard_memtable(state_lock_observer, shard)?;
+        }
+        Ok(())
+    }
 
-        self.manifest().add_record(
-            state_lock_observer,
-            ManifestRecord::NewMemtable(memtable_id),
+    /// Freezes the current memtable of `shard`. The new memtable is recorded in the manifest
+    /// before any write can reach it, so that a memtable recovery does not find is known to be
+    /// flushed, which the recovery of the batches spanning several shards relies on.
+    fn freeze_shard_memtable(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        shard: usize,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let memtable_id = self.next_sst_id();
+        // the memtables of a keyspace log to the log shared by the keyspaces
+        let memtable = Self::create_memtable(
+            &self.path,
+            &self.options,
+            memtable_id,
+            shard,
+            self.options.enable_wal && self.keyspace.is_none(),
         )?;
+
+        // that of a keyspace is recorded as it is swapped in
+        if self.keyspace.is_none() {
+            self.manifest().add_record(
+                state_lock_observer,
+                ManifestRecord::NewMemtable(memtable_id),
+            )?;
+        }
+        self.freeze_memtable_with_memtable(state_lock_observer, shard, Arc::new(memtable))?;
         self.sync_dir()?;
 
         Ok(())
@@ -699,61 +2474,130 @@ impl LsmStorageInner {
 
     /// Force flush the earliest-created immutable memtable to disk
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
-        let state_lock = self.state_lock.lock();
+        self.check_writable()?;
+        let _flush_lock = self.flush_lock.lock();
 
         let flush_memtable;
 
         {
-            let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
+            let guard = self.state.load();
+            // the flush thread may have flushed the last one before we got the flush lock
+            let Some(memtable) = guard.imm_memtables.last() else {
+                return Ok(());
+            };
+            flush_memtable = memtable.clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
+        let flush_info = FlushJobInfo {
+            sst_id: flush_memtable.id(),
+            memtable_size: flush_memtable.approximate_size(),
+        };
+        self.notify_listeners(|listener| listener.on_flush_begin(&flush_info));
+        let span = trace_span!(
+            "flush",
+            sst_id = flush_info.sst_id,
+            input_bytes = flush_info.memtable_size,
+            output_bytes = tracing::field::Empty
+        );
+        let _entered = span.enter();
+
+        let state_lock = self.state_lock.lock();
+        let begin = Instant::now();
+        let mut builder = if flush_memtable.has_entry_flags() {
+            SsTableBuilder::new_with_format_version(self.options.block_size, BLOCK_FORMAT_V6)
+        } else {
+            SsTableBuilder::new(self.options.block_size)
+        };
+        builder.set_creation_time(self.options.clock.now_secs());
+        builder.set_rate_limiter(self.rate_limiter.clone(), IoPriority::High);
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+        let mut sst = builder
+            .build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_new_sst(sst_id)?,
+            )?
+            .with_cache_namespace(self.cache_namespace)
+            .with_metrics(self.metrics.clone());
+        if let Some(shard) = flush_memtable.shard() {
+            sst = sst.with_shard(shard);
+        }
+        let sst = Arc::new(sst);
 
         // Add the flushed L0 table to the list.
         {
-            let mut guard = self.state.write();
-            let mut snapshot = guard.as_ref().clone();
+            let mut snapshot = self.state.load().as_ref().clone();
             // Remove the memtable from the immutable memtables.
             let mem = snapshot.imm_memtables.pop().unwrap();
             assert_eq!(mem.id(), sst_id);
             // Add L0 table
-            if self.compaction_controller.flush_to_l0() {
+            if self.compaction_controller.load().flush_to_l0() {
                 // In leveled compaction or no compaction, simply flush to L0
                 snapshot.l0_sstables.insert(0, sst_id);
             } else {
                 // In tiered compaction, create a new tier
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
+            self.metrics.record_write_stall(
+                snapshot.imm_memtables.len() >= self.options.num_memtable_limit,
+            );
             println!("flushed {}.sst with size={}", sst_id, sst.table_size());
-            snapshot.sstables.insert(sst_id, sst);
+            snapshot.sstables.insert(sst_id, sst.clone());
             // Update the snapshot.
-            *guard = Arc::new(snapshot);
+            self.state.store(Arc::new(snapshot));
         }
 
-        if self.options.enable_wal {
+        if self.options.enable_wal && self.keyspace.is_none() {
             std::fs::remove_file(self.path_of_wal(sst_id))?;
         }
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        let record = match flush_memtable.shard() {
+            Some(shard) => ManifestRecord::FlushShard(sst_id, shard, self.options.num_shards),
+            None => ManifestRecord::Flush(sst_id),
+        };
+        self.manifest().add_record(&state_lock, record)?;
+        if let Some(keyspace) = &self.keyspace {
+            keyspace.log.memtable_flushed(&keyspace.name)?;
+        }
 
         self.sync_dir()?;
+        drop(state_lock);
+        self.write_controller.notify_progress();
+        span.record("output_bytes", sst.table_size());
+
+        let entries = sst.properties().num_entries;
+        let job_stats = BackgroundJobStats {
+            kind: BackgroundJobKind::Flush,
+            input_levels: Vec::new(),
+            output_level: 0,
+            input_files: 0,
+            input_bytes: flush_memtable.approximate_size() as u64,
+            output_files: 1,
+            output_bytes: sst.table_size(),
+            entries_read: entries,
+            entries_written: entries,
+            entries_dropped: 0,
+            duration: begin.elapsed(),
+        };
+        self.stats.record_job(job_stats.clone());
+        self.metrics.record_job(&job_stats);
+        self.notify_listeners(|listener| listener.on_flush_complete(&flush_info, &job_stats));
 
         Ok(())
     }
 
+    /// Calls `f` on every event listener. Must not be called with `state_lock` held.
+    pub(crate) fn notify_listeners(&self, f: impl Fn(&dyn EventListener)) {
+        for listener in &self.options.event_listeners {
+            f(listener.as_ref());
+        }
+    }
+
+    pub(crate) fn next_compaction_job_id(&self) -> u64 {
+        self.next_compaction_job_id.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
         Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
     }
@@ -764,8 +2608,47 @@ impl LsmStorageInner {
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<TxnIterator> {
+        let span = trace_span!(
+            "scan",
+            lower_key_len = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => key.len(),
+                Bound::Unbounded => 0,
+            },
+            outcome = tracing::field::Empty
+        );
+        let _entered = span.enter();
         let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.scan(lower, upper)
+        let result = txn.scan(lower, upper);
+        trace::record_outcome(&span, &result, "ok");
+        result
+    }
+
+    /// Create an iterator over a range of keys in descending order.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnRevIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan_rev(lower, upper)
+    }
+
+    /// Create an iterator over a range of keys like [`Self::scan`], reading as told by
+    /// `options`. The scan is not part of a transaction.
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let read_ts = options
+            .read_ts
+            .unwrap_or_else(|| self.mvcc().latest_commit_ts());
+        let read_options = options.block_read_options(self.options.scan_cache_hint);
+        let timer = self.metrics.start_timer();
+        let iter = self.scan_with_ts_inner(lower, upper, read_ts, read_options)?;
+        self.metrics.record_scan(timer);
+        Ok(iter)
     }
 
     pub(crate) fn scan_with_ts(
@@ -774,21 +2657,43 @@ impl LsmStorageInner {
         upper: Bound<&[u8]>,
         read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
-        let snapshot = {
-            let guard = self.state.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        let timer = self.metrics.start_timer();
+        let iter =
+            self.scan_with_ts_inner(lower, upper, read_ts, self.options.scan_cache_hint.into())?;
+        self.metrics.record_scan(timer);
+        Ok(iter)
+    }
+
+    fn scan_with_ts_inner(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+        read_options: BlockReadOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let snapshot = self.state.load_full();
+
+        if is_empty_range(lower, upper) {
+            let iter = TwoMergeIterator::create(
+                MergeIterator::create(Vec::new()),
+                MergeIterator::create(Vec::new()),
+            )?;
+            let iter = TwoMergeIterator::create(iter, MergeIterator::create(Vec::new()))?;
+            return Ok(FusedIterator::new(LsmIterator::new(
+                iter,
+                Bound::Unbounded,
+                read_ts,
+                self.options.clock.now_secs(),
+                FragmentedRangeTombstones::default(),
+                None,
+                None,
+            )?));
+        }
 
+        let (key_lower, key_upper) = map_user_key_range(lower, upper);
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-            map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-                map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-            )));
+        for memtable in snapshot.memtables() {
+            memtable_iters.push(Box::new(memtable.scan(key_lower, key_upper)));
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
@@ -802,21 +2707,30 @@ impl LsmStorageInner {
                 table.last_key().as_key_slice(),
             ) {
                 let iter = match lower {
-                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
-                        table,
-                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                    )?,
+                    Bound::Included(key) => {
+                        SsTableIterator::create_and_seek_to_key_with_read_options(
+                            table,
+                            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                            read_options,
+                        )?
+                    }
                     Bound::Excluded(key) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
+                        let mut iter = SsTableIterator::create_and_seek_to_key_with_read_options(
                             table,
                             KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                            read_options,
                         )?;
                         while iter.is_valid() && iter.key().key_ref() == key {
                             iter.next()?;
                         }
                         iter
                     }
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
+                    Bound::Unbounded => {
+                        SsTableIterator::create_and_seek_to_first_with_read_options(
+                            table,
+                            read_options,
+                        )?
+                    }
                 };
 
                 table_iters.push(Box::new(iter));
@@ -840,21 +2754,28 @@ impl LsmStorageInner {
             }
 
             let level_iter = match lower {
-                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
-                    level_ssts,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?,
+                Bound::Included(key) => {
+                    SstConcatIterator::create_and_seek_to_key_with_read_options(
+                        level_ssts,
+                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        read_options,
+                    )?
+                }
                 Bound::Excluded(key) => {
-                    let mut iter = SstConcatIterator::create_and_seek_to_key(
+                    let mut iter = SstConcatIterator::create_and_seek_to_key_with_read_options(
                         level_ssts,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        read_options,
                     )?;
                     while iter.is_valid() && iter.key().key_ref() == key {
                         iter.next()?;
                     }
                     iter
                 }
-                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first_with_read_options(
+                    level_ssts,
+                    read_options,
+                )?,
             };
             level_iters.push(Box::new(level_iter));
         }
@@ -866,6 +2787,103 @@ impl LsmStorageInner {
             iter,
             map_bound(upper),
             read_ts,
+            self.options.clock.now_secs(),
+            snapshot.range_tombstones(lower, upper, read_ts),
+            self.options.merge_operator.clone(),
+            self.tombstone_reports.clone(),
+        )?))
+    }
+
+    /// Like [`Self::scan_with_ts`], but every iterator is created in descending order, seeking to
+    /// the upper bound instead of the lower one.
+    pub(crate) fn scan_rev_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmRevIterator>> {
+        let snapshot = self.state.load_full();
+        let empty_range = is_empty_range(lower, upper);
+
+        let (key_lower, key_upper) = map_user_key_range(lower, upper);
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        if !empty_range {
+            for memtable in snapshot.memtables() {
+                memtable_iters.push(Box::new(memtable.scan_rev(key_lower, key_upper)));
+            }
+            for table_id in snapshot.l0_sstables.iter() {
+                let table = snapshot.sstables[table_id].clone();
+                if range_overlap(
+                    lower,
+                    upper,
+                    table.first_key().as_key_slice(),
+                    table.last_key().as_key_slice(),
+                ) {
+                    // every version of an excluded upper bound is after its key at
+                    // `TS_RANGE_BEGIN`, and every version of an included one is before its key at
+                    // `TS_RANGE_END`
+                    let iter = match upper {
+                        Bound::Included(key) => SsTableIterator::create_and_seek_to_key_rev(
+                            table,
+                            KeySlice::from_slice(key, key::TS_RANGE_END),
+                        )?,
+                        Bound::Excluded(key) => SsTableIterator::create_and_seek_to_key_rev(
+                            table,
+                            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        )?,
+                        Bound::Unbounded => SsTableIterator::create_and_seek_to_last(table)?,
+                    };
+                    table_iters.push(Box::new(iter));
+                }
+            }
+            for (_, level_sst_ids) in &snapshot.levels {
+                let level_ssts = level_sst_ids
+                    .iter()
+                    .map(|id| snapshot.sstables[id].clone())
+                    .filter(|table| {
+                        range_overlap(
+                            lower,
+                            upper,
+                            table.first_key().as_key_slice(),
+                            table.last_key().as_key_slice(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                let level_iter = match upper {
+                    Bound::Included(key) => SstConcatIterator::create_and_seek_to_key_rev(
+                        level_ssts,
+                        KeySlice::from_slice(key, key::TS_RANGE_END),
+                    )?,
+                    Bound::Excluded(key) => SstConcatIterator::create_and_seek_to_key_rev(
+                        level_ssts,
+                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    )?,
+                    Bound::Unbounded => SstConcatIterator::create_and_seek_to_last(level_ssts)?,
+                };
+                level_iters.push(Box::new(level_iter));
+            }
+        }
+
+        // newer data still takes precedence for the same key: memtables over L0 over levels
+        let iter = TwoMergeIterator::create_rev(
+            MergeIterator::create_rev(memtable_iters),
+            MergeIterator::create_rev(table_iters),
+        )?;
+        let iter = TwoMergeIterator::create_rev(iter, MergeIterator::create_rev(level_iters))?;
+        let range_tombstones = if empty_range {
+            FragmentedRangeTombstones::default()
+        } else {
+            snapshot.range_tombstones(lower, upper, read_ts)
+        };
+        Ok(FusedIterator::new(LsmRevIterator::new(
+            iter,
+            map_bound(lower),
+            read_ts,
+            self.options.clock.now_secs(),
+            range_tombstones,
+            self.options.merge_operator.clone(),
         )?))
     }
 }
@@ -12,6 +12,9 @@ use crate::compact::CompactionTask;
 
 pub struct Manifest {
     file: Arc<Mutex<File>>,
+    /// The keyspace whose records this handle adds, tagged with it, see
+    /// [`Self::for_keyspace`].
+    keyspace: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -19,6 +22,26 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// An SST ingested from outside of the engine, with the level and the index in the level it
+    /// was installed at, see `LsmStorageInner::ingest_sst`.
+    Ingest(usize, usize, usize),
+    /// SSTs ingested at once, each like [`Self::Ingest`], in the order they were installed, see
+    /// `LsmStorageInner::ingest_ssts`.
+    IngestBatch(Vec<(usize, usize, usize)>),
+    /// A flush like [`Self::Flush`] of the memtable of a shard, with the shard and the number of
+    /// shards, so that the SST keeps its shard tag once reopened with as many shards, see
+    /// `LsmStorageOptions::num_shards`.
+    FlushShard(usize, usize, usize),
+    /// The batch across shards with this sequence number was dropped by a recovery, as one of
+    /// its parts was missing. Its other parts stay dropped when recovered again.
+    DropBatch(u64),
+    /// A keyspace created in the storage, see `MiniLsm::open_with_keyspaces`.
+    NewKeyspace(String),
+    /// A record of the keyspace of this name, applied to the state of the keyspace only.
+    Keyspace(String, Box<ManifestRecord>),
+    /// A memtable of a keyspace, like [`Self::NewMemtable`], with the segment of the log shared
+    /// by the keyspaces its writes start at, see `KeyspaceLog`.
+    NewKeyspaceMemtable(usize, usize),
 }
 
 impl Manifest {
@@ -32,6 +55,7 @@ impl Manifest {
                     .open(path)
                     .context("failed to create manifest")?,
             )),
+            keyspace: None,
         })
     }
 
@@ -43,27 +67,47 @@ impl Manifest {
             .context("failed to recover manifest")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut buf_ptr = buf.as_slice();
-        let mut records = Vec::new();
-        while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
-            let checksum = buf_ptr.get_u32();
-            if checksum != crc32fast::hash(slice) {
-                bail!("checksum mismatched!");
-            }
-            records.push(json);
+        let (records, decoded_len) = decode_records(&buf)?;
+        if decoded_len != buf.len() {
+            bail!("incomplete manifest record");
         }
         Ok((
             Self {
                 file: Arc::new(Mutex::new(file)),
+                keyspace: None,
             },
             records,
         ))
     }
 
+    /// Reads the records of the manifest without opening it for writing, e.g. while the engine
+    /// owning it appends to it. A record cut short at the end of the file, which is still being
+    /// written, is skipped.
+    pub fn read_records(path: impl AsRef<Path>) -> Result<Vec<ManifestRecord>> {
+        let buf = std::fs::read(path).context("failed to read manifest")?;
+        Ok(decode_records(&buf)?.0)
+    }
+
+    /// Reads the records of the manifest, failing unless every record is complete and matches
+    /// its checksum, e.g. to validate a manifest restored from a backup.
+    pub fn verify_records(path: impl AsRef<Path>) -> Result<Vec<ManifestRecord>> {
+        let buf = std::fs::read(path).context("failed to read manifest")?;
+        let (records, len) = decode_records(&buf)?;
+        if len != buf.len() {
+            bail!("manifest ends with an incomplete record");
+        }
+        Ok(records)
+    }
+
+    /// A handle to the same manifest adding the records of the keyspace `name`, each wrapped in
+    /// a [`ManifestRecord::Keyspace`].
+    pub fn for_keyspace(&self, name: &str) -> Self {
+        Self {
+            file: self.file.clone(),
+            keyspace: Some(name.to_string()),
+        }
+    }
+
     pub fn add_record(
         &self,
         _state_lock_observer: &MutexGuard<()>,
@@ -72,7 +116,18 @@ impl Manifest {
         self.add_record_when_init(record)
     }
 
+    /// Syncs the manifest file. Records are synced as they are added, so this only makes sure of
+    /// it, e.g. when closing the storage.
+    pub fn sync(&self) -> Result<()> {
+        self.file.lock().sync_all()?;
+        Ok(())
+    }
+
     pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
+        let record = match &self.keyspace {
+            Some(name) => ManifestRecord::Keyspace(name.clone(), Box::new(record)),
+            None => record,
+        };
         let mut file = self.file.lock();
         let mut buf = serde_json::to_vec(&record)?;
         let hash = crc32fast::hash(&buf);
@@ -83,3 +138,26 @@ impl Manifest {
         Ok(())
     }
 }
+
+/// Decodes the records in `buf` up to the first incomplete one, if any. Returns the records and
+/// the number of bytes they take.
+fn decode_records(buf: &[u8]) -> Result<(Vec<ManifestRecord>, usize)> {
+    let mut buf_ptr = buf;
+    let mut records = Vec::new();
+    while buf_ptr.remaining() >= std::mem::size_of::<u64>() {
+        let len = (&buf_ptr[..]).get_u64() as usize;
+        if buf_ptr.remaining() < std::mem::size_of::<u64>() + len + std::mem::size_of::<u32>() {
+            break;
+        }
+        buf_ptr.advance(std::mem::size_of::<u64>());
+        let slice = &buf_ptr[..len];
+        let json = serde_json::from_slice::<ManifestRecord>(slice)?;
+        buf_ptr.advance(len);
+        let checksum = buf_ptr.get_u32();
+        if checksum != crc32fast::hash(slice) {
+            bail!("checksum mismatched!");
+        }
+        records.push(json);
+    }
+    Ok((records, buf.len() - buf_ptr.remaining()))
+}
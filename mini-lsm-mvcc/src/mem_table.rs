@@ -8,11 +8,14 @@ use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
+use crate::block::{implied_entry_flags, ENTRY_FLAG_TOMBSTONE};
 use crate::iterators::StorageIterator;
-use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
+use crate::key::{KeyBytes, KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalBatchPart};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
@@ -20,9 +23,16 @@ use crate::wal::Wal;
 /// chapters of week 1 and week 2.
 pub struct MemTable {
     pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    /// The flags of the entries having any, e.g. merge operands, kept apart from `map` as most
+    /// entries have none.
+    entry_flags: Arc<SkipMap<KeyBytes, u8>>,
+    /// Range tombstones are kept apart from the point entries, in the order they were written.
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    /// The shard whose keys the mem-table holds, see [`Self::with_shard`].
+    shard: Option<usize>,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -58,14 +68,37 @@ pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySl
     }
 }
 
+/// Map a range of user keys to the range of keys covering all of their versions: an excluded lower
+/// bound skips past the oldest version of the key, and an excluded upper bound stops before the
+/// newest one.
+pub(crate) fn map_user_key_range<'a>(
+    lower: Bound<&'a [u8]>,
+    upper: Bound<&'a [u8]>,
+) -> (Bound<KeySlice<'a>>, Bound<KeySlice<'a>>) {
+    let lower = match lower {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_RANGE_BEGIN)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_RANGE_END)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let upper = match upper {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_RANGE_END)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_RANGE_BEGIN)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (lower, upper)
+}
+
 impl MemTable {
     /// Create a new mem-table.
     pub fn create(id: usize) -> Self {
         Self {
             id,
             map: Arc::new(SkipMap::new()),
+            entry_flags: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            shard: None,
         }
     }
 
@@ -74,22 +107,83 @@ impl MemTable {
         Ok(Self {
             id,
             map: Arc::new(SkipMap::new()),
+            entry_flags: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
             wal: Some(Wal::create(path.as_ref())?),
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            shard: None,
+        })
+    }
+
+    /// Create a memtable from WAL. The parts of batches across shards found in the WAL are not
+    /// applied but collected into `batch_parts`, see [`Self::apply_batch_part`].
+    pub fn recover_from_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        batch_parts: &mut Vec<WalBatchPart>,
+    ) -> Result<Self> {
+        let map = Arc::new(SkipMap::new());
+        let entry_flags = Arc::new(SkipMap::new());
+        let mut range_tombstones = Vec::new();
+        Ok(Self {
+            id,
+            wal: Some(Wal::recover(
+                path.as_ref(),
+                &map,
+                &entry_flags,
+                &mut range_tombstones,
+                batch_parts,
+            )?),
+            map,
+            entry_flags,
+            range_tombstones: RwLock::new(range_tombstones),
+            approximate_size: Arc::new(AtomicUsize::new(0)),
+            shard: None,
         })
     }
 
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+    /// Create a memtable from a WAL another engine may still append to, see [`Wal::replay`]. The
+    /// memtable has no WAL of its own. The batch parts are collected like
+    /// [`Self::recover_from_wal`] does.
+    pub fn replay_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        batch_parts: &mut Vec<WalBatchPart>,
+    ) -> Result<Self> {
         let map = Arc::new(SkipMap::new());
+        let entry_flags = Arc::new(SkipMap::new());
+        let mut range_tombstones = Vec::new();
+        Wal::replay(
+            path.as_ref(),
+            &map,
+            &entry_flags,
+            &mut range_tombstones,
+            batch_parts,
+        )?;
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
+            wal: None,
             map,
+            entry_flags,
+            range_tombstones: RwLock::new(range_tombstones),
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            shard: None,
         })
     }
 
+    /// Tags the mem-table as holding only the keys of `shard`, so that point reads of the other
+    /// shards skip it, see [`crate::lsm_storage::LsmStorageOptions::num_shards`].
+    pub fn with_shard(mut self, shard: usize) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// The shard whose keys the mem-table holds, or `None` if it may hold keys of any shard, as
+    /// do the mem-tables of an unsharded storage and those recovered from their WALs.
+    pub fn shard(&self) -> Option<usize> {
+        self.shard
+    }
+
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
@@ -123,19 +217,99 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let estimated_size = key.raw_len() + value.len();
-        self.map.insert(
-            key.to_key_vec().into_key_bytes(),
-            Bytes::copy_from_slice(value),
-        );
+        self.put_batch(&[(key, value)])
+    }
+
+    /// Put a batch of key-value pairs into the mem-table, logged to the WAL as a single record
+    /// before any of them is inserted. An empty value is a tombstone.
+    pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        self.put_batch_with_flags(
+            data.iter()
+                .map(|(key, value)| (*key, *value, implied_entry_flags(value))),
+        )
+    }
+
+    /// Put a batch of entries with their flags, e.g. [`crate::block::ENTRY_FLAG_MERGE_OPERAND`],
+    /// into the mem-table, like [`Self::put_batch`]. An empty value without
+    /// [`ENTRY_FLAG_TOMBSTONE`] is stored as an empty value.
+    pub fn put_batch_with_flags<'a>(
+        &self,
+        data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)> + Clone,
+    ) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_batch_with_flags(data.clone())?;
+        }
+        self.insert_entries(data);
+        Ok(())
+    }
+
+    /// Put the part of the batch `seq` across shards going to this mem-table, logged to the WAL
+    /// as a [`WalBatchPart`] naming the mem-tables of all the parts, see
+    /// [`Wal::put_batch_part`].
+    pub fn put_batch_part<'a>(
+        &self,
+        seq: u64,
+        memtable_ids: &[usize],
+        data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)> + Clone,
+    ) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_batch_part(seq, memtable_ids, data.clone())?;
+        }
+        self.insert_entries(data);
+        Ok(())
+    }
+
+    /// Applies a batch part recovered from the WAL of this mem-table, once all the parts of its
+    /// batch are known to be durable. It is already in the WAL, so it is not logged again.
+    pub fn apply_batch_part(&self, part: &WalBatchPart) {
+        for (key, value, flags) in &part.entries {
+            if let Some(flags) = flags {
+                self.entry_flags.insert(key.clone(), *flags);
+            }
+            self.map.insert(key.clone(), value.clone());
+        }
+    }
+
+    fn insert_entries<'a>(&self, data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)>) {
+        let mut estimated_size = 0;
+        for (key, value, flags) in data {
+            estimated_size += key.raw_len() + value.len();
+            let key = key.to_key_vec().into_key_bytes();
+            // set before the entry is visible, so that readers never miss them
+            if flags != implied_entry_flags(value) {
+                self.entry_flags.insert(key.clone(), flags);
+            }
+            self.map.insert(key, Bytes::copy_from_slice(value));
+        }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Put a range tombstone into the mem-table, logged to the WAL first like the entries.
+    pub fn put_range_tombstone(&self, tombstone: RangeTombstone) -> Result<()> {
         if let Some(ref wal) = self.wal {
-            wal.put(key, value)?;
+            wal.put_range_tombstone(&tombstone)?;
         }
+        self.approximate_size.fetch_add(
+            tombstone.encoded_len(),
+            std::sync::atomic::Ordering::Relaxed,
+        );
+        self.range_tombstones.write().push(tombstone);
         Ok(())
     }
 
+    /// The range tombstones in the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
+    }
+
+    /// The newest timestamp of the entries and range tombstones in the mem-table.
+    pub fn max_ts(&self) -> u64 {
+        let max_entry_ts = self.map.iter().map(|x| x.key().ts()).max();
+        let max_tombstone_ts = self.range_tombstones.read().iter().map(|x| x.ts).max();
+        max_entry_ts.max(max_tombstone_ts).unwrap_or_default()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -145,22 +319,41 @@ impl MemTable {
 
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        self.scan_inner(lower, upper, false)
+    }
+
+    /// Get an iterator over a range of keys in descending order.
+    pub fn scan_rev(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
+        self.scan_inner(lower, upper, true)
+    }
+
+    fn scan_inner(
+        &self,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+        reverse: bool,
+    ) -> MemTableIterator {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
+            entry_flags: self.entry_flags.clone(),
             iter_builder: |map| map.range((lower, upper)),
             item: (KeyBytes::new(), Bytes::new()),
+            reverse,
         }
         .build();
-        let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        iter.with_mut(|x| *x.item = entry);
+        iter.next().unwrap();
         iter
     }
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+            let flags = entry_flags_of(&self.entry_flags, entry.key(), entry.value());
+            builder.add_with_flags(entry.key().as_key_slice(), &entry.value()[..], flags);
+        }
+        for tombstone in self.range_tombstones.read().iter() {
+            builder.add_range_tombstone(tombstone.clone());
         }
         Ok(())
     }
@@ -169,17 +362,49 @@ impl MemTable {
         self.id
     }
 
+    /// Whether any entry has flags other than those implied by its value, in which case the
+    /// mem-table must be flushed in a block format storing them.
+    pub fn has_entry_flags(&self) -> bool {
+        !self.entry_flags.is_empty()
+    }
+
     pub fn approximate_size(&self) -> usize {
         self.approximate_size
             .load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Number of entries, including tombstones and all versions.
+    pub fn num_entries(&self) -> usize {
+        self.map.len()
+    }
+
+    /// The size of the entries with user keys in the range, counted like
+    /// [`Self::approximate_size`], i.e. the keys with their timestamps and the values.
+    pub fn approximate_bytes_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> usize {
+        let (lower, upper) = map_user_key_range(lower, upper);
+        self.map
+            .range((map_key_bound(lower), map_key_bound(upper)))
+            .map(|entry| entry.key().raw_len() + entry.value().len())
+            .sum()
+    }
+
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
     }
 }
 
+/// The flags of an entry, kept in `entry_flags` only if they differ from those implied by its
+/// value.
+fn entry_flags_of(entry_flags: &SkipMap<KeyBytes, u8>, key: &KeyBytes, value: &[u8]) -> u8 {
+    if entry_flags.is_empty() {
+        return implied_entry_flags(value);
+    }
+    entry_flags
+        .get(key)
+        .map_or_else(|| implied_entry_flags(value), |entry| *entry.value())
+}
+
 type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
     'a,
     KeyBytes,
@@ -196,12 +421,16 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
 pub struct MemTableIterator {
     /// Stores a reference to the skipmap.
     map: Arc<SkipMap<KeyBytes, Bytes>>,
+    /// The flags of the entries of `map` having any.
+    entry_flags: Arc<SkipMap<KeyBytes, u8>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (KeyBytes, Bytes),
+    /// Whether the range is iterated in descending order.
+    reverse: bool,
 }
 
 impl MemTableIterator {
@@ -219,6 +448,22 @@ impl StorageIterator for MemTableIterator {
         &self.borrow_item().1[..]
     }
 
+    fn value_bytes(&self) -> Bytes {
+        self.borrow_item().1.clone()
+    }
+
+    fn flags(&self) -> u8 {
+        entry_flags_of(
+            self.borrow_entry_flags(),
+            &self.borrow_item().0,
+            &self.borrow_item().1,
+        )
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.flags() & ENTRY_FLAG_TOMBSTONE != 0
+    }
+
     fn key(&self) -> KeySlice {
         self.borrow_item().0.as_key_slice()
     }
@@ -228,7 +473,11 @@ impl StorageIterator for MemTableIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
+        let entry = if *self.borrow_reverse() {
+            self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next_back()))
+        } else {
+            self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()))
+        };
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+/// Combines merge operands, written by [`crate::lsm_storage::LsmStorageInner::merge`], with the
+/// value of their key, e.g. to increment a counter or append to a list without reading it first.
+///
+/// Reads combine the newest value of a key (`None` if the key is absent or deleted) with the
+/// operands written after it through [`Self::full_merge`]. Compaction does the same once it reaches
+/// the value, and otherwise combines consecutive operands through [`Self::partial_merge`] to
+/// shorten the chains of operands. The result must only depend on the arguments, as merges may
+/// be re-run by any read or compaction.
+pub trait MergeOperator: Send + Sync {
+    /// The name of the operator, used for debugging.
+    fn name(&self) -> &str;
+
+    /// Combines `existing` with `operands`, oldest first, into the value of `key`. An empty value
+    /// deletes the key.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes>;
+
+    /// Combines two consecutive operands, `left` being the older one, into one operand with the
+    /// same effect, or returns `None` if they cannot be combined. The default never combines them.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Bytes> {
+        None
+    }
+}
+
+impl std::fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MergeOperator({})", self.name())
+    }
+}
+
+/// Combines `existing` with `operands`, given newest first as iterators find them, through
+/// `operator`, which must be set since the operands were written with one.
+pub(crate) fn full_merge(
+    operator: Option<&Arc<dyn MergeOperator>>,
+    key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &[Bytes],
+) -> Result<Bytes> {
+    let Some(operator) = operator else {
+        bail!(
+            "merge operands found for key {:?} without a merge operator",
+            Bytes::copy_from_slice(key)
+        );
+    };
+    let operands = operands.iter().rev().map(|x| &x[..]).collect::<Vec<_>>();
+    operator.full_merge(key, existing, &operands)
+}
@@ -0,0 +1,418 @@
+//! Counters and histograms of the engine, exported in the Prometheus text format by
+//! [`crate::lsm_storage::MiniLsm::gather_metrics`] with the `metrics` feature. Without it, nothing
+//! is recorded, and the instrumentation of the read and write paths compiles to nothing.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::compact::{BackgroundJobKind, BackgroundJobStats};
+
+/// Upper bounds in seconds of the buckets of the latency histograms of reads.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5,
+    1.0,
+];
+/// Upper bounds in seconds of the buckets of the duration histograms of background jobs.
+const JOB_DURATION_BUCKETS: &[f64] = &[0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+/// A histogram of durations with fixed buckets.
+struct Histogram {
+    bounds: &'static [f64],
+    /// The number of durations in each bucket, not cumulative, followed by the `+Inf` bucket.
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = self.bounds.partition_point(|bound| *bound < secs);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn encode(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+        let mut count = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            match self.bounds.get(idx) {
+                Some(bound) => writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}").unwrap(),
+                None => writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}").unwrap(),
+            }
+        }
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "{name}_sum {sum}").unwrap();
+        writeln!(out, "{name}_count {count}").unwrap();
+    }
+}
+
+/// Writes a metric holding a single sample.
+pub(crate) fn encode_sample(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+/// The registry of the metrics of a storage. Counters are updated with relaxed atomics on the
+/// read and write paths, the block cache and the background jobs, while gauges of the shape of
+/// the tree are taken from its state when gathered.
+pub struct Metrics {
+    writes: AtomicU64,
+    write_bytes: AtomicU64,
+    reads: AtomicU64,
+    read_bytes: AtomicU64,
+    scans: AtomicU64,
+    block_cache_hits: AtomicU64,
+    block_cache_misses: AtomicU64,
+    bloom_checks: AtomicU64,
+    bloom_negatives: AtomicU64,
+    flushes: AtomicU64,
+    flush_bytes: AtomicU64,
+    compactions: AtomicU64,
+    compaction_read_bytes: AtomicU64,
+    compaction_write_bytes: AtomicU64,
+    write_stall_nanos: AtomicU64,
+    /// When the immutable memtables reached their limit, if they still are at it.
+    write_stall_begin: Mutex<Option<Instant>>,
+    get_latency: Histogram,
+    scan_latency: Histogram,
+    flush_duration: Histogram,
+    compaction_duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            writes: AtomicU64::new(0),
+            write_bytes: AtomicU64::new(0),
+            reads: AtomicU64::new(0),
+            read_bytes: AtomicU64::new(0),
+            scans: AtomicU64::new(0),
+            block_cache_hits: AtomicU64::new(0),
+            block_cache_misses: AtomicU64::new(0),
+            bloom_checks: AtomicU64::new(0),
+            bloom_negatives: AtomicU64::new(0),
+            flushes: AtomicU64::new(0),
+            flush_bytes: AtomicU64::new(0),
+            compactions: AtomicU64::new(0),
+            compaction_read_bytes: AtomicU64::new(0),
+            compaction_write_bytes: AtomicU64::new(0),
+            write_stall_nanos: AtomicU64::new(0),
+            write_stall_begin: Mutex::new(None),
+            get_latency: Histogram::new(LATENCY_BUCKETS),
+            scan_latency: Histogram::new(LATENCY_BUCKETS),
+            flush_duration: Histogram::new(JOB_DURATION_BUCKETS),
+            compaction_duration: Histogram::new(JOB_DURATION_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    const ENABLED: bool = cfg!(feature = "metrics");
+
+    /// The start of an operation whose latency is recorded, `None` when nothing is recorded.
+    pub(crate) fn start_timer(&self) -> Option<Instant> {
+        Self::ENABLED.then(Instant::now)
+    }
+
+    pub(crate) fn record_write(&self, entries: usize, bytes: usize) {
+        if !Self::ENABLED {
+            return;
+        }
+        self.writes.fetch_add(entries as u64, Ordering::Relaxed);
+        self.write_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a point lookup started at `timer`, which found `value`.
+    pub(crate) fn record_get(&self, timer: Option<Instant>, value: Option<&Bytes>) {
+        let Some(begin) = timer else {
+            return;
+        };
+        self.get_latency.observe(begin.elapsed());
+        self.record_reads(1, value.map_or(0, |value| value.len()));
+    }
+
+    /// Records lookups whose latency is not recorded, e.g. those of a batch.
+    pub(crate) fn record_reads(&self, reads: usize, bytes: usize) {
+        if !Self::ENABLED {
+            return;
+        }
+        self.reads.fetch_add(reads as u64, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a scan whose iterator was created, i.e. seeked to its first entry, since `timer`.
+    pub(crate) fn record_scan(&self, timer: Option<Instant>) {
+        let Some(begin) = timer else {
+            return;
+        };
+        self.scan_latency.observe(begin.elapsed());
+        self.scans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_block_cache_access(&self, hit: bool) {
+        if !Self::ENABLED {
+            return;
+        }
+        let counter = if hit {
+            &self.block_cache_hits
+        } else {
+            &self.block_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a bloom filter check, which saved reading the SST if `may_contain` is false.
+    pub(crate) fn record_bloom_check(&self, may_contain: bool) {
+        if !Self::ENABLED {
+            return;
+        }
+        self.bloom_checks.fetch_add(1, Ordering::Relaxed);
+        if !may_contain {
+            self.bloom_negatives.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_job(&self, job: &BackgroundJobStats) {
+        if !Self::ENABLED {
+            return;
+        }
+        match job.kind {
+            BackgroundJobKind::Flush => {
+                self.flushes.fetch_add(1, Ordering::Relaxed);
+                self.flush_bytes
+                    .fetch_add(job.output_bytes, Ordering::Relaxed);
+                self.flush_duration.observe(job.duration);
+            }
+            BackgroundJobKind::Compaction => {
+                self.compactions.fetch_add(1, Ordering::Relaxed);
+                self.compaction_read_bytes
+                    .fetch_add(job.input_bytes, Ordering::Relaxed);
+                self.compaction_write_bytes
+                    .fetch_add(job.output_bytes, Ordering::Relaxed);
+                self.compaction_duration.observe(job.duration);
+            }
+        }
+    }
+
+    /// Records whether the immutable memtables are at their limit, so that writes outpace the
+    /// flushes, after a memtable is frozen or flushed.
+    pub(crate) fn record_write_stall(&self, stalled: bool) {
+        if !Self::ENABLED {
+            return;
+        }
+        let mut begin = self.write_stall_begin.lock();
+        match (begin.is_some(), stalled) {
+            (false, true) => *begin = Some(Instant::now()),
+            (true, false) => {
+                let elapsed = begin.take().unwrap().elapsed();
+                self.write_stall_nanos
+                    .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Time spent with the immutable memtables at their limit, including the ongoing stall.
+    fn write_stall_time(&self) -> Duration {
+        let ongoing = self
+            .write_stall_begin
+            .lock()
+            .map_or(Duration::ZERO, |begin| begin.elapsed());
+        Duration::from_nanos(self.write_stall_nanos.load(Ordering::Relaxed)) + ongoing
+    }
+
+    /// Encodes the counters and histograms in the Prometheus text format.
+    pub fn gather(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "lsm_writes_total",
+                "Entries written by the user, including deletes.",
+                &self.writes,
+            ),
+            (
+                "lsm_write_bytes_total",
+                "Bytes of keys and values written by the user.",
+                &self.write_bytes,
+            ),
+            ("lsm_reads_total", "Point lookups.", &self.reads),
+            (
+                "lsm_read_bytes_total",
+                "Bytes of the values returned by point lookups.",
+                &self.read_bytes,
+            ),
+            ("lsm_scans_total", "Scans created.", &self.scans),
+            (
+                "lsm_block_cache_hits_total",
+                "SST blocks read from the block cache.",
+                &self.block_cache_hits,
+            ),
+            (
+                "lsm_block_cache_misses_total",
+                "SST blocks read from the disk through the block cache.",
+                &self.block_cache_misses,
+            ),
+            (
+                "lsm_bloom_checks_total",
+                "Bloom filter checks of point lookups.",
+                &self.bloom_checks,
+            ),
+            (
+                "lsm_bloom_negatives_total",
+                "Bloom filter checks that ruled out an SST.",
+                &self.bloom_negatives,
+            ),
+            ("lsm_flushes_total", "Memtables flushed.", &self.flushes),
+            (
+                "lsm_flush_bytes_total",
+                "Bytes of the SSTs written by flushes.",
+                &self.flush_bytes,
+            ),
+            (
+                "lsm_compactions_total",
+                "Compactions completed.",
+                &self.compactions,
+            ),
+            (
+                "lsm_compaction_read_bytes_total",
+                "Bytes of the SSTs read by compactions.",
+                &self.compaction_read_bytes,
+            ),
+            (
+                "lsm_compaction_write_bytes_total",
+                "Bytes of the SSTs written by compactions.",
+                &self.compaction_write_bytes,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            encode_sample(
+                &mut out,
+                name,
+                "counter",
+                help,
+                counter.load(Ordering::Relaxed),
+            );
+        }
+        encode_sample(
+            &mut out,
+            "lsm_write_stall_seconds_total",
+            "counter",
+            "Time spent with the immutable memtables at their limit.",
+            self.write_stall_time().as_secs_f64(),
+        );
+        self.get_latency.encode(
+            &mut out,
+            "lsm_get_latency_seconds",
+            "Latency of point lookups.",
+        );
+        self.scan_latency.encode(
+            &mut out,
+            "lsm_scan_latency_seconds",
+            "Latency of creating a scan, up to its first entry.",
+        );
+        self.flush_duration.encode(
+            &mut out,
+            "lsm_flush_duration_seconds",
+            "Duration of memtable flushes.",
+        );
+        self.compaction_duration.encode(
+            &mut out,
+            "lsm_compaction_duration_seconds",
+            "Duration of compactions.",
+        );
+        out
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl crate::lsm_storage::LsmStorageInner {
+    /// The metrics of the storage in the Prometheus text format: the counters and histograms of
+    /// [`Metrics::gather`], followed by gauges of the memtables, the levels and the block cache.
+    pub fn gather_metrics(&self) -> String {
+        let snapshot = self.state.load_full();
+        let mut out = self.metrics.gather();
+        let memtable_size = snapshot
+            .memtables()
+            .map(|memtable| memtable.approximate_size())
+            .sum::<usize>();
+        encode_sample(
+            &mut out,
+            "lsm_memtable_size_bytes",
+            "gauge",
+            "Approximate size of the mutable and immutable memtables.",
+            memtable_size,
+        );
+        encode_sample(
+            &mut out,
+            "lsm_immutable_memtables",
+            "gauge",
+            "Immutable memtables waiting to be flushed.",
+            snapshot.imm_memtables.len(),
+        );
+        encode_sample(
+            &mut out,
+            "lsm_block_cache_usage_bytes",
+            "gauge",
+            "Weighted size of the blocks in the block cache.",
+            self.block_cache.weighted_size(),
+        );
+        // L0 first, then the levels numbered from 1
+        let levels = std::iter::once(&snapshot.l0_sstables)
+            .chain(snapshot.levels.iter().map(|(_, ids)| ids))
+            .collect::<Vec<_>>();
+        writeln!(
+            out,
+            "# HELP lsm_level_size_bytes Size of the SSTs of each level."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE lsm_level_size_bytes gauge").unwrap();
+        for (level, ids) in levels.iter().enumerate() {
+            let size = ids
+                .iter()
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>();
+            writeln!(out, "lsm_level_size_bytes{{level=\"{level}\"}} {size}").unwrap();
+        }
+        writeln!(out, "# HELP lsm_level_files Number of SSTs of each level.").unwrap();
+        writeln!(out, "# TYPE lsm_level_files gauge").unwrap();
+        for (level, ids) in levels.iter().enumerate() {
+            writeln!(out, "lsm_level_files{{level=\"{level}\"}} {}", ids.len()).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl crate::lsm_storage::MiniLsm {
+    /// The registry of the counters and histograms of the storage.
+    pub fn metrics(&self) -> &Metrics {
+        &self.inner.metrics
+    }
+
+    /// See [`crate::lsm_storage::LsmStorageInner::gather_metrics`].
+    pub fn gather_metrics(&self) -> String {
+        self.inner.gather_metrics()
+    }
+}
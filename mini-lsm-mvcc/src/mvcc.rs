@@ -5,12 +5,12 @@ pub mod txn;
 pub mod watermark;
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     sync::{atomic::AtomicBool, Arc},
 };
 
 use crossbeam_skiplist::SkipMap;
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex, MutexGuard};
 
 use crate::lsm_storage::LsmStorageInner;
 
@@ -24,11 +24,24 @@ pub(crate) struct CommittedTxnData {
     pub(crate) commit_ts: u64,
 }
 
+/// The commits given a timestamp by [`LsmMvccInner::start_commit`] and not finished yet.
+#[derive(Default)]
+struct PendingCommits {
+    /// The largest timestamp given so far.
+    last_allocated: u64,
+    in_flight: BTreeSet<u64>,
+}
+
 pub(crate) struct LsmMvccInner {
+    /// Serializes the writes. Those of a sharded storage only hold it to start their commit, see
+    /// [`Self::start_commit`]; the others take it with [`Self::lock_writes`].
     pub(crate) write_lock: Mutex<()>,
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    pending: Mutex<PendingCommits>,
+    /// Notified whenever a commit started by [`Self::start_commit`] finishes.
+    commit_finished: Condvar,
 }
 
 impl LsmMvccInner {
@@ -38,6 +51,52 @@ impl LsmMvccInner {
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            pending: Mutex::new(PendingCommits::default()),
+            commit_finished: Condvar::new(),
+        }
+    }
+
+    /// Takes the write lock once no commit started by [`Self::start_commit`] is in flight, for
+    /// the writes committing at `latest_commit_ts() + 1` or reading the latest value first.
+    pub fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        let write_lock = self.write_lock.lock();
+        let mut pending = self.pending.lock();
+        // no commit can start while the write lock is held
+        while !pending.in_flight.is_empty() {
+            self.commit_finished.wait(&mut pending);
+        }
+        write_lock
+    }
+
+    /// Gives the next commit timestamp to a write that is applied without the write lock, so
+    /// that writes to different shards proceed in parallel. The timestamp only becomes visible
+    /// once the commits before it are finished too, see [`Self::finish_commit`].
+    pub fn start_commit(&self, _write_lock: &MutexGuard<'_, ()>) -> u64 {
+        let mut pending = self.pending.lock();
+        let ts = pending.last_allocated.max(self.latest_commit_ts()) + 1;
+        pending.last_allocated = ts;
+        pending.in_flight.insert(ts);
+        ts
+    }
+
+    /// Finishes the commit at `ts` started by [`Self::start_commit`], which must be called even
+    /// if the write failed. The latest commit timestamp moves up to just before the oldest commit
+    /// still in flight, so that reads never see a batch partially applied. Returns once `ts` is
+    /// visible, so that a write is read by the reads after it.
+    pub fn finish_commit(&self, ts: u64) {
+        let mut pending = self.pending.lock();
+        pending.in_flight.remove(&ts);
+        let committed = match pending.in_flight.first() {
+            Some(oldest) => oldest - 1,
+            None => pending.last_allocated,
+        };
+        {
+            let mut current = self.ts.lock();
+            current.0 = current.0.max(committed);
+        }
+        self.commit_finished.notify_all();
+        while pending.in_flight.first().is_some_and(|oldest| *oldest < ts) {
+            self.commit_finished.wait(&mut pending);
         }
     }
 
@@ -55,6 +114,12 @@ impl LsmMvccInner {
         ts.1.watermark().unwrap_or(ts.0)
     }
 
+    /// The read timestamp of the latest active snapshot. Versions above it are not visible to
+    /// any snapshot.
+    pub fn newest_snapshot_ts(&self) -> Option<u64> {
+        self.ts.lock().1.newest_reader()
+    }
+
     pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
         let mut ts = self.ts.lock();
         let read_ts = ts.0;
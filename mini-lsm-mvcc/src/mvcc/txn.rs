@@ -15,7 +15,7 @@ use parking_lot::Mutex;
 
 use crate::{
     iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
-    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_iterator::{FusedIterator, LsmIterator, LsmRevIterator},
     lsm_storage::{LsmStorageInner, WriteBatchRecord},
     mem_table::map_bound,
     mvcc::CommittedTxnData,
@@ -24,6 +24,7 @@ use crate::{
 pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
+    /// The writes of the transaction, where a delete is an empty value.
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
@@ -50,28 +51,86 @@ impl Transaction {
         self.inner.get_with_ts(key, self.read_ts)
     }
 
-    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+    /// Get many keys at once, which is faster than calling [`Self::get`] on each of them, see
+    /// [`LsmStorageInner::multi_get_with_ts`].
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        let mut local_iter = TxnLocalIteratorBuilder {
-            map: self.local_storage.clone(),
-            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
-            item: (Bytes::new(), Bytes::new()),
+        if let Some(guard) = &self.key_hashes {
+            let mut guard = guard.lock();
+            let (_, read_set) = &mut *guard;
+            read_set.extend(keys.iter().map(|key| farmhash::hash32(key)));
         }
-        .build();
-        let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
-        local_iter.with_mut(|x| *x.item = entry);
+        let mut values = vec![None; keys.len()];
+        let mut remaining = Vec::with_capacity(keys.len());
+        for (idx, key) in keys.iter().enumerate() {
+            match self.local_storage.get(*key) {
+                Some(entry) if entry.value().is_empty() => {}
+                Some(entry) => values[idx] = Some(entry.value().clone()),
+                None => remaining.push(idx),
+            }
+        }
+        let remaining_keys = remaining.iter().map(|&idx| keys[idx]).collect::<Vec<_>>();
+        let remaining_values = self
+            .inner
+            .multi_get_with_ts(&remaining_keys, self.read_ts)?;
+        for (idx, value) in remaining.into_iter().zip(remaining_values) {
+            values[idx] = value;
+        }
+        Ok(values)
+    }
 
+    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
         TxnIterator::create(
             self.clone(),
             TwoMergeIterator::create(
-                local_iter,
+                self.local_iter(lower, upper, false)?,
                 self.inner.scan_with_ts(lower, upper, self.read_ts)?,
             )?,
         )
     }
 
+    /// Like [`Self::scan`], but yields the keys in descending order.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnRevIterator> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        TxnIterator::create(
+            self.clone(),
+            TwoMergeIterator::create_rev(
+                self.local_iter(lower, upper, true)?,
+                self.inner.scan_rev_with_ts(lower, upper, self.read_ts)?,
+            )?,
+        )
+    }
+
+    fn local_iter(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        reverse: bool,
+    ) -> Result<TxnLocalIterator> {
+        let mut local_iter = TxnLocalIteratorBuilder {
+            map: self.local_storage.clone(),
+            iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
+            item: (Bytes::new(), Bytes::new()),
+            reverse,
+        }
+        .build();
+        local_iter.next()?;
+        Ok(local_iter)
+    }
+
+    /// Puts a key-value pair in the transaction. The writes of a transaction do not tell an empty
+    /// value apart from a delete, so an empty value deletes the key.
     pub fn put(&self, key: &[u8], value: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
@@ -185,6 +244,8 @@ pub struct TxnLocalIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// Whether the range is iterated in descending order.
+    reverse: bool,
 }
 
 impl TxnLocalIterator {
@@ -202,6 +263,10 @@ impl StorageIterator for TxnLocalIterator {
         &self.borrow_item().1[..]
     }
 
+    fn value_bytes(&self) -> Bytes {
+        self.borrow_item().1.clone()
+    }
+
     fn key(&self) -> &[u8] {
         &self.borrow_item().0[..]
     }
@@ -211,21 +276,30 @@ impl StorageIterator for TxnLocalIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
+        let entry = if *self.borrow_reverse() {
+            self.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next_back()))
+        } else {
+            self.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()))
+        };
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
 }
 
-pub struct TxnIterator {
+/// Iterates over the keys of a transaction, merging its local writes with the keys in the storage
+/// read by `I`.
+pub struct TxnIterator<I: StorageIterator = FusedIterator<LsmIterator>> {
     txn: Arc<Transaction>,
-    iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    iter: TwoMergeIterator<TxnLocalIterator, I>,
 }
 
-impl TxnIterator {
+/// Iterates over the keys of a transaction in descending order.
+pub type TxnRevIterator = TxnIterator<FusedIterator<LsmRevIterator>>;
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = &'a [u8]>> TxnIterator<I> {
     pub fn create(
         txn: Arc<Transaction>,
-        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        iter: TwoMergeIterator<TxnLocalIterator, I>,
     ) -> Result<Self> {
         let mut iter = Self { txn, iter };
         iter.skip_deletes()?;
@@ -236,7 +310,7 @@ impl TxnIterator {
     }
 
     fn skip_deletes(&mut self) -> Result<()> {
-        while self.iter.is_valid() && self.iter.value().is_empty() {
+        while self.iter.is_valid() && self.iter.is_tombstone() {
             self.iter.next()?;
         }
         Ok(())
@@ -251,17 +325,27 @@ impl TxnIterator {
     }
 }
 
-impl StorageIterator for TxnIterator {
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = &'a [u8]>> StorageIterator
+    for TxnIterator<I>
+{
     type KeyType<'a> = &'a [u8] where Self: 'a;
 
     fn value(&self) -> &[u8] {
         self.iter.value()
     }
 
+    fn value_bytes(&self) -> Bytes {
+        self.iter.value_bytes()
+    }
+
     fn key(&self) -> Self::KeyType<'_> {
         self.iter.key()
     }
 
+    fn is_tombstone(&self) -> bool {
+        false
+    }
+
     fn is_valid(&self) -> bool {
         self.iter.is_valid()
     }
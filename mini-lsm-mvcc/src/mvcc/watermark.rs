@@ -36,4 +36,9 @@ impl Watermark {
     pub fn watermark(&self) -> Option<u64> {
         self.readers.first_key_value().map(|(ts, _)| *ts)
     }
+
+    /// The read timestamp of the latest active reader.
+    pub fn newest_reader(&self) -> Option<u64> {
+        self.readers.last_key_value().map(|(ts, _)| *ts)
+    }
 }
@@ -0,0 +1,101 @@
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+
+/// Number of immutable memtables waiting to be flushed.
+pub const NUM_IMMUTABLE_MEMTABLES: &str = "lsm.num-immutable-memtables";
+/// Approximate size of the mutable and immutable memtables in bytes.
+pub const MEMTABLE_SIZE_BYTES: &str = "lsm.memtable-size-bytes";
+/// Number of live SSTs.
+pub const NUM_SST_FILES: &str = "lsm.num-sst-files";
+/// Prefix of `lsm.level{N}-size-bytes`, the size of the SSTs of level N in bytes, where level 0
+/// is L0 and the levels after it are numbered from 1, like in [`crate::lsm_storage::DiskUsage`].
+pub const LEVEL_SIZE_BYTES_PREFIX: &str = "lsm.level";
+const LEVEL_SIZE_BYTES_SUFFIX: &str = "-size-bytes";
+/// Weighted size of the blocks in the block cache.
+pub const BLOCK_CACHE_USAGE: &str = "lsm.block-cache-usage";
+/// Estimated number of live entries, i.e. the entries of the memtables and the entries of the
+/// SSTs that are neither tombstones nor shadowed in their table.
+pub const ESTIMATED_KEYS: &str = "lsm.estimated-keys";
+/// Number of background compactions that failed.
+pub const BACKGROUND_ERRORS: &str = "lsm.background-errors";
+/// `1` if the immutable memtables reached `num_memtable_limit`, so that the flush thread is
+/// behind the writes, or `0`.
+pub const IS_WRITE_STALLED: &str = "lsm.is-write-stalled";
+
+/// The names of the properties that do not depend on the shape of the tree, see
+/// [`LsmStorageInner::get_property`].
+pub const PROPERTIES: &[&str] = &[
+    NUM_IMMUTABLE_MEMTABLES,
+    MEMTABLE_SIZE_BYTES,
+    NUM_SST_FILES,
+    BLOCK_CACHE_USAGE,
+    ESTIMATED_KEYS,
+    BACKGROUND_ERRORS,
+    IS_WRITE_STALLED,
+];
+
+/// The name of the property reporting the size of `level`.
+pub fn level_size_bytes(level: usize) -> String {
+    format!("{LEVEL_SIZE_BYTES_PREFIX}{level}{LEVEL_SIZE_BYTES_SUFFIX}")
+}
+
+impl LsmStorageInner {
+    /// Gets an operational metric by name, formatted as a decimal integer, so that monitoring
+    /// tools can poll the engine without depending on its stats structs. The names are the
+    /// constants of [`crate::property`]. Returns `None` for unknown names and for levels past
+    /// the last one.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        let snapshot = self.state.load_full();
+        let value = match name {
+            NUM_IMMUTABLE_MEMTABLES => snapshot.imm_memtables.len() as u64,
+            MEMTABLE_SIZE_BYTES => snapshot
+                .memtables()
+                .map(|memtable| memtable.approximate_size() as u64)
+                .sum(),
+            NUM_SST_FILES => snapshot.sstables.len() as u64,
+            BLOCK_CACHE_USAGE => self.block_cache.weighted_size(),
+            ESTIMATED_KEYS => {
+                let memtable_entries = snapshot
+                    .memtables()
+                    .map(|memtable| memtable.num_entries() as u64)
+                    .sum::<u64>();
+                let sst_entries = snapshot
+                    .sstables
+                    .values()
+                    .map(|table| {
+                        let properties = table.properties();
+                        properties.num_entries.saturating_sub(
+                            properties.num_tombstones + properties.num_shadowed_entries,
+                        )
+                    })
+                    .sum::<u64>();
+                memtable_entries + sst_entries
+            }
+            BACKGROUND_ERRORS => self.compaction_stats().num_compaction_failures,
+            IS_WRITE_STALLED => {
+                (snapshot.imm_memtables.len() >= self.options.num_memtable_limit) as u64
+            }
+            _ => {
+                let level = name
+                    .strip_prefix(LEVEL_SIZE_BYTES_PREFIX)?
+                    .strip_suffix(LEVEL_SIZE_BYTES_SUFFIX)?
+                    .parse::<usize>()
+                    .ok()?;
+                let ids = if level == 0 {
+                    &snapshot.l0_sstables
+                } else {
+                    &snapshot.levels.get(level - 1)?.1
+                };
+                ids.iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum()
+            }
+        };
+        Some(value.to_string())
+    }
+}
+
+impl MiniLsm {
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        self.inner.get_property(name)
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+/// Deletes all versions older than `ts` of the user keys from `start` (inclusive) to `end`
+/// (exclusive), written by [`crate::lsm_storage::LsmStorageInner::delete_range`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, ts: u64) -> Self {
+        Self { start, end, ts }
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by this tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        self.start.as_ref() <= key && key < self.end.as_ref() && ts < self.ts
+    }
+
+    /// Whether the tombstone may cover a user key in the range.
+    pub(crate) fn overlaps(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let above_lower = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => self.end.as_ref() > key,
+            Bound::Unbounded => true,
+        };
+        let below_upper = match upper {
+            Bound::Included(key) => self.start.as_ref() <= key,
+            Bound::Excluded(key) => self.start.as_ref() < key,
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+
+    /// The part of the tombstone within the user keys `[lower, upper)`, where `None` means
+    /// unbounded, if any.
+    pub(crate) fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<Self> {
+        let start = match lower {
+            Some(lower) if lower > self.start.as_ref() => Bytes::copy_from_slice(lower),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < self.end.as_ref() => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        (start < end).then(|| Self::new(start, end, self.ts))
+    }
+
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.start.len() + self.end.len() + std::mem::size_of::<u16>() * 2 + 8
+    }
+
+    /// Encodes the tombstone as the start and end keys, each prefixed with its u16 length,
+    /// followed by the u64 timestamp.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u16(self.start.len() as u16);
+        buf.put_slice(&self.start);
+        buf.put_u16(self.end.len() as u16);
+        buf.put_slice(&self.end);
+        buf.put_u64(self.ts);
+    }
+
+    pub(crate) fn decode(buf: &mut &[u8]) -> Self {
+        let start_len = buf.get_u16() as usize;
+        let start = buf.copy_to_bytes(start_len);
+        let end_len = buf.get_u16() as usize;
+        let end = buf.copy_to_bytes(end_len);
+        Self::new(start, end, buf.get_u64())
+    }
+
+    /// Encodes the range tombstone section of an SST: the u32 number of tombstones, the
+    /// tombstones and a u32 checksum of them.
+    pub(crate) fn encode_section(tombstones: &[Self], buf: &mut Vec<u8>) {
+        buf.put_u32(tombstones.len() as u32);
+        let begin = buf.len();
+        for tombstone in tombstones {
+            tombstone.encode(buf);
+        }
+        let checksum = crc32fast::hash(&buf[begin..]);
+        buf.put_u32(checksum);
+    }
+
+    pub(crate) fn decode_section(mut buf: &[u8]) -> Result<Vec<Self>> {
+        let num = buf.get_u32() as usize;
+        if buf.remaining() < 4 {
+            bail!("range tombstone section too short");
+        }
+        let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
+        let mut tombstones = Vec::with_capacity(num);
+        for _ in 0..num {
+            tombstones.push(Self::decode(&mut buf));
+        }
+        if buf.get_u32() != checksum {
+            bail!("range tombstone checksum mismatched");
+        }
+        Ok(tombstones)
+    }
+}
+
+/// A set of range tombstones split into non-overlapping fragments, each holding the newest
+/// timestamp of the tombstones covering it, so that the tombstones covering a key are found with
+/// a binary search.
+#[derive(Clone, Debug, Default)]
+pub struct FragmentedRangeTombstones {
+    /// `(start, end, ts)`, sorted by `start`.
+    fragments: Vec<(Bytes, Bytes, u64)>,
+}
+
+impl FragmentedRangeTombstones {
+    pub fn new(tombstones: impl IntoIterator<Item = RangeTombstone>) -> Self {
+        let mut tombstones = tombstones
+            .into_iter()
+            .filter(|tombstone| tombstone.start < tombstone.end)
+            .collect::<Vec<_>>();
+        if tombstones.is_empty() {
+            return Self::default();
+        }
+        let mut boundaries = tombstones
+            .iter()
+            .flat_map(|tombstone| [tombstone.start.clone(), tombstone.end.clone()])
+            .collect::<Vec<_>>();
+        boundaries.sort();
+        boundaries.dedup();
+        tombstones.sort_by(|a, b| a.start.cmp(&b.start));
+
+        // sweep the boundaries, keeping the timestamps of the tombstones covering the fragment
+        // that starts at each of them
+        let mut fragments: Vec<(Bytes, Bytes, u64)> = Vec::new();
+        let mut active = BTreeMap::<u64, usize>::new();
+        let mut ends = Vec::<(Bytes, u64)>::new();
+        let mut next = 0;
+        for window in boundaries.windows(2) {
+            let (begin, end) = (&window[0], &window[1]);
+            while next < tombstones.len() && tombstones[next].start == *begin {
+                *active.entry(tombstones[next].ts).or_default() += 1;
+                ends.push((tombstones[next].end.clone(), tombstones[next].ts));
+                next += 1;
+            }
+            ends.retain(|(tombstone_end, ts)| {
+                if tombstone_end > begin {
+                    return true;
+                }
+                let count = active.get_mut(ts).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(ts);
+                }
+                false
+            });
+            let Some((&ts, _)) = active.last_key_value() else {
+                continue;
+            };
+            match fragments.last_mut() {
+                Some(last) if last.1 == *begin && last.2 == ts => last.1 = end.clone(),
+                _ => fragments.push((begin.clone(), end.clone(), ts)),
+            }
+        }
+        Self { fragments }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// The newest timestamp of the tombstones covering `key`, if any.
+    pub fn max_covering_ts(&self, key: &[u8]) -> Option<u64> {
+        let idx = self
+            .fragments
+            .partition_point(|(start, _, _)| start.as_ref() <= key)
+            .checked_sub(1)?;
+        let (_, end, ts) = &self.fragments[idx];
+        (key < end.as_ref()).then_some(*ts)
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by a tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        matches!(self.max_covering_ts(key), Some(max_ts) if ts < max_ts)
+    }
+}
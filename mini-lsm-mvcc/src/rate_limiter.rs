@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// The priority of a background I/O request. When the limiter is saturated, high-priority
+/// requests (flushes) are served before low-priority ones (compactions).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    High,
+    Low,
+}
+
+impl IoPriority {
+    fn index(self) -> usize {
+        match self {
+            IoPriority::High => 0,
+            IoPriority::Low => 1,
+        }
+    }
+}
+
+struct RateLimiterState {
+    /// Bytes per second, 0 means unlimited.
+    bytes_per_sec: u64,
+    /// Available tokens in bytes. Can go negative when a request is larger than the bucket.
+    available: f64,
+    last_refill: Instant,
+    /// Number of high-priority requests waiting for tokens.
+    high_priority_waiters: usize,
+}
+
+impl RateLimiterState {
+    /// The bucket holds at most 100ms worth of tokens so that idle periods do not turn into bursts.
+    fn capacity(&self) -> f64 {
+        self.bytes_per_sec as f64 / 10.0
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.available =
+            (self.available + elapsed * self.bytes_per_sec as f64).min(self.capacity());
+    }
+}
+
+/// A token-bucket rate limiter for background I/O (flush and compaction). Foreground reads and
+/// writes never go through the limiter.
+pub struct RateLimiter {
+    state: Mutex<RateLimiterState>,
+    /// Total time spent waiting for tokens, in nanoseconds, indexed by priority.
+    throttled_nanos: [AtomicU64; 2],
+    /// Total bytes requested, indexed by priority, counted even when the limiter is disabled.
+    requested_bytes: [AtomicU64; 2],
+    /// Number of requests that had to wait for tokens, indexed by priority.
+    throttled_requests: [AtomicU64; 2],
+}
+
+impl RateLimiter {
+    /// The longest time a request sleeps before re-checking the limiter, so that runtime changes
+    /// to the rate take effect quickly.
+    const MAX_SLEEP: Duration = Duration::from_millis(10);
+
+    /// Create a rate limiter allowing `bytes_per_sec` bytes per second. 0 means unlimited.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let mut state = RateLimiterState {
+            bytes_per_sec,
+            available: 0.0,
+            last_refill: Instant::now(),
+            high_priority_waiters: 0,
+        };
+        state.available = state.capacity();
+        Self {
+            state: Mutex::new(state),
+            throttled_nanos: [AtomicU64::new(0), AtomicU64::new(0)],
+            requested_bytes: [AtomicU64::new(0), AtomicU64::new(0)],
+            throttled_requests: [AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+
+    /// Change the rate at runtime. 0 disables rate limiting.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        let mut state = self.state.lock();
+        state.refill(Instant::now());
+        state.bytes_per_sec = bytes_per_sec;
+        state.available = state.available.min(state.capacity());
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.state.lock().bytes_per_sec
+    }
+
+    /// Block until `bytes` bytes of I/O are allowed. A request is granted as soon as the bucket
+    /// is not in debt, so a single request larger than the bucket does not block forever.
+    pub fn request(&self, bytes: usize, priority: IoPriority) {
+        self.requested_bytes[priority.index()].fetch_add(bytes as u64, Ordering::Relaxed);
+        let mut waiting_since = None;
+        loop {
+            let wait = {
+                let mut state = self.state.lock();
+                if state.bytes_per_sec == 0 {
+                    if waiting_since.is_some() && priority == IoPriority::High {
+                        state.high_priority_waiters -= 1;
+                    }
+                    break;
+                }
+                state.refill(Instant::now());
+                let blocked_by_high =
+                    priority == IoPriority::Low && state.high_priority_waiters > 0;
+                if state.available >= 0.0 && !blocked_by_high {
+                    state.available -= bytes as f64;
+                    if waiting_since.is_some() && priority == IoPriority::High {
+                        state.high_priority_waiters -= 1;
+                    }
+                    break;
+                }
+                if waiting_since.is_none() {
+                    waiting_since = Some(Instant::now());
+                    if priority == IoPriority::High {
+                        state.high_priority_waiters += 1;
+                    }
+                }
+                let deficit = (-state.available).max(1.0);
+                Duration::from_secs_f64(deficit / state.bytes_per_sec as f64).min(Self::MAX_SLEEP)
+            };
+            std::thread::sleep(wait);
+        }
+        if let Some(waiting_since) = waiting_since {
+            self.throttled_nanos[priority.index()]
+                .fetch_add(waiting_since.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            self.throttled_requests[priority.index()].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Total time requests of the given priority have spent blocked by the limiter.
+    pub fn throttled_time(&self, priority: IoPriority) -> Duration {
+        Duration::from_nanos(self.throttled_nanos[priority.index()].load(Ordering::Relaxed))
+    }
+
+    /// Total bytes requested with the given priority, whether or not they were throttled.
+    pub fn requested_bytes(&self, priority: IoPriority) -> u64 {
+        self.requested_bytes[priority.index()].load(Ordering::Relaxed)
+    }
+
+    /// Number of requests of the given priority that had to wait for tokens.
+    pub fn throttled_requests(&self, priority: IoPriority) -> u64 {
+        self.throttled_requests[priority.index()].load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use crate::backup::check_not_restoring;
+use crate::compact::CompactionController;
+use crate::lsm_storage::{
+    block_size_weigher, new_block_cache, replay_manifest, BlockCache, LsmStorageInner,
+    LsmStorageOptions, MiniLsm, ShardedStorageState,
+};
+use crate::manifest::Manifest;
+use crate::mem_table::MemTable;
+use crate::shard::{apply_batch_parts, validate_num_shards};
+use crate::table::{FileObject, SsTable};
+
+/// The error of the writes to a storage opened read-only, see [`MiniLsm::open_read_only`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOnlyError;
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the storage is opened read-only")
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+/// A file the manifest refers to was removed by the engine writing to the directory before a
+/// read-only storage could open it, e.g. an input of a compaction that finished in the meantime.
+/// The manifest has changed since it was read, so opening or refreshing again is expected to
+/// succeed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRemovedError {
+    pub path: PathBuf,
+}
+
+impl fmt::Display for FileRemovedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was removed after the manifest was read",
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for FileRemovedError {}
+
+/// Opens the file at `path` with `open`, reporting a missing file as a [`FileRemovedError`].
+fn open_unless_removed<T>(path: &Path, open: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    open(path).map_err(|e| {
+        let removed = e.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+        });
+        if removed {
+            FileRemovedError {
+                path: path.to_path_buf(),
+            }
+            .into()
+        } else {
+            e
+        }
+    })
+}
+
+impl LsmStorageInner {
+    /// Opens the storage in `path` without writing anything to the directory, while another
+    /// engine may keep writing to it. The state is recovered from the manifest, and the WALs of
+    /// the memtables not flushed yet are replayed into memtables kept in memory only. The
+    /// writes fail with a [`ReadOnlyError`], and the storage only sees the changes of the writer
+    /// once [`Self::refresh`] is called.
+    pub(crate) fn open_read_only(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        check_not_restoring(path)?;
+        validate_num_shards(options.num_shards)?;
+        let block_cache = Arc::new(new_block_cache(
+            options.block_cache_size,
+            block_size_weigher,
+        ));
+        let (state, last_commit_ts) =
+            Self::read_state(path, &options, &block_cache, &HashMap::new())?;
+        let next_sst_id = state.memtable.id() + 1;
+        println!(
+            "{} SSTs and {} memtables opened read-only",
+            state.sstables.len(),
+            state.imm_memtables.len()
+        );
+        Ok(Self::from_state(
+            path,
+            options,
+            state,
+            block_cache,
+            next_sst_id,
+            None,
+            last_commit_ts,
+        ))
+    }
+
+    /// Whether the storage was opened by [`Self::open_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.manifest.is_none()
+    }
+
+    /// Fails with a [`ReadOnlyError`] if the storage was opened read-only.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            return Err(ReadOnlyError.into());
+        }
+        Ok(())
+    }
+
+    /// Re-reads the manifest and the WALs of a storage opened read-only, to pick up what the
+    /// writer flushed, compacted and wrote since. The SSTs already opened are kept open, so that
+    /// iterators created before still read the ones compacted away in the meantime. On error,
+    /// the state is left unchanged; a [`FileRemovedError`] means the writer changed the
+    /// directory while it was read, and the refresh can be retried.
+    pub fn refresh(&self) -> Result<()> {
+        if !self.is_read_only() {
+            bail!("only a storage opened read-only can be refreshed");
+        }
+        let _state_lock = self.state_lock.lock();
+        let opened = self.state.load().sstables.clone();
+        let (state, last_commit_ts) =
+            Self::read_state(&self.path, &self.options, &self.block_cache, &opened)?;
+        self.state.store(Arc::new(state));
+        if last_commit_ts > self.mvcc().latest_commit_ts() {
+            self.mvcc().update_commit_ts(last_commit_ts);
+        }
+        Ok(())
+    }
+
+    /// Builds the state described by the manifest in `path`, reusing the SSTs in `opened`, and
+    /// replays the WALs of the memtables not flushed yet. Returns the state and the largest
+    /// commit timestamp in it.
+    fn read_state(
+        path: &Path,
+        options: &LsmStorageOptions,
+        block_cache: &Arc<BlockCache>,
+        opened: &HashMap<usize, Arc<SsTable>>,
+    ) -> Result<(ShardedStorageState, u64)> {
+        let compaction_controller = CompactionController::new(&options.compaction_options);
+        let records = Manifest::read_records(path.join("MANIFEST"))?;
+        let mut state = ShardedStorageState::create(options);
+        let replayed = replay_manifest(&mut state, &compaction_controller, records);
+        let mut last_commit_ts = 0;
+
+        let table_ids = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<Vec<_>>();
+        for table_id in table_ids {
+            let sst = match opened.get(&table_id) {
+                Some(sst) => sst.clone(),
+                None => {
+                    let file = open_unless_removed(
+                        &Self::path_of_sst_static(path, table_id),
+                        FileObject::open,
+                    )?;
+                    let mut sst = SsTable::open(table_id, Some(block_cache.clone()), file)?;
+                    if let Some(shard) = replayed.sst_shard(table_id, options.num_shards) {
+                        sst = sst.with_shard(shard);
+                    }
+                    Arc::new(sst)
+                }
+            };
+            last_commit_ts = last_commit_ts.max(sst.max_ts());
+            state.sstables.insert(table_id, sst);
+        }
+
+        if options.enable_wal {
+            let mut replayed_wals = Vec::with_capacity(replayed.memtables.len());
+            for &id in replayed.memtables.iter() {
+                let mut batch_parts = Vec::new();
+                let memtable = open_unless_removed(&Self::path_of_wal_static(path, id), |path| {
+                    MemTable::replay_wal(id, path, &mut batch_parts)
+                })?;
+                replayed_wals.push((memtable, batch_parts));
+            }
+            // a batch the writer is still writing is left out, the manifest is not written
+            apply_batch_parts(
+                &replayed_wals,
+                &replayed.memtables,
+                &replayed.dropped_batches,
+            );
+            for (memtable, _) in replayed_wals {
+                last_commit_ts = last_commit_ts.max(memtable.max_ts());
+                if !memtable.is_empty() {
+                    state.imm_memtables.insert(0, Arc::new(memtable));
+                }
+            }
+        }
+        // never written to, the id only needs to differ from those of the other memtables
+        state.memtable = Arc::new(MemTable::create(replayed.max_id + 1));
+        Ok((state, last_commit_ts))
+    }
+}
+
+impl MiniLsm {
+    /// Whether the storage was opened read-only, see [`LsmStorageInner::is_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    /// Picks up the changes of the writer, see [`LsmStorageInner::refresh`].
+    pub fn refresh(&self) -> Result<()> {
+        self.inner.refresh()
+    }
+}
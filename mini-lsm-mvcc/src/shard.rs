@@ -0,0 +1,74 @@
+//! Sharding of the writes by key, see [`crate::lsm_storage::LsmStorageOptions::num_shards`].
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use crate::mem_table::MemTable;
+use crate::wal::WalBatchPart;
+
+/// Seeds the hash picking the shard of a key, so that it does not correlate with the unseeded
+/// hash of the bloom filters.
+const SHARD_SEED: u32 = 0x5348_4152;
+
+/// Fails unless `num_shards` is a power of two.
+pub(crate) fn validate_num_shards(num_shards: usize) -> Result<()> {
+    if !num_shards.is_power_of_two() {
+        bail!("num_shards must be a power of two, got {}", num_shards);
+    }
+    Ok(())
+}
+
+/// The shard of `key` among `num_shards`, a power of two.
+pub(crate) fn shard_of(key: &[u8], num_shards: usize) -> usize {
+    if num_shards == 1 {
+        return 0;
+    }
+    farmhash::hash32_with_seed(key, SHARD_SEED) as usize & (num_shards - 1)
+}
+
+/// The batches across shards recovered from the WALs, see [`apply_batch_parts`].
+#[derive(Debug, Default)]
+pub(crate) struct RecoveredBatches {
+    /// The sequence numbers of the batches dropped by this recovery, to be recorded in the
+    /// manifest.
+    pub(crate) dropped: Vec<u64>,
+    /// The largest sequence number of a part, applied or not, so that no later batch reuses it.
+    pub(crate) max_seq: u64,
+}
+
+/// Applies the batch parts recovered from the WALs of the memtables not flushed yet, `unflushed`
+/// by id, each given with the parts read from its WAL. A batch is applied only if none of its
+/// parts is lost: every memtable it was written to was either flushed, with its part, or is
+/// recovered here with its part. Otherwise all its parts are dropped, as they are if an earlier
+/// recovery dropped it, which `dropped` holds: once the memtables holding its other parts are
+/// flushed, the missing part would go unnoticed.
+pub(crate) fn apply_batch_parts(
+    memtables: &[(MemTable, Vec<WalBatchPart>)],
+    unflushed: &BTreeSet<usize>,
+    dropped: &HashSet<u64>,
+) -> RecoveredBatches {
+    let mut found = HashMap::<u64, HashSet<usize>>::new();
+    for (memtable, parts) in memtables {
+        for part in parts {
+            found.entry(part.seq).or_default().insert(memtable.id());
+        }
+    }
+    let mut recovered = RecoveredBatches::default();
+    for (memtable, parts) in memtables {
+        for part in parts {
+            recovered.max_seq = recovered.max_seq.max(part.seq);
+            let complete = !dropped.contains(&part.seq)
+                && part
+                    .memtable_ids
+                    .iter()
+                    .all(|id| !unflushed.contains(id) || found[&part.seq].contains(id));
+            if complete {
+                memtable.apply_batch_part(part);
+            } else if !dropped.contains(&part.seq) && !recovered.dropped.contains(&part.seq) {
+                recovered.dropped.push(part.seq);
+            }
+        }
+    }
+    recovered
+}
@@ -1,23 +1,44 @@
 pub(crate) mod bloom;
 mod builder;
+mod checksum;
+#[cfg(test)]
+pub(crate) mod faulty_file;
 mod iterator;
+mod mmap_pool;
+mod rebuild;
+mod tombstone_bitmap;
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Result};
-pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+pub use bloom::Bloom;
+pub use builder::{BloomOptions, SsTableBuilder};
+use bytes::{Buf, BufMut, Bytes};
+pub use checksum::ChecksumType;
+use iterator::BoundedSsTableIterator;
 pub use iterator::SsTableIterator;
+pub use mmap_pool::MmapPool;
+pub use tombstone_bitmap::TombstoneBitmap;
+use tombstone_bitmap::TOMBSTONE_BITMAP_MAGIC;
 
-use crate::block::Block;
-use crate::key::{KeyBytes, KeySlice};
-use crate::lsm_storage::BlockCache;
-
-use self::bloom::Bloom;
+use crate::block::{
+    get_key_len, has_entry_flags, key_len_width, put_key_len, Block, BlockIterator,
+};
+use crate::iterators::StorageIterator;
+use crate::key::{KeyBytes, KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{BlockCache, BlockCacheKey};
+use crate::metrics::Metrics;
+use crate::range_tombstone::RangeTombstone;
+use crate::trace;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlockMeta {
     /// Offset of this data block.
     pub offset: usize,
@@ -25,86 +46,296 @@ pub struct BlockMeta {
     pub first_key: KeyBytes,
     /// The last key of the data block.
     pub last_key: KeyBytes,
+    /// Number of entries in the data block.
+    pub num_entries: u32,
+}
+
+/// Properties of an SSTable and statistics of its entries, stored along with the block meta.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Format version of the data blocks, see [`crate::block::BLOCK_FORMAT_VERSION`].
+    pub format_version: u8,
+    /// The checksum of the data blocks and the block meta.
+    pub checksum_type: ChecksumType,
+    /// Number of entries, including tombstones and all versions.
+    pub num_entries: u64,
+    /// Number of tombstones, see [`crate::block::ENTRY_FLAG_TOMBSTONE`].
+    pub num_tombstones: u64,
+    /// Number of entries shadowed by a newer version of the same key in this table. They become
+    /// garbage once the watermark passes the newer version.
+    pub num_shadowed_entries: u64,
+    /// When the table was built, in seconds since the Unix epoch, or 0 if unknown.
+    pub creation_time: u64,
+    /// Number of distinct user keys, which the bloom filter is sized for.
+    pub num_distinct_keys: u64,
+    /// Size of the bloom filter in bits.
+    pub num_bloom_bits: u64,
+    /// Sizes of the values, see [`SsTable::value_size_histogram`].
+    pub value_sizes: ValueSizeHistogram,
+}
+
+impl TableProperties {
+    /// The fraction of entries that are tombstones or shadowed versions.
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.num_entries == 0 {
+            return 0.0;
+        }
+        (self.num_tombstones + self.num_shadowed_entries) as f64 / self.num_entries as f64
+    }
+
+    /// The bits per key the bloom filter achieved for the distinct keys of the table.
+    pub fn bloom_bits_per_key(&self) -> f64 {
+        if self.num_distinct_keys == 0 {
+            return 0.0;
+        }
+        self.num_bloom_bits as f64 / self.num_distinct_keys as f64
+    }
+}
+
+/// Number of buckets of a [`ValueSizeHistogram`].
+pub const VALUE_SIZE_BUCKETS: usize = 16;
+
+/// Statistics of the sizes of the values of an SSTable, e.g. to tell whether separating large
+/// values from the keys would pay off. Tombstones are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValueSizeHistogram {
+    /// The size of the smallest value, or 0 if there are none.
+    pub min: u64,
+    pub max: u64,
+    pub sum: u64,
+    /// `buckets[i]` counts the values of `2^i` to `2^(i + 1) - 1` bytes, except for the first
+    /// bucket, which also counts the empty values, and the last bucket, which counts all the
+    /// values of at least `2^(VALUE_SIZE_BUCKETS - 1)` bytes.
+    pub buckets: [u64; VALUE_SIZE_BUCKETS],
+}
+
+impl ValueSizeHistogram {
+    /// The bucket counting values of `size` bytes.
+    pub fn bucket_of(size: u64) -> usize {
+        (size.checked_ilog2().unwrap_or(0) as usize).min(VALUE_SIZE_BUCKETS - 1)
+    }
+
+    pub(crate) fn add(&mut self, size: u64) {
+        self.min = if self.count() == 0 {
+            size
+        } else {
+            self.min.min(size)
+        };
+        self.max = self.max.max(size);
+        self.sum += size;
+        self.buckets[Self::bucket_of(size)] += 1;
+    }
+
+    /// Adds the values counted by `other`.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        if other.count() == 0 {
+            return;
+        }
+        self.min = if self.count() == 0 {
+            other.min
+        } else {
+            self.min.min(other.min)
+        };
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += other_bucket;
+        }
+    }
+
+    /// Number of values.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The average value size, or 0 if there are no values.
+    pub fn mean(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => self.sum as f64 / count as f64,
+        }
+    }
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+    /// Encode block meta to a buffer. Like every other integer in SST files, the WAL and the
+    /// manifest, all integers are encoded in big-endian byte order, so the files are portable
+    /// across architectures.
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        max_ts: u64,
+        properties: &TableProperties,
+        buf: &mut Vec<u8>,
+    ) {
+        // key lengths take the narrowest width fitting the longest first or last key
+        let max_key_len = block_meta
+            .iter()
+            .map(|meta| meta.first_key.key_len().max(meta.last_key.key_len()))
+            .max()
+            .unwrap_or(0);
+        let key_len_width = key_len_width(max_key_len);
         let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
+        estimated_size += std::mem::size_of::<u8>(); // width of key lengths
         for meta in block_meta {
             // The size of offset
             estimated_size += std::mem::size_of::<u32>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += key_len_width as usize;
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += key_len_width as usize;
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
+            // The size of number of entries
+            estimated_size += std::mem::size_of::<u32>();
         }
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
-        estimated_size += std::mem::size_of::<u32>(); // checksum
+        estimated_size += std::mem::size_of::<u8>() + std::mem::size_of::<u64>() * 6; // table properties
+        estimated_size += std::mem::size_of::<u64>() * (3 + VALUE_SIZE_BUCKETS); // value sizes
+        estimated_size += properties.checksum_type.size(); // checksum
 
         // Reserve the space to improve performance, especially when the size of incoming data is
         // large
         buf.reserve(estimated_size);
         let original_len = buf.len();
         buf.put_u32(block_meta.len() as u32);
+        buf.put_u8(key_len_width);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            put_key_len(buf, meta.first_key.key_len(), key_len_width);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            put_key_len(buf, meta.last_key.key_len(), key_len_width);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
+            buf.put_u32(meta.num_entries);
         }
         buf.put_u64(max_ts);
-        buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
+        buf.put_u8(
+            properties
+                .checksum_type
+                .encode_with_format_version(properties.format_version),
+        );
+        buf.put_u64(properties.num_entries);
+        buf.put_u64(properties.num_tombstones);
+        buf.put_u64(properties.num_shadowed_entries);
+        buf.put_u64(properties.creation_time);
+        buf.put_u64(properties.num_distinct_keys);
+        buf.put_u64(properties.num_bloom_bits);
+        let value_sizes = &properties.value_sizes;
+        buf.put_u64(value_sizes.min);
+        buf.put_u64(value_sizes.max);
+        buf.put_u64(value_sizes.sum);
+        for bucket in value_sizes.buckets {
+            buf.put_u64(bucket);
+        }
+        properties.checksum_type.put_checksum(buf, original_len + 4);
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64, TableProperties)> {
+        Self::decode_block_meta_prefix(&mut buf)
+    }
+
+    /// Decode block meta from the start of a buffer, leaving `buf` at the data following it.
+    pub(crate) fn decode_block_meta_prefix(
+        buf: &mut &[u8],
+    ) -> Result<(Vec<BlockMeta>, u64, TableProperties)> {
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
-        let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
+        let checksummed: &[u8] = buf;
+        let key_len_width = buf.get_u8();
+        if !matches!(key_len_width, 1 | 2 | 4) {
+            bail!("invalid key length width {} in block meta", key_len_width);
+        }
         for _ in 0..num {
             let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let first_key_len = get_key_len(buf, key_len_width);
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = get_key_len(buf, key_len_width);
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
+            let num_entries = buf.get_u32();
             block_meta.push(BlockMeta {
                 offset,
                 first_key,
                 last_key,
+                num_entries,
             });
         }
         let max_ts = buf.get_u64();
-        if buf.get_u32() != checksum {
+        let (format_version, checksum_type) =
+            ChecksumType::decode_with_format_version(buf.get_u8())?;
+        let properties = TableProperties {
+            format_version,
+            checksum_type,
+            num_entries: buf.get_u64(),
+            num_tombstones: buf.get_u64(),
+            num_shadowed_entries: buf.get_u64(),
+            creation_time: buf.get_u64(),
+            num_distinct_keys: buf.get_u64(),
+            num_bloom_bits: buf.get_u64(),
+            value_sizes: ValueSizeHistogram {
+                min: buf.get_u64(),
+                max: buf.get_u64(),
+                sum: buf.get_u64(),
+                buckets: std::array::from_fn(|_| buf.get_u64()),
+            },
+        };
+        let checksum = checksum_type.checksum(&checksummed[..checksummed.len() - buf.len()]);
+        if checksum_type.get_checksum(buf) != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok((block_meta, max_ts, properties))
     }
 }
 
+/// Where `key` falls between `first` and `last`, from 0 to 1, comparing the 8 bytes after their
+/// common prefix as numbers.
+fn key_position(first: &[u8], last: &[u8], key: &[u8]) -> f64 {
+    let prefix_len = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    let as_number = |key: &[u8]| {
+        let mut bytes = [0; 8];
+        for (byte, key_byte) in bytes.iter_mut().zip(key.iter().skip(prefix_len)) {
+            *byte = *key_byte;
+        }
+        u64::from_be_bytes(bytes) as f64
+    };
+    let (first, last) = (as_number(first), as_number(last));
+    if last <= first {
+        return 0.5;
+    }
+    ((as_number(key) - first) / (last - first)).clamp(0.0, 1.0)
+}
+
+enum FileBackend {
+    File(File),
+    /// The file is read through a mapping owned by a shared pool.
+    Pooled(Arc<MmapPool>, PathBuf),
+    #[cfg(test)]
+    Faulty(faulty_file::FaultyFileObject),
+}
+
 /// A file object.
-pub struct FileObject(Option<File>, u64);
+pub struct FileObject(Option<FileBackend>, u64);
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
         use std::os::unix::fs::FileExt;
-        let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
-        Ok(data)
+        match self.0.as_ref().unwrap() {
+            FileBackend::File(file) => {
+                let mut data = vec![0; len as usize];
+                file.read_exact_at(&mut data[..], offset)?;
+                Ok(data)
+            }
+            FileBackend::Pooled(pool, path) => pool.read(path, offset, len),
+            #[cfg(test)]
+            FileBackend::Faulty(file) => file.read(offset, len),
+        }
     }
 
     pub fn size(&self) -> u64 {
@@ -112,11 +343,13 @@ impl FileObject {
     }
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
-    pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
+    pub fn create(path: &Path, data: &[u8]) -> Result<Self> {
+        std::fs::write(path, data)?;
         File::open(path)?.sync_all()?;
         Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
+            Some(FileBackend::File(
+                File::options().read(true).write(false).open(path)?,
+            )),
             data.len() as u64,
         ))
     }
@@ -124,10 +357,107 @@ impl FileObject {
     pub fn open(path: &Path) -> Result<Self> {
         let file = File::options().read(true).write(false).open(path)?;
         let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+        Ok(FileObject(Some(FileBackend::File(file)), size))
+    }
+
+    /// Open a file through a shared mmap pool. No file descriptor is kept open.
+    pub fn open_with_pool(path: &Path, pool: Arc<MmapPool>) -> Result<Self> {
+        let size = pool.file_size(path)?;
+        Ok(FileObject(
+            Some(FileBackend::Pooled(pool, path.to_path_buf())),
+            size,
+        ))
+    }
+}
+
+impl Drop for FileObject {
+    fn drop(&mut self) {
+        if let Some(FileBackend::Pooled(pool, path)) = &self.0 {
+            pool.release(path);
+        }
+    }
+}
+
+/// How a block read goes through the block cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheHint {
+    /// Read from the cache, inserting the block on a miss. Meant for point lookups, which are
+    /// likely to read the same blocks again.
+    #[default]
+    Fill,
+    /// Read from the cache if the block is there, but do not insert it on a miss. Meant for
+    /// scans, which benefit from hot blocks without evicting them with blocks read only once.
+    Probe,
+    /// Always read from the disk, never touching the cache.
+    Bypass,
+}
+
+/// How the blocks of an SST are read, see [`SsTable::read_block_with_options`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockReadOptions {
+    pub cache_hint: CacheHint,
+    /// Whether blocks read from the disk are checked against their checksums. When off, a block
+    /// whose layout is corrupted still fails to decode, but corrupted keys and values are
+    /// returned as they are. Blocks served by the block cache were checked when first read.
+    pub verify_checksums: bool,
+}
+
+impl Default for BlockReadOptions {
+    fn default() -> Self {
+        Self {
+            cache_hint: CacheHint::Fill,
+            verify_checksums: true,
+        }
     }
 }
 
+impl From<CacheHint> for BlockReadOptions {
+    fn from(cache_hint: CacheHint) -> Self {
+        Self {
+            cache_hint,
+            ..Default::default()
+        }
+    }
+}
+
+/// A user key along with its hash for the bloom filters, so that a key probed against many SSTs
+/// is hashed only once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashedKey<'a> {
+    key: &'a [u8],
+    hash: u32,
+}
+
+impl<'a> HashedKey<'a> {
+    pub fn new(key: &'a [u8]) -> Self {
+        Self {
+            key,
+            hash: farmhash::fingerprint32(key),
+        }
+    }
+
+    pub fn key(&self) -> &'a [u8] {
+        self.key
+    }
+
+    /// The hash the bloom filters are built with.
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+}
+
+/// The outcome of [`SsTable::get_cached_only`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CachedLookup {
+    /// The newest version of the key at or below the read timestamp, a tombstone if `flags` has
+    /// [`crate::block::ENTRY_FLAG_TOMBSTONE`].
+    Found { ts: u64, value: Bytes, flags: u8 },
+    /// The table holds no version of the key at or below the read timestamp.
+    NotFound,
+    /// A block that may hold the key is not in the block cache.
+    WouldBlock,
+}
+
 /// An SSTable.
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
@@ -136,12 +466,57 @@ pub struct SsTable {
     pub(crate) block_meta: Vec<BlockMeta>,
     /// The offset that indicates the start point of meta blocks in `file`.
     pub(crate) block_meta_offset: usize,
+    /// The meta, the bloom filter and the footer, if they are kept apart from the blocks in
+    /// `file`. Their offsets are still those of a single file, so that `file` followed by the
+    /// index file is a valid table.
+    index_file: Option<FileObject>,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    /// Tells apart the blocks of tables sharing an id in the block cache.
+    cache_namespace: u32,
+    /// Records the block cache accesses and bloom filter checks of the table, see
+    /// [`Self::with_metrics`].
+    metrics: Option<Arc<Metrics>>,
+    /// The shard whose keys the table holds, see [`Self::with_shard`].
+    shard: Option<usize>,
     first_key: KeyBytes,
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    properties: TableProperties,
+    range_tombstones: Vec<RangeTombstone>,
+    tombstone_bitmap: Option<TombstoneBitmap>,
+    /// Number of times the bloom filter has been consulted. Only point lookups should do so.
+    bloom_probes: AtomicU64,
+}
+
+/// The first and last key of a table. The key range is extended to the range tombstones reaching
+/// beyond the entries, with the smallest key of their start and (exclusive) end user keys, so
+/// that a table touching the next one at such an end never overlaps it.
+pub(crate) fn table_key_range(
+    block_meta: &[BlockMeta],
+    range_tombstones: &[RangeTombstone],
+) -> (KeyBytes, KeyBytes) {
+    let mut first_key = block_meta.first().map(|meta| meta.first_key.clone());
+    let mut last_key = block_meta.last().map(|meta| meta.last_key.clone());
+    for tombstone in range_tombstones {
+        if !matches!(&first_key, Some(key) if key.key_ref() <= tombstone.start.as_ref()) {
+            first_key = Some(KeyBytes::from_bytes_with_ts(
+                tombstone.start.clone(),
+                TS_RANGE_BEGIN,
+            ));
+        }
+        if !matches!(&last_key, Some(key) if key.key_ref() >= tombstone.end.as_ref()) {
+            last_key = Some(KeyBytes::from_bytes_with_ts(
+                tombstone.end.clone(),
+                TS_RANGE_BEGIN,
+            ));
+        }
+    }
+    (
+        first_key.expect("table without entries or range tombstones"),
+        last_key.expect("table without entries or range tombstones"),
+    )
 }
 impl SsTable {
     #[cfg(test)]
@@ -151,28 +526,99 @@ impl SsTable {
 
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
+        Self::open_inner(id, block_cache, file, None)
+    }
+
+    /// Open SSTable from a data file and the index file written next to it by
+    /// [`SsTableBuilder::build_with_separate_index`]. Only the index file is read here, so that a
+    /// table on remote storage can be opened without fetching its blocks.
+    pub fn open_with_index(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        index_file: FileObject,
+    ) -> Result<Self> {
+        Self::open_inner(id, block_cache, file, Some(index_file))
+    }
+
+    fn open_inner(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        index_file: Option<FileObject>,
+    ) -> Result<Self> {
+        // the offsets of the index file start where the data file ends
+        let index_begin = if index_file.is_some() { file.size() } else { 0 };
+        let index = index_file.as_ref().unwrap_or(&file);
+        let read = |offset: u64, len: u64| match offset.checked_sub(index_begin) {
+            Some(offset) => index.read(offset, len),
+            None => bail!("offset {} points into the data file", offset),
+        };
+        let mut len = index_begin + index.size();
+        let mut bloom_offset = (&read(len - 4, 4)?[..]).get_u32() as u64;
+        // the tombstone bitmap follows what the table would be without it
+        let mut raw_tombstone_bitmap = None;
+        if bloom_offset == TOMBSTONE_BITMAP_MAGIC as u64 {
+            let bitmap_offset = (&read(len - 8, 4)?[..]).get_u32() as u64;
+            if bitmap_offset + 8 > len {
+                bail!("tombstone bitmap offset {} out of range", bitmap_offset);
+            }
+            raw_tombstone_bitmap = Some(read(bitmap_offset, len - 8 - bitmap_offset)?);
+            len = bitmap_offset;
+            bloom_offset = (&read(len - 4, 4)?[..]).get_u32() as u64;
+        }
+        let raw_bloom = read(bloom_offset, len - 4 - bloom_offset)?;
         let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
+        let raw_meta_offset = read(bloom_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        if index_file.is_some() && block_meta_offset != index_begin {
+            bail!(
+                "index file expects a data file of {} bytes, found {}",
+                block_meta_offset,
+                index_begin
+            );
+        }
+        let raw_meta = read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
+        let (block_meta, max_ts, properties, range_tombstones) = Self::decode_meta(&raw_meta)?;
+        let tombstone_bitmap = raw_tombstone_bitmap
+            .map(|raw| TombstoneBitmap::decode(&raw, &block_meta))
+            .transpose()?;
+        let (first_key, last_key) = table_key_range(&block_meta, &range_tombstones);
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta,
             block_meta_offset: block_meta_offset as usize,
+            index_file,
             id,
             block_cache,
+            cache_namespace: 0,
+            shard: None,
+            metrics: None,
             bloom: Some(bloom_filter),
             max_ts,
+            properties,
+            range_tombstones,
+            tombstone_bitmap,
+            bloom_probes: AtomicU64::new(0),
         })
     }
 
+    /// Decode the block meta and the range tombstones following it.
+    fn decode_meta(
+        mut raw_meta: &[u8],
+    ) -> Result<(Vec<BlockMeta>, u64, TableProperties, Vec<RangeTombstone>)> {
+        let (block_meta, max_ts, properties) = BlockMeta::decode_block_meta_prefix(&mut raw_meta)?;
+        // the range tombstone section is only written by tables with range tombstones
+        let range_tombstones = if raw_meta.has_remaining() {
+            RangeTombstone::decode_section(raw_meta)?
+        } else {
+            Vec::new()
+        };
+        Ok((block_meta, max_ts, properties, range_tombstones))
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -184,51 +630,600 @@ impl SsTable {
             file: FileObject(None, file_size),
             block_meta: vec![],
             block_meta_offset: 0,
+            index_file: None,
             id,
             block_cache: None,
+            cache_namespace: 0,
+            shard: None,
+            metrics: None,
             first_key,
             last_key,
             bloom: None,
             max_ts: 0,
+            properties: TableProperties::default(),
+            range_tombstones: Vec::new(),
+            tombstone_bitmap: None,
+            bloom_probes: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes a table holding the entries of `a` followed by those of `b` to `path`, copying the
+    /// encoded blocks of both as they are. Only the block meta, properties and footer are
+    /// rebuilt. The bloom filters are combined with [`Bloom::union`] when they have the same
+    /// shape; otherwise the filter is rebuilt from the keys of the blocks, which is the only case
+    /// where entries are decoded. The new table shares the block cache of `a`, and has a
+    /// tombstone bitmap if both tables have one.
+    ///
+    /// Panics unless all the user keys of `a` are smaller than those of `b`, so that no user key
+    /// has versions in both. Tables with range tombstones cannot be concatenated.
+    pub fn concat(a: &SsTable, b: &SsTable, new_id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        assert!(
+            a.last_key().key_ref() < b.first_key().key_ref(),
+            "tables to concatenate overlap: {:?} >= {:?}",
+            a.last_key(),
+            b.first_key()
+        );
+        if a.properties.format_version != b.properties.format_version {
+            bail!(
+                "cannot concatenate tables of format versions {} and {}",
+                a.properties.format_version,
+                b.properties.format_version
+            );
+        }
+        if a.properties.checksum_type != b.properties.checksum_type {
+            bail!(
+                "cannot concatenate tables with {:?} and {:?} checksums",
+                a.properties.checksum_type,
+                b.properties.checksum_type
+            );
+        }
+        if !a.range_tombstones.is_empty() || !b.range_tombstones.is_empty() {
+            bail!("cannot concatenate tables with range tombstones");
+        }
+        let (Some(bloom_a), Some(bloom_b)) = (&a.bloom, &b.bloom) else {
+            bail!("cannot concatenate tables without bloom filters");
+        };
+        let bloom = match bloom_a.union(bloom_b) {
+            Some(bloom) => bloom,
+            None => {
+                let mut key_hashes = Vec::new();
+                for table in [a, b] {
+                    table.collect_key_hashes(&mut key_hashes)?;
+                }
+                Bloom::build_from_key_hashes(
+                    &key_hashes,
+                    Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+                )
+            }
+        };
+
+        let mut buf = a.file.read(0, a.block_meta_offset as u64)?;
+        buf.extend(b.file.read(0, b.block_meta_offset as u64)?);
+        let block_meta = a
+            .block_meta
+            .iter()
+            .cloned()
+            .chain(b.block_meta.iter().map(|meta| BlockMeta {
+                offset: meta.offset + a.block_meta_offset,
+                ..meta.clone()
+            }))
+            .collect::<Vec<_>>();
+        let max_ts = a.max_ts.max(b.max_ts);
+        let properties = TableProperties {
+            format_version: a.properties.format_version,
+            checksum_type: a.properties.checksum_type,
+            num_entries: a.properties.num_entries + b.properties.num_entries,
+            num_tombstones: a.properties.num_tombstones + b.properties.num_tombstones,
+            num_shadowed_entries: a.properties.num_shadowed_entries
+                + b.properties.num_shadowed_entries,
+            // the age of the table is the age of its oldest data
+            creation_time: a.properties.creation_time.min(b.properties.creation_time),
+            num_distinct_keys: a.properties.num_distinct_keys + b.properties.num_distinct_keys,
+            num_bloom_bits: bloom.filter.len() as u64 * 8,
+            value_sizes: {
+                let mut value_sizes = a.properties.value_sizes;
+                value_sizes.merge(&b.properties.value_sizes);
+                value_sizes
+            },
+        };
+        let block_meta_offset = buf.len();
+        BlockMeta::encode_block_meta(&block_meta, max_ts, &properties, &mut buf);
+        buf.put_u32(block_meta_offset as u32);
+        let bloom_offset = buf.len();
+        bloom.encode(&mut buf);
+        buf.put_u32(bloom_offset as u32);
+        let tombstone_bitmap = match (&a.tombstone_bitmap, &b.tombstone_bitmap) {
+            (Some(bitmap_a), Some(bitmap_b)) => {
+                let mut bitmap = TombstoneBitmap::default();
+                for bitmap_in in [bitmap_a, bitmap_b] {
+                    for entry in 0..bitmap_in.num_entries() {
+                        bitmap.push(bitmap_in.is_tombstone(entry));
+                    }
+                }
+                bitmap.set_blocks(&block_meta)?;
+                bitmap.encode(&mut buf);
+                Some(bitmap)
+            }
+            _ => None,
+        };
+        let file = FileObject::create(path.as_ref(), &buf)?;
+        Ok(Self {
+            file,
+            first_key: a.first_key.clone(),
+            last_key: b.last_key.clone(),
+            block_meta,
+            block_meta_offset,
+            index_file: None,
+            id: new_id,
+            block_cache: a.block_cache.clone(),
+            cache_namespace: a.cache_namespace,
+            shard: None,
+            metrics: a.metrics.clone(),
+            bloom: Some(bloom),
+            max_ts,
+            properties,
+            range_tombstones: Vec::new(),
+            tombstone_bitmap,
+            bloom_probes: AtomicU64::new(0),
+        })
+    }
+
+    /// Streams the file of the table to `w`, e.g. to replicate it to a follower, and returns the
+    /// number of bytes written. Each block is checked against its checksum before it is written,
+    /// and so are the meta and the bloom filter, so that a corrupt table fails the copy instead of
+    /// producing a bad replica. On error, `w` holds an incomplete copy, which must be discarded.
+    /// A table with a separate index file is copied as a single file.
+    pub fn copy_to(&self, w: &mut impl Write) -> Result<u64> {
+        let mut bytes_written = 0;
+        for block_idx in 0..self.num_of_blocks() {
+            let offset = self.block_meta[block_idx].offset;
+            let data = self.file.read(
+                offset as u64,
+                (self.block_offset_end(block_idx) - offset) as u64,
+            )?;
+            if self.properties.checksum_type.verify(&data).is_none() {
+                trace::checksum_failure(self.id, block_idx);
+                bail!("block checksum mismatched");
+            }
+            w.write_all(&data)?;
+            bytes_written += data.len() as u64;
+        }
+
+        // the meta, the bloom filter, the tombstone bitmap and their offsets
+        let tail = match &self.index_file {
+            Some(index_file) => index_file.read(0, index_file.size())?,
+            None => self.file.read(
+                self.block_meta_offset as u64,
+                self.file.size() - self.block_meta_offset as u64,
+            )?,
+        };
+        let mut footer_end = tail.len();
+        if (&tail[footer_end - 4..]).get_u32() == TOMBSTONE_BITMAP_MAGIC {
+            let bitmap_offset = (&tail[footer_end - 8..]).get_u32() as usize;
+            let Some(bitmap_begin) = bitmap_offset
+                .checked_sub(self.block_meta_offset)
+                .filter(|begin| *begin <= tail.len() - 8)
+            else {
+                bail!("tombstone bitmap offset {} out of range", bitmap_offset);
+            };
+            TombstoneBitmap::decode(&tail[bitmap_begin..footer_end - 8], &self.block_meta)?;
+            footer_end = bitmap_begin;
+        }
+        let bloom_offset = (&tail[footer_end - 4..footer_end]).get_u32() as usize;
+        let Some(bloom_begin) = bloom_offset
+            .checked_sub(self.block_meta_offset)
+            .filter(|begin| (4..=footer_end - 4).contains(begin))
+        else {
+            bail!("bloom filter offset {} out of range", bloom_offset);
+        };
+        Bloom::decode(&tail[bloom_begin..footer_end - 4])?;
+        Self::decode_meta(&tail[..bloom_begin - 4])?;
+        w.write_all(&tail)?;
+        bytes_written += tail.len() as u64;
+        Ok(bytes_written)
+    }
+
+    /// Appends the bloom filter hashes of the distinct user keys in the table to `key_hashes`.
+    fn collect_key_hashes(&self, key_hashes: &mut Vec<u32>) -> Result<()> {
+        let mut last_key = Vec::new();
+        for block_idx in 0..self.num_of_blocks() {
+            let mut iter = BlockIterator::create_and_seek_to_first(self.read_block(block_idx)?);
+            while iter.is_valid() {
+                if iter.key().key_ref() != last_key {
+                    last_key.clear();
+                    last_key.extend(iter.key().key_ref());
+                    key_hashes.push(farmhash::fingerprint32(&last_key));
+                }
+                iter.next();
+            }
         }
+        Ok(())
     }
 
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let mut blocks = self.read_blocks(block_idx, 0)?;
+        Ok(blocks.pop().unwrap())
+    }
+
+    /// Read the blocks starting at `block_idx` from the disk with a single read, bypassing the
+    /// block cache. Blocks are read as long as they fit in `readahead_bytes` (including their
+    /// checksums), but at least one block is always read.
+    pub fn read_blocks(&self, block_idx: usize, readahead_bytes: usize) -> Result<Vec<Arc<Block>>> {
+        self.read_blocks_inner(block_idx, readahead_bytes, true)
+    }
+
+    fn read_blocks_inner(
+        &self,
+        block_idx: usize,
+        readahead_bytes: usize,
+        verify_checksums: bool,
+    ) -> Result<Vec<Arc<Block>>> {
+        if block_idx >= self.block_meta.len() {
+            bail!(
+                "block index {} out of range, the table has {} blocks",
+                block_idx,
+                self.block_meta.len()
+            );
+        }
         let offset = self.block_meta[block_idx].offset;
-        let offset_end = self
-            .block_meta
-            .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
-        let block_data_with_chksum: Vec<u8> = self
-            .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
-        let block_data = &block_data_with_chksum[..block_len];
-        let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
-            bail!("block checksum mismatched");
-        }
-        Ok(Arc::new(Block::decode(block_data)))
-    }
-
-    /// Read a block from disk, with block cache.
-    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
-        } else {
-            self.read_block(block_idx)
+        let mut end_idx = block_idx + 1;
+        while end_idx < self.block_meta.len()
+            && self.block_offset_end(end_idx) - offset <= readahead_bytes
+        {
+            end_idx += 1;
+        }
+        let data = self.file.read(
+            offset as u64,
+            self.blocks_len_on_disk(block_idx, end_idx) as u64,
+        )?;
+        let mut blocks = Vec::with_capacity(end_idx - block_idx);
+        for idx in block_idx..end_idx {
+            let begin = self.block_meta[idx].offset - offset;
+            let end = self.block_offset_end(idx) - offset;
+            let checksum_type = self.properties.checksum_type;
+            let block_data = &data[begin..end - checksum_type.size()];
+            if !verify_checksums {
+                blocks.push(Arc::new(Block::try_decode_with_format_version(
+                    block_data,
+                    self.properties.format_version,
+                )?));
+                continue;
+            }
+            if checksum_type.verify(&data[begin..end]).is_none() {
+                trace::checksum_failure(self.id, idx);
+                bail!("block checksum mismatched");
+            }
+            blocks.push(Arc::new(Block::decode_with_format_version(
+                block_data,
+                self.properties.format_version,
+            )));
         }
+        Ok(blocks)
     }
 
-    /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
+    /// Get the offset right after a block (and its checksum) on the disk.
+    fn block_offset_end(&self, block_idx: usize) -> usize {
         self.block_meta
-            .partition_point(|meta| meta.first_key.as_key_slice() <= key)
-            .saturating_sub(1)
+            .get(block_idx + 1)
+            .map_or(self.block_meta_offset, |x| x.offset)
+    }
+
+    /// Get the size of the blocks `[begin, end)` on the disk, including their checksums.
+    pub(crate) fn blocks_len_on_disk(&self, begin: usize, end: usize) -> usize {
+        self.block_offset_end(end - 1) - self.block_meta[begin].offset
+    }
+
+    /// Get a block from the block cache without reading it from the disk on a miss.
+    pub fn get_cached_block(&self, block_idx: usize) -> Option<Arc<Block>> {
+        self.block_cache
+            .as_ref()
+            .and_then(|block_cache| block_cache.get(&self.cache_key(block_idx)))
+    }
+
+    /// Read a block from disk, going through the block cache as told by `hint`.
+    pub fn read_block_cached(&self, block_idx: usize, hint: CacheHint) -> Result<Arc<Block>> {
+        self.read_block_with_options(block_idx, hint.into())
+    }
+
+    /// Read a block as told by `options`.
+    pub fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        options: BlockReadOptions,
+    ) -> Result<Arc<Block>> {
+        let read_block = || -> Result<Arc<Block>> {
+            let mut blocks = self.read_blocks_inner(block_idx, 0, options.verify_checksums)?;
+            Ok(blocks.pop().unwrap())
+        };
+        match (&self.block_cache, options.cache_hint) {
+            (Some(block_cache), CacheHint::Fill) => {
+                let mut missed = false;
+                let block = block_cache
+                    .try_get_with(self.cache_key(block_idx), || {
+                        missed = true;
+                        read_block()
+                    })
+                    .map_err(|e| anyhow!("{}", e));
+                self.record_block_cache_access(!missed);
+                block
+            }
+            (Some(_), CacheHint::Probe) => {
+                let block = self.get_cached_block(block_idx);
+                self.record_block_cache_access(block.is_some());
+                match block {
+                    Some(block) => Ok(block),
+                    None => read_block(),
+                }
+            }
+            _ => read_block(),
+        }
+    }
+
+    /// Find the block that may contain `key`, or `None` if the table has no blocks, e.g. one
+    /// created by [`SsTable::create_meta_only`].
+    pub fn find_block_idx(&self, key: KeySlice) -> Option<usize> {
+        if self.block_meta.is_empty() {
+            return None;
+        }
+        Some(
+            self.block_meta
+                .partition_point(|meta| meta.first_key.as_key_slice() <= key)
+                .saturating_sub(1),
+        )
+    }
+
+    /// Loads the blocks holding user keys in the range into the block cache, e.g. ahead of a
+    /// latency-sensitive scan of it, and returns how many blocks were loaded or already cached.
+    /// Nothing is loaded if the table has no block cache or does not overlap the range.
+    pub fn warm_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<usize> {
+        if self.block_cache.is_none() {
+            return Ok(0);
+        }
+        let begin = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                match self.find_block_idx(KeySlice::from_slice(key, TS_RANGE_BEGIN)) {
+                    Some(idx) => idx,
+                    None => return Ok(0),
+                }
+            }
+            Bound::Unbounded => 0,
+        };
+        let mut warmed = 0;
+        for (idx, meta) in self.block_meta.iter().enumerate().skip(begin) {
+            let (first, last) = (meta.first_key.key_ref(), meta.last_key.key_ref());
+            let below_lower = match lower {
+                Bound::Included(lower) => last < lower,
+                Bound::Excluded(lower) => last <= lower,
+                Bound::Unbounded => false,
+            };
+            if below_lower {
+                continue;
+            }
+            let above_upper = match upper {
+                Bound::Included(upper) => first > upper,
+                Bound::Excluded(upper) => first >= upper,
+                Bound::Unbounded => false,
+            };
+            if above_upper {
+                break;
+            }
+            self.read_block_cached(idx, CacheHint::Fill)?;
+            warmed += 1;
+        }
+        Ok(warmed)
+    }
+
+    /// Estimates the number of entries, counting every version, with user keys in the range from
+    /// the block meta alone. Blocks inside the range count all their entries, and the entries of
+    /// blocks only partially inside it are interpolated from where the bounds fall between the
+    /// first and last key of the block, assuming the keys are uniformly distributed.
+    pub fn estimated_key_count_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> usize {
+        self.estimate_in_range(lower, upper, |idx| self.block_meta[idx].num_entries as f64)
+            .round() as usize
+    }
+
+    /// Estimates the bytes on the disk of the entries with user keys in the range, interpolating
+    /// the size of the blocks the same way as [`Self::estimated_key_count_in_range`]. No block is
+    /// read, and the meta, bloom filter and other sections of the table are not counted.
+    pub fn approximate_bytes_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> u64 {
+        self.estimate_in_range(lower, upper, |idx| {
+            self.blocks_len_on_disk(idx, idx + 1) as f64
+        })
+        .round() as u64
+    }
+
+    /// Sums `weight` over the blocks overlapping the range, each weighted by the fraction of the
+    /// block estimated to be inside it.
+    fn estimate_in_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        weight: impl Fn(usize) -> f64,
+    ) -> f64 {
+        let above_lower = |key: &[u8]| match lower {
+            Bound::Included(lower) => key >= lower,
+            Bound::Excluded(lower) => key > lower,
+            Bound::Unbounded => true,
+        };
+        let below_upper = |key: &[u8]| match upper {
+            Bound::Included(upper) => key <= upper,
+            Bound::Excluded(upper) => key < upper,
+            Bound::Unbounded => true,
+        };
+        let mut estimate = 0.0;
+        for (idx, meta) in self.block_meta.iter().enumerate() {
+            let (first, last) = (meta.first_key.key_ref(), meta.last_key.key_ref());
+            if !above_lower(last) || !below_upper(first) {
+                continue;
+            }
+            let begin = match lower {
+                Bound::Included(lower) | Bound::Excluded(lower) if !above_lower(first) => {
+                    key_position(first, last, lower)
+                }
+                _ => 0.0,
+            };
+            let end = match upper {
+                Bound::Included(upper) | Bound::Excluded(upper) if !below_upper(last) => {
+                    key_position(first, last, upper)
+                }
+                _ => 1.0,
+            };
+            estimate += weight(idx) * (end - begin).max(0.0);
+        }
+        estimate
+    }
+
+    /// Check whether the table may contain `key` using only the key range and the bloom filter,
+    /// without reading any block. `false` is definitive, `true` may be a false positive.
+    ///
+    /// This is meant for point lookups only. Range scans cannot use the bloom filter and must
+    /// not call this.
+    pub fn may_contain_key(&self, key: &[u8]) -> bool {
+        self.may_contain_hashed_key(HashedKey::new(key))
+    }
+
+    /// Like [`Self::may_contain_key`], with the hash of the key computed by the caller.
+    pub fn may_contain_hashed_key(&self, key: HashedKey) -> bool {
+        if key.key() < self.first_key.key_ref() || key.key() > self.last_key.key_ref() {
+            return false;
+        }
+        match &self.bloom {
+            Some(bloom) => {
+                self.bloom_probes.fetch_add(1, Ordering::Relaxed);
+                let may_contain = bloom.may_contain(key.hash());
+                if let (Some(metrics), false) = (&self.metrics, bloom.is_always_match()) {
+                    metrics.record_bloom_check(may_contain);
+                }
+                may_contain
+            }
+            None => true,
+        }
+    }
+
+    /// Looks up the newest version at or below `read_ts` of each of the user keys `keys`, sorted in
+    /// ascending order, calling `f` with the index in `keys` of each key found, the timestamp and
+    /// the value of its version. Keys outside of the table or rejected by the bloom filter are
+    /// skipped, and each block is read at most once, however many keys fall in it.
+    pub fn multi_get(
+        &self,
+        keys: &[&[u8]],
+        read_ts: u64,
+        f: impl FnMut(usize, u64, &[u8]),
+    ) -> Result<()> {
+        let keys = keys
+            .iter()
+            .map(|key| HashedKey::new(key))
+            .collect::<Vec<_>>();
+        self.multi_get_hashed(&keys, read_ts, f)
+    }
+
+    /// Like [`Self::multi_get`], with the hashes of the keys computed by the caller.
+    pub fn multi_get_hashed(
+        &self,
+        keys: &[HashedKey],
+        read_ts: u64,
+        mut f: impl FnMut(usize, u64, &[u8]),
+    ) -> Result<()> {
+        let begin = keys.partition_point(|key| key.key() < self.first_key.key_ref());
+        let end = keys.partition_point(|key| key.key() <= self.last_key.key_ref());
+        let mut blocks = HashMap::new();
+        let mut read_block = |block_idx: usize| -> Result<Arc<Block>> {
+            if let Some(block) = blocks.get(&block_idx) {
+                return Ok(Arc::clone(block));
+            }
+            let block = self.read_block_cached(block_idx, CacheHint::Fill)?;
+            blocks.insert(block_idx, block.clone());
+            Ok(block)
+        };
+        for (idx, key) in keys.iter().enumerate().take(end).skip(begin) {
+            if !self.may_contain_hashed_key(*key) {
+                continue;
+            }
+            let key = key.key();
+            let seek_key = KeySlice::from_slice(key, read_ts);
+            let Some(block_idx) = self.find_block_idx(seek_key) else {
+                return Ok(());
+            };
+            let mut iter = BlockIterator::create_and_seek_to_key(read_block(block_idx)?, seek_key);
+            // the version may be the first entry of the next block
+            if !iter.is_valid() && block_idx + 1 < self.num_of_blocks() {
+                iter = BlockIterator::create_and_seek_to_first(read_block(block_idx + 1)?);
+            }
+            if iter.is_valid() && iter.key().key_ref() == key {
+                f(idx, iter.key().ts(), iter.value());
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the newest version at or below `read_ts` of `key` like [`Self::multi_get`], only
+    /// through the blocks already in the block cache. Nothing is read from the disk: if a block
+    /// that may hold the key is not cached, or the table has no block cache, the lookup gives up
+    /// with [`CachedLookup::WouldBlock`].
+    pub fn get_cached_only(&self, key: HashedKey, read_ts: u64) -> CachedLookup {
+        if !self.may_contain_hashed_key(key) {
+            return CachedLookup::NotFound;
+        }
+        let seek_key = KeySlice::from_slice(key.key(), read_ts);
+        let Some(block_idx) = self.find_block_idx(seek_key) else {
+            return CachedLookup::NotFound;
+        };
+        let Some(block) = self.get_cached_block(block_idx) else {
+            return CachedLookup::WouldBlock;
+        };
+        let mut iter = BlockIterator::create_and_seek_to_key(block, seek_key);
+        // the version may be the first entry of the next block
+        if !iter.is_valid() && block_idx + 1 < self.num_of_blocks() {
+            let Some(block) = self.get_cached_block(block_idx + 1) else {
+                return CachedLookup::WouldBlock;
+            };
+            iter = BlockIterator::create_and_seek_to_first(block);
+        }
+        if !iter.is_valid() || iter.key().key_ref() != key.key() {
+            return CachedLookup::NotFound;
+        }
+        CachedLookup::Found {
+            ts: iter.key().ts(),
+            value: iter.value_bytes(),
+            flags: iter.flags(),
+        }
+    }
+
+    /// The expected false positive rate of the bloom filter, from its size and number of hash
+    /// functions as read back from the file, see [`SsTableBuilder::set_bloom_options`]. 1.0 when
+    /// the filter matches every key, i.e. when it is disabled or the table holds a single key.
+    pub fn bloom_fpr(&self) -> f64 {
+        match &self.bloom {
+            Some(bloom) => bloom.false_positive_rate(self.properties.num_distinct_keys),
+            None => 1.0,
+        }
+    }
+
+    /// Number of times the bloom filter of this table has been consulted.
+    pub fn bloom_probes(&self) -> u64 {
+        self.bloom_probes.load(Ordering::Relaxed)
+    }
+
+    /// Split the table into iterators over the user key ranges `[first, pivots[0])`,
+    /// `[pivots[0], pivots[1])`, ..., `[pivots[n - 1], last]` without rewriting it. The iterators
+    /// cover the whole table without overlapping, so they can be scanned on separate threads.
+    /// `pivots` must be sorted; ranges without any entry yield empty iterators.
+    pub fn split_iterators(
+        self: &Arc<Self>,
+        pivots: &[&[u8]],
+    ) -> Result<Vec<impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + Send>> {
+        assert!(
+            pivots.windows(2).all(|pair| pair[0] <= pair[1]),
+            "pivots must be sorted"
+        );
+        let mut iters = Vec::with_capacity(pivots.len() + 1);
+        let mut lower = None;
+        for pivot in pivots.iter().copied().map(Some).chain([None]) {
+            iters.push(BoundedSsTableIterator::create(self.clone(), lower, pivot)?);
+            lower = pivot;
+        }
+        Ok(iters)
     }
 
     /// Get number of data blocks.
@@ -236,6 +1231,11 @@ impl SsTable {
         self.block_meta.len()
     }
 
+    /// Get the metadata of all data blocks.
+    pub fn block_metas(&self) -> &[BlockMeta] {
+        &self.block_meta
+    }
+
     pub fn first_key(&self) -> &KeyBytes {
         &self.first_key
     }
@@ -244,15 +1244,102 @@ impl SsTable {
         &self.last_key
     }
 
+    /// The user key of [`Self::first_key`], without its timestamp.
+    pub fn first_key_bytes(&self) -> &[u8] {
+        self.first_key.key_ref()
+    }
+
+    /// The user key of [`Self::last_key`], without its timestamp.
+    pub fn last_key_bytes(&self) -> &[u8] {
+        self.last_key.key_ref()
+    }
+
     pub fn table_size(&self) -> u64 {
-        self.file.1
+        self.file.1 + self.index_file.as_ref().map_or(0, FileObject::size)
     }
 
     pub fn sst_id(&self) -> usize {
         self.id
     }
 
+    /// Puts the blocks of the table in the block cache under `namespace`, so that tables sharing
+    /// an id, e.g. those of storages sharing a block cache, never read each other's blocks. The
+    /// default namespace is 0.
+    pub fn with_cache_namespace(mut self, namespace: u32) -> Self {
+        self.cache_namespace = namespace;
+        self
+    }
+
+    pub fn cache_namespace(&self) -> u32 {
+        self.cache_namespace
+    }
+
+    /// Tags the table as holding only the keys of `shard`, as flushed from the memtable of the
+    /// shard, so that point reads of the other shards skip it, see
+    /// [`crate::lsm_storage::LsmStorageOptions::num_shards`].
+    pub fn with_shard(mut self, shard: usize) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// The shard whose keys the table holds, or `None` if it may hold keys of any shard.
+    pub fn shard(&self) -> Option<usize> {
+        self.shard
+    }
+
+    /// Whether the table may hold keys of `shard`.
+    pub fn in_shard(&self, shard: usize) -> bool {
+        self.shard.is_none_or(|tag| tag == shard)
+    }
+
+    /// Records the block cache accesses and the bloom filter checks of the table in `metrics`,
+    /// those of the storage holding it.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn record_block_cache_access(&self, hit: bool) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_block_cache_access(hit);
+        }
+    }
+
+    fn cache_key(&self, block_idx: usize) -> BlockCacheKey {
+        (self.cache_namespace, self.id, block_idx)
+    }
+
+    /// The range tombstones written to the table.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// The tombstone bitmap, if the table was built with one.
+    pub fn tombstone_bitmap(&self) -> Option<&TombstoneBitmap> {
+        self.tombstone_bitmap.as_ref()
+    }
+
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// Whether the entries of the table store flags, e.g. to mark merge operands.
+    pub fn has_entry_flags(&self) -> bool {
+        has_entry_flags(self.properties.format_version)
+    }
+
+    /// The sizes of the values of the table, recorded when it was built. Diagnostic only.
+    pub fn value_size_histogram(&self) -> &ValueSizeHistogram {
+        &self.properties.value_sizes
+    }
+
+    /// The size of the table inflated by its garbage ratio, so that tables full of tombstones and
+    /// shadowed versions are compacted earlier than their raw size suggests.
+    pub fn compensated_size(&self) -> u64 {
+        (self.table_size() as f64 * (1.0 + self.properties.garbage_ratio())) as u64
+    }
 }
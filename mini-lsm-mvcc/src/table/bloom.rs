@@ -3,6 +3,10 @@
 use anyhow::{bail, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+/// The largest number of hash functions of a real filter. A larger `k` marks a short filter, see
+/// [`Bloom::always_match`].
+const MAX_K: u8 = 30;
+
 /// Implements a bloom filter
 pub struct Bloom {
     /// data of filter in bits
@@ -45,14 +49,21 @@ impl<T: AsMut<[u8]>> BitSliceMut for T {
 }
 
 impl Bloom {
-    /// Decode a bloom filter
+    /// Decode a bloom filter. Any `k` larger than 30 is read as a short filter that matches
+    /// every key, whatever its filter bytes, so that such encodings can be added later.
     pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 5 {
+            bail!("bloom filter too short: {} bytes", buf.len());
+        }
         let checksum = (&buf[buf.len() - 4..buf.len()]).get_u32();
         if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
             bail!("checksum mismatched for bloom filters");
         }
         let filter = &buf[..buf.len() - 5];
         let k = buf[buf.len() - 5];
+        if k <= MAX_K && filter.is_empty() {
+            bail!("empty bloom filter with {} hash functions", k);
+        }
         Ok(Self {
             filter: filter.to_vec().into(),
             k,
@@ -68,6 +79,20 @@ impl Bloom {
         buf.put_u32(checksum);
     }
 
+    /// A short filter that matches every key, encoded without any filter bytes. It is used for
+    /// tables with a single key, where the key range check is already exact.
+    pub fn always_match() -> Self {
+        Self {
+            filter: Bytes::new(),
+            k: u8::MAX,
+        }
+    }
+
+    /// Whether this is a short filter matching every key.
+    pub fn is_always_match(&self) -> bool {
+        self.k > MAX_K
+    }
+
     /// Get bloom filter bits per key from entries count and FPR
     pub fn bloom_bits_per_key(entries: usize, false_positive_rate: f64) -> usize {
         let size =
@@ -76,34 +101,65 @@ impl Bloom {
         locs as usize
     }
 
+    /// The false positive rate of a filter with `bits` bits per key and the optimal number of
+    /// hash functions, the inverse of [`Bloom::bloom_bits_per_key`].
+    pub fn fpr_for_bits_per_key(bits: usize) -> f64 {
+        (-(bits as f64) * std::f64::consts::LN_2.powi(2)).exp()
+    }
+
+    /// The expected false positive rate of this filter holding `num_keys` keys, given its actual
+    /// size and number of hash functions, 1.0 for a short filter matching every key.
+    pub fn false_positive_rate(&self, num_keys: u64) -> f64 {
+        if self.is_always_match() {
+            return 1.0;
+        }
+        let k = self.k as f64;
+        let bits = self.filter.bit_len() as f64;
+        (1.0 - (-k * num_keys as f64 / bits).exp()).powf(k)
+    }
+
+    /// The bits per key that gives the lowest false positive rate for `total_keys` keys while
+    /// keeping the filters within `budget_bytes`, or 0 if there are no keys.
+    pub fn bits_per_key_for_budget(total_keys: usize, budget_bytes: usize) -> usize {
+        if total_keys == 0 {
+            return 0;
+        }
+        budget_bytes.saturating_mul(8) / total_keys
+    }
+
     /// Build bloom filter from key hashes
     pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
-        let k = (bits_per_key as f64 * 0.69) as u32;
-        let k = k.min(30).max(1);
-        let nbits = (keys.len() * bits_per_key).max(64);
-        let nbytes = (nbits + 7) / 8;
-        let nbits = nbytes * 8;
-        let mut filter = BytesMut::with_capacity(nbytes);
-        filter.resize(nbytes, 0);
+        let mut builder = BloomBuilder::new(keys.len(), bits_per_key);
         for h in keys {
-            let mut h = *h;
-            let delta = (h >> 17) | (h << 15);
-            for _ in 0..k {
-                let bit_pos = (h as usize) % nbits;
-                filter.set_bit(bit_pos, true);
-                h = h.wrapping_add(delta);
-            }
+            builder.add_key_hash(*h);
         }
-        Self {
-            filter: filter.freeze(),
-            k: k as u8,
+        builder.build()
+    }
+
+    /// The union of two filters with the same number of bits and hash functions, which matches
+    /// every key that either of them matches. Filters of different shapes put the same key at
+    /// different bits, so their union cannot be computed from the bits alone and `None` is
+    /// returned. The union holds the keys of both filters in the same number of bits, so its false
+    /// positive rate is higher than theirs.
+    pub fn union(&self, other: &Bloom) -> Option<Bloom> {
+        if self.k != other.k || self.filter.len() != other.filter.len() {
+            return None;
         }
+        let filter = self
+            .filter
+            .iter()
+            .zip(other.filter.iter())
+            .map(|(a, b)| a | b)
+            .collect::<Vec<_>>();
+        Some(Self {
+            filter: filter.into(),
+            k: self.k,
+        })
     }
 
     /// Check if a bloom filter may contain some data
     pub fn may_contain(&self, mut h: u32) -> bool {
-        if self.k > 30 {
-            // potential new encoding for short bloom filters
+        if self.is_always_match() {
             true
         } else {
             let nbits = self.filter.bit_len();
@@ -119,3 +175,40 @@ impl Bloom {
         }
     }
 }
+
+/// Builds a bloom filter incrementally, so that the key hashes do not have to be in memory at the
+/// same time. The number of keys must be known upfront to size the filter.
+pub struct BloomBuilder {
+    filter: BytesMut,
+    nbits: usize,
+    k: u32,
+}
+
+impl BloomBuilder {
+    pub fn new(num_keys: usize, bits_per_key: usize) -> Self {
+        let k = (bits_per_key as f64 * 0.69) as u32;
+        let k = k.min(MAX_K as u32).max(1);
+        let nbits = (num_keys * bits_per_key).max(64);
+        let nbytes = (nbits + 7) / 8;
+        let nbits = nbytes * 8;
+        let mut filter = BytesMut::with_capacity(nbytes);
+        filter.resize(nbytes, 0);
+        Self { filter, nbits, k }
+    }
+
+    pub fn add_key_hash(&mut self, mut h: u32) {
+        let delta = (h >> 17) | (h << 15);
+        for _ in 0..self.k {
+            let bit_pos = (h as usize) % self.nbits;
+            self.filter.set_bit(bit_pos, true);
+            h = h.wrapping_add(delta);
+        }
+    }
+
+    pub fn build(self) -> Bloom {
+        Bloom {
+            filter: self.filter.freeze(),
+            k: self.k as u8,
+        }
+    }
+}
@@ -1,14 +1,104 @@
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
-use bytes::BufMut;
+use anyhow::{anyhow, Result};
+use bytes::{BufMut, Bytes};
 
-use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
-use crate::key::{KeySlice, KeyVec};
+use super::bloom::{Bloom, BloomBuilder};
+use super::{
+    table_key_range, BlockMeta, ChecksumType, FileObject, SsTable, TableProperties, TombstoneBitmap,
+};
+use crate::block::{
+    implied_entry_flags, BlockBuilder, BLOCK_FORMAT_V2, BLOCK_FORMAT_VERSION, ENTRY_FLAG_TOMBSTONE,
+};
+use crate::key::{KeyBytes, KeySlice, KeyVec, TS_DEFAULT};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::RangeTombstone;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+
+/// Called with the meta and the encoded size of each finalized data block.
+pub type BlockFlushedCallback = Box<dyn FnMut(&BlockMeta, usize) + Send>;
+
+/// How the bloom filter of an SST is built, see [`SsTableBuilder::set_bloom_options`]. Levels
+/// can trade space for lookups differently, e.g. a low rate for L0, whose SSTs every lookup
+/// probes, and a higher one for the bottom level, which holds most of the keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomOptions {
+    /// Without a bloom filter, the SST holds a short filter matching every key, so that lookups
+    /// read a block of every SST whose key range holds their key.
+    pub enabled: bool,
+    /// The target false positive rate, from which the bits per key are derived.
+    pub fpr: f64,
+}
+
+impl Default for BloomOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fpr: 0.01,
+        }
+    }
+}
+
+/// Key hashes written to a temporary file instead of being kept in memory.
+struct SpilledKeyHashes {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    count: usize,
+    /// The first write error, reported when the SST is built since `add` cannot fail.
+    error: Option<std::io::Error>,
+}
+
+impl SpilledKeyHashes {
+    fn create(path: PathBuf, key_hashes: &[u32]) -> Result<Self> {
+        let mut spilled = Self {
+            writer: BufWriter::new(File::create(&path)?),
+            path,
+            count: 0,
+            error: None,
+        };
+        for h in key_hashes {
+            spilled.push(*h);
+        }
+        Ok(spilled)
+    }
+
+    fn push(&mut self, h: u32) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_all(&h.to_be_bytes()) {
+                self.error = Some(e);
+            }
+        }
+        self.count += 1;
+    }
+
+    /// Builds the bloom filter in a final pass over the file, reading the hashes in chunks.
+    fn build_bloom(&mut self, false_positive_rate: f64) -> Result<Bloom> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.writer.flush()?;
+        let mut builder = BloomBuilder::new(
+            self.count,
+            Bloom::bloom_bits_per_key(self.count, false_positive_rate),
+        );
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut buf = [0; 4];
+        for _ in 0..self.count {
+            reader.read_exact(&mut buf)?;
+            builder.add_key_hash(u32::from_be_bytes(buf));
+        }
+        Ok(builder.build())
+    }
+}
+
+impl Drop for SpilledKeyHashes {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -17,28 +107,142 @@ pub struct SsTableBuilder {
     last_key: KeyVec,
     data: Vec<u8>,
     pub(crate) meta: Vec<BlockMeta>,
-    block_size: usize,
     key_hashes: Vec<u32>,
+    /// When set, key hashes are appended to this file instead of `key_hashes`.
+    spilled_key_hashes: Option<SpilledKeyHashes>,
     max_ts: u64,
+    properties: TableProperties,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Set when the table is built with a tombstone bitmap.
+    tombstone_bitmap: Option<TombstoneBitmap>,
+    /// Set when every key added must be strictly greater than the previous one.
+    check_key_order: bool,
+    /// The first key added out of order, reported when the SST is built since `add` cannot fail.
+    key_order_error: Option<anyhow::Error>,
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
+    on_block_flushed: Option<BlockFlushedCallback>,
+    bloom_options: BloomOptions,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_format_version(block_size, BLOCK_FORMAT_VERSION)
+    }
+
+    /// Create a builder writing data blocks in the given format version.
+    pub fn new_with_format_version(block_size: usize, format_version: u8) -> Self {
         Self {
             data: Vec::new(),
             meta: Vec::new(),
             first_key: KeyVec::new(),
             last_key: KeyVec::new(),
-            block_size,
-            builder: BlockBuilder::new(block_size),
+            builder: BlockBuilder::new_with_format_version(block_size, format_version),
             key_hashes: Vec::new(),
+            spilled_key_hashes: None,
             max_ts: 0,
+            properties: TableProperties {
+                format_version,
+                ..Default::default()
+            },
+            range_tombstones: Vec::new(),
+            tombstone_bitmap: None,
+            check_key_order: false,
+            key_order_error: None,
+            rate_limiter: None,
+            on_block_flushed: None,
+            bloom_options: BloomOptions::default(),
         }
     }
 
-    /// Adds a key-value pair to SSTable
+    /// Record when the table is built, in seconds since the Unix epoch. Unset, the creation time
+    /// is 0 (unknown).
+    pub fn set_creation_time(&mut self, creation_time: u64) {
+        self.properties.creation_time = creation_time;
+    }
+
+    /// Follow the data blocks and the block meta with checksums of `checksum_type` instead of
+    /// [`ChecksumType::Crc32`]. Must be called before any key is added.
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        assert!(
+            self.data.is_empty() && self.builder.is_empty(),
+            "checksum type set after keys were added"
+        );
+        self.properties.checksum_type = checksum_type;
+    }
+
+    /// Build the bloom filter with `options` instead of [`BloomOptions::default`]. The rate of
+    /// an open table is reported by [`SsTable::bloom_fpr`].
+    pub fn set_bloom_options(&mut self, options: BloomOptions) {
+        assert!(
+            options.fpr > 0.0 && options.fpr < 1.0,
+            "bloom filter false positive rate must be between 0 and 1"
+        );
+        self.bloom_options = options;
+    }
+
+    /// Charge the file write of this SSTable to a rate limiter, used by flush and compaction.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>, priority: IoPriority) {
+        self.rate_limiter = Some((rate_limiter, priority));
+    }
+
+    /// Register a callback invoked whenever a data block is finalized, with the meta of the block
+    /// and its encoded size in bytes (excluding the checksum).
+    pub fn set_on_block_flushed(
+        &mut self,
+        on_block_flushed: impl FnMut(&BlockMeta, usize) + Send + 'static,
+    ) {
+        self.on_block_flushed = Some(Box::new(on_block_flushed));
+    }
+
+    /// Write a [`TombstoneBitmap`] after the bloom filter, which lets iterators skipping
+    /// tombstones avoid decoding them. Must be called before any entry is added.
+    pub fn enable_tombstone_bitmap(&mut self) {
+        assert_eq!(
+            self.properties.num_entries, 0,
+            "the tombstone bitmap must be enabled before adding entries"
+        );
+        self.tombstone_bitmap = Some(TombstoneBitmap::default());
+    }
+
+    /// Check that every key added is strictly greater than the previous one, so that building
+    /// the SST fails if the same key (and timestamp) is added twice, instead of writing a table
+    /// that reads back one of the duplicates at random.
+    pub fn enable_key_order_check(&mut self) {
+        self.check_key_order = true;
+    }
+
+    /// Stream the key hashes collected for the bloom filter to a temporary file at `path` instead
+    /// of keeping them in memory, for building SSTs with a huge number of keys. The bloom filter
+    /// is built from the file when the SST is built, and the file is removed afterwards.
+    pub fn spill_key_hashes_to(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        assert!(
+            self.spilled_key_hashes.is_none(),
+            "key hashes are already spilled"
+        );
+        let key_hashes = std::mem::take(&mut self.key_hashes);
+        self.spilled_key_hashes = Some(SpilledKeyHashes::create(
+            path.as_ref().to_path_buf(),
+            &key_hashes,
+        )?);
+        Ok(())
+    }
+
+    /// Adds a key-value pair to SSTable. An empty value is a tombstone.
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_with_flags(key, value, implied_entry_flags(value))
+    }
+
+    /// Adds a key-value pair with entry flags, e.g. [`crate::block::ENTRY_FLAG_MERGE_OPERAND`],
+    /// to SSTable. Flags other than those implied by the value need a format version storing
+    /// them, see [`crate::block::BLOCK_FORMAT_V6`].
+    pub fn add_with_flags(&mut self, key: KeySlice, value: &[u8], flags: u8) {
+        let key = if self.properties.format_version < BLOCK_FORMAT_V2 {
+            // keep the block meta consistent with the keys read back from the blocks
+            KeySlice::from_slice(key.key_ref(), TS_DEFAULT)
+        } else {
+            key
+        };
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -46,9 +250,53 @@ impl SsTableBuilder {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
-        self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        let previous_key = if self.last_key.is_empty() {
+            self.meta.last().map(|meta| meta.last_key.as_key_slice())
+        } else {
+            Some(self.last_key.as_key_slice())
+        };
+        if self.check_key_order && self.key_order_error.is_none() {
+            if let Some(previous_key) = previous_key.filter(|previous_key| key <= *previous_key) {
+                self.key_order_error = Some(if key == previous_key {
+                    anyhow!(
+                        "duplicate key {:?} at ts {} added to the SST",
+                        Bytes::copy_from_slice(key.key_ref()),
+                        key.ts()
+                    )
+                } else {
+                    anyhow!(
+                        "key {:?} at ts {} added to the SST after key {:?} at ts {}",
+                        Bytes::copy_from_slice(key.key_ref()),
+                        key.ts(),
+                        Bytes::copy_from_slice(previous_key.key_ref()),
+                        previous_key.ts()
+                    )
+                });
+            }
+        }
+        if previous_key.map(|previous_key| previous_key.key_ref()) == Some(key.key_ref()) {
+            self.properties.num_shadowed_entries += 1;
+        } else {
+            // the bloom filter is sized for the distinct user keys, not for all their versions
+            self.properties.num_distinct_keys += 1;
+            let key_hash = farmhash::fingerprint32(key.key_ref());
+            match &mut self.spilled_key_hashes {
+                Some(spilled) => spilled.push(key_hash),
+                None => self.key_hashes.push(key_hash),
+            }
+        }
+        self.properties.num_entries += 1;
+        let is_tombstone = flags & ENTRY_FLAG_TOMBSTONE != 0;
+        if is_tombstone {
+            self.properties.num_tombstones += 1;
+        } else {
+            self.properties.value_sizes.add(value.len() as u64);
+        }
+        if let Some(tombstone_bitmap) = &mut self.tombstone_bitmap {
+            tombstone_bitmap.push(is_tombstone);
+        }
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_flags(key, value, flags) {
             self.last_key.set_from_slice(key);
             return;
         }
@@ -57,27 +305,54 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_flags(key, value, flags));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
     }
 
+    /// Adds a range tombstone to the SSTable. The key range of the table is extended to cover it.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(tombstone.ts);
+        self.range_tombstones.push(tombstone);
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
     }
 
+    /// Whether no key has been added since the builder was created or reset.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.builder.is_empty()
+    }
+
+    /// Encodes the current block into `data`, keeping the buffers of the block builder and the
+    /// key scratch space for the next block.
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
-        let encoded_block = builder.build().encode();
+        let begin = self.data.len();
+        self.builder.encode_into(&mut self.data);
+        let num_entries = self.builder.num_entries() as u32;
+        self.builder.reset();
         self.meta.push(BlockMeta {
-            offset: self.data.len(),
-            first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
-            last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
+            offset: begin,
+            first_key: KeyBytes::from_bytes_with_ts(
+                Bytes::copy_from_slice(self.first_key.key_ref()),
+                self.first_key.ts(),
+            ),
+            last_key: KeyBytes::from_bytes_with_ts(
+                Bytes::copy_from_slice(self.last_key.key_ref()),
+                self.last_key.ts(),
+            ),
+            num_entries,
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
-        self.data.put_u32(checksum);
+        self.first_key.clear();
+        self.last_key.clear();
+        if let Some(on_block_flushed) = &mut self.on_block_flushed {
+            on_block_flushed(self.meta.last().unwrap(), self.data.len() - begin);
+        }
+        self.properties
+            .checksum_type
+            .put_checksum(&mut self.data, begin);
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
@@ -87,32 +362,144 @@ impl SsTableBuilder {
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
-        let mut buf = self.data;
+        self.build_and_reset(id, block_cache, path)
+    }
+
+    /// Builds the SSTable like [`Self::build`], then resets the builder for the next SSTable. The
+    /// buffers are cleared, not freed, so that writing many SSTables in a row (e.g. the outputs of
+    /// a compaction) does not allocate them again. The block size, format version, creation time,
+    /// rate limiter, block callback, bloom options, tombstone bitmap and key order check settings
+    /// are kept, while spilling key hashes has to be enabled again.
+    pub fn build_and_reset(
+        &mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        let result = self.build_inner(id, block_cache, path.as_ref(), None);
+        self.reset();
+        result
+    }
+
+    /// Builds the SSTable like [`Self::build`], but writes the meta, the bloom filter and the
+    /// footer to `index_path` instead of after the blocks, so that the small index can be fetched
+    /// without the blocks, e.g. from remote storage. Open the table with
+    /// [`SsTable::open_with_index`]. The data file holds the blocks exactly as a single-file
+    /// table does, each followed by its checksum, so the block boundaries, and with them the
+    /// index, can be re-derived from the data file alone; appending the index file to the data
+    /// file gives a valid single-file table.
+    pub fn build_with_separate_index(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+        index_path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        self.build_inner(id, block_cache, path.as_ref(), Some(index_path.as_ref()))
+    }
+
+    fn build_inner(
+        &mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: &Path,
+        index_path: Option<&Path>,
+    ) -> Result<SsTable> {
+        if let Some(e) = self.key_order_error.take() {
+            return Err(e);
+        }
+        // a table may hold nothing but range tombstones
+        if !self.builder.is_empty() || self.range_tombstones.is_empty() {
+            self.finish_block();
+        }
+        let fpr = self.bloom_options.fpr;
+        let bloom = match &mut self.spilled_key_hashes {
+            // the key range check is exact for a single key
+            _ if self.properties.num_distinct_keys <= 1 => Bloom::always_match(),
+            _ if !self.bloom_options.enabled => Bloom::always_match(),
+            Some(spilled) => spilled.build_bloom(fpr)?,
+            None => Bloom::build_from_key_hashes(
+                &self.key_hashes,
+                Bloom::bloom_bits_per_key(self.key_hashes.len(), fpr),
+            ),
+        };
+        self.properties.num_bloom_bits = bloom.filter.len() as u64 * 8;
+        let buf = &mut self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &self.properties, buf);
+        if !self.range_tombstones.is_empty() {
+            RangeTombstone::encode_section(&self.range_tombstones, buf);
+        }
         buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
         let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
+        bloom.encode(buf);
         buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
+        let tombstone_bitmap = match &mut self.tombstone_bitmap {
+            Some(tombstone_bitmap) => {
+                tombstone_bitmap.set_blocks(&self.meta)?;
+                tombstone_bitmap.encode(buf);
+                Some(tombstone_bitmap.clone())
+            }
+            None => None,
+        };
+        if let Some((rate_limiter, priority)) = &self.rate_limiter {
+            rate_limiter.request(buf.len(), *priority);
+        }
+        let (file, index_file) = match index_path {
+            Some(index_path) => {
+                let (data, index) = buf.split_at(meta_offset);
+                let index_file = FileObject::create(index_path, index)?;
+                (FileObject::create(path, data)?, Some(index_file))
+            }
+            None => (FileObject::create(path, buf)?, None),
+        };
+        let block_meta = std::mem::take(&mut self.meta);
+        let range_tombstones = std::mem::take(&mut self.range_tombstones);
+        let (first_key, last_key) = table_key_range(&block_meta, &range_tombstones);
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
-            block_meta: self.meta,
+            first_key,
+            last_key,
+            block_meta,
             block_meta_offset: meta_offset,
+            index_file,
             block_cache,
+            cache_namespace: 0,
+            metrics: None,
             bloom: Some(bloom),
             max_ts: self.max_ts,
+            properties: self.properties,
+            shard: None,
+            range_tombstones,
+            tombstone_bitmap,
+            bloom_probes: Default::default(),
         })
     }
 
+    /// Clears the builder for a new SSTable, keeping its buffers and settings.
+    pub fn reset(&mut self) {
+        self.builder.reset();
+        self.first_key.clear();
+        self.last_key.clear();
+        self.data.clear();
+        self.meta.clear();
+        self.key_hashes.clear();
+        self.range_tombstones.clear();
+        if let Some(tombstone_bitmap) = &mut self.tombstone_bitmap {
+            tombstone_bitmap.clear();
+        }
+        self.spilled_key_hashes = None;
+        self.key_order_error = None;
+        self.max_ts = 0;
+        self.properties = TableProperties {
+            format_version: self.properties.format_version,
+            checksum_type: self.properties.checksum_type,
+            creation_time: self.properties.creation_time,
+            ..Default::default()
+        };
+    }
+
     #[cfg(test)]
     pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable> {
         self.build(0, None, path)
@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+/// The bits of the format version byte of the table properties holding the checksum type, so
+/// that tables with CRC32 checksums keep the encoding they had before checksum types existed.
+const CHECKSUM_TYPE_MASK: u8 = 0xc0;
+
+/// The checksum following each data block and the block meta of an SST, chosen with
+/// [`super::SsTableBuilder::set_checksum_type`]. The bloom filter, the tombstone bitmap and the
+/// range tombstones are small and always checked with CRC32.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumType {
+    /// A u32 CRC32 (IEEE), the smallest.
+    #[default]
+    Crc32,
+    /// A u64 CRC-64/XZ, for very large SSTs, where millions of blocks make an undetected
+    /// corruption of CRC32 likely enough to matter.
+    Crc64,
+}
+
+impl ChecksumType {
+    /// The size of a checksum on the disk.
+    pub fn size(self) -> usize {
+        match self {
+            Self::Crc32 => std::mem::size_of::<u32>(),
+            Self::Crc64 => std::mem::size_of::<u64>(),
+        }
+    }
+
+    pub fn checksum(self, data: &[u8]) -> u64 {
+        match self {
+            Self::Crc32 => crc32fast::hash(data) as u64,
+            Self::Crc64 => {
+                let mut digest = crc64fast::Digest::new();
+                digest.write(data);
+                digest.sum64()
+            }
+        }
+    }
+
+    /// A checksum to feed the data piece by piece.
+    pub(crate) fn digest(self) -> Digest {
+        match self {
+            Self::Crc32 => Digest::Crc32(crc32fast::Hasher::new()),
+            Self::Crc64 => Digest::Crc64(crc64fast::Digest::new()),
+        }
+    }
+
+    /// Appends the checksum of `buf[begin..]` to `buf`.
+    pub(crate) fn put_checksum(self, buf: &mut Vec<u8>, begin: usize) {
+        let checksum = self.checksum(&buf[begin..]);
+        match self {
+            Self::Crc32 => buf.put_u32(checksum as u32),
+            Self::Crc64 => buf.put_u64(checksum),
+        }
+    }
+
+    pub(crate) fn get_checksum(self, buf: &mut &[u8]) -> u64 {
+        match self {
+            Self::Crc32 => buf.get_u32() as u64,
+            Self::Crc64 => buf.get_u64(),
+        }
+    }
+
+    /// Checks `data`, which ends with its checksum, and returns it without the checksum, or
+    /// `None` if the checksum does not match.
+    pub(crate) fn verify(self, data: &[u8]) -> Option<&[u8]> {
+        let (body, mut checksum) = data.split_at(data.len().checked_sub(self.size())?);
+        (self.get_checksum(&mut checksum) == self.checksum(body)).then_some(body)
+    }
+
+    /// Combines the type with `format_version` into the format version byte of the properties.
+    pub(crate) fn encode_with_format_version(self, format_version: u8) -> u8 {
+        debug_assert_eq!(format_version & CHECKSUM_TYPE_MASK, 0);
+        match self {
+            Self::Crc32 => format_version,
+            Self::Crc64 => format_version | 0x40,
+        }
+    }
+
+    /// Splits the format version byte of the properties into the format version and the type.
+    pub(crate) fn decode_with_format_version(byte: u8) -> Result<(u8, Self)> {
+        let checksum_type = match byte & CHECKSUM_TYPE_MASK {
+            0 => Self::Crc32,
+            0x40 => Self::Crc64,
+            bits => bail!("unknown checksum type {:#x}", bits),
+        };
+        Ok((byte & !CHECKSUM_TYPE_MASK, checksum_type))
+    }
+}
+
+/// A checksum computed incrementally, see [`ChecksumType::digest`].
+pub(crate) enum Digest {
+    Crc32(crc32fast::Hasher),
+    Crc64(crc64fast::Digest),
+}
+
+impl Digest {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(hasher) => hasher.update(data),
+            Self::Crc64(digest) => digest.write(data),
+        }
+    }
+
+    /// The checksum of the data fed so far, which can be fed more afterwards.
+    pub(crate) fn value(&self) -> u64 {
+        match self {
+            Self::Crc32(hasher) => hasher.clone().finalize() as u64,
+            Self::Crc64(digest) => digest.sum64(),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{bail, Result};
+
+use super::{FileBackend, FileObject};
+
+/// An in-memory file that corrupts what is read from it, for testing how the SST reader handles
+/// damaged or unreliable storage. Turn it into a [`FileObject`] to open an [`super::SsTable`] on
+/// it; faults are injected at known offsets, e.g. the `block_meta_offset` of the table the data
+/// was built as.
+pub(crate) struct FaultyFileObject {
+    data: Vec<u8>,
+    /// Byte ranges whose bits are inverted when read.
+    flipped: Vec<Range<u64>>,
+    /// Reads return at most this many bytes.
+    max_read_len: Option<u64>,
+    /// The read (counting from 1) that fails with an I/O error.
+    fail_on_read: Option<usize>,
+    reads: AtomicUsize,
+}
+
+impl FaultyFileObject {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            flipped: Vec::new(),
+            max_read_len: None,
+            fail_on_read: None,
+            reads: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn from_file(path: &Path) -> Result<Self> {
+        Ok(Self::new(std::fs::read(path)?))
+    }
+
+    /// Invert every bit of the bytes in `range`.
+    pub(crate) fn flip_bytes(mut self, range: Range<u64>) -> Self {
+        self.flipped.push(range);
+        self
+    }
+
+    /// Return at most `max_read_len` bytes from each read, as a short read would.
+    pub(crate) fn short_reads(mut self, max_read_len: u64) -> Self {
+        self.max_read_len = Some(max_read_len);
+        self
+    }
+
+    /// Fail the `n`-th read, counting from 1. Other reads succeed.
+    pub(crate) fn fail_on_read(mut self, n: usize) -> Self {
+        self.fail_on_read = Some(n);
+        self
+    }
+
+    pub(crate) fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let n = self.reads.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.fail_on_read == Some(n) {
+            return Err(std::io::Error::other(format!("injected failure of read {}", n)).into());
+        }
+        let end = offset + len.min(self.max_read_len.unwrap_or(u64::MAX));
+        if end > self.data.len() as u64 {
+            bail!(
+                "read of {}..{} past the end of the file ({} bytes)",
+                offset,
+                end,
+                self.data.len()
+            );
+        }
+        let mut data = self.data[offset as usize..end as usize].to_vec();
+        for range in &self.flipped {
+            for pos in range.start.max(offset)..range.end.min(end) {
+                data[(pos - offset) as usize] ^= 0xff;
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl From<FaultyFileObject> for FileObject {
+    fn from(file: FaultyFileObject) -> Self {
+        let size = file.data.len() as u64;
+        FileObject(Some(FileBackend::Faulty(file)), size)
+    }
+}
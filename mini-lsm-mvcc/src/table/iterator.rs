@@ -1,76 +1,402 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
-use super::SsTable;
-use crate::block::BlockIterator;
+use super::{BlockReadOptions, CacheHint, SsTable};
+use crate::block::{Block, BlockIterator};
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+
+/// The number of bytes compaction reads from an SST with a single read.
+pub const COMPACTION_READAHEAD_BYTES: usize = 256 * 1024;
+
+/// Block reads issued by a compaction iterator. Compaction scans each SST once, so blocks are
+/// read ahead in large sequential reads and never inserted into the block cache, where they
+/// would evict the blocks used by foreground reads.
+struct CompactionReader {
+    rate_limiter: Arc<RateLimiter>,
+    /// Blocks that have been read ahead but not yet visited, in ascending order of block index.
+    readahead: VecDeque<(usize, Arc<Block>)>,
+}
+
+impl CompactionReader {
+    fn read_block(&mut self, table: &SsTable, blk_idx: usize) -> Result<Arc<Block>> {
+        while let Some((idx, block)) = self.readahead.pop_front() {
+            if idx == blk_idx {
+                return Ok(block);
+            }
+        }
+        if let Some(block) = table.get_cached_block(blk_idx) {
+            return Ok(block);
+        }
+        let blocks = table.read_blocks(blk_idx, COMPACTION_READAHEAD_BYTES)?;
+        self.rate_limiter.request(
+            table.blocks_len_on_disk(blk_idx, blk_idx + blocks.len()),
+            IoPriority::Low,
+        );
+        let mut blocks = (blk_idx..).zip(blocks);
+        let (_, block) = blocks.next().unwrap();
+        self.readahead.extend(blocks);
+        Ok(block)
+    }
+}
+
+/// How an [`SsTableIterator`] reads blocks.
+enum BlockReads {
+    /// Through the block cache as told by the options.
+    Cached(BlockReadOptions),
+    /// For compaction, see [`CompactionReader`].
+    Compaction(CompactionReader),
+}
+
+impl BlockReads {
+    fn with_rate_limiter(rate_limiter: Option<Arc<RateLimiter>>) -> Self {
+        match rate_limiter {
+            Some(rate_limiter) => Self::Compaction(CompactionReader {
+                rate_limiter,
+                readahead: VecDeque::new(),
+            }),
+            None => Self::Cached(BlockReadOptions::default()),
+        }
+    }
+}
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    reads: BlockReads,
+    skip_tombstones: bool,
+    /// Whether the iterator moves in descending key order.
+    reverse: bool,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+    fn read_block(table: &SsTable, blk_idx: usize, reads: &mut BlockReads) -> Result<Arc<Block>> {
+        match reads {
+            BlockReads::Cached(options) => table.read_block_with_options(blk_idx, *options),
+            BlockReads::Compaction(reader) => reader.read_block(table, blk_idx),
+        }
+    }
+
+    fn create_and_seek_to_first_inner(table: Arc<SsTable>, mut reads: BlockReads) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table, &mut reads)?;
+        Ok(Self {
+            blk_iter,
+            table,
+            blk_idx,
+            reads,
+            skip_tombstones: false,
+            reverse: false,
+        })
+    }
+
+    fn create_and_seek_to_key_inner(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        mut reads: BlockReads,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key, &mut reads)?;
+        Ok(Self {
+            blk_iter,
+            table,
+            blk_idx,
+            reads,
+            skip_tombstones: false,
+            reverse: false,
+        })
+    }
+
+    fn seek_to_first_inner(
+        table: &Arc<SsTable>,
+        reads: &mut BlockReads,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, BlockIterator::empty()));
+        }
         Ok((
             0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
+            BlockIterator::create_and_seek_to_first(Self::read_block(table, 0, reads)?),
         ))
     }
 
-    /// Create a new iterator and seek to the first key-value pair.
+    /// Create a new iterator and seek to the first key-value pair. Iterators never consult the
+    /// bloom filter, which is only useful for point lookups.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
-        Ok(iter)
+        Self::create_and_seek_to_first_with_rate_limiter(table, None)
+    }
+
+    /// Create a new iterator for compaction and seek to the first key-value pair. If a rate
+    /// limiter is given, blocks are read ahead without filling the block cache, and reads from
+    /// the disk are charged to the rate limiter.
+    pub fn create_and_seek_to_first_with_rate_limiter(
+        table: Arc<SsTable>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(table, BlockReads::with_rate_limiter(rate_limiter))
+    }
+
+    /// Create a new iterator that reads every block from the disk without looking it up in or
+    /// inserting it into the block cache, and seek to the first key-value pair. This is meant
+    /// for scans that read each block once, which would otherwise evict the blocks of live
+    /// queries from the cache.
+    pub fn create_and_seek_to_first_no_cache(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_cache_hint(table, CacheHint::Bypass)
+    }
+
+    /// Create a new iterator reading blocks through the block cache as told by `hint`, and seek
+    /// to the first key-value pair.
+    pub fn create_and_seek_to_first_with_cache_hint(
+        table: Arc<SsTable>,
+        hint: CacheHint,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_with_read_options(table, hint.into())
+    }
+
+    /// Create a new iterator reading blocks as told by `options`, and seek to the first
+    /// key-value pair.
+    pub fn create_and_seek_to_first_with_read_options(
+        table: Arc<SsTable>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_first_inner(table, BlockReads::Cached(options))
     }
 
     /// Seek to the first key-value pair.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table, &mut self.reads)?;
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
-        Ok(())
+        self.skip_tombstones_from_current()
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
+    fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        reads: &mut BlockReads,
+    ) -> Result<(usize, BlockIterator)> {
+        let Some(mut blk_idx) = table.find_block_idx(key) else {
+            return Ok((0, BlockIterator::empty()));
+        };
         let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
+            BlockIterator::create_and_seek_to_key(Self::read_block(table, blk_idx, reads)?, key);
         if !blk_iter.is_valid() {
             blk_idx += 1;
             if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
+                blk_iter = BlockIterator::create_and_seek_to_first(Self::read_block(
+                    table, blk_idx, reads,
+                )?);
             }
         }
         Ok((blk_idx, blk_iter))
     }
 
-    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    /// Create a new iterator and seek to the first key-value pair which >= `key`. Like
+    /// [`SsTableIterator::create_and_seek_to_first`], this never consults the bloom filter, as
+    /// the iterator may be used for a range scan starting at `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
-        Ok(iter)
+        Self::create_and_seek_to_key_with_rate_limiter(table, key, None)
+    }
+
+    /// Create a new iterator for compaction and seek to the first key-value pair which >= `key`.
+    /// Blocks are read the same way as [`SsTableIterator::create_and_seek_to_first_with_rate_limiter`].
+    pub fn create_and_seek_to_key_with_rate_limiter(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(table, key, BlockReads::with_rate_limiter(rate_limiter))
+    }
+
+    /// Create a new iterator that bypasses the block cache like
+    /// [`SsTableIterator::create_and_seek_to_first_no_cache`], and seek to the first key-value
+    /// pair which >= `key`.
+    pub fn create_and_seek_to_key_no_cache(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_to_key_with_cache_hint(table, key, CacheHint::Bypass)
+    }
+
+    /// Create a new iterator reading blocks through the block cache as told by `hint`, and seek
+    /// to the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key_with_cache_hint(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        hint: CacheHint,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_with_read_options(table, key, hint.into())
+    }
+
+    /// Create a new iterator reading blocks as told by `options`, and seek to the first
+    /// key-value pair which >= `key`.
+    pub fn create_and_seek_to_key_with_read_options(
+        table: Arc<SsTable>,
+        key: KeySlice,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(table, key, BlockReads::Cached(options))
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key, &mut self.reads)?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
+        self.skip_tombstones_from_current()
+    }
+
+    /// Create a new iterator moving in descending key order, and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_last_inner(table, true)
+    }
+
+    /// Create a new iterator moving in ascending key order, and seek to the last key-value pair,
+    /// e.g. to move back from there with [`Self::prev`].
+    pub(crate) fn create_and_seek_to_last_ascending(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_last_inner(table, false)
+    }
+
+    fn create_and_seek_to_last_inner(table: Arc<SsTable>, reverse: bool) -> Result<Self> {
+        let mut reads = BlockReads::Cached(BlockReadOptions::default());
+        let blk_iter = match table.num_of_blocks().checked_sub(1) {
+            Some(blk_idx) => BlockIterator::create_and_seek_to_last(Self::read_block(
+                &table, blk_idx, &mut reads,
+            )?),
+            None => BlockIterator::empty(),
+        };
+        Ok(Self {
+            blk_idx: table.num_of_blocks().saturating_sub(1),
+            blk_iter,
+            table,
+            reads,
+            skip_tombstones: false,
+            reverse,
+        })
+    }
+
+    /// Create a new iterator moving in descending key order, and seek to the last key-value pair
+    /// which <= `key`.
+    pub fn create_and_seek_to_key_rev(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        let mut reads = BlockReads::Cached(BlockReadOptions::default());
+        let (mut blk_idx, mut blk_iter) = Self::seek_to_key_inner(&table, key, &mut reads)?;
+        // the first entry >= `key` is found, unless it is `key` the entry before it is the last
+        // one < `key`, which may be the last entry of the previous block
+        if blk_iter.is_valid() && blk_iter.key() != key {
+            blk_iter.prev();
+        }
+        if !blk_iter.is_valid() && blk_idx > 0 {
+            blk_idx -= 1;
+            blk_iter = BlockIterator::create_and_seek_to_last(Self::read_block(
+                &table, blk_idx, &mut reads,
+            )?);
+        }
+        Ok(Self {
+            blk_iter,
+            table,
+            blk_idx,
+            reads,
+            skip_tombstones: false,
+            reverse: true,
+        })
+    }
+
+    /// Skip the tombstones (see [`StorageIterator::is_tombstone`]) from now on, starting with the current
+    /// entry. If the table has a [`super::TombstoneBitmap`], runs of tombstones are skipped
+    /// without decoding them and blocks holding only tombstones are never read; otherwise every
+    /// entry is decoded and checked.
+    ///
+    /// The skipped tombstones no longer hide the older versions of their keys in the table, so
+    /// this is meant for readers of tables holding a single version of each key, such as the
+    /// bottom level after a full compaction.
+    pub fn skip_tombstones(&mut self) -> Result<()> {
+        assert!(
+            !self.reverse,
+            "tombstones are only skipped in ascending order"
+        );
+        self.skip_tombstones = true;
+        self.skip_tombstones_from_current()
+    }
+
+    fn skip_tombstones_from_current(&mut self) -> Result<()> {
+        if !self.skip_tombstones || !self.blk_iter.is_valid() {
+            return Ok(());
+        }
+        let Some(bitmap) = self.table.tombstone_bitmap() else {
+            while self.blk_iter.is_valid() && self.blk_iter.is_tombstone() {
+                self.next_entry()?;
+            }
+            return Ok(());
+        };
+        let entry = bitmap.block_start(self.blk_idx) + self.blk_iter.index();
+        let live_entry = bitmap.next_live_entry(entry);
+        self.seek_to_entry(live_entry)
+    }
+
+    /// Moves to the `entry`-th entry of the table, or past the end for `None`, reading the block
+    /// holding it unless it is the current block. Only for tables with a tombstone bitmap.
+    fn seek_to_entry(&mut self, entry: Option<usize>) -> Result<()> {
+        let Some(entry) = entry else {
+            self.blk_idx = self.table.num_of_blocks();
+            self.blk_iter = BlockIterator::empty();
+            return Ok(());
+        };
+        let (blk_idx, idx) = self.table.tombstone_bitmap().unwrap().locate(entry);
+        if blk_idx != self.blk_idx {
+            self.blk_iter = BlockIterator::create_and_seek_to_first(Self::read_block(
+                &self.table,
+                blk_idx,
+                &mut self.reads,
+            )?);
+            self.blk_idx = blk_idx;
+        }
+        if self.blk_iter.index() != idx {
+            self.blk_iter.seek_to_index(idx);
+        }
+        Ok(())
+    }
+
+    /// Moves to the previous entry in key order, whichever the direction of the iterator, and
+    /// becomes invalid when moving back from the first entry. Does nothing once invalid.
+    pub fn prev(&mut self) -> Result<()> {
+        assert!(
+            !self.skip_tombstones,
+            "tombstones are only skipped in ascending order"
+        );
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.prev_entry()
+    }
+
+    /// Moves to the previous entry, in descending order.
+    fn prev_entry(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter = BlockIterator::create_and_seek_to_last(Self::read_block(
+                &self.table,
+                self.blk_idx,
+                &mut self.reads,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Moves to the next entry, tombstone or not.
+    fn next_entry(&mut self) -> Result<()> {
+        self.blk_iter.next();
+        if !self.blk_iter.is_valid() {
+            self.blk_idx += 1;
+            if self.blk_idx < self.table.num_of_blocks() {
+                self.blk_iter = BlockIterator::create_and_seek_to_first(Self::read_block(
+                    &self.table,
+                    self.blk_idx,
+                    &mut self.reads,
+                )?);
+            }
+        }
         Ok(())
     }
 }
@@ -82,6 +408,18 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.value()
     }
 
+    fn value_bytes(&self) -> Bytes {
+        self.blk_iter.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.blk_iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.blk_iter.is_tombstone()
+    }
+
     fn key(&self) -> KeySlice {
         self.blk_iter.key()
     }
@@ -91,15 +429,78 @@ impl StorageIterator for SsTableIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        self.blk_iter.next();
-        if !self.blk_iter.is_valid() {
-            self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
+        if self.reverse {
+            return self.prev_entry();
+        }
+        if self.skip_tombstones && self.blk_iter.is_valid() {
+            if let Some(bitmap) = self.table.tombstone_bitmap() {
+                // find the next live entry without decoding the tombstones before it
+                let entry = bitmap.block_start(self.blk_idx) + self.blk_iter.index();
+                let live_entry = bitmap.next_live_entry(entry + 1);
+                return self.seek_to_entry(live_entry);
             }
         }
-        Ok(())
+        self.next_entry()?;
+        self.skip_tombstones_from_current()
+    }
+}
+
+/// An [`SsTableIterator`] that stops before the first entry whose user key is >= `upper`.
+pub(crate) struct BoundedSsTableIterator {
+    iter: SsTableIterator,
+    upper: Option<Bytes>,
+}
+
+impl BoundedSsTableIterator {
+    /// Create an iterator over the user key range `[lower, upper)`, where `None` is unbounded.
+    pub(crate) fn create(
+        table: Arc<SsTable>,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<Self> {
+        let iter = match lower {
+            Some(lower) => SsTableIterator::create_and_seek_to_key(
+                table,
+                KeySlice::from_slice(lower, TS_RANGE_BEGIN),
+            )?,
+            None => SsTableIterator::create_and_seek_to_first(table)?,
+        };
+        Ok(Self {
+            iter,
+            upper: upper.map(Bytes::copy_from_slice),
+        })
+    }
+}
+
+impl StorageIterator for BoundedSsTableIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn value_bytes(&self) -> Bytes {
+        self.iter.value_bytes()
+    }
+
+    fn flags(&self) -> u8 {
+        self.iter.flags()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.iter.is_tombstone()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+            && !matches!(&self.upper, Some(upper) if self.iter.key().key_ref() >= upper.as_ref())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
     }
 }
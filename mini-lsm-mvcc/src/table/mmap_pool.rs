@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+use parking_lot::Mutex;
+
+struct MmapPoolInner {
+    /// Live mappings and the logical time they were last used.
+    maps: HashMap<PathBuf, (Arc<Mmap>, u64)>,
+    clock: u64,
+}
+
+/// A pool of read-only mappings shared by many SSTs. The file descriptor is closed as soon as a
+/// file is mapped, and at most `capacity` mappings are kept alive; the least recently used one is
+/// unmapped when the pool is full and re-mapped on its next read.
+pub struct MmapPool {
+    capacity: usize,
+    inner: Mutex<MmapPoolInner>,
+}
+
+impl MmapPool {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "mmap pool capacity must be positive");
+        Self {
+            capacity,
+            inner: Mutex::new(MmapPoolInner {
+                maps: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    fn map(&self, path: &Path) -> Result<Arc<Mmap>> {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let clock = inner.clock;
+        if let Some((mmap, last_used)) = inner.maps.get_mut(path) {
+            *last_used = clock;
+            return Ok(mmap.clone());
+        }
+        let file = File::open(path)?;
+        // SAFETY: SST files are immutable once written, so the mapping is never modified.
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        while inner.maps.len() >= self.capacity {
+            let lru = inner
+                .maps
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(path, _)| path.clone())
+                .unwrap();
+            inner.maps.remove(&lru);
+        }
+        inner.maps.insert(path.to_path_buf(), (mmap.clone(), clock));
+        Ok(mmap)
+    }
+
+    /// Read `len` bytes at `offset` from the file at `path`.
+    pub(crate) fn read(&self, path: &Path, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mmap = self.map(path)?;
+        let (offset, len) = (offset as usize, len as usize);
+        if offset + len > mmap.len() {
+            bail!("read out of range: {}+{} > {}", offset, len, mmap.len());
+        }
+        Ok(mmap[offset..offset + len].to_vec())
+    }
+
+    /// Get the size of the file at `path`.
+    pub(crate) fn file_size(&self, path: &Path) -> Result<u64> {
+        Ok(self.map(path)?.len() as u64)
+    }
+
+    /// Unmap the file at `path`, called when its SST is dropped.
+    pub(crate) fn release(&self, path: &Path) {
+        self.inner.lock().maps.remove(path);
+    }
+
+    /// Number of live mappings.
+    pub fn num_mappings(&self) -> usize {
+        self.inner.lock().maps.len()
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes};
+
+use super::{
+    table_key_range, BlockMeta, Bloom, ChecksumType, FileObject, SsTable, TableProperties,
+};
+use crate::block::{
+    get_key_len, Block, BlockIterator, BlockRef, BLOCK_FORMAT_V1, BLOCK_FORMAT_V2, BLOCK_FORMAT_V4,
+    BLOCK_FORMAT_V5, BLOCK_FORMAT_V6, ENTRY_FLAG_TOMBSTONE, SIZEOF_U16,
+};
+use crate::key::KeyVec;
+
+/// The block formats the first block is tried in. Blocks of [`crate::block::BLOCK_FORMAT_V3`]
+/// read the same as [`BLOCK_FORMAT_V2`] ones, and blocks of [`BLOCK_FORMAT_V6`] the same as
+/// [`BLOCK_FORMAT_V5`] ones, until an entry with [`ENTRY_FLAG_TOMBSTONE`] tells them apart.
+const FORMAT_VERSIONS: [u8; 4] = [
+    BLOCK_FORMAT_V2,
+    BLOCK_FORMAT_V4,
+    BLOCK_FORMAT_V5,
+    BLOCK_FORMAT_V1,
+];
+
+impl SsTable {
+    /// Rebuilds the index of a table whose block meta, bloom filter or footer is corrupt, from
+    /// its data blocks, which are scanned from the start of `file`. A block ends where the bytes
+    /// before are followed by their checksum and decode into entries laid out end to end, in
+    /// ascending key order. The scan stops at the first offset where no block is found, which is
+    /// taken as the start of the meta. The checksum type and the block format are detected from
+    /// the first block.
+    ///
+    /// The properties and the bloom filter are rebuilt from the entries. What only the meta held
+    /// is lost: the range tombstones, the tombstone bitmap and the creation time. The table has
+    /// id 0 and no block cache.
+    pub fn rebuild_index(file: FileObject) -> Result<SsTable> {
+        let data = Bytes::from(file.read(0, file.size())?);
+        let Some((checksum_type, mut format_version, first_block)) =
+            [ChecksumType::Crc32, ChecksumType::Crc64]
+                .into_iter()
+                .find_map(|checksum_type| {
+                    let (block, format_version) =
+                        scan_block(&data, 0, checksum_type, &FORMAT_VERSIONS)?;
+                    Some((checksum_type, format_version, block))
+                })
+        else {
+            bail!("no data block found at the start of the table");
+        };
+
+        let mut block_meta = Vec::new();
+        let mut properties = TableProperties {
+            format_version,
+            checksum_type,
+            ..Default::default()
+        };
+        let mut max_ts = 0;
+        let mut key_hashes = Vec::new();
+        let mut last_key = KeyVec::new();
+        let mut offset = 0;
+        let mut block = Some(first_block);
+        while let Some(current) = block {
+            let len = current.raw().len() + checksum_type.size();
+            if format_version == BLOCK_FORMAT_V5 && stores_tombstone_flag(&current) {
+                format_version = BLOCK_FORMAT_V6;
+            }
+            let mut iter = BlockIterator::create_and_seek_to_first_ref(current);
+            let first_key = iter.key().to_key_vec().into_key_bytes();
+            let mut num_entries = 0;
+            while iter.is_valid() {
+                let key = iter.key();
+                max_ts = max_ts.max(key.ts());
+                if key.key_ref() == last_key.key_ref() {
+                    properties.num_shadowed_entries += 1;
+                } else {
+                    properties.num_distinct_keys += 1;
+                    key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+                }
+                last_key.set_from_slice(key);
+                properties.num_entries += 1;
+                if iter.is_tombstone() {
+                    properties.num_tombstones += 1;
+                } else {
+                    properties.value_sizes.add(iter.value().len() as u64);
+                }
+                num_entries += 1;
+                iter.next();
+            }
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key: last_key.clone().into_key_bytes(),
+                num_entries,
+            });
+            offset += len;
+            block =
+                scan_block(&data, offset, checksum_type, &[format_version]).map(|(block, _)| block);
+        }
+        properties.format_version = format_version;
+
+        let bloom = if properties.num_distinct_keys <= 1 {
+            Bloom::always_match()
+        } else {
+            Bloom::build_from_key_hashes(
+                &key_hashes,
+                Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+            )
+        };
+        properties.num_bloom_bits = bloom.filter.len() as u64 * 8;
+        let (first_key, last_key) = table_key_range(&block_meta, &[]);
+        Ok(Self {
+            file,
+            first_key,
+            last_key,
+            block_meta,
+            block_meta_offset: offset,
+            index_file: None,
+            id: 0,
+            block_cache: None,
+            cache_namespace: 0,
+            shard: None,
+            metrics: None,
+            bloom: Some(bloom),
+            max_ts,
+            properties,
+            range_tombstones: Vec::new(),
+            tombstone_bitmap: None,
+            bloom_probes: AtomicU64::new(0),
+        })
+    }
+}
+
+/// Finds the block at `begin` of `data`, the shortest run of bytes followed by its checksum
+/// that is a well-formed block in one of `format_versions`, see [`is_well_formed`].
+fn scan_block(
+    data: &Bytes,
+    begin: usize,
+    checksum_type: ChecksumType,
+    format_versions: &[u8],
+) -> Option<(Arc<BlockRef>, u8)> {
+    let last_end = data.len().checked_sub(checksum_type.size())?;
+    let mut digest = checksum_type.digest();
+    for end in begin..=last_end {
+        if end > begin && digest.value() == checksum_type.get_checksum(&mut &data[end..]) {
+            for &format_version in format_versions {
+                let Ok(block) =
+                    Block::decode_bytes_with_format_version(data.slice(begin..end), format_version)
+                else {
+                    continue;
+                };
+                let block = Arc::new(block);
+                if is_well_formed(&block) {
+                    return Some((block, format_version));
+                }
+            }
+        }
+        if end < last_end {
+            digest.update(&data[end..end + 1]);
+        }
+    }
+    None
+}
+
+/// Whether `block` has entries, laid out end to end from the start of the block, with keys in
+/// ascending order.
+fn is_well_formed(block: &Arc<BlockRef>) -> bool {
+    let data = block.data();
+    let width = block.key_len_width() as usize;
+    let ts_len = if block.has_ts() {
+        std::mem::size_of::<u64>()
+    } else {
+        0
+    };
+    let flags_len = usize::from(block.has_flags());
+    let mut first_key_len = 0;
+    let mut pos = 0;
+    for idx in 0..block.num_entries() {
+        if block.offset(idx) != pos || data.len() - pos < 2 * width {
+            return false;
+        }
+        let mut entry = &data[pos..];
+        let overlap = get_key_len(&mut entry, block.key_len_width());
+        let key_len = get_key_len(&mut entry, block.key_len_width());
+        if idx == 0 {
+            first_key_len = key_len;
+        }
+        let value_len_offset = key_len + ts_len + flags_len;
+        if (idx == 0 && overlap > 0)
+            || overlap > first_key_len
+            || overlap + key_len == 0
+            || entry.len() < value_len_offset + SIZEOF_U16
+        {
+            return false;
+        }
+        let value_len = (&entry[value_len_offset..]).get_u16() as usize;
+        let entry_len = 2 * width + value_len_offset + SIZEOF_U16 + value_len;
+        if data.len() - pos < entry_len {
+            return false;
+        }
+        pos += entry_len;
+    }
+    if block.num_entries() == 0 || pos != data.len() {
+        return false;
+    }
+
+    let mut iter = BlockIterator::create_and_seek_to_first_ref(block.clone());
+    let mut previous_key: Option<KeyVec> = None;
+    while iter.is_valid() {
+        if previous_key
+            .as_ref()
+            .is_some_and(|previous_key| iter.key() <= previous_key.as_key_slice())
+        {
+            return false;
+        }
+        previous_key = Some(iter.key().to_key_vec());
+        iter.next();
+    }
+    true
+}
+
+/// Whether an entry of `block`, a well-formed block storing flags, has [`ENTRY_FLAG_TOMBSTONE`]
+/// stored rather than implied by an empty value, which only blocks of [`BLOCK_FORMAT_V6`] do.
+fn stores_tombstone_flag(block: &BlockRef) -> bool {
+    let width = block.key_len_width();
+    (0..block.num_entries()).any(|idx| {
+        let mut entry = &block.data()[block.offset(idx) + width as usize..];
+        let key_len = get_key_len(&mut entry, width);
+        entry[key_len + std::mem::size_of::<u64>()] & ENTRY_FLAG_TOMBSTONE != 0
+    })
+}
@@ -0,0 +1,133 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use super::BlockMeta;
+
+/// Marks the tables carrying a tombstone bitmap. It is written where other tables store the
+/// offset of their bloom filter, which can never be `u32::MAX` since offsets are u32.
+pub(crate) const TOMBSTONE_BITMAP_MAGIC: u32 = u32::MAX;
+
+/// One bit per entry of an SSTable, in the order of the entries, set for the tombstones (see
+/// [`crate::block::ENTRY_FLAG_TOMBSTONE`]). It lets iterators skip runs of tombstones without
+/// decoding them, and skip the blocks holding nothing but tombstones without reading them.
+///
+/// The bitmap is optional and stored after the bloom filter: a table with a bitmap is a table
+/// without one, followed by the bits, a u32 checksum of them, the u32 offset of the bits and
+/// [`TOMBSTONE_BITMAP_MAGIC`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TombstoneBitmap {
+    bits: Vec<u8>,
+    num_entries: usize,
+    /// The index of the first entry of each block, only known once decoded along with the block
+    /// meta.
+    block_starts: Vec<usize>,
+}
+
+impl TombstoneBitmap {
+    pub(crate) fn push(&mut self, is_tombstone: bool) {
+        if self.num_entries.is_multiple_of(8) {
+            self.bits.push(0);
+        }
+        if is_tombstone {
+            *self.bits.last_mut().unwrap() |= 1 << (self.num_entries % 8);
+        }
+        self.num_entries += 1;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bits.clear();
+        self.num_entries = 0;
+        self.block_starts.clear();
+    }
+
+    /// Records where the blocks start, once all the entries are pushed.
+    pub(crate) fn set_blocks(&mut self, block_meta: &[BlockMeta]) -> Result<()> {
+        self.block_starts.clear();
+        let mut num_entries = 0;
+        for meta in block_meta {
+            self.block_starts.push(num_entries);
+            num_entries += meta.num_entries as usize;
+        }
+        if num_entries != self.num_entries {
+            bail!(
+                "tombstone bitmap of {} entries for a table of {} entries",
+                self.num_entries,
+                num_entries
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.extend(&self.bits);
+        buf.put_u32(crc32fast::hash(&buf[offset..]));
+        buf.put_u32(offset as u32);
+        buf.put_u32(TOMBSTONE_BITMAP_MAGIC);
+    }
+
+    /// Decodes the bits and their checksum, as written by [`Self::encode`] before the offset.
+    pub(crate) fn decode(buf: &[u8], block_meta: &[BlockMeta]) -> Result<Self> {
+        if buf.len() < 4 {
+            bail!("tombstone bitmap too short: {} bytes", buf.len());
+        }
+        let (bits, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(bits) {
+            bail!("checksum mismatched for tombstone bitmap");
+        }
+        let num_entries = block_meta
+            .iter()
+            .map(|meta| meta.num_entries as usize)
+            .sum::<usize>();
+        if bits.len() != num_entries.div_ceil(8) {
+            bail!(
+                "tombstone bitmap of {} bytes for a table of {} entries",
+                bits.len(),
+                num_entries
+            );
+        }
+        let mut bitmap = Self {
+            bits: bits.to_vec(),
+            num_entries,
+            block_starts: Vec::new(),
+        };
+        bitmap.set_blocks(block_meta)?;
+        Ok(bitmap)
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Whether the `entry`-th entry of the table is a tombstone.
+    pub fn is_tombstone(&self, entry: usize) -> bool {
+        self.bits[entry / 8] & (1 << (entry % 8)) != 0
+    }
+
+    /// The first entry from `entry` on that is not a tombstone, if any. Whole bytes of
+    /// tombstones are skipped at once.
+    pub fn next_live_entry(&self, mut entry: usize) -> Option<usize> {
+        while entry < self.num_entries {
+            let byte = self.bits[entry / 8] >> (entry % 8);
+            if byte == 0xff >> (entry % 8) {
+                // the rest of the byte is all tombstones
+                entry = (entry / 8 + 1) * 8;
+                continue;
+            }
+            entry += byte.trailing_ones() as usize;
+            return (entry < self.num_entries).then_some(entry);
+        }
+        None
+    }
+
+    /// The index of the first entry of a block among all the entries of the table.
+    pub fn block_start(&self, blk_idx: usize) -> usize {
+        self.block_starts[blk_idx]
+    }
+
+    /// The block holding the `entry`-th entry of the table, and the index of the entry in it.
+    pub fn locate(&self, entry: usize) -> (usize, usize) {
+        let blk_idx = self.block_starts.partition_point(|start| *start <= entry) - 1;
+        (blk_idx, entry - self.block_starts[blk_idx])
+    }
+}
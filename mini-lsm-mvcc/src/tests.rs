@@ -1,4 +1,101 @@
+mod backup;
+mod block_cache_weigher;
+mod block_flush_callback;
+mod block_format;
+mod block_get_with_ts;
+mod block_key_at;
+#[cfg(feature = "serde")]
+mod block_meta_serde;
+mod block_offset;
+mod block_ref;
+mod bloom_budget;
+mod bloom_options;
+mod bloom_probes;
+mod bloom_short_filter;
+mod bloom_spill;
+mod bottom_level_tombstone;
+mod builder_reuse;
+mod byte_order;
+mod cache_hint;
+mod cache_namespace;
+mod changes_only_iterator;
+mod checksum_type;
+mod close;
+mod compaction_bloom_sizing;
+mod compaction_cache;
+mod compaction_crash;
+mod compaction_entry_filter;
+mod compaction_iterator;
+mod compaction_plan;
+mod compaction_priority;
+mod compaction_retry;
+mod compaction_stats;
+mod compaction_threads;
+mod compare_and_swap;
+mod concat_iterator_rev;
+mod delete_range;
+mod delete_triggered_compaction;
+mod describe;
+mod destroy;
+mod disk_usage;
+mod duplicate_keys;
+mod empty_sst;
+mod empty_value;
+mod event_listener;
+mod faulty_file;
+mod full_compaction_writes;
+mod garbage_aware_compaction;
+mod get_cached_only;
+mod get_property;
 mod harness;
+mod ingest;
+mod ingest_ssts;
+mod key_count_estimate;
+mod key_len_encoding;
+mod keyspace;
+mod limited_merge_iterator;
+mod lock_free_state;
+mod may_contain_key;
+mod merge_build;
+mod merge_operator;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mmap_pool;
+mod multi_get;
+mod no_cache_iterator;
+mod ordered_guard;
+mod paginated_iterator;
+mod pause_compaction;
+mod peekable_iterator;
+mod periodic_compaction;
+mod rate_limiter;
+mod read_only;
+mod read_options;
+mod rebuild_index;
+mod restore;
+mod scan_bounds;
+mod scan_rev;
+mod separate_index;
+mod set_options;
+mod sharded_writes;
+mod single_entry_block;
+mod sorted_run;
+mod split_iterators;
+mod split_user_key;
+mod sst_concat;
+mod sst_copy;
+mod sst_key_bytes;
+mod std_iterator;
+mod streaming_compaction;
+mod subcompaction;
+mod tombstone_bitmap;
+#[cfg(feature = "tracing")]
+mod tracing;
+mod ttl;
+mod value_bytes;
+mod value_size_histogram;
+mod version_gc;
+mod warm_range;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +116,6 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_batch;
+mod write_stall;
+mod zero_copy_get;
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::path::Path;
+
+use tempfile::tempdir;
+
+use crate::backup::CheckpointManifest;
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Writes to the storage and to `model` alike, deleting the keys if `value` is `None`.
+fn write(
+    storage: &MiniLsm,
+    model: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+    range: std::ops::Range<usize>,
+    value: Option<&[u8]>,
+) {
+    for idx in range {
+        match value {
+            Some(value) => {
+                storage.put(&key_of(idx), value).unwrap();
+                model.insert(key_of(idx), value.to_vec());
+            }
+            None => {
+                storage.delete(&key_of(idx)).unwrap();
+                model.remove(&key_of(idx));
+            }
+        }
+    }
+}
+
+/// The L0 SSTs and the levels of the storage.
+fn levels(storage: &MiniLsm) -> (Vec<usize>, Vec<Vec<usize>>) {
+    let state = storage.inner.state.load();
+    (
+        state.l0_sstables.clone(),
+        state.levels.iter().map(|(_, ssts)| ssts.clone()).collect(),
+    )
+}
+
+/// Opens the checkpoint in `path` and checks it holds `expected`, in SSTs laid out as `levels`.
+fn check_checkpoint(
+    path: &Path,
+    expected: &BTreeMap<Vec<u8>, Vec<u8>>,
+    expected_levels: &(Vec<usize>, Vec<Vec<usize>>),
+) {
+    let storage = MiniLsm::open_read_only(path, options()).unwrap();
+    assert_eq!(&levels(&storage), expected_levels);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut entries = BTreeMap::new();
+    while iter.is_valid() {
+        entries.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    assert_eq!(&entries, expected);
+}
+
+#[test]
+fn test_backup_delta() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    let mut model = BTreeMap::new();
+
+    // a full backup is a delta from the empty checkpoint
+    write(&storage, &mut model, 0..100, Some(b"v1"));
+    storage.force_flush().unwrap();
+    write(&storage, &mut model, 100..120, Some(b"v1"));
+    let plan = storage
+        .backup_delta(&CheckpointManifest::default())
+        .unwrap();
+    assert_eq!(plan.new_ssts.len(), 2);
+    assert_eq!(plan.checkpoint.ssts.len(), 2);
+    let checkpoint0 = plan.checkpoint.clone();
+    let (model0, levels0) = (model.clone(), levels(&storage));
+    let path0 = dir.path().join("checkpoint0");
+    MiniLsm::apply_backup_delta(dir.path().join("none"), plan, &path0).unwrap();
+
+    // after a compaction, only its output and the SST flushed since are copied
+    write(&storage, &mut model, 0..50, Some(b"v2"));
+    write(&storage, &mut model, 90..100, None);
+    storage.force_full_compaction().unwrap();
+    write(&storage, &mut model, 120..150, Some(b"v2"));
+    let plan = storage.backup_delta(&checkpoint0).unwrap();
+    let new_ids = plan
+        .new_ssts
+        .iter()
+        .map(|sst| sst.sst_id())
+        .collect::<BTreeSet<_>>();
+    for id in plan.checkpoint.ssts.keys() {
+        assert_eq!(new_ids.contains(id), !checkpoint0.ssts.contains_key(id));
+    }
+    let checkpoint1 = plan.checkpoint.clone();
+    let (model1, levels1) = (model.clone(), levels(&storage));
+    let path1 = dir.path().join("checkpoint1");
+    MiniLsm::apply_backup_delta(&path0, plan, &path1).unwrap();
+
+    // the SSTs of the previous checkpoint are linked from it
+    write(&storage, &mut model, 150..200, Some(b"v3"));
+    let plan = storage.backup_delta(&checkpoint1).unwrap();
+    assert_eq!(plan.new_ssts.len(), 1);
+    assert_eq!(plan.checkpoint.ssts.len(), checkpoint1.ssts.len() + 1);
+    let (model2, levels2) = (model.clone(), levels(&storage));
+    let path2 = dir.path().join("checkpoint2");
+    MiniLsm::apply_backup_delta(&path1, plan, &path2).unwrap();
+
+    check_checkpoint(&path0, &model0, &levels0);
+    check_checkpoint(&path1, &model1, &levels1);
+    check_checkpoint(&path2, &model2, &levels2);
+
+    // a checkpoint is not overwritten
+    let plan = storage.backup_delta(&checkpoint1).unwrap();
+    assert!(MiniLsm::apply_backup_delta(&path1, plan, &path2).is_err());
+}
+
+#[test]
+fn test_sst_never_rewritten() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    let sst_path = storage
+        .inner
+        .path_of_sst(storage.inner.state.load().memtable.id());
+    std::fs::write(&sst_path, b"not to be overwritten").unwrap();
+    assert!(storage.force_flush().is_err());
+    assert_eq!(std::fs::read(&sst_path).unwrap(), b"not to be overwritten");
+
+    std::fs::remove_file(&sst_path).unwrap();
+    storage.force_flush().unwrap();
+    assert!(storage.inner.state.load().imm_memtables.is_empty());
+}
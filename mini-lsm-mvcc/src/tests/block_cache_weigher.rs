@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use moka::sync::ConcurrentCacheExt;
+
+use crate::block::{Block, BlockBuilder};
+use crate::key::KeySlice;
+use crate::lsm_storage::{block_size_weigher, new_block_cache};
+
+/// Builds a block holding a single entry with a value of `value_len` bytes.
+fn block_with_value(value_len: usize) -> Arc<Block> {
+    let mut builder = BlockBuilder::new(value_len + 64);
+    assert!(builder.add(
+        KeySlice::for_testing_from_slice_no_ts(b"key"),
+        &vec![0; value_len]
+    ));
+    Arc::new(builder.build())
+}
+
+#[test]
+fn test_block_cache_weighed_by_decoded_size() {
+    let cache = new_block_cache(64 << 10, block_size_weigher);
+
+    // 100 small blocks fit in the budget
+    for idx in 0..100 {
+        cache.insert((0, 1, idx), block_with_value(100));
+    }
+    cache.sync();
+    assert_eq!(cache.entry_count(), 100);
+
+    // blocks of varying sizes up to 16KB are evicted to stay within 64KB
+    for idx in 0..100 {
+        cache.insert((0, 2, idx), block_with_value(1024 * (idx % 16 + 1)));
+        cache.sync();
+        assert!(
+            cache.weighted_size() <= 64 << 10,
+            "{}",
+            cache.weighted_size()
+        );
+    }
+    // a cache bounded by entry count would have kept all 200 blocks
+    assert!(cache.entry_count() < 200);
+    let weighted_size = cache
+        .iter()
+        .map(|(key, block)| block_size_weigher(&key, &block) as u64)
+        .sum::<u64>();
+    assert_eq!(weighted_size, cache.weighted_size());
+}
+
+#[test]
+fn test_block_cache_custom_weigher() {
+    // every block weighs 1, so the capacity is an entry count again
+    let cache = new_block_cache(10, |_, _| 1);
+    for idx in 0..100 {
+        cache.insert((0, 1, idx), block_with_value(1024));
+    }
+    cache.sync();
+    assert_eq!(cache.weighted_size(), 10);
+    assert_eq!(cache.entry_count(), 10);
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::{BlockMeta, SsTableBuilder};
+
+#[test]
+fn test_on_block_flushed_fires_once_per_block() {
+    let flushed = Arc::new(Mutex::new(Vec::<(BlockMeta, usize)>::new()));
+    let mut builder = SsTableBuilder::new(128);
+    {
+        let flushed = flushed.clone();
+        builder.set_on_block_flushed(move |meta, encoded_size| {
+            flushed.lock().push((meta.clone(), encoded_size));
+        });
+    }
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:03}", idx).as_bytes()),
+            format!("value_{:03}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+
+    let flushed = flushed.lock();
+    assert!(sst.num_of_blocks() > 1);
+    assert_eq!(flushed.len(), sst.num_of_blocks());
+    for (idx, (meta, encoded_size)) in flushed.iter().enumerate() {
+        assert_eq!(meta, &sst.block_metas()[idx]);
+        let block_end = sst
+            .block_metas()
+            .get(idx + 1)
+            .map_or(sst.block_meta_offset, |next| next.offset);
+        // each block is followed by a 4-byte checksum
+        assert_eq!(*encoded_size, block_end - meta.offset - 4);
+    }
+}
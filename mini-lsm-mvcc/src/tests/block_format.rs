@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::{
+    Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V1, BLOCK_FORMAT_V2, BLOCK_FORMAT_V3,
+    BLOCK_FORMAT_VERSION,
+};
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT};
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+/// Entries ordered by user key ascending, then timestamp descending.
+fn versioned_entries() -> Vec<(String, u64, String)> {
+    let mut entries = Vec::new();
+    for i in 0..20 {
+        for ts in (1..=3).rev() {
+            entries.push((
+                format!("key_{:03}", i),
+                ts * 10 + i,
+                format!("value_{}_{}", i, ts),
+            ));
+        }
+    }
+    entries
+}
+
+#[test]
+fn test_block_ts_round_trip() {
+    assert_eq!(BLOCK_FORMAT_VERSION, BLOCK_FORMAT_V2);
+    let entries = versioned_entries();
+    let mut builder = BlockBuilder::new(10000);
+    for (key, ts, value) in &entries {
+        assert!(builder.add(KeySlice::from_slice(key.as_bytes(), *ts), value.as_bytes()));
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    let mut prev: Option<(Vec<u8>, u64)> = None;
+    for (key, ts, value) in &entries {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), key.as_bytes());
+        assert_eq!(iter.key().ts(), *ts);
+        assert_eq!(iter.value(), value.as_bytes());
+        if let Some((prev_key, prev_ts)) = &prev {
+            let key_ref = iter.key().key_ref();
+            assert!(
+                prev_key.as_slice() < key_ref
+                    || (prev_key.as_slice() == key_ref && *prev_ts > iter.key().ts())
+            );
+        }
+        prev = Some((iter.key().key_ref().to_vec(), iter.key().ts()));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+
+    // seeking with a timestamp lands on the newest version not newer than it
+    let iter = BlockIterator::create_and_seek_to_key(block, KeySlice::from_slice(b"key_005", 20));
+    assert_eq!(iter.key().key_ref(), b"key_005");
+    assert_eq!(iter.key().ts(), 15);
+}
+
+#[test]
+fn test_block_without_ts_is_readable() {
+    let entries = versioned_entries()
+        .into_iter()
+        .filter(|(_, ts, _)| *ts > 30)
+        .collect::<Vec<_>>();
+    let mut builder = BlockBuilder::new_with_format_version(10000, BLOCK_FORMAT_V1);
+    for (key, _, value) in &entries {
+        assert!(builder.add(
+            KeySlice::from_slice(key.as_bytes(), TS_DEFAULT),
+            value.as_bytes()
+        ));
+    }
+    let encoded = builder.build().encode();
+    let block = Arc::new(Block::decode_with_format_version(&encoded, BLOCK_FORMAT_V1));
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for (key, _, value) in &entries {
+        assert_eq!(iter.key().key_ref(), key.as_bytes());
+        assert_eq!(iter.key().ts(), TS_DEFAULT);
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    let iter =
+        BlockIterator::create_and_seek_to_key(block, KeySlice::from_slice(b"key_010", TS_DEFAULT));
+    assert_eq!(iter.value(), b"value_10_3");
+}
+
+#[test]
+fn test_block_without_prefix_compression() {
+    let entries = versioned_entries();
+    let mut prefix_builder = BlockBuilder::new(10000);
+    let mut builder = BlockBuilder::new_no_prefix(10000);
+    for (key, ts, value) in &entries {
+        let key = KeySlice::from_slice(key.as_bytes(), *ts);
+        assert!(prefix_builder.add(key, value.as_bytes()));
+        assert!(builder.add(key, value.as_bytes()));
+    }
+    let prefix_encoded = prefix_builder.build().encode();
+    let encoded = builder.build().encode();
+    // every key repeats the "key_" prefix
+    assert!(encoded.len() >= prefix_encoded.len() + entries.len() * 4);
+
+    let block = Arc::new(Block::decode_with_format_version(&encoded, BLOCK_FORMAT_V3));
+    let block_ref = Arc::new(
+        Block::decode_bytes_with_format_version(encoded.clone(), BLOCK_FORMAT_V3).unwrap(),
+    );
+    for mut iter in [
+        BlockIterator::create_and_seek_to_first(block.clone()),
+        BlockIterator::create_and_seek_to_first_ref(block_ref.clone()),
+    ] {
+        for (idx, (key, ts, value)) in entries.iter().enumerate() {
+            assert_eq!(iter.key().key_ref(), key.as_bytes());
+            assert_eq!(iter.key().ts(), *ts);
+            assert_eq!(iter.value(), value.as_bytes());
+            assert_eq!(iter.key_at(idx).as_key_slice(), iter.key());
+            iter.next();
+        }
+        assert!(!iter.is_valid());
+    }
+
+    for (seek_key, seek_ts, expected) in [
+        // the newest version not newer than the timestamp
+        ("key_005", 20, Some(("key_005", 15))),
+        ("key_005", 100, Some(("key_005", 35))),
+        // an absent key, and a key before and after all keys
+        ("key_005a", 100, Some(("key_006", 36))),
+        ("a", 0, Some(("key_000", 30))),
+        ("key_019", 0, None),
+    ] {
+        let key = KeySlice::from_slice(seek_key.as_bytes(), seek_ts);
+        for iter in [
+            BlockIterator::create_and_seek_to_key(block.clone(), key),
+            BlockIterator::create_and_seek_to_key_ref(block_ref.clone(), key),
+        ] {
+            match expected {
+                Some((key, ts)) => {
+                    assert_eq!(iter.key().key_ref(), key.as_bytes());
+                    assert_eq!(iter.key().ts(), ts);
+                }
+                None => assert!(!iter.is_valid()),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sst_format_version() {
+    let dir = tempdir().unwrap();
+    let entries = versioned_entries();
+    for format_version in [BLOCK_FORMAT_V1, BLOCK_FORMAT_V2, BLOCK_FORMAT_V3] {
+        let mut builder = SsTableBuilder::new_with_format_version(128, format_version);
+        let mut expected = Vec::new();
+        for (key, ts, value) in &entries {
+            // the old format cannot hold more than one version of a key
+            if format_version == BLOCK_FORMAT_V1 && *ts <= 30 {
+                continue;
+            }
+            builder.add(KeySlice::from_slice(key.as_bytes(), *ts), value.as_bytes());
+            let ts = if format_version == BLOCK_FORMAT_V1 {
+                TS_DEFAULT
+            } else {
+                *ts
+            };
+            expected.push((key.clone(), ts, value.clone()));
+        }
+        let path = dir.path().join(format!("{}.sst", format_version));
+        let sst = builder.build_for_test(&path).unwrap();
+        assert_eq!(sst.properties().format_version, format_version);
+        assert!(sst.num_of_blocks() > 1);
+
+        assert_eq!(sst.first_key().ts(), expected[0].1);
+        assert_eq!(sst.last_key().ts(), expected.last().unwrap().1);
+
+        let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+        assert_eq!(sst.properties().format_version, format_version);
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for (key, ts, value) in &expected {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key().key_ref(), key.as_bytes());
+            assert_eq!(iter.key().ts(), *ts);
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
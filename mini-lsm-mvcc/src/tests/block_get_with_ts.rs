@@ -0,0 +1,86 @@
+use bytes::Bytes;
+
+use crate::block::{Block, BlockBuilder, BLOCK_FORMAT_V1, BLOCK_FORMAT_V3, BLOCK_FORMAT_V6};
+use crate::key::{KeySlice, TS_DEFAULT};
+
+/// Builds a block holding versions of `key_b` at 8, 5, 3 and 1, with a key around it.
+fn build_block(mut builder: BlockBuilder) -> Block {
+    for (key, ts, value) in [
+        ("key_a", 4, "a@4"),
+        ("key_b", 8, ""),
+        ("key_b", 5, "b@5"),
+        ("key_b", 3, "b@3"),
+        ("key_b", 1, "b@1"),
+        ("key_c", 2, "c@2"),
+    ] {
+        assert!(builder.add(KeySlice::from_slice(key.as_bytes(), ts), value.as_bytes()));
+    }
+    builder.build()
+}
+
+fn get(block: &Block, key: &str, read_ts: u64) -> Option<(Bytes, u64)> {
+    block.get_with_ts(key.as_bytes(), read_ts)
+}
+
+fn version(value: &'static str, ts: u64) -> Option<(Bytes, u64)> {
+    Some((Bytes::from_static(value.as_bytes()), ts))
+}
+
+fn check_versions(block: &Block) {
+    assert_eq!(get(block, "key_b", 0), None);
+    assert_eq!(get(block, "key_b", 1), version("b@1", 1));
+    assert_eq!(get(block, "key_b", 2), version("b@1", 1));
+    assert_eq!(get(block, "key_b", 3), version("b@3", 3));
+    assert_eq!(get(block, "key_b", 4), version("b@3", 3));
+    assert_eq!(get(block, "key_b", 5), version("b@5", 5));
+    assert_eq!(get(block, "key_b", 7), version("b@5", 5));
+    // the tombstone is returned as its empty value
+    assert_eq!(get(block, "key_b", 8), version("", 8));
+    assert_eq!(get(block, "key_b", u64::MAX), version("", 8));
+
+    // the first and the last key of the block
+    assert_eq!(get(block, "key_a", 3), None);
+    assert_eq!(get(block, "key_a", 4), version("a@4", 4));
+    assert_eq!(get(block, "key_c", 1), None);
+    assert_eq!(get(block, "key_c", 10), version("c@2", 2));
+
+    // keys around and between those of the block
+    for key in ["key_", "key_a0", "key_bb", "key_d"] {
+        assert_eq!(get(block, key, u64::MAX), None);
+    }
+}
+
+#[test]
+fn test_block_get_with_ts() {
+    let block = build_block(BlockBuilder::new(4096));
+    check_versions(&block);
+    check_versions(&Block::decode(&block.encode()));
+}
+
+#[test]
+fn test_block_get_with_ts_in_other_formats() {
+    for format_version in [BLOCK_FORMAT_V3, BLOCK_FORMAT_V6] {
+        let block = build_block(BlockBuilder::new_with_format_version(4096, format_version));
+        check_versions(&Block::decode_with_format_version(
+            &block.encode(),
+            format_version,
+        ));
+    }
+}
+
+#[test]
+fn test_block_get_with_ts_without_timestamps() {
+    let mut builder = BlockBuilder::new_with_format_version(4096, BLOCK_FORMAT_V1);
+    for key in ["key_a", "key_b", "key_c"] {
+        assert!(builder.add(
+            KeySlice::from_slice(key.as_bytes(), TS_DEFAULT),
+            key.as_bytes()
+        ));
+    }
+    let block = Block::decode_with_format_version(&builder.build().encode(), BLOCK_FORMAT_V1);
+    // every entry is read at the default timestamp, visible at any read timestamp
+    for read_ts in [TS_DEFAULT, 1, u64::MAX] {
+        assert_eq!(get(&block, "key_b", read_ts), version("key_b", TS_DEFAULT));
+    }
+    assert_eq!(get(&block, "key_bb", u64::MAX), None);
+}
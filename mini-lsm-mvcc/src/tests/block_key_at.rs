@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use crate::block::{Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V1};
+use crate::key::KeySlice;
+
+fn build_block(builder: &mut BlockBuilder) {
+    for i in 0..100 {
+        // keys share prefixes of varying lengths with the first key
+        let key = format!("key_{:03}_{}", i * 7, "x".repeat(i % 5));
+        assert!(builder.add(
+            KeySlice::from_slice(key.as_bytes(), 100 - i as u64),
+            b"value"
+        ));
+    }
+}
+
+fn check_key_at(block: Block) {
+    let block = Arc::new(block);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    let lookahead = BlockIterator::create_and_seek_to_first(block);
+    let mut idx = 0;
+    while iter.is_valid() {
+        assert_eq!(lookahead.key_at(idx).as_key_slice(), iter.key());
+        // looking at an entry does not move the iterator
+        assert_eq!(iter.key_at(idx).as_key_slice(), iter.key());
+        idx += 1;
+        iter.next();
+    }
+    assert_eq!(idx, 100);
+    assert_eq!(lookahead.key_at(0), lookahead.key().to_key_vec());
+}
+
+#[test]
+fn test_block_key_at() {
+    let mut builder = BlockBuilder::new(10000);
+    build_block(&mut builder);
+    check_key_at(Block::decode(&builder.build().encode()));
+}
+
+#[test]
+fn test_block_key_at_v1() {
+    let mut builder = BlockBuilder::new_with_format_version(10000, BLOCK_FORMAT_V1);
+    build_block(&mut builder);
+    check_key_at(Block::decode_with_format_version(
+        &builder.build().encode(),
+        BLOCK_FORMAT_V1,
+    ));
+}
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn test_block_key_at_out_of_range() {
+    let mut builder = BlockBuilder::new(10000);
+    build_block(&mut builder);
+    BlockIterator::create_and_seek_to_first(Arc::new(builder.build())).key_at(100);
+}
@@ -0,0 +1,34 @@
+use tempfile::tempdir;
+
+use crate::key::{KeyBytes, KeySlice};
+use crate::table::{BlockMeta, SsTableBuilder};
+
+#[test]
+fn test_block_meta_serde_round_trip() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..100u64 {
+        // binary keys that are not valid UTF-8 must survive the JSON round trip
+        let key = [b"key_".as_slice(), &[0xff, 0x00, idx as u8]].concat();
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key, idx),
+            b"value",
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.block_metas().len() > 1);
+
+    let json = serde_json::to_string(sst.block_metas()).unwrap();
+    let decoded: Vec<BlockMeta> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, sst.block_metas());
+}
+
+#[test]
+fn test_key_bytes_serde_format() {
+    let key = KeyBytes::from_bytes_with_ts(bytes::Bytes::from_static(b"\x00\xab"), 233);
+    let json = serde_json::to_string(&key).unwrap();
+    assert_eq!(json, r#"{"key":"00ab","ts":233}"#);
+    assert_eq!(serde_json::from_str::<KeyBytes>(&json).unwrap(), key);
+    assert!(serde_json::from_str::<KeyBytes>(r#"{"key":"0g","ts":1}"#).is_err());
+    assert!(serde_json::from_str::<KeyBytes>(r#"{"key":"abc","ts":1}"#).is_err());
+}
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::key::KeySlice;
+
+/// The offsets reported by `iter` through a forward scan.
+fn scan_offsets(mut iter: BlockIterator) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    while iter.is_valid() {
+        offsets.push(iter.current_offset());
+        iter.next();
+    }
+    offsets
+}
+
+#[test]
+fn test_block_iterator_current_offset() {
+    let mut builder = BlockBuilder::new(4096);
+    for i in 0..100 {
+        let key = format!("key_{:03}", i);
+        let value = "v".repeat(i % 7);
+        assert!(builder.add(KeySlice::from_slice(key.as_bytes(), 1), value.as_bytes()));
+    }
+    let encoded = builder.build().encode();
+    let block = Arc::new(Block::decode(&encoded));
+    let expected = block
+        .offsets
+        .iter()
+        .map(|offset| *offset as usize)
+        .collect::<Vec<_>>();
+    assert_eq!(expected.len(), 100);
+
+    let offsets = scan_offsets(BlockIterator::create_and_seek_to_first(block.clone()));
+    assert_eq!(offsets, expected);
+    assert_eq!(offsets[0], 0);
+    assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // read in place, the offsets are the same
+    let block_ref = Arc::new(Block::decode_bytes(encoded).unwrap());
+    assert_eq!(
+        scan_offsets(BlockIterator::create_and_seek_to_first_ref(block_ref)),
+        expected
+    );
+
+    // the offset locates the entry, which is where a seek lands
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    iter.seek_to_key(KeySlice::from_slice(b"key_042", 1));
+    assert_eq!(iter.current_offset(), expected[42]);
+}
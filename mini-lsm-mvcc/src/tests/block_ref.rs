@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::block::{Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V1, BLOCK_FORMAT_VERSION};
+use crate::key::KeySlice;
+
+fn encoded_block(format_version: u8) -> Bytes {
+    let mut builder = BlockBuilder::new_with_format_version(4096, format_version);
+    for i in 0..100 {
+        let key = format!("key_{:03}", i * 5);
+        let value = format!("value_{}", i);
+        assert!(builder.add(
+            KeySlice::from_slice(key.as_bytes(), 100 - i),
+            value.as_bytes()
+        ));
+    }
+    builder.build().encode()
+}
+
+fn assert_same_entries(mut expected: BlockIterator, mut actual: BlockIterator) {
+    while expected.is_valid() {
+        assert!(actual.is_valid());
+        assert_eq!(expected.key(), actual.key());
+        assert_eq!(expected.value(), actual.value());
+        expected.next();
+        actual.next();
+    }
+    assert!(!actual.is_valid());
+}
+
+#[test]
+fn test_block_ref_iterator() {
+    for format_version in [BLOCK_FORMAT_V1, BLOCK_FORMAT_VERSION] {
+        let raw = encoded_block(format_version);
+        let block = Arc::new(Block::decode_with_format_version(&raw, format_version));
+        let block_ref =
+            Arc::new(Block::decode_bytes_with_format_version(raw.clone(), format_version).unwrap());
+        assert_eq!(block_ref.num_entries(), 100);
+        assert_same_entries(
+            BlockIterator::create_and_seek_to_first(block.clone()),
+            BlockIterator::create_and_seek_to_first_ref(block_ref.clone()),
+        );
+        for key in ["key_000", "key_123", "key_125", "key_495", "key_999"] {
+            let key = KeySlice::for_testing_from_slice_no_ts(key.as_bytes());
+            assert_same_entries(
+                BlockIterator::create_and_seek_to_key(block.clone(), key),
+                BlockIterator::create_and_seek_to_key_ref(block_ref.clone(), key),
+            );
+        }
+    }
+}
+
+#[test]
+fn test_block_ref_shares_buffer() {
+    let raw = encoded_block(BLOCK_FORMAT_VERSION);
+    let block_ref = Arc::new(Block::decode_bytes(raw.clone()).unwrap());
+    assert_eq!(block_ref.raw().as_ptr(), raw.as_ptr());
+    let iter = BlockIterator::create_and_seek_to_first_ref(block_ref);
+    // values point into the original buffer
+    let value = iter.value().as_ptr() as usize;
+    let begin = raw.as_ptr() as usize;
+    assert!(value > begin && value < begin + raw.len());
+}
+
+#[test]
+fn test_block_ref_malformed() {
+    assert!(Block::decode_bytes(Bytes::from_static(b"")).is_err());
+    // 16 entries do not fit in a 4 byte block
+    assert!(Block::decode_bytes(Bytes::from_static(&[0, 0, 0, 16])).is_err());
+    // the offset of the only entry points past the entries
+    assert!(Block::decode_bytes(Bytes::from_static(&[1, 2, 0, 5, 0, 1])).is_err());
+    assert!(Block::decode_bytes_with_format_version(encoded_block(2), 9).is_err());
+}
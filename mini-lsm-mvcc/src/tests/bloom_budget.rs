@@ -0,0 +1,38 @@
+use crate::table::bloom::{Bloom, BloomBuilder};
+
+#[test]
+fn test_fpr_for_bits_per_key_inverts_bits_per_key() {
+    for bits in 1..=40 {
+        let fpr = Bloom::fpr_for_bits_per_key(bits);
+        assert!(fpr > 0.0 && fpr < 1.0);
+        assert_eq!(
+            Bloom::bloom_bits_per_key(1000, fpr * 1.0001),
+            bits,
+            "{}",
+            fpr
+        );
+        // a slightly lower rate needs one more bit per key
+        assert_eq!(Bloom::bloom_bits_per_key(1000, fpr * 0.9999), bits + 1);
+    }
+    // more bits always give a lower rate
+    for bits in 1..40 {
+        assert!(Bloom::fpr_for_bits_per_key(bits + 1) < Bloom::fpr_for_bits_per_key(bits));
+    }
+    // the usual 10 bits per key are about 1%
+    assert!((Bloom::fpr_for_bits_per_key(10) - 0.0082).abs() < 0.0001);
+}
+
+#[test]
+fn test_bits_per_key_for_budget() {
+    // 1M keys in 1.25MB
+    assert_eq!(Bloom::bits_per_key_for_budget(1_000_000, 1_250_000), 10);
+    // rounded down to stay within the budget
+    assert_eq!(Bloom::bits_per_key_for_budget(1_000_000, 1_249_999), 9);
+    assert_eq!(Bloom::bits_per_key_for_budget(1_000_000, 100), 0);
+    assert_eq!(Bloom::bits_per_key_for_budget(0, 1 << 20), 0);
+    // the filters built with the picked bits per key fit in the budget
+    let (total_keys, budget_bytes) = (12_345, 20_000);
+    let bits = Bloom::bits_per_key_for_budget(total_keys, budget_bytes);
+    let bloom = BloomBuilder::new(total_keys, bits).build();
+    assert!(bloom.filter.len() <= budget_bytes);
+}
@@ -0,0 +1,93 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::{BloomOptions, SsTable, SsTableBuilder};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Builds and reopens a table of the even keys in `[0, 20000)`.
+fn build(path: &std::path::Path, options: BloomOptions) -> SsTable {
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_bloom_options(options);
+    for idx in (0..20000).step_by(2) {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            b"value",
+        );
+    }
+    let sst = builder.build_for_test(path).unwrap();
+    let fpr = sst.bloom_fpr();
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    // the rate is read back from the filter in the file
+    assert_eq!(sst.bloom_fpr(), fpr);
+    sst
+}
+
+/// The fraction of the odd keys, all absent but within the key range, the table may contain.
+fn measured_fpr(sst: &SsTable) -> f64 {
+    for idx in (0..20000).step_by(2) {
+        assert!(sst.may_contain_key(&key_of(idx)));
+    }
+    let false_positives = (1..19999)
+        .step_by(2)
+        .filter(|idx| sst.may_contain_key(&key_of(*idx)))
+        .count();
+    false_positives as f64 / 9999.0
+}
+
+#[test]
+fn test_bloom_fpr_per_table() {
+    let dir = tempdir().unwrap();
+    let loose = build(
+        &dir.path().join("1.sst"),
+        BloomOptions {
+            enabled: true,
+            fpr: 0.1,
+        },
+    );
+    let tight = build(
+        &dir.path().join("2.sst"),
+        BloomOptions {
+            enabled: true,
+            fpr: 0.001,
+        },
+    );
+    // the filters are sized for the target rates, rounded up to whole bits per key
+    assert!(loose.bloom_fpr() <= 0.1 && loose.bloom_fpr() > 0.05);
+    assert!(tight.bloom_fpr() <= 0.001 && tight.bloom_fpr() > 0.0001);
+    assert!(loose.properties().num_bloom_bits < tight.properties().num_bloom_bits);
+
+    let loose_measured = measured_fpr(&loose);
+    let tight_measured = measured_fpr(&tight);
+    assert!(
+        (loose_measured - loose.bloom_fpr()).abs() < loose.bloom_fpr() / 2.0,
+        "{} vs {}",
+        loose_measured,
+        loose.bloom_fpr()
+    );
+    assert!(tight_measured < 0.005, "{}", tight_measured);
+    assert!(tight_measured * 10.0 < loose_measured);
+
+    // the default rate is in between
+    let default = build(&dir.path().join("3.sst"), BloomOptions::default());
+    assert!(default.bloom_fpr() < loose.bloom_fpr());
+    assert!(default.bloom_fpr() > tight.bloom_fpr());
+}
+
+#[test]
+fn test_bloom_disabled() {
+    let dir = tempdir().unwrap();
+    let sst = build(
+        &dir.path().join("1.sst"),
+        BloomOptions {
+            enabled: false,
+            fpr: 0.01,
+        },
+    );
+    assert!(sst.bloom.as_ref().unwrap().is_always_match());
+    assert_eq!(sst.properties().num_bloom_bits, 0);
+    assert_eq!(sst.bloom_fpr(), 1.0);
+    assert_eq!(measured_fpr(&sst), 1.0);
+}
@@ -0,0 +1,71 @@
+use std::{ops::Bound, sync::Arc};
+
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::harness::sync;
+
+fn total_bloom_probes(storage: &LsmStorageInner) -> u64 {
+    let snapshot = storage.state.load();
+    snapshot
+        .sstables
+        .values()
+        .map(|sst| sst.bloom_probes())
+        .sum()
+}
+
+#[test]
+fn test_scans_never_consult_bloom() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    for round in 0..3 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key_{:03}", i * 3 + round).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    assert!(storage.state.load().l0_sstables.len() >= 3);
+    assert_eq!(total_bloom_probes(&storage), 0);
+
+    // full scans and range scans go through the iterator construction path only
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut cnt = 0;
+    while iter.is_valid() {
+        cnt += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(cnt, 300);
+    let mut iter = storage
+        .scan(Bound::Included(b"key_100"), Bound::Excluded(b"key_200"))
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    assert_eq!(total_bloom_probes(&storage), 0);
+
+    // point lookups consult the bloom filter of every SST whose key range covers the key
+    assert_eq!(
+        storage.get(b"key_150").unwrap().as_deref(),
+        Some(&b"value_0"[..])
+    );
+    let after_get = total_bloom_probes(&storage);
+    assert!(after_get >= 3, "get consulted {} bloom filters", after_get);
+    assert!(storage.get(b"key_150x").unwrap().is_none());
+    assert!(total_bloom_probes(&storage) > after_get);
+
+    // keys outside every SST's range are rejected before the bloom filter
+    let before = total_bloom_probes(&storage);
+    assert!(storage.get(b"zzz").unwrap().is_none());
+    assert_eq!(total_bloom_probes(&storage), before);
+}
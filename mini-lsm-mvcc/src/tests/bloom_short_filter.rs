@@ -0,0 +1,69 @@
+use bytes::BufMut;
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::bloom::Bloom;
+use crate::table::{SsTable, SsTableBuilder};
+
+fn encode_raw(filter: &[u8], k: u8) -> Vec<u8> {
+    let mut buf = filter.to_vec();
+    buf.push(k);
+    let checksum = crc32fast::hash(&buf);
+    buf.put_u32(checksum);
+    buf
+}
+
+#[test]
+fn test_single_key_table_uses_short_filter() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    // versions of the same key count as one key
+    for ts in (1..=3).rev() {
+        builder.add(KeySlice::from_slice(b"key", ts), b"value");
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let bloom = sst.bloom.as_ref().unwrap();
+    assert!(bloom.is_always_match());
+    assert!(bloom.filter.is_empty());
+    assert_eq!(sst.properties().num_bloom_bits, 0);
+
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    let bloom = sst.bloom.as_ref().unwrap();
+    assert!(bloom.is_always_match());
+    for h in [0, 1, 12345, u32::MAX] {
+        assert!(bloom.may_contain(h));
+    }
+    assert!(sst.may_contain_key(b"key"));
+    // the key range check still rejects other keys
+    assert!(!sst.may_contain_key(b"kez"));
+    assert!(!sst.may_contain_key(b"a"));
+
+    // two keys get a real filter
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(KeySlice::from_slice(b"a", 1), b"value");
+    builder.add(KeySlice::from_slice(b"b", 1), b"value");
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    assert!(!sst.bloom.as_ref().unwrap().is_always_match());
+}
+
+#[test]
+fn test_short_filter_encoding() {
+    let mut buf = Vec::new();
+    Bloom::always_match().encode(&mut buf);
+    assert_eq!(buf, encode_raw(&[], 0xff));
+    let bloom = Bloom::decode(&buf).unwrap();
+    assert!(bloom.is_always_match());
+    assert!(bloom.may_contain(42));
+
+    // any k above 30 matches every key, whatever the filter bytes are
+    let bloom = Bloom::decode(&encode_raw(&[0, 0], 31)).unwrap();
+    assert!(bloom.is_always_match());
+    assert!(bloom.may_contain(42));
+    let bloom = Bloom::decode(&encode_raw(&[0; 8], 30)).unwrap();
+    assert!(!bloom.is_always_match());
+    assert!(!bloom.may_contain(42));
+
+    // malformed filters are rejected instead of panicking
+    assert!(Bloom::decode(&encode_raw(&[], 3)).is_err());
+    assert!(Bloom::decode(&[0, 0, 0]).is_err());
+}
@@ -0,0 +1,35 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::SsTableBuilder;
+
+fn build(spill: bool) -> (Vec<u8>, u8, bool) {
+    let dir = tempdir().unwrap();
+    let spill_path = dir.path().join("1.sst.hashes");
+    let mut builder = SsTableBuilder::new(4096);
+    for i in 0..50000 {
+        if spill && i == 1000 {
+            // hashes collected so far are moved to the file as well
+            builder.spill_key_hashes_to(&spill_path).unwrap();
+        }
+        builder.add(
+            KeySlice::from_slice(format!("key_{:08}", i).as_bytes(), 1),
+            b"value",
+        );
+    }
+    if spill {
+        assert!(spill_path.exists());
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let bloom = sst.bloom.as_ref().unwrap();
+    (bloom.filter.to_vec(), bloom.k, spill_path.exists())
+}
+
+#[test]
+fn test_spilled_bloom_is_identical() {
+    let (in_memory_filter, in_memory_k, _) = build(false);
+    let (spilled_filter, spilled_k, spill_file_left) = build(true);
+    assert_eq!(in_memory_k, spilled_k);
+    assert!(in_memory_filter == spilled_filter);
+    assert!(!spill_file_left, "temporary hash file is not removed");
+}
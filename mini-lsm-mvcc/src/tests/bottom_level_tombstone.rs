@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::harness::{construct_merge_iterator_over_storage, sync};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn populate_and_delete_half(storage: &Arc<LsmStorageInner>) {
+    for i in 0..1000 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    sync(storage);
+    for i in (0..1000).step_by(2) {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    sync(storage);
+}
+
+/// Returns all versions stored in the SSTs as (key, is_tombstone).
+fn dump_sst_entries(storage: &LsmStorageInner) -> Vec<(Bytes, bool)> {
+    let mut iter = construct_merge_iterator_over_storage(&storage.state.load());
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            iter.value().is_empty(),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_bottom_level_compaction_drops_tombstones() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    populate_and_delete_half(&storage);
+    storage.force_full_compaction().unwrap();
+
+    let entries = dump_sst_entries(&storage);
+    let expected = (1..1000)
+        .step_by(2)
+        .map(|i| (Bytes::from(key_of(i)), false))
+        .collect::<Vec<_>>();
+    assert_eq!(entries, expected);
+    for i in 0..1000 {
+        let value = storage.get(&key_of(i)).unwrap();
+        assert_eq!(value.is_some(), i % 2 == 1);
+    }
+}
+
+#[test]
+fn test_bottom_level_compaction_keeps_tombstones_above_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    for i in 0..1000 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    sync(&storage);
+    let txn = storage.new_txn().unwrap();
+    for i in (0..1000).step_by(2) {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    sync(&storage);
+
+    // the snapshot taken before the deletes must still see the deleted values
+    storage.force_full_compaction().unwrap();
+    let entries = dump_sst_entries(&storage);
+    assert_eq!(entries.len(), 1500);
+    assert_eq!(
+        entries.iter().filter(|(_, tombstone)| *tombstone).count(),
+        500
+    );
+    assert_eq!(
+        txn.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+
+    drop(txn);
+    storage.force_full_compaction().unwrap();
+    let entries = dump_sst_entries(&storage);
+    assert_eq!(entries.len(), 500);
+    assert!(entries.iter().all(|(_, tombstone)| !tombstone));
+}
+
+#[test]
+fn test_bottom_level_compaction_all_deleted() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    for i in 0..100 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    sync(&storage);
+    for i in 0..100 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.state.load_full();
+    assert!(snapshot.l0_sstables.is_empty());
+    assert!(snapshot.levels[0].1.is_empty());
+    assert!(snapshot.sstables.is_empty());
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::BLOCK_FORMAT_V1;
+use crate::clock::MockClock;
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+/// Entries of the `n`-th test SST: several versions per key, some of them tombstones, and values
+/// of varying sizes so that every SST has a different layout.
+fn entries(n: usize) -> Vec<(Vec<u8>, u64, Vec<u8>)> {
+    let mut entries = Vec::new();
+    for i in 0..(50 + n * 70) {
+        let key = format!("key_{}_{:05}", n, i).into_bytes();
+        for ts in (1..=(i % 3) as u64 + 1).rev() {
+            let value = if (i + ts as usize) % 7 == 3 {
+                Vec::new()
+            } else {
+                vec![b'a' + (i % 26) as u8; (i * 13 + n) % 90]
+            };
+            entries.push((key.clone(), ts, value));
+        }
+    }
+    entries
+}
+
+fn add_all(builder: &mut SsTableBuilder, entries: &[(Vec<u8>, u64, Vec<u8>)]) {
+    for (key, ts, value) in entries {
+        builder.add(KeySlice::from_slice(key, *ts), value);
+    }
+}
+
+#[test]
+fn test_reused_builder_writes_identical_ssts() {
+    let dir = tempdir().unwrap();
+    for format_version in [BLOCK_FORMAT_V1, crate::block::BLOCK_FORMAT_VERSION] {
+        let mut reused = SsTableBuilder::new_with_format_version(256, format_version);
+        reused.set_creation_time(42);
+        for n in 0..4 {
+            let entries = entries(n);
+            let mut fresh = SsTableBuilder::new_with_format_version(256, format_version);
+            fresh.set_creation_time(42);
+            add_all(&mut fresh, &entries);
+            let fresh_path = dir.path().join(format!("fresh_{}.sst", n));
+            let fresh_sst = fresh.build(n, None, &fresh_path).unwrap();
+
+            add_all(&mut reused, &entries);
+            let reused_path = dir.path().join(format!("reused_{}.sst", n));
+            let reused_sst = reused.build_and_reset(n, None, &reused_path).unwrap();
+
+            assert_eq!(
+                std::fs::read(&fresh_path).unwrap(),
+                std::fs::read(&reused_path).unwrap(),
+                "SST {} of format {} differs",
+                n,
+                format_version
+            );
+            assert_eq!(fresh_sst.block_meta, reused_sst.block_meta);
+            assert_eq!(fresh_sst.properties(), reused_sst.properties());
+            assert_eq!(fresh_sst.max_ts(), reused_sst.max_ts());
+        }
+    }
+}
+
+#[test]
+fn test_compaction_outputs_match_fresh_builders() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.block_size = 512;
+    options.target_sst_size = 2048;
+    options.clock = Arc::new(MockClock::new(1000));
+    let block_size = options.block_size;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for round in 0..2 {
+        for i in 0..300 {
+            storage
+                .put(
+                    format!("key_{:05}", i * 2 + round).as_bytes(),
+                    format!("value_{}_{}", i, round)
+                        .repeat(i % 5 + 1)
+                        .as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        while !storage.state.load().imm_memtables.is_empty() {
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+    }
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.load().l0_sstables.is_empty());
+
+    let outputs = storage.state.load().levels[0].1.clone();
+    assert!(outputs.len() > 2);
+    let mut num_entries = 0;
+    for id in outputs {
+        let sst = storage.state.load().sstables[&id].clone();
+        num_entries += sst.properties().num_entries;
+        let mut fresh = SsTableBuilder::new(block_size);
+        fresh.set_creation_time(1000);
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            fresh.add(iter.key(), iter.value());
+            iter.next().unwrap();
+        }
+        let path = dir.path().join("fresh.sst");
+        fresh.build(0, None, &path).unwrap();
+        assert_eq!(
+            std::fs::read(storage.path_of_sst(id)).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+    }
+    assert_eq!(num_entries, 600);
+}
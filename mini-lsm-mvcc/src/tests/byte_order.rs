@@ -0,0 +1,127 @@
+use bytes::Bytes;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::key::{KeyBytes, KeySlice};
+use crate::table::bloom::Bloom;
+use crate::table::{BlockMeta, ChecksumType, TableProperties, ValueSizeHistogram};
+
+// SST files must decode to the same values on every architecture, so their encoding is pinned
+// byte by byte. All integers are big-endian.
+const META_BYTES: &[u8] = &[
+    0x00, 0x00, 0x00, 0x01, // number of blocks
+    0x01, // width of key lengths
+    0x01, 0x02, 0x03, 0x04, // offset
+    0x01, b'a', // first key
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // first key ts
+    0x01, b'b', // last key
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // last key ts
+    0x00, 0x00, 0x00, 0x03, // number of entries in the block
+    0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // max ts
+    0x02, // format version
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // number of entries
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // number of tombstones
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // number of shadowed entries
+    0x00, 0x00, 0x00, 0x00, 0x0a, 0x0b, 0x0c, 0x0d, // creation time
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // number of distinct keys
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, // number of bloom bits
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, // smallest value size
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x2c, // largest value size
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x31, // sum of value sizes
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 0
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 1
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // value size bucket 2
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 3
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 4
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 5
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 6
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 7
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // value size bucket 8
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 9
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 10
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 11
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 12
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 13
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 14
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // value size bucket 15
+    0x85, 0x33, 0x7d, 0xf2, // checksum
+];
+
+fn meta() -> (Vec<BlockMeta>, u64, TableProperties) {
+    let block_meta = vec![BlockMeta {
+        offset: 0x01020304,
+        first_key: KeyBytes::from_bytes_with_ts(Bytes::from_static(b"a"), 0x0102030405060708),
+        last_key: KeyBytes::from_bytes_with_ts(Bytes::from_static(b"b"), 2),
+        num_entries: 3,
+    }];
+    let properties = TableProperties {
+        format_version: 2,
+        checksum_type: ChecksumType::Crc32,
+        num_entries: 3,
+        num_tombstones: 1,
+        num_shadowed_entries: 0,
+        creation_time: 0x0a0b0c0d,
+        num_distinct_keys: 2,
+        num_bloom_bits: 64,
+        value_sizes: ValueSizeHistogram {
+            min: 5,
+            max: 300,
+            sum: 305,
+            buckets: std::array::from_fn(|idx| matches!(idx, 2 | 8) as u64),
+        },
+    };
+    (block_meta, 0x1122334455667788, properties)
+}
+
+#[test]
+fn test_block_meta_encoding_pinned() {
+    let (block_meta, max_ts, properties) = meta();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&block_meta, max_ts, &properties, &mut buf);
+    assert_eq!(buf, META_BYTES);
+    assert_eq!(
+        BlockMeta::decode_block_meta(META_BYTES).unwrap(),
+        meta(),
+        "decoding pinned bytes"
+    );
+}
+
+const BLOCK_BYTES: &[u8] = &[
+    0x00, 0x00, // key overlap
+    0x00, 0x03, b'k', b'e', b'y', // key
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, // ts
+    0x00, 0x05, b'v', b'a', b'l', b'u', b'e', // value
+    0x00, 0x02, // key overlap
+    0x00, 0x01, b'z', // key suffix
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // ts
+    0x00, 0x00, // empty value
+    0x00, 0x00, 0x00, 0x16, // offsets
+    0x00, 0x02, // number of entries
+];
+
+#[test]
+fn test_block_encoding_pinned() {
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::from_slice(b"key", 0x0102), b"value"));
+    assert!(builder.add(KeySlice::from_slice(b"kez", 1), b""));
+    assert_eq!(&builder.build().encode()[..], BLOCK_BYTES);
+
+    let mut iter = BlockIterator::create_and_seek_to_first(Block::decode(BLOCK_BYTES).into());
+    assert_eq!(iter.key(), KeySlice::from_slice(b"key", 0x0102));
+    assert_eq!(iter.value(), b"value");
+    iter.next();
+    assert_eq!(iter.key(), KeySlice::from_slice(b"kez", 1));
+    assert_eq!(iter.value(), b"");
+    iter.next();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_bloom_encoding_pinned() {
+    let bytes = [0xab, 0xcd, 0x03, 0xcd, 0x31, 0x93, 0x2a];
+    let bloom = Bloom::decode(&bytes).unwrap();
+    assert_eq!(&bloom.filter[..], &[0xab, 0xcd]);
+    assert_eq!(bloom.k, 3);
+    let mut buf = Vec::new();
+    bloom.encode(&mut buf);
+    assert_eq!(buf, bytes);
+}
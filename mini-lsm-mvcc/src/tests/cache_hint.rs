@@ -0,0 +1,109 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{new_block_cache, LsmStorageInner, LsmStorageOptions};
+use crate::table::{CacheHint, SsTableBuilder};
+
+#[test]
+fn test_read_block_cache_hints() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        builder.add(
+            KeySlice::from_slice(format!("key_{:03}", i).as_bytes(), 1),
+            b"value",
+        );
+    }
+    let table = builder
+        .build(
+            0,
+            Some(Arc::new(new_block_cache(1000, |_, _| 1))),
+            dir.path().join("1.sst"),
+        )
+        .unwrap();
+    assert!(table.num_of_blocks() > 3);
+
+    // probing misses read from the disk without inserting
+    table.read_block_cached(0, CacheHint::Probe).unwrap();
+    assert!(table.get_cached_block(0).is_none());
+    table.read_block_cached(0, CacheHint::Bypass).unwrap();
+    assert!(table.get_cached_block(0).is_none());
+
+    // filling inserts, and probing then returns the cached block
+    let block = table.read_block_cached(0, CacheHint::Fill).unwrap();
+    assert!(Arc::ptr_eq(&table.get_cached_block(0).unwrap(), &block));
+    assert!(Arc::ptr_eq(
+        &table.read_block_cached(0, CacheHint::Probe).unwrap(),
+        &block
+    ));
+    assert!(!Arc::ptr_eq(
+        &table.read_block_cached(0, CacheHint::Bypass).unwrap(),
+        &block
+    ));
+    assert!(table.get_cached_block(1).is_none());
+}
+
+#[test]
+fn test_probing_scans_do_not_fill_cache() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 128;
+    options.scan_cache_hint = CacheHint::Probe;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..200 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"value")
+            .unwrap();
+        if i == 99 {
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage.force_flush_next_imm_memtable().unwrap();
+            storage.force_full_compaction().unwrap();
+        }
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    let state = storage.state.load_full();
+    let tables = state.sstables.values().cloned().collect::<Vec<_>>();
+    assert_eq!(tables.len(), 2);
+    let cached_blocks = || {
+        tables
+            .iter()
+            .map(|table| {
+                (0..table.num_of_blocks())
+                    .filter(|idx| table.get_cached_block(*idx).is_some())
+                    .count()
+            })
+            .sum::<usize>()
+    };
+    storage.block_cache.invalidate_all();
+
+    // a point lookup fills the cache with the blocks it reads
+    assert!(storage.get(b"key_050").unwrap().is_some());
+    assert!(storage.get(b"key_150").unwrap().is_some());
+    let filled = cached_blocks();
+    assert!(filled >= 2);
+    assert!(filled + 10 < tables.iter().map(|table| table.num_of_blocks()).sum());
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut num_keys = 0;
+    while iter.is_valid() {
+        num_keys += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_keys, 200);
+    let mut iter = storage
+        .scan(Bound::Excluded(b"key_049"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), b"key_050");
+    iter.next().unwrap();
+    assert_eq!(cached_blocks(), filled);
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::BlockIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{block_size_weigher, new_block_cache, BlockCache};
+use crate::table::{CacheHint, SsTable, SsTableBuilder};
+
+/// Builds the table 1 with the keys `prefix_{i}`, reading through `block_cache`.
+fn build(path: &std::path::Path, prefix: &str, block_cache: &Arc<BlockCache>) -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        builder.add(
+            KeySlice::from_slice(format!("{}_{:03}", prefix, i).as_bytes(), 1),
+            b"value",
+        );
+    }
+    builder
+        .build(
+            1,
+            Some(block_cache.clone()),
+            path.join(format!("{}.sst", prefix)),
+        )
+        .unwrap()
+}
+
+/// The first key of the `block_idx`-th block, read through the block cache.
+fn first_key_of_block(table: &SsTable, block_idx: usize) -> Vec<u8> {
+    let block = table.read_block_cached(block_idx, CacheHint::Fill).unwrap();
+    BlockIterator::create_and_seek_to_first(block)
+        .key()
+        .key_ref()
+        .to_vec()
+}
+
+#[test]
+fn test_cache_namespaces_keep_tables_apart() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(new_block_cache(1 << 20, block_size_weigher));
+    let a = build(dir.path(), "a", &block_cache).with_cache_namespace(1);
+    let b = build(dir.path(), "b", &block_cache).with_cache_namespace(2);
+    assert_eq!(a.sst_id(), b.sst_id());
+    assert_eq!(a.cache_namespace(), 1);
+
+    for block_idx in 0..a.num_of_blocks() {
+        assert_eq!(
+            first_key_of_block(&a, block_idx),
+            a.block_metas()[block_idx].first_key.key_ref()
+        );
+    }
+    // the blocks of `a` are cached, but not under the namespace of `b`
+    assert!(block_cache.contains_key(&(1, 1, 0)));
+    assert!(b.get_cached_block(0).is_none());
+    for block_idx in 0..b.num_of_blocks() {
+        assert_eq!(
+            first_key_of_block(&b, block_idx),
+            b.block_metas()[block_idx].first_key.key_ref()
+        );
+    }
+    assert!(block_cache.contains_key(&(2, 1, 0)));
+    assert_eq!(first_key_of_block(&a, 0), b"a_000");
+}
+
+#[test]
+fn test_same_namespace_shares_blocks() {
+    // the collision namespaces exist to prevent
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(new_block_cache(1 << 20, block_size_weigher));
+    let a = build(dir.path(), "a", &block_cache);
+    let b = build(dir.path(), "b", &block_cache);
+    assert_eq!(first_key_of_block(&a, 0), b"a_000");
+    assert_eq!(first_key_of_block(&b, 0), b"a_000");
+}
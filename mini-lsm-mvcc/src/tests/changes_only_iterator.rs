@@ -0,0 +1,109 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::changes_only_iterator::ChangesOnlyIterator;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+use super::harness::{check_iter_result_by_key, check_lsm_iter_result_by_key, MockIterator};
+
+fn entries(entries: &[(&'static str, &'static str)]) -> Vec<(Bytes, Bytes)> {
+    entries
+        .iter()
+        .map(|(key, value)| (Bytes::from(*key), Bytes::from(*value)))
+        .collect()
+}
+
+#[test]
+fn test_changes_only_yields_boundaries() {
+    let mut iter = ChangesOnlyIterator::new(MockIterator::new(entries(&[
+        ("a", "1"),
+        ("b", "1"),
+        ("c", "1"),
+        ("d", "2"),
+        ("e", "2"),
+        ("f", "1"),
+        ("g", "3"),
+        ("h", "3"),
+        ("i", "3"),
+    ])));
+    check_iter_result_by_key(
+        &mut iter,
+        entries(&[("a", "1"), ("d", "2"), ("f", "1"), ("g", "3")]),
+    );
+    // advancing past the end is a no-op
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_changes_only_first_and_single_runs() {
+    // the first entry is yielded even when it repeats nothing, and a single run yields it alone
+    let mut iter = ChangesOnlyIterator::new(MockIterator::new(entries(&[
+        ("a", "1"),
+        ("b", "1"),
+        ("c", "1"),
+    ])));
+    check_iter_result_by_key(&mut iter, entries(&[("a", "1")]));
+
+    let mut iter = ChangesOnlyIterator::new(MockIterator::new(entries(&[
+        ("a", "1"),
+        ("b", "2"),
+        ("c", "3"),
+    ])));
+    check_iter_result_by_key(&mut iter, entries(&[("a", "1"), ("b", "2"), ("c", "3")]));
+
+    let iter = ChangesOnlyIterator::new(MockIterator::new(Vec::new()));
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_changes_only_propagates_errors() {
+    // the error is hit while skipping the run of "1"
+    let mut iter = ChangesOnlyIterator::new(MockIterator::new_with_error(
+        entries(&[("a", "1"), ("b", "1"), ("c", "1"), ("d", "2")]),
+        2,
+    ));
+    assert!(iter.next().is_err());
+}
+
+#[test]
+fn test_changes_only_over_scan() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    // a config snapshot per second, changing twice
+    for second in 0..100 {
+        let config = match second {
+            0..=39 => "v1",
+            40..=79 => "v2",
+            _ => "v3",
+        };
+        storage
+            .put(
+                format!("config_{:03}", second).as_bytes(),
+                config.as_bytes(),
+            )
+            .unwrap();
+        if second == 50 {
+            storage.force_flush().unwrap();
+        }
+    }
+    let mut iter = ChangesOnlyIterator::new(
+        storage
+            .scan(Bound::Included(b"config_020"), Bound::Unbounded)
+            .unwrap(),
+    );
+    check_lsm_iter_result_by_key(
+        &mut iter,
+        entries(&[
+            ("config_020", "v1"),
+            ("config_040", "v2"),
+            ("config_080", "v3"),
+        ]),
+    );
+    storage.close().unwrap();
+}
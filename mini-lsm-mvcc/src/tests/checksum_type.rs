@@ -0,0 +1,102 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{ChecksumType, FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+fn build(path: &Path, checksum_type: ChecksumType) -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_checksum_type(checksum_type);
+    for idx in 0..200 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+fn open(path: &Path) -> anyhow::Result<SsTable> {
+    SsTable::open_for_test(FileObject::open(path)?)
+}
+
+#[test]
+fn test_crc64_check_value() {
+    assert_eq!(
+        ChecksumType::Crc64.checksum(b"123456789"),
+        0x995d_c9bb_df19_39fa
+    );
+    assert_eq!(ChecksumType::Crc32.checksum(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn test_crc64_table_round_trip() {
+    let dir = tempdir().unwrap();
+    let crc32 = build(&dir.path().join("crc32.sst"), ChecksumType::Crc32);
+    let crc64 = build(&dir.path().join("crc64.sst"), ChecksumType::Crc64);
+    assert_eq!(crc32.properties().checksum_type, ChecksumType::Crc32);
+    assert_eq!(crc64.properties().checksum_type, ChecksumType::Crc64);
+    // 4 more bytes for each block and for the meta
+    assert_eq!(crc32.num_of_blocks(), crc64.num_of_blocks());
+    assert_eq!(
+        crc64.table_size(),
+        crc32.table_size() + 4 * (crc64.num_of_blocks() as u64 + 1)
+    );
+
+    let table = Arc::new(open(&dir.path().join("crc64.sst")).unwrap());
+    assert_eq!(table.properties(), crc64.properties());
+    let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+    for idx in 0..200 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    let mut copy = Vec::new();
+    table.copy_to(&mut copy).unwrap();
+    assert_eq!(copy, std::fs::read(dir.path().join("crc64.sst")).unwrap());
+}
+
+#[test]
+fn test_crc64_detects_byte_flip() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("crc64.sst");
+    build(&path, ChecksumType::Crc64);
+    let data = std::fs::read(&path).unwrap();
+
+    // a flipped byte in the second block
+    let table = open(&path).unwrap();
+    let offset = table.block_meta[1].offset + 3;
+    let mut corrupted = data.clone();
+    corrupted[offset] ^= 0x01;
+    std::fs::write(&path, &corrupted).unwrap();
+    let table = open(&path).unwrap();
+    table.read_block(0).unwrap();
+    let Err(err) = table.read_block(1) else {
+        panic!("read a corrupt block");
+    };
+    assert_eq!(err.to_string(), "block checksum mismatched");
+    assert!(table.copy_to(&mut Vec::new()).is_err());
+
+    // a flipped byte in the meta fails the open
+    let mut corrupted = data;
+    corrupted[table.block_meta_offset + 5] ^= 0x80;
+    std::fs::write(&path, &corrupted).unwrap();
+    assert_eq!(
+        open(&path).err().unwrap().to_string(),
+        "meta checksum mismatched"
+    );
+}
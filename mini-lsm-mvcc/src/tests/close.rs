@@ -0,0 +1,163 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::event_listener::{CompactionJobInfo, EventListener};
+use crate::lsm_storage::{ClosedError, LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 2000;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}_{:0100}", idx, 0).into_bytes()
+}
+
+/// Writes the keys, deleting every tenth one after writing it.
+fn write_keys(storage: &MiniLsm) {
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        if idx % 10 == 0 {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+    }
+}
+
+fn check_keys(storage: &MiniLsm) {
+    for idx in 0..NUM_KEYS {
+        let expected = (idx % 10 != 0).then(|| Bytes::from(value_of(idx)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected, "key {}", idx);
+    }
+}
+
+/// Closes the storage, which must not take long.
+fn close(storage: &MiniLsm) {
+    let begin = Instant::now();
+    storage.close().unwrap();
+    assert!(
+        begin.elapsed() < Duration::from_secs(10),
+        "close took {:?}",
+        begin.elapsed()
+    );
+}
+
+#[test]
+fn test_close_keeps_acknowledged_writes() {
+    for (enable_wal, close_flush) in [(true, false), (true, true), (false, false)] {
+        let dir = tempdir().unwrap();
+        let mut options = options();
+        options.enable_wal = enable_wal;
+        options.close_flush = close_flush;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        write_keys(&storage);
+        close(&storage);
+
+        let error = storage.put(b"key", b"value").unwrap_err();
+        assert_eq!(error.downcast_ref::<ClosedError>(), Some(&ClosedError));
+        assert!(storage.delete(b"key").unwrap_err().is::<ClosedError>());
+        assert!(storage.compaction_threads.lock().is_empty());
+        // closing again does nothing
+        storage.close().unwrap();
+        {
+            let state = storage.inner.state.load();
+            let in_memtables = !state.memtable.is_empty() || !state.imm_memtables.is_empty();
+            // without flushing them, the last writes are only in the WAL
+            assert_eq!(in_memtables, !close_flush && enable_wal);
+        }
+        check_keys(&storage);
+        drop(storage);
+
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        check_keys(&storage);
+        if close_flush {
+            let state = storage.inner.state.load();
+            assert!(state.memtable.is_empty() && state.imm_memtables.is_empty());
+        }
+    }
+}
+
+/// Blocks the compactions as they begin, until released.
+struct BlockingListener {
+    begun: crossbeam_channel::Sender<()>,
+    release: crossbeam_channel::Receiver<()>,
+}
+
+impl EventListener for BlockingListener {
+    fn on_compaction_begin(&self, _info: &CompactionJobInfo) {
+        self.begun.send(()).ok();
+        // returns at once once the sender is dropped
+        self.release.recv().ok();
+    }
+}
+
+#[test]
+fn test_close_abandons_compactions() {
+    let dir = tempdir().unwrap();
+    let (begun_tx, begun_rx) = crossbeam_channel::unbounded();
+    let (release_tx, release_rx) = crossbeam_channel::unbounded();
+    let mut options = options();
+    options.close_abandons_compactions = true;
+    options.event_listeners = vec![Arc::new(BlockingListener {
+        begun: begun_tx,
+        release: release_rx,
+    })];
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.pause_background_compaction();
+    write_keys(&storage);
+    while {
+        let state = storage.inner.state.load();
+        !state.memtable.is_empty() || !state.imm_memtables.is_empty()
+    } {
+        storage.force_flush().unwrap();
+    }
+    let l0_sstables = storage.inner.state.load().l0_sstables.clone();
+    assert!(l0_sstables.len() >= 2);
+    storage.resume_background_compaction();
+    begun_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    let closer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || close(&storage))
+    };
+    let begin = Instant::now();
+    while storage.inner.check_compaction_abandoned().is_ok() {
+        assert!(begin.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    drop(release_tx);
+    closer.join().unwrap();
+
+    // the compaction left the state and the directory as they were
+    {
+        let state = storage.inner.state.load();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert!(state.levels.iter().all(|(_, ssts)| ssts.is_empty()));
+    }
+    let num_ssts = std::fs::read_dir(dir.path())
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+        .count();
+    assert_eq!(num_ssts, l0_sstables.len());
+    drop(storage);
+
+    options.event_listeners = Vec::new();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_keys(&storage);
+}
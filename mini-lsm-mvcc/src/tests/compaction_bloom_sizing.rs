@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_compaction_bloom_sized_for_distinct_keys() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.target_sst_size = 1 << 20;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), b"old").unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    // keep the old versions alive through the compaction
+    let _snapshot = storage.new_txn().unwrap();
+    // 90% of the keys overlap with the first SST
+    for idx in 100..1100 {
+        storage.put(&key_of(idx), b"new").unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    let state = storage.state.load();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(state.levels[0].1.len(), 1);
+    let sst = &state.sstables[&state.levels[0].1[0]];
+    let properties = sst.properties();
+    assert_eq!(properties.num_entries, 2000);
+    assert_eq!(properties.num_shadowed_entries, 900);
+    assert_eq!(properties.num_distinct_keys, 1100);
+    // sized for 1% false positives on the 1100 distinct keys, not on the 2000 entries
+    let bits_per_key = properties.bloom_bits_per_key();
+    assert!((9.5..10.5).contains(&bits_per_key), "{}", bits_per_key);
+    assert_eq!(
+        sst.bloom.as_ref().unwrap().filter.len() as u64 * 8,
+        properties.num_bloom_bits
+    );
+    for idx in 0..1100 {
+        assert!(sst.may_contain_key(&key_of(idx)));
+    }
+}
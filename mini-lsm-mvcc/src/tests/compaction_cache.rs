@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::BlockCache;
+use crate::rate_limiter::RateLimiter;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(prefix: &str, idx: usize) -> String {
+    format!("{}_{:05}", prefix, idx)
+}
+
+fn build_sst(
+    dir: &Path,
+    id: usize,
+    prefix: &str,
+    num_keys: usize,
+    block_cache: &Arc<BlockCache>,
+) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(256);
+    for i in 0..num_keys {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(key_of(prefix, i).as_bytes()),
+            format!("value_{:050}", i).as_bytes(),
+        );
+    }
+    Arc::new(
+        builder
+            .build(
+                id,
+                Some(block_cache.clone()),
+                dir.join(format!("{}.sst", id)),
+            )
+            .unwrap(),
+    )
+}
+
+fn cached_blocks(sst: &SsTable, block_cache: &BlockCache) -> usize {
+    (0..sst.num_of_blocks())
+        .filter(|idx| block_cache.contains_key(&(0, sst.sst_id(), *idx)))
+        .count()
+}
+
+fn check_keys(mut iter: SsTableIterator, prefix: &str, range: std::ops::Range<usize>) {
+    for i in range {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), key_of(prefix, i).as_bytes());
+        assert_eq!(iter.value(), format!("value_{:050}", i).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_compaction_reads_bypass_block_cache() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let hot = build_sst(dir.path(), 1, "hot", 100, &block_cache);
+    let cold = build_sst(dir.path(), 2, "cold", 20000, &block_cache);
+    assert!(cold.num_of_blocks() > 1000);
+
+    // foreground reads fill the cache
+    check_keys(
+        SsTableIterator::create_and_seek_to_first(hot.clone()).unwrap(),
+        "hot",
+        0..100,
+    );
+    assert_eq!(cached_blocks(&hot, &block_cache), hot.num_of_blocks());
+
+    // compaction reads, including the readahead across many blocks, leave the cache alone
+    let rate_limiter = Some(Arc::new(RateLimiter::new(0)));
+    check_keys(
+        SsTableIterator::create_and_seek_to_first_with_rate_limiter(
+            cold.clone(),
+            rate_limiter.clone(),
+        )
+        .unwrap(),
+        "cold",
+        0..20000,
+    );
+    check_keys(
+        SsTableIterator::create_and_seek_to_key_with_rate_limiter(
+            cold.clone(),
+            KeySlice::for_testing_from_slice_no_ts(key_of("cold", 12345).as_bytes()),
+            rate_limiter,
+        )
+        .unwrap(),
+        "cold",
+        12345..20000,
+    );
+    assert_eq!(cached_blocks(&cold, &block_cache), 0);
+    assert_eq!(cached_blocks(&hot, &block_cache), hot.num_of_blocks());
+
+    // compaction still uses blocks that are already cached
+    let mut iter = SsTableIterator::create_and_seek_to_first_with_rate_limiter(
+        hot.clone(),
+        Some(Arc::new(RateLimiter::new(0))),
+    )
+    .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    assert_eq!(cached_blocks(&hot, &block_cache), hot.num_of_blocks());
+
+    // foreground scans of the same table do fill the cache
+    check_keys(
+        SsTableIterator::create_and_seek_to_first(cold.clone()).unwrap(),
+        "cold",
+        0..20000,
+    );
+    assert_eq!(cached_blocks(&cold, &block_cache), cold.num_of_blocks());
+}
+
+#[test]
+fn test_read_blocks_readahead() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(16));
+    let sst = build_sst(dir.path(), 1, "key", 1000, &block_cache);
+    let num_blocks = sst.num_of_blocks();
+
+    // at least one block is read even if it does not fit in the readahead window
+    assert_eq!(sst.read_blocks(0, 0).unwrap().len(), 1);
+    let window = sst.blocks_len_on_disk(3, 8);
+    let blocks = sst.read_blocks(3, window).unwrap();
+    assert_eq!(blocks.len(), 5);
+    for (idx, block) in (3..).zip(blocks) {
+        assert_eq!(block.data, sst.read_block(idx).unwrap().data);
+    }
+    // the readahead stops at the end of the table
+    let blocks = sst.read_blocks(num_blocks - 2, usize::MAX).unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert!(block_cache.get(&(0, 1, 0)).is_none());
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ))
+}
+
+/// Writes two L0 SSTs, the second one overwriting or deleting some keys of the first.
+fn write_l0(storage: &Arc<LsmStorageInner>) {
+    for round in 0..2 {
+        for i in 0..100 {
+            let key = format!("key_{:03}", i);
+            if round == 1 && i % 10 == 0 {
+                storage.delete(key.as_bytes()).unwrap();
+            } else if round == 0 || i % 2 == 0 {
+                let value = format!("value_{}_{}", round, i);
+                storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+            }
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+fn check_data(storage: &Arc<LsmStorageInner>) {
+    for i in 0..100 {
+        let expected = if i % 10 == 0 {
+            None
+        } else if i % 2 == 0 {
+            Some(Bytes::from(format!("value_1_{}", i)))
+        } else {
+            Some(Bytes::from(format!("value_0_{}", i)))
+        };
+        assert_eq!(
+            storage.get(format!("key_{:03}", i).as_bytes()).unwrap(),
+            expected
+        );
+    }
+}
+
+fn sst_files(path: &Path) -> HashSet<usize> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".sst").map(|id| id.parse().unwrap())
+        })
+        .collect()
+}
+
+/// Every SST of the state has a file, and every file belongs to an SST of the state.
+fn check_files(storage: &Arc<LsmStorageInner>, path: &Path) {
+    let referenced = storage
+        .state
+        .load()
+        .sstables
+        .keys()
+        .copied()
+        .collect::<HashSet<_>>();
+    assert_eq!(sst_files(path), referenced);
+}
+
+#[test]
+fn test_crash_during_compaction_installation() {
+    for (failpoint, committed) in [
+        ("compaction_outputs_written", false),
+        ("compaction_dir_synced", false),
+        ("compaction_manifest_written", true),
+        ("compaction_state_installed", true),
+    ] {
+        let dir = tempdir().unwrap();
+        let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+        write_l0(&storage);
+        let l0_sstables = storage.state.load().l0_sstables.clone();
+
+        storage.failpoints.lock().insert(failpoint);
+        let err = storage.trigger_compaction().unwrap_err();
+        assert!(err.to_string().contains(failpoint), "{}", err);
+        // the running engine still reads everything, whatever it has installed
+        check_data(&storage);
+        drop(storage);
+
+        let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+        check_data(&storage);
+        check_files(&storage, dir.path());
+        {
+            let state = storage.state.load();
+            if committed {
+                assert!(state.l0_sstables.is_empty(), "{}", failpoint);
+                assert!(!state.levels[0].1.is_empty(), "{}", failpoint);
+            } else {
+                assert_eq!(state.l0_sstables, l0_sstables, "{}", failpoint);
+                assert!(state.levels[0].1.is_empty(), "{}", failpoint);
+            }
+        }
+
+        // the engine keeps working after recovery
+        storage.trigger_compaction().unwrap();
+        check_data(&storage);
+        check_files(&storage, dir.path());
+    }
+}
+
+#[test]
+fn test_compaction_inputs_deleted_after_iterators_release_them() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    write_l0(&storage);
+    let inputs = storage.state.load().l0_sstables.clone();
+
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.load().l0_sstables.is_empty());
+    // the iterator still reads the inputs, so their files are kept
+    let files = sst_files(dir.path());
+    assert!(inputs.iter().all(|id| files.contains(id)));
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 100 - 10);
+    drop(iter);
+
+    // the next deletion removes them
+    storage.delete_obsolete_ssts(Vec::new()).unwrap();
+    check_files(&storage, dir.path());
+    check_data(&storage);
+}
+
+#[test]
+fn test_recovery_removes_orphan_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    write_l0(&storage);
+    drop(storage);
+    // the output of a compaction that never reached the manifest
+    let orphan = LsmStorageInner::path_of_sst_static(dir.path(), 100);
+    let existing = sst_files(dir.path()).into_iter().min().unwrap();
+    std::fs::copy(
+        LsmStorageInner::path_of_sst_static(dir.path(), existing),
+        &orphan,
+    )
+    .unwrap();
+
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert!(!orphan.exists());
+    check_files(&storage, dir.path());
+    check_data(&storage);
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionEntryFilter, CompactionTask, FilterDecision, SimpleLeveledCompactionTask},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    trace::trace_span,
+};
+
+use super::harness::{construct_merge_iterator_over_storage, sync};
+
+/// Values are encoded as `<expire_at>|<payload>`.
+struct ExpireFilter {
+    now: u64,
+    strip_payload: bool,
+}
+
+impl CompactionEntryFilter for ExpireFilter {
+    fn name(&self) -> &str {
+        "expire"
+    }
+
+    fn filter(&self, _level: usize, _key: &[u8], value: &[u8]) -> FilterDecision {
+        let value = std::str::from_utf8(value).unwrap();
+        let (expire_at, payload) = value.split_once('|').unwrap();
+        if expire_at.parse::<u64>().unwrap() < self.now {
+            FilterDecision::Remove
+        } else if self.strip_payload && !payload.is_empty() {
+            FilterDecision::ChangeValue(Bytes::from(format!("{}|", expire_at)))
+        } else {
+            FilterDecision::Keep
+        }
+    }
+}
+
+fn open_with_filter(path: &std::path::Path, strip_payload: bool) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.compaction_entry_filter = Some(Arc::new(ExpireFilter {
+        now: 50,
+        strip_payload,
+    }));
+    Arc::new(LsmStorageInner::open(path, options).unwrap())
+}
+
+fn key_of(i: u64) -> Vec<u8> {
+    format!("key_{:03}", i).into_bytes()
+}
+
+fn populate(storage: &Arc<LsmStorageInner>) {
+    for i in 0..100 {
+        storage
+            .put(&key_of(i), format!("{}|payload_{}", i, i).as_bytes())
+            .unwrap();
+    }
+    sync(storage);
+}
+
+/// Returns all entries stored in the SSTs as (key, value).
+fn dump_sst_entries(storage: &LsmStorageInner) -> Vec<(Bytes, Bytes)> {
+    let mut iter = construct_merge_iterator_over_storage(&storage.state.load());
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_entry_filter_removes_expired_at_bottom_level() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_filter(dir.path(), false);
+    populate(&storage);
+    storage.force_full_compaction().unwrap();
+
+    let entries = dump_sst_entries(&storage);
+    let expected = (50..100)
+        .map(|i| {
+            (
+                Bytes::from(key_of(i)),
+                Bytes::from(format!("{}|payload_{}", i, i)),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(entries, expected);
+    for i in 0..100 {
+        assert_eq!(storage.get(&key_of(i)).unwrap().is_some(), i >= 50);
+    }
+}
+
+#[test]
+fn test_entry_filter_change_value() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_filter(dir.path(), true);
+    populate(&storage);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(&key_of(10)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(60)).unwrap(),
+        Some(Bytes::from_static(b"60|"))
+    );
+}
+
+#[test]
+fn test_entry_filter_skips_entries_visible_to_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_filter(dir.path(), false);
+    populate(&storage);
+    let snapshot = storage.new_txn().unwrap();
+    // written after the snapshot, so it is not visible to it and can be filtered
+    storage.put(&key_of(100), b"0|late").unwrap();
+    sync(&storage);
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        snapshot.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"0|payload_0"))
+    );
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"0|payload_0"))
+    );
+    assert_eq!(storage.get(&key_of(100)).unwrap(), None);
+    // the removed entry is above the watermark and becomes a tombstone
+    assert_eq!(dump_sst_entries(&storage).len(), 101);
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    assert_eq!(dump_sst_entries(&storage).len(), 50);
+}
+
+#[test]
+fn test_entry_filter_writes_tombstone_above_bottom_level() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_filter(dir.path(), false);
+    populate(&storage);
+    let l0_sstables = storage.state.load().l0_sstables.clone();
+    let task = CompactionTask::Simple(SimpleLeveledCompactionTask {
+        upper_level: None,
+        upper_level_sst_ids: l0_sstables,
+        lower_level: 1,
+        lower_level_sst_ids: Vec::new(),
+        is_lower_level_bottom_level: false,
+    });
+    let ssts = storage
+        .compact(&task, 0, &trace_span!("compaction"))
+        .unwrap();
+    let mut tombstones = 0;
+    for sst in &ssts {
+        let mut iter =
+            crate::table::SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            if iter.value().is_empty() {
+                tombstones += 1;
+            }
+            iter.next().unwrap();
+        }
+    }
+    // the expired entries may still shadow older versions in lower levels
+    assert_eq!(tombstones, 50);
+}
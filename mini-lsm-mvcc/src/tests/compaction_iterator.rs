@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionIterStats, CompactionIterator};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn collect(
+    upper: Option<&[u8]>,
+    drop_tombstones: bool,
+) -> (Vec<(String, u64)>, CompactionIterStats) {
+    let entries: &[(&str, u64, &str)] = &[
+        ("a", 5, "a5"),
+        ("a", 3, "a3"),
+        ("a", 2, "a2"),
+        ("b", 3, ""),
+        ("b", 1, "b1"),
+        ("c", 6, ""),
+        ("c", 2, "c2"),
+        ("d", 1, "d1"),
+    ];
+    let mut builder = SsTableBuilder::new(4096);
+    for (key, ts, value) in entries {
+        builder.add(KeySlice::from_slice(key.as_bytes(), *ts), value.as_bytes());
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    let mut iter = CompactionIterator::create(
+        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+        upper,
+        4,
+        drop_tombstones,
+    )
+    .unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            String::from_utf8(iter.key().key_ref().to_vec()).unwrap(),
+            iter.key().ts(),
+        ));
+        iter.next().unwrap();
+    }
+    (result, iter.stats())
+}
+
+fn keys(expected: &[(&str, u64)]) -> Vec<(String, u64)> {
+    expected
+        .iter()
+        .map(|(k, ts)| (k.to_string(), *ts))
+        .collect()
+}
+
+#[test]
+fn test_compaction_iterator_bottom_level() {
+    let (result, stats) = collect(None, true);
+    assert_eq!(
+        result,
+        keys(&[("a", 5), ("a", 3), ("c", 6), ("c", 2), ("d", 1)])
+    );
+    assert_eq!(
+        stats,
+        CompactionIterStats {
+            tombstones_dropped: 1,
+            versions_collapsed: 2,
+            entries_kept: 5,
+        }
+    );
+}
+
+#[test]
+fn test_compaction_iterator_keeps_tombstones_above_bottom_level() {
+    let (result, stats) = collect(None, false);
+    assert_eq!(
+        result,
+        keys(&[("a", 5), ("a", 3), ("b", 3), ("c", 6), ("c", 2), ("d", 1)])
+    );
+    assert_eq!(
+        stats,
+        CompactionIterStats {
+            tombstones_dropped: 0,
+            versions_collapsed: 2,
+            entries_kept: 6,
+        }
+    );
+}
+
+#[test]
+fn test_compaction_iterator_upper_bound() {
+    let (result, stats) = collect(Some(b"c"), true);
+    assert_eq!(result, keys(&[("a", 5), ("a", 3)]));
+    assert_eq!(
+        stats,
+        CompactionIterStats {
+            tombstones_dropped: 1,
+            versions_collapsed: 2,
+            entries_kept: 2,
+        }
+    );
+}
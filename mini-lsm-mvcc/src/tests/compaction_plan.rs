@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn open(dir: &std::path::Path, compaction_options: CompactionOptions) -> Arc<LsmStorageInner> {
+    Arc::new(
+        LsmStorageInner::open(
+            dir,
+            LsmStorageOptions::default_for_week2_test(compaction_options),
+        )
+        .unwrap(),
+    )
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_plan_matches_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = open(
+        dir.path(),
+        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+        }),
+    );
+    // each SST shadows two versions of one key, and a value with a tombstone on top of it
+    for i in 0..2 {
+        for round in 0..3 {
+            storage
+                .put(
+                    format!("key_{}", i).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .put(format!("deleted_{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.delete(format!("deleted_{}", i).as_bytes()).unwrap();
+        assert!(storage.plan_compaction().is_none());
+        flush(&storage);
+    }
+
+    let plan = storage.plan_compaction().unwrap();
+    let l0_sstables = storage.state.load().l0_sstables.clone();
+    assert_eq!(plan.inputs.len(), 2);
+    assert_eq!(plan.inputs[0].level, 0);
+    assert_eq!(
+        plan.inputs[0]
+            .ssts
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>(),
+        l0_sstables
+    );
+    assert_eq!(plan.inputs[1].level, 1);
+    assert!(plan.inputs[1].ssts.is_empty());
+    assert_eq!(plan.estimated_entries_dropped, 2 * 4);
+    assert!(plan.estimated_output_bytes < plan.input_bytes());
+    assert!(
+        plan.reason.contains("between level 0 and 1"),
+        "{}",
+        plan.reason
+    );
+    // planning does not claim or change anything
+    assert_eq!(
+        storage.plan_compaction().unwrap().task.input_sst_ids(),
+        plan.task.input_sst_ids()
+    );
+
+    storage.trigger_compaction().unwrap();
+    let job = storage.compaction_stats().recent_jobs.pop().unwrap();
+    assert_eq!(job.input_levels, vec![0, 1]);
+    assert_eq!(job.input_files, plan.task.input_sst_ids().len());
+    assert_eq!(job.input_bytes, plan.input_bytes());
+    assert_eq!(job.entries_dropped, plan.estimated_entries_dropped);
+    let state = storage.state.load();
+    assert!(state.l0_sstables.is_empty());
+    for id in plan.task.input_sst_ids() {
+        assert!(!state.sstables.contains_key(&id));
+    }
+}
+
+#[test]
+fn test_no_plan_without_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), CompactionOptions::NoCompaction);
+    for _ in 0..3 {
+        storage.put(b"key", b"value").unwrap();
+        flush(&storage);
+    }
+    assert!(storage.plan_compaction().is_none());
+}
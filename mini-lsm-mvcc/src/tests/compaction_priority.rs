@@ -0,0 +1,198 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{
+    CompactionOptions, CompactionPriority, LeveledCompactionController, LeveledCompactionOptions,
+    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, SstMap};
+use crate::mem_table::MemTable;
+use crate::table::{SsTable, SsTableBuilder};
+
+/// Builds an SST with `num_keys` keys under `prefix`, where every key is a tombstone if
+/// `tombstones` is set.
+fn build_sst(dir: &Path, id: usize, prefix: &str, num_keys: usize, tombstones: bool) -> SsTable {
+    let mut builder = SsTableBuilder::new(4096);
+    for i in 0..num_keys {
+        let key = format!("{}_{:05}", prefix, i);
+        let value = if tombstones {
+            Vec::new()
+        } else {
+            format!("value_{:0100}", i).into_bytes()
+        };
+        builder.add(KeySlice::from_slice(key.as_bytes(), 1), &value);
+    }
+    builder
+        .build(id, None, dir.join(format!("{}.sst", id)))
+        .unwrap()
+}
+
+fn state_with(
+    l0_sstables: Vec<usize>,
+    levels: Vec<Vec<usize>>,
+    ssts: Vec<SsTable>,
+) -> LsmStorageState {
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables,
+        levels: levels
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| (i + 1, x))
+            .collect(),
+        sstables: ssts
+            .into_iter()
+            .map(|sst| (sst.sst_id(), Arc::new(sst)))
+            .collect::<SstMap>(),
+    }
+}
+
+#[test]
+fn test_simple_leveled_l0_first() {
+    let dir = tempdir().unwrap();
+    let ssts = (1..=7)
+        .map(|id| build_sst(dir.path(), id, &format!("key{}", id), 100, id >= 4))
+        .collect();
+    // L0 reached its trigger, while L2 (all tombstones) is far beyond its size ratio
+    let mut state = state_with(vec![2, 1], vec![vec![3], vec![4, 5, 6, 7], vec![]], ssts);
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let debt = controller.compaction_debt(&state);
+    let priorities = debt.iter().map(|x| x.priority).collect::<Vec<_>>();
+    assert_eq!(
+        priorities,
+        vec![
+            Some(CompactionPriority::L0Pressure),
+            None,
+            Some(CompactionPriority::StallImminent),
+            None
+        ]
+    );
+    assert!(debt[0].debt_bytes > 0);
+    assert!(debt[2].debt_bytes > 0);
+    assert_eq!(debt[3].debt_bytes, 0);
+
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.upper_level_sst_ids, vec![2, 1]);
+
+    // once L0 is relieved, the deep level is next
+    state.l0_sstables.clear();
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+}
+
+#[test]
+fn test_simple_leveled_stall_before_size_ratio() {
+    let dir = tempdir().unwrap();
+    let ssts = (1..=7)
+        .map(|id| build_sst(dir.path(), id, &format!("key{}", id), 100, id <= 3))
+        .collect();
+    // L1 (all tombstones) slightly violates the size ratio, L2 is about to stall
+    let state = state_with(
+        Vec::new(),
+        vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]],
+        ssts,
+    );
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 150,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let debt = controller.compaction_debt(&state);
+    assert_eq!(debt[1].priority, Some(CompactionPriority::SizeRatio));
+    assert_eq!(debt[2].priority, Some(CompactionPriority::StallImminent));
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+}
+
+#[test]
+fn test_leveled_l0_first() {
+    let dir = tempdir().unwrap();
+    let state = |l0_sstables| {
+        state_with(
+            l0_sstables,
+            vec![vec![], vec![3, 4, 5], vec![6, 7]],
+            vec![
+                build_sst(dir.path(), 1, "a", 100, false),
+                build_sst(dir.path(), 2, "b", 100, false),
+                build_sst(dir.path(), 3, "c", 10000, false),
+                build_sst(dir.path(), 4, "d", 10000, false),
+                build_sst(dir.path(), 5, "e", 10000, false),
+                build_sst(dir.path(), 6, "f", 10000, false),
+                build_sst(dir.path(), 7, "g", 10000, false),
+            ],
+        )
+    };
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    });
+
+    // L0 reached its trigger, while L2 is three times its target size
+    let state_with_l0 = state(vec![2, 1]);
+    let debt = controller.compaction_debt(&state_with_l0);
+    assert_eq!(debt[0].priority, Some(CompactionPriority::L0Pressure));
+    assert_eq!(debt[2].priority, Some(CompactionPriority::StallImminent));
+    assert!(debt[2].debt_bytes > 0);
+    assert_eq!(debt[3].priority, None);
+    let task = controller.generate_compaction_task(&state_with_l0).unwrap();
+    assert_eq!(task.upper_level, None);
+
+    let task = controller
+        .generate_compaction_task(&state(Vec::new()))
+        .unwrap();
+    assert_eq!(task.upper_level, Some(2));
+}
+
+#[test]
+fn test_compaction_debt_in_stats() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            dir.path(),
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+                SimpleLeveledCompactionOptions {
+                    size_ratio_percent: 200,
+                    level0_file_num_compaction_trigger: 2,
+                    max_levels: 2,
+                },
+            )),
+        )
+        .unwrap(),
+    );
+    let debt = storage.compaction_stats().compaction_debt;
+    assert_eq!(debt.len(), 3);
+    assert!(debt
+        .iter()
+        .all(|x| x.priority.is_none() && x.debt_bytes == 0));
+    for i in 0..2 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    let debt = storage.compaction_stats().compaction_debt;
+    assert_eq!(debt[0].priority, Some(CompactionPriority::L0Pressure));
+    assert!(debt[0].debt_bytes > 0);
+    storage.trigger_compaction().unwrap();
+    // L0 is relieved, now L1 violates the size ratio against the empty L2
+    let debt = storage.compaction_stats().compaction_debt;
+    assert_eq!(debt[0].debt_bytes, 0);
+    assert_eq!(debt[1].priority, Some(CompactionPriority::StallImminent));
+    storage.trigger_compaction().unwrap();
+    let debt = storage.compaction_stats().compaction_debt;
+    assert!(debt.iter().all(|x| x.debt_bytes == 0));
+}
@@ -0,0 +1,117 @@
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+/// Flips the first byte of an SST on disk, so that the checksum of its first block fails.
+fn flip_first_byte(storage: &LsmStorageInner, sst_id: usize) {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(storage.path_of_sst(sst_id))
+        .unwrap();
+    let mut byte = [0];
+    file.read_exact_at(&mut byte, 0).unwrap();
+    byte[0] ^= 0xff;
+    file.write_all_at(&byte, 0).unwrap();
+    file.sync_all().unwrap();
+}
+
+fn num_sst_files(path: &std::path::Path) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "sst")
+        })
+        .count()
+}
+
+#[test]
+fn test_compaction_retry_and_background_error() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.compaction_retry_backoff = Duration::from_millis(20);
+    options.max_compaction_failures = 2;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..2 {
+        for j in 0..100 {
+            storage
+                .put(
+                    format!("key_{:03}", j).as_bytes(),
+                    format!("value_{}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    let corrupted_sst_id = storage.state.load().l0_sstables[0];
+    flip_first_byte(&storage, corrupted_sst_id);
+    assert_eq!(num_sst_files(dir.path()), 2);
+
+    // the first failure is recorded, but not reported as a background error yet
+    assert!(storage.trigger_compaction_with_backoff().is_err());
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_compaction_failures, 1);
+    assert!(
+        stats
+            .last_compaction_error
+            .as_ref()
+            .unwrap()
+            .contains("checksum"),
+        "{:?}",
+        stats.last_compaction_error
+    );
+    assert!(storage.background_error().is_none());
+    // no partial output is left behind, and the inputs are untouched
+    assert_eq!(num_sst_files(dir.path()), 2);
+    assert_eq!(storage.state.load().l0_sstables.len(), 2);
+
+    // retrying right away is skipped until the backoff has passed
+    assert!(storage.trigger_compaction_with_backoff().is_ok());
+    assert_eq!(storage.compaction_stats().num_compaction_failures, 1);
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(storage.trigger_compaction_with_backoff().is_err());
+    assert_eq!(storage.compaction_stats().num_compaction_failures, 2);
+    let error = storage.background_error().unwrap();
+    assert!(
+        error.contains("2 consecutive compaction failures"),
+        "{}",
+        error
+    );
+    assert_eq!(num_sst_files(dir.path()), 2);
+
+    // the backoff doubled
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(storage.trigger_compaction_with_backoff().is_ok());
+    assert_eq!(storage.compaction_stats().num_compaction_failures, 2);
+
+    // once the fault is gone, the next retry succeeds and clears the background error
+    flip_first_byte(&storage, corrupted_sst_id);
+    std::thread::sleep(Duration::from_millis(20));
+    storage.trigger_compaction_with_backoff().unwrap();
+    assert!(storage.background_error().is_none());
+    assert!(storage.state.load().l0_sstables.is_empty());
+    assert_eq!(storage.compaction_stats().num_compaction_failures, 2);
+    assert_eq!(
+        storage.get(b"key_042").unwrap().unwrap().as_ref(),
+        b"value_1"
+    );
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{BackgroundJobKind, StatsCollector},
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::harness::sync;
+
+#[test]
+fn test_flush_and_compaction_stats() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    // 3 rounds overwriting the same 100 keys, each key and value is 10 bytes
+    for round in 0..3 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key_{:06}", i).as_bytes(),
+                    format!("value_{:04}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.user_bytes_written, 3 * 100 * 20);
+    assert_eq!(stats.num_flushes, 3);
+    assert_eq!(stats.num_compactions, 0);
+    assert_eq!(stats.recent_jobs.len(), 3);
+    for job in &stats.recent_jobs {
+        assert_eq!(job.kind, BackgroundJobKind::Flush);
+        assert_eq!(job.output_files, 1);
+        assert_eq!(job.entries_read, 100);
+        assert_eq!(job.entries_written, 100);
+        assert_eq!(job.entries_dropped, 0);
+    }
+    let flushed_bytes = stats.flush_bytes_written;
+    assert_eq!(
+        flushed_bytes,
+        stats
+            .recent_jobs
+            .iter()
+            .map(|x| x.output_bytes)
+            .sum::<u64>()
+    );
+    // SSTs store the timestamps, block offsets and a bloom filter on top of the user data
+    let write_amp = stats.write_amplification();
+    assert!(
+        write_amp > 1.0 && write_amp < 2.0,
+        "write amplification after flush is {}",
+        write_amp
+    );
+
+    // no snapshot is held, so only the latest version of each key survives
+    storage.force_full_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_compactions, 1);
+    let job = stats.recent_jobs.last().unwrap();
+    assert_eq!(job.kind, BackgroundJobKind::Compaction);
+    assert_eq!(job.input_levels, vec![0, 1]);
+    assert_eq!(job.output_level, 1);
+    assert_eq!(job.input_files, 3);
+    assert_eq!(job.input_bytes, flushed_bytes);
+    assert_eq!(job.entries_read, 300);
+    assert_eq!(job.entries_written, 100);
+    assert_eq!(job.entries_dropped, 200);
+    assert_eq!(stats.compaction_bytes_read, flushed_bytes);
+    assert_eq!(stats.compaction_bytes_written, job.output_bytes);
+    assert!(job.output_bytes < flushed_bytes);
+    let compacted_write_amp = stats.write_amplification();
+    assert!(
+        compacted_write_amp > write_amp && compacted_write_amp < 2.0 * write_amp,
+        "write amplification after compaction is {}",
+        compacted_write_amp
+    );
+}
+
+#[test]
+fn test_recent_jobs_ring_buffer() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let num_flushes = StatsCollector::RECENT_JOBS_CAPACITY + 5;
+    for i in 0..num_flushes {
+        storage
+            .put(format!("key_{:04}", i).as_bytes(), b"value")
+            .unwrap();
+        sync(&storage);
+    }
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_flushes, num_flushes as u64);
+    assert_eq!(
+        stats.recent_jobs.len(),
+        StatsCollector::RECENT_JOBS_CAPACITY
+    );
+}
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{
+    CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
+    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+};
+use crate::key::KeyBytes;
+use crate::lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm, SstMap};
+use crate::mem_table::MemTable;
+use crate::table::SsTable;
+
+fn meta_only_sst(id: usize, size: u64, first_key: &str, last_key: &str) -> Arc<SsTable> {
+    Arc::new(SsTable::create_meta_only(
+        id,
+        size,
+        KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(first_key.as_bytes()), 1),
+        KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(last_key.as_bytes()), 1),
+    ))
+}
+
+fn state_with(
+    l0_sstables: Vec<usize>,
+    levels: Vec<(usize, Vec<usize>)>,
+    ssts: Vec<Arc<SsTable>>,
+) -> LsmStorageState {
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables,
+        levels,
+        sstables: ssts
+            .into_iter()
+            .map(|sst| (sst.sst_id(), sst))
+            .collect::<SstMap>(),
+    }
+}
+
+#[test]
+fn test_simple_leveled_skips_busy_levels() {
+    let state = state_with(
+        vec![11, 10],
+        vec![(1, vec![1]), (2, vec![2]), (3, Vec::new())],
+        Vec::new(),
+    );
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state, &HashSet::new())
+        .unwrap();
+    assert_eq!(task.upper_level, None);
+
+    // L0 -> L1 is running, so neither L0 nor L1 can be picked
+    let busy = HashSet::from([10, 11, 1]);
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state, &busy)
+        .unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert_eq!(task.lower_level, 3);
+
+    let busy = HashSet::from([10, 2]);
+    assert!(controller
+        .generate_compaction_task_excluding(&state, &busy)
+        .is_none());
+}
+
+#[test]
+fn test_leveled_skips_busy_ssts() {
+    let state = state_with(
+        Vec::new(),
+        vec![(1, vec![1, 2]), (2, vec![3])],
+        vec![
+            meta_only_sst(1, 1 << 20, "a", "c"),
+            meta_only_sst(2, 1 << 20, "x", "z"),
+            meta_only_sst(3, 1 << 10, "b", "d"),
+        ],
+    );
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state, &HashSet::new())
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.lower_level_sst_ids, vec![3]);
+
+    // the lower level SST overlapping SST 1 is busy, so SST 2 is picked instead
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state, &HashSet::from([3]))
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert!(task.lower_level_sst_ids.is_empty());
+
+    assert!(controller
+        .generate_compaction_task_excluding(&state, &HashSet::from([2, 3]))
+        .is_none());
+}
+
+#[test]
+fn test_leveled_overlap_compares_user_keys() {
+    let with_ts = |id, size, first_key: &str, first_ts, last_key: &str, last_ts| {
+        Arc::new(SsTable::create_meta_only(
+            id,
+            size,
+            KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(first_key.as_bytes()), first_ts),
+            KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(last_key.as_bytes()), last_ts),
+        ))
+    };
+    // SST 3 starts with an older version of the last key of SST 1
+    let state = state_with(
+        Vec::new(),
+        vec![(1, vec![1]), (2, vec![2, 3])],
+        vec![
+            with_ts(1, 1 << 20, "b", 5, "c", 5),
+            with_ts(2, 1 << 10, "a", 1, "a", 1),
+            with_ts(3, 1 << 10, "c", 1, "d", 1),
+        ],
+    );
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state, &HashSet::new())
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.lower_level_sst_ids, vec![3]);
+}
+
+#[test]
+fn test_leveled_skips_tasks_overlapping_running_output() {
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    let state = |l0_first_key, l0_last_key| {
+        state_with(
+            vec![11, 10],
+            vec![(1, vec![1]), (2, Vec::new())],
+            vec![
+                meta_only_sst(1, 1 << 20, "m", "n"),
+                meta_only_sst(10, 1 << 10, "a", "b"),
+                meta_only_sst(11, 1 << 10, l0_first_key, l0_last_key),
+            ],
+        )
+    };
+    let busy = HashSet::from([10, 11]);
+    // the L0 SSTs being compacted into L2 leave a gap around SST 1, but their output may not
+    assert!(controller
+        .generate_compaction_task_excluding(&state("x", "z"), &busy)
+        .is_none());
+
+    let (task, _) = controller
+        .generate_compaction_task_excluding(&state("c", "d"), &busy)
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+}
+
+fn check_level_invariants(state: &LsmStorageState) {
+    for (level, sst_ids) in &state.levels {
+        for pair in sst_ids.windows(2) {
+            let left = &state.sstables[&pair[0]];
+            let right = &state.sstables[&pair[1]];
+            assert!(
+                left.last_key().key_ref() < right.first_key().key_ref(),
+                "SST {} and {} of level {} overlap",
+                pair[0],
+                pair[1],
+                level
+            );
+        }
+    }
+}
+
+#[test]
+fn test_concurrent_compaction_workers() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options.num_compaction_threads = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+
+    let mut expected = HashMap::new();
+    for round in 0..3 {
+        for i in 0..30000 {
+            let key = format!("key_{:05}", (i * 7 + round * 13) % 40000);
+            if (i + round) % 11 == 0 {
+                storage.delete(key.as_bytes()).unwrap();
+                expected.remove(&key);
+            } else {
+                let value = format!("value_{}_{:0100}", round, i);
+                storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+                expected.insert(key, value);
+            }
+        }
+        check_level_invariants(&storage.inner.state.load());
+    }
+
+    // the flush thread only flushes one memtable per tick, drain the backlog here
+    while !storage.inner.state.load().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+    // wait for the background work to settle
+    let begin = Instant::now();
+    loop {
+        let settled = {
+            let state = storage.inner.state.load();
+            state.l0_sstables.len() < 2
+        } && storage.inner.compacting_ssts.lock().is_empty();
+        if settled {
+            break;
+        }
+        assert!(begin.elapsed() < Duration::from_secs(30));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    check_level_invariants(&storage.inner.state.load());
+    assert!(storage.compaction_stats().num_compactions > 0);
+
+    for i in 0..40000 {
+        let key = format!("key_{:05}", i);
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            expected
+                .get(&key)
+                .map(|v| Bytes::copy_from_slice(v.as_bytes())),
+            "mismatch at {}",
+            key
+        );
+    }
+    storage.close().unwrap();
+    assert!(storage.compaction_threads.lock().is_empty());
+}
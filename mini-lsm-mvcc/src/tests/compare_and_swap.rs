@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{CasResult, LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+fn mismatch(current: Option<&'static str>) -> CasResult {
+    CasResult::Mismatch {
+        current: current.map(Bytes::from),
+    }
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+
+    assert_eq!(
+        storage
+            .compare_and_swap(b"key", Some(b"1"), Some(b"2"))
+            .unwrap(),
+        mismatch(None)
+    );
+    assert_eq!(storage.get(b"key").unwrap(), None);
+    assert_eq!(
+        storage.compare_and_swap(b"key", None, Some(b"1")).unwrap(),
+        CasResult::Swapped
+    );
+    assert_eq!(
+        storage.compare_and_swap(b"key", None, Some(b"2")).unwrap(),
+        mismatch(Some("1"))
+    );
+    assert_eq!(
+        storage
+            .compare_and_swap(b"key", Some(b"2"), Some(b"3"))
+            .unwrap(),
+        mismatch(Some("1"))
+    );
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("1")));
+
+    // the current value may come from an SST
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(
+        storage
+            .compare_and_swap(b"key", Some(b"1"), Some(b"2"))
+            .unwrap(),
+        CasResult::Swapped
+    );
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("2")));
+
+    // swapping to `None` deletes the key, which is then absent
+    assert_eq!(
+        storage.compare_and_swap(b"key", Some(b"2"), None).unwrap(),
+        CasResult::Swapped
+    );
+    assert_eq!(storage.get(b"key").unwrap(), None);
+    assert_eq!(
+        storage.compare_and_swap(b"key", Some(b"2"), None).unwrap(),
+        mismatch(None)
+    );
+    assert_eq!(
+        storage.compare_and_swap(b"key", None, Some(b"4")).unwrap(),
+        CasResult::Swapped
+    );
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("4")));
+}
+
+#[test]
+fn test_compare_and_swap_concurrent_increments() {
+    const NUM_THREADS: usize = 8;
+    const INCREMENTS_PER_THREAD: usize = 50;
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1 << 12;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+
+    std::thread::scope(|scope| {
+        for thread in 0..NUM_THREADS {
+            let storage = storage.clone();
+            scope.spawn(move || {
+                for i in 0..INCREMENTS_PER_THREAD {
+                    // plain puts interleaved with the swaps, freezing memtables along the way
+                    storage
+                        .put(format!("key_{}_{}", thread, i).as_bytes(), &[b'x'; 64])
+                        .unwrap();
+                    let mut current = storage.get(b"counter").unwrap();
+                    loop {
+                        let count = current.as_ref().map_or(0, |value| {
+                            std::str::from_utf8(value)
+                                .unwrap()
+                                .parse::<usize>()
+                                .unwrap()
+                        });
+                        let new = (count + 1).to_string();
+                        match storage
+                            .compare_and_swap(b"counter", current.as_deref(), Some(new.as_bytes()))
+                            .unwrap()
+                        {
+                            CasResult::Swapped => break,
+                            CasResult::Mismatch { current: actual } => current = actual,
+                        }
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(
+        storage.get(b"counter").unwrap(),
+        Some(Bytes::from(
+            (NUM_THREADS * INCREMENTS_PER_THREAD).to_string()
+        ))
+    );
+    assert!(!storage.state.load().imm_memtables.is_empty());
+}
+
+#[test]
+fn test_compare_and_swap_recovered_from_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    assert_eq!(
+        storage.compare_and_swap(b"a", Some(b"1"), None).unwrap(),
+        CasResult::Swapped
+    );
+    assert_eq!(
+        storage.compare_and_swap(b"b", None, Some(b"2")).unwrap(),
+        CasResult::Swapped
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_compare_and_swap_conflicts_with_serializable_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    storage.put(b"key", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    assert_eq!(txn.get(b"key").unwrap(), Some(Bytes::from("1")));
+    txn.put(b"other", b"1");
+    assert_eq!(
+        storage
+            .compare_and_swap(b"key", Some(b"1"), Some(b"2"))
+            .unwrap(),
+        CasResult::Swapped
+    );
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(b"other").unwrap(), None);
+}
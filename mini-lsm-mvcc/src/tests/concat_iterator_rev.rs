@@ -0,0 +1,93 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::table::{SsTable, SsTableBuilder};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:03}", i).into_bytes()
+}
+
+/// Three non-overlapping tables over the keys `0..30`, `30..60` and `60..90`, of several blocks.
+fn tables(dir: &Path) -> Vec<Arc<SsTable>> {
+    (0..3)
+        .map(|t| {
+            let mut builder = SsTableBuilder::new(64);
+            for i in t * 30..(t + 1) * 30 {
+                builder.add(KeySlice::from_slice(&key_of(i), 1), b"value");
+            }
+            let table = builder
+                .build_for_test(dir.join(format!("{}.sst", t)))
+                .unwrap();
+            assert!(table.num_of_blocks() > 2);
+            Arc::new(table)
+        })
+        .collect()
+}
+
+fn current(iter: &SstConcatIterator) -> Option<Vec<u8>> {
+    iter.is_valid().then(|| iter.key().key_ref().to_vec())
+}
+
+#[test]
+fn test_concat_iterator_descending() {
+    let dir = tempdir().unwrap();
+    let tables = tables(dir.path());
+    let descending = (0..90).rev().map(key_of).collect::<Vec<_>>();
+
+    // a reverse iterator moves back with both `next` and `prev`
+    for use_prev in [false, true] {
+        let mut iter = SstConcatIterator::create_and_seek_to_last(tables.clone()).unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = current(&iter) {
+            keys.push(key);
+            if use_prev {
+                iter.prev().unwrap();
+            } else {
+                iter.next().unwrap();
+            }
+        }
+        assert_eq!(keys, descending);
+        iter.prev().unwrap();
+        assert!(!iter.is_valid());
+    }
+
+    let mut iter = SstConcatIterator::create_and_seek_to_key_rev(
+        tables.clone(),
+        KeySlice::from_slice(&key_of(60), TS_RANGE_BEGIN),
+    )
+    .unwrap();
+    assert_eq!(current(&iter), Some(key_of(59)));
+    iter.next().unwrap();
+    assert_eq!(current(&iter), Some(key_of(58)));
+
+    // a forward iterator moves back across the tables, then forward again
+    let mut iter = SstConcatIterator::create_and_seek_to_key(
+        tables.clone(),
+        KeySlice::from_slice(&key_of(61), 1),
+    )
+    .unwrap();
+    let mut keys = Vec::new();
+    for _ in 0..40 {
+        iter.prev().unwrap();
+        keys.push(current(&iter).unwrap());
+    }
+    assert_eq!(keys, (21..61).rev().map(key_of).collect::<Vec<_>>());
+    for i in 22..90 {
+        iter.next().unwrap();
+        assert_eq!(current(&iter), Some(key_of(i)));
+    }
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+
+    let mut iter = SstConcatIterator::create_and_seek_to_first(tables).unwrap();
+    iter.next().unwrap();
+    iter.prev().unwrap();
+    assert_eq!(current(&iter), Some(key_of(0)));
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+}
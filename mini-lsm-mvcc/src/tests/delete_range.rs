@@ -0,0 +1,294 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:04}", i).into_bytes()
+}
+
+fn value_of(i: usize) -> Vec<u8> {
+    format!("value_{:04}", i).into_bytes()
+}
+
+fn scan_keys(
+    storage: &Arc<LsmStorageInner>,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> Vec<Bytes> {
+    let mut iter = storage.scan(lower, upper).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+fn expected_keys(range: impl Iterator<Item = usize>) -> Vec<Bytes> {
+    range.map(|i| Bytes::from(key_of(i))).collect()
+}
+
+/// Flushes the memtable and all immutable memtables.
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+#[test]
+fn test_fragmented_range_tombstones() {
+    let tombstone = |start: &'static str, end: &'static str, ts| {
+        RangeTombstone::new(Bytes::from(start), Bytes::from(end), ts)
+    };
+    let fragments = FragmentedRangeTombstones::new([
+        tombstone("b", "f", 5),
+        tombstone("d", "h", 9),
+        tombstone("e", "g", 3),
+        tombstone("x", "x", 10),
+    ]);
+    assert_eq!(fragments.max_covering_ts(b"a"), None);
+    assert_eq!(fragments.max_covering_ts(b"b"), Some(5));
+    assert_eq!(fragments.max_covering_ts(b"c"), Some(5));
+    assert_eq!(fragments.max_covering_ts(b"d"), Some(9));
+    assert_eq!(fragments.max_covering_ts(b"g"), Some(9));
+    assert_eq!(fragments.max_covering_ts(b"h"), None);
+    assert_eq!(fragments.max_covering_ts(b"x"), None);
+    assert!(fragments.covers(b"c", 4));
+    assert!(!fragments.covers(b"c", 5));
+    assert!(fragments.covers(b"e", 8));
+}
+
+#[test]
+fn test_delete_range_masks_memtables_and_ssts() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush(&storage);
+    for i in 100..150 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    let snapshot = storage.new_txn().unwrap();
+
+    storage.delete_range(&key_of(20), &key_of(120)).unwrap();
+    assert_eq!(storage.get(&key_of(19)).unwrap(), Some(value_of(19).into()));
+    assert_eq!(storage.get(&key_of(20)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(99)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(119)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(120)).unwrap(),
+        Some(value_of(120).into())
+    );
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..20).chain(120..150))
+    );
+    assert_eq!(
+        scan_keys(
+            &storage,
+            Bound::Included(&key_of(10)),
+            Bound::Excluded(&key_of(125))
+        ),
+        expected_keys((10..20).chain(120..125))
+    );
+
+    // the snapshot taken before the range deletion still sees the keys
+    assert_eq!(
+        snapshot.get(&key_of(50)).unwrap(),
+        Some(value_of(50).into())
+    );
+
+    // a newer write is not covered, also once the tombstone is flushed
+    storage.put(&key_of(50), b"new").unwrap();
+    flush(&storage);
+    assert_eq!(storage.get(&key_of(50)).unwrap(), Some(Bytes::from("new")));
+    assert_eq!(storage.get(&key_of(51)).unwrap(), None);
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..20).chain([50]).chain(120..150))
+    );
+}
+
+#[test]
+fn test_delete_range_recovers_from_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options.clone()).unwrap());
+    for i in 0..50 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.delete_range(&key_of(10), &key_of(40)).unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..10).chain(40..50))
+    );
+    // writes after the recovery are newer than the recovered tombstone
+    storage.put(&key_of(20), &value_of(20)).unwrap();
+    assert_eq!(storage.get(&key_of(20)).unwrap(), Some(value_of(20).into()));
+}
+
+#[test]
+fn test_compaction_drops_range_deleted_keys() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1 << 12;
+    options.max_subcompactions = 4;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..1000 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush(&storage);
+    storage.delete_range(&key_of(100), &key_of(900)).unwrap();
+    flush(&storage);
+    let size_before = storage
+        .state
+        .load()
+        .sstables
+        .values()
+        .map(|sst| sst.table_size())
+        .sum::<u64>();
+
+    storage.force_full_compaction().unwrap();
+    let state = storage.state.load_full();
+    assert!(state.l0_sstables.is_empty());
+    let ssts = state.levels[0]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ssts.iter()
+            .map(|sst| sst.properties().num_entries)
+            .sum::<u64>(),
+        200
+    );
+    // nothing is older than the tombstone at the bottom level, so it is dropped
+    assert!(ssts.iter().all(|sst| sst.range_tombstones().is_empty()));
+    assert!(ssts.iter().map(|sst| sst.table_size()).sum::<u64>() < size_before / 2);
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..100).chain(900..1000))
+    );
+}
+
+#[test]
+fn test_compaction_keeps_range_tombstones_seen_by_snapshots() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1 << 12;
+    options.max_subcompactions = 4;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..1000 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush(&storage);
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(100), &key_of(900)).unwrap();
+    storage.delete_range(&key_of(2000), &key_of(3000)).unwrap();
+    flush(&storage);
+
+    storage.force_full_compaction().unwrap();
+    let state = storage.state.load_full();
+    let ssts = state.levels[0]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    // the tombstones are split between the output SSTs, which stay ordered
+    assert!(ssts.len() > 1);
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key() <= pair[1].first_key());
+    }
+    assert!(ssts.iter().any(|sst| !sst.range_tombstones().is_empty()));
+    assert_eq!(
+        ssts.last().unwrap().last_key().key_ref(),
+        key_of(3000).as_slice()
+    );
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..100).chain(900..1000))
+    );
+    assert_eq!(
+        snapshot.get(&key_of(500)).unwrap(),
+        Some(value_of(500).into())
+    );
+
+    // once the snapshot is gone, the covered keys and the tombstones are dropped
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    let state = storage.state.load_full();
+    for id in &state.levels[0].1 {
+        assert!(state.sstables[id].range_tombstones().is_empty());
+    }
+    assert_eq!(
+        scan_keys(&storage, Bound::Unbounded, Bound::Unbounded),
+        expected_keys((0..100).chain(900..1000))
+    );
+}
+
+#[test]
+fn test_range_tombstone_index_tracks_ssts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1 << 12;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..1000 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush(&storage);
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(100), &key_of(200)).unwrap();
+    flush(&storage);
+
+    let state = storage.state.load_full();
+    let with_range_tombstones = |state: &LsmStorageState| {
+        let mut ids = state
+            .sstables
+            .with_range_tombstones()
+            .map(|sst| sst.sst_id())
+            .collect::<Vec<_>>();
+        ids.sort();
+        let mut expected = state
+            .sstables
+            .iter()
+            .filter(|(_, sst)| !sst.range_tombstones().is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(ids, expected);
+        ids
+    };
+    assert_eq!(with_range_tombstones(&state).len(), 1);
+    let read_ts = storage.mvcc().latest_commit_ts();
+    let range = |lower: usize, upper: usize| {
+        state.range_tombstones(
+            Bound::Included(&key_of(lower)),
+            Bound::Excluded(&key_of(upper)),
+            read_ts,
+        )
+    };
+    assert!(!range(150, 160).is_empty());
+    assert!(range(300, 400).is_empty());
+
+    // compacting the tombstones away takes their SSTs out of the index
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert!(with_range_tombstones(&storage.state.load_full()).is_empty());
+}
@@ -0,0 +1,127 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn open(path: &std::path::Path, threshold: usize) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.delete_triggered_compaction_threshold = threshold;
+    Arc::new(LsmStorageInner::open(path, options).unwrap())
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+/// Loads 10000 keys into the bottom level, and then deletes 6000 of them in a single L0 SST,
+/// which is below the L0 compaction trigger.
+fn load_and_delete(storage: &Arc<LsmStorageInner>) {
+    for idx in 0..10000 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    flush(storage);
+    storage.put(b"zzz", b"value").unwrap();
+    flush(storage);
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    for idx in 2000..8000 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    flush(storage);
+    assert_eq!(storage.state.load().l0_sstables.len(), 1);
+}
+
+fn scan_all(storage: &Arc<LsmStorageInner>) -> usize {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut num_keys = 0;
+    while iter.is_valid() {
+        num_keys += 1;
+        iter.next().unwrap();
+    }
+    num_keys
+}
+
+#[test]
+fn test_delete_triggered_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), 1000);
+    load_and_delete(&storage);
+    assert!(storage.plan_compaction().is_none());
+
+    for _ in 0..3 {
+        assert_eq!(scan_all(&storage), 4001);
+    }
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_tombstone_reports, 3);
+    assert_eq!(stats.scan_skipped_tombstones, 3 * 6000);
+
+    // the overlapping reports are compacted once
+    let plan = storage.plan_compaction().unwrap();
+    assert!(plan.reason.contains("delete-triggered"), "{}", plan.reason);
+    let reports = storage.tombstone_reports.as_ref().unwrap();
+    let queued = reports.queued();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(&queued[0].first[..], key_of(2000));
+    assert_eq!(&queued[0].last[..], key_of(7999));
+
+    // L0 -> L1 -> L2, where the tombstones are dropped
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    assert!(reports.queued().is_empty());
+    {
+        let state = storage.state.load();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels[0].1.is_empty());
+    }
+    assert_eq!(scan_all(&storage), 4001);
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.scan_skipped_tombstones, 3 * 6000);
+    assert_eq!(stats.num_tombstone_reports, 3);
+}
+
+#[test]
+fn test_delete_triggered_compaction_disabled() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), 0);
+    load_and_delete(&storage);
+    for _ in 0..3 {
+        assert_eq!(scan_all(&storage), 4001);
+    }
+    assert!(storage.plan_compaction().is_none());
+    assert_eq!(storage.compaction_stats().num_tombstone_reports, 0);
+}
+
+#[test]
+fn test_short_tombstone_runs_not_reported() {
+    let dir = tempdir().unwrap();
+    let storage = open(dir.path(), 1000);
+    load_and_delete(&storage);
+    // every other key in the deleted range is live again
+    for idx in (2000..8000).step_by(2) {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    assert_eq!(scan_all(&storage), 7001);
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.scan_skipped_tombstones, 3000);
+    assert_eq!(stats.num_tombstone_reports, 0);
+    assert!(storage.plan_compaction().is_none());
+}
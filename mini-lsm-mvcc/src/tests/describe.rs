@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::describe::{
+    describe_key, LevelDescription, LsmStateDescription, MemTableDescription, SstDescription,
+};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_describe_key() {
+    assert_eq!(describe_key(b"key_001"), "key_001");
+    assert_eq!(describe_key(b"\x00a\"b\xff"), "\\x00a\\\"b\\xff");
+    assert_eq!(describe_key(&[b'z'; 32]), "z".repeat(32));
+    assert_eq!(describe_key(&[b'z'; 33]), format!("{}...", "z".repeat(32)));
+}
+
+#[test]
+fn test_describe() {
+    let dir = tempdir().unwrap();
+    let compaction_options = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+    });
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            dir.path(),
+            LsmStorageOptions::default_for_week2_test(compaction_options),
+        )
+        .unwrap(),
+    );
+    let long_key = [b'z'; 40];
+    // one SST in L1, two in L0, an immutable memtable and the memtable
+    storage.put(b"\x00first", b"value").unwrap();
+    flush(&storage);
+    storage.put(&long_key, b"value").unwrap();
+    flush(&storage);
+    storage.trigger_compaction().unwrap();
+    for i in 0..2 {
+        for j in 0..10 {
+            storage
+                .put(format!("key_{}{}", i, j).as_bytes(), b"value")
+                .unwrap();
+        }
+        storage.delete(format!("key_{}0", i).as_bytes()).unwrap();
+        flush(&storage);
+    }
+    storage.put(b"imm", b"value").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"memtable", b"value").unwrap();
+
+    let state = storage.state.load_full();
+    let sst = |id: usize, first_key: &str, last_key: &str, num_entries: u64| SstDescription {
+        id,
+        size: state.sstables[&id].table_size(),
+        first_key: Some(first_key.to_string()),
+        last_key: Some(last_key.to_string()),
+        num_entries,
+    };
+    let expected = LsmStateDescription {
+        memtable: MemTableDescription {
+            id: state.memtable.id(),
+            approximate_size: state.memtable.approximate_size(),
+        },
+        imm_memtables: vec![MemTableDescription {
+            id: state.imm_memtables[0].id(),
+            approximate_size: state.imm_memtables[0].approximate_size(),
+        }],
+        l0_sstables: vec![
+            sst(state.l0_sstables[0], "key_10", "key_19", 11),
+            sst(state.l0_sstables[1], "key_00", "key_09", 11),
+        ],
+        levels: vec![
+            LevelDescription {
+                level: 1,
+                sstables: vec![sst(
+                    state.levels[0].1[0],
+                    "\\x00first",
+                    &format!("{}...", "z".repeat(32)),
+                    2,
+                )],
+            },
+            LevelDescription {
+                level: 2,
+                sstables: Vec::new(),
+            },
+        ],
+        compaction_strategy: format!("{:?}", storage.dynamic_options().compaction_options),
+        pending_task: Some(format!("{:?}", storage.plan_compaction().unwrap().task)),
+    };
+    let description = storage.describe(true);
+    assert_eq!(description, expected);
+    assert!(description.compaction_strategy.starts_with("Simple("));
+    assert!(description
+        .pending_task
+        .as_ref()
+        .unwrap()
+        .starts_with("Simple("));
+
+    // without keys
+    let description = storage.describe(false);
+    assert!(description
+        .l0_sstables
+        .iter()
+        .chain(&description.levels[0].sstables)
+        .all(|sst| sst.first_key.is_none() && sst.last_key.is_none()));
+    assert_eq!(description.l0_sstables[0].num_entries, 11);
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&storage.describe(true)).unwrap();
+        assert!(json.contains("\"first_key\":\"key_10\""), "{}", json);
+        let decoded: LsmStateDescription = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, expected);
+    }
+}
@@ -0,0 +1,108 @@
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::keyspace::KEYSPACES_DIR;
+use crate::lsm_storage::{ClosedError, LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn populate(storage: &MiniLsm) {
+    for idx in 0..100 {
+        let key = format!("key_{:05}", idx);
+        storage.put(key.as_bytes(), b"value").unwrap();
+        if idx % 30 == 0 {
+            storage.force_flush().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_destroy_populated_storage() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let storage = MiniLsm::open(&path, options()).unwrap();
+    populate(&storage);
+    assert!(path.join("LOCK").exists());
+
+    // refused while open
+    assert!(MiniLsm::destroy(&path).is_err());
+    assert!(path.join("MANIFEST").exists());
+
+    storage.close().unwrap();
+    assert!(!path.join("LOCK").exists());
+    MiniLsm::destroy(&path).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_close_and_destroy() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let storage = MiniLsm::open(&path, options()).unwrap();
+    populate(&storage);
+    storage.close_and_destroy().unwrap();
+    assert!(!path.exists());
+    assert!(storage
+        .put(b"key", b"value")
+        .unwrap_err()
+        .is::<ClosedError>());
+}
+
+#[test]
+fn test_destroy_storage_with_keyspaces() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("db");
+    let keyspaces = || {
+        vec![
+            ("users".to_string(), options()),
+            ("orders".to_string(), options()),
+        ]
+    };
+    let storage = MiniLsm::open_with_keyspaces(&path, options(), keyspaces()).unwrap();
+    populate(&storage);
+    populate(storage.keyspace("users").unwrap().storage());
+    populate(storage.keyspace("orders").unwrap().storage());
+    assert!(MiniLsm::destroy(&path).is_err());
+    storage.close().unwrap();
+    drop(storage);
+
+    // a keyspace holding a file of someone else leaves the whole storage alone
+    let users = path.join(KEYSPACES_DIR).join("users");
+    std::fs::write(users.join("notes.txt"), b"keep me").unwrap();
+    assert!(MiniLsm::destroy(&path).is_err());
+    assert!(path.join("MANIFEST").exists());
+    std::fs::remove_file(users.join("notes.txt")).unwrap();
+    let storage = MiniLsm::open_with_keyspaces(&path, options(), keyspaces()).unwrap();
+    let users = storage.keyspace("users").unwrap();
+    assert!(users.get(b"key_00000").unwrap().is_some());
+    drop(users);
+
+    storage.close_and_destroy().unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_destroy_refuses_other_directories() {
+    let dir = tempdir().unwrap();
+    assert!(MiniLsm::destroy(dir.path()).is_err());
+    std::fs::write(dir.path().join("notes.txt"), b"keep me").unwrap();
+    assert!(MiniLsm::destroy(dir.path()).is_err());
+    assert!(dir.path().join("notes.txt").exists());
+    assert!(MiniLsm::destroy(dir.path().join("missing")).is_err());
+
+    // a storage holding a file of someone else is left alone as well
+    let path = dir.path().join("db");
+    let storage = MiniLsm::open(&path, options()).unwrap();
+    populate(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    std::fs::write(path.join("notes.txt"), b"keep me").unwrap();
+    assert!(MiniLsm::destroy(&path).is_err());
+    assert!(path.join("MANIFEST").exists());
+    let storage = MiniLsm::open(&path, options()).unwrap();
+    assert!(storage.get(b"key_00000").unwrap().is_some());
+}
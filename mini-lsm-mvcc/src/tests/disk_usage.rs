@@ -0,0 +1,145 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+fn value_of(i: usize) -> Vec<u8> {
+    format!("value_{:0200}", i).into_bytes()
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+fn size_of_files(dir: &Path, extension: &str) -> u64 {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .map(|path| std::fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+#[test]
+fn test_approximate_size_of_range() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 1024;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    // every key written once, in a level, L0, an immutable memtable and the memtable
+    for i in 0..4000 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+        match i {
+            999 => {
+                flush(&storage);
+                storage.force_full_compaction().unwrap();
+            }
+            1999 => flush(&storage),
+            2999 => storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap(),
+            _ => {}
+        }
+    }
+    for (lower, upper) in [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(100), Bound::Excluded(600)),
+        (Bound::Included(500), Bound::Included(3500)),
+        (Bound::Excluded(1800), Bound::Excluded(2300)),
+        (Bound::Included(3200), Bound::Unbounded),
+        (Bound::Unbounded, Bound::Included(1500)),
+    ] {
+        let (lower, upper) = (lower.map(key_of), upper.map(key_of));
+        let (lower, upper) = (
+            lower.as_ref().map(|key| key.as_slice()),
+            upper.as_ref().map(|key| key.as_slice()),
+        );
+        let mut iter = storage.scan(lower, upper).unwrap();
+        let mut exact = 0;
+        while iter.is_valid() {
+            exact += (iter.key().len() + iter.value().len()) as u64;
+            iter.next().unwrap();
+        }
+        let estimate = storage.approximate_size_of_range(lower, upper);
+        // the keys are encoded with a timestamp and lengths, and the blocks at the bounds are
+        // interpolated
+        assert!(
+            estimate.abs_diff(exact) <= exact / 10 + 4 * 1024,
+            "{:?}..{:?}: estimated {} bytes, scanned {} bytes",
+            lower,
+            upper,
+            estimate,
+            exact
+        );
+    }
+    let (k1, k2) = (key_of(200), key_of(100));
+    assert_eq!(
+        storage.approximate_size_of_range(Bound::Included(&k1), Bound::Included(&k2)),
+        0
+    );
+    assert_eq!(
+        storage.approximate_size_of_range(Bound::Included(b"zzz"), Bound::Unbounded),
+        0
+    );
+}
+
+#[test]
+fn test_disk_usage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    // three versions of each key in the same SST
+    for round in 0..3 {
+        for i in 0..500 {
+            storage.put(&key_of(i), &value_of(round)).unwrap();
+        }
+    }
+    flush(&storage);
+    for i in 0..500 {
+        storage.put(&key_of(i + 500), &value_of(i)).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"key", b"value").unwrap();
+    storage.sync().unwrap();
+
+    let usage = storage.disk_usage().unwrap();
+    assert_eq!(usage.sst_bytes, size_of_files(dir.path(), "sst"));
+    assert_eq!(usage.by_level.len(), 2);
+    assert_eq!(usage.by_level, vec![usage.sst_bytes, 0]);
+    assert!(usage.wal_bytes > 0);
+    assert_eq!(usage.wal_bytes, size_of_files(dir.path(), "wal"));
+    // two thirds of the entries are shadowed
+    assert!(
+        (2.5..3.5).contains(&usage.space_amplification),
+        "{}",
+        usage.space_amplification
+    );
+
+    flush(&storage);
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    let usage = storage.disk_usage().unwrap();
+    assert_eq!(usage.sst_bytes, size_of_files(dir.path(), "sst"));
+    assert_eq!(usage.by_level, vec![0, usage.sst_bytes]);
+    assert_eq!(usage.wal_bytes, size_of_files(dir.path(), "wal"));
+    assert!(
+        (1.0..1.05).contains(&usage.space_amplification),
+        "{}",
+        usage.space_amplification
+    );
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:03}", i).into_bytes()
+}
+
+#[test]
+fn test_builder_rejects_duplicate_keys() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    builder.enable_key_order_check();
+    for i in 0..100 {
+        builder.add(KeySlice::from_slice(&key_of(i), 2), b"value");
+        builder.add(KeySlice::from_slice(&key_of(i), 1), b"value");
+    }
+    builder.add(KeySlice::from_slice(&key_of(99), 1), b"value");
+    let err = builder
+        .build_and_reset(0, None, dir.path().join("1.sst"))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("duplicate key"), "{}", err);
+    assert!(!dir.path().join("1.sst").exists());
+
+    // a key going backwards is rejected as well
+    builder.add(KeySlice::from_slice(&key_of(1), 1), b"value");
+    builder.add(KeySlice::from_slice(&key_of(1), 2), b"value");
+    let err = builder
+        .build_and_reset(0, None, dir.path().join("2.sst"))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("after key"), "{}", err);
+
+    // strictly increasing keys still build once the builder is reset
+    for i in 0..100 {
+        builder.add(KeySlice::from_slice(&key_of(i), 2), b"value");
+        builder.add(KeySlice::from_slice(&key_of(i), 1), b"");
+    }
+    let table = Arc::new(
+        builder
+            .build_and_reset(0, None, dir.path().join("3.sst"))
+            .unwrap(),
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(table).unwrap();
+    let mut num_entries = 0;
+    while iter.is_valid() {
+        num_entries += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_entries, 200);
+
+    // without the check, duplicates are written as before
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(KeySlice::from_slice(&key_of(1), 1), b"value");
+    builder.add(KeySlice::from_slice(&key_of(1), 1), b"value");
+    builder.build_for_test(dir.path().join("4.sst")).unwrap();
+}
@@ -0,0 +1,58 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::{KeyBytes, KeySlice};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTable, SsTableIterator};
+
+fn meta_only_table(id: usize) -> SsTable {
+    SsTable::create_meta_only(
+        id,
+        0,
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"a")),
+        KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"z")),
+    )
+}
+
+#[test]
+fn test_empty_sst_iterator() {
+    let table = Arc::new(meta_only_table(1));
+    let key = KeySlice::for_testing_from_slice_no_ts(b"m");
+    assert_eq!(table.find_block_idx(key), None);
+    assert!(table.read_block(0).is_err());
+
+    let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), key).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_first().unwrap();
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_to_first(table).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_empty_sst_get() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    storage.put(b"b", b"1").unwrap();
+    {
+        let _state_lock = storage.state_lock.lock();
+        let mut snapshot = storage.state.load().as_ref().clone();
+        snapshot.l0_sstables.insert(0, 100);
+        snapshot
+            .sstables
+            .insert(100, Arc::new(meta_only_table(100)));
+        storage.state.store(Arc::new(snapshot));
+    }
+    assert_eq!(storage.get(b"b").unwrap().unwrap().as_ref(), b"1");
+    assert!(storage.get(b"m").unwrap().is_none());
+    let iter = storage
+        .scan(Bound::Included(b"c"), Bound::Unbounded)
+        .unwrap();
+    assert!(!iter.is_valid());
+}
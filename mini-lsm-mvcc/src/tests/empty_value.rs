@@ -0,0 +1,145 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::block::{
+    Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V2, BLOCK_FORMAT_V6, ENTRY_FLAG_TOMBSTONE,
+};
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::SsTableIterator;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+/// Checks that `empty` reads as an empty value and `deleted` as absent, both with `get` and with
+/// a scan.
+fn check_storage(storage: &MiniLsm) {
+    assert_eq!(storage.get(b"empty").unwrap(), Some(Bytes::new()));
+    assert_eq!(storage.get(b"deleted").unwrap(), None);
+    let expected = vec![
+        (Bytes::from("a"), Bytes::from("1")),
+        (Bytes::from("empty"), Bytes::new()),
+        (Bytes::from("z"), Bytes::from("2")),
+    ];
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected.clone(),
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected.into_iter().rev().collect(),
+    );
+}
+
+#[test]
+fn test_block_tombstone_flag() {
+    let key = |key: &'static [u8]| KeySlice::for_testing_from_slice_no_ts(key);
+    let mut builder = BlockBuilder::new_with_format_version(4096, BLOCK_FORMAT_V6);
+    assert!(builder.add_with_flags(key(b"a"), b"", 0));
+    assert!(builder.add_with_flags(key(b"b"), b"", ENTRY_FLAG_TOMBSTONE));
+    assert!(builder.add_with_flags(key(b"c"), b"value", 0));
+    let block = Arc::new(Block::decode_with_format_version(
+        &builder.build().encode(),
+        BLOCK_FORMAT_V6,
+    ));
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    for (value, is_tombstone) in [
+        (b"".as_slice(), false),
+        (b"".as_slice(), true),
+        (b"value".as_slice(), false),
+    ] {
+        assert_eq!(iter.value(), value);
+        assert_eq!(iter.is_tombstone(), is_tombstone);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+
+    // formats without the flag take an empty value for a tombstone
+    let mut builder = BlockBuilder::new_with_format_version(4096, BLOCK_FORMAT_V2);
+    assert!(builder.add(key(b"a"), b""));
+    assert!(builder.add(key(b"b"), b"value"));
+    let block = Arc::new(Block::decode_with_format_version(
+        &builder.build().encode(),
+        BLOCK_FORMAT_V2,
+    ));
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    assert!(iter.is_tombstone());
+    assert_eq!(iter.flags(), ENTRY_FLAG_TOMBSTONE);
+    iter.next();
+    assert!(!iter.is_tombstone());
+}
+
+#[test]
+fn test_empty_value_and_tombstone() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"deleted", b"old").unwrap();
+    storage.put(b"empty", b"old").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"empty", b"").unwrap();
+    storage.delete(b"deleted").unwrap();
+    storage.put(b"z", b"2").unwrap();
+    check_storage(&storage);
+    let txn = storage.new_txn().unwrap();
+    assert_eq!(txn.get(b"empty").unwrap(), Some(Bytes::new()));
+    check_lsm_iter_result_by_key(
+        &mut txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("empty"), Bytes::new()),
+            (Bytes::from("z"), Bytes::from("2")),
+        ],
+    );
+    drop(txn);
+
+    // recovered from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check_storage(&storage);
+
+    // the SST tells the empty value apart from the tombstone
+    storage.force_flush().unwrap();
+    check_storage(&storage);
+    let sst = {
+        let state = storage.inner.state.load();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().key_ref().to_vec(), iter.is_tombstone()));
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        entries,
+        vec![
+            (b"deleted".to_vec(), true),
+            (b"empty".to_vec(), false),
+            (b"z".to_vec(), false),
+        ]
+    );
+
+    // compacted to the bottom level, the tombstone is dropped and the empty value kept
+    storage.force_full_compaction().unwrap();
+    check_storage(&storage);
+    let state = storage.inner.state.load_full();
+    let num_tombstones = state
+        .sstables
+        .values()
+        .map(|sst| sst.properties().num_tombstones)
+        .sum::<u64>();
+    assert_eq!(num_tombstones, 0);
+}
@@ -0,0 +1,135 @@
+use std::sync::{Arc, OnceLock, Weak};
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::compact::{BackgroundJobStats, CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::event_listener::{CompactionJobInfo, EventListener, FlushJobInfo};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    FlushBegin(usize),
+    FlushComplete(usize, u64),
+    CompactionBegin(u64, Vec<usize>),
+    CompactionProgress(u64, u8),
+    CompactionComplete(u64, Vec<usize>, u64),
+}
+
+#[derive(Default)]
+struct RecordingListener {
+    storage: OnceLock<Weak<LsmStorageInner>>,
+    events: Mutex<Vec<Event>>,
+    /// Whether `state_lock` was held by the engine during any event.
+    called_with_state_lock: Mutex<bool>,
+}
+
+impl RecordingListener {
+    fn record(&self, event: Event) {
+        if let Some(storage) = self.storage.get().and_then(Weak::upgrade) {
+            if storage.state_lock.try_lock().is_none() {
+                *self.called_with_state_lock.lock() = true;
+            }
+        }
+        self.events.lock().push(event);
+    }
+}
+
+impl EventListener for RecordingListener {
+    fn on_flush_begin(&self, info: &FlushJobInfo) {
+        self.record(Event::FlushBegin(info.sst_id));
+    }
+
+    fn on_flush_complete(&self, info: &FlushJobInfo, stats: &BackgroundJobStats) {
+        self.record(Event::FlushComplete(info.sst_id, stats.entries_written));
+    }
+
+    fn on_compaction_begin(&self, info: &CompactionJobInfo) {
+        self.record(Event::CompactionBegin(
+            info.job_id,
+            info.input_sst_ids.clone(),
+        ));
+    }
+
+    fn on_compaction_progress(&self, job_id: u64, percent: u8) {
+        self.record(Event::CompactionProgress(job_id, percent));
+    }
+
+    fn on_compaction_complete(
+        &self,
+        info: &CompactionJobInfo,
+        output_sst_ids: &[usize],
+        stats: &BackgroundJobStats,
+    ) {
+        self.record(Event::CompactionComplete(
+            info.job_id,
+            output_sst_ids.to_vec(),
+            stats.entries_written,
+        ));
+    }
+}
+
+#[test]
+fn test_flush_and_compaction_events() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(RecordingListener::default());
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.event_listeners = vec![listener.clone()];
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    listener.storage.set(Arc::downgrade(&storage)).unwrap();
+
+    let mut flushed = Vec::new();
+    for round in 0..2 {
+        for i in 0..3000 {
+            storage
+                .put(
+                    format!("key_{:05}", i).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        flushed.push(storage.state.load().imm_memtables[0].id());
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.trigger_compaction().unwrap();
+    let output = storage.state.load().levels[0].1.clone();
+    assert!(!output.is_empty());
+
+    let events = std::mem::take(&mut *listener.events.lock());
+    assert_eq!(
+        events[..5],
+        [
+            Event::FlushBegin(flushed[0]),
+            Event::FlushComplete(flushed[0], 3000),
+            Event::FlushBegin(flushed[1]),
+            Event::FlushComplete(flushed[1], 3000),
+            Event::CompactionBegin(0, vec![flushed[1], flushed[0]]),
+        ]
+    );
+    // the older versions are dropped, so only half of the input is written
+    assert_eq!(
+        events.last().unwrap(),
+        &Event::CompactionComplete(0, output, 3000)
+    );
+    let progress = events[5..events.len() - 1]
+        .iter()
+        .map(|event| match event {
+            Event::CompactionProgress(0, percent) => *percent,
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect::<Vec<_>>();
+    // 6000 input entries are reported in steps of 1024
+    assert!(progress.len() >= 5, "{:?}", progress);
+    assert!(progress.windows(2).all(|x| x[0] < x[1]), "{:?}", progress);
+    assert_eq!(*progress.last().unwrap(), 100);
+    assert!(!*listener.called_with_state_lock.lock());
+}
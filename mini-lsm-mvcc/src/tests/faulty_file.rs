@@ -0,0 +1,72 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::faulty_file::FaultyFileObject;
+use crate::table::{SsTable, SsTableBuilder};
+
+/// Builds an SST of several blocks, returning it with the path of its file.
+fn build_sst(dir: &std::path::Path) -> (SsTable, std::path::PathBuf) {
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(format!("key_{:03}", i).as_bytes(), 1),
+            format!("value_{:03}", i).as_bytes(),
+        );
+    }
+    let path = dir.join("1.sst");
+    (builder.build_for_test(&path).unwrap(), path)
+}
+
+#[test]
+fn test_flipped_meta_byte() {
+    let dir = tempdir().unwrap();
+    let (sst, path) = build_sst(dir.path());
+    let meta_offset = sst.block_meta_offset as u64;
+    let file = FaultyFileObject::from_file(&path)
+        .unwrap()
+        .flip_bytes(meta_offset + 10..meta_offset + 11);
+    let err = SsTable::open_for_test(file.into()).err().unwrap();
+    assert_eq!(err.to_string(), "meta checksum mismatched");
+}
+
+#[test]
+fn test_flipped_block_byte() {
+    let dir = tempdir().unwrap();
+    let (sst, path) = build_sst(dir.path());
+    assert!(sst.num_of_blocks() > 2);
+    let second_block = sst.block_meta[1].offset as u64;
+    let file = FaultyFileObject::from_file(&path)
+        .unwrap()
+        .flip_bytes(second_block..second_block + 1);
+    let sst = SsTable::open_for_test(file.into()).unwrap();
+    sst.read_block(0).unwrap();
+    let err = sst.read_block(1).err().unwrap();
+    assert_eq!(err.to_string(), "block checksum mismatched");
+    sst.read_block(2).unwrap();
+}
+
+#[test]
+fn test_failed_read() {
+    let dir = tempdir().unwrap();
+    let (_, path) = build_sst(dir.path());
+    // opening the SST takes four reads: the bloom offset, the bloom filter, the meta offset and
+    // the meta
+    let file = FaultyFileObject::from_file(&path).unwrap().fail_on_read(5);
+    let sst = SsTable::open_for_test(file.into()).unwrap();
+    let err = sst.read_block(0).err().unwrap();
+    assert_eq!(err.to_string(), "injected failure of read 5");
+    sst.read_block(0).unwrap();
+
+    let file = FaultyFileObject::from_file(&path).unwrap().fail_on_read(2);
+    assert!(SsTable::open_for_test(file.into()).is_err());
+}
+
+#[test]
+fn test_short_read() {
+    let dir = tempdir().unwrap();
+    let (sst, path) = build_sst(dir.path());
+    let file = FaultyFileObject::from_file(&path).unwrap().short_reads(4);
+    let file: crate::table::FileObject = file.into();
+    assert_eq!(file.read(0, 16).unwrap().len(), 4);
+    assert_eq!(file.read(sst.block_meta_offset as u64, 2).unwrap().len(), 2);
+}
@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn seed_key(idx: usize) -> Vec<u8> {
+    format!("seed_{:06}", idx).into_bytes()
+}
+
+fn write_key(idx: usize) -> Vec<u8> {
+    format!("write_{:06}", idx).into_bytes()
+}
+
+#[test]
+fn test_full_compaction_keeps_concurrent_writes() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for chunk in 0..8 {
+        for idx in chunk * 5000..(chunk + 1) * 5000 {
+            storage.put(&seed_key(idx), b"seed_value").unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+
+    let compacting = Arc::new(AtomicBool::new(true));
+    let written_during_compaction = Arc::new(AtomicUsize::new(0));
+    let writer = {
+        let storage = storage.clone();
+        let compacting = compacting.clone();
+        let written_during_compaction = written_during_compaction.clone();
+        std::thread::spawn(move || {
+            let mut idx = 0;
+            // keep writing and flushing until the compaction is done, and a bit longer
+            let mut remaining_after = 100;
+            while remaining_after > 0 {
+                let during = compacting.load(Ordering::SeqCst);
+                storage
+                    .put(&write_key(idx), format!("value_{}", idx).as_bytes())
+                    .unwrap();
+                if during {
+                    written_during_compaction.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    remaining_after -= 1;
+                }
+                // overwrite a seeded key, which must win over the compacted version
+                storage.put(&seed_key(idx), b"new_value").unwrap();
+                idx += 1;
+                if idx % 500 == 0 {
+                    storage.force_flush().unwrap();
+                }
+            }
+            idx
+        })
+    };
+    storage.force_full_compaction().unwrap();
+    compacting.store(false, Ordering::SeqCst);
+    let num_written = writer.join().unwrap();
+    assert!(written_during_compaction.load(Ordering::SeqCst) > 0);
+
+    let check = |storage: &MiniLsm| {
+        for idx in 0..num_written {
+            assert_eq!(
+                storage.get(&write_key(idx)).unwrap().unwrap().as_ref(),
+                format!("value_{}", idx).as_bytes()
+            );
+            assert_eq!(
+                storage.get(&seed_key(idx)).unwrap().unwrap().as_ref(),
+                b"new_value"
+            );
+        }
+        for idx in num_written..40000 {
+            assert_eq!(
+                storage.get(&seed_key(idx)).unwrap().unwrap().as_ref(),
+                b"seed_value"
+            );
+        }
+    };
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
@@ -0,0 +1,169 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::BLOCK_FORMAT_VERSION;
+use crate::compact::{
+    CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
+    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState, SstMap};
+use crate::mem_table::MemTable;
+use crate::table::{ChecksumType, SsTable, SsTableBuilder, TableProperties};
+
+/// Builds an SST with `num_keys` keys under `prefix`, where every key is a tombstone if
+/// `tombstones` is set.
+fn build_sst(dir: &Path, id: usize, prefix: &str, num_keys: usize, tombstones: bool) -> SsTable {
+    let mut builder = SsTableBuilder::new(4096);
+    for i in 0..num_keys {
+        let key = format!("{}_{:05}", prefix, i);
+        let value = if tombstones {
+            Vec::new()
+        } else {
+            format!("value_{:0100}", i).into_bytes()
+        };
+        builder.add(KeySlice::from_slice(key.as_bytes(), 1), &value);
+    }
+    builder
+        .build(id, None, dir.join(format!("{}.sst", id)))
+        .unwrap()
+}
+
+fn state_with(
+    l0_sstables: Vec<usize>,
+    levels: Vec<(usize, Vec<usize>)>,
+    ssts: Vec<SsTable>,
+) -> LsmStorageState {
+    let sstables = ssts
+        .into_iter()
+        .map(|sst| (sst.sst_id(), Arc::new(sst)))
+        .collect::<SstMap>();
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables,
+        levels,
+        sstables,
+    }
+}
+
+#[test]
+fn test_table_properties() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(64);
+    for i in 0..100 {
+        let key = format!("key_{:03}", i);
+        // three versions per key, the latest one is a tombstone for every other key
+        for ts in (1..=3).rev() {
+            let value = if ts == 3 && i % 2 == 0 {
+                Vec::new()
+            } else {
+                format!("value_{}", ts).into_bytes()
+            };
+            builder.add(KeySlice::from_slice(key.as_bytes(), ts), &value);
+        }
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let expected = TableProperties {
+        format_version: BLOCK_FORMAT_VERSION,
+        checksum_type: ChecksumType::Crc32,
+        num_entries: 300,
+        num_tombstones: 50,
+        num_shadowed_entries: 200,
+        creation_time: 0,
+        num_distinct_keys: 100,
+        num_bloom_bits: 1000,
+        value_sizes: *sst.value_size_histogram(),
+    };
+    assert_eq!(sst.properties(), &expected);
+    assert!((sst.properties().garbage_ratio() - 250.0 / 300.0).abs() < 1e-9);
+    assert!(sst.compensated_size() > sst.table_size());
+
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(sst.properties(), &expected);
+}
+
+#[test]
+fn test_leveled_prefers_tombstone_heavy_sst() {
+    let dir = tempdir().unwrap();
+    let clean = build_sst(dir.path(), 1, "a", 1000, false);
+    let tombstones = build_sst(dir.path(), 2, "b", 100, true);
+    assert!(clean.table_size() > tombstones.table_size());
+    let state = state_with(
+        Vec::new(),
+        vec![(1, vec![1, 2]), (2, Vec::new())],
+        vec![clean, tombstones],
+    );
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    let (task, reason) = controller
+        .generate_compaction_task_with_reason(&state)
+        .unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert!(reason.contains("garbage ratio 1.000"), "{}", reason);
+}
+
+#[test]
+fn test_simple_leveled_prefers_tombstone_heavy_level() {
+    let dir = tempdir().unwrap();
+    let state = state_with(
+        Vec::new(),
+        vec![(1, vec![2, 1]), (2, vec![3]), (3, Vec::new())],
+        vec![
+            build_sst(dir.path(), 1, "a", 1000, false),
+            build_sst(dir.path(), 2, "b", 1000, false),
+            build_sst(dir.path(), 3, "c", 100, true),
+        ],
+    );
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    // both L1 -> L2 and L2 -> L3 violate the size ratio, L2 is all tombstones
+    let (task, reason) = controller
+        .generate_compaction_task_with_reason(&state)
+        .unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![3]);
+    assert!(task.is_lower_level_bottom_level);
+    assert!(reason.contains("garbage ratio 1.000"), "{}", reason);
+}
+
+#[test]
+fn test_compaction_reason_in_stats() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            dir.path(),
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+                SimpleLeveledCompactionOptions {
+                    size_ratio_percent: 200,
+                    level0_file_num_compaction_trigger: 2,
+                    max_levels: 2,
+                },
+            )),
+        )
+        .unwrap(),
+    );
+    assert!(storage.compaction_stats().last_task_reason.is_none());
+    for i in 0..2 {
+        storage
+            .put(format!("key_{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.trigger_compaction().unwrap();
+    let reason = storage.compaction_stats().last_task_reason.unwrap();
+    assert!(reason.contains("between level 0 and 1"), "{}", reason);
+}
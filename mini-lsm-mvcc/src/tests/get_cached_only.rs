@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{CachedGet, LsmStorageInner, LsmStorageOptions};
+
+#[test]
+fn test_get_cached_only() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 128;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..100 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    storage.delete(b"key_060").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.put(b"key_010", b"new").unwrap();
+    storage.block_cache.invalidate_all();
+
+    // cold blocks are not read
+    assert_eq!(
+        storage.get_cached_only(b"key_050").unwrap(),
+        CachedGet::WouldBlock
+    );
+    assert_eq!(
+        storage.get_cached_only(b"key_060").unwrap(),
+        CachedGet::WouldBlock
+    );
+    // the memtables and the key ranges of the SSTs need no block
+    assert_eq!(
+        storage.get_cached_only(b"key_010").unwrap(),
+        CachedGet::Value(Some(Bytes::from("new")))
+    );
+    assert_eq!(
+        storage.get_cached_only(b"zzz").unwrap(),
+        CachedGet::Value(None)
+    );
+
+    // a point lookup warms the blocks it reads
+    assert_eq!(storage.get(b"key_050").unwrap(), Some(Bytes::from("value")));
+    assert_eq!(storage.get(b"key_060").unwrap(), None);
+    assert_eq!(
+        storage.get_cached_only(b"key_050").unwrap(),
+        CachedGet::Value(Some(Bytes::from("value")))
+    );
+    assert_eq!(
+        storage.get_cached_only(b"key_060").unwrap(),
+        CachedGet::Value(None)
+    );
+    assert_eq!(
+        storage.get_cached_only(b"key_090").unwrap(),
+        CachedGet::WouldBlock
+    );
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::property::{self, PROPERTIES};
+
+fn get_u64(storage: &LsmStorageInner, name: &str) -> u64 {
+    storage
+        .get_property(name)
+        .unwrap_or_else(|| panic!("missing property {}", name))
+        .parse()
+        .unwrap_or_else(|e| panic!("property {} is not a number: {}", name, e))
+}
+
+#[test]
+fn test_get_property() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for name in PROPERTIES {
+        assert_eq!(get_u64(&storage, name), 0, "{}", name);
+    }
+    for level in 0..=3 {
+        assert_eq!(get_u64(&storage, &property::level_size_bytes(level)), 0);
+    }
+
+    for round in 0..3 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key_{:03}", i).as_bytes(),
+                    format!("value_{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.trigger_compaction().unwrap();
+    storage.put(b"key_000", b"value_3").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    for _ in 0..2 {
+        storage.put(b"key_001", b"value_3").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+    }
+    assert!(storage.get(b"key_050").unwrap().is_some());
+
+    let snapshot = storage.state.load_full();
+    for name in PROPERTIES {
+        get_u64(&storage, name);
+    }
+    assert_eq!(
+        get_u64(&storage, property::NUM_IMMUTABLE_MEMTABLES),
+        snapshot.imm_memtables.len() as u64
+    );
+    assert!(get_u64(&storage, property::MEMTABLE_SIZE_BYTES) > 0);
+    assert_eq!(
+        get_u64(&storage, property::NUM_SST_FILES),
+        snapshot.sstables.len() as u64
+    );
+    assert_eq!(
+        get_u64(&storage, property::BLOCK_CACHE_USAGE),
+        storage.block_cache.weighted_size()
+    );
+    // the overwritten versions live in different tables, so only the memtable versions are extra
+    assert!(get_u64(&storage, property::ESTIMATED_KEYS) >= 100);
+    assert_eq!(get_u64(&storage, property::BACKGROUND_ERRORS), 0);
+    assert_eq!(get_u64(&storage, property::IS_WRITE_STALLED), 1);
+
+    let usage = storage.disk_usage().unwrap();
+    assert!(usage.sst_bytes > 0);
+    for (level, bytes) in usage.by_level.iter().enumerate() {
+        assert_eq!(
+            get_u64(&storage, &property::level_size_bytes(level)),
+            *bytes,
+            "level {}",
+            level
+        );
+    }
+    assert_eq!(
+        storage.get_property(&property::level_size_bytes(usage.by_level.len())),
+        None
+    );
+
+    while storage.state.load().imm_memtables.len() >= storage.options.num_memtable_limit {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    assert_eq!(get_u64(&storage, property::IS_WRITE_STALLED), 0);
+
+    for name in [
+        "",
+        "lsm",
+        "lsm.unknown",
+        "lsm.level",
+        "lsm.level-size-bytes",
+        "lsm.levelx-size-bytes",
+        "lsm.level0",
+        "lsm.num-sst-files.",
+    ] {
+        assert_eq!(storage.get_property(name), None, "{:?}", name);
+    }
+}
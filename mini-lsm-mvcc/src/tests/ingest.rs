@@ -0,0 +1,186 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::SsTableBuilder;
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 1,
+            max_levels: 3,
+        },
+    ))
+}
+
+/// Writes an external SST with `prefix_{i}` keys for `i` in `range`, valued `value_{i}`.
+fn external_sst(path: &Path, prefix: &str, range: std::ops::Range<usize>, value: &str) {
+    let mut builder = SsTableBuilder::new(128);
+    for i in range {
+        builder.add(
+            KeySlice::from_slice(format!("{}_{:03}", prefix, i).as_bytes(), 1),
+            format!("{}_{}", value, i).as_bytes(),
+        );
+    }
+    builder.build(0, None, path).unwrap();
+}
+
+fn put_and_flush(storage: &Arc<LsmStorageInner>, prefix: &str, range: std::ops::Range<usize>) {
+    for i in range {
+        storage
+            .put(
+                format!("{}_{:03}", prefix, i).as_bytes(),
+                format!("value_{}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+fn get(storage: &Arc<LsmStorageInner>, prefix: &str, i: usize) -> Option<Bytes> {
+    storage
+        .get(format!("{}_{:03}", prefix, i).as_bytes())
+        .unwrap()
+}
+
+#[test]
+fn test_ingest_into_deepest_non_overlapping_level() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    // move b_000..b_199 down to the bottom level
+    put_and_flush(&storage, "b", 0..200);
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    assert!(storage.state.load().levels[..2]
+        .iter()
+        .all(|(_, ssts)| ssts.is_empty()));
+
+    // files overlapping nothing go to the bottom level, ordered by key
+    for (name, prefix) in [("d.sst", "d"), ("a.sst", "a")] {
+        external_sst(&external.path().join(name), prefix, 0..50, "external");
+        assert_eq!(storage.ingest_sst(external.path().join(name)).unwrap(), 3);
+    }
+    {
+        let state = storage.state.load();
+        let bottom = &state.levels[2].1;
+        assert!(bottom
+            .windows(2)
+            .all(|x| state.sstables[&x[0]].last_key() < state.sstables[&x[1]].first_key()));
+    }
+
+    // overlapping the bottom level, then overlapping the previous ingestion
+    let snapshot = storage.new_txn().unwrap();
+    external_sst(&external.path().join("b1.sst"), "b", 100..150, "first");
+    assert_eq!(
+        storage.ingest_sst(external.path().join("b1.sst")).unwrap(),
+        2
+    );
+    external_sst(&external.path().join("b2.sst"), "b", 140..160, "second");
+    assert_eq!(
+        storage.ingest_sst(external.path().join("b2.sst")).unwrap(),
+        1
+    );
+
+    // overlapping L0
+    put_and_flush(&storage, "c", 0..10);
+    external_sst(&external.path().join("c.sst"), "c", 5..20, "external");
+    assert_eq!(
+        storage.ingest_sst(external.path().join("c.sst")).unwrap(),
+        0
+    );
+
+    let check = |storage: &Arc<LsmStorageInner>| {
+        for i in 0..50 {
+            for prefix in ["a", "d"] {
+                assert_eq!(
+                    get(storage, prefix, i),
+                    Some(Bytes::from(format!("external_{}", i)))
+                );
+            }
+        }
+        for i in 0..200 {
+            let expected = match i {
+                140..160 => format!("second_{}", i),
+                100..140 => format!("first_{}", i),
+                _ => format!("value_{}", i),
+            };
+            assert_eq!(get(storage, "b", i), Some(Bytes::from(expected)));
+        }
+        for i in 0..20 {
+            let expected = if i < 5 {
+                format!("value_{}", i)
+            } else {
+                format!("external_{}", i)
+            };
+            assert_eq!(get(storage, "c", i), Some(Bytes::from(expected)));
+        }
+    };
+    check(&storage);
+    // the snapshot was taken before the ingestions
+    assert_eq!(
+        snapshot.get(b"b_120").unwrap(),
+        Some(Bytes::from("value_120"))
+    );
+    drop(snapshot);
+
+    let levels = storage.state.load().levels.clone();
+    let l0_sstables = storage.state.load().l0_sstables.clone();
+    drop(storage);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert_eq!(storage.state.load().levels, levels);
+    assert_eq!(storage.state.load().l0_sstables, l0_sstables);
+    check(&storage);
+    // new SSTs do not reuse the ids of the ingested ones
+    put_and_flush(&storage, "e", 0..10);
+    assert_eq!(
+        storage.state.load().sstables.len(),
+        levels.iter().map(|x| x.1.len()).sum::<usize>() + l0_sstables.len() + 1
+    );
+}
+
+#[test]
+fn test_ingest_skips_levels_being_compacted() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    put_and_flush(&storage, "b", 0..200);
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    let bottom = storage.state.load().levels[2].1.clone();
+
+    // pretend the bottom level is being compacted
+    storage
+        .compacting_ssts
+        .lock()
+        .extend(bottom.iter().copied());
+    external_sst(&external.path().join("a.sst"), "a", 0..50, "external");
+    let level = storage.ingest_sst(external.path().join("a.sst")).unwrap();
+    assert_eq!(level, 2);
+    assert_eq!(storage.state.load().levels[2].1, bottom);
+
+    // with L0 being compacted, nothing below it down to the next non-empty level can be used
+    put_and_flush(&storage, "c", 0..10);
+    let l0 = storage.state.load().l0_sstables.clone();
+    storage.compacting_ssts.lock().extend(l0.iter().copied());
+    external_sst(&external.path().join("d.sst"), "d", 0..50, "external");
+    assert_eq!(
+        storage.ingest_sst(external.path().join("d.sst")).unwrap(),
+        0
+    );
+    let l0_after = storage.state.load().l0_sstables.clone();
+    assert_eq!(l0_after.len(), l0.len() + 1);
+    assert!(!l0.contains(&l0_after[0]));
+    assert_eq!(get(&storage, "d", 7), Some(Bytes::from("external_7")));
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::ingest::{IngestOptions, SstWriter};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 1,
+            max_levels: 3,
+        },
+    ))
+}
+
+fn key_of(prefix: &str, i: usize) -> Vec<u8> {
+    format!("{}_{:03}", prefix, i).into_bytes()
+}
+
+/// Writes an SST with `prefix_{i}` keys for `i` in `range`, valued `value_{i}`, but for the keys
+/// in `deleted`, which are tombstones.
+fn write_sst(
+    path: &Path,
+    prefix: &str,
+    range: std::ops::Range<usize>,
+    value: &str,
+    deleted: &[usize],
+) -> PathBuf {
+    let path = path.join(format!("{}_{}.sst", prefix, value));
+    let mut writer = SstWriter::new(&path, 128);
+    for i in range {
+        if deleted.contains(&i) {
+            writer.delete(&key_of(prefix, i)).unwrap();
+        } else {
+            writer
+                .put(&key_of(prefix, i), format!("{}_{}", value, i).as_bytes())
+                .unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    path
+}
+
+fn put_and_flush(storage: &Arc<LsmStorageInner>, prefix: &str, range: std::ops::Range<usize>) {
+    for i in range {
+        storage
+            .put(&key_of(prefix, i), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+fn get(storage: &Arc<LsmStorageInner>, prefix: &str, i: usize) -> Option<Bytes> {
+    storage.get(&key_of(prefix, i)).unwrap()
+}
+
+/// The names of the SST files in `path`.
+fn sst_files(path: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".sst"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn check(storage: &Arc<LsmStorageInner>) {
+    for i in 0..50 {
+        for prefix in ["a", "d"] {
+            assert_eq!(
+                get(storage, prefix, i),
+                Some(Bytes::from(format!("external_{}", i)))
+            );
+        }
+    }
+    for i in 0..200 {
+        let expected = match i {
+            155 => None,
+            140..160 => Some(format!("second_{}", i)),
+            100..140 => Some(format!("first_{}", i)),
+            _ => Some(format!("value_{}", i)),
+        };
+        assert_eq!(get(storage, "b", i), expected.map(Bytes::from), "b_{}", i);
+    }
+    // ingested behind the data of the storage
+    for i in 0..20 {
+        let expected = if i < 10 {
+            format!("value_{}", i)
+        } else {
+            format!("behind_{}", i)
+        };
+        assert_eq!(get(storage, "c", i), Some(Bytes::from(expected)));
+    }
+    for i in 0..10 {
+        assert_eq!(
+            get(storage, "e", i),
+            Some(Bytes::from(format!("behind_{}", i)))
+        );
+    }
+}
+
+#[test]
+fn test_ingest_ssts() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    // move b_000..b_199 down to the bottom level
+    put_and_flush(&storage, "b", 0..200);
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+
+    // disjoint files go to the bottom level
+    let paths = [
+        write_sst(external.path(), "d", 0..50, "external", &[]),
+        write_sst(external.path(), "a", 0..50, "external", &[]),
+    ];
+    let levels = storage
+        .ingest_ssts(&paths, &IngestOptions::default())
+        .unwrap();
+    assert_eq!(levels, vec![3, 3]);
+    assert!(paths.iter().all(|path| path.exists()));
+
+    // a file overlapping the one before it goes above it, and is newer
+    let snapshot = storage.new_txn().unwrap();
+    let paths = [
+        write_sst(external.path(), "b", 100..150, "first", &[]),
+        write_sst(external.path(), "b", 140..160, "second", &[155]),
+    ];
+    let ingest_options = IngestOptions {
+        move_files: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.ingest_ssts(&paths, &ingest_options).unwrap(),
+        vec![2, 1]
+    );
+    assert!(paths.iter().all(|path| !path.exists()));
+    assert_eq!(
+        snapshot.get(b"b_120").unwrap(),
+        Some(Bytes::from("value_120"))
+    );
+    drop(snapshot);
+
+    // behind the data of the storage, existing keys keep their values
+    put_and_flush(&storage, "c", 0..10);
+    let paths = [
+        write_sst(external.path(), "c", 5..20, "behind", &[]),
+        write_sst(external.path(), "e", 0..10, "behind", &[]),
+    ];
+    let ingest_options = IngestOptions {
+        move_files: true,
+        ingest_behind: true,
+    };
+    assert_eq!(
+        storage.ingest_ssts(&paths, &ingest_options).unwrap(),
+        vec![3, 3]
+    );
+    check(&storage);
+
+    let levels = storage.state.load().levels.clone();
+    let l0_sstables = storage.state.load().l0_sstables.clone();
+    drop(storage);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert_eq!(storage.state.load().levels, levels);
+    assert_eq!(storage.state.load().l0_sstables, l0_sstables);
+    check(&storage);
+}
+
+#[test]
+fn test_ingest_behind_refuses_overlaps() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    put_and_flush(&storage, "b", 0..200);
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    let sstables = storage.state.load().sstables.len();
+    let files = sst_files(dir.path());
+    let ingest_options = IngestOptions {
+        move_files: true,
+        ingest_behind: true,
+    };
+
+    // overlapping the bottom level
+    let paths = [
+        write_sst(external.path(), "a", 0..10, "behind", &[]),
+        write_sst(external.path(), "b", 50..60, "behind", &[]),
+    ];
+    assert!(storage.ingest_ssts(&paths, &ingest_options).is_err());
+    // overlapping each other
+    let paths = [
+        write_sst(external.path(), "c", 0..10, "first", &[]),
+        write_sst(external.path(), "c", 5..15, "second", &[]),
+    ];
+    assert!(storage.ingest_ssts(&paths, &ingest_options).is_err());
+
+    // nothing is installed, and the files are left in place
+    assert_eq!(storage.state.load().sstables.len(), sstables);
+    assert!(paths.iter().all(|path| path.exists()));
+    assert_eq!(get(&storage, "a", 0), None);
+    assert_eq!(sst_files(dir.path()), files);
+}
+
+#[test]
+fn test_sst_writer_validates_keys() {
+    let dir = tempdir().unwrap();
+    let mut writer = SstWriter::new(dir.path().join("1.sst"), 128);
+    writer.put(b"b", b"value").unwrap();
+    assert!(writer.put(b"a", b"value").is_err());
+    assert!(writer.put(b"b", b"value").is_err());
+    assert!(writer.put(b"c", b"").is_err());
+    assert!(writer.put(b"", b"value").is_err());
+    writer.delete(b"c").unwrap();
+    assert!(writer.finish().unwrap() > 0);
+    assert!(SstWriter::new(dir.path().join("2.sst"), 128)
+        .finish()
+        .is_err());
+}
@@ -0,0 +1,100 @@
+use std::ops::{Bound, RangeBounds};
+
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder};
+
+fn key_of(idx: usize) -> String {
+    format!("key_{:05}", idx)
+}
+
+#[test]
+fn test_estimated_key_count_in_range() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(256);
+    // keys 100, 102, ..., 19998, leaving gaps for absent bounds
+    let keys = (50..10000).map(|i| key_of(i * 2)).collect::<Vec<_>>();
+    for key in &keys {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+            b"value",
+        );
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    // the entry counts of the blocks survive reopening the table
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    let block_entries = sst
+        .block_metas()
+        .iter()
+        .map(|meta| meta.num_entries as usize)
+        .collect::<Vec<_>>();
+    assert!(block_entries.len() > 100);
+    assert_eq!(
+        block_entries.iter().sum::<usize>() as u64,
+        sst.properties().num_entries
+    );
+    let max_block_entries = *block_entries.iter().max().unwrap();
+
+    assert_eq!(
+        sst.estimated_key_count_in_range(Bound::Unbounded, Bound::Unbounded),
+        keys.len()
+    );
+    assert_eq!(
+        sst.estimated_key_count_in_range(
+            Bound::Included(key_of(100).as_bytes()),
+            Bound::Included(key_of(19998).as_bytes())
+        ),
+        keys.len()
+    );
+    // ranges before, after and between the keys of the table
+    for (lower, upper) in [
+        (Bound::Unbounded, Bound::Excluded(key_of(100))),
+        (Bound::Excluded(key_of(19998)), Bound::Unbounded),
+        (
+            Bound::Included(key_of(20000)),
+            Bound::Included(key_of(30000)),
+        ),
+        (Bound::Included(key_of(0)), Bound::Included(key_of(99))),
+    ] {
+        assert_eq!(
+            sst.estimated_key_count_in_range(
+                lower.as_ref().map(|x| x.as_bytes()),
+                upper.as_ref().map(|x| x.as_bytes())
+            ),
+            0,
+            "{:?} {:?}",
+            lower,
+            upper
+        );
+    }
+
+    for (lower, upper) in [
+        (Bound::Included(1000), Bound::Excluded(2000)),
+        (Bound::Excluded(1001), Bound::Included(1999)),
+        (Bound::Included(123), Bound::Included(9876)),
+        (Bound::Unbounded, Bound::Included(5555)),
+        (Bound::Included(15432), Bound::Unbounded),
+        (Bound::Included(7000), Bound::Excluded(7010)),
+        (Bound::Included(4321), Bound::Included(4321)),
+    ] {
+        let lower = lower.map(key_of);
+        let upper = upper.map(key_of);
+        let exact = keys
+            .iter()
+            .filter(|key| RangeBounds::<String>::contains(&(lower.as_ref(), upper.as_ref()), *key))
+            .count();
+        let estimate = sst.estimated_key_count_in_range(
+            lower.as_ref().map(|x| x.as_bytes()),
+            upper.as_ref().map(|x| x.as_bytes()),
+        );
+        assert!(
+            estimate.abs_diff(exact) <= max_block_entries,
+            "{:?} {:?}: estimated {}, exact {}",
+            lower,
+            upper,
+            estimate,
+            exact
+        );
+    }
+}
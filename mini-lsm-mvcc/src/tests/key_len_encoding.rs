@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::{Block, BlockBuilder, BlockIterator, BLOCK_FORMAT_V2, BLOCK_FORMAT_V4};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+/// The width of the key lengths, stored before the number of entries of a V4 block.
+fn encoded_key_len_width(encoded: &[u8]) -> u8 {
+    encoded[encoded.len() - 3]
+}
+
+fn build_block(format_version: u8, keys: &[Vec<u8>]) -> bytes::Bytes {
+    let mut builder = BlockBuilder::new_with_format_version(1 << 20, format_version);
+    for (ts, key) in keys.iter().enumerate() {
+        assert!(builder.add(KeySlice::from_slice(key, ts as u64), b"value"));
+    }
+    builder.build().encode()
+}
+
+fn check_block(encoded: &[u8], format_version: u8, keys: &[Vec<u8>]) {
+    let block = Arc::new(Block::decode_with_format_version(encoded, format_version));
+    let block_ref = Arc::new(
+        Block::decode_bytes_with_format_version(
+            bytes::Bytes::copy_from_slice(encoded),
+            format_version,
+        )
+        .unwrap(),
+    );
+    for mut iter in [
+        BlockIterator::create_and_seek_to_first(block.clone()),
+        BlockIterator::create_and_seek_to_first_ref(block_ref),
+    ] {
+        for (ts, key) in keys.iter().enumerate() {
+            assert_eq!(iter.key().key_ref(), key.as_slice());
+            assert_eq!(iter.key().ts(), ts as u64);
+            assert_eq!(iter.value(), b"value");
+            iter.next();
+        }
+        assert!(!iter.is_valid());
+    }
+    for (ts, key) in keys.iter().enumerate() {
+        let iter = BlockIterator::create_and_seek_to_key(
+            block.clone(),
+            KeySlice::from_slice(key, ts as u64),
+        );
+        assert_eq!(iter.key().key_ref(), key.as_slice());
+        assert_eq!(iter.key().ts(), ts as u64);
+    }
+}
+
+#[test]
+fn test_short_keys_use_one_byte_lengths() {
+    let keys = (0..100)
+        .map(|i| format!("key_{:03}", i).into_bytes())
+        .collect::<Vec<_>>();
+    let compact = build_block(BLOCK_FORMAT_V4, &keys);
+    let wide = build_block(BLOCK_FORMAT_V2, &keys);
+    assert_eq!(encoded_key_len_width(&compact), 1);
+    // one byte saved for each of the overlap and the key length, plus the width byte
+    assert_eq!(compact.len() + 2 * keys.len() - 1, wide.len());
+    check_block(&compact, BLOCK_FORMAT_V4, &keys);
+}
+
+#[test]
+fn test_longer_key_widens_block() {
+    let mut keys = (0..10)
+        .map(|i| format!("key_{:03}", i).into_bytes())
+        .collect::<Vec<_>>();
+    let mut long_key = b"key_010_".to_vec();
+    long_key.resize(300, b'x');
+    keys.push(long_key);
+    keys.push(b"key_011".to_vec());
+    let encoded = build_block(BLOCK_FORMAT_V4, &keys);
+    assert_eq!(encoded_key_len_width(&encoded), 2);
+    check_block(&encoded, BLOCK_FORMAT_V4, &keys);
+
+    // the next block starts again with the narrowest width
+    let mut builder = BlockBuilder::new_with_format_version(1 << 20, BLOCK_FORMAT_V4);
+    for key in &keys {
+        assert!(builder.add(KeySlice::from_slice(key, 1), b"value"));
+    }
+    builder.reset();
+    assert!(builder.add(KeySlice::from_slice(b"key_100", 1), b"value"));
+    assert_eq!(encoded_key_len_width(&builder.build().encode()), 1);
+}
+
+#[test]
+fn test_sst_with_short_and_long_keys() {
+    let dir = tempdir().unwrap();
+    for (name, key_len) in [("short", 16), ("long", 70_000)] {
+        let keys = (0..20)
+            .map(|i| {
+                let mut key = format!("key_{:03}_", i).into_bytes();
+                key.resize(key_len, b'x');
+                key
+            })
+            .collect::<Vec<_>>();
+        let mut builder = SsTableBuilder::new_with_format_version(256, BLOCK_FORMAT_V4);
+        for key in &keys {
+            builder.add(KeySlice::from_slice(key, 1), b"value");
+        }
+        let path = dir.path().join(format!("{}.sst", name));
+        let sst = builder.build_for_test(&path).unwrap();
+        assert!(sst.num_of_blocks() > 1);
+        let expected_width = if key_len > u16::MAX as usize { 4 } else { 1 };
+        let block_meta = sst.block_metas().to_vec();
+        for block_idx in 0..sst.num_of_blocks() {
+            assert_eq!(
+                sst.read_block(block_idx).unwrap().key_len_width,
+                expected_width
+            );
+        }
+
+        let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+        assert_eq!(sst.properties().format_version, BLOCK_FORMAT_V4);
+        assert_eq!(sst.block_metas(), block_meta);
+        assert_eq!(sst.first_key().key_ref(), keys[0].as_slice());
+        assert_eq!(sst.last_key().key_ref(), keys[19].as_slice());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        for key in &keys {
+            assert_eq!(iter.key().key_ref(), key.as_slice());
+            assert_eq!(iter.value(), b"value");
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        let iter = SsTableIterator::create_and_seek_to_key(sst, KeySlice::from_slice(&keys[7], 1))
+            .unwrap();
+        assert_eq!(iter.key().key_ref(), keys[7].as_slice());
+    }
+}
@@ -0,0 +1,362 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::keyspace::KEYSPACES_DIR;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+
+fn options(compaction_options: CompactionOptions) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    options.enable_wal = true;
+    options
+}
+
+fn simple() -> CompactionOptions {
+    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    })
+}
+
+fn keyspaces() -> Vec<(String, LsmStorageOptions)> {
+    vec![
+        (
+            "users".to_string(),
+            options(CompactionOptions::NoCompaction),
+        ),
+        ("orders".to_string(), options(simple())),
+    ]
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn get(storage: &MiniLsm, key: &[u8]) -> Option<Bytes> {
+    storage.get(key).unwrap()
+}
+
+#[test]
+fn test_keyspace_batch_is_atomic() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    assert!(storage.keyspace("missing").is_none());
+
+    // the same key holds different values in each keyspace
+    users.put(b"a", b"user_a").unwrap();
+    orders.put(b"a", b"order_a").unwrap();
+    storage.put(b"a", b"root_a").unwrap();
+    assert_eq!(
+        users.get(b"a").unwrap(),
+        Some(Bytes::from_static(b"user_a"))
+    );
+    assert_eq!(
+        orders.get(b"a").unwrap(),
+        Some(Bytes::from_static(b"order_a"))
+    );
+    assert_eq!(get(&storage, b"a"), Some(Bytes::from_static(b"root_a")));
+
+    let snapshot = users.storage().new_txn().unwrap();
+    let batch: [(_, WriteBatchRecord<&[u8]>); 3] = [
+        (&users, WriteBatchRecord::Put(b"b", b"user_b")),
+        (&orders, WriteBatchRecord::Put(b"b", b"order_b")),
+        (&users, WriteBatchRecord::Del(b"a")),
+    ];
+    storage.write_keyspace_batch(&batch).unwrap();
+    // the batch commits at a single timestamp shared by the keyspaces
+    assert_eq!(snapshot.get(b"b").unwrap(), None);
+    assert_eq!(
+        snapshot.get(b"a").unwrap(),
+        Some(Bytes::from_static(b"user_a"))
+    );
+    assert_eq!(users.get(b"a").unwrap(), None);
+    assert_eq!(
+        users.get(b"b").unwrap(),
+        Some(Bytes::from_static(b"user_b"))
+    );
+    assert_eq!(
+        orders.get(b"b").unwrap(),
+        Some(Bytes::from_static(b"order_b"))
+    );
+
+    let mut iter = orders.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        keys,
+        vec![Bytes::from_static(b"a"), Bytes::from_static(b"b")]
+    );
+
+    storage.close().unwrap();
+    drop((users, orders, storage));
+
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    assert_eq!(users.get(b"a").unwrap(), None);
+    assert_eq!(
+        users.get(b"b").unwrap(),
+        Some(Bytes::from_static(b"user_b"))
+    );
+    assert_eq!(
+        orders.get(b"a").unwrap(),
+        Some(Bytes::from_static(b"order_a"))
+    );
+    assert_eq!(
+        orders.get(b"b").unwrap(),
+        Some(Bytes::from_static(b"order_b"))
+    );
+    assert_eq!(get(&storage, b"a"), Some(Bytes::from_static(b"root_a")));
+    storage.close().unwrap();
+}
+
+/// The segments of the log shared by the keyspaces of the storage in `dir`.
+fn log_segments(dir: &Path) -> Vec<String> {
+    let mut segments = std::fs::read_dir(dir.join(KEYSPACES_DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| entry.file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    segments.sort();
+    segments
+}
+
+#[test]
+fn test_keyspace_log_is_replayed() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    users.put(b"a", b"user_a").unwrap();
+    {
+        let inner = &users.storage().inner;
+        inner
+            .force_freeze_memtable(&inner.state_lock.lock())
+            .unwrap();
+    }
+    storage
+        .write_keyspace_batch(&[
+            (&orders, WriteBatchRecord::Put(b"c".as_slice(), b"batch_c")),
+            (&users, WriteBatchRecord::Put(b"c", b"batch_c")),
+        ])
+        .unwrap();
+    orders.put(b"d", b"order_d").unwrap();
+    orders.storage().force_flush().unwrap();
+    orders.delete(b"d").unwrap();
+    // closed without flushing, the writes to the keyspaces are only in the log
+    storage.close().unwrap();
+    drop((users, orders, storage));
+
+    // the keyspaces have no WAL or manifest of their own
+    for name in ["users", "orders"] {
+        for entry in std::fs::read_dir(dir.path().join(KEYSPACES_DIR).join(name)).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            assert!(name.ends_with(".sst"), "unexpected file {}", name);
+        }
+    }
+    assert!(!log_segments(dir.path()).is_empty());
+
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    assert_eq!(
+        users.get(b"a").unwrap(),
+        Some(Bytes::from_static(b"user_a"))
+    );
+    assert_eq!(
+        users.get(b"c").unwrap(),
+        Some(Bytes::from_static(b"batch_c"))
+    );
+    assert_eq!(
+        orders.get(b"c").unwrap(),
+        Some(Bytes::from_static(b"batch_c"))
+    );
+    // the delete is replayed over the flushed put
+    assert_eq!(orders.get(b"d").unwrap(), None);
+    // the writes of a keyspace are not replayed to the others
+    assert_eq!(users.get(b"d").unwrap(), None);
+    assert_eq!(get(&storage, b"c"), None);
+    // later writes are ordered after the recovered ones
+    users.put(b"c", b"user_c").unwrap();
+    assert_eq!(
+        users.get(b"c").unwrap(),
+        Some(Bytes::from_static(b"user_c"))
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_keyspace_log_is_purged() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    for round in 0..3 {
+        storage
+            .write_keyspace_batch(&[
+                (&users, WriteBatchRecord::Put(key_of(round), key_of(round))),
+                (&orders, WriteBatchRecord::Put(key_of(round), key_of(round))),
+            ])
+            .unwrap();
+        users.storage().force_flush().unwrap();
+    }
+    // the memtable of orders needs every segment since it was created
+    assert!(log_segments(dir.path()).len() > 3);
+
+    orders.storage().force_flush().unwrap();
+    // only the segments since the earliest current memtable started are left
+    assert_eq!(log_segments(dir.path()).len(), 2);
+    storage.close().unwrap();
+    drop((users, orders, storage));
+
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    assert_eq!(log_segments(dir.path()).len(), 1);
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    for idx in 0..3 {
+        assert_eq!(
+            users.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(key_of(idx)))
+        );
+        assert_eq!(
+            orders.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(key_of(idx)))
+        );
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_keyspaces_compact_independently() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    let users = storage.keyspace("users").unwrap();
+    let orders = storage.keyspace("orders").unwrap();
+    for round in 0..4 {
+        for idx in round * 100..(round + 1) * 100 {
+            storage
+                .write_keyspace_batch(&[
+                    (&users, WriteBatchRecord::Put(key_of(idx), key_of(idx))),
+                    (&orders, WriteBatchRecord::Put(key_of(idx), key_of(idx))),
+                ])
+                .unwrap();
+        }
+        users.storage().force_flush().unwrap();
+        orders.storage().force_flush().unwrap();
+    }
+
+    // only the keyspace with a compaction strategy compacts its L0 SSTs
+    let begin = Instant::now();
+    loop {
+        let settled = {
+            let state = orders.storage().inner.state.load();
+            state.l0_sstables.len() < 2
+        } && orders.storage().inner.compacting_ssts.lock().is_empty();
+        if settled {
+            break;
+        }
+        assert!(begin.elapsed() < Duration::from_secs(30));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(orders
+        .storage()
+        .inner
+        .state
+        .load()
+        .levels
+        .iter()
+        .any(|(_, ssts)| !ssts.is_empty()));
+    assert_eq!(users.storage().inner.state.load().l0_sstables.len(), 4);
+    assert!(storage.inner.state.load().l0_sstables.is_empty());
+
+    // the keyspaces have their own SSTs, in their own directories
+    assert_ne!(users.storage().inner.path, orders.storage().inner.path);
+    for idx in 0..400 {
+        assert_eq!(
+            users.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(key_of(idx)))
+        );
+        assert_eq!(
+            orders.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(key_of(idx)))
+        );
+        assert_eq!(get(&storage, &key_of(idx)), None);
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_keyspaces_must_be_given() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces(),
+    )
+    .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    assert!(MiniLsm::open_with_keyspaces(
+        dir.path(),
+        options(CompactionOptions::NoCompaction),
+        keyspaces().into_iter().take(1).collect(),
+    )
+    .is_err());
+    assert!(MiniLsm::open_with_keyspaces(
+        tempdir().unwrap().path(),
+        options(CompactionOptions::NoCompaction),
+        vec![(
+            "../escape".to_string(),
+            options(CompactionOptions::NoCompaction)
+        )],
+    )
+    .is_err());
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::limited_merge_iterator::LimitedMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder};
+
+/// Builds SSTs with 12 keys each, where the last two keys of each SST are also the first two keys
+/// of the next one at a newer timestamp, so that at most two SSTs overlap at any key.
+fn build_ssts(dir: &std::path::Path, num_ssts: usize) -> Vec<Arc<SsTable>> {
+    (0..num_ssts)
+        .map(|i| {
+            let mut builder = SsTableBuilder::new(128);
+            for k in i * 10..i * 10 + 12 {
+                builder.add(
+                    KeySlice::from_slice(format!("key_{:04}", k).as_bytes(), i as u64 + 1),
+                    format!("value_{}_{}", k, i).as_bytes(),
+                );
+            }
+            Arc::new(
+                builder
+                    .build_for_test(dir.join(format!("{}.sst", i)))
+                    .unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_limited_merge_iterator_order() {
+    let dir = tempdir().unwrap();
+    let mut ssts = build_ssts(dir.path(), 50);
+    // the order of the SSTs does not matter when they have no key in common
+    ssts.reverse();
+    ssts.swap(3, 40);
+
+    let mut expected = Vec::new();
+    for i in 0..50 {
+        for k in i * 10..i * 10 + 12 {
+            expected.push((format!("key_{:04}", k), i as u64 + 1));
+        }
+    }
+    expected.sort_by(|(a_key, a_ts), (b_key, b_ts)| (a_key, b_ts).cmp(&(b_key, a_ts)));
+
+    let mut iter = LimitedMergeIterator::create_and_seek_to_first(ssts, 2).unwrap();
+    let mut actual = Vec::new();
+    while iter.is_valid() {
+        assert!(iter.num_active_iterators() <= 2);
+        let key = iter.key();
+        actual.push((String::from_utf8(key.key_ref().to_vec()).unwrap(), key.ts()));
+        let k = &actual.last().unwrap().0[4..];
+        assert_eq!(
+            iter.value(),
+            format!("value_{}_{}", k.parse::<usize>().unwrap(), key.ts() - 1).as_bytes()
+        );
+        iter.next().unwrap();
+    }
+    assert_eq!(actual, expected);
+    assert_eq!(iter.num_active_iterators(), 0);
+}
+
+#[test]
+fn test_limited_merge_iterator_too_many_overlapping() {
+    let dir = tempdir().unwrap();
+    let ssts = build_ssts(dir.path(), 3);
+    let mut iter = LimitedMergeIterator::create_and_seek_to_first(ssts, 1).unwrap();
+    let mut result = Ok(());
+    while iter.is_valid() && result.is_ok() {
+        assert_eq!(iter.num_active_iterators(), 1);
+        result = iter.next();
+    }
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_limited_merge_iterator_prefers_smaller_index() {
+    let dir = tempdir().unwrap();
+    let mut ssts = Vec::new();
+    for (id, value) in [b"new", b"old"].into_iter().enumerate() {
+        let mut builder = SsTableBuilder::new(128);
+        builder.add(KeySlice::from_slice(b"a", 1), b"a");
+        builder.add(KeySlice::from_slice(b"b", 1), value);
+        ssts.push(Arc::new(
+            builder
+                .build_for_test(dir.path().join(format!("{}.sst", id)))
+                .unwrap(),
+        ));
+    }
+    let mut iter = LimitedMergeIterator::create_and_seek_to_first(ssts, 2).unwrap();
+    assert_eq!(iter.key().key_ref(), b"a");
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), b"b");
+    assert_eq!(iter.value(), b"new");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
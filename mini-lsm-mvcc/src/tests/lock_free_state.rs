@@ -0,0 +1,179 @@
+//! Stress tests of the state read without locking, which the flushes, the freezes and the
+//! compactions replace under the readers.
+
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 300;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(round: usize) -> Vec<u8> {
+    format!("value_{:08}", round).into_bytes()
+}
+
+fn round_of(value: &[u8]) -> usize {
+    std::str::from_utf8(&value[6..]).unwrap().parse().unwrap()
+}
+
+#[test]
+fn test_reads_see_one_snapshot_under_churn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    // the memtables freeze every few hundred writes
+    options.target_sst_size = 1 << 13;
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), &value_of(0)).unwrap();
+    }
+
+    let done = AtomicBool::new(false);
+    let num_rounds = 20;
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // the rounds overwrite the keys in order
+            for round in 1..=num_rounds {
+                for idx in 0..NUM_KEYS {
+                    storage.put(&key_of(idx), &value_of(round)).unwrap();
+                }
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                storage.force_flush().unwrap();
+                std::thread::sleep(Duration::from_millis(2));
+            }
+        });
+        for reader in 0..4 {
+            let (storage, done) = (&storage, &done);
+            scope.spawn(move || {
+                let mut last_round = 0;
+                while !done.load(Ordering::SeqCst) {
+                    // a scan reads from one state, where a prefix of the keys is one round
+                    // ahead of the others
+                    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+                    let mut rounds = Vec::with_capacity(NUM_KEYS);
+                    while iter.is_valid() {
+                        assert_eq!(iter.key(), key_of(rounds.len()));
+                        rounds.push(round_of(iter.value()));
+                        iter.next().unwrap();
+                    }
+                    assert_eq!(rounds.len(), NUM_KEYS);
+                    assert!(
+                        rounds
+                            .windows(2)
+                            .all(|pair| pair[0] == pair[1] || pair[0] == pair[1] + 1),
+                        "{:?}",
+                        rounds
+                    );
+
+                    // a key never reads older than it did before
+                    let value = storage.get(&key_of(reader * 50)).unwrap().unwrap();
+                    assert!(round_of(&value) >= last_round);
+                    last_round = round_of(&value);
+                }
+            });
+        }
+    });
+
+    for idx in 0..NUM_KEYS {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap().unwrap(),
+            value_of(num_rounds)
+        );
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_no_write_lost_to_a_frozen_memtable() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let writers = (0..4)
+            .map(|writer| {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for idx in (writer..NUM_KEYS * 4).step_by(4) {
+                        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        // each write races with freezes and with the flush of the memtable it may have gone to
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                if !storage.state.load().memtable.is_empty() {
+                    storage
+                        .force_freeze_memtable(&storage.state_lock.lock())
+                        .unwrap();
+                }
+                storage.force_flush_next_imm_memtable().unwrap();
+            }
+        });
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+
+    // only the SSTs are left to hold the writes
+    if !storage.state.load().memtable.is_empty() {
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+    }
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    for idx in 0..NUM_KEYS * 4 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(value_of(idx).into()),
+            "key {} lost",
+            idx
+        );
+    }
+}
+
+#[test]
+fn test_state_write_guard_holds_state_lock() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    storage.put(b"key", b"value").unwrap();
+    {
+        let mut state = storage.state.write();
+        // a freeze would store a state the guard then overwrites
+        assert!(storage.state_lock.try_lock().is_none());
+        let mut snapshot = state.as_ref().clone();
+        snapshot.l0_sstables.clear();
+        *state = snapshot.into();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    assert_eq!(storage.state.load().imm_memtables.len(), 1);
+    assert_eq!(storage.get(b"key").unwrap().unwrap().as_ref(), b"value");
+}
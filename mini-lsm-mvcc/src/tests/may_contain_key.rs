@@ -0,0 +1,84 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::{HashedKey, SsTableBuilder};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_sst_may_contain_key() {
+    let mut builder = SsTableBuilder::new(128);
+    // only even keys within [key_01000, key_02998] are present
+    for idx in (1000..3000).step_by(2) {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            b"value",
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+
+    // present keys are always reported
+    for idx in (1000..3000).step_by(2) {
+        assert!(sst.may_contain_key(&key_of(idx)));
+    }
+
+    // out of range keys are rejected even if the bloom filter would accept them
+    assert!(!sst.may_contain_key(&key_of(999)));
+    assert!(!sst.may_contain_key(&key_of(2999)));
+    assert!(!sst.may_contain_key(b""));
+    assert!(!sst.may_contain_key(b"zzz"));
+
+    // in-range absent keys are rejected by the bloom filter most of the time
+    let bloom = sst.bloom.as_ref().unwrap();
+    let mut rejected = 0;
+    for idx in (1001..2999).step_by(2) {
+        let key = key_of(idx);
+        let may_contain = sst.may_contain_key(&key);
+        assert_eq!(
+            may_contain,
+            bloom.may_contain(farmhash::fingerprint32(&key))
+        );
+        if !may_contain {
+            rejected += 1;
+        }
+    }
+    assert!(
+        rejected > 900,
+        "bloom filter rejected only {} keys",
+        rejected
+    );
+}
+
+#[test]
+fn test_sst_may_contain_hashed_key() {
+    let dir = tempdir().unwrap();
+    let tables = (0..4)
+        .map(|id| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in (id * 100..id * 100 + 200).step_by(4) {
+                builder.add(KeySlice::from_slice(&key_of(idx), 1), b"value");
+            }
+            builder
+                .build_for_test(dir.path().join(format!("{}.sst", id)))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for idx in 0..600 {
+        let key = key_of(idx);
+        let hashed_key = HashedKey::new(&key);
+        assert_eq!(hashed_key.key(), key.as_slice());
+        assert_eq!(hashed_key.hash(), farmhash::fingerprint32(&key));
+        for table in &tables {
+            let probes = table.bloom_probes();
+            assert_eq!(
+                table.may_contain_hashed_key(hashed_key),
+                table.may_contain_key(&key)
+            );
+            // both consult the bloom filter only for keys in the range of the table
+            assert!(matches!(table.bloom_probes() - probes, 0 | 2));
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::BLOCK_FORMAT_VERSION;
+use crate::compact::{merge_build, MergeBuildOptions};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+const WATERMARK: u64 = 25;
+
+/// A key, its timestamp and its value, empty for a tombstone.
+type Entry = (Vec<u8>, u64, Vec<u8>);
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// The entries of an input: keys `idx` in `range` stepping by `step`, all at `ts`, every
+/// `tombstone_every`-th of them a tombstone.
+fn input_entries(
+    range: std::ops::Range<usize>,
+    step: usize,
+    ts: u64,
+    tombstone_every: usize,
+) -> Vec<Entry> {
+    range
+        .step_by(step)
+        .enumerate()
+        .map(|(i, idx)| {
+            let value = if i % tombstone_every == 0 {
+                Vec::new()
+            } else {
+                format!("value_{:05}@{}", idx, ts).into_bytes()
+            };
+            (key_of(idx), ts, value)
+        })
+        .collect()
+}
+
+fn build_input(entries: &[Entry], path: &std::path::Path) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(256);
+    for (key, ts, value) in entries {
+        builder.add(KeySlice::from_slice(key, *ts), value);
+    }
+    Arc::new(builder.build_for_test(path).unwrap())
+}
+
+/// What a compaction into the bottom level keeps of `inputs`: all versions above the watermark
+/// and the newest one at or below it, unless it is a tombstone.
+fn expected_entries(inputs: &[Vec<Entry>]) -> Vec<Entry> {
+    let mut versions = BTreeMap::<Vec<u8>, Vec<(u64, Vec<u8>)>>::new();
+    for (key, ts, value) in inputs.iter().flatten() {
+        versions
+            .entry(key.clone())
+            .or_default()
+            .push((*ts, value.clone()));
+    }
+    let mut expected = Vec::new();
+    for (key, mut versions) in versions {
+        versions.sort_by_key(|(ts, _)| std::cmp::Reverse(*ts));
+        for (ts, value) in versions {
+            if ts <= WATERMARK {
+                if !value.is_empty() {
+                    expected.push((key.clone(), ts, value));
+                }
+                break;
+            }
+            expected.push((key.clone(), ts, value));
+        }
+    }
+    expected
+}
+
+#[test]
+fn test_merge_build_overlapping_inputs() {
+    let dir = tempdir().unwrap();
+    let inputs = [
+        input_entries(0..300, 1, 30, 7),
+        input_entries(100..400, 1, 20, 5),
+        input_entries(0..500, 2, 10, 11),
+    ];
+    let input_ssts = inputs
+        .iter()
+        .enumerate()
+        .map(|(i, entries)| build_input(entries, &dir.path().join(format!("input_{}.sst", i))))
+        .collect::<Vec<_>>();
+    let iters = input_ssts
+        .iter()
+        .map(|sst| Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()))
+        .collect();
+
+    let out_dir = tempdir().unwrap();
+    let options = MergeBuildOptions {
+        block_size: 256,
+        format_version: BLOCK_FORMAT_VERSION,
+        target_sst_size: 4096,
+        watermark: WATERMARK,
+        drop_tombstones: true,
+        iter_options: Default::default(),
+        block_cache: None,
+    };
+    let outputs = merge_build(iters, 100, &out_dir, &options)
+        .unwrap()
+        .into_iter()
+        .map(Arc::new)
+        .collect::<Vec<_>>();
+    assert!(outputs.len() > 2, "{} outputs", outputs.len());
+
+    let mut entries = Vec::new();
+    for (i, sst) in outputs.iter().enumerate() {
+        assert_eq!(sst.sst_id(), 100 + i);
+        assert!(out_dir.path().join(format!("{:05}.sst", 100 + i)).exists());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            let key = iter.key().key_ref();
+            assert!(sst.may_contain_key(key));
+            entries.push((key.to_vec(), iter.key().ts(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(entries, expected_entries(&inputs));
+
+    // split by size, between user keys
+    for pair in outputs.windows(2) {
+        assert!(pair[0].last_key().key_ref() < pair[1].first_key().key_ref());
+        assert!(pair[0].table_size() >= options.target_sst_size as u64);
+    }
+}
+
+#[test]
+fn test_merge_build_only_tombstones() {
+    let dir = tempdir().unwrap();
+    let sst = build_input(
+        &input_entries(0..10, 1, 5, 1),
+        &dir.path().join("input.sst"),
+    );
+    let iters = vec![Box::new(
+        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+    )];
+    let options = MergeBuildOptions {
+        block_size: 256,
+        format_version: BLOCK_FORMAT_VERSION,
+        target_sst_size: 4096,
+        watermark: WATERMARK,
+        drop_tombstones: true,
+        iter_options: Default::default(),
+        block_cache: None,
+    };
+    // only tombstones, all dropped at the bottom level
+    let outputs = merge_build(iters, 1, dir.path(), &options).unwrap();
+    assert!(outputs.is_empty());
+}
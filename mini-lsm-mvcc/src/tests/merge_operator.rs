@@ -0,0 +1,181 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::merge_operator::MergeOperator;
+
+/// Adds the decimal operands to the decimal value of the key.
+struct CounterOperator;
+
+fn parse(value: &[u8]) -> u64 {
+    std::str::from_utf8(value).unwrap().parse().unwrap()
+}
+
+impl MergeOperator for CounterOperator {
+    fn name(&self) -> &str {
+        "counter"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Bytes> {
+        let sum = existing.map_or(0, parse) + operands.iter().map(|x| parse(x)).sum::<u64>();
+        Ok(Bytes::from(sum.to_string()))
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Bytes> {
+        Some(Bytes::from((parse(left) + parse(right)).to_string()))
+    }
+}
+
+/// Appends the operands to the value of the key, never combining two operands.
+struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Bytes> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        for operand in operands {
+            value.extend_from_slice(operand);
+        }
+        Ok(Bytes::from(value))
+    }
+}
+
+fn options_with(operator: Arc<dyn MergeOperator>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.merge_operator = Some(operator);
+    options
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_merge_operands_across_layers() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), options_with(Arc::new(AppendOperator))).unwrap(),
+    );
+    storage.merge(b"a", b"1").unwrap();
+    storage.put(b"b", b"x").unwrap();
+    flush(&storage);
+    storage.merge(b"a", b"2").unwrap();
+    storage.merge(b"b", b"y").unwrap();
+    flush(&storage);
+    storage.merge(b"a", b"3").unwrap();
+    storage.merge(b"c", b"z").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("123")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("xy")));
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("z")));
+
+    let expected = vec![
+        (Bytes::from("a"), Bytes::from("123")),
+        (Bytes::from("b"), Bytes::from("xy")),
+        (Bytes::from("c"), Bytes::from("z")),
+    ];
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected.clone(),
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected.into_iter().rev().collect(),
+    );
+    assert_eq!(
+        storage
+            .multi_get(&[b"c".as_slice(), b"a".as_slice()])
+            .unwrap(),
+        vec![Some(Bytes::from("z")), Some(Bytes::from("123"))]
+    );
+
+    // a delete or a range tombstone ends the chain of operands
+    storage.delete(b"a").unwrap();
+    storage.merge(b"a", b"4").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("4")));
+    storage.delete_range(b"b", b"c").unwrap();
+    storage.merge(b"b", b"5").unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("5")));
+
+    // a full compaction combines the operands with the values below them
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("4")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("5")));
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("z")));
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    assert!(storage.merge(b"a", b"1").is_err());
+    assert!(storage
+        .write_batch(&[
+            WriteBatchRecord::Put(b"b".as_slice(), b"1".as_slice()),
+            WriteBatchRecord::Merge(b"a".as_slice(), b"1".as_slice()),
+        ])
+        .is_err());
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn test_merge_counter_concurrent() {
+    const NUM_THREADS: usize = 8;
+    const MERGES_PER_THREAD: usize = 200;
+    let dir = tempdir().unwrap();
+    let mut options = options_with(Arc::new(CounterOperator));
+    options.enable_wal = true;
+    options.target_sst_size = 1 << 12;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"counter", b"1000").unwrap();
+
+    std::thread::scope(|scope| {
+        for thread in 0..NUM_THREADS {
+            let storage = storage.clone();
+            scope.spawn(move || {
+                for i in 0..MERGES_PER_THREAD {
+                    storage.merge(b"counter", b"1").unwrap();
+                    if thread == 0 && i % 50 == 0 {
+                        storage.force_flush().unwrap();
+                    }
+                }
+            });
+        }
+    });
+    let expected = Bytes::from((1000 + NUM_THREADS * MERGES_PER_THREAD).to_string());
+    assert_eq!(storage.get(b"counter").unwrap(), Some(expected.clone()));
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"counter").unwrap(), Some(expected.clone()));
+    storage.merge(b"counter", b"5").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    let expected = Bytes::from((1005 + NUM_THREADS * MERGES_PER_THREAD).to_string());
+    assert_eq!(storage.get(b"counter").unwrap(), Some(expected));
+}
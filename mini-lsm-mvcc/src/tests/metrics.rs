@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Parses the samples of a scrape, keyed by their name and labels.
+fn scrape(storage: &MiniLsm) -> HashMap<String, f64> {
+    let output = storage.gather_metrics();
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let (name, value) = line.rsplit_once(' ').unwrap();
+            (name.to_string(), value.parse::<f64>().unwrap())
+        })
+        .collect()
+}
+
+/// Writes, flushes and reads back the keys in `range`, and scans them.
+fn workload(storage: &MiniLsm, range: std::ops::Range<usize>) {
+    for idx in range.clone() {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in range.clone() {
+        assert!(storage.get(&key_of(idx)).unwrap().is_some());
+        // absent keys within the key range of the SST
+        assert!(storage
+            .get(&[key_of(idx), b"_".to_vec()].concat())
+            .unwrap()
+            .is_none());
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+}
+
+#[test]
+fn test_metrics_scrape() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    workload(&storage, 0..200);
+    let first = scrape(&storage);
+    for name in [
+        "lsm_writes_total",
+        "lsm_write_bytes_total",
+        "lsm_reads_total",
+        "lsm_read_bytes_total",
+        "lsm_scans_total",
+        "lsm_block_cache_hits_total",
+        "lsm_block_cache_misses_total",
+        "lsm_bloom_checks_total",
+        "lsm_bloom_negatives_total",
+        "lsm_flushes_total",
+        "lsm_flush_bytes_total",
+        "lsm_compactions_total",
+        "lsm_compaction_read_bytes_total",
+        "lsm_compaction_write_bytes_total",
+        "lsm_write_stall_seconds_total",
+        "lsm_get_latency_seconds_count",
+        "lsm_get_latency_seconds_sum",
+        "lsm_get_latency_seconds_bucket{le=\"+Inf\"}",
+        "lsm_scan_latency_seconds_count",
+        "lsm_flush_duration_seconds_count",
+        "lsm_compaction_duration_seconds_count",
+        "lsm_memtable_size_bytes",
+        "lsm_immutable_memtables",
+        "lsm_block_cache_usage_bytes",
+        "lsm_level_size_bytes{level=\"0\"}",
+        "lsm_level_files{level=\"0\"}",
+    ] {
+        assert!(first.contains_key(name), "{} missing", name);
+    }
+    assert_eq!(first["lsm_writes_total"], 200.0);
+    assert_eq!(first["lsm_reads_total"], 400.0);
+    assert_eq!(first["lsm_read_bytes_total"], 200.0 * 5.0);
+    assert_eq!(first["lsm_get_latency_seconds_count"], 400.0);
+    assert_eq!(first["lsm_scans_total"], 1.0);
+    assert_eq!(first["lsm_flushes_total"], 1.0);
+    assert_eq!(first["lsm_level_files{level=\"0\"}"], 1.0);
+    assert!(first["lsm_level_size_bytes{level=\"0\"}"] > 0.0);
+    assert!(first["lsm_block_cache_misses_total"] > 0.0);
+    assert!(first["lsm_block_cache_hits_total"] > 0.0);
+    // most absent keys are ruled out by the bloom filter
+    assert!(first["lsm_bloom_negatives_total"] > 150.0);
+    assert!(first["lsm_bloom_checks_total"] >= first["lsm_bloom_negatives_total"]);
+
+    workload(&storage, 200..400);
+    let second = scrape(&storage);
+    for (name, value) in &first {
+        let is_gauge = name.starts_with("lsm_memtable_size_bytes")
+            || name.starts_with("lsm_immutable_memtables")
+            || name.starts_with("lsm_block_cache_usage_bytes");
+        if !is_gauge {
+            assert!(second[name] >= *value, "{} decreased", name);
+        }
+    }
+    assert_eq!(second["lsm_writes_total"], 400.0);
+    assert_eq!(second["lsm_flushes_total"], 2.0);
+    assert_eq!(second["lsm_level_files{level=\"0\"}"], 2.0);
+    // the histogram buckets are cumulative
+    let buckets = second
+        .iter()
+        .filter(|(name, _)| name.starts_with("lsm_get_latency_seconds_bucket"))
+        .map(|(_, count)| *count)
+        .collect::<Vec<_>>();
+    assert!(buckets.iter().all(|count| *count <= 800.0));
+    assert_eq!(second["lsm_get_latency_seconds_bucket{le=\"+Inf\"}"], 800.0);
+    storage.close().unwrap();
+}
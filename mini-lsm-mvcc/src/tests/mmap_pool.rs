@@ -0,0 +1,72 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{FileObject, MmapPool, SsTable, SsTableBuilder, SsTableIterator};
+
+/// Count the file descriptors of this process that point into `dir`.
+fn open_fds_in(dir: &Path) -> usize {
+    std::fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+        .filter(|target| target.starts_with(dir))
+        .count()
+}
+
+fn key_of(table: usize, idx: usize) -> Vec<u8> {
+    format!("key_{:03}_{:03}", table, idx).into_bytes()
+}
+
+#[test]
+fn test_open_many_ssts_through_mmap_pool() {
+    let dir = tempdir().unwrap();
+    for table in 0..100 {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in 0..20 {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(table, idx)),
+                format!("value_{}_{}", table, idx).as_bytes(),
+            );
+        }
+        builder
+            .build_for_test(dir.path().join(format!("{}.sst", table)))
+            .unwrap();
+    }
+    assert_eq!(open_fds_in(dir.path()), 0);
+
+    let pool = Arc::new(MmapPool::new(8));
+    let tables = (0..100)
+        .map(|table| {
+            let file = FileObject::open_with_pool(
+                &dir.path().join(format!("{}.sst", table)),
+                pool.clone(),
+            )
+            .unwrap();
+            Arc::new(SsTable::open(table, None, file).unwrap())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(open_fds_in(dir.path()), 0);
+    assert!(pool.num_mappings() <= 8);
+
+    // read every table twice so that evicted mappings are re-created
+    for _ in 0..2 {
+        for (table, sst) in tables.iter().enumerate() {
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+            for idx in 0..20 {
+                assert!(iter.is_valid());
+                assert_eq!(iter.key().key_ref(), key_of(table, idx));
+                assert_eq!(iter.value(), format!("value_{}_{}", table, idx).as_bytes());
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+            assert!(pool.num_mappings() <= 8);
+        }
+    }
+    assert_eq!(open_fds_in(dir.path()), 0);
+
+    drop(tables);
+    assert_eq!(pool.num_mappings(), 0);
+}
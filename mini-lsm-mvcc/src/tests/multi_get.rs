@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::faulty_file::FaultyFileObject;
+use crate::table::{SsTable, SsTableBuilder};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+/// Writes a round of random puts and deletes, with a range deletion now and then.
+fn write_round(storage: &Arc<LsmStorageInner>, rng: &mut StdRng, round: usize) {
+    for _ in 0..300 {
+        let key = key_of(rng.gen_range(0..1000));
+        if rng.gen_bool(0.2) {
+            storage.delete(&key).unwrap();
+        } else {
+            storage
+                .put(&key, format!("value_{}", round).as_bytes())
+                .unwrap();
+        }
+    }
+    if rng.gen_bool(0.5) {
+        let start = rng.gen_range(0..1000);
+        storage
+            .delete_range(&key_of(start), &key_of(start + rng.gen_range(1..50)))
+            .unwrap();
+    }
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_multi_get_matches_get() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut snapshots = Vec::new();
+    for round in 0..12 {
+        write_round(&storage, &mut rng, round);
+        if round == 5 {
+            storage.force_full_compaction().unwrap();
+        }
+        if round % 3 != 2 {
+            flush(&storage);
+        } else {
+            // leave an immutable memtable behind
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+        }
+        snapshots.push(storage.new_txn().unwrap());
+    }
+    write_round(&storage, &mut rng, 12);
+    snapshots.push(storage.new_txn().unwrap());
+    let state = storage.state.load_full();
+    assert!(!state.l0_sstables.is_empty());
+    assert!(!state.levels[0].1.is_empty());
+    assert!(!state.imm_memtables.is_empty());
+
+    for txn in &snapshots {
+        // unsorted, with duplicates and keys that were never written
+        let keys = (0..200)
+            .map(|_| key_of(rng.gen_range(0..1100)))
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_slice()).collect::<Vec<_>>();
+        let expected = keys
+            .iter()
+            .map(|key| txn.get(key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(txn.multi_get(&keys).unwrap(), expected);
+    }
+
+    // the local writes of a transaction take precedence
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(1), b"local");
+    txn.delete(&key_of(2));
+    let values = txn.multi_get(&[&key_of(1), &key_of(2)]).unwrap();
+    assert_eq!(values, vec![Some("local".into()), None]);
+    assert_eq!(storage.multi_get(&[]).unwrap(), Vec::new());
+}
+
+#[test]
+fn test_multi_get_reads_each_block_once() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(256);
+    for i in 0..500 {
+        builder.add(KeySlice::from_slice(&key_of(i), 1), b"value");
+    }
+    let num_blocks = builder.build_for_test(&path).unwrap().num_of_blocks();
+    assert!(num_blocks > 10);
+
+    // opening the table takes 4 reads, one more read than the number of blocks fails
+    let file = FaultyFileObject::from_file(&path)
+        .unwrap()
+        .fail_on_read(4 + num_blocks + 1);
+    let sst = SsTable::open_for_test(file.into()).unwrap();
+    let keys = (0..510).map(key_of).collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| key.as_slice()).collect::<Vec<_>>();
+    let mut found = Vec::new();
+    sst.multi_get(&keys, 1, |idx, ts, value| {
+        assert_eq!((ts, value), (1, b"value".as_slice()));
+        found.push(idx);
+    })
+    .unwrap();
+    assert_eq!(found, (0..500).collect::<Vec<_>>());
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+use crate::lsm_storage::new_block_cache;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn collect(mut iter: SsTableIterator) -> Vec<(KeyVec, Vec<u8>)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().to_key_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_no_cache_iterator_skips_block_cache() {
+    // the weigher is called once for every insertion
+    let insertions = Arc::new(AtomicUsize::new(0));
+    let block_cache = {
+        let insertions = insertions.clone();
+        Arc::new(new_block_cache(1 << 20, move |_, _| {
+            insertions.fetch_add(1, Ordering::SeqCst);
+            1
+        }))
+    };
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            format!("value_{}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let table = Arc::new(
+        builder
+            .build(1, Some(block_cache.clone()), dir.path().join("1.sst"))
+            .unwrap(),
+    );
+    assert!(table.num_of_blocks() > 1);
+    let insertions_after_build = insertions.load(Ordering::SeqCst);
+
+    let uncached =
+        collect(SsTableIterator::create_and_seek_to_first_no_cache(table.clone()).unwrap());
+    assert_eq!(uncached.len(), 1000);
+    let key = KeySlice::for_testing_from_slice_no_ts(b"key_00500");
+    let uncached_from_key =
+        collect(SsTableIterator::create_and_seek_to_key_no_cache(table.clone(), key).unwrap());
+    assert_eq!(uncached_from_key.len(), 500);
+    assert_eq!(insertions.load(Ordering::SeqCst), insertions_after_build);
+
+    // the cached iterators return the same entries, filling the cache
+    assert_eq!(
+        collect(SsTableIterator::create_and_seek_to_first(table.clone()).unwrap()),
+        uncached
+    );
+    assert_eq!(
+        collect(SsTableIterator::create_and_seek_to_key(table.clone(), key).unwrap()),
+        uncached_from_key
+    );
+    assert_eq!(
+        insertions.load(Ordering::SeqCst),
+        insertions_after_build + table.num_of_blocks()
+    );
+}
@@ -0,0 +1,48 @@
+use bytes::Bytes;
+
+use super::harness::{check_iter_result_by_key, MockIterator};
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::ordered_guard::OrderedGuard;
+use crate::iterators::StorageIterator;
+
+fn entries(keys: &[&'static str]) -> Vec<(Bytes, Bytes)> {
+    keys.iter()
+        .map(|key| (Bytes::from(*key), Bytes::from("value")))
+        .collect()
+}
+
+fn drain(iter: &mut impl StorageIterator) {
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+}
+
+#[test]
+fn test_ordered_guard_passes_ordered_iterators() {
+    let merge = MergeIterator::create(vec![
+        Box::new(MockIterator::new(entries(&["a", "c", "e"]))),
+        Box::new(MockIterator::new(entries(&["b", "c", "d"]))),
+    ]);
+    let mut iter = OrderedGuard::new(merge);
+    check_iter_result_by_key(&mut iter, entries(&["a", "b", "c", "d", "e"]));
+
+    let mut iter = OrderedGuard::new(MockIterator::new(entries(&["a", "b", "b", "c"])));
+    iter.allow_equal_keys();
+    drain(&mut iter);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "iterator out of order: key b\"b\"@0 after key b\"c\"@0")]
+fn test_ordered_guard_catches_broken_iterator() {
+    let mut iter = OrderedGuard::new(MockIterator::new(entries(&["a", "c", "b", "d"])));
+    drain(&mut iter);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "iterator out of order")]
+fn test_ordered_guard_rejects_equal_keys() {
+    let mut iter = OrderedGuard::new(MockIterator::new(entries(&["a", "b", "b", "c"])));
+    drain(&mut iter);
+}
@@ -0,0 +1,153 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::paginated_iterator::PaginatedIterator;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+use super::harness::MockIterator;
+
+fn mock_data(n: usize) -> Vec<(Bytes, Bytes)> {
+    (0..n)
+        .map(|i| {
+            (
+                Bytes::from(format!("key_{:03}", i)),
+                Bytes::from(format!("value_{:03}", i)),
+            )
+        })
+        .collect()
+}
+
+fn collect_keys<I>(iter: I) -> Vec<Bytes>
+where
+    I: StorageIterator + 'static,
+    for<'a> I::KeyType<'a>: crate::iterators::std_iterator::ToKeyBytes,
+{
+    iter.into_std_iter()
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap()
+}
+
+#[test]
+fn test_paginated_offset_beyond_end() {
+    let mut iter = PaginatedIterator::new(MockIterator::new(mock_data(5)), 10, 3).unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+
+    let iter = PaginatedIterator::new(MockIterator::new(mock_data(5)), 5, 3).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_paginated_zero_limit() {
+    let mut iter = PaginatedIterator::new(MockIterator::new(mock_data(5)), 2, 0).unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_paginated_stops_at_limit() {
+    let mut iter = PaginatedIterator::new(MockIterator::new(mock_data(5)), 1, 2).unwrap();
+    assert_eq!(iter.key().key_ref(), b"key_001");
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), b"key_002");
+    assert_eq!(iter.value(), b"value_002");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    // further calls keep it exhausted, without moving the underlying iterator
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+
+    // a limit larger than the rest of the iterator ends with it
+    let iter = PaginatedIterator::new(MockIterator::new(mock_data(5)), 3, 10).unwrap();
+    assert_eq!(
+        collect_keys(iter),
+        vec![Bytes::from("key_003"), Bytes::from("key_004")]
+    );
+}
+
+#[test]
+fn test_paginated_error_while_skipping() {
+    assert!(PaginatedIterator::new(MockIterator::new_with_error(mock_data(5), 2), 3, 1).is_err());
+}
+
+#[test]
+fn test_paginated_sst_across_blocks() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:03}", idx).as_bytes()),
+            format!("value_{:03}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 10);
+
+    let mut pages = Vec::new();
+    for page in 0..7 {
+        let iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        let keys = collect_keys(PaginatedIterator::new(iter, page * 15, 15).unwrap());
+        assert_eq!(keys.len(), if page < 6 { 15 } else { 10 });
+        pages.extend(keys);
+    }
+    let expected = (0..100)
+        .map(|idx| Bytes::from(format!("key_{:03}", idx)))
+        .collect::<Vec<_>>();
+    assert_eq!(pages, expected);
+}
+
+#[test]
+fn test_paginated_bounded_scan() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    for idx in 0..100 {
+        storage
+            .put(format!("key_{:03}", idx).as_bytes(), b"value")
+            .unwrap();
+    }
+    for idx in (0..100).step_by(3) {
+        storage
+            .delete(format!("key_{:03}", idx).as_bytes())
+            .unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+
+    // deleted keys do not count towards the offset or the limit
+    let scan = storage
+        .scan(Bound::Included(b"key_010"), Bound::Excluded(b"key_050"))
+        .unwrap();
+    let keys = collect_keys(PaginatedIterator::new(scan, 4, 5).unwrap());
+    let expected = [16, 17, 19, 20, 22]
+        .iter()
+        .map(|idx| Bytes::from(format!("key_{:03}", idx)))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    // the upper bound ends the last page
+    let scan = storage
+        .scan(Bound::Included(b"key_010"), Bound::Excluded(b"key_050"))
+        .unwrap();
+    let keys = collect_keys(PaginatedIterator::new(scan, 24, 5).unwrap());
+    assert_eq!(
+        keys,
+        vec![
+            Bytes::from("key_046"),
+            Bytes::from("key_047"),
+            Bytes::from("key_049")
+        ]
+    );
+}
@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.target_sst_size = 1 << 14;
+    options
+}
+
+fn wait_until(what: &str, mut cond: impl FnMut() -> bool) {
+    let begin = Instant::now();
+    while !cond() {
+        assert!(
+            begin.elapsed() < Duration::from_secs(10),
+            "timed out waiting for {}",
+            what
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn bulk_load(storage: &MiniLsm) {
+    for i in 0..2000 {
+        storage
+            .put(
+                format!("key_{:05}", i).as_bytes(),
+                format!("value_{:05}_{:0100}", i, 0).as_bytes(),
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_pause_and_resume_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.pause_background_compaction();
+    assert!(storage.is_background_compaction_paused());
+    bulk_load(&storage);
+
+    // flushes keep going while L0 accumulates
+    wait_until("flushes", || {
+        storage.inner.state.load().imm_memtables.len() < 2
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    {
+        let snapshot = storage.inner.state.load();
+        assert!(
+            snapshot.l0_sstables.len() >= 6,
+            "only {} L0 SSTs",
+            snapshot.l0_sstables.len()
+        );
+        assert!(snapshot.levels.iter().all(|(_, ssts)| ssts.is_empty()));
+    }
+    assert_eq!(storage.compaction_stats().num_compactions, 0);
+
+    storage.resume_background_compaction();
+    wait_until("the compaction backlog to drain", || {
+        storage.inner.state.load().l0_sstables.len() < 2
+    });
+    assert!(storage.compaction_stats().num_compactions > 0);
+    for i in (0..2000).step_by(97) {
+        assert!(storage
+            .get(format!("key_{:05}", i).as_bytes())
+            .unwrap()
+            .is_some());
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_close_while_compaction_paused() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.pause_background_compaction();
+    bulk_load(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert!(!storage.is_background_compaction_paused());
+    for i in (0..2000).step_by(97) {
+        assert!(storage
+            .get(format!("key_{:05}", i).as_bytes())
+            .unwrap()
+            .is_some());
+    }
+    storage.close().unwrap();
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::peekable_iterator::Peekable;
+use crate::iterators::StorageIterator;
+
+use super::harness::MockIterator;
+
+fn entries(keys: &[(&'static str, &'static str)]) -> Vec<(Bytes, Bytes)> {
+    keys.iter()
+        .map(|(key, value)| (Bytes::from(*key), Bytes::from(*value)))
+        .collect()
+}
+
+#[test]
+fn test_peek_between_advances() {
+    let merged = MergeIterator::create(vec![
+        Box::new(MockIterator::new(entries(&[
+            ("a", "1"),
+            ("c", "3"),
+            ("e", "5"),
+        ]))),
+        Box::new(MockIterator::new(entries(&[
+            ("b", "2"),
+            ("c", "old"),
+            ("d", "4"),
+        ]))),
+    ]);
+    let mut iter = Peekable::new(merged);
+    let mut seen = Vec::new();
+    while let Some((key, value)) = iter.peek() {
+        let entry = (
+            Bytes::copy_from_slice(key.key_ref()),
+            Bytes::copy_from_slice(value),
+        );
+        // peeking again, or through the iterator interface, sees the same entry
+        for _ in 0..3 {
+            assert_eq!(iter.peek_key().unwrap().key_ref(), entry.0);
+            assert_eq!(iter.peek_value().unwrap(), entry.1);
+        }
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), entry.0);
+        assert_eq!(iter.value(), entry.1);
+        seen.push(entry);
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        seen,
+        entries(&[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4"), ("e", "5")])
+    );
+    assert!(!iter.is_valid());
+    assert!(iter.peek().is_none());
+    // advancing at the end does nothing
+    iter.next().unwrap();
+    assert!(iter.peek_key().is_none());
+}
+
+#[test]
+fn test_peek_empty() {
+    let iter = Peekable::new(MergeIterator::<MockIterator>::create(Vec::new()));
+    assert!(!iter.is_valid());
+    assert!(iter.peek().is_none());
+    assert!(iter.peek_value().is_none());
+}
+
+#[test]
+fn test_peek_after_error() {
+    let merged = MergeIterator::create(vec![Box::new(MockIterator::new_with_error(
+        entries(&[("a", "1"), ("b", "2"), ("c", "3")]),
+        1,
+    ))]);
+    let mut iter = Peekable::new(merged);
+    assert_eq!(iter.peek_value(), Some(b"1".as_slice()));
+    assert!(iter.next().is_err());
+    assert!(!iter.is_valid());
+    assert!(iter.peek().is_none());
+    assert!(iter.next().is_err());
+}
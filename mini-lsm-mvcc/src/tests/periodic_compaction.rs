@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::clock::{Clock, MockClock};
+use crate::compact::{
+    CompactionEntryFilter, CompactionOptions, CompactionTask, FilterDecision,
+    SimpleLeveledCompactionOptions,
+};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+/// Values are the time they expire at, in seconds since the Unix epoch.
+struct ExpireFilter {
+    clock: Arc<MockClock>,
+}
+
+impl CompactionEntryFilter for ExpireFilter {
+    fn name(&self) -> &str {
+        "expire"
+    }
+
+    fn filter(&self, _level: usize, _key: &[u8], value: &[u8]) -> FilterDecision {
+        let expire_at = std::str::from_utf8(value).unwrap().parse::<u64>().unwrap();
+        if expire_at < self.clock.now_secs() {
+            FilterDecision::Remove
+        } else {
+            FilterDecision::Keep
+        }
+    }
+}
+
+fn open(path: &std::path::Path, clock: Arc<MockClock>) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.periodic_compaction_seconds = DAY.as_secs();
+    options.compaction_entry_filter = Some(Arc::new(ExpireFilter {
+        clock: clock.clone(),
+    }));
+    options.clock = clock;
+    Arc::new(LsmStorageInner::open(path, options).unwrap())
+}
+
+#[test]
+fn test_periodic_compaction() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let storage = open(dir.path(), clock.clone());
+    let expire_at = clock.now_secs() + DAY.as_secs() / 2;
+    for i in 0..2 {
+        storage
+            .put(
+                format!("expiring_{}", i).as_bytes(),
+                expire_at.to_string().as_bytes(),
+            )
+            .unwrap();
+        storage
+            .put(format!("kept_{}", i).as_bytes(), b"99999999999")
+            .unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    // L0 -> L1 -> L2, after which the size-based triggers have nothing to do
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    let old_sst_id = {
+        let state = storage.state.load();
+        assert!(state.levels[0].1.is_empty());
+        assert_eq!(state.levels[1].1.len(), 1);
+        state.levels[1].1[0]
+    };
+    assert_eq!(
+        storage.get(b"expiring_0").unwrap().unwrap(),
+        expire_at.to_string().as_bytes()
+    );
+
+    // the data has expired, but nothing rewrites the bottom level until the SST is old enough
+    clock.advance(DAY / 2 + Duration::from_secs(1));
+    assert!(storage.plan_compaction().is_none());
+    clock.advance(DAY / 2);
+    let plan = storage.plan_compaction().unwrap();
+    assert!(
+        matches!(
+            plan.task,
+            CompactionTask::Periodic {
+                level: 2,
+                sst_id,
+                is_bottom_level: true,
+            } if sst_id == old_sst_id
+        ),
+        "{:?}",
+        plan.task
+    );
+    assert!(
+        plan.reason.contains("periodic compaction"),
+        "{}",
+        plan.reason
+    );
+    storage.trigger_compaction().unwrap();
+
+    let new_sst_id = {
+        let state = storage.state.load();
+        assert!(state.levels[0].1.is_empty());
+        assert_eq!(state.levels[1].1.len(), 1);
+        let new_sst_id = state.levels[1].1[0];
+        assert_ne!(new_sst_id, old_sst_id);
+        let properties = state.sstables[&new_sst_id].properties();
+        assert_eq!(properties.creation_time, clock.now_secs());
+        // expired entries were dropped by the filter at the bottom level
+        assert_eq!(properties.num_entries, 2);
+        new_sst_id
+    };
+    assert!(storage.plan_compaction().is_none());
+    assert!(storage.get(b"expiring_0").unwrap().is_none());
+    assert!(storage.get(b"kept_1").unwrap().is_some());
+    drop(storage);
+
+    // the rewrite is recovered from the manifest
+    let storage = open(dir.path(), clock);
+    assert_eq!(storage.state.load().levels[1].1, vec![new_sst_id]);
+    assert!(storage.get(b"expiring_1").unwrap().is_none());
+    assert!(storage.get(b"kept_0").unwrap().is_some());
+}
+
+#[test]
+fn test_periodic_compaction_disabled() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.clock = clock.clone();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for _ in 0..2 {
+        storage.put(b"key", b"value").unwrap();
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    while storage.plan_compaction().is_some() {
+        storage.trigger_compaction().unwrap();
+    }
+    clock.advance(DAY * 365);
+    assert!(storage.plan_compaction().is_none());
+}
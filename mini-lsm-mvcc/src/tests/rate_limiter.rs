@@ -0,0 +1,130 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+    rate_limiter::{IoPriority, RateLimiter},
+};
+
+use super::harness::sync;
+
+#[test]
+fn test_rate_limiter_throughput() {
+    let limiter = RateLimiter::new(100 << 10);
+    let begin = Instant::now();
+    // drain the initial bucket, then 50KB at 100KB/s should take about 0.5s
+    limiter.request(10 << 10, IoPriority::Low);
+    for _ in 0..50 {
+        limiter.request(1 << 10, IoPriority::Low);
+    }
+    let elapsed = begin.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(limiter.throttled_time(IoPriority::Low) >= Duration::from_millis(300));
+    assert_eq!(limiter.throttled_time(IoPriority::High), Duration::ZERO);
+    assert_eq!(limiter.requested_bytes(IoPriority::Low), 60 << 10);
+    let throttled_requests = limiter.throttled_requests(IoPriority::Low);
+    assert!(throttled_requests > 0);
+
+    // disabling the limiter at runtime takes effect immediately
+    limiter.set_bytes_per_sec(0);
+    for _ in 0..1000 {
+        limiter.request(1 << 20, IoPriority::Low);
+    }
+    assert_eq!(
+        limiter.throttled_requests(IoPriority::Low),
+        throttled_requests
+    );
+    assert_eq!(
+        limiter.requested_bytes(IoPriority::Low),
+        (60 << 10) + 1000 * (1 << 20)
+    );
+}
+
+#[test]
+fn test_rate_limiter_high_priority_first() {
+    let limiter = Arc::new(RateLimiter::new(100 << 10));
+    limiter.request(100 << 10, IoPriority::Low);
+    let low = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.request(1, IoPriority::Low);
+            Instant::now()
+        })
+    };
+    std::thread::sleep(Duration::from_millis(10));
+    limiter.request(1, IoPriority::High);
+    let high_done = Instant::now();
+    assert!(low.join().unwrap() >= high_done);
+}
+
+fn populate(storage: &Arc<LsmStorageInner>) {
+    for round in 0..2 {
+        for i in 0..1000 {
+            storage
+                .put(
+                    format!("key_{:05}", i).as_bytes(),
+                    format!("value_{:05}_{:0100}", i, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(storage);
+    }
+}
+
+#[test]
+fn test_rate_limited_compaction() {
+    let dir1 = tempdir().unwrap();
+    let dir2 = tempdir().unwrap();
+    let unlimited = Arc::new(
+        LsmStorageInner::open(dir1.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let limited = Arc::new(
+        LsmStorageInner::open(dir2.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    populate(&unlimited);
+    populate(&limited);
+    // the foreground reads below are served from the block cache in both storages, so that both
+    // compactions read the same blocks from the disk
+    assert!(unlimited.get(b"key_00000").unwrap().is_some());
+    assert!(limited.get(b"key_00000").unwrap().is_some());
+
+    unlimited.force_full_compaction().unwrap();
+    let compaction_bytes = unlimited.rate_limiter.requested_bytes(IoPriority::Low);
+    assert!(compaction_bytes > 0);
+    assert_eq!(
+        unlimited.rate_limiter.throttled_requests(IoPriority::Low),
+        0
+    );
+
+    // the compaction reads about 240KB and writes about 120KB, so 200KB/s throttles it
+    let flush_bytes = limited.rate_limiter.requested_bytes(IoPriority::High);
+    limited.set_rate_limit(200 << 10);
+    let compaction = {
+        let limited = limited.clone();
+        std::thread::spawn(move || limited.force_full_compaction().unwrap())
+    };
+
+    // foreground operations never go through the limiter
+    for i in 0..1000 {
+        limited
+            .put(format!("fg_{:05}", i).as_bytes(), b"value")
+            .unwrap();
+        assert!(limited.get(b"key_00000").unwrap().is_some());
+    }
+
+    compaction.join().unwrap();
+    assert!(limited.rate_limiter.throttled_requests(IoPriority::Low) > 0);
+    assert!(limited.throttled_time(IoPriority::Low) > Duration::ZERO);
+    assert_eq!(
+        limited.rate_limiter.requested_bytes(IoPriority::Low),
+        compaction_bytes
+    );
+    assert_eq!(
+        limited.rate_limiter.requested_bytes(IoPriority::High),
+        flush_bytes
+    );
+}
@@ -0,0 +1,206 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::read_only::{FileRemovedError, ReadOnlyError};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn list_dir(path: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// The user keys visible to the storage, in order.
+fn scan_keys(storage: &MiniLsm) -> Vec<Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+/// Refreshes the reader until no file is removed in the meantime.
+fn refresh(reader: &MiniLsm) {
+    loop {
+        match reader.refresh() {
+            Ok(()) => return,
+            Err(e) if e.is::<FileRemovedError>() => continue,
+            Err(e) => panic!("refresh failed: {:?}", e),
+        }
+    }
+}
+
+#[test]
+fn test_read_only_rejects_writes() {
+    let dir = tempdir().unwrap();
+    let writer = MiniLsm::open(&dir, options()).unwrap();
+    writer.put(b"a", b"1").unwrap();
+    writer.force_flush().unwrap();
+    writer.put(b"b", b"2").unwrap();
+    writer.sync().unwrap();
+    let files = list_dir(dir.path());
+
+    let reader = MiniLsm::open_read_only(&dir, options()).unwrap();
+    assert!(reader.is_read_only());
+    assert!(!writer.is_read_only());
+    // the memtable of the writer is replayed from its WAL
+    assert_eq!(reader.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(reader.get(b"b").unwrap(), Some(Bytes::from("2")));
+
+    let errors = [
+        reader.put(b"c", b"3").unwrap_err(),
+        reader.delete(b"a").unwrap_err(),
+        reader
+            .write_batch(&[WriteBatchRecord::Put(b"c", b"3")])
+            .unwrap_err(),
+        reader.delete_range(b"a", b"z").unwrap_err(),
+        reader
+            .compare_and_swap(b"a", Some(b"1".as_slice()), Some(b"2".as_slice()))
+            .unwrap_err(),
+        reader.force_flush().unwrap_err(),
+        reader.force_full_compaction().unwrap_err(),
+    ];
+    for error in errors {
+        assert_eq!(error.downcast_ref::<ReadOnlyError>(), Some(&ReadOnlyError));
+    }
+    let txn = reader.new_txn().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from("1")));
+    txn.put(b"c", b"3");
+    assert!(txn.commit().unwrap_err().is::<ReadOnlyError>());
+    assert!(writer.refresh().is_err());
+
+    reader.close().unwrap();
+    drop(reader);
+    assert_eq!(list_dir(dir.path()), files);
+    assert_eq!(writer.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn test_read_only_refresh() {
+    let dir = tempdir().unwrap();
+    let writer = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        writer.put(&key_of(idx), b"old").unwrap();
+    }
+    writer.force_flush().unwrap();
+    let reader = MiniLsm::open_read_only(&dir, options()).unwrap();
+
+    for idx in 0..100 {
+        writer.put(&key_of(idx), b"new").unwrap();
+    }
+    writer.force_flush().unwrap();
+    writer.put(&key_of(100), b"new").unwrap();
+    writer.sync().unwrap();
+    assert_eq!(reader.get(&key_of(0)).unwrap(), Some(Bytes::from("old")));
+    assert_eq!(reader.get(&key_of(100)).unwrap(), None);
+
+    refresh(&reader);
+    assert_eq!(reader.get(&key_of(0)).unwrap(), Some(Bytes::from("new")));
+    assert_eq!(reader.get(&key_of(100)).unwrap(), Some(Bytes::from("new")));
+
+    // an iterator keeps reading the SSTs compacted away by the writer
+    let mut iter = reader.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    writer.force_full_compaction().unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.value(), b"new");
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 101);
+    drop(iter);
+
+    refresh(&reader);
+    assert_eq!(scan_keys(&reader).len(), 101);
+    assert_eq!(
+        reader.inner.state.load().sstables.len(),
+        writer.inner.state.load().sstables.len()
+    );
+}
+
+#[test]
+fn test_read_only_open_missing_file() {
+    let dir = tempdir().unwrap();
+    let writer = MiniLsm::open(&dir, options()).unwrap();
+    writer.put(b"a", b"1").unwrap();
+    writer.force_flush().unwrap();
+    let sst_id = writer.inner.state.load().l0_sstables[0];
+    writer.close().unwrap();
+    drop(writer);
+
+    // as if the writer compacted the SST away right after the manifest was read
+    std::fs::remove_file(dir.path().join(format!("{:05}.sst", sst_id))).unwrap();
+    let error = MiniLsm::open_read_only(&dir, options()).err().unwrap();
+    assert!(error.is::<FileRemovedError>());
+}
+
+#[test]
+fn test_read_only_concurrent_with_writer() {
+    const NUM_KEYS: usize = 3000;
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.target_sst_size = 1 << 12;
+    let writer = MiniLsm::open(&dir, options.clone()).unwrap();
+    writer.put(&key_of(0), b"value").unwrap();
+    writer.sync().unwrap();
+    let reader = MiniLsm::open_read_only(&dir, options).unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let writer_thread = {
+        let writer = writer.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            for idx in 1..NUM_KEYS {
+                writer.put(&key_of(idx), b"value").unwrap();
+                if idx % 200 == 0 {
+                    writer.force_flush().unwrap();
+                }
+                if idx % 1000 == 0 {
+                    writer.force_full_compaction().unwrap();
+                }
+            }
+            writer.sync().unwrap();
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+
+    // the writes are sequential, so the reader always sees the keys up to some point
+    let mut seen = 0;
+    loop {
+        let finished = done.load(Ordering::SeqCst);
+        refresh(&reader);
+        let keys = scan_keys(&reader);
+        assert!(keys.len() >= seen);
+        for (idx, key) in keys.iter().enumerate() {
+            assert_eq!(key, &key_of(idx));
+        }
+        seen = keys.len();
+        if finished {
+            break;
+        }
+    }
+    writer_thread.join().unwrap();
+    assert_eq!(seen, NUM_KEYS);
+}
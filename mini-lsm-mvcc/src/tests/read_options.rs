@@ -0,0 +1,158 @@
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::{self, KeySlice};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, ReadOptions};
+use crate::table::SsTable;
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:04}", i).into_bytes()
+}
+
+fn value_of(i: usize) -> Vec<u8> {
+    format!("value_{:04}", i).into_bytes()
+}
+
+/// Overwrites the bytes at `offset` of the file in place.
+fn patch_file(path: &Path, offset: usize, bytes: &[u8]) {
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset as u64)).unwrap();
+    file.write_all(bytes).unwrap();
+    file.sync_all().unwrap();
+}
+
+/// Capitalizes the value of the `i`-th key in the file.
+fn corrupt_value(path: &Path, i: usize) {
+    let data = std::fs::read(path).unwrap();
+    let value = value_of(i);
+    let offset = data
+        .windows(value.len())
+        .position(|window| window == value)
+        .unwrap();
+    patch_file(path, offset, b"V");
+}
+
+/// Whether the block holding the only version of the `i`-th key is cached.
+fn is_cached(storage: &LsmStorageInner, table: &SsTable, i: usize) -> bool {
+    let block_idx = table
+        .find_block_idx(KeySlice::from_slice(&key_of(i), key::TS_RANGE_END))
+        .unwrap();
+    storage
+        .block_cache
+        .contains_key(&(0, table.sst_id(), block_idx))
+}
+
+fn scan_values(storage: &LsmStorageInner, options: &ReadOptions) -> Result<Vec<Bytes>> {
+    let (lower, upper) = (key_of(110), key_of(130));
+    let mut iter =
+        storage.scan_with_options(Bound::Included(&lower), Bound::Excluded(&upper), options)?;
+    let mut values = Vec::new();
+    while iter.is_valid() {
+        values.push(Bytes::copy_from_slice(iter.value()));
+        iter.next()?;
+    }
+    Ok(values)
+}
+
+#[test]
+fn test_read_options() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..200 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    let table = {
+        let state = storage.state.load();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    let path = storage.path_of_sst(table.sst_id());
+    assert!(table.num_of_blocks() > 10);
+
+    let no_fill = ReadOptions {
+        fill_cache: false,
+        ..Default::default()
+    };
+    let no_verify = ReadOptions {
+        verify_checksums: false,
+        fill_cache: false,
+        ..Default::default()
+    };
+
+    // reads at an older timestamp
+    let read_ts = storage.mvcc().latest_commit_ts();
+    storage.put(&key_of(50), b"newer").unwrap();
+    let at_read_ts = ReadOptions {
+        read_ts: Some(read_ts),
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(50), &at_read_ts).unwrap(),
+        Some(Bytes::from(value_of(50)))
+    );
+    assert_eq!(
+        storage
+            .get_with_options(&key_of(50), &ReadOptions::default())
+            .unwrap(),
+        Some(Bytes::from("newer"))
+    );
+
+    // only filling reads insert their blocks into the cache
+    assert!(!is_cached(&storage, &table, 150));
+    assert_eq!(
+        storage.get_with_options(&key_of(150), &no_fill).unwrap(),
+        Some(Bytes::from(value_of(150)))
+    );
+    assert!(!is_cached(&storage, &table, 150));
+    storage
+        .get_with_options(&key_of(150), &ReadOptions::default())
+        .unwrap();
+    assert!(is_cached(&storage, &table, 150));
+
+    // a corrupted block is rejected when verifying checksums, and returned as it is otherwise
+    assert!(!is_cached(&storage, &table, 120));
+    corrupt_value(&path, 120);
+    let err = storage
+        .get_with_options(&key_of(120), &no_fill)
+        .unwrap_err();
+    assert!(err.to_string().contains("checksum"), "{}", err);
+    assert!(storage.get(&key_of(120)).is_err());
+    assert!(!is_cached(&storage, &table, 120));
+    assert_eq!(
+        storage.get_with_options(&key_of(120), &no_verify).unwrap(),
+        Some(Bytes::from("Value_0120"))
+    );
+    assert!(scan_values(&storage, &ReadOptions::default()).is_err());
+    let values = scan_values(&storage, &no_verify).unwrap();
+    assert_eq!(values.len(), 20);
+    assert!(values.contains(&Bytes::from("Value_0120")));
+
+    // blocks in the cache were verified when they were read and are not checked again
+    corrupt_value(&path, 150);
+    assert_eq!(
+        storage
+            .get_with_options(&key_of(150), &ReadOptions::default())
+            .unwrap(),
+        Some(Bytes::from(value_of(150)))
+    );
+
+    // a block whose layout is corrupted fails to decode even without verifying checksums
+    assert!(!is_cached(&storage, &table, 0));
+    let num_entries_offset = table.block_meta[1].offset - 4 - 2;
+    patch_file(&path, num_entries_offset, &[0xff, 0xff]);
+    assert!(storage.get_with_options(&key_of(0), &no_fill).is_err());
+    assert!(storage.get_with_options(&key_of(0), &no_verify).is_err());
+}
@@ -0,0 +1,117 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::{BLOCK_FORMAT_V2, BLOCK_FORMAT_V6, ENTRY_FLAG_TOMBSTONE};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{
+    ChecksumType, FileObject, SsTable, SsTableBuilder, SsTableIterator, TableProperties,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+/// Builds a table of several blocks with two versions of every tenth key, the older one a
+/// tombstone.
+fn build(path: &Path, format_version: u8, checksum_type: ChecksumType) -> SsTable {
+    let mut builder = SsTableBuilder::new_with_format_version(128, format_version);
+    builder.set_checksum_type(checksum_type);
+    for idx in 0..200 {
+        let key = key_of(idx);
+        builder.add(KeySlice::from_slice(&key, 2), &value_of(idx));
+        if idx % 10 == 0 {
+            builder.add_with_flags(KeySlice::from_slice(&key, 1), b"", ENTRY_FLAG_TOMBSTONE);
+        }
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+/// Zeroes everything after the data blocks of the table at `path`.
+fn zero_meta(path: &Path, table: &SsTable) {
+    let mut data = std::fs::read(path).unwrap();
+    data[table.block_meta_offset..].fill(0);
+    std::fs::write(path, &data).unwrap();
+}
+
+fn entries(table: SsTable) -> Vec<(Vec<u8>, u64, Vec<u8>, bool)> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(table)).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value().to_vec(),
+            iter.is_tombstone(),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+fn check_rebuilt(format_version: u8, checksum_type: ChecksumType) {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let table = build(&path, format_version, checksum_type);
+    assert!(table.num_of_blocks() > 10);
+    let expected = entries(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(expected.len(), 220);
+
+    zero_meta(&path, &table);
+    assert!(SsTable::open_for_test(FileObject::open(&path).unwrap()).is_err());
+    let rebuilt = SsTable::rebuild_index(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(rebuilt.block_metas(), table.block_metas());
+    assert_eq!(rebuilt.block_meta_offset, table.block_meta_offset);
+    assert_eq!(rebuilt.first_key(), table.first_key());
+    assert_eq!(rebuilt.last_key(), table.last_key());
+    assert_eq!(rebuilt.max_ts(), 2);
+    assert_eq!(
+        *rebuilt.properties(),
+        TableProperties {
+            creation_time: 0,
+            num_bloom_bits: rebuilt.properties().num_bloom_bits,
+            ..*table.properties()
+        }
+    );
+    assert_eq!(rebuilt.properties().format_version, format_version);
+    assert_eq!(rebuilt.properties().checksum_type, checksum_type);
+    for idx in 0..200 {
+        assert!(rebuilt.may_contain_key(&key_of(idx)));
+    }
+    assert_eq!(entries(rebuilt), expected);
+}
+
+#[test]
+fn test_rebuild_index_recovers_all_entries() {
+    check_rebuilt(BLOCK_FORMAT_V2, ChecksumType::Crc32);
+}
+
+#[test]
+fn test_rebuild_index_detects_format_and_checksum_type() {
+    check_rebuilt(BLOCK_FORMAT_V6, ChecksumType::Crc64);
+}
+
+#[test]
+fn test_rebuild_index_stops_at_corrupt_block() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let table = build(&path, BLOCK_FORMAT_V2, ChecksumType::Crc32);
+    zero_meta(&path, &table);
+    let mut data = std::fs::read(&path).unwrap();
+    data[table.block_metas()[3].offset + 5] ^= 0x01;
+    std::fs::write(&path, &data).unwrap();
+    let rebuilt = SsTable::rebuild_index(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(rebuilt.block_metas(), &table.block_metas()[..3]);
+    assert_eq!(rebuilt.block_meta_offset, table.block_metas()[3].offset);
+
+    // nothing to recover without the first block
+    data[5] ^= 0x01;
+    std::fs::write(&path, &data).unwrap();
+    assert!(SsTable::rebuild_index(FileObject::open(&path).unwrap()).is_err());
+}
@@ -0,0 +1,84 @@
+use tempfile::tempdir;
+
+use crate::backup::CheckpointManifest;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Writes a backup of a storage holding `key_of(0..300)` to `path`, and returns its SSTs.
+fn write_backup(path: &std::path::Path) -> Vec<usize> {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    for idx in 0..300 {
+        storage.put(&key_of(idx), &key_of(idx)).unwrap();
+        if idx % 100 == 99 {
+            storage.force_flush().unwrap();
+        }
+    }
+    let plan = storage
+        .backup_delta(&CheckpointManifest::default())
+        .unwrap();
+    let ids = plan.checkpoint.ssts.keys().copied().collect();
+    MiniLsm::apply_backup_delta(dir.path().join("none"), plan, path).unwrap();
+    storage.close().unwrap();
+    ids
+}
+
+#[test]
+fn test_restore() {
+    let dir = tempdir().unwrap();
+    let backup = dir.path().join("backup");
+    write_backup(&backup);
+
+    let path = dir.path().join("db");
+    let storage = MiniLsm::restore(&backup, &path, options()).unwrap();
+    assert!(!path.join("RESTORE_IN_PROGRESS").exists());
+    for idx in 0..300 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap().as_deref(),
+            Some(&key_of(idx)[..])
+        );
+    }
+    storage.put(b"key", b"value").unwrap();
+    storage.close().unwrap();
+
+    // the target must be empty
+    assert!(MiniLsm::restore(&backup, &path, options()).is_err());
+    let storage = MiniLsm::open(&path, options()).unwrap();
+    assert_eq!(storage.get(b"key").unwrap().as_deref(), Some(&b"value"[..]));
+}
+
+#[test]
+fn test_restore_corrupt_sst() {
+    let dir = tempdir().unwrap();
+    let backup = dir.path().join("backup");
+    let ids = write_backup(&backup);
+
+    // flip a byte in the first block of an SST
+    let sst_path = LsmStorageInner::path_of_sst_static(&backup, ids[1]);
+    let mut data = std::fs::read(&sst_path).unwrap();
+    data[0] ^= 0xff;
+    std::fs::write(&sst_path, data).unwrap();
+
+    let path = dir.path().join("db");
+    let Err(err) = MiniLsm::restore(&backup, &path, options()) else {
+        panic!("restored a backup with a corrupt SST");
+    };
+    let file_name = sst_path.file_name().unwrap().to_str().unwrap();
+    assert!(format!("{:#}", err).contains(file_name), "{:#}", err);
+
+    // the unfinished restore is never opened
+    assert!(path.join("RESTORE_IN_PROGRESS").exists());
+    assert!(MiniLsm::open(&path, options()).is_err());
+    assert!(MiniLsm::open_read_only(&path, options()).is_err());
+    MiniLsm::destroy(&path).unwrap();
+}
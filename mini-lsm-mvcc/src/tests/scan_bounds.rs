@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mvcc::txn::Transaction;
+
+fn key_of(i: usize) -> Bytes {
+    Bytes::from(format!("key_{:03}", i))
+}
+
+/// The bounds to try around the keys `0..=max`, at existing keys and between them.
+fn bounds(max: usize) -> Vec<Bound<Bytes>> {
+    let mut bounds = vec![Bound::Unbounded];
+    for i in 0..=max {
+        bounds.push(Bound::Included(key_of(i)));
+        bounds.push(Bound::Excluded(key_of(i)));
+    }
+    bounds
+}
+
+fn in_range(key: &Bytes, lower: &Bound<Bytes>, upper: &Bound<Bytes>) -> bool {
+    let above_lower = match lower {
+        Bound::Included(lower) => key >= lower,
+        Bound::Excluded(lower) => key > lower,
+        Bound::Unbounded => true,
+    };
+    let below_upper = match upper {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true,
+    };
+    above_lower && below_upper
+}
+
+fn collect(
+    txn: &Arc<Transaction>,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> Vec<(Bytes, Bytes)> {
+    let mut iter = txn.scan(lower, upper).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+/// Scans every combination of bounds and compares the result with the expected entries.
+fn check_all_bounds(txn: &Arc<Transaction>, expected: &BTreeMap<Bytes, Bytes>, max: usize) {
+    let bounds = bounds(max);
+    for lower in &bounds {
+        for upper in &bounds {
+            let expected = expected
+                .iter()
+                .filter(|(key, _)| in_range(key, lower, upper))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                collect(
+                    txn,
+                    lower.as_ref().map(|key| key.as_ref()),
+                    upper.as_ref().map(|key| key.as_ref())
+                ),
+                expected,
+                "scan({:?}, {:?})",
+                lower,
+                upper
+            );
+        }
+    }
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_scan_bounds_across_layers() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let mut expected = BTreeMap::new();
+    let mut snapshots = Vec::new();
+
+    // even keys up to 20, several versions each, spread over a level, L0, an immutable memtable
+    // and the memtable, so that bounds fall on keys and between them in every layer
+    for round in 0..4 {
+        for i in (round..=20).step_by(2 + round) {
+            let key = key_of(i - i % 2);
+            let value = Bytes::from(format!("value_{}_{}", i, round));
+            storage.put(&key, &value).unwrap();
+            expected.insert(key, value);
+        }
+        if round == 1 {
+            storage.delete(&key_of(6)).unwrap();
+            expected.remove(&key_of(6));
+        }
+        snapshots.push((storage.new_txn().unwrap(), expected.clone()));
+        match round {
+            0 => {
+                flush(&storage);
+                storage.force_full_compaction().unwrap();
+            }
+            1 => flush(&storage),
+            2 => storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap(),
+            _ => {}
+        }
+    }
+    let state = storage.state.load_full();
+    assert!(!state.levels[0].1.is_empty());
+    assert!(!state.l0_sstables.is_empty());
+    assert!(!state.imm_memtables.is_empty());
+
+    // every snapshot sees the latest version below its read timestamp within the bounds
+    for (txn, expected) in &snapshots {
+        check_all_bounds(txn, expected, 22);
+    }
+
+    // the local writes of a transaction are bounded the same way
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(3), b"local");
+    txn.delete(&key_of(4));
+    expected.insert(key_of(3), Bytes::from("local"));
+    expected.remove(&key_of(4));
+    check_all_bounds(&txn, &expected, 22);
+}
+
+#[test]
+fn test_scan_empty_ranges() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..10 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    flush(&storage);
+    for i in 0..10 {
+        storage.put(&key_of(i), b"value").unwrap();
+    }
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(5), b"local");
+    let (k3, k5) = (key_of(3), key_of(5));
+    for (lower, upper) in [
+        (Bound::Included(k5.as_ref()), Bound::Included(k3.as_ref())),
+        (Bound::Excluded(k5.as_ref()), Bound::Excluded(k3.as_ref())),
+        (Bound::Included(k5.as_ref()), Bound::Excluded(k5.as_ref())),
+        (Bound::Excluded(k5.as_ref()), Bound::Included(k5.as_ref())),
+        (Bound::Excluded(k5.as_ref()), Bound::Excluded(k5.as_ref())),
+        (
+            Bound::Excluded(b"key_005".as_ref()),
+            Bound::Excluded(b"key_0050".as_ref()),
+        ),
+    ] {
+        assert!(
+            !txn.scan(lower, upper).unwrap().is_valid(),
+            "scan({:?}, {:?})",
+            lower,
+            upper
+        );
+        assert!(!storage.scan(lower, upper).unwrap().is_valid());
+    }
+    assert_eq!(
+        collect(
+            &txn,
+            Bound::Included(k5.as_ref()),
+            Bound::Included(k5.as_ref())
+        ),
+        vec![(k5, Bytes::from("local"))]
+    );
+}
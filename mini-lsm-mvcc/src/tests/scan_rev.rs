@@ -0,0 +1,184 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:04}", i).into_bytes()
+}
+
+fn collect<I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>>(mut iter: I) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+fn random_bound<'a>(rng: &mut StdRng, key: &'a [u8]) -> Bound<&'a [u8]> {
+    match rng.gen_range(0..3) {
+        0 => Bound::Included(key),
+        1 => Bound::Excluded(key),
+        _ => Bound::Unbounded,
+    }
+}
+
+#[test]
+fn test_scan_rev_matches_scan() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut snapshots = Vec::new();
+    for round in 0..10 {
+        for _ in 0..200 {
+            let key = key_of(rng.gen_range(0..500));
+            if rng.gen_bool(0.2) {
+                storage.delete(&key).unwrap();
+            } else {
+                storage
+                    .put(&key, format!("value_{}", round).as_bytes())
+                    .unwrap();
+            }
+        }
+        if rng.gen_bool(0.3) {
+            let start = rng.gen_range(0..500);
+            storage
+                .delete_range(&key_of(start), &key_of(start + rng.gen_range(1..30)))
+                .unwrap();
+        }
+        if round == 4 {
+            storage.force_full_compaction().unwrap();
+        }
+        // spread the rounds over the memtable, immutable memtables, L0 and a level
+        if round < 8 {
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            if round % 3 != 2 {
+                storage.force_flush_next_imm_memtable().unwrap();
+            }
+        }
+        snapshots.push(storage.new_txn().unwrap());
+    }
+    let state = storage.state.load_full();
+    assert!(!state.levels[0].1.is_empty());
+    assert!(!state.l0_sstables.is_empty());
+    assert!(!state.imm_memtables.is_empty());
+
+    for txn in &snapshots {
+        for _ in 0..30 {
+            let (a, b) = (key_of(rng.gen_range(0..520)), key_of(rng.gen_range(0..520)));
+            let (lower, upper) = if a <= b { (a, b) } else { (b, a) };
+            let lower = random_bound(&mut rng, &lower);
+            let upper = random_bound(&mut rng, &upper);
+            let mut expected = collect(txn.scan(lower, upper).unwrap());
+            expected.reverse();
+            assert_eq!(
+                collect(txn.scan_rev(lower, upper).unwrap()),
+                expected,
+                "scan_rev({:?}, {:?})",
+                lower,
+                upper
+            );
+        }
+    }
+
+    // the local writes of a transaction are merged in descending order too
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(100), b"local");
+    txn.put(&key_of(1000), b"local");
+    txn.delete(&key_of(101));
+    let mut expected = collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+    expected.reverse();
+    assert_eq!(
+        collect(txn.scan_rev(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        expected
+    );
+    assert_eq!(
+        expected[0],
+        (Bytes::from(key_of(1000)), Bytes::from("local"))
+    );
+
+    // empty ranges
+    let (k1, k2) = (key_of(200), key_of(100));
+    assert!(!storage
+        .scan_rev(Bound::Included(&k1), Bound::Included(&k2))
+        .unwrap()
+        .is_valid());
+    assert!(!storage
+        .scan_rev(Bound::Excluded(&k1), Bound::Included(&k1))
+        .unwrap()
+        .is_valid());
+}
+
+#[test]
+fn test_sst_iterator_seek_rev() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    // even keys with two versions each
+    for i in (0..200).step_by(2) {
+        for ts in [2, 1] {
+            builder.add(KeySlice::from_slice(&key_of(i), ts), b"value");
+        }
+    }
+    let table = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(table.num_of_blocks() > 4);
+    let keys = |mut iter: SsTableIterator| {
+        let mut keys = Vec::new();
+        while iter.is_valid() {
+            keys.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+            iter.next().unwrap();
+        }
+        keys
+    };
+    let all = (0..200)
+        .step_by(2)
+        .flat_map(|i| [(key_of(i), 2), (key_of(i), 1)])
+        .rev()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys(SsTableIterator::create_and_seek_to_last(table.clone()).unwrap()),
+        all
+    );
+    for (i, ts) in [
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (1, 0),
+        (50, 1),
+        (50, 2),
+        (51, 5),
+        (198, 0),
+        (300, 0),
+    ] {
+        let seek_key = (key_of(i), ts);
+        let expected = all
+            .iter()
+            .filter(|(key, key_ts)| {
+                KeySlice::from_slice(key, *key_ts) <= KeySlice::from_slice(&seek_key.0, ts)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let iter = SsTableIterator::create_and_seek_to_key_rev(
+            table.clone(),
+            KeySlice::from_slice(&seek_key.0, ts),
+        )
+        .unwrap();
+        assert_eq!(keys(iter), expected, "{:?}", seek_key);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+fn builder() -> SsTableBuilder {
+    let mut builder = SsTableBuilder::new(128);
+    builder.enable_tombstone_bitmap();
+    for i in 0..200 {
+        let key = format!("key_{:05}", i);
+        let value = if i % 7 == 3 {
+            String::new()
+        } else {
+            format!("value_{}", i)
+        };
+        builder.add(KeySlice::from_slice(key.as_bytes(), 1), value.as_bytes());
+    }
+    builder
+}
+
+fn entries(sst: Arc<SsTable>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().key_ref().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_separate_index_round_trip() {
+    let dir = tempdir().unwrap();
+    let single = builder().build(1, None, dir.path().join("1.sst")).unwrap();
+    let split = builder()
+        .build_with_separate_index(
+            2,
+            None,
+            dir.path().join("2.sst"),
+            dir.path().join("2.index"),
+        )
+        .unwrap();
+    assert!(split.num_of_blocks() > 1);
+    assert_eq!(split.table_size(), single.table_size());
+    // the data file holds the blocks only
+    let data = std::fs::read(dir.path().join("2.sst")).unwrap();
+    let index = std::fs::read(dir.path().join("2.index")).unwrap();
+    let single_data = std::fs::read(dir.path().join("1.sst")).unwrap();
+    assert_eq!(data.len(), single.block_meta_offset);
+    assert_eq!([data, index].concat(), single_data);
+
+    let opened = SsTable::open_with_index(
+        2,
+        None,
+        FileObject::open(&dir.path().join("2.sst")).unwrap(),
+        FileObject::open(&dir.path().join("2.index")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(opened.first_key(), single.first_key());
+    assert_eq!(opened.last_key(), single.last_key());
+    assert_eq!(opened.properties(), single.properties());
+    assert!(opened.tombstone_bitmap().unwrap().is_tombstone(3));
+    let expected = entries(Arc::new(single));
+    assert_eq!(entries(Arc::new(split)), expected);
+    let opened = Arc::new(opened);
+    assert_eq!(entries(opened.clone()), expected);
+
+    // a copy of a split table is a single-file table
+    let mut copy = Vec::new();
+    opened.copy_to(&mut copy).unwrap();
+    assert_eq!(copy, single_data);
+}
+
+#[test]
+fn test_separate_index_mismatched_data_file() {
+    let dir = tempdir().unwrap();
+    builder()
+        .build_with_separate_index(
+            1,
+            None,
+            dir.path().join("1.sst"),
+            dir.path().join("1.index"),
+        )
+        .unwrap();
+    let mut other = SsTableBuilder::new(128);
+    other.add(KeySlice::from_slice(b"a", 1), b"b");
+    other.build(2, None, dir.path().join("2.sst")).unwrap();
+    assert!(SsTable::open_with_index(
+        1,
+        None,
+        FileObject::open(&dir.path().join("2.sst")).unwrap(),
+        FileObject::open(&dir.path().join("1.index")).unwrap(),
+    )
+    .is_err());
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, OptionsDelta};
+
+fn simple_options(level0_file_num_compaction_trigger: usize) -> CompactionOptions {
+    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger,
+        max_levels: 2,
+    })
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_lower_l0_trigger_at_runtime() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(simple_options(4));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..2 {
+        storage.put(format!("key{i}").as_bytes(), b"value").unwrap();
+        flush(&storage);
+    }
+    assert!(storage.plan_compaction().is_none());
+
+    storage
+        .set_options(OptionsDelta {
+            compaction_options: Some(simple_options(2)),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(storage.plan_compaction().is_some());
+    assert!(matches!(
+        storage.dynamic_options().compaction_options,
+        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            ..
+        })
+    ));
+    storage.trigger_compaction().unwrap();
+    assert!(storage.state.load().l0_sstables.is_empty());
+}
+
+#[test]
+fn test_set_target_sst_size() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(simple_options(4));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    storage
+        .set_options(OptionsDelta {
+            target_sst_size: Some(64),
+            rate_limit_bytes_per_sec: Some(1 << 20),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(storage.dynamic_options().target_sst_size, 64);
+    assert_eq!(storage.dynamic_options().rate_limit_bytes_per_sec, 1 << 20);
+    storage.put(b"key", &[0; 128]).unwrap();
+    assert_eq!(storage.state.load().imm_memtables.len(), 1);
+}
+
+#[test]
+fn test_reject_invalid_options() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(simple_options(4));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+
+    let tiered = CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+    let more_levels = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 4,
+        max_levels: 3,
+    });
+    for delta in [
+        OptionsDelta {
+            compaction_options: Some(tiered),
+            ..Default::default()
+        },
+        OptionsDelta {
+            compaction_options: Some(more_levels),
+            ..Default::default()
+        },
+        OptionsDelta {
+            compaction_options: Some(simple_options(0)),
+            ..Default::default()
+        },
+        OptionsDelta {
+            target_sst_size: Some(0),
+            compaction_options: Some(simple_options(2)),
+            ..Default::default()
+        },
+    ] {
+        assert!(storage.set_options(delta).is_err());
+    }
+    // None of the rejected deltas took effect, not even their valid parts.
+    let dynamic_options = storage.dynamic_options();
+    assert_eq!(dynamic_options.target_sst_size, 1 << 20);
+    assert!(matches!(
+        dynamic_options.compaction_options,
+        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 2,
+            ..
+        })
+    ));
+}
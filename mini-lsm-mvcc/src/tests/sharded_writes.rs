@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{
+    LsmStorageInner, LsmStorageOptions, MiniLsm, OptionsDelta, WriteBatchRecord,
+};
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::shard::shard_of;
+
+const NUM_SHARDS: usize = 4;
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.num_shards = NUM_SHARDS;
+    options
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Two keys of different shards, the one of the smaller shard first.
+fn keys_of_two_shards() -> (Vec<u8>, Vec<u8>) {
+    let first = key_of(0);
+    let second = (1..)
+        .map(key_of)
+        .find(|key| shard_of(key, NUM_SHARDS) != shard_of(&first, NUM_SHARDS))
+        .unwrap();
+    if shard_of(&first, NUM_SHARDS) < shard_of(&second, NUM_SHARDS) {
+        (first, second)
+    } else {
+        (second, first)
+    }
+}
+
+fn check_scan(storage: &MiniLsm, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    assert_eq!(entries, expected.clone().into_iter().collect::<Vec<_>>());
+    for (key, value) in expected {
+        assert_eq!(
+            storage.get(key).unwrap(),
+            Some(Bytes::copy_from_slice(value))
+        );
+    }
+}
+
+/// Checks the reads of all the keys up to `num_keys` against `expected`.
+fn check_reads(storage: &MiniLsm, expected: &BTreeMap<Vec<u8>, Vec<u8>>, num_keys: usize) {
+    check_scan(storage, expected);
+    for idx in 0..num_keys {
+        if !expected.contains_key(&key_of(idx)) {
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), None);
+        }
+    }
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(100)), Bound::Excluded(&key_of(200)))
+        .unwrap();
+    for (key, value) in expected.range(key_of(100)..key_of(200)) {
+        assert!(iter.is_valid());
+        assert_eq!((iter.key(), iter.value()), (&key[..], &value[..]));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// Runs random puts, deletes, batches and range deletions against a model of the storage,
+/// checking all the reads after each round, across freezes, flushes, compactions and a reopen.
+fn run_correctness_suite(num_shards: usize, compaction_options: CompactionOptions) {
+    const NUM_KEYS: usize = 500;
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options.clone());
+    options.enable_wal = true;
+    options.target_sst_size = 1 << 12;
+    options.num_shards = num_shards;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    let mut rng = StdRng::seed_from_u64(num_shards as u64);
+    let mut expected = BTreeMap::new();
+    for round in 0..16 {
+        for _ in 0..200 {
+            let idx = rng.gen_range(0..NUM_KEYS);
+            if rng.gen_bool(0.2) {
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            } else {
+                let value = format!("value_{}_{}", idx, round).into_bytes();
+                storage.put(&key_of(idx), &value).unwrap();
+                expected.insert(key_of(idx), value);
+            }
+        }
+        let batch = (0..20)
+            .map(|_| {
+                let idx = rng.gen_range(0..NUM_KEYS);
+                if rng.gen_bool(0.5) {
+                    expected.remove(&key_of(idx));
+                    WriteBatchRecord::Del(key_of(idx))
+                } else {
+                    let value = format!("batch_{}_{}", idx, round).into_bytes();
+                    expected.insert(key_of(idx), value.clone());
+                    WriteBatchRecord::Put(key_of(idx), value)
+                }
+            })
+            .collect::<Vec<_>>();
+        storage.write_batch(&batch).unwrap();
+        if rng.gen_bool(0.5) {
+            let start = rng.gen_range(0..NUM_KEYS);
+            let end = start + rng.gen_range(1..30);
+            storage.delete_range(&key_of(start), &key_of(end)).unwrap();
+            expected.retain(|key, _| key < &key_of(start) || key >= &key_of(end));
+        }
+        check_reads(&storage, &expected, NUM_KEYS);
+        match round % 4 {
+            1 => storage.force_flush().unwrap(),
+            2 => {
+                storage.force_flush().unwrap();
+                while !storage.inner.state.load().imm_memtables.is_empty() {
+                    storage.inner.force_flush_next_imm_memtable().unwrap();
+                }
+                if let CompactionOptions::NoCompaction = compaction_options {
+                    storage.force_full_compaction().unwrap();
+                }
+            }
+            _ => {}
+        }
+        check_reads(&storage, &expected, NUM_KEYS);
+    }
+    storage.close().unwrap();
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    check_reads(&storage, &expected, NUM_KEYS);
+    storage.close().unwrap();
+}
+
+fn run_correctness_suites(num_shards: usize) {
+    run_correctness_suite(num_shards, CompactionOptions::NoCompaction);
+    run_correctness_suite(
+        num_shards,
+        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        }),
+    );
+}
+
+#[test]
+fn test_correctness_suite_without_shards() {
+    run_correctness_suites(1);
+}
+
+#[test]
+fn test_correctness_suite_with_shards() {
+    run_correctness_suites(NUM_SHARDS);
+}
+
+#[test]
+fn test_sharded_reads_and_writes() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    assert_eq!(storage.inner.state.load().current_memtables().count(), 4);
+    let mut expected = BTreeMap::new();
+    for round in 0..3 {
+        for idx in 0..300 {
+            let value = format!("value_{}_{}", idx, round).into_bytes();
+            storage.put(&key_of(idx), &value).unwrap();
+            expected.insert(key_of(idx), value);
+        }
+        // the batches span the shards
+        let batch = (0..300)
+            .step_by(7 + round)
+            .map(|idx| WriteBatchRecord::Del(key_of(idx)))
+            .collect::<Vec<_>>();
+        storage.write_batch(&batch).unwrap();
+        for idx in (0..300).step_by(7 + round) {
+            expected.remove(&key_of(idx));
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), None);
+        }
+        check_scan(&storage, &expected);
+        storage.force_flush().unwrap();
+        while !storage.inner.state.load().imm_memtables.is_empty() {
+            storage.inner.force_flush_next_imm_memtable().unwrap();
+        }
+        check_scan(&storage, &expected);
+    }
+
+    // each L0 SST holds the keys of the shard it was flushed from
+    {
+        let state = storage.inner.state.load();
+        assert_eq!(state.l0_sstables.len(), 3 * NUM_SHARDS);
+        for id in &state.l0_sstables {
+            let sst = &state.sstables[id];
+            let shard = sst.shard().unwrap();
+            assert_eq!(shard_of(sst.first_key().key_ref(), NUM_SHARDS), shard);
+            assert_eq!(shard_of(sst.last_key().key_ref(), NUM_SHARDS), shard);
+        }
+    }
+
+    // the shard of the SSTs is recovered
+    storage.put(&key_of(1000), b"unflushed").unwrap();
+    expected.insert(key_of(1000), b"unflushed".to_vec());
+    storage.close().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    check_scan(&storage, &expected);
+    {
+        let state = storage.inner.state.load();
+        assert!(state
+            .l0_sstables
+            .iter()
+            .all(|id| state.sstables[id].shard().is_some()));
+    }
+    storage.close().unwrap();
+
+    // without shards, the SSTs are read as holding any key
+    let mut unsharded = options();
+    unsharded.num_shards = 1;
+    let storage = MiniLsm::open(dir.path(), unsharded).unwrap();
+    check_scan(&storage, &expected);
+    {
+        let state = storage.inner.state.load();
+        assert!(state
+            .l0_sstables
+            .iter()
+            .all(|id| state.sstables[id].shard().is_none()));
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_batch_across_shards_is_recovered() {
+    let dir = tempdir().unwrap();
+    let (first, second) = keys_of_two_shards();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&first[..], b"1"),
+            WriteBatchRecord::Put(&second[..], b"2"),
+        ])
+        .unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert_eq!(storage.get(&first).unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(
+        storage.get(&second).unwrap(),
+        Some(Bytes::from_static(b"2"))
+    );
+}
+
+#[test]
+fn test_torn_batch_across_shards_is_dropped() {
+    let dir = tempdir().unwrap();
+    let (first, second) = keys_of_two_shards();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    storage.put(&second, b"before").unwrap();
+    // the part of the first shard is written, that of the second is not
+    storage.failpoints.lock().insert("sharded-batch-part");
+    let err = storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&first[..], b"1"),
+            WriteBatchRecord::Put(&second[..], b"2"),
+        ])
+        .unwrap_err();
+    assert!(err.to_string().contains("sharded-batch-part"), "{}", err);
+    storage.sync().unwrap();
+    drop(storage);
+
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert_eq!(storage.get(&first).unwrap(), None);
+    assert_eq!(
+        storage.get(&second).unwrap(),
+        Some(Bytes::from_static(b"before"))
+    );
+    let records = Manifest::read_records(dir.path().join("MANIFEST")).unwrap();
+    assert_eq!(
+        records
+            .iter()
+            .filter(|record| matches!(record, ManifestRecord::DropBatch(_)))
+            .count(),
+        1
+    );
+
+    // once the memtable of the second shard is flushed, the manifest still drops the part left
+    // in the WAL of the first
+    assert_eq!(storage.state.load().imm_memtables.len(), 1);
+    storage.force_flush_next_imm_memtable().unwrap();
+    drop(storage);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    assert_eq!(storage.get(&first).unwrap(), None);
+    assert_eq!(
+        storage.get(&second).unwrap(),
+        Some(Bytes::from_static(b"before"))
+    );
+
+    // the storage keeps working after the recovery
+    storage.put(&first, b"after").unwrap();
+    assert_eq!(
+        storage.get(&first).unwrap(),
+        Some(Bytes::from_static(b"after"))
+    );
+}
+
+#[test]
+fn test_concurrent_sharded_writers() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.target_sst_size = 1 << 14;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    std::thread::scope(|scope| {
+        for writer in 0..8 {
+            let storage = &storage;
+            scope.spawn(move || {
+                for idx in (writer..2000).step_by(8) {
+                    let value = format!("value_{}", idx);
+                    if idx % 5 == 0 {
+                        storage
+                            .write_batch(&[
+                                WriteBatchRecord::Put(key_of(idx), value.clone().into_bytes()),
+                                WriteBatchRecord::Put(key_of(idx + 10000), value.into_bytes()),
+                            ])
+                            .unwrap();
+                    } else {
+                        storage.put(&key_of(idx), value.as_bytes()).unwrap();
+                    }
+                    // a write is read right after it
+                    assert!(storage.get(&key_of(idx)).unwrap().is_some());
+                }
+            });
+        }
+    });
+
+    let check = |storage: &Arc<MiniLsm>| {
+        for idx in 0..2000 {
+            let value = Some(Bytes::from(format!("value_{}", idx)));
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), value);
+            if idx % 5 == 0 {
+                assert_eq!(storage.get(&key_of(idx + 10000)).unwrap(), value);
+            }
+        }
+    };
+    check(&storage);
+    storage.close().unwrap();
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    check(&storage);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_range_tombstone_is_written_to_every_shard() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options()).unwrap());
+    let key = (0..)
+        .map(key_of)
+        .find(|key| shard_of(key, NUM_SHARDS) != 0)
+        .unwrap();
+    storage.put(&key, b"value").unwrap();
+    storage.delete_range(&key_of(0), &key_of(1000)).unwrap();
+    assert!(storage
+        .state
+        .load()
+        .current_memtables()
+        .all(|memtable| memtable.range_tombstones().len() == 1));
+
+    // shard 0 flushes its copy of the tombstone first, which the full compaction drops at the
+    // bottom level, while the version it covers is still in the memtable of its shard
+    storage
+        .set_options(OptionsDelta {
+            target_sst_size: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+    storage.try_freeze(0, usize::MAX).unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(&key).unwrap(), None);
+
+    // the shard of the key flushes its own copy along with the version
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    assert_eq!(storage.get(&key).unwrap(), None);
+
+    // a compaction keeps a single copy of the tombstone a snapshot still needs, every shard
+    // freezes its copy right away with the tiny target SST size
+    let txn = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(0), &key_of(1000)).unwrap();
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    let state = storage.state.load();
+    assert!(state
+        .sstables
+        .values()
+        .all(|sst| sst.range_tombstones().len() == 1));
+    drop(txn);
+}
+
+#[test]
+fn test_num_shards_must_be_a_power_of_two() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.num_shards = 3;
+    assert!(MiniLsm::open(dir.path(), options.clone()).is_err());
+    options.num_shards = 0;
+    assert!(MiniLsm::open(dir.path(), options).is_err());
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+const LARGE_KEY: usize = 5;
+
+fn key_of(idx: usize) -> KeyVec {
+    KeyVec::for_testing_from_vec_no_ts(format!("key_{:03}", idx * 2).into_bytes())
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    if idx == LARGE_KEY {
+        vec![b'x'; 4096]
+    } else {
+        format!("value_{:03}", idx).into_bytes()
+    }
+}
+
+/// Builds a table of small blocks, where the large value of `LARGE_KEY` is stored alone in a
+/// block larger than the block size.
+fn generate_sst() -> (tempfile::TempDir, Arc<SsTable>) {
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..10 {
+        builder.add(key_of(idx).as_key_slice(), &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    (dir, Arc::new(sst))
+}
+
+#[test]
+fn test_single_oversized_entry_block() {
+    let mut builder = BlockBuilder::new(16);
+    let key = KeySlice::for_testing_from_slice_no_ts(b"large_key");
+    assert!(builder.add(key, &[b'x'; 1024]));
+    assert!(!builder.add(KeySlice::for_testing_from_slice_no_ts(b"large_kez"), b"v"));
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    assert_eq!(block.num_entries(), 1);
+
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    assert_eq!(iter.key().for_testing_key_ref(), b"large_key");
+    assert_eq!(iter.value(), &[b'x'; 1024]);
+    iter.next();
+    assert!(!iter.is_valid());
+
+    let mut iter = BlockIterator::create_and_seek_to_last(block.clone());
+    assert_eq!(iter.key().for_testing_key_ref(), b"large_key");
+    iter.prev();
+    assert!(!iter.is_valid());
+
+    for (seek, found) in [
+        (b"a".as_slice(), true),
+        (b"large_key".as_slice(), true),
+        (b"large_key0".as_slice(), false),
+    ] {
+        let iter = BlockIterator::create_and_seek_to_key(
+            block.clone(),
+            KeySlice::for_testing_from_slice_no_ts(seek),
+        );
+        assert_eq!(iter.is_valid(), found);
+        if found {
+            assert_eq!(iter.key().for_testing_key_ref(), b"large_key");
+        }
+    }
+}
+
+#[test]
+fn test_single_entry_block_in_sst() {
+    let (_dir, sst) = generate_sst();
+    let large_idx = sst
+        .block_meta
+        .iter()
+        .position(|meta| meta.first_key.key_ref() == key_of(LARGE_KEY).key_ref())
+        .unwrap();
+    let meta = &sst.block_meta[large_idx];
+    assert_eq!(meta.num_entries, 1);
+    assert_eq!(meta.first_key, meta.last_key);
+    assert_eq!(sst.read_block(large_idx).unwrap().num_entries(), 1);
+    // the neighbours of the large entry are in the blocks around it
+    assert_eq!(
+        sst.block_meta[large_idx - 1].last_key.key_ref(),
+        key_of(LARGE_KEY - 1).key_ref()
+    );
+    assert_eq!(
+        sst.block_meta[large_idx + 1].first_key.key_ref(),
+        key_of(LARGE_KEY + 1).key_ref()
+    );
+
+    let large_key = key_of(LARGE_KEY);
+    let before = KeyVec::for_testing_from_vec_no_ts(b"key_009".to_vec());
+    let after = KeyVec::for_testing_from_vec_no_ts(b"key_011".to_vec());
+    assert_eq!(
+        sst.find_block_idx(large_key.as_key_slice()),
+        Some(large_idx)
+    );
+    assert_eq!(
+        sst.find_block_idx(before.as_key_slice()),
+        Some(large_idx - 1)
+    );
+    // a key after the single entry routes to its block, and the iterator moves on to the next one
+    assert_eq!(sst.find_block_idx(after.as_key_slice()), Some(large_idx));
+
+    for (seek, expected) in [
+        (&before, LARGE_KEY),
+        (&large_key, LARGE_KEY),
+        (&after, LARGE_KEY + 1),
+    ] {
+        let iter =
+            SsTableIterator::create_and_seek_to_key(sst.clone(), seek.as_key_slice()).unwrap();
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(expected).key_ref());
+        assert_eq!(iter.value(), value_of(expected).as_slice());
+    }
+    for (seek, expected) in [
+        (&before, LARGE_KEY - 1),
+        (&large_key, LARGE_KEY),
+        (&after, LARGE_KEY),
+    ] {
+        let iter =
+            SsTableIterator::create_and_seek_to_key_rev(sst.clone(), seek.as_key_slice()).unwrap();
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(expected).key_ref());
+    }
+
+    // iterating across the block in both directions visits every entry once
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..10 {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx).key_ref());
+        assert_eq!(iter.value(), value_of(idx).as_slice());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    for idx in (0..10).rev() {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx).key_ref());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
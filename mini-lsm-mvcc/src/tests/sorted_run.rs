@@ -0,0 +1,69 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:03}", i).into_bytes()
+}
+
+/// Builds the table `id` with the given keys and timestamps.
+fn table(dir: &Path, id: usize, entries: &[(usize, u64)]) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(64);
+    for (i, ts) in entries {
+        builder.add(KeySlice::from_slice(&key_of(*i), *ts), b"value");
+    }
+    Arc::new(
+        builder
+            .build(id, None, dir.join(format!("{}.sst", id)))
+            .unwrap(),
+    )
+}
+
+#[test]
+fn test_valid_sorted_run() {
+    let dir = tempdir().unwrap();
+    let run = vec![
+        table(dir.path(), 1, &(0..30).map(|i| (i, 1)).collect::<Vec<_>>()),
+        // the versions of a key may be split between tables, newest first
+        table(dir.path(), 2, &[(30, 3), (30, 2)]),
+        table(dir.path(), 3, &(30..60).map(|i| (i, 1)).collect::<Vec<_>>()),
+    ];
+    SstConcatIterator::validate_non_overlapping(&run).unwrap();
+    SstConcatIterator::validate_non_overlapping(&[]).unwrap();
+    let mut iter = SstConcatIterator::create_and_seek_to_first(run).unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 62);
+}
+
+#[test]
+fn test_overlapping_sorted_run() {
+    let dir = tempdir().unwrap();
+    let first = table(dir.path(), 1, &(0..30).map(|i| (i, 1)).collect::<Vec<_>>());
+
+    // the next table starts with the last key of the previous one
+    let run = vec![
+        first.clone(),
+        table(dir.path(), 2, &(29..60).map(|i| (i, 1)).collect::<Vec<_>>()),
+    ];
+    let err = SstConcatIterator::validate_non_overlapping(&run).unwrap_err();
+    assert!(err.to_string().contains("SST 2"), "{}", err);
+    assert!(err.to_string().contains("SST 1"), "{}", err);
+    if cfg!(debug_assertions) {
+        assert!(SstConcatIterator::create_and_seek_to_first(run).is_err());
+    }
+
+    // ... or with a newer version of it
+    let run = vec![first, table(dir.path(), 3, &[(29, 2), (30, 1)])];
+    let err = SstConcatIterator::validate_non_overlapping(&run).unwrap_err();
+    assert!(err.to_string().contains("SST 3"), "{}", err);
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+type Entry = (Vec<u8>, u64, Vec<u8>);
+
+fn drain(mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>) -> Vec<Entry> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_split_iterators_cover_table() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(256);
+    for i in 0..500 {
+        // a few versions per key, so that pivots fall between versions in the same block
+        for ts in (1..=(i % 3 + 1) as u64).rev() {
+            builder.add(
+                KeySlice::from_slice(format!("key_{:04}", i * 2).as_bytes(), ts),
+                format!("value_{}_{}", i, ts).as_bytes(),
+            );
+        }
+    }
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    let full = drain(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap());
+
+    let pivot_sets: &[&[&[u8]]] = &[
+        &[],
+        &[b"key_0500"],
+        // absent keys, keys with several versions and duplicated pivots
+        &[
+            b"key_0001",
+            b"key_0300",
+            b"key_0300",
+            b"key_0555",
+            b"key_0998",
+        ],
+        // pivots outside of the key range of the table
+        &[b"a", b"key_0100", b"zzz"],
+    ];
+    for pivots in pivot_sets {
+        let iters = sst.split_iterators(pivots).unwrap();
+        assert_eq!(iters.len(), pivots.len() + 1);
+        let parts = std::thread::scope(|scope| {
+            let handles = iters
+                .into_iter()
+                .map(|iter| scope.spawn(move || drain(iter)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for (idx, part) in parts.iter().enumerate() {
+            for (key, _, _) in part {
+                if idx > 0 {
+                    assert!(key.as_slice() >= pivots[idx - 1]);
+                }
+                if idx < pivots.len() {
+                    assert!(key.as_slice() < pivots[idx]);
+                }
+            }
+        }
+        assert_eq!(parts.concat(), full);
+    }
+}
+
+#[test]
+#[should_panic(expected = "pivots must be sorted")]
+fn test_split_iterators_unsorted_pivots() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(256);
+    builder.add(KeySlice::from_slice(b"key", 1), b"value");
+    let sst: Arc<SsTable> = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    let _ = sst.split_iterators(&[b"b", b"a"]);
+}
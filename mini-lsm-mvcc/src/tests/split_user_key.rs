@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState};
+
+use super::harness::sync;
+
+const HOT_KEY: &[u8] = b"key_0500";
+const HOT_KEY_VERSIONS: usize = 3000;
+
+/// Checks that the SSTs of every level are sorted and disjoint by user key.
+fn check_level_invariants(snapshot: &LsmStorageState) {
+    for (level, sst_ids) in &snapshot.levels {
+        for pair in sst_ids.windows(2) {
+            let left = &snapshot.sstables[&pair[0]];
+            let right = &snapshot.sstables[&pair[1]];
+            assert!(
+                left.last_key().key_ref() < right.first_key().key_ref(),
+                "SST {} and {} of level {} overlap",
+                pair[0],
+                pair[1],
+                level
+            );
+        }
+    }
+}
+
+#[test]
+fn test_compaction_never_splits_user_key() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.target_sst_size = 1 << 14;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    // keep every version visible so that compaction has to retain all of them
+    let _txn = storage.new_txn().unwrap();
+    for i in 0..1000 {
+        storage
+            .put(
+                format!("key_{:04}", i).as_bytes(),
+                format!("value_{:0100}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    for version in 0..HOT_KEY_VERSIONS {
+        storage
+            .put(HOT_KEY, format!("hot_value_{:05}", version).as_bytes())
+            .unwrap();
+    }
+    sync(&storage);
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+
+    let snapshot = storage.state.load_full();
+    check_level_invariants(&snapshot);
+    let l1 = &snapshot.levels[0].1;
+    assert!(l1.len() > 1);
+    let holders = l1
+        .iter()
+        .map(|id| &snapshot.sstables[id])
+        .filter(|sst| sst.first_key().key_ref() <= HOT_KEY && HOT_KEY <= sst.last_key().key_ref())
+        .collect::<Vec<_>>();
+    assert_eq!(holders.len(), 1);
+    let holder = holders[0];
+    // the versions of the hot key alone exceed the target size
+    assert!(holder.table_size() > 1 << 14);
+    assert!(holder.properties().num_entries as usize > HOT_KEY_VERSIONS);
+    for id in l1 {
+        let sst = &snapshot.sstables[id];
+        if !Arc::ptr_eq(sst, holder) {
+            assert!(sst.table_size() < 2 << 14);
+        }
+    }
+    assert_eq!(
+        storage.get(HOT_KEY).unwrap().as_deref(),
+        Some(format!("hot_value_{:05}", HOT_KEY_VERSIONS - 1).as_bytes())
+    );
+}
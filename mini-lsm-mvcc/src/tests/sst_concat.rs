@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator, TableProperties};
+
+/// Entries for the keys in `range`, with a few versions and tombstones.
+fn entries(range: std::ops::Range<usize>) -> Vec<(String, u64, String)> {
+    let mut entries = Vec::new();
+    for i in range {
+        let key = format!("key_{:05}", i);
+        for ts in (1..=(i % 3) as u64 + 1).rev() {
+            let value = if (i + ts as usize) % 5 == 2 {
+                String::new()
+            } else {
+                format!("value_{}_{}", i, ts)
+            };
+            entries.push((key.clone(), ts, value));
+        }
+    }
+    entries
+}
+
+fn build(entries: &[(String, u64, String)], path: &std::path::Path) -> SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    for (key, ts, value) in entries {
+        builder.add(KeySlice::from_slice(key.as_bytes(), *ts), value.as_bytes());
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+fn assert_same_table(concat: SsTable, rebuilt: SsTable) {
+    assert_eq!(concat.first_key(), rebuilt.first_key());
+    assert_eq!(concat.last_key(), rebuilt.last_key());
+    assert_eq!(concat.max_ts(), rebuilt.max_ts());
+    assert_eq!(
+        TableProperties {
+            num_bloom_bits: 0,
+            ..*concat.properties()
+        },
+        TableProperties {
+            num_bloom_bits: 0,
+            ..*rebuilt.properties()
+        }
+    );
+    let concat = Arc::new(concat);
+    let bloom = concat.bloom.as_ref().unwrap();
+    let mut concat_iter = SsTableIterator::create_and_seek_to_first(concat.clone()).unwrap();
+    let mut rebuilt_iter = SsTableIterator::create_and_seek_to_first(Arc::new(rebuilt)).unwrap();
+    while rebuilt_iter.is_valid() {
+        assert!(concat_iter.is_valid());
+        assert_eq!(concat_iter.key(), rebuilt_iter.key());
+        assert_eq!(concat_iter.value(), rebuilt_iter.value());
+        assert!(bloom.may_contain(farmhash::fingerprint32(rebuilt_iter.key().key_ref())));
+        concat_iter.next().unwrap();
+        rebuilt_iter.next().unwrap();
+    }
+    assert!(!concat_iter.is_valid());
+}
+
+#[test]
+fn test_concat_same_bloom_shape() {
+    let dir = tempdir().unwrap();
+    let (left, right) = (entries(0..300), entries(300..600));
+    let a = build(&left, &dir.path().join("a.sst"));
+    let b = build(&right, &dir.path().join("b.sst"));
+    assert_eq!(a.properties().num_bloom_bits, b.properties().num_bloom_bits);
+    let path = dir.path().join("concat.sst");
+    let concat = SsTable::concat(&a, &b, 3, &path).unwrap();
+    assert_eq!(concat.sst_id(), 3);
+    assert_eq!(
+        concat.num_of_blocks(),
+        a.num_of_blocks() + b.num_of_blocks()
+    );
+    // the filters are combined, not rebuilt
+    assert_eq!(
+        concat.properties().num_bloom_bits,
+        a.properties().num_bloom_bits
+    );
+    // the blocks are copied as they are
+    let data = std::fs::read(&path).unwrap();
+    let a_data = std::fs::read(dir.path().join("a.sst")).unwrap();
+    let b_data = std::fs::read(dir.path().join("b.sst")).unwrap();
+    assert_eq!(data[..a.block_meta_offset], a_data[..a.block_meta_offset]);
+    assert_eq!(
+        data[a.block_meta_offset..concat.block_meta_offset],
+        b_data[..b.block_meta_offset]
+    );
+
+    let all = left.into_iter().chain(right).collect::<Vec<_>>();
+    let rebuilt = build(&all, &dir.path().join("rebuilt.sst"));
+    assert_same_table(concat, rebuilt);
+
+    // the table reads back the same from the disk
+    let reopened = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let rebuilt = build(&all, &dir.path().join("rebuilt.sst"));
+    assert_same_table(reopened, rebuilt);
+}
+
+#[test]
+fn test_concat_rebuilds_bloom_of_different_shapes() {
+    let dir = tempdir().unwrap();
+    let (left, right) = (entries(0..50), entries(50..400));
+    let a = build(&left, &dir.path().join("a.sst"));
+    let b = build(&right, &dir.path().join("b.sst"));
+    assert_ne!(a.properties().num_bloom_bits, b.properties().num_bloom_bits);
+    let concat = SsTable::concat(&a, &b, 3, dir.path().join("concat.sst")).unwrap();
+    let all = left.into_iter().chain(right).collect::<Vec<_>>();
+    let rebuilt = build(&all, &dir.path().join("rebuilt.sst"));
+    assert_eq!(
+        concat.properties().num_bloom_bits,
+        rebuilt.properties().num_bloom_bits
+    );
+    assert_same_table(concat, rebuilt);
+
+    // a single-key table has a filter that matches everything
+    let single = build(&entries(400..401), &dir.path().join("single.sst"));
+    let concat = SsTable::concat(&b, &single, 4, dir.path().join("concat2.sst")).unwrap();
+    let all = entries(50..401);
+    let rebuilt = build(&all, &dir.path().join("rebuilt2.sst"));
+    assert_same_table(concat, rebuilt);
+}
+
+#[test]
+#[should_panic(expected = "tables to concatenate overlap")]
+fn test_concat_overlapping() {
+    let dir = tempdir().unwrap();
+    let a = build(&entries(0..100), &dir.path().join("a.sst"));
+    let b = build(&entries(99..200), &dir.path().join("b.sst"));
+    SsTable::concat(&a, &b, 3, dir.path().join("concat.sst")).unwrap();
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+fn build(path: &std::path::Path) -> SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    for i in 0..500 {
+        let key = format!("key_{:05}", i);
+        for ts in (1..=(i % 3) as u64 + 1).rev() {
+            builder.add(
+                KeySlice::from_slice(key.as_bytes(), ts),
+                format!("value_{}_{}", i, ts).as_bytes(),
+            );
+        }
+    }
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from("key_00100"),
+        Bytes::from("key_00200"),
+        5,
+    ));
+    builder.build_for_test(path).unwrap()
+}
+
+fn entries(table: SsTable) -> Vec<(Vec<u8>, u64, Vec<u8>)> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(table)).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_copy_to() {
+    let dir = tempdir().unwrap();
+    let table = build(&dir.path().join("1.sst"));
+    let mut replica = Vec::new();
+    assert_eq!(table.copy_to(&mut replica).unwrap(), table.table_size());
+    assert_eq!(replica.len() as u64, table.table_size());
+
+    let replica_path = dir.path().join("2.sst");
+    std::fs::write(&replica_path, &replica).unwrap();
+    let copy = SsTable::open_for_test(FileObject::open(&replica_path).unwrap()).unwrap();
+    assert_eq!(copy.first_key(), table.first_key());
+    assert_eq!(copy.last_key(), table.last_key());
+    assert_eq!(copy.properties(), table.properties());
+    assert_eq!(copy.range_tombstones(), table.range_tombstones());
+    assert_eq!(entries(copy), entries(table));
+}
+
+#[test]
+fn test_copy_to_fails_on_corruption() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let table = build(&path);
+    let last_block_offset = table.block_meta.last().unwrap().offset;
+    drop(table);
+
+    let mut data = std::fs::read(&path).unwrap();
+    data[last_block_offset + 1] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+    // only the meta and the bloom filter are read when the table is opened
+    let table = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let mut replica = Vec::new();
+    assert!(table.copy_to(&mut replica).is_err());
+    assert!(replica.len() <= last_block_offset);
+}
@@ -0,0 +1,23 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::SsTableBuilder;
+
+#[test]
+fn test_sst_key_bytes() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(64);
+    for i in 0..100 {
+        let key = format!("key_{:05}", i);
+        builder.add(KeySlice::from_slice(key.as_bytes(), 100 - i), b"value");
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.num_of_blocks() > 1);
+    assert_eq!(sst.first_key_bytes(), b"key_00000");
+    assert_eq!(sst.last_key_bytes(), b"key_00099");
+    assert_eq!(sst.first_key_bytes(), sst.first_key().key_ref());
+    assert_eq!(sst.last_key_bytes(), sst.last_key().key_ref());
+    // the timestamps are left out
+    assert_eq!(sst.first_key().ts(), 100);
+    assert_eq!(sst.last_key().ts(), 1);
+}
@@ -0,0 +1,83 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+use super::harness::MockIterator;
+
+#[test]
+fn test_std_iter_over_sst() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("key_{:03}", idx).as_bytes()),
+            format!("value_{:03}", idx).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+
+    let mut expected = Vec::new();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    while iter.is_valid() && iter.key().key_ref() < b"key_050".as_slice() {
+        expected.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+
+    let result = SsTableIterator::create_and_seek_to_first(sst)
+        .unwrap()
+        .into_std_iter()
+        .take_while(|entry| match entry {
+            Ok((key, _)) => key.as_ref() < b"key_050".as_slice(),
+            Err(_) => true,
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(result.len(), 50);
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_std_iter_over_storage_scan() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.delete(b"2").unwrap();
+    let result = storage
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .unwrap()
+        .into_std_iter()
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(result, vec![Bytes::from("1"), Bytes::from("3")]);
+}
+
+#[test]
+fn test_std_iter_error() {
+    let data = vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ];
+    let mut iter = MockIterator::new_with_error(data, 1).into_std_iter();
+    assert_eq!(
+        iter.next().unwrap().unwrap(),
+        (Bytes::from("a"), Bytes::from("1.1"))
+    );
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+}
@@ -0,0 +1,163 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::StreamingCompactionIterator;
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{SsTable, SsTableBuilder};
+
+use super::harness::sync;
+
+const NUM_RUNS: usize = 6;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// Builds `NUM_RUNS` overlapping runs of two SSTs each. Run `r` holds every `r + 1`-th key,
+/// the even keys all at the same timestamp, so that the newer runs shadow the older ones.
+fn build_runs(path: &Path) -> Vec<Vec<Arc<SsTable>>> {
+    (0..NUM_RUNS)
+        .map(|run| {
+            let idxs = (run * 10..600).step_by(run + 1).collect::<Vec<_>>();
+            idxs.chunks(idxs.len().div_ceil(2))
+                .enumerate()
+                .map(|(part, idxs)| {
+                    let mut builder = SsTableBuilder::new(128);
+                    for &idx in idxs {
+                        let ts = if idx % 2 == 0 { 1 } else { 10 - run as u64 };
+                        let value = format!("value_{}@{}", idx, run);
+                        builder.add(KeySlice::from_slice(&key_of(idx), ts), value.as_bytes());
+                    }
+                    let table = builder
+                        .build_for_test(path.join(format!("{}_{}.sst", run, part)))
+                        .unwrap();
+                    Arc::new(table)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn collect(
+    iter: &mut impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+) -> Vec<(Vec<u8>, u64, Vec<u8>)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+fn merge_expected(
+    runs: &[Vec<Arc<SsTable>>],
+    lower: Option<KeySlice>,
+) -> Vec<(Vec<u8>, u64, Vec<u8>)> {
+    let iters = runs
+        .iter()
+        .map(|run| {
+            Box::new(match lower {
+                Some(key) => SstConcatIterator::create_and_seek_to_key(run.clone(), key).unwrap(),
+                None => SstConcatIterator::create_and_seek_to_first(run.clone()).unwrap(),
+            })
+        })
+        .collect();
+    collect(&mut MergeIterator::create(iters))
+}
+
+#[test]
+fn test_streaming_merge_stays_within_budget() {
+    let dir = tempdir().unwrap();
+    let runs = build_runs(dir.path());
+    let num_blocks = runs
+        .iter()
+        .flatten()
+        .map(|table| table.num_of_blocks())
+        .sum::<usize>();
+    assert!(num_blocks > 100);
+    let expected = merge_expected(&runs, None);
+
+    let mut iter = StreamingCompactionIterator::create(runs.clone(), None, 3, None).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        assert!(iter.buffered_blocks() <= 3);
+        entries.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    assert_eq!(entries, expected);
+    assert_eq!(iter.peak_buffered_blocks(), 3);
+    // the released blocks are read again
+    assert!(iter.num_block_reads() > num_blocks as u64);
+    assert_eq!(iter.buffered_blocks(), 0);
+    assert_eq!(iter.num_active_iterators(), 0);
+
+    // a budget as large as the inputs reads each block once
+    let mut iter = StreamingCompactionIterator::create(runs, None, NUM_RUNS, None).unwrap();
+    assert_eq!(collect(&mut iter), expected);
+    assert_eq!(iter.num_block_reads(), num_blocks as u64);
+}
+
+#[test]
+fn test_streaming_merge_with_single_block_and_lower_bound() {
+    let dir = tempdir().unwrap();
+    let runs = build_runs(dir.path());
+    let mut iter = StreamingCompactionIterator::create(runs.clone(), None, 1, None).unwrap();
+    assert_eq!(collect(&mut iter), merge_expected(&runs, None));
+    assert_eq!(iter.peak_buffered_blocks(), 1);
+
+    for idx in [0, 7, 301, 598, 700] {
+        let key = key_of(idx);
+        let lower = KeySlice::from_slice(&key, TS_RANGE_BEGIN);
+        let mut iter =
+            StreamingCompactionIterator::create(runs.clone(), Some(lower), 2, None).unwrap();
+        assert_eq!(collect(&mut iter), merge_expected(&runs, Some(lower)));
+        assert!(iter.peak_buffered_blocks() <= 2);
+    }
+
+    assert!(StreamingCompactionIterator::create(runs, None, 0, None).is_err());
+}
+
+#[test]
+fn test_full_compaction_with_block_budget() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.compaction_block_budget = 2;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for round in 0..4 {
+        for idx in (round..300).step_by(round + 1) {
+            storage
+                .put(&key_of(idx), format!("value_{}@{}", idx, round).as_bytes())
+                .unwrap();
+        }
+        for idx in (0..300).step_by(7 + round) {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+        sync(&storage);
+    }
+    let expected = (0..300)
+        .map(|idx| storage.get(&key_of(idx)).unwrap())
+        .collect::<Vec<_>>();
+    storage.force_full_compaction().unwrap();
+    {
+        let state = storage.state.load();
+        assert!(state.l0_sstables.is_empty());
+        assert!(!state.levels[0].1.is_empty());
+    }
+    for (idx, value) in expected.into_iter().enumerate() {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), value);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::SubcompactionPool,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::harness::{construct_merge_iterator_over_storage, sync};
+
+fn open_with_subcompactions(path: &Path, max_subcompactions: usize) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.target_sst_size = 1 << 16;
+    options.max_subcompactions = max_subcompactions;
+    Arc::new(LsmStorageInner::open(path, options).unwrap())
+}
+
+fn populate(storage: &Arc<LsmStorageInner>) {
+    for round in 0..4 {
+        for i in 0..2000 {
+            if (i + round) % 7 == 0 {
+                storage.delete(format!("key_{:05}", i).as_bytes()).unwrap();
+            } else {
+                storage
+                    .put(
+                        format!("key_{:05}", i).as_bytes(),
+                        format!("value_{:05}_{:0100}", i, round).as_bytes(),
+                    )
+                    .unwrap();
+            }
+        }
+        sync(storage);
+    }
+}
+
+fn dump_all_versions(storage: &LsmStorageInner) -> Vec<(Bytes, u64, Bytes)> {
+    let mut iter = construct_merge_iterator_over_storage(&storage.state.load());
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            iter.key().ts(),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_subcompaction_matches_single_threaded() {
+    let dir1 = tempdir().unwrap();
+    let dir2 = tempdir().unwrap();
+    let single = open_with_subcompactions(dir1.path(), 1);
+    let parallel = open_with_subcompactions(dir2.path(), 4);
+    // keep all versions alive so that compaction has to preserve them
+    let _txn1 = single.new_txn().unwrap();
+    let _txn2 = parallel.new_txn().unwrap();
+    populate(&single);
+    populate(&parallel);
+
+    single.force_full_compaction().unwrap();
+    parallel.force_full_compaction().unwrap();
+
+    assert_eq!(dump_all_versions(&single), dump_all_versions(&parallel));
+
+    let snapshot = parallel.state.load_full();
+    assert!(snapshot.l0_sstables.is_empty());
+    let l1 = &snapshot.levels[0].1;
+    assert!(l1.len() >= 4);
+    for pair in l1.windows(2) {
+        let left = &snapshot.sstables[&pair[0]];
+        let right = &snapshot.sstables[&pair[1]];
+        // versions of the same user key must never be split across two SSTs
+        assert!(left.last_key().key_ref() < right.first_key().key_ref());
+    }
+}
+
+#[test]
+fn test_subcompaction_split_points_follow_block_boundaries() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_subcompactions(dir.path(), 8);
+    populate(&storage);
+    let snapshot = storage.state.load_full();
+    let task = crate::compact::CompactionTask::ForceFullCompaction {
+        l0_sstables: snapshot.l0_sstables.clone(),
+        l1_sstables: snapshot.levels[0].1.clone(),
+    };
+    let split_points = storage.subcompaction_split_points(&snapshot, &task);
+    assert_eq!(split_points.len(), 7);
+    for pair in split_points.windows(2) {
+        assert!(pair[0] < pair[1]);
+    }
+    for split_point in &split_points {
+        assert!(snapshot.sstables.values().any(|sst| sst
+            .block_meta
+            .iter()
+            .any(|meta| meta.first_key.key_ref() == split_point.as_ref())));
+    }
+}
+
+#[test]
+fn test_subcompaction_pool_bounds_running_jobs() {
+    let pool = SubcompactionPool::new(2);
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+    let receivers = (0..8)
+        .map(|i| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            pool.spawn(move || {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(i)
+            })
+        })
+        .collect::<Vec<_>>();
+    for (i, receiver) in receivers.into_iter().enumerate() {
+        assert_eq!(receiver.recv().unwrap().unwrap(), i);
+    }
+    assert!(max_running.load(Ordering::SeqCst) <= 2);
+
+    // a panicking sub-compaction fails without taking its thread down
+    let receiver = pool.spawn(|| -> anyhow::Result<()> { panic!("sub-compaction failed") });
+    assert!(receiver.recv().unwrap().is_err());
+    assert_eq!(pool.spawn(|| Ok(1)).recv().unwrap().unwrap(), 1);
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::table::faulty_file::FaultyFileObject;
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+/// Most keys in `300..1500` are tombstones, and every third key elsewhere.
+fn is_tombstone(i: usize) -> bool {
+    (300..1500).contains(&i) && i != 1000 || i.is_multiple_of(3)
+}
+
+fn build(path: &Path, with_bitmap: bool) -> SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    if with_bitmap {
+        builder.enable_tombstone_bitmap();
+    }
+    for i in 1..2000 {
+        let value = if is_tombstone(i) { "" } else { "value" };
+        builder.add(KeySlice::from_slice(&key_of(i), 1), value.as_bytes());
+    }
+    builder.build_for_test(path).unwrap()
+}
+
+fn collect(mut iter: SsTableIterator) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        assert!(!iter.value().is_empty());
+        keys.push(iter.key().key_ref().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+fn skipping(table: Arc<SsTable>, seek_to: Option<usize>) -> Vec<Vec<u8>> {
+    let mut iter = match seek_to {
+        Some(i) => {
+            SsTableIterator::create_and_seek_to_key(table, KeySlice::from_slice(&key_of(i), 1))
+                .unwrap()
+        }
+        None => SsTableIterator::create_and_seek_to_first(table).unwrap(),
+    };
+    iter.skip_tombstones().unwrap();
+    collect(iter)
+}
+
+fn live_keys(from: usize) -> Vec<Vec<u8>> {
+    (from.max(1)..2000)
+        .filter(|i| !is_tombstone(*i))
+        .map(key_of)
+        .collect()
+}
+
+#[test]
+fn test_tombstone_bitmap() {
+    let dir = tempdir().unwrap();
+    let table = build(&dir.path().join("1.sst"), true);
+    let bitmap = table.tombstone_bitmap().unwrap().clone();
+    assert_eq!(bitmap.num_entries(), 1999);
+    for i in 1..2000 {
+        assert_eq!(bitmap.is_tombstone(i - 1), is_tombstone(i));
+    }
+    let table =
+        SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+    assert_eq!(table.tombstone_bitmap(), Some(&bitmap));
+    let table = Arc::new(table);
+
+    let plain = build(&dir.path().join("2.sst"), false);
+    assert!(plain.tombstone_bitmap().is_none());
+    let plain = Arc::new(plain);
+    for seek_to in [
+        None,
+        Some(0),
+        Some(3),
+        Some(299),
+        Some(300),
+        Some(1000),
+        Some(1001),
+        Some(1998),
+        Some(2000),
+    ] {
+        let expected = live_keys(seek_to.unwrap_or(0));
+        assert_eq!(skipping(table.clone(), seek_to), expected, "{:?}", seek_to);
+        assert_eq!(skipping(plain.clone(), seek_to), expected, "{:?}", seek_to);
+    }
+
+    // the bitmap is copied along with the table and kept by concatenation
+    let mut replica = Vec::new();
+    table.copy_to(&mut replica).unwrap();
+    std::fs::write(dir.path().join("3.sst"), &replica).unwrap();
+    let copy =
+        SsTable::open_for_test(FileObject::open(&dir.path().join("3.sst")).unwrap()).unwrap();
+    assert_eq!(copy.tombstone_bitmap(), Some(&bitmap));
+
+    let mut builder = SsTableBuilder::new(256);
+    builder.enable_tombstone_bitmap();
+    builder.add(KeySlice::from_slice(b"zzz", 1), b"");
+    builder.add(KeySlice::from_slice(b"zzzz", 1), b"value");
+    let tail = builder.build_for_test(dir.path().join("4.sst")).unwrap();
+    SsTable::concat(&copy, &tail, 5, dir.path().join("5.sst")).unwrap();
+    let mut expected = live_keys(0);
+    expected.push(b"zzzz".to_vec());
+    let concat =
+        SsTable::open_for_test(FileObject::open(&dir.path().join("5.sst")).unwrap()).unwrap();
+    assert_eq!(concat.tombstone_bitmap().unwrap().num_entries(), 2001);
+    assert_eq!(skipping(Arc::new(concat), None), expected);
+}
+
+#[test]
+fn test_skip_tombstones_does_not_read_tombstone_blocks() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let table = build(&path, true);
+    let bitmap = table.tombstone_bitmap().unwrap();
+    let live_blocks = (0..table.num_of_blocks())
+        .filter(|blk_idx| {
+            let start = bitmap.block_start(*blk_idx);
+            let end = start + table.block_metas()[*blk_idx].num_entries as usize;
+            (start..end).any(|entry| !bitmap.is_tombstone(entry))
+        })
+        .count();
+    assert!(live_blocks + 10 < table.num_of_blocks());
+
+    // opening a table with a bitmap takes 7 reads, one more read than the number of blocks with
+    // live entries fails
+    let file = FaultyFileObject::from_file(&path)
+        .unwrap()
+        .fail_on_read(7 + live_blocks + 1);
+    let table = Arc::new(SsTable::open_for_test(file.into()).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first_no_cache(table).unwrap();
+    iter.skip_tombstones().unwrap();
+    assert_eq!(collect(iter), live_keys(0));
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::ThreadId;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[derive(Debug)]
+struct SpanRecord {
+    name: &'static str,
+    parent: Option<u64>,
+    fields: HashMap<&'static str, String>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    spans: HashMap<u64, SpanRecord>,
+    events: Vec<(Option<u64>, HashMap<&'static str, String>)>,
+    /// The entered spans of each thread.
+    stacks: HashMap<ThreadId, Vec<u64>>,
+}
+
+impl Recorded {
+    fn spans_named(&self, name: &str) -> Vec<(u64, &SpanRecord)> {
+        let mut spans = self
+            .spans
+            .iter()
+            .filter(|(_, span)| span.name == name)
+            .map(|(id, span)| (*id, span))
+            .collect::<Vec<_>>();
+        spans.sort_by_key(|(id, _)| *id);
+        spans
+    }
+
+    fn current(&mut self) -> &mut Vec<u64> {
+        self.stacks.entry(std::thread::current().id()).or_default()
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+/// A subscriber recording the spans with their parents and fields, and the events.
+#[derive(Clone, Default)]
+struct Recorder {
+    next_id: Arc<AtomicU64>,
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut recorded = self.recorded.lock();
+        let parent = if attrs.is_root() {
+            None
+        } else if let Some(parent) = attrs.parent() {
+            Some(parent.into_u64())
+        } else {
+            recorded.current().last().copied()
+        };
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        recorded.spans.insert(
+            id,
+            SpanRecord {
+                name: attrs.metadata().name(),
+                parent,
+                fields,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut recorded = self.recorded.lock();
+        let span = recorded.spans.get_mut(&span.into_u64()).unwrap();
+        values.record(&mut FieldVisitor(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = HashMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let mut recorded = self.recorded.lock();
+        let parent = recorded.current().last().copied();
+        recorded.events.push((parent, fields));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.recorded.lock().current().push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut recorded = self.recorded.lock();
+        let stack = recorded.current();
+        let idx = stack.iter().rposition(|id| *id == span.into_u64()).unwrap();
+        stack.remove(idx);
+    }
+}
+
+/// Freezes the memtable and flushes every immutable memtable.
+fn flush_all(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    while !storage.state.load().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_tracing_read_write_spans() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        storage.put(b"key", b"value").unwrap();
+        storage.delete(b"other").unwrap();
+        assert!(storage.get(b"key").unwrap().is_some());
+        assert!(storage.get(b"missing_key").unwrap().is_none());
+        storage
+            .scan(Bound::Included(b"k".as_slice()), Bound::Unbounded)
+            .unwrap();
+    });
+
+    let recorded = recorder.recorded.lock();
+    let puts = recorded.spans_named("put");
+    assert_eq!(puts.len(), 1);
+    assert_eq!(puts[0].1.fields["key_len"], "3");
+    assert_eq!(puts[0].1.fields["outcome"], "ok");
+    let deletes = recorded.spans_named("delete");
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].1.fields["key_len"], "5");
+    let gets = recorded.spans_named("get");
+    let outcomes = gets
+        .iter()
+        .map(|(_, span)| {
+            (
+                span.fields["key_len"].as_str(),
+                span.fields["outcome"].as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(outcomes, vec![("3", "found"), ("11", "not_found")]);
+    let scans = recorded.spans_named("scan");
+    assert_eq!(scans.len(), 1);
+    assert_eq!(scans[0].1.fields["lower_key_len"], "1");
+    assert_eq!(scans[0].1.fields["outcome"], "ok");
+    assert!(recorded.stacks.values().all(|stack| stack.is_empty()));
+}
+
+#[test]
+fn test_tracing_flush_and_compaction_spans() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.target_sst_size = 1 << 14;
+    options.max_subcompactions = 2;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let recorder = Recorder::default();
+    let num_flushed = tracing::subscriber::with_default(recorder.clone(), || {
+        for round in 0..2 {
+            for idx in 0..500 {
+                storage
+                    .put(&key_of(idx), format!("value_{:0100}", round).as_bytes())
+                    .unwrap();
+            }
+            flush_all(&storage);
+        }
+        let num_flushed = storage.state.load().l0_sstables.len();
+        storage.force_full_compaction().unwrap();
+        num_flushed
+    });
+
+    let recorded = recorder.recorded.lock();
+    // every memtable, including the ones frozen by the writes, is flushed under its own span
+    let flushes = recorded.spans_named("flush");
+    assert_eq!(flushes.len(), num_flushed);
+    for (_, flush) in &flushes {
+        assert_eq!(flush.parent, None);
+        assert!(flush.fields.contains_key("sst_id"));
+        assert_ne!(flush.fields["input_bytes"], "0");
+        assert_ne!(flush.fields["output_bytes"], "0");
+    }
+
+    let compactions = recorded.spans_named("compaction");
+    assert_eq!(compactions.len(), 1);
+    let (compaction_id, compaction) = compactions[0];
+    assert_eq!(compaction.parent, None);
+    assert!(compaction.fields["task"].contains("ForceFullCompaction"));
+    assert!(compaction.fields.contains_key("job_id"));
+    assert_ne!(compaction.fields["input_bytes"], "0");
+    assert_ne!(compaction.fields["output_bytes"], "0");
+
+    // each output SST, including the ones built by sub-compactions on other threads, is a
+    // child of the compaction
+    let outputs = recorded.spans_named("compaction_output");
+    let l1 = &storage.state.load().levels[0].1;
+    assert!(l1.len() > 1);
+    assert_eq!(outputs.len(), l1.len());
+    for (_, output) in &outputs {
+        assert_eq!(output.parent, Some(compaction_id));
+        assert!(l1.contains(&output.fields["sst_id"].parse().unwrap()));
+        assert_ne!(output.fields["output_bytes"], "0");
+    }
+    let output_bytes = outputs
+        .iter()
+        .map(|(_, output)| output.fields["output_bytes"].parse::<u64>().unwrap())
+        .sum::<u64>();
+    assert_eq!(compaction.fields["output_bytes"], output_bytes.to_string());
+
+    // the writes are not nested in any background span
+    for (_, put) in recorded.spans_named("put") {
+        assert_eq!(put.parent, None);
+    }
+    assert!(recorded.stacks.values().all(|stack| stack.is_empty()));
+}
+
+#[test]
+fn test_tracing_write_stall_event() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.target_sst_size = 1 << 12;
+    options.num_memtable_limit = 2;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        // nothing flushes the frozen memtables
+        for idx in 0..500 {
+            storage
+                .put(&key_of(idx), format!("value_{:0100}", idx).as_bytes())
+                .unwrap();
+        }
+    });
+
+    let recorded = recorder.recorded.lock();
+    let stall = recorded
+        .events
+        .iter()
+        .find(|(_, fields)| fields.contains_key("num_imm_memtables"))
+        .expect("no write stall event");
+    assert_eq!(stall.1["num_memtable_limit"], "2");
+    // the stall is reported within the write that froze the memtable
+    assert_eq!(recorded.spans[&stall.0.unwrap()].name, "put");
+}
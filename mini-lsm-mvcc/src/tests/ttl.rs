@@ -0,0 +1,146 @@
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::clock::MockClock;
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+use crate::table::SsTableIterator;
+
+fn options_with(clock: Arc<MockClock>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.clock = clock;
+    options
+}
+
+fn flush(storage: &LsmStorageInner) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+/// The user keys of all the entries in the SSTs, including tombstones.
+fn keys_on_disk(storage: &LsmStorageInner) -> Vec<Bytes> {
+    let state = storage.state.load_full();
+    let mut keys = Vec::new();
+    for table in state.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+        while iter.is_valid() {
+            keys.push(Bytes::copy_from_slice(iter.key().key_ref()));
+            iter.next().unwrap();
+        }
+    }
+    keys
+}
+
+#[test]
+fn test_put_with_ttl() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(1000));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options_with(clock.clone())).unwrap());
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage.put_with_ttl(b"b", b"2", Duration::ZERO).unwrap();
+    storage
+        .put_with_ttl(b"c", b"3", Duration::from_secs(10))
+        .unwrap();
+    // a put without a TTL replaces the expiry time
+    storage.put(b"c", b"4").unwrap();
+    storage
+        .put_with_ttl(b"d", b"5", Duration::from_millis(10500))
+        .unwrap();
+
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("c"), Bytes::from("4")),
+            (Bytes::from("d"), Bytes::from("5")),
+        ],
+    );
+
+    // keys expire at their expiry time, rounded up to the second
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("4")));
+    assert_eq!(storage.get(b"d").unwrap(), Some(Bytes::from("5")));
+    let expected = vec![
+        (Bytes::from("c"), Bytes::from("4")),
+        (Bytes::from("d"), Bytes::from("5")),
+    ];
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected.clone(),
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected.into_iter().rev().collect(),
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(storage.get(b"d").unwrap(), None);
+    assert_eq!(
+        storage
+            .multi_get(&[b"a".as_slice(), b"c".as_slice()])
+            .unwrap(),
+        vec![None, Some(Bytes::from("4"))]
+    );
+}
+
+#[test]
+fn test_ttl_expiry_between_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(1000));
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options_with(clock.clone())).unwrap());
+    storage.put(b"a", b"old").unwrap();
+    flush(&storage);
+    storage
+        .put_with_ttl(b"a", b"new", Duration::from_secs(10))
+        .unwrap();
+    storage
+        .put_with_ttl(b"b", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage.put(b"c", b"1").unwrap();
+    flush(&storage);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("new")));
+
+    // an expired value deletes the older versions as well
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert!(keys_on_disk(&storage).contains(&Bytes::from("b")));
+
+    storage.force_full_compaction().unwrap();
+    assert_eq!(keys_on_disk(&storage), vec![Bytes::from("c")]);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_ttl_recovered_from_wal() {
+    let dir = tempdir().unwrap();
+    let clock = Arc::new(MockClock::new(1000));
+    let mut options = options_with(clock.clone());
+    options.enable_wal = true;
+    let storage = MiniLsm::open(dir.path(), options.clone()).unwrap();
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(10))
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+}
@@ -0,0 +1,81 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn value_of(i: usize) -> Vec<u8> {
+    format!("value_{:050}", i).into_bytes()
+}
+
+#[test]
+fn test_block_ref_value_bytes() {
+    let mut builder = BlockBuilder::new(4096);
+    for i in 0..20 {
+        let key = format!("key_{:03}", i);
+        assert!(builder.add(KeySlice::from_slice(key.as_bytes(), 1), &value_of(i)));
+    }
+    let raw = builder.build().encode();
+    let block = Arc::new(Block::decode_bytes(raw.clone()).unwrap());
+    let mut iter = BlockIterator::create_and_seek_to_first_ref(block.clone());
+    let mut values = Vec::new();
+    while iter.is_valid() {
+        values.push(iter.value_bytes());
+        iter.next();
+    }
+    drop(iter);
+    drop(block);
+    // the values are slices of the buffer the block was decoded from, kept past the iterator
+    let raw_range = raw.as_ptr() as usize..raw.as_ptr() as usize + raw.len();
+    assert_eq!(values.len(), 20);
+    for (i, value) in values.iter().enumerate() {
+        assert_eq!(value, &value_of(i));
+        assert!(raw_range.contains(&(value.as_ptr() as usize)));
+    }
+}
+
+#[test]
+fn test_value_bytes_outlive_next() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    // values in a level, L0 and the memtable
+    for i in 0..300 {
+        storage
+            .put(format!("key_{:03}", i).as_bytes(), &value_of(i))
+            .unwrap();
+        if i == 100 || i == 200 {
+            storage
+                .force_freeze_memtable(&storage.state_lock.lock())
+                .unwrap();
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+        if i == 100 {
+            storage.force_full_compaction().unwrap();
+        }
+    }
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key_150", b"local");
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut values = Vec::new();
+    while iter.is_valid() {
+        values.push((Bytes::copy_from_slice(iter.key()), iter.value_bytes()));
+        iter.next().unwrap();
+    }
+    drop(iter);
+    assert_eq!(values.len(), 300);
+    for (i, (key, value)) in values.iter().enumerate() {
+        assert_eq!(key, format!("key_{:03}", i).as_bytes());
+        if i == 150 {
+            assert_eq!(value, "local");
+        } else {
+            assert_eq!(value, &value_of(i));
+        }
+    }
+}
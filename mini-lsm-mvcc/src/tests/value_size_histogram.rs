@@ -0,0 +1,62 @@
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::table::{SsTable, SsTableBuilder, ValueSizeHistogram, VALUE_SIZE_BUCKETS};
+
+#[test]
+fn test_value_size_histogram() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    // (value size, number of values), tombstones being values of 0 bytes
+    let sizes = [
+        (0, 7),
+        (1, 3),
+        (3, 5),
+        (100, 10),
+        (127, 1),
+        (128, 2),
+        (4095, 1),
+        (40000, 2),
+        (200000, 1),
+    ];
+    let mut idx = 0;
+    for (size, count) in sizes {
+        for _ in 0..count {
+            builder.add(
+                KeySlice::from_slice(format!("key_{:04}", idx).as_bytes(), 1),
+                &vec![b'x'; size],
+            );
+            idx += 1;
+        }
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+
+    let mut buckets = [0; VALUE_SIZE_BUCKETS];
+    buckets[0] = 3; // 1
+    buckets[1] = 5; // 2..=3
+    buckets[6] = 11; // 64..=127
+    buckets[7] = 2; // 128..=255
+    buckets[11] = 1; // 2048..=4095
+    buckets[15] = 3; // 32768 and more
+    let expected = ValueSizeHistogram {
+        min: 1,
+        max: 200000,
+        sum: 3 + 15 + 1000 + 127 + 256 + 4095 + 80000 + 200000,
+        buckets,
+    };
+    assert_eq!(sst.value_size_histogram(), &expected);
+    assert_eq!(expected.count(), 25);
+    assert_eq!(sst.properties().num_tombstones, 7);
+    assert!((expected.mean() - expected.sum as f64 / 25.0).abs() < 1e-9);
+
+    // persisted along with the other properties
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(sst.value_size_histogram(), &expected);
+
+    // a table of tombstones only has no values
+    let mut builder = SsTableBuilder::new(4096);
+    builder.add(KeySlice::from_slice(b"key", 1), b"");
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    assert_eq!(sst.value_size_histogram(), &ValueSizeHistogram::default());
+    assert_eq!(sst.value_size_histogram().mean(), 0.0);
+}
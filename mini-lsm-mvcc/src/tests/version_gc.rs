@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::harness::{construct_merge_iterator_over_storage, sync};
+
+/// Returns all versions of `key` stored in the SSTs, from the latest to the earliest.
+fn versions_of(storage: &LsmStorageInner, key: &[u8]) -> Vec<(u64, Bytes)> {
+    let mut iter = construct_merge_iterator_over_storage(&storage.state.load());
+    let mut versions = Vec::new();
+    while iter.is_valid() {
+        if iter.key().key_ref() == key {
+            versions.push((iter.key().ts(), Bytes::copy_from_slice(iter.value())));
+        }
+        iter.next().unwrap();
+    }
+    versions
+}
+
+#[test]
+fn test_snapshot_pins_old_versions() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let mut snapshot = None;
+    for version in 1..=5 {
+        storage
+            .put(b"key", format!("value_{}", version).as_bytes())
+            .unwrap();
+        sync(&storage);
+        if version == 2 {
+            snapshot = Some(storage.new_txn().unwrap());
+        }
+    }
+    let snapshot = snapshot.unwrap();
+
+    storage.force_full_compaction().unwrap();
+    // versions 3..=5 are above the watermark, version 2 is the newest one below it
+    let versions = versions_of(&storage, b"key");
+    assert_eq!(
+        versions.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>(),
+        vec![
+            Bytes::from("value_5"),
+            Bytes::from("value_4"),
+            Bytes::from("value_3"),
+            Bytes::from("value_2"),
+        ]
+    );
+    assert_eq!(snapshot.get(b"key").unwrap(), Some(Bytes::from("value_2")));
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    let versions = versions_of(&storage, b"key");
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].1, Bytes::from("value_5"));
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value_5")));
+}
+
+fn sst_size(storage: &LsmStorageInner) -> u64 {
+    let state = storage.state.load();
+    state.sstables.values().map(|sst| sst.table_size()).sum()
+}
+
+#[test]
+fn test_snapshot_survives_overwrites_and_deletes() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let key = |i: usize| format!("key_{:03}", i);
+    for i in 0..100 {
+        storage
+            .put(key(i).as_bytes(), format!("old_{:0128}", i).as_bytes())
+            .unwrap();
+    }
+    sync(&storage);
+    let snapshot = storage.new_txn().unwrap();
+
+    // overwrite the even keys and delete the odd ones
+    for i in 0..100 {
+        if i % 2 == 0 {
+            storage
+                .put(key(i).as_bytes(), format!("new_{:0128}", i).as_bytes())
+                .unwrap();
+        } else {
+            storage.delete(key(i).as_bytes()).unwrap();
+        }
+    }
+    sync(&storage);
+
+    // the full compaction writes to the bottom level, but neither the old versions nor the
+    // tombstones shadowing them can be dropped while the snapshot is alive
+    storage.force_full_compaction().unwrap();
+    for i in 0..100 {
+        assert_eq!(
+            snapshot.get(key(i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("old_{:0128}", i)))
+        );
+    }
+    let mut iter = snapshot
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    for i in 0..100 {
+        assert_eq!(iter.key(), key(i).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    drop(iter);
+    assert_eq!(versions_of(&storage, key(1).as_bytes()).len(), 2);
+    assert_eq!(versions_of(&storage, key(2).as_bytes()).len(), 2);
+    let size_with_snapshot = sst_size(&storage);
+
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    for i in 0..100 {
+        let expected = (i % 2 == 0).then(|| Bytes::from(format!("new_{:0128}", i)));
+        assert_eq!(storage.get(key(i).as_bytes()).unwrap(), expected);
+        assert_eq!(
+            versions_of(&storage, key(i).as_bytes()).len(),
+            (i % 2 == 0) as usize
+        );
+    }
+    assert!(sst_size(&storage) * 2 < size_with_snapshot);
+}
+
+#[test]
+fn test_versions_never_split_across_ssts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 64;
+    options.target_sst_size = 1024;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    // keep every version alive
+    let _snapshot = storage.new_txn().unwrap();
+    for version in 0..50 {
+        for key in 0..10 {
+            storage
+                .put(
+                    format!("key_{:02}", key).as_bytes(),
+                    format!("value_{:02}_{:064}", key, version).as_bytes(),
+                )
+                .unwrap();
+        }
+        sync(&storage);
+    }
+    storage.force_full_compaction().unwrap();
+
+    let snapshot = storage.state.load_full();
+    let l1 = &snapshot.levels[0].1;
+    assert!(l1.len() > 1);
+    for pair in l1.windows(2) {
+        let left = &snapshot.sstables[&pair[0]];
+        let right = &snapshot.sstables[&pair[1]];
+        assert!(left.last_key().key_ref() < right.first_key().key_ref());
+    }
+    for key in 0..10 {
+        assert_eq!(
+            versions_of(&storage, format!("key_{:02}", key).as_bytes()).len(),
+            50
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use moka::sync::ConcurrentCacheExt;
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::lsm_storage::{block_size_weigher, new_block_cache, BlockCache};
+use crate::table::{SsTable, SsTableBuilder};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// Builds a table with the keys `key_of(100..200)`, reading through `block_cache`.
+fn build(path: &std::path::Path, block_cache: Option<Arc<BlockCache>>) -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 100..200 {
+        builder.add(KeySlice::from_slice(&key_of(idx), 1), b"value");
+    }
+    builder.build(1, block_cache, path.join("1.sst")).unwrap()
+}
+
+/// The blocks of `table` holding keys in `lower..upper`.
+fn covering_blocks(table: &SsTable, lower: &[u8], upper: &[u8]) -> Vec<usize> {
+    (0..table.num_of_blocks())
+        .filter(|idx| {
+            let meta = &table.block_metas()[*idx];
+            meta.last_key.key_ref() >= lower && meta.first_key.key_ref() < upper
+        })
+        .collect()
+}
+
+#[test]
+fn test_warm_range() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(new_block_cache(1 << 20, block_size_weigher));
+    let table = build(dir.path(), Some(block_cache.clone()));
+    assert!(table.num_of_blocks() > 4);
+
+    let (lower, upper) = (key_of(130), key_of(160));
+    let covering = covering_blocks(&table, &lower, &upper);
+    let warmed = table
+        .warm_range(Bound::Included(&lower), Bound::Excluded(&upper))
+        .unwrap();
+    assert_eq!(warmed, covering.len());
+    for idx in 0..table.num_of_blocks() {
+        assert_eq!(
+            table.get_cached_block(idx).is_some(),
+            covering.contains(&idx),
+            "block {}",
+            idx
+        );
+    }
+
+    // warming again loads nothing new
+    assert_eq!(
+        table
+            .warm_range(Bound::Included(&lower), Bound::Excluded(&upper))
+            .unwrap(),
+        covering.len()
+    );
+    block_cache.sync();
+    assert_eq!(block_cache.entry_count(), covering.len() as u64);
+
+    // the whole table
+    assert_eq!(
+        table
+            .warm_range(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        table.num_of_blocks()
+    );
+}
+
+#[test]
+fn test_warm_range_without_overlap() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(new_block_cache(1 << 20, block_size_weigher));
+    let table = build(dir.path(), Some(block_cache.clone()));
+    for (lower, upper) in [
+        (Bound::Unbounded, Bound::Excluded(&key_of(100)[..])),
+        (Bound::Excluded(&key_of(199)[..]), Bound::Unbounded),
+        (Bound::Included(&b"zzz"[..]), Bound::Included(&b"zzzz"[..])),
+        (Bound::Included(&b"a"[..]), Bound::Included(&b"b"[..])),
+    ] {
+        assert_eq!(table.warm_range(lower, upper).unwrap(), 0);
+    }
+    block_cache.sync();
+    assert_eq!(block_cache.entry_count(), 0);
+
+    // a table without a block cache warms nothing
+    let dir = tempdir().unwrap();
+    let table = build(dir.path(), None);
+    assert_eq!(
+        table
+            .warm_range(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        0
+    );
+}
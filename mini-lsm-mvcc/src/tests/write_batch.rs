@@ -0,0 +1,131 @@
+use std::fs::OpenOptions;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, WriteBatchRecord};
+
+const NUM_KEYS: usize = 200;
+
+/// A batch setting every key to `round`, and deleting every third key in odd rounds.
+fn batch(round: usize) -> Vec<WriteBatchRecord<Vec<u8>>> {
+    (0..NUM_KEYS)
+        .map(|i| {
+            let key = format!("key_{:03}", i).into_bytes();
+            if round % 2 == 1 && i % 3 == 0 {
+                WriteBatchRecord::Del(key)
+            } else {
+                WriteBatchRecord::Put(key, format!("round_{:05}", round).into_bytes())
+            }
+        })
+        .collect()
+}
+
+/// Checks that a scan sees exactly the state after some batch, and returns its round.
+fn check_batch_boundary(storage: &Arc<LsmStorageInner>) -> Option<usize> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    let (_, value) = entries.first()?;
+    let round = std::str::from_utf8(&value[6..]).unwrap().parse().unwrap();
+    let expected = batch(round)
+        .into_iter()
+        .filter_map(|record| match record {
+            WriteBatchRecord::Put(key, value) => Some((Bytes::from(key), Bytes::from(value))),
+            WriteBatchRecord::Del(_)
+            | WriteBatchRecord::Merge(..)
+            | WriteBatchRecord::PutWithTtl(..) => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(entries, expected, "partial batch observed");
+    Some(round)
+}
+
+#[test]
+fn test_concurrent_reads_see_whole_batches() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // freeze the memtable every few batches
+    options.target_sst_size = 1 << 14;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers = (0..4)
+        .map(|_| {
+            let storage = storage.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut last_round = None;
+                while !done.load(Ordering::Acquire) {
+                    let round = check_batch_boundary(&storage);
+                    assert!(round >= last_round, "went back in time");
+                    last_round = round;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for round in 0..200 {
+        storage.write_batch(&batch(round)).unwrap();
+    }
+    done.store(true, Ordering::Release);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert!(!storage.state.load().imm_memtables.is_empty());
+    assert_eq!(check_batch_boundary(&storage), Some(199));
+}
+
+#[test]
+fn test_recovery_drops_torn_batch() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options.clone()).unwrap());
+    storage.write_batch(&batch(1)).unwrap();
+    storage.sync().unwrap();
+    let wal_path = storage.path_of_wal(storage.state.load().memtable.id());
+    let committed_len = std::fs::metadata(&wal_path).unwrap().len();
+    storage.write_batch(&batch(2)).unwrap();
+    storage.sync().unwrap();
+    let full_len = std::fs::metadata(&wal_path).unwrap().len();
+    drop(storage);
+
+    // a crash in the middle of writing the second batch leaves a prefix of its record
+    for len in [
+        committed_len + 3,
+        (committed_len + full_len) / 2,
+        full_len - 1,
+    ] {
+        let copy = tempdir().unwrap();
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), copy.path().join(entry.file_name())).unwrap();
+        }
+        let wal_copy = copy.path().join(wal_path.file_name().unwrap());
+        OpenOptions::new()
+            .write(true)
+            .open(&wal_copy)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+        let storage = Arc::new(LsmStorageInner::open(copy.path(), options.clone()).unwrap());
+        assert_eq!(check_batch_boundary(&storage), Some(1));
+        // the engine writes after the committed batches
+        storage.write_batch(&batch(3)).unwrap();
+        assert_eq!(check_batch_boundary(&storage), Some(3));
+    }
+
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    assert_eq!(check_batch_boundary(&storage), Some(2));
+}
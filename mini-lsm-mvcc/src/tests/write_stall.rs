@@ -0,0 +1,160 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::event_listener::EventListener;
+use crate::lsm_storage::{ClosedError, LsmStorageOptions, MiniLsm, WriteOptions};
+use crate::write_stall::{WouldBlock, WriteStallCause, WriteStallOptions};
+
+#[derive(Default)]
+struct StallListener {
+    reasons: Mutex<Vec<String>>,
+}
+
+impl EventListener for StallListener {
+    fn on_write_stall(&self, reason: &str) {
+        self.reasons.lock().push(reason.to_string());
+    }
+}
+
+fn options(listener: Arc<StallListener>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.write_stall = WriteStallOptions {
+        l0_slowdown_trigger: 2,
+        l0_stop_trigger: 4,
+        slowdown_delay: Duration::from_millis(20),
+        ..Default::default()
+    };
+    options.event_listeners.push(listener);
+    options
+}
+
+const NO_STALL: WriteOptions = WriteOptions { no_stall: true };
+
+/// The stall a write with [`WriteOptions::no_stall`] fails with, if any.
+fn try_put(storage: &MiniLsm, key: &[u8]) -> Option<WouldBlock> {
+    match storage.put_with_options(key, b"value", &NO_STALL) {
+        Ok(()) => None,
+        Err(e) => Some(*e.downcast_ref::<WouldBlock>().unwrap()),
+    }
+}
+
+/// Writes a key and flushes it to a new L0 SST.
+fn flush_key(storage: &MiniLsm, key: &[u8]) {
+    storage.put(key, b"value").unwrap();
+    storage.force_flush().unwrap();
+}
+
+#[test]
+fn test_writes_slow_down_stop_and_recover() {
+    let dir = tempdir().unwrap();
+    let listener = Arc::new(StallListener::default());
+    let storage = MiniLsm::open(dir.path(), options(listener.clone())).unwrap();
+    storage.pause_background_compaction();
+
+    // below the slowdown trigger, nothing is delayed
+    flush_key(&storage, b"a");
+    assert_eq!(try_put(&storage, b"b"), None);
+    assert_eq!(storage.compaction_stats().num_delayed_writes, 0);
+
+    // from the slowdown trigger, each write is delayed, more so with every L0 SST over it
+    flush_key(&storage, b"c");
+    let stall = try_put(&storage, b"d").unwrap().0;
+    assert_eq!(stall.cause, WriteStallCause::L0Files);
+    assert!(!stall.stopped);
+    assert_eq!((stall.count, stall.trigger), (2, 2));
+    let begin = Instant::now();
+    storage.put(b"d", b"value").unwrap();
+    assert!(begin.elapsed() >= Duration::from_millis(20));
+    storage.force_flush().unwrap();
+    let begin = Instant::now();
+    storage.put(b"e", b"value").unwrap();
+    assert!(begin.elapsed() >= Duration::from_millis(40));
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_delayed_writes, 2);
+    assert!(stats.write_delayed_time >= Duration::from_millis(60));
+    assert_eq!(stats.num_stopped_writes, 0);
+
+    // from the stop trigger, writes wait for compaction
+    storage.force_flush().unwrap();
+    let stall = try_put(&storage, b"f").unwrap().0;
+    assert!(stall.stopped);
+    assert_eq!((stall.count, stall.trigger), (4, 4));
+    assert_eq!(storage.compaction_stats().last_write_stall, Some(stall));
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"f", b"value"))
+    };
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!writer.is_finished());
+    assert_eq!(storage.get(b"f").unwrap(), None);
+
+    // compaction catching up lets the write through
+    storage.resume_background_compaction();
+    writer.join().unwrap().unwrap();
+    assert_eq!(
+        storage.get(b"f").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    assert!(storage.inner.state.load().l0_sstables.len() < 2);
+    assert_eq!(try_put(&storage, b"g"), None);
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_stopped_writes, 1);
+    assert!(stats.write_stopped_time >= Duration::from_millis(300));
+    assert_eq!(stats.last_write_stall, None);
+
+    let reasons = listener.reasons.lock().clone();
+    assert!(
+        reasons[0].starts_with("writes slowed down: 2 L0 SSTs"),
+        "{:?}",
+        reasons
+    );
+    assert!(
+        reasons
+            .iter()
+            .any(|reason| reason == "writes stopped: 4 L0 SSTs reached the trigger of 4"),
+        "{:?}",
+        reasons
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_close_fails_stopped_writes() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options(Arc::new(StallListener::default()))).unwrap();
+    storage.pause_background_compaction();
+    for key in [b"a", b"b", b"c", b"d"] {
+        flush_key(&storage, key);
+    }
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || storage.put(b"e", b"value"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    storage.close().unwrap();
+    let err = writer.join().unwrap().unwrap_err();
+    assert!(err.is::<ClosedError>(), "{}", err);
+}
+
+#[test]
+fn test_write_stall_options_are_validated() {
+    let dir = tempdir().unwrap();
+    let mut options = options(Arc::new(StallListener::default()));
+    options.write_stall.l0_slowdown_trigger = 4;
+    assert!(MiniLsm::open(dir.path(), options.clone()).is_err());
+    options.write_stall.l0_slowdown_trigger = 2;
+    options.write_stall.imm_stop_trigger = 1;
+    assert!(MiniLsm::open(dir.path(), options).is_err());
+}
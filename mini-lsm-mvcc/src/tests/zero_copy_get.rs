@@ -0,0 +1,62 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::CacheHint;
+
+fn ptr_range(data: &[u8]) -> Range<usize> {
+    let begin = data.as_ptr() as usize;
+    begin..begin + data.len()
+}
+
+#[test]
+fn test_get_shares_cached_block() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    for i in 0..100 {
+        storage
+            .put(
+                format!("key_{:03}", i).as_bytes(),
+                format!("value_{:0100}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+
+    let key = b"key_042";
+    let value = storage.get(key).unwrap().unwrap();
+    assert_eq!(value, format!("value_{:0100}", 42).as_bytes());
+    let table = {
+        let state = storage.state.load();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    let block_idx = table.find_block_idx(KeySlice::from_slice(key, 0)).unwrap();
+    let block = table.read_block_cached(block_idx, CacheHint::Fill).unwrap();
+    let block_range = ptr_range(&block.data);
+    let value_range = ptr_range(&value);
+    assert!(
+        block_range.start <= value_range.start && value_range.end <= block_range.end,
+        "value at {:?} outside of the cached block at {:?}",
+        value_range,
+        block_range
+    );
+
+    // the copied value has its own allocation
+    let copied = storage.get_copied(key).unwrap().unwrap();
+    assert_eq!(copied, value);
+    let copied_range = ptr_range(&copied);
+    assert!(copied_range.end <= block_range.start || block_range.end <= copied_range.start);
+
+    // the value outlives the block cache entry
+    storage.block_cache.invalidate_all();
+    drop(block);
+    assert_eq!(value, format!("value_{:0100}", 42).as_bytes());
+}
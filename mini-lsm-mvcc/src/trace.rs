@@ -0,0 +1,108 @@
+//! Spans and events for the `tracing` crate, emitted with the `tracing` feature. Without it,
+//! [`trace_span!`] returns a [`Span`] that does nothing and the events are empty functions, so
+//! that the instrumentation costs nothing and the dependency is not needed. Spans and events are
+//! never emitted with the state lock or the memtable locks held.
+
+#[cfg(feature = "tracing")]
+pub use tracing::Span;
+
+/// A span that records nothing, standing for [`tracing::Span`] without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub fn enter(&self) -> Entered {
+        Entered
+    }
+
+    pub fn record<V>(&self, _field: &str, _value: V) -> &Self {
+        self
+    }
+}
+
+/// The guard of an entered [`Span`].
+#[cfg(not(feature = "tracing"))]
+pub struct Entered;
+
+/// The current span and subscriber of a thread, to trace the work it hands to other threads
+/// under the same span.
+#[derive(Clone)]
+pub(crate) struct SpanContext {
+    span: Span,
+    #[cfg(feature = "tracing")]
+    dispatch: tracing::Dispatch,
+}
+
+impl SpanContext {
+    /// Captures `span` with the subscriber of the current thread. The span is given explicitly
+    /// rather than taken from `Span::current`, which only subscribers tracking the entered spans
+    /// know.
+    pub(crate) fn new(span: &Span) -> Self {
+        Self {
+            span: span.clone(),
+            #[cfg(feature = "tracing")]
+            dispatch: tracing::dispatcher::get_default(|dispatch| dispatch.clone()),
+        }
+    }
+
+    /// Runs `f` in the span, with the subscriber of the thread that captured the context.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        tracing::dispatcher::with_default(&self.dispatch, || self.span.in_scope(f))
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _entered = self.span.enter();
+        f()
+    }
+}
+
+/// Creates an info-level span, see [`tracing::info_span!`].
+macro_rules! trace_span {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+
+/// Records the outcome of an operation in the `outcome` field of `span`.
+pub(crate) fn record_outcome<T>(span: &Span, result: &anyhow::Result<T>, ok: &'static str) {
+    span.record("outcome", if result.is_ok() { ok } else { "error" });
+}
+
+/// The immutable memtables reached `num_memtable_limit`, so that the flushes fall behind the
+/// writes.
+pub(crate) fn write_stall(num_imm_memtables: usize, num_memtable_limit: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        num_imm_memtables,
+        num_memtable_limit,
+        "write stall: too many immutable memtables"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (num_imm_memtables, num_memtable_limit);
+}
+
+/// A block of an SST failed its checksum.
+pub(crate) fn checksum_failure(sst_id: usize, block_idx: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(sst_id, block_idx, "block checksum mismatched");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (sst_id, block_idx);
+}
+
+/// A background flush or compaction failed.
+pub(crate) fn background_error(job: &'static str, error: &anyhow::Error) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(job, error = %format!("{:#}", error), "background job failed");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (job, error);
+}
+
+pub(crate) use trace_span;
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes};
+
+use crate::block::ENTRY_FLAG_TTL;
+
+/// The length of the expiry time at the end of the values of entries flagged with
+/// [`ENTRY_FLAG_TTL`], in seconds since the Unix epoch.
+const EXPIRY_LEN: usize = std::mem::size_of::<u64>();
+
+/// The expiry time of a value written at `now_secs` to live for `ttl`, rounded up to the next
+/// second. A value with a TTL of zero is expired as soon as it is written.
+pub fn expiry_time(now_secs: u64, ttl: Duration) -> u64 {
+    let ttl_secs = ttl.as_secs() + (ttl.subsec_nanos() > 0) as u64;
+    now_secs.saturating_add(ttl_secs)
+}
+
+/// Appends `expires_at` to `value`, to be stored in an entry flagged with [`ENTRY_FLAG_TTL`].
+pub fn encode_value_with_expiry(value: &[u8], expires_at: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.len() + EXPIRY_LEN);
+    buf.put_slice(value);
+    buf.put_u64(expires_at);
+    buf
+}
+
+/// The expiry time of an entry with `flags` whose stored value is `value`, if it has one.
+pub fn expiry_of(flags: u8, value: &[u8]) -> Option<u64> {
+    if flags & ENTRY_FLAG_TTL == 0 {
+        return None;
+    }
+    let expiry = &value[value.len() - EXPIRY_LEN..];
+    Some(u64::from_be_bytes(expiry.try_into().unwrap()))
+}
+
+/// Whether an entry with `flags` whose stored value is `value` has expired at `now_secs`, in
+/// which case it reads as a tombstone.
+pub fn is_expired(flags: u8, value: &[u8], now_secs: u64) -> bool {
+    matches!(expiry_of(flags, value), Some(expires_at) if expires_at <= now_secs)
+}
+
+/// The value written by the user of an entry with `flags` whose stored value is `value`, i.e.
+/// without its expiry time.
+pub fn user_value(flags: u8, value: &[u8]) -> &[u8] {
+    if flags & ENTRY_FLAG_TTL == 0 {
+        return value;
+    }
+    &value[..value.len() - EXPIRY_LEN]
+}
+
+/// Like [`user_value`], sharing the allocation of `value`.
+pub fn user_value_bytes(flags: u8, value: Bytes) -> Bytes {
+    if flags & ENTRY_FLAG_TTL == 0 {
+        return value;
+    }
+    value.slice(..value.len() - EXPIRY_LEN)
+}
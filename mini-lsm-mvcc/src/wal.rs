@@ -1,5 +1,4 @@
 use std::fs::{File, OpenOptions};
-use std::hash::Hasher;
 use std::io::{BufWriter, Read, Write};
 use std::path::Path;
 use std::sync::Arc;
@@ -9,12 +8,31 @@ use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
+use crate::block::implied_entry_flags;
 use crate::key::{KeyBytes, KeySlice};
+use crate::range_tombstone::RangeTombstone;
+
+/// The key length marking an entry with flags, which are stored before the key length. User keys
+/// are shorter, as all lengths are stored in a `u16`. Only the flags other than those implied by
+/// the value are stored, so an empty value without the marker is a tombstone.
+const FLAGGED_ENTRY: u16 = u16::MAX;
 
 pub struct Wal {
     file: Arc<Mutex<BufWriter<File>>>,
 }
 
+/// The part of a batch across shards written to one WAL, see [`Wal::put_batch_part`]. Replaying
+/// the WAL collects it rather than applying it, as it may only be applied once every other part
+/// of the batch is known to be durable.
+pub struct WalBatchPart {
+    /// The sequence number of the batch, which is its commit timestamp.
+    pub seq: u64,
+    /// The ids of the memtables the parts of the batch were written to, one per shard.
+    pub memtable_ids: Vec<usize>,
+    /// The entries of the part, with their flags if they differ from those implied by the value.
+    pub(crate) entries: Vec<(KeyBytes, Bytes, Option<u8>)>,
+}
+
 impl Wal {
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
         Ok(Self {
@@ -29,58 +47,218 @@ impl Wal {
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Replays the batches in the WAL into `skiplist`, the flags of the entries having any other
+    /// than those implied by their values into `entry_flags`, and the range tombstones into `range_tombstones`. A batch is applied only if
+    /// it was written entirely: a batch cut short at the end of the file, by a crash while it was
+    /// written, is dropped as a whole. The parts of batches across shards are collected into
+    /// `batch_parts` instead, for the caller to apply.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        entry_flags: &SkipMap<KeyBytes, u8>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        batch_parts: &mut Vec<WalBatchPart>,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .read(true)
             .append(true)
             .open(path)
             .context("failed to recover from WAL")?;
+        Self::replay_file(&file, skiplist, entry_flags, range_tombstones, batch_parts)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Replays the batches in the WAL like [`Self::recover`], without opening it for writing, e.g.
+    /// while the engine owning it appends to it. A batch still being written is dropped.
+    pub fn replay(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        entry_flags: &SkipMap<KeyBytes, u8>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        batch_parts: &mut Vec<WalBatchPart>,
+    ) -> Result<()> {
+        let file = File::open(path.as_ref()).context("failed to replay WAL")?;
+        Self::replay_file(&file, skiplist, entry_flags, range_tombstones, batch_parts)
+    }
+
+    fn replay_file(
+        mut file: &File,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        entry_flags: &SkipMap<KeyBytes, u8>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        batch_parts: &mut Vec<WalBatchPart>,
+    ) -> Result<()> {
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
         let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let mut hasher = crc32fast::Hasher::new();
-            let key_len = rbuf.get_u16() as usize;
-            hasher.write_u16(key_len as u16);
-            let key = Bytes::copy_from_slice(&rbuf[..key_len]);
-            hasher.write(&key);
-            rbuf.advance(key_len);
-            let ts = rbuf.get_u64();
-            hasher.write_u64(ts);
-            let value_len = rbuf.get_u16() as usize;
-            hasher.write_u16(value_len as u16);
-            let value = Bytes::copy_from_slice(&rbuf[..value_len]);
-            hasher.write(&value);
-            rbuf.advance(value_len);
+        while rbuf.remaining() >= std::mem::size_of::<u32>() {
+            let batch_len = (&rbuf[..]).get_u32() as usize;
+            if rbuf.remaining() < std::mem::size_of::<u32>() * 2 + batch_len {
+                break;
+            }
+            rbuf.advance(std::mem::size_of::<u32>());
+            let mut body = &rbuf[..batch_len];
+            rbuf.advance(batch_len);
             let checksum = rbuf.get_u32();
-            if hasher.finalize() != checksum {
+            if crc32fast::hash(body) != checksum {
                 bail!("checksum mismatch");
             }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            let mut entries = Vec::new();
+            let mut tombstones = Vec::new();
+            let mut part = None;
+            while body.has_remaining() {
+                let mut key_len = body.get_u16();
+                if key_len == 0 {
+                    tombstones.push(RangeTombstone::decode(&mut body));
+                    continue;
+                }
+                let mut flags = None;
+                if key_len == FLAGGED_ENTRY {
+                    flags = Some(body.get_u8());
+                    key_len = body.get_u16();
+                    if key_len == 0 {
+                        let seq = body.get_u64();
+                        let num_memtables = body.get_u16() as usize;
+                        let memtable_ids = (0..num_memtables)
+                            .map(|_| body.get_u64() as usize)
+                            .collect::<Vec<_>>();
+                        part = Some((seq, memtable_ids));
+                        continue;
+                    }
+                }
+                let key = Bytes::copy_from_slice(&body[..key_len as usize]);
+                body.advance(key_len as usize);
+                let ts = body.get_u64();
+                let value_len = body.get_u16() as usize;
+                let value = Bytes::copy_from_slice(&body[..value_len]);
+                body.advance(value_len);
+                entries.push((KeyBytes::from_bytes_with_ts(key, ts), value, flags));
+            }
+            if let Some((seq, memtable_ids)) = part {
+                batch_parts.push(WalBatchPart {
+                    seq,
+                    memtable_ids,
+                    entries,
+                });
+                continue;
+            }
+            for (key, value, flags) in entries {
+                if let Some(flags) = flags {
+                    entry_flags.insert(key.clone(), flags);
+                }
+                skiplist.insert(key, value);
+            }
+            range_tombstones.extend(tombstones);
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+        Ok(())
     }
 
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.put_batch(&[(key, value)])
+    }
+
+    /// Writes the entries as a single record, which recovery replays entirely or not at all. An
+    /// empty value is a tombstone.
+    pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        self.put_batch_with_flags(
+            data.iter()
+                .map(|(key, value)| (*key, *value, implied_entry_flags(value))),
+        )
+    }
+
+    /// Writes the entries with their flags as a single record, like [`Self::put_batch`]. Entries
+    /// whose flags are implied by their values are encoded as [`Self::put_batch`] does.
+    pub fn put_batch_with_flags<'a>(
+        &self,
+        data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)> + Clone,
+    ) -> Result<()> {
+        self.put_entries(None, data)
+    }
+
+    /// Writes the part of the batch `seq` across shards going to this WAL, as a single record
+    /// like [`Self::put_batch_with_flags`], where `memtable_ids` are the memtables all the parts
+    /// go to. The record starts with a header encoded as a flagged entry with an empty key,
+    /// which user keys never are, and is replayed into a [`WalBatchPart`].
+    pub fn put_batch_part<'a>(
+        &self,
+        seq: u64,
+        memtable_ids: &[usize],
+        data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)> + Clone,
+    ) -> Result<()> {
+        self.put_entries(Some((seq, memtable_ids)), data)
+    }
+
+    fn put_entries<'a>(
+        &self,
+        part: Option<(u64, &[usize])>,
+        data: impl Iterator<Item = (KeySlice<'a>, &'a [u8], u8)> + Clone,
+    ) -> Result<()> {
+        let header_len = part.map_or(0, |(_, memtable_ids)| {
+            std::mem::size_of::<u16>() * 3
+                + 1
+                + std::mem::size_of::<u64>() * (memtable_ids.len() + 1)
+        });
+        let body_len = header_len
+            + data
+                .clone()
+                .map(|(key, value, flags)| {
+                    let flags_len = if flags != implied_entry_flags(value) {
+                        std::mem::size_of::<u16>() + 1
+                    } else {
+                        0
+                    };
+                    key.raw_len() + value.len() + std::mem::size_of::<u16>() * 2 + flags_len
+                })
+                .sum::<usize>();
+        self.write_record(body_len, |buf| {
+            if let Some((seq, memtable_ids)) = part {
+                buf.put_u16(FLAGGED_ENTRY);
+                buf.put_u8(0);
+                buf.put_u16(0);
+                buf.put_u64(seq);
+                buf.put_u16(memtable_ids.len() as u16);
+                for id in memtable_ids {
+                    buf.put_u64(*id as u64);
+                }
+            }
+            for (key, value, flags) in data {
+                if flags != implied_entry_flags(value) {
+                    buf.put_u16(FLAGGED_ENTRY);
+                    buf.put_u8(flags);
+                }
+                buf.put_u16(key.key_len() as u16);
+                buf.put_slice(key.key_ref());
+                buf.put_u64(key.ts());
+                buf.put_u16(value.len() as u16);
+                buf.put_slice(value);
+            }
+        })
+    }
+
+    /// Writes a range tombstone as a record of its own. It is encoded as an entry with an empty
+    /// key, which user keys never are, followed by the tombstone.
+    pub fn put_range_tombstone(&self, tombstone: &RangeTombstone) -> Result<()> {
+        self.write_record(
+            std::mem::size_of::<u16>() + tombstone.encoded_len(),
+            |buf| {
+                buf.put_u16(0);
+                tombstone.encode(buf);
+            },
+        )
+    }
+
+    /// Writes a record: the u32 length of the body, the body and a u32 checksum of the body.
+    fn write_record(&self, body_len: usize, encode_body: impl FnOnce(&mut Vec<u8>)) -> Result<()> {
         let mut file = self.file.lock();
-        let mut buf: Vec<u8> =
-            Vec::with_capacity(key.raw_len() + value.len() + std::mem::size_of::<u16>());
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.write_u16(key.key_len() as u16);
-        buf.put_u16(key.key_len() as u16);
-        hasher.write(key.key_ref());
-        buf.put_slice(key.key_ref());
-        hasher.write_u64(key.ts());
-        buf.put_u64(key.ts());
-        hasher.write_u16(value.len() as u16);
-        buf.put_u16(value.len() as u16);
-        buf.put_slice(value);
-        hasher.write(value);
+        let mut buf: Vec<u8> = Vec::with_capacity(body_len + std::mem::size_of::<u32>() * 2);
+        buf.put_u32(body_len as u32);
+        encode_body(&mut buf);
+        debug_assert_eq!(buf.len(), body_len + std::mem::size_of::<u32>());
         // add checksum: week 2 day 7
-        buf.put_u32(hasher.finalize());
+        buf.put_u32(crc32fast::hash(&buf[std::mem::size_of::<u32>()..]));
         file.write_all(&buf)?;
         Ok(())
     }
@@ -0,0 +1,287 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::{Condvar, Mutex};
+
+use crate::compact::CompactionStats;
+use crate::lsm_storage::{ClosedError, LsmStorageInner, WriteOptions};
+
+/// When writes are slowed down or stopped to let the flushes and compactions catch up, instead
+/// of letting L0, and with it the reads, grow without bound, see
+/// [`crate::lsm_storage::LsmStorageOptions::write_stall`]. A trigger of 0 is disabled, as are all
+/// of them by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteStallOptions {
+    /// Number of L0 SSTs (tiers in tiered compaction) from which each write is delayed.
+    pub l0_slowdown_trigger: usize,
+    /// Number of L0 SSTs (tiers in tiered compaction) from which writes wait for compaction to
+    /// bring it back below.
+    pub l0_stop_trigger: usize,
+    /// Number of immutable memtables from which each write is delayed.
+    pub imm_slowdown_trigger: usize,
+    /// Number of immutable memtables from which writes wait for the flushes to bring it back
+    /// below. Must be at least `num_memtable_limit`, which the flush thread waits for.
+    pub imm_stop_trigger: usize,
+    /// The delay of a write at a slowdown trigger, doubled for every L0 SST or immutable memtable
+    /// over it.
+    pub slowdown_delay: Duration,
+    pub max_slowdown_delay: Duration,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            l0_slowdown_trigger: 0,
+            l0_stop_trigger: 0,
+            imm_slowdown_trigger: 0,
+            imm_stop_trigger: 0,
+            slowdown_delay: Duration::from_millis(1),
+            max_slowdown_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl WriteStallOptions {
+    pub(crate) fn validate(&self, num_memtable_limit: usize) -> Result<()> {
+        for (name, slowdown_trigger, stop_trigger) in [
+            ("l0", self.l0_slowdown_trigger, self.l0_stop_trigger),
+            ("imm", self.imm_slowdown_trigger, self.imm_stop_trigger),
+        ] {
+            if slowdown_trigger > 0 && stop_trigger > 0 && slowdown_trigger >= stop_trigger {
+                bail!(
+                    "{}_slowdown_trigger must be below {}_stop_trigger",
+                    name,
+                    name
+                );
+            }
+        }
+        if self.imm_stop_trigger > 0 && self.imm_stop_trigger < num_memtable_limit {
+            bail!(
+                "imm_stop_trigger must be at least num_memtable_limit, or writes would stop \
+                 before the memtables are flushed"
+            );
+        }
+        Ok(())
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.l0_slowdown_trigger > 0
+            || self.l0_stop_trigger > 0
+            || self.imm_slowdown_trigger > 0
+            || self.imm_stop_trigger > 0
+    }
+}
+
+/// What a write stall is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WriteStallCause {
+    /// Compaction is behind, see [`WriteStallOptions::l0_slowdown_trigger`].
+    L0Files,
+    /// The flushes are behind, see [`WriteStallOptions::imm_slowdown_trigger`].
+    ImmutableMemtables,
+}
+
+/// Why writes are slowed down or stopped, see [`LsmStorageInner::write_stall`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WriteStall {
+    pub cause: WriteStallCause,
+    /// Whether writes wait, rather than being delayed.
+    pub stopped: bool,
+    /// The number of L0 SSTs or immutable memtables.
+    pub count: usize,
+    /// The trigger `count` reached.
+    pub trigger: usize,
+}
+
+impl fmt::Display for WriteStall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.cause {
+            WriteStallCause::L0Files => "L0 SSTs",
+            WriteStallCause::ImmutableMemtables => "immutable memtables",
+        };
+        let action = if self.stopped {
+            "stopped"
+        } else {
+            "slowed down"
+        };
+        write!(
+            f,
+            "writes {}: {} {} reached the trigger of {}",
+            action, self.count, what, self.trigger
+        )
+    }
+}
+
+/// The error of a write with [`WriteOptions::no_stall`] that would have been delayed or
+/// stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock(pub WriteStall);
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the write would block, {}", self.0)
+    }
+}
+
+impl std::error::Error for WouldBlock {}
+
+/// Tracks the write stalls, and wakes the stopped writes when a flush or a compaction completes.
+#[derive(Default)]
+pub(crate) struct WriteController {
+    /// The stall last seen by a write, `None` if it was not stalled. The stopped writes wait on
+    /// `progress` with it locked.
+    last_stall: Mutex<Option<WriteStall>>,
+    progress: Condvar,
+    /// Set once the storage stops accepting writes, which fails the stopped ones.
+    closed: AtomicBool,
+    delayed_nanos: AtomicU64,
+    stopped_nanos: AtomicU64,
+    num_delayed_writes: AtomicU64,
+    num_stopped_writes: AtomicU64,
+}
+
+impl WriteController {
+    /// Wakes the stopped writes to check the stall again.
+    pub(crate) fn notify_progress(&self) {
+        let _last_stall = self.last_stall.lock();
+        self.progress.notify_all();
+    }
+
+    /// Fails the stopped writes and the later ones with a [`ClosedError`].
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify_progress();
+    }
+
+    /// Fill the write stall counters of `stats`.
+    pub(crate) fn fill(&self, stats: &mut CompactionStats) {
+        stats.write_delayed_time = Duration::from_nanos(self.delayed_nanos.load(Ordering::Relaxed));
+        stats.write_stopped_time = Duration::from_nanos(self.stopped_nanos.load(Ordering::Relaxed));
+        stats.num_delayed_writes = self.num_delayed_writes.load(Ordering::Relaxed);
+        stats.num_stopped_writes = self.num_stopped_writes.load(Ordering::Relaxed);
+        stats.last_write_stall = *self.last_stall.lock();
+    }
+}
+
+impl LsmStorageInner {
+    /// The stall the shape of the tree imposes on writes, if any, where stops take precedence
+    /// over slowdowns.
+    pub fn write_stall(&self) -> Option<WriteStall> {
+        let options = &self.options.write_stall;
+        let (num_l0, num_imm_memtables) = {
+            let snapshot = self.state.load();
+            let num_l0 = if self.compaction_controller.load().flush_to_l0() {
+                snapshot.l0_sstables.len()
+            } else {
+                snapshot.levels.len()
+            };
+            (num_l0, snapshot.imm_memtables.len())
+        };
+        [
+            (
+                WriteStallCause::L0Files,
+                true,
+                num_l0,
+                options.l0_stop_trigger,
+            ),
+            (
+                WriteStallCause::ImmutableMemtables,
+                true,
+                num_imm_memtables,
+                options.imm_stop_trigger,
+            ),
+            (
+                WriteStallCause::L0Files,
+                false,
+                num_l0,
+                options.l0_slowdown_trigger,
+            ),
+            (
+                WriteStallCause::ImmutableMemtables,
+                false,
+                num_imm_memtables,
+                options.imm_slowdown_trigger,
+            ),
+        ]
+        .into_iter()
+        .find(|(_, _, count, trigger)| *trigger > 0 && count >= trigger)
+        .map(|(cause, stopped, count, trigger)| WriteStall {
+            cause,
+            stopped,
+            count,
+            trigger,
+        })
+    }
+
+    /// Delays or blocks a write while the writes are stalled, or fails it with [`WouldBlock`]
+    /// under [`WriteOptions::no_stall`]. The event listeners are told of every new stall.
+    pub(crate) fn wait_for_write_stall(&self, options: &WriteOptions) -> Result<()> {
+        if !self.options.write_stall.is_enabled() {
+            return Ok(());
+        }
+        let controller = &self.write_controller;
+        let begin = Instant::now();
+        // the time spent stopped, if the write was
+        let mut stopped_time = None;
+        let result = loop {
+            let mut last_stall = controller.last_stall.lock();
+            if controller.closed.load(Ordering::SeqCst) {
+                break Err(ClosedError.into());
+            }
+            // computed with the controller locked, so that no progress is missed before waiting
+            let stall = self.write_stall();
+            if *last_stall != stall {
+                *last_stall = stall;
+                drop(last_stall);
+                if let Some(stall) = stall {
+                    let reason = stall.to_string();
+                    self.notify_listeners(|listener| listener.on_write_stall(&reason));
+                }
+                continue;
+            }
+            match stall {
+                None => break Ok(()),
+                Some(stall) if options.no_stall => break Err(WouldBlock(stall).into()),
+                Some(stall) if stall.stopped => {
+                    // bounded, so that a stall cleared other than by a flush or a compaction,
+                    // e.g. by changing the compaction options, is noticed
+                    controller
+                        .progress
+                        .wait_for(&mut last_stall, Duration::from_millis(100));
+                    stopped_time = Some(begin.elapsed());
+                }
+                Some(stall) => {
+                    drop(last_stall);
+                    let delay_begin = Instant::now();
+                    std::thread::sleep(self.slowdown_delay(&stall));
+                    controller
+                        .delayed_nanos
+                        .fetch_add(delay_begin.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    controller
+                        .num_delayed_writes
+                        .fetch_add(1, Ordering::Relaxed);
+                    break Ok(());
+                }
+            }
+        };
+        if let Some(stopped_time) = stopped_time {
+            controller
+                .stopped_nanos
+                .fetch_add(stopped_time.as_nanos() as u64, Ordering::Relaxed);
+            controller
+                .num_stopped_writes
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn slowdown_delay(&self, stall: &WriteStall) -> Duration {
+        let options = &self.options.write_stall;
+        let excess = (stall.count - stall.trigger).min(16) as u32;
+        (options.slowdown_delay * 2u32.pow(excess)).min(options.max_slowdown_delay)
+    }
+}